@@ -13,7 +13,7 @@ extern crate test;
 use rand::{rngs::SmallRng, Rng, SeedableRng};
 use solana_rbpf::{
     memory_region::{AccessType, AlignedMemoryMapping, MemoryRegion, UnalignedMemoryMapping},
-    vm::Config,
+    vm::{Config, TestUserError},
 };
 use test::Bencher;
 
@@ -69,11 +69,12 @@ macro_rules! bench_gapped_randomized_access_with_1024_entries {
             bencher
                 .bench(|bencher| {
                     let config = Config::default();
-                    let memory_mapping = $mem::new(memory_regions.clone(), &config).unwrap();
+                    let memory_mapping =
+                        $mem::new::<TestUserError>(memory_regions.clone(), &config).unwrap();
                     let mut prng = new_prng!();
                     bencher.iter(|| {
                         assert!(memory_mapping
-                            .map(
+                            .map::<TestUserError>(
                                 AccessType::Load,
                                 0x100000000 + (prng.gen::<u64>() % frame_count * (frame_size * 2)),
                                 1
@@ -107,10 +108,10 @@ macro_rules! bench_randomized_access_with_0001_entry {
             let content = vec![0; 1024 * 2];
             let memory_regions = vec![MemoryRegion::new_readonly(&content[..], 0x100000000)];
             let config = Config::default();
-            let memory_mapping = $mem::new(memory_regions, &config).unwrap();
+            let memory_mapping = $mem::new::<TestUserError>(memory_regions, &config).unwrap();
             let mut prng = new_prng!();
             bencher.iter(|| {
-                let _ = memory_mapping.map(
+                let _ = memory_mapping.map::<TestUserError>(
                     AccessType::Load,
                     0x100000000 + (prng.gen::<u64>() % content.len() as u64),
                     1,
@@ -140,9 +141,9 @@ macro_rules! bench_randomized_access_with_n_entries {
             let mut prng = new_prng!();
             let (memory_regions, end_address) = generate_memory_regions($n, false, Some(&mut prng));
             let config = Config::default();
-            let memory_mapping = $mem::new(memory_regions, &config).unwrap();
+            let memory_mapping = $mem::new::<TestUserError>(memory_regions, &config).unwrap();
             bencher.iter(|| {
-                let _ = memory_mapping.map(
+                let _ = memory_mapping.map::<TestUserError>(
                     AccessType::Load,
                     0x100000000 + (prng.gen::<u64>() % end_address),
                     1,
@@ -189,9 +190,9 @@ macro_rules! bench_randomized_mapping_with_n_entries {
             let (memory_regions, _end_address) =
                 generate_memory_regions($n, false, Some(&mut prng));
             let config = Config::default();
-            let memory_mapping = $mem::new(memory_regions, &config).unwrap();
+            let memory_mapping = $mem::new::<TestUserError>(memory_regions, &config).unwrap();
             bencher.iter(|| {
-                let _ = memory_mapping.map(AccessType::Load, 0x100000000, 1);
+                let _ = memory_mapping.map::<TestUserError>(AccessType::Load, 0x100000000, 1);
             });
         }
     };
@@ -237,9 +238,9 @@ macro_rules! bench_mapping_with_n_entries {
         fn $name(bencher: &mut Bencher) {
             let (memory_regions, _end_address) = generate_memory_regions($n, false, None);
             let config = Config::default();
-            let memory_mapping = $mem::new(memory_regions, &config).unwrap();
+            let memory_mapping = $mem::new::<TestUserError>(memory_regions, &config).unwrap();
             bencher.iter(|| {
-                let _ = memory_mapping.map(AccessType::Load, 0x100000000, 1);
+                let _ = memory_mapping.map::<TestUserError>(AccessType::Load, 0x100000000, 1);
             });
         }
     };
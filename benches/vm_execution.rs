@@ -10,10 +10,12 @@ extern crate solana_rbpf;
 extern crate test;
 
 use solana_rbpf::{
+    aligned_memory::AlignedMemory,
     ebpf,
     elf::Executable,
     memory_region::MemoryRegion,
     vm::{Config, EbpfVm, SyscallRegistry, TestContextObject, VerifiedExecutable},
+    vm_pool::VmPool,
 };
 use std::{fs::File, io::Read};
 use test::Bencher;
@@ -47,6 +49,58 @@ fn bench_init_interpreter_execution(bencher: &mut Bencher) {
     });
 }
 
+#[bench]
+fn bench_fresh_vm_interpreter_execution(bencher: &mut Bencher) {
+    let mut file = File::open("tests/elfs/pass_stack_reference.so").unwrap();
+    let mut elf = Vec::new();
+    file.read_to_end(&mut elf).unwrap();
+    let executable = Executable::<TestContextObject>::from_elf(
+        &elf,
+        Config::default(),
+        SyscallRegistry::default(),
+    )
+    .unwrap();
+    let verified_executable =
+        VerifiedExecutable::<TautologyVerifier, TestContextObject>::from_executable(executable)
+            .unwrap();
+    bencher.iter(|| {
+        let mut heap = AlignedMemory::<{ ebpf::HOST_ALIGN }>::zero_filled(32 * 1024);
+        let mut context_object = TestContextObject::new(29);
+        let mut vm = EbpfVm::new(
+            &verified_executable,
+            &mut context_object,
+            heap.as_slice_mut(),
+            Vec::new(),
+        )
+        .unwrap();
+        vm.execute_program(true).1.unwrap()
+    });
+}
+
+#[bench]
+fn bench_pooled_vm_interpreter_execution(bencher: &mut Bencher) {
+    let mut file = File::open("tests/elfs/pass_stack_reference.so").unwrap();
+    let mut elf = Vec::new();
+    file.read_to_end(&mut elf).unwrap();
+    let executable = Executable::<TestContextObject>::from_elf(
+        &elf,
+        Config::default(),
+        SyscallRegistry::default(),
+    )
+    .unwrap();
+    let verified_executable =
+        VerifiedExecutable::<TautologyVerifier, TestContextObject>::from_executable(executable)
+            .unwrap();
+    let mut pool = VmPool::new(&verified_executable, 32 * 1024, 1);
+    bencher.iter(|| {
+        let mut context_object = TestContextObject::new(29);
+        let mut vm = pool.get(&mut context_object, Vec::new()).unwrap();
+        let result = vm.execute_program(true).1.unwrap();
+        pool.recycle(vm);
+        result
+    });
+}
+
 #[cfg(not(windows))]
 #[bench]
 fn bench_init_jit_execution(bencher: &mut Bencher) {
@@ -270,3 +324,142 @@ fn bench_jit_vs_interpreter_call_depth_dynamic(bencher: &mut Bencher) {
         &mut [],
     );
 }
+
+fn bench_interpreter(bencher: &mut Bencher, assembly: &str, instruction_meter: u64) -> f64 {
+    let executable = solana_rbpf::assembler::assemble::<TestContextObject>(
+        assembly,
+        Config::default(),
+        SyscallRegistry::default(),
+    )
+    .unwrap();
+    let verified_executable =
+        VerifiedExecutable::<TautologyVerifier, TestContextObject>::from_executable(executable)
+            .unwrap();
+    let mut context_object = TestContextObject::default();
+    let mut vm = EbpfVm::new(
+        &verified_executable,
+        &mut context_object,
+        &mut [],
+        Vec::new(),
+    )
+    .unwrap();
+    bencher
+        .bench(|bencher| {
+            bencher.iter(|| {
+                vm.context_object.remaining = instruction_meter;
+                let (instruction_count, result) = vm.execute_program(true);
+                assert!(result.is_ok(), "{:?}", result);
+                assert_eq!(instruction_count, instruction_meter);
+            });
+            Ok(())
+        })
+        .unwrap()
+        .unwrap()
+        .mean
+}
+
+#[bench]
+fn bench_interpreter_without_internal_calls(bencher: &mut Bencher) {
+    let loop_code = "
+    mov r1, r2
+    and r1, 1023
+    add r2, 1
+    jlt r2, 0x10000, -4
+    exit";
+    // The unreachable call makes the interpreter track call frames
+    let with_call_code = format!(
+        "{}\n    call function_foo\n    function_foo:\n    exit",
+        loop_code
+    );
+    let call_free = bench_interpreter(bencher, loop_code, 262145);
+    let with_call = bench_interpreter(bencher, &with_call_code, 262145);
+    println!("call_frame_tracking_ratio={}", with_call / call_free);
+}
+
+#[cfg(not(windows))]
+fn bench_jit(
+    bencher: &mut Bencher,
+    assembly: &str,
+    config: Config,
+    enable_bounds_checks: bool,
+    instruction_meter: u64,
+    mem: &mut [u8],
+) -> (f64, usize) {
+    let executable = solana_rbpf::assembler::assemble::<TestContextObject>(
+        assembly,
+        config,
+        SyscallRegistry::default(),
+    )
+    .unwrap();
+    let mut verified_executable =
+        VerifiedExecutable::<TautologyVerifier, TestContextObject>::from_executable(executable)
+            .unwrap();
+    if enable_bounds_checks {
+        verified_executable.jit_compile().unwrap();
+    } else {
+        unsafe { verified_executable.jit_compile_without_bounds_checks() }.unwrap();
+    }
+    let machine_code_length = verified_executable
+        .get_executable()
+        .get_compiled_program()
+        .unwrap()
+        .machine_code_length();
+    let mut context_object = TestContextObject::default();
+    let mem_region = MemoryRegion::new_writable(mem, ebpf::MM_INPUT_START);
+    let mut vm = EbpfVm::new(
+        &verified_executable,
+        &mut context_object,
+        &mut [],
+        vec![mem_region],
+    )
+    .unwrap();
+    let mean = bencher
+        .bench(|bencher| {
+            bencher.iter(|| {
+                vm.context_object.remaining = instruction_meter;
+                let (instruction_count, result) = vm.execute_program(false);
+                assert!(result.is_ok(), "{:?}", result);
+                assert_eq!(instruction_count, instruction_meter);
+            });
+            Ok(())
+        })
+        .unwrap()
+        .unwrap()
+        .mean;
+    (mean, machine_code_length)
+}
+
+#[cfg(not(windows))]
+#[bench]
+fn bench_jit_without_bounds_checks(bencher: &mut Bencher) {
+    let assembly = "
+    ldxb r0, [r1]
+    stxb [r1], r0
+    add r1, 1
+    mov r0, r1
+    and r0, 0xFFFFFF
+    jlt r0, 0x20000, -6
+    exit";
+    let (checked, checked_length) = bench_jit(
+        bencher,
+        assembly,
+        Config::default(),
+        true,
+        786433,
+        &mut [0; 0x20000],
+    );
+    let (unchecked, unchecked_length) = bench_jit(
+        bencher,
+        assembly,
+        Config::default(),
+        false,
+        786433,
+        &mut [0; 0x20000],
+    );
+    println!(
+        "bounds_checks_ratio={} machine_code_length={}/{}",
+        checked / unchecked,
+        checked_length,
+        unchecked_length
+    );
+}
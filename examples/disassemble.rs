@@ -6,8 +6,8 @@
 
 extern crate solana_rbpf;
 use solana_rbpf::{
+    disassembler::disassemble_to_stdout,
     elf::Executable,
-    static_analysis::Analysis,
     vm::{Config, FunctionRegistry, SyscallRegistry, TestContextObject},
 };
 
@@ -38,7 +38,5 @@ fn main() {
         FunctionRegistry::default(),
     )
     .unwrap();
-    let analysis = Analysis::from_executable(&executable).unwrap();
-    let stdout = std::io::stdout();
-    analysis.disassemble(&mut stdout.lock()).unwrap();
+    disassemble_to_stdout(&executable).unwrap();
 }
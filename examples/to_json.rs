@@ -12,7 +12,7 @@ use std::path::PathBuf;
 
 extern crate solana_rbpf;
 use solana_rbpf::{
-    disassembler::disassemble_instruction,
+    disassembler::disassemble_instruction_to_string,
     elf::Executable,
     static_analysis::Analysis,
     vm::{Config, FunctionRegistry, SyscallRegistry, TestContextObject},
@@ -51,7 +51,7 @@ fn to_json(program: &[u8]) -> String {
             // number is negative. When values takes more than 32 bits with `lddw`, the cast
             // has no effect and the complete value is printed anyway.
             "imm"  => format!("{:#x}", insn.imm as i32), // => insn.imm,
-            "desc" => disassemble_instruction(insn, &analysis),
+            "desc" => disassemble_instruction_to_string(insn, &analysis),
         ));
     }
     json::stringify_pretty(
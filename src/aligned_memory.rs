@@ -1,6 +1,7 @@
 //! Aligned memory
 
-use std::{mem, ptr};
+use alloc::{vec, vec::Vec};
+use core::{mem, ptr};
 
 /// Scalar types, aka "plain old data"
 pub trait Pod {}
@@ -113,6 +114,7 @@ impl<const ALIGN: usize> AlignedMemory<ALIGN> {
         &mut self.mem[start..end]
     }
     /// Grows memory with `value` repeated `num` times starting at the `write_index`
+    #[cfg(feature = "std")]
     pub fn fill_write(&mut self, num: usize, value: u8) -> std::io::Result<()> {
         let new_len = match (
             self.mem.len().checked_add(num),
@@ -178,6 +180,7 @@ impl<const ALIGN: usize> Clone for AlignedMemory<ALIGN> {
     }
 }
 
+#[cfg(feature = "std")]
 impl<const ALIGN: usize> std::io::Write for AlignedMemory<ALIGN> {
     fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
         match (
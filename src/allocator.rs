@@ -0,0 +1,155 @@
+//! Bump allocator for the heap region
+
+use crate::memory_region::MemoryRegion;
+
+/// Hands out guest virtual addresses from a dedicated heap region
+///
+/// Allocations are never freed, so the same sequence of requests always yields the same addresses.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct BpfAllocator {
+    /// Virtual address of the heap region
+    vm_addr: u64,
+    /// Length of the heap region in bytes
+    len: u64,
+    /// Offset of the first unallocated byte
+    pos: u64,
+    /// Sum of all requested allocation sizes, excluding alignment padding
+    total_allocated: u64,
+    /// Upper bound of `total_allocated`, independent of the region length
+    max_total_allocated: Option<u64>,
+}
+
+impl BpfAllocator {
+    /// Alignment of every allocation in bytes
+    pub const ALIGN: u64 = 8;
+
+    /// Creates an allocator covering the specified virtual address range
+    pub fn new(vm_addr: u64, len: u64) -> Self {
+        Self {
+            vm_addr,
+            len,
+            pos: 0,
+            total_allocated: 0,
+            max_total_allocated: None,
+        }
+    }
+
+    /// Creates an allocator which fails once the sum of all requested sizes would exceed
+    /// `max_total_allocated`, even if the region still has room
+    pub fn new_with_limit(vm_addr: u64, len: u64, max_total_allocated: u64) -> Self {
+        Self {
+            max_total_allocated: Some(max_total_allocated),
+            ..Self::new(vm_addr, len)
+        }
+    }
+
+    /// Creates an allocator covering the given memory region
+    pub fn from_region(region: &MemoryRegion) -> Self {
+        Self::new(region.vm_addr, region.len)
+    }
+
+    /// Allocates `size` bytes and returns their virtual address
+    ///
+    /// Returns `None` if the remaining heap is too small or the allocation limit would be crossed.
+    pub fn alloc(&mut self, size: u64) -> Option<u64> {
+        let total_allocated = self.total_allocated.checked_add(size)?;
+        if self
+            .max_total_allocated
+            .is_some_and(|max| total_allocated > max)
+        {
+            return None;
+        }
+        let misalignment = self.vm_addr.checked_add(self.pos)? % Self::ALIGN;
+        let start = if misalignment == 0 {
+            self.pos
+        } else {
+            self.pos.checked_add(Self::ALIGN - misalignment)?
+        };
+        let end = start.checked_add(size)?;
+        if end > self.len {
+            return None;
+        }
+        self.pos = end;
+        self.total_allocated = total_allocated;
+        self.vm_addr.checked_add(start)
+    }
+
+    /// Number of bytes allocated so far, including alignment padding
+    pub fn get_allocated(&self) -> u64 {
+        self.pos
+    }
+
+    /// Number of bytes which have not been allocated yet
+    pub fn get_remaining(&self) -> u64 {
+        self.len.saturating_sub(self.pos)
+    }
+
+    /// Sum of all requested allocation sizes, excluding alignment padding
+    pub fn get_total_allocated(&self) -> u64 {
+        self.total_allocated
+    }
+
+    /// Releases all allocations so that the allocator can be reused for another run
+    ///
+    /// The allocation limit is kept, but the bytes counted against it start from zero again.
+    pub fn reset(&mut self) {
+        self.pos = 0;
+        self.total_allocated = 0;
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::ebpf::MM_HEAP_START;
+
+    #[test]
+    fn test_alloc_fill_exactly() {
+        let mut allocator = BpfAllocator::new(MM_HEAP_START, 32);
+        assert_eq!(allocator.alloc(8), Some(MM_HEAP_START));
+        assert_eq!(allocator.alloc(1), Some(MM_HEAP_START + 8));
+        assert_eq!(allocator.alloc(16), Some(MM_HEAP_START + 16));
+        assert_eq!(allocator.get_allocated(), 32);
+        assert_eq!(allocator.get_remaining(), 0);
+        assert_eq!(allocator.alloc(1), None);
+    }
+
+    #[test]
+    fn test_alloc_overflow_by_one_byte() {
+        let mut allocator = BpfAllocator::new(MM_HEAP_START, 32);
+        assert_eq!(allocator.alloc(33), None);
+        assert_eq!(allocator.alloc(32), Some(MM_HEAP_START));
+        assert_eq!(allocator.get_remaining(), 0);
+        assert_eq!(allocator.alloc(1), None);
+        assert_eq!(allocator.alloc(0), Some(MM_HEAP_START + 32));
+    }
+
+    #[test]
+    fn test_alloc_deterministic() {
+        let sizes = [3, 17, 8, 1, 0, 5];
+        let mut a = BpfAllocator::new(MM_HEAP_START + 4, 64);
+        let mut b = a.clone();
+        for size in sizes {
+            let addr = a.alloc(size);
+            assert_eq!(addr, b.alloc(size));
+            assert_eq!(addr.map(|addr| addr % BpfAllocator::ALIGN), Some(0));
+        }
+    }
+
+    #[test]
+    fn test_alloc_max_total_allocated() {
+        let mut allocator = BpfAllocator::new_with_limit(MM_HEAP_START, 1024, 20);
+        for i in 0..5 {
+            assert_eq!(allocator.alloc(4), Some(MM_HEAP_START + i * 8));
+        }
+        assert_eq!(allocator.get_total_allocated(), 20);
+        assert_eq!(allocator.alloc(1), None);
+        assert_eq!(allocator.alloc(0), Some(MM_HEAP_START + 40));
+        assert!(allocator.get_remaining() > 0);
+        allocator.reset();
+        assert_eq!(allocator.get_total_allocated(), 0);
+        assert_eq!(allocator.alloc(16), Some(MM_HEAP_START));
+        assert_eq!(allocator.alloc(5), None);
+        assert_eq!(allocator.alloc(4), Some(MM_HEAP_START + 16));
+    }
+}
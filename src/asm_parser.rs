@@ -15,7 +15,7 @@ use combine::{
     char::{alpha_num, char, digit, hex_digit, spaces, string},
     combine_parse_partial, combine_parser_impl,
     easy::{Error, Errors, Info},
-    eof, many, many1, one_of, optional, parse_mode, parser, sep_by, skip_many,
+    eof, many, many1, one_of, optional, parse_mode, parser, position, sep_by, skip_many,
     stream::state::{SourcePosition, State},
     Parser, Stream,
 };
@@ -148,13 +148,32 @@ fn format_parse_error(parse_error: &Errors<char, &str, SourcePosition>) -> Strin
 /// Parse a string into a list of instructions.
 ///
 /// The instructions are not validated and may have invalid names and operand types.
+#[cfg(test)]
 pub fn parse(input: &str) -> Result<Vec<Statement>, String> {
+    parse_with_lines(input).map(|statements| {
+        statements
+            .into_iter()
+            .map(|(_line, statement)| statement)
+            .collect()
+    })
+}
+
+/// Same as `parse`, but annotates every statement with the line it starts at.
+pub fn parse_with_lines(input: &str) -> Result<Vec<(usize, Statement)>, String> {
     match spaces()
-        .with(many(attempt(label()).or(instruction()).skip(spaces())))
+        .with(many(
+            (position(), attempt(label()).or(instruction())).skip(spaces()),
+        ))
         .skip(eof())
         .easy_parse(State::with_positioner(input, SourcePosition::default()))
     {
-        Ok((insts, _)) => Ok(insts),
+        Ok((statements, _)) => {
+            let statements: Vec<(SourcePosition, Statement)> = statements;
+            Ok(statements
+                .into_iter()
+                .map(|(position, statement)| (position.line as usize, statement))
+                .collect())
+        }
         Err(err) => Err(format_parse_error(&err)),
     }
 }
@@ -8,12 +8,12 @@
 //! This module translates eBPF assembly language to binary.
 
 use self::InstructionType::{
-    AluBinary, AluUnary, CallImm, CallReg, Endian, JumpConditional, JumpUnconditional, LoadAbs,
-    LoadImm, LoadInd, LoadReg, NoOperand, StoreImm, StoreReg, Syscall,
+    AluBinary, AluUnary, Atomic, CallImm, CallReg, Endian, JumpConditional, JumpUnconditional,
+    LoadAbs, LoadImm, LoadInd, LoadReg, NoOperand, StoreImm, StoreReg, Syscall,
 };
 use crate::{
     asm_parser::{
-        parse,
+        parse_with_lines,
         Operand::{Integer, Label, Memory, Register},
         Statement,
     },
@@ -33,6 +33,7 @@ enum InstructionType {
     LoadReg,
     StoreImm,
     StoreReg,
+    Atomic(i64),
     JumpUnconditional,
     JumpConditional,
     Syscall,
@@ -67,6 +68,19 @@ fn make_instruction_map() -> HashMap<String, (InstructionType, u8)> {
         ("dw", ebpf::BPF_DW),
     ];
 
+    let atomic_ops = [
+        ("xadd", ebpf::ATOMIC_ADD),
+        ("xor", ebpf::ATOMIC_OR),
+        ("xand", ebpf::ATOMIC_AND),
+        ("xxor", ebpf::ATOMIC_XOR),
+        ("fetchadd", ebpf::ATOMIC_FETCH_ADD),
+        ("fetchor", ebpf::ATOMIC_FETCH_OR),
+        ("fetchand", ebpf::ATOMIC_FETCH_AND),
+        ("fetchxor", ebpf::ATOMIC_FETCH_XOR),
+        ("xchg", ebpf::ATOMIC_XCHG),
+        ("cmpxchg", ebpf::ATOMIC_CMPXCHG),
+    ];
+
     let jump_conditions = [
         ("jeq", ebpf::BPF_JEQ),
         ("jgt", ebpf::BPF_JGT),
@@ -109,6 +123,7 @@ fn make_instruction_map() -> HashMap<String, (InstructionType, u8)> {
         entry("sdiv", AluBinary, ebpf::BPF_ALU64 | ebpf::BPF_SDIV);
         entry("sdiv64", AluBinary, ebpf::BPF_ALU64 | ebpf::BPF_SDIV);
         entry("sdiv32", AluBinary, ebpf::BPF_ALU | ebpf::BPF_SDIV);
+        entry("uhmul64", AluBinary, ebpf::BPF_ALU64 | ebpf::BPF_UHMUL);
 
         // LoadAbs, LoadInd, LoadReg, StoreImm, and StoreReg.
         for &(suffix, size) in &mem_sizes {
@@ -139,9 +154,28 @@ fn make_instruction_map() -> HashMap<String, (InstructionType, u8)> {
             );
         }
 
+        // Atomic.
+        for &(name, operation) in &atomic_ops {
+            entry(
+                &format!("stx{}w", name),
+                Atomic(operation),
+                ebpf::ST_W_ATOMIC,
+            );
+            entry(
+                &format!("stx{}dw", name),
+                Atomic(operation),
+                ebpf::ST_DW_ATOMIC,
+            );
+        }
+
         // JumpConditional.
         for &(name, condition) in &jump_conditions {
             entry(name, JumpConditional, ebpf::BPF_JMP | condition);
+            entry(
+                &format!("{}32", name),
+                JumpConditional,
+                ebpf::BPF_JMP32 | condition,
+            );
         }
 
         // Endian.
@@ -219,24 +253,33 @@ pub fn assemble<C: ContextObject>(
     config: Config,
     syscall_registry: SyscallRegistry<C>,
 ) -> Result<Executable<C>, String> {
+    fn lookup_label(
+        labels: &HashMap<&str, usize>,
+        label: &str,
+        line: usize,
+    ) -> Result<usize, String> {
+        labels
+            .get(label)
+            .copied()
+            .ok_or_else(|| format!("Label not found {} at line {}", label, line))
+    }
+
     fn resolve_label(
         insn_ptr: usize,
         labels: &HashMap<&str, usize>,
         label: &str,
+        line: usize,
     ) -> Result<i64, String> {
-        labels
-            .get(label)
-            .map(|target_pc| *target_pc as i64 - insn_ptr as i64 - 1)
-            .ok_or_else(|| format!("Label not found {}", label))
+        lookup_label(labels, label, line).map(|target_pc| target_pc as i64 - insn_ptr as i64 - 1)
     }
 
-    let statements = parse(src)?;
+    let statements = parse_with_lines(src)?;
     let instruction_map = make_instruction_map();
     let mut insn_ptr = 0;
     let mut function_registry = FunctionRegistry::default();
     let mut labels = HashMap::new();
     labels.insert("entrypoint", 0);
-    for statement in statements.iter() {
+    for (_line, statement) in statements.iter() {
         match statement {
             Statement::Label { name } => {
                 if name.starts_with("function_") || name == "entrypoint" {
@@ -258,7 +301,8 @@ pub fn assemble<C: ContextObject>(
     }
     insn_ptr = 0;
     let mut instructions: Vec<Insn> = Vec::new();
-    for statement in statements.iter() {
+    for (line, statement) in statements.iter() {
+        let line = *line;
         if let Statement::Instruction { name, operands } = statement {
             let name = name.as_str();
             match instruction_map.get(name) {
@@ -280,6 +324,9 @@ pub fn assemble<C: ContextObject>(
                         (StoreImm, [Memory(dst, off), Integer(imm)]) => {
                             insn(opc, *dst, 0, *off, *imm)
                         }
+                        (Atomic(operation), [Memory(dst, off), Register(src)]) => {
+                            insn(opc, *dst, *src, *off, operation)
+                        }
                         (NoOperand, []) => insn(opc, 0, 0, 0, 0),
                         (JumpUnconditional, [Integer(off)]) => insn(opc, 0, 0, *off, 0),
                         (JumpConditional, [Register(dst), Register(src), Integer(off)]) => {
@@ -289,7 +336,7 @@ pub fn assemble<C: ContextObject>(
                             insn(opc | ebpf::BPF_K, *dst, 0, *off, *imm)
                         }
                         (JumpUnconditional, [Label(label)]) => {
-                            insn(opc, 0, 0, resolve_label(insn_ptr, &labels, label)?, 0)
+                            insn(opc, 0, 0, resolve_label(insn_ptr, &labels, label, line)?, 0)
                         }
                         (CallImm, [Integer(imm)]) => {
                             let target_pc = *imm + insn_ptr as i64 + 1;
@@ -309,14 +356,14 @@ pub fn assemble<C: ContextObject>(
                             opc | ebpf::BPF_X,
                             *dst,
                             *src,
-                            resolve_label(insn_ptr, &labels, label)?,
+                            resolve_label(insn_ptr, &labels, label, line)?,
                             0,
                         ),
                         (JumpConditional, [Register(dst), Integer(imm), Label(label)]) => insn(
                             opc | ebpf::BPF_K,
                             *dst,
                             0,
-                            resolve_label(insn_ptr, &labels, label)?,
+                            resolve_label(insn_ptr, &labels, label, line)?,
                             *imm,
                         ),
                         (Syscall, [Label(label)]) => insn(
@@ -327,10 +374,7 @@ pub fn assemble<C: ContextObject>(
                             ebpf::hash_symbol_name(label.as_bytes()) as i32 as i64,
                         ),
                         (CallImm, [Label(label)]) => {
-                            let label: &str = label;
-                            let target_pc = *labels
-                                .get(label)
-                                .ok_or_else(|| format!("Label not found {}", label))?;
+                            let target_pc = lookup_label(&labels, label, line)?;
                             insn(opc, 0, 1, 0, target_pc as i64)
                         }
                         (Endian(size), [Register(dst)]) => insn(opc, *dst, 0, 0, size),
@@ -0,0 +1,171 @@
+#![allow(clippy::integer_arithmetic)]
+//! Interpreter driver for syscalls which complete asynchronously.
+
+use crate::{
+    ebpf,
+    error::EbpfError,
+    interpreter::{Interpreter, InterpreterState},
+    memory_region::MemoryMapping,
+    verifier::Verifier,
+    vm::{ContextObject, EbpfVm, ProgramResult},
+};
+use alloc::{boxed::Box, collections::BTreeMap};
+use core::{future::Future, pin::Pin};
+
+/// Future returned by an asynchronous syscall, resolving to the value of r0
+pub type AsyncSyscallFuture<'a, E> = Pin<Box<dyn Future<Output = Result<u64, EbpfError<E>>> + 'a>>;
+
+/// Asynchronous syscall function
+///
+/// The returned future may borrow the context object and the memory mapping,
+/// the program is suspended until it completes.
+pub type AsyncSyscallFunction<C> =
+    for<'a, 'b> fn(
+        &'a mut C,
+        u64,
+        u64,
+        u64,
+        u64,
+        u64,
+        &'a mut MemoryMapping<'b>,
+    ) -> AsyncSyscallFuture<'a, <C as ContextObject>::UserError>;
+
+/// Holds the asynchronous syscalls
+pub struct AsyncSyscallRegistry<C: ContextObject> {
+    /// Function pointers by symbol hash
+    entries: BTreeMap<u32, AsyncSyscallFunction<C>>,
+}
+
+impl<C: ContextObject> Default for AsyncSyscallRegistry<C> {
+    fn default() -> Self {
+        Self {
+            entries: BTreeMap::new(),
+        }
+    }
+}
+
+impl<C: ContextObject> AsyncSyscallRegistry<C> {
+    /// Register a syscall function by its symbol name
+    pub fn register_syscall_by_name(
+        &mut self,
+        name: &[u8],
+        function: AsyncSyscallFunction<C>,
+    ) -> Result<(), EbpfError<C::UserError>> {
+        let hash = ebpf::hash_symbol_name(name);
+        if self.entries.insert(hash, function).is_some() {
+            Err(EbpfError::SyscallAlreadyRegistered(hash as usize))
+        } else {
+            Ok(())
+        }
+    }
+
+    /// Get a symbol's function pointer
+    pub fn lookup_syscall(&self, hash: u32) -> Option<AsyncSyscallFunction<C>> {
+        self.entries.get(&hash).copied()
+    }
+
+    /// Get the number of registered syscalls
+    pub fn get_number_of_syscalls(&self) -> usize {
+        self.entries.len()
+    }
+}
+
+/// Interpreter which awaits asynchronous syscalls
+///
+/// Every other instruction, including the synchronous syscalls of the
+/// executable, is dispatched by [`Interpreter::step`]. An asynchronous
+/// syscall takes precedence over a synchronous one with the same hash.
+pub struct AsyncInterpreter<'a, 'b, V: Verifier, C: ContextObject> {
+    interpreter: Interpreter<'a, 'b, V, C>,
+    syscall_registry: &'a AsyncSyscallRegistry<C>,
+}
+
+impl<'a, 'b, V: Verifier, C: ContextObject> AsyncInterpreter<'a, 'b, V, C> {
+    /// Creates a new interpreter state
+    pub fn new(
+        vm: &'a mut EbpfVm<'b, V, C>,
+        syscall_registry: &'a AsyncSyscallRegistry<C>,
+    ) -> Result<Self, EbpfError<C::UserError>> {
+        Ok(Self {
+            interpreter: Interpreter::new(vm)?,
+            syscall_registry,
+        })
+    }
+
+    /// Returns the underlying interpreter, e.g. to inspect the registers
+    pub fn get_interpreter(&self) -> &Interpreter<'a, 'b, V, C> {
+        &self.interpreter
+    }
+
+    /// Advances the interpreter state by one instruction
+    ///
+    /// Awaits the syscall if the instruction invokes an asynchronous one.
+    pub async fn step(&mut self) -> Result<InterpreterState, EbpfError<C::UserError>> {
+        let syscall = self
+            .interpreter
+            .next_syscall_hash()
+            .and_then(|hash| self.syscall_registry.lookup_syscall(hash));
+        let syscall = match syscall {
+            Some(syscall) => syscall,
+            None => return self.interpreter.step(),
+        };
+        let ([arg1, arg2, arg3, arg4, arg5], replacement) = self.interpreter.enter_syscall()?;
+        let result = match replacement {
+            Some(replacement) => replacement.into(),
+            None => {
+                let vm = &mut *self.interpreter.vm;
+                syscall(
+                    vm.context_object,
+                    arg1,
+                    arg2,
+                    arg3,
+                    arg4,
+                    arg5,
+                    &mut vm.memory_mapping,
+                )
+                .await
+            }
+        };
+        self.interpreter.leave_syscall(result)
+    }
+
+    /// Runs the program until it exits or fails
+    pub async fn run(&mut self) -> Result<u64, EbpfError<C::UserError>> {
+        loop {
+            if let InterpreterState::Exited(value) = self.step().await? {
+                return Ok(value);
+            }
+        }
+    }
+
+    /// Runs the program and returns the number of executed instructions
+    /// alongside the result, like [`EbpfVm::execute_program`]
+    pub async fn execute_program(&mut self) -> (u64, ProgramResult<C::UserError>) {
+        let result = match self.run().await {
+            Ok(value) => ProgramResult::Ok(value),
+            Err(error) => ProgramResult::Err(error),
+        };
+        let interpreter = &mut self.interpreter;
+        let enable_instruction_meter = interpreter
+            .vm
+            .verified_executable
+            .get_executable()
+            .get_config()
+            .enable_instruction_meter;
+        let instruction_count = if enable_instruction_meter {
+            let context_object = &mut interpreter.vm.context_object;
+            context_object.consume(
+                interpreter
+                    .due_insn_count
+                    .min(context_object.get_remaining()),
+            );
+            interpreter.due_insn_count = 0;
+            interpreter
+                .initial_insn_count
+                .saturating_sub(context_object.get_remaining())
+        } else {
+            0
+        };
+        (instruction_count, result)
+    }
+}
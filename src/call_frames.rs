@@ -4,19 +4,31 @@
 use crate::{
     aligned_memory::AlignedMemory,
     ebpf::{ELF_INSN_DUMP_OFFSET, HOST_ALIGN, MM_STACK_START, SCRATCH_REGS},
-    error::EbpfError,
+    error::{EbpfError, UserDefinedError},
     memory_region::MemoryRegion,
     vm::Config,
 };
+use alloc::{vec, vec::Vec};
 
 /// One call frame
-#[derive(Clone, Debug)]
+#[derive(Clone, Debug, Default)]
 struct CallFrame {
     frame_ptr: u64,
     saved_reg: [u64; 4],
     return_ptr: usize,
 }
 
+/// Saved state of [`CallFrames`], see [`CallFrames::snapshot`]
+#[derive(Clone, Debug)]
+pub struct CallFramesSnapshot {
+    stack: Vec<u8>,
+    stack_ptr: u64,
+    frame_index: usize,
+    frame_index_max: usize,
+    stack_bytes_max: usize,
+    frames: Vec<CallFrame>,
+}
+
 /// When BPF calls a function other then a `syscall` it expect the new
 /// function to be called in its own frame.  CallFrames manages
 /// call frames
@@ -27,13 +39,36 @@ pub struct CallFrames<'a> {
     stack_ptr: u64,
     frame_index: usize,
     frame_index_max: usize,
+    /// High water mark of `get_stack_bytes()`
+    stack_bytes_max: usize,
     frames: Vec<CallFrame>,
+    /// Number of frames backed by `stack`
+    frame_capacity: usize,
+    /// Set when `stack` was reallocated and its memory region needs to be remapped
+    resized: bool,
 }
 impl<'a> CallFrames<'a> {
     /// New call frame, depth indicates maximum call depth
     pub fn new(config: &'a Config) -> Self {
+        let frame_capacity = match config.initial_stack_frames {
+            Some(initial_stack_frames) if !config.dynamic_stack_frames => {
+                initial_stack_frames.clamp(1, config.max_call_depth)
+            }
+            _ => config.max_call_depth,
+        };
+        Self::new_with_stack(
+            config,
+            AlignedMemory::zero_filled(config.stack_frame_size * frame_capacity),
+        )
+    }
+
+    /// New call frames backed by a zero filled `stack`, see `into_stack()`
+    pub(crate) fn new_with_stack(config: &'a Config, stack: AlignedMemory<{ HOST_ALIGN }>) -> Self {
         let stack_len = config.stack_size();
-        let stack = AlignedMemory::zero_filled(stack_len);
+        let frame_capacity = stack
+            .len()
+            .checked_div(config.stack_frame_size)
+            .unwrap_or(config.max_call_depth);
 
         let mut frames = CallFrames {
             config,
@@ -41,6 +76,7 @@ impl<'a> CallFrames<'a> {
             stack_ptr: 0,
             frame_index: 0,
             frame_index_max: 0,
+            stack_bytes_max: 0,
             frames: vec![
                 CallFrame {
                     frame_ptr: 0,
@@ -49,6 +85,8 @@ impl<'a> CallFrames<'a> {
                 };
                 config.max_call_depth
             ],
+            frame_capacity,
+            resized: false,
         };
 
         let frame = &mut frames.frames[0];
@@ -62,10 +100,16 @@ impl<'a> CallFrames<'a> {
             frame.frame_ptr = MM_STACK_START + config.stack_frame_size as u64;
             frames.stack_ptr = MM_STACK_START;
         }
+        frames.track_stack_bytes();
 
         frames
     }
 
+    /// Gives up the stack memory so that it can back another `CallFrames`
+    pub(crate) fn into_stack(self) -> AlignedMemory<{ HOST_ALIGN }> {
+        self.stack
+    }
+
     /// Get stack memory region
     pub fn get_memory_region(&mut self) -> MemoryRegion {
         MemoryRegion::new_writable_gapped(
@@ -107,12 +151,53 @@ impl<'a> CallFrames<'a> {
         self.frame_index_max
     }
 
+    /// Number of bytes of the stack region which the active frames occupy
+    ///
+    /// With dynamic stack frames this is the distance of the stack pointer from the top of the
+    /// stack, otherwise the size of the frames up to and including the current one (without gaps).
+    pub fn get_stack_bytes(&self) -> usize {
+        if self.config.dynamic_stack_frames {
+            (MM_STACK_START + self.config.stack_size() as u64)
+                .saturating_sub(self.stack_ptr)
+                .min(self.config.stack_size() as u64) as usize
+        } else if self.config.enable_stack_frame_gaps {
+            (self.frame_index + 1) * self.config.stack_frame_size
+        } else {
+            self.get_frame_ptr().saturating_sub(MM_STACK_START) as usize
+        }
+    }
+
+    /// Largest `get_stack_bytes()` since the creation or `reset_high_water_marks()`
+    ///
+    /// Stays zero unless `Config::enable_stack_usage_tracking` is set.
+    pub fn get_max_stack_bytes(&self) -> usize {
+        self.stack_bytes_max
+    }
+
+    /// Restarts the max frame index and the max stack bytes from the current frame
+    pub fn reset_high_water_marks(&mut self) {
+        self.frame_index_max = self.frame_index;
+        self.stack_bytes_max = 0;
+        self.track_stack_bytes();
+    }
+
+    /// Raises the max stack bytes to `get_stack_bytes()` if `Config::enable_stack_usage_tracking` is set
+    fn track_stack_bytes(&mut self) {
+        if self.config.enable_stack_usage_tracking {
+            self.stack_bytes_max = self.stack_bytes_max.max(self.get_stack_bytes());
+        }
+    }
+
     /// Push a frame
-    pub fn push(&mut self, saved_reg: &[u64], return_ptr: usize) -> Result<u64, EbpfError> {
-        if self.frame_index + 1 >= self.frames.len() {
+    pub fn push<E: UserDefinedError>(
+        &mut self,
+        saved_reg: &[u64],
+        return_ptr: usize,
+    ) -> Result<u64, EbpfError<E>> {
+        if self.frame_index + 1 >= self.config.max_call_depth {
             return Err(EbpfError::CallDepthExceeded(
                 return_ptr + ELF_INSN_DUMP_OFFSET - 1,
-                self.frames.len(),
+                self.config.max_call_depth,
             ));
         }
 
@@ -135,15 +220,83 @@ impl<'a> CallFrames<'a> {
                         1
                     };
             self.stack_ptr = frame.frame_ptr - self.config.stack_frame_size as u64;
+            if self.frame_index >= self.frame_capacity {
+                self.grow((self.frame_capacity * 2).max(self.frame_index + 1));
+            }
         }
 
         self.frame_index_max = self.frame_index_max.max(self.frame_index);
+        if !self.config.dynamic_stack_frames {
+            self.track_stack_bytes();
+        }
+
+        if self.config.clear_stack_on_entry {
+            let frame = if self.config.dynamic_stack_frames {
+                0..self.stack_ptr.saturating_sub(MM_STACK_START) as usize
+            } else {
+                let start = self.config.stack_frame_size * self.frame_index;
+                start..start + self.config.stack_frame_size
+            };
+            // A stack pointer moved out of the stack leaves nothing to clear
+            if let Some(frame) = self.stack.as_slice_mut().get_mut(frame) {
+                frame.fill(0);
+            }
+        }
 
         Ok(self.get_frame_ptr())
     }
 
+    /// Push a frame of `frame_size` bytes, for callees which have their stack frame size recorded
+    ///
+    /// Only applies to fixed stack frames without gaps, see `Executable::set_stack_frame_size()`.
+    /// Instead of `max_call_depth` the call depth is limited by the stack size.
+    pub fn push_sized<E: UserDefinedError>(
+        &mut self,
+        saved_reg: &[u64],
+        return_ptr: usize,
+        frame_size: usize,
+    ) -> Result<u64, EbpfError<E>> {
+        debug_assert!(!self.config.dynamic_stack_frames && !self.config.enable_stack_frame_gaps);
+        let caller_frame_ptr = self.frames[self.frame_index].frame_ptr;
+        let frame_ptr = caller_frame_ptr.saturating_add(frame_size as u64);
+        if frame_ptr > MM_STACK_START + self.config.stack_size() as u64 {
+            return Err(EbpfError::CallDepthExceeded(
+                return_ptr + ELF_INSN_DUMP_OFFSET - 1,
+                self.frame_index + 1,
+            ));
+        }
+
+        self.frames[self.frame_index].saved_reg[..].copy_from_slice(saved_reg);
+        self.frames[self.frame_index].return_ptr = return_ptr;
+
+        self.frame_index += 1;
+        if self.frame_index >= self.frames.len() {
+            self.frames.push(CallFrame::default());
+        }
+        self.frames[self.frame_index].frame_ptr = frame_ptr;
+        self.stack_ptr = caller_frame_ptr;
+        let frame =
+            (caller_frame_ptr - MM_STACK_START) as usize..(frame_ptr - MM_STACK_START) as usize;
+        if frame.end > self.stack.len() {
+            self.grow(
+                (frame.end + self.config.stack_frame_size - 1) / self.config.stack_frame_size,
+            );
+        }
+
+        self.frame_index_max = self.frame_index_max.max(self.frame_index);
+        self.track_stack_bytes();
+
+        if self.config.clear_stack_on_entry {
+            self.stack.as_slice_mut()[frame].fill(0);
+        }
+
+        Ok(frame_ptr)
+    }
+
     /// Pop a frame
-    pub fn pop(&mut self) -> Result<([u64; SCRATCH_REGS], u64, usize), EbpfError> {
+    pub fn pop<E: UserDefinedError>(
+        &mut self,
+    ) -> Result<([u64; SCRATCH_REGS], u64, usize), EbpfError<E>> {
         if self.frame_index == 0 {
             return Err(EbpfError::ExitRootCallFrame);
         }
@@ -155,6 +308,79 @@ impl<'a> CallFrames<'a> {
         ))
     }
 
+    /// Allocates the frames which are not backed by host memory yet
+    pub fn allocate_all_frames(&mut self) {
+        if self.frame_capacity < self.config.max_call_depth {
+            self.grow(self.config.max_call_depth);
+        }
+    }
+
+    /// Returns the memory region of the stack if it was reallocated since the last call
+    ///
+    /// The vm addresses of the frames stay the same, only the host memory moves.
+    pub fn take_resized_memory_region(&mut self) -> Option<MemoryRegion> {
+        if self.resized {
+            self.resized = false;
+            Some(self.get_memory_region())
+        } else {
+            None
+        }
+    }
+
+    /// Captures the active frames and the contents of the stack
+    pub fn snapshot(&self) -> CallFramesSnapshot {
+        CallFramesSnapshot {
+            stack: self.stack.as_slice().to_vec(),
+            stack_ptr: self.stack_ptr,
+            frame_index: self.frame_index,
+            frame_index_max: self.frame_index_max,
+            stack_bytes_max: self.stack_bytes_max,
+            frames: self.frames[..=self.frame_index].to_vec(),
+        }
+    }
+
+    /// Rewinds the frames and the stack to a previously taken snapshot
+    ///
+    /// Frames allocated since the snapshot was taken are zeroed. Check
+    /// `take_resized_memory_region()` afterwards, as the stack might have to grow.
+    pub fn restore<E: UserDefinedError>(
+        &mut self,
+        snapshot: &CallFramesSnapshot,
+    ) -> Result<(), EbpfError<E>> {
+        if snapshot.frame_index + 1 != snapshot.frames.len()
+            || snapshot.stack.len() > self.config.stack_size()
+        {
+            return Err(EbpfError::SnapshotMismatch(MM_STACK_START));
+        }
+        if snapshot.frames.len() > self.frames.len() {
+            // Frames pushed by `push_sized()` are not limited by `max_call_depth`
+            self.frames
+                .resize(snapshot.frames.len(), CallFrame::default());
+        }
+        if snapshot.stack.len() > self.stack.len() {
+            self.grow(snapshot.stack.len() / self.config.stack_frame_size.max(1));
+        }
+        let stack = self.stack.as_slice_mut();
+        stack[..snapshot.stack.len()].copy_from_slice(&snapshot.stack);
+        stack[snapshot.stack.len()..].fill(0);
+        self.frames[..snapshot.frames.len()].clone_from_slice(&snapshot.frames);
+        self.stack_ptr = snapshot.stack_ptr;
+        self.frame_index = snapshot.frame_index;
+        self.frame_index_max = snapshot.frame_index_max;
+        self.stack_bytes_max = snapshot.stack_bytes_max;
+        Ok(())
+    }
+
+    /// Reallocates the stack to hold the given number of frames, at most max_call_depth
+    fn grow(&mut self, frame_capacity: usize) {
+        let frame_capacity = frame_capacity.min(self.config.max_call_depth);
+        let mut stack = AlignedMemory::zero_filled(self.config.stack_frame_size * frame_capacity);
+        stack.as_slice_mut()[..self.stack.len()].copy_from_slice(self.stack.as_slice());
+        self.stack = stack;
+        self.frame_capacity = frame_capacity;
+        self.resized = true;
+    }
+
     /// Resize the stack
     pub fn resize_stack(&mut self, amount: i64) {
         debug_assert!(self.config.dynamic_stack_frames);
@@ -166,12 +392,14 @@ impl<'a> CallFrames<'a> {
         // InvalidVirtualAddress(stack_ptr) once an invalid stack address is
         // accessed.
         self.stack_ptr = self.stack_ptr.overflowing_add(amount as u64).0;
+        self.track_stack_bytes();
     }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::vm::TestUserError;
 
     #[test]
     fn test_frames() {
@@ -205,7 +433,7 @@ mod tests {
 
                 // push the next frame, get the new frame pointers and check
                 // that push returns the newly added frame pointer
-                let top = frames.push(&registers[0..4], i).unwrap();
+                let top = frames.push::<TestUserError>(&registers[0..4], i).unwrap();
                 let new_ptrs = frames.get_frame_pointers();
                 assert_eq!(top, new_ptrs[i + 1]);
 
@@ -221,17 +449,55 @@ mod tests {
             assert_eq!(frames.get_frame_index(), i);
             frame_ptrs.push(frames.get_frame_pointers()[i]);
 
-            assert!(frames.push(&registers, config.max_call_depth - 1).is_err());
+            assert!(frames
+                .push::<TestUserError>(&registers, config.max_call_depth - 1)
+                .is_err());
 
             for i in (0..config.max_call_depth - 1).rev() {
-                let (saved_reg, frame_ptr, return_ptr) = frames.pop().unwrap();
+                let (saved_reg, frame_ptr, return_ptr) = frames.pop::<TestUserError>().unwrap();
                 assert_eq!(saved_reg, [i as u64, i as u64, i as u64, i as u64]);
                 assert_eq!(frame_ptrs[i], frame_ptr);
                 assert_eq!(i, return_ptr);
             }
 
-            assert!(frames.pop().is_err());
+            assert!(frames.pop::<TestUserError>().is_err());
+        }
+    }
+
+    #[test]
+    fn test_growable_frames() {
+        let config = Config {
+            max_call_depth: 5,
+            stack_frame_size: 8,
+            dynamic_stack_frames: false,
+            initial_stack_frames: Some(1),
+            ..Config::default()
+        };
+        let mut frames = CallFrames::new(&config);
+        assert_eq!(frames.get_memory_region().len, 8);
+        assert!(frames.take_resized_memory_region().is_none());
+        let frame_ptr = frames.get_frame_ptr();
+        let host_addr = frames.get_memory_region().host_addr + 7;
+        unsafe { *(host_addr as *mut u8) = 0xAA };
+
+        let mut region_lens = Vec::new();
+        for i in 0..config.max_call_depth - 1 {
+            frames.push::<TestUserError>(&[i as u64; 4], i).unwrap();
+            region_lens.push(frames.take_resized_memory_region().map(|region| region.len));
+        }
+        assert_eq!(region_lens, vec![Some(16), Some(32), None, Some(40)]);
+        assert!(matches!(
+            frames.push::<TestUserError>(&[0; 4], 4),
+            Err(EbpfError::CallDepthExceeded(_, 5))
+        ));
+
+        // The frames keep their vm addresses and contents
+        for _ in 0..config.max_call_depth - 1 {
+            frames.pop::<TestUserError>().unwrap();
         }
+        assert_eq!(frames.get_frame_ptr(), frame_ptr);
+        let host_addr = frames.get_memory_region().host_addr + 7;
+        assert_eq!(unsafe { *(host_addr as *const u8) }, 0xAA);
     }
 
     #[test]
@@ -24,7 +24,7 @@ use gdbstub::target::ext::section_offsets::Offsets;
 use crate::{
     ebpf,
     error::EbpfError,
-    interpreter::{DebugState, Interpreter},
+    interpreter::{DebugState, Interpreter, InterpreterState},
     memory_region::AccessType,
     translate_memory_access,
     verifier::Verifier,
@@ -48,7 +48,7 @@ fn wait_for_tcp(port: u16) -> DynResult<TcpStream> {
 pub fn execute<V: Verifier, C: ContextObject>(
     interpreter: &mut Interpreter<V, C>,
     port: u16,
-) -> (u64, ProgramResult) {
+) -> (u64, ProgramResult<C::UserError>) {
     let connection: Box<dyn ConnectionExt<Error = std::io::Error>> =
         Box::new(wait_for_tcp(port).expect("Cannot connect to Debugger"));
 
@@ -80,11 +80,11 @@ pub fn execute<V: Verifier, C: ContextObject>(
                 match interpreter.debug_state {
                     DebugState::Step => {
                         let mut stop_reason = match interpreter.step() {
-                            Ok(None) => SingleThreadStopReason::DoneStep,
-                            Ok(Some(step_result)) => {
+                            Ok(InterpreterState::Exited(step_result)) => {
                                 result = ProgramResult::Ok(step_result);
                                 SingleThreadStopReason::Exited(step_result as u8)
                             }
+                            Ok(_) => SingleThreadStopReason::DoneStep,
                             Err(err) => {
                                 result = ProgramResult::Err(err);
                                 SingleThreadStopReason::Terminated(Signal::SIGSTOP)
@@ -101,7 +101,15 @@ pub fn execute<V: Verifier, C: ContextObject>(
                             break dbg_inner.incoming_data(interpreter, byte).unwrap();
                         }
                         match interpreter.step() {
-                            Ok(None) => {
+                            Ok(InterpreterState::Exited(step_result)) => {
+                                break dbg_inner
+                                    .report_stop(
+                                        interpreter,
+                                        SingleThreadStopReason::Exited(step_result as u8),
+                                    )
+                                    .unwrap();
+                            }
+                            Ok(_) => {
                                 if interpreter.breakpoints.contains(&interpreter.get_dbg_pc()) {
                                     break dbg_inner
                                         .report_stop(
@@ -111,14 +119,6 @@ pub fn execute<V: Verifier, C: ContextObject>(
                                         .unwrap();
                                 }
                             }
-                            Ok(Some(step_result)) => {
-                                break dbg_inner
-                                    .report_stop(
-                                        interpreter,
-                                        SingleThreadStopReason::Exited(step_result as u8),
-                                    )
-                                    .unwrap();
-                            }
                             Err(err) => {
                                 result = ProgramResult::Err(err);
                                 break dbg_inner
@@ -191,9 +191,14 @@ fn get_host_ptr<V: Verifier, C: ContextObject>(
     interpreter: &mut Interpreter<V, C>,
     mut vm_addr: u64,
     pc: usize,
-) -> Result<*mut u8, EbpfError> {
+) -> Result<*mut u8, EbpfError<C::UserError>> {
     if vm_addr < ebpf::MM_PROGRAM_START {
-        vm_addr += ebpf::MM_PROGRAM_START;
+        vm_addr += interpreter
+            .vm
+            .verified_executable
+            .get_executable()
+            .get_config()
+            .rodata_vaddr_base;
     }
     Ok(translate_memory_access!(
         interpreter,
@@ -9,9 +9,53 @@
 //! for example to disassemble the code into a human-readable format.
 
 use crate::ebpf;
+use crate::elf::Executable;
 use crate::static_analysis::Analysis;
 use crate::vm::ContextObject;
 
+/// Operand of a disassembled instruction
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Operand {
+    /// Register by index
+    Register(u8),
+    /// Immediate value (the full 64 bits for lddw)
+    Immediate(i64),
+    /// Memory at a base register plus a signed offset
+    Memory(u8, i16),
+    /// Jump offset relative to the next instruction
+    Offset(i16),
+}
+
+/// Machine-readable representation of a single instruction
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DisassembledInsn {
+    /// Instruction pointer of the first slot
+    pub ptr: usize,
+    /// Number of instruction slots occupied (2 for lddw)
+    pub size: usize,
+    /// Operation code
+    pub opc: u8,
+    /// Mnemonic as understood by the assembler
+    pub mnemonic: &'static str,
+    /// Operands in the order of the assembly syntax
+    pub operands: Vec<Operand>,
+    /// Target instruction pointer of jumps
+    pub jump_target: Option<usize>,
+}
+
+/// How the targets of jumps and calls are rendered by the disassembler
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum JumpTargetFormat {
+    /// Label of the target basic block or function, e.g. `jeq r1, 0, lbb_42`
+    Label,
+    /// Raw offset relative to the next instruction, e.g. `jeq r1, 0, +5`
+    Offset,
+    /// Raw offset followed by the absolute target instruction index, e.g. `jeq r1, 0, +5 <insn 42>`
+    ///
+    /// Calls are annotated with the instruction index of the start of the called function.
+    OffsetAndIndex,
+}
+
 fn resolve_label<'a, C: ContextObject>(analysis: &'a Analysis<C>, pc: usize) -> &'a str {
     analysis
         .cfg_nodes
@@ -21,248 +65,390 @@ fn resolve_label<'a, C: ContextObject>(analysis: &'a Analysis<C>, pc: usize) ->
 }
 
 #[inline]
-fn alu_imm_str(name: &str, insn: &ebpf::Insn) -> String {
-    format!("{} r{}, {}", name, insn.dst, insn.imm)
+fn alu_imm(insn: &ebpf::Insn) -> Vec<Operand> {
+    vec![Operand::Register(insn.dst), Operand::Immediate(insn.imm)]
 }
 
 #[inline]
-fn alu_reg_str(name: &str, insn: &ebpf::Insn) -> String {
-    format!("{} r{}, r{}", name, insn.dst, insn.src)
+fn alu_reg(insn: &ebpf::Insn) -> Vec<Operand> {
+    vec![Operand::Register(insn.dst), Operand::Register(insn.src)]
 }
 
 #[inline]
-fn byteswap_str(name: &str, insn: &ebpf::Insn) -> String {
-    match insn.imm {
-        16 | 32 | 64 => {}
-        _ => println!(
-            "[Disassembler] Warning: Invalid offset value for {} insn",
-            name
-        ),
-    }
-    format!("{}{} r{}", name, insn.imm, insn.dst)
+fn alu_unary(insn: &ebpf::Insn) -> Vec<Operand> {
+    vec![Operand::Register(insn.dst)]
 }
 
 #[inline]
-fn signed_off_str(value: i16) -> String {
-    if value < 0 {
-        format!("-{:#x}", -value)
-    } else {
-        format!("+{:#x}", value)
-    }
+fn ld_st_imm(insn: &ebpf::Insn) -> Vec<Operand> {
+    vec![
+        Operand::Memory(insn.dst, insn.off),
+        Operand::Immediate(insn.imm),
+    ]
 }
 
 #[inline]
-fn ld_st_imm_str(name: &str, insn: &ebpf::Insn) -> String {
-    format!(
-        "{} [r{}{}], {}",
-        name,
-        insn.dst,
-        signed_off_str(insn.off),
-        insn.imm
-    )
+fn ld_reg(insn: &ebpf::Insn) -> Vec<Operand> {
+    vec![
+        Operand::Register(insn.dst),
+        Operand::Memory(insn.src, insn.off),
+    ]
 }
 
 #[inline]
-fn ld_reg_str(name: &str, insn: &ebpf::Insn) -> String {
-    format!(
-        "{} r{}, [r{}{}]",
-        name,
-        insn.dst,
-        insn.src,
-        signed_off_str(insn.off)
-    )
+fn st_reg(insn: &ebpf::Insn) -> Vec<Operand> {
+    vec![
+        Operand::Memory(insn.dst, insn.off),
+        Operand::Register(insn.src),
+    ]
 }
 
 #[inline]
-fn st_reg_str(name: &str, insn: &ebpf::Insn) -> String {
-    format!(
-        "{} [r{}{}], r{}",
-        name,
-        insn.dst,
-        signed_off_str(insn.off),
-        insn.src
-    )
+fn ldabs(insn: &ebpf::Insn) -> Vec<Operand> {
+    vec![Operand::Immediate(insn.imm)]
 }
 
 #[inline]
-fn ldabs_str(name: &str, insn: &ebpf::Insn) -> String {
-    format!("{} {}", name, insn.imm)
+fn ldind(insn: &ebpf::Insn) -> Vec<Operand> {
+    vec![Operand::Register(insn.src), Operand::Immediate(insn.imm)]
 }
 
 #[inline]
-fn ldind_str(name: &str, insn: &ebpf::Insn) -> String {
-    format!("{} r{}, {}", name, insn.src, insn.imm)
+fn jmp_imm(insn: &ebpf::Insn) -> Vec<Operand> {
+    vec![
+        Operand::Register(insn.dst),
+        Operand::Immediate(insn.imm),
+        Operand::Offset(insn.off),
+    ]
 }
 
 #[inline]
-fn jmp_imm_str<C: ContextObject>(name: &str, insn: &ebpf::Insn, analysis: &Analysis<C>) -> String {
-    let target_pc = (insn.ptr as isize + insn.off as isize + 1) as usize;
-    format!(
-        "{} r{}, {}, {}",
-        name,
-        insn.dst,
-        insn.imm,
-        resolve_label(analysis, target_pc)
-    )
+fn jmp_reg(insn: &ebpf::Insn) -> Vec<Operand> {
+    vec![
+        Operand::Register(insn.dst),
+        Operand::Register(insn.src),
+        Operand::Offset(insn.off),
+    ]
 }
 
-#[inline]
-fn jmp_reg_str<C: ContextObject>(name: &str, insn: &ebpf::Insn, analysis: &Analysis<C>) -> String {
-    let target_pc = (insn.ptr as isize + insn.off as isize + 1) as usize;
-    format!(
-        "{} r{}, r{}, {}",
-        name,
-        insn.dst,
-        insn.src,
-        resolve_label(analysis, target_pc)
-    )
+#[rustfmt::skip]
+fn atomic_mnemonic(insn: &ebpf::Insn) -> &'static str {
+    let dw = insn.opc == ebpf::ST_DW_ATOMIC;
+    match (insn.imm, dw) {
+        (ebpf::ATOMIC_ADD,       false) => "stxxaddw",
+        (ebpf::ATOMIC_ADD,       true)  => "stxxadddw",
+        (ebpf::ATOMIC_OR,        false) => "stxxorw",
+        (ebpf::ATOMIC_OR,        true)  => "stxxordw",
+        (ebpf::ATOMIC_AND,       false) => "stxxandw",
+        (ebpf::ATOMIC_AND,       true)  => "stxxanddw",
+        (ebpf::ATOMIC_XOR,       false) => "stxxxorw",
+        (ebpf::ATOMIC_XOR,       true)  => "stxxxordw",
+        (ebpf::ATOMIC_FETCH_ADD, false) => "stxfetchaddw",
+        (ebpf::ATOMIC_FETCH_ADD, true)  => "stxfetchadddw",
+        (ebpf::ATOMIC_FETCH_OR,  false) => "stxfetchorw",
+        (ebpf::ATOMIC_FETCH_OR,  true)  => "stxfetchordw",
+        (ebpf::ATOMIC_FETCH_AND, false) => "stxfetchandw",
+        (ebpf::ATOMIC_FETCH_AND, true)  => "stxfetchanddw",
+        (ebpf::ATOMIC_FETCH_XOR, false) => "stxfetchxorw",
+        (ebpf::ATOMIC_FETCH_XOR, true)  => "stxfetchxordw",
+        (ebpf::ATOMIC_XCHG,      false) => "stxxchgw",
+        (ebpf::ATOMIC_XCHG,      true)  => "stxxchgdw",
+        (ebpf::ATOMIC_CMPXCHG,   false) => "stxcmpxchgw",
+        (ebpf::ATOMIC_CMPXCHG,   true)  => "stxcmpxchgdw",
+        _                               => "unknown",
+    }
 }
 
-/// Disassemble an eBPF instruction
+/// Disassemble an eBPF instruction into a structured record
+///
+/// The immediate of lddw is expected to be already augmented by its second slot,
+/// see [`disassemble_program`] for decoding raw bytecode.
 #[rustfmt::skip]
-pub fn disassemble_instruction<C: ContextObject>(insn: &ebpf::Insn, analysis: &Analysis<C>) -> String {
-    let name;
-    let desc;
-    match insn.opc {
+pub fn disassemble_instruction(insn: &ebpf::Insn) -> DisassembledInsn {
+    let (mnemonic, operands) = match insn.opc {
         // BPF_LD class
-        ebpf::LD_ABS_B   => { name = "ldabsb";  desc = ldabs_str(name, insn); },
-        ebpf::LD_ABS_H   => { name = "ldabsh";  desc = ldabs_str(name, insn); },
-        ebpf::LD_ABS_W   => { name = "ldabsw";  desc = ldabs_str(name, insn); },
-        ebpf::LD_ABS_DW  => { name = "ldabsdw"; desc = ldabs_str(name, insn); },
-        ebpf::LD_IND_B   => { name = "ldindb";  desc = ldind_str(name, insn); },
-        ebpf::LD_IND_H   => { name = "ldindh";  desc = ldind_str(name, insn); },
-        ebpf::LD_IND_W   => { name = "ldindw";  desc = ldind_str(name, insn); },
-        ebpf::LD_IND_DW  => { name = "ldinddw"; desc = ldind_str(name, insn); },
+        ebpf::LD_ABS_B   => ("ldabsb",  ldabs(insn)),
+        ebpf::LD_ABS_H   => ("ldabsh",  ldabs(insn)),
+        ebpf::LD_ABS_W   => ("ldabsw",  ldabs(insn)),
+        ebpf::LD_ABS_DW  => ("ldabsdw", ldabs(insn)),
+        ebpf::LD_IND_B   => ("ldindb",  ldind(insn)),
+        ebpf::LD_IND_H   => ("ldindh",  ldind(insn)),
+        ebpf::LD_IND_W   => ("ldindw",  ldind(insn)),
+        ebpf::LD_IND_DW  => ("ldinddw", ldind(insn)),
 
-        ebpf::LD_DW_IMM  => { name = "lddw"; desc = format!("{} r{:}, {:#x}", name, insn.dst, insn.imm); },
+        ebpf::LD_DW_IMM  => ("lddw", alu_imm(insn)),
 
         // BPF_LDX class
-        ebpf::LD_B_REG   => { name = "ldxb";  desc = ld_reg_str(name, insn); },
-        ebpf::LD_H_REG   => { name = "ldxh";  desc = ld_reg_str(name, insn); },
-        ebpf::LD_W_REG   => { name = "ldxw";  desc = ld_reg_str(name, insn); },
-        ebpf::LD_DW_REG  => { name = "ldxdw"; desc = ld_reg_str(name, insn); },
+        ebpf::LD_B_REG   => ("ldxb",  ld_reg(insn)),
+        ebpf::LD_H_REG   => ("ldxh",  ld_reg(insn)),
+        ebpf::LD_W_REG   => ("ldxw",  ld_reg(insn)),
+        ebpf::LD_DW_REG  => ("ldxdw", ld_reg(insn)),
 
         // BPF_ST class
-        ebpf::ST_B_IMM   => { name = "stb";  desc = ld_st_imm_str(name, insn); },
-        ebpf::ST_H_IMM   => { name = "sth";  desc = ld_st_imm_str(name, insn); },
-        ebpf::ST_W_IMM   => { name = "stw";  desc = ld_st_imm_str(name, insn); },
-        ebpf::ST_DW_IMM  => { name = "stdw"; desc = ld_st_imm_str(name, insn); },
+        ebpf::ST_B_IMM   => ("stb",  ld_st_imm(insn)),
+        ebpf::ST_H_IMM   => ("sth",  ld_st_imm(insn)),
+        ebpf::ST_W_IMM   => ("stw",  ld_st_imm(insn)),
+        ebpf::ST_DW_IMM  => ("stdw", ld_st_imm(insn)),
 
         // BPF_STX class
-        ebpf::ST_B_REG   => { name = "stxb";      desc = st_reg_str(name, insn); },
-        ebpf::ST_H_REG   => { name = "stxh";      desc = st_reg_str(name, insn); },
-        ebpf::ST_W_REG   => { name = "stxw";      desc = st_reg_str(name, insn); },
-        ebpf::ST_DW_REG  => { name = "stxdw";     desc = st_reg_str(name, insn); },
-        ebpf::ST_W_XADD  => { name = "stxxaddw";  desc = st_reg_str(name, insn); },
-        ebpf::ST_DW_XADD => { name = "stxxadddw"; desc = st_reg_str(name, insn); },
+        ebpf::ST_B_REG   => ("stxb",      st_reg(insn)),
+        ebpf::ST_H_REG   => ("stxh",      st_reg(insn)),
+        ebpf::ST_W_REG   => ("stxw",      st_reg(insn)),
+        ebpf::ST_DW_REG  => ("stxdw",     st_reg(insn)),
+        ebpf::ST_W_ATOMIC | ebpf::ST_DW_ATOMIC => (atomic_mnemonic(insn), st_reg(insn)),
 
         // BPF_ALU class
-        ebpf::ADD32_IMM  => { name = "add32";  desc = alu_imm_str(name, insn);  },
-        ebpf::ADD32_REG  => { name = "add32";  desc = alu_reg_str(name, insn);  },
-        ebpf::SUB32_IMM  => { name = "sub32";  desc = alu_imm_str(name, insn);  },
-        ebpf::SUB32_REG  => { name = "sub32";  desc = alu_reg_str(name, insn);  },
-        ebpf::MUL32_IMM  => { name = "mul32";  desc = alu_imm_str(name, insn);  },
-        ebpf::MUL32_REG  => { name = "mul32";  desc = alu_reg_str(name, insn);  },
-        ebpf::DIV32_IMM  => { name = "div32";  desc = alu_imm_str(name, insn);  },
-        ebpf::DIV32_REG  => { name = "div32";  desc = alu_reg_str(name, insn);  },
-        ebpf::SDIV32_IMM => { name = "sdiv32"; desc = alu_imm_str(name, insn);  },
-        ebpf::SDIV32_REG => { name = "sdiv32"; desc = alu_reg_str(name, insn);  },
-        ebpf::OR32_IMM   => { name = "or32";   desc = alu_imm_str(name, insn);  },
-        ebpf::OR32_REG   => { name = "or32";   desc = alu_reg_str(name, insn);  },
-        ebpf::AND32_IMM  => { name = "and32";  desc = alu_imm_str(name, insn);  },
-        ebpf::AND32_REG  => { name = "and32";  desc = alu_reg_str(name, insn);  },
-        ebpf::LSH32_IMM  => { name = "lsh32";  desc = alu_imm_str(name, insn);  },
-        ebpf::LSH32_REG  => { name = "lsh32";  desc = alu_reg_str(name, insn);  },
-        ebpf::RSH32_IMM  => { name = "rsh32";  desc = alu_imm_str(name, insn);  },
-        ebpf::RSH32_REG  => { name = "rsh32";  desc = alu_reg_str(name, insn);  },
-        ebpf::NEG32      => { name = "neg32";  desc = format!("{} r{}", name, insn.dst); },
-        ebpf::MOD32_IMM  => { name = "mod32";  desc = alu_imm_str(name, insn);  },
-        ebpf::MOD32_REG  => { name = "mod32";  desc = alu_reg_str(name, insn);  },
-        ebpf::XOR32_IMM  => { name = "xor32";  desc = alu_imm_str(name, insn);  },
-        ebpf::XOR32_REG  => { name = "xor32";  desc = alu_reg_str(name, insn);  },
-        ebpf::MOV32_IMM  => { name = "mov32";  desc = alu_imm_str(name, insn);  },
-        ebpf::MOV32_REG  => { name = "mov32";  desc = alu_reg_str(name, insn);  },
-        ebpf::ARSH32_IMM => { name = "arsh32"; desc = alu_imm_str(name, insn);  },
-        ebpf::ARSH32_REG => { name = "arsh32"; desc = alu_reg_str(name, insn);  },
-        ebpf::LE         => { name = "le";     desc = byteswap_str(name, insn); },
-        ebpf::BE         => { name = "be";     desc = byteswap_str(name, insn); },
+        ebpf::ADD32_IMM  => ("add32",  alu_imm(insn)),
+        ebpf::ADD32_REG  => ("add32",  alu_reg(insn)),
+        ebpf::SUB32_IMM  => ("sub32",  alu_imm(insn)),
+        ebpf::SUB32_REG  => ("sub32",  alu_reg(insn)),
+        ebpf::MUL32_IMM  => ("mul32",  alu_imm(insn)),
+        ebpf::MUL32_REG  => ("mul32",  alu_reg(insn)),
+        ebpf::DIV32_IMM  => ("div32",  alu_imm(insn)),
+        ebpf::DIV32_REG  => ("div32",  alu_reg(insn)),
+        ebpf::SDIV32_IMM => ("sdiv32", alu_imm(insn)),
+        ebpf::SDIV32_REG => ("sdiv32", alu_reg(insn)),
+        ebpf::OR32_IMM   => ("or32",   alu_imm(insn)),
+        ebpf::OR32_REG   => ("or32",   alu_reg(insn)),
+        ebpf::AND32_IMM  => ("and32",  alu_imm(insn)),
+        ebpf::AND32_REG  => ("and32",  alu_reg(insn)),
+        ebpf::LSH32_IMM  => ("lsh32",  alu_imm(insn)),
+        ebpf::LSH32_REG  => ("lsh32",  alu_reg(insn)),
+        ebpf::RSH32_IMM  => ("rsh32",  alu_imm(insn)),
+        ebpf::RSH32_REG  => ("rsh32",  alu_reg(insn)),
+        ebpf::NEG32      => ("neg32",  alu_unary(insn)),
+        ebpf::MOD32_IMM  => ("mod32",  alu_imm(insn)),
+        ebpf::MOD32_REG  => ("mod32",  alu_reg(insn)),
+        ebpf::XOR32_IMM  => ("xor32",  alu_imm(insn)),
+        ebpf::XOR32_REG  => ("xor32",  alu_reg(insn)),
+        ebpf::MOV32_IMM  => ("mov32",  alu_imm(insn)),
+        ebpf::MOV32_REG  => ("mov32",  alu_reg(insn)),
+        ebpf::ARSH32_IMM => ("arsh32", alu_imm(insn)),
+        ebpf::ARSH32_REG => ("arsh32", alu_reg(insn)),
+        ebpf::LE         => ("le",     alu_imm(insn)),
+        ebpf::BE         => ("be",     alu_imm(insn)),
 
         // BPF_ALU64 class
-        ebpf::ADD64_IMM  => { name = "add64";  desc = alu_imm_str(name, insn); },
-        ebpf::ADD64_REG  => { name = "add64";  desc = alu_reg_str(name, insn); },
-        ebpf::SUB64_IMM  => { name = "sub64";  desc = alu_imm_str(name, insn); },
-        ebpf::SUB64_REG  => { name = "sub64";  desc = alu_reg_str(name, insn); },
-        ebpf::MUL64_IMM  => { name = "mul64";  desc = alu_imm_str(name, insn); },
-        ebpf::MUL64_REG  => { name = "mul64";  desc = alu_reg_str(name, insn); },
-        ebpf::DIV64_IMM  => { name = "div64";  desc = alu_imm_str(name, insn); },
-        ebpf::DIV64_REG  => { name = "div64";  desc = alu_reg_str(name, insn); },
-        ebpf::SDIV64_IMM => { name = "sdiv64"; desc = alu_imm_str(name, insn); },
-        ebpf::SDIV64_REG => { name = "sdiv64"; desc = alu_reg_str(name, insn); },
-        ebpf::OR64_IMM   => { name = "or64";   desc = alu_imm_str(name, insn); },
-        ebpf::OR64_REG   => { name = "or64";   desc = alu_reg_str(name, insn); },
-        ebpf::AND64_IMM  => { name = "and64";  desc = alu_imm_str(name, insn); },
-        ebpf::AND64_REG  => { name = "and64";  desc = alu_reg_str(name, insn); },
-        ebpf::LSH64_IMM  => { name = "lsh64";  desc = alu_imm_str(name, insn); },
-        ebpf::LSH64_REG  => { name = "lsh64";  desc = alu_reg_str(name, insn); },
-        ebpf::RSH64_IMM  => { name = "rsh64";  desc = alu_imm_str(name, insn); },
-        ebpf::RSH64_REG  => { name = "rsh64";  desc = alu_reg_str(name, insn); },
-        ebpf::NEG64      => { name = "neg64";  desc = format!("{} r{}", name, insn.dst); },
-        ebpf::MOD64_IMM  => { name = "mod64";  desc = alu_imm_str(name, insn); },
-        ebpf::MOD64_REG  => { name = "mod64";  desc = alu_reg_str(name, insn); },
-        ebpf::XOR64_IMM  => { name = "xor64";  desc = alu_imm_str(name, insn); },
-        ebpf::XOR64_REG  => { name = "xor64";  desc = alu_reg_str(name, insn); },
-        ebpf::MOV64_IMM  => { name = "mov64";  desc = alu_imm_str(name, insn); },
-        ebpf::MOV64_REG  => { name = "mov64";  desc = alu_reg_str(name, insn); },
-        ebpf::ARSH64_IMM => { name = "arsh64"; desc = alu_imm_str(name, insn); },
-        ebpf::ARSH64_REG => { name = "arsh64"; desc = alu_reg_str(name, insn); },
+        ebpf::ADD64_IMM  => ("add64",  alu_imm(insn)),
+        ebpf::ADD64_REG  => ("add64",  alu_reg(insn)),
+        ebpf::SUB64_IMM  => ("sub64",  alu_imm(insn)),
+        ebpf::SUB64_REG  => ("sub64",  alu_reg(insn)),
+        ebpf::MUL64_IMM  => ("mul64",  alu_imm(insn)),
+        ebpf::MUL64_REG  => ("mul64",  alu_reg(insn)),
+        ebpf::DIV64_IMM  => ("div64",  alu_imm(insn)),
+        ebpf::DIV64_REG  => ("div64",  alu_reg(insn)),
+        ebpf::SDIV64_IMM => ("sdiv64", alu_imm(insn)),
+        ebpf::SDIV64_REG => ("sdiv64", alu_reg(insn)),
+        ebpf::UHMUL64_IMM => ("uhmul64", alu_imm(insn)),
+        ebpf::UHMUL64_REG => ("uhmul64", alu_reg(insn)),
+        ebpf::OR64_IMM   => ("or64",   alu_imm(insn)),
+        ebpf::OR64_REG   => ("or64",   alu_reg(insn)),
+        ebpf::AND64_IMM  => ("and64",  alu_imm(insn)),
+        ebpf::AND64_REG  => ("and64",  alu_reg(insn)),
+        ebpf::LSH64_IMM  => ("lsh64",  alu_imm(insn)),
+        ebpf::LSH64_REG  => ("lsh64",  alu_reg(insn)),
+        ebpf::RSH64_IMM  => ("rsh64",  alu_imm(insn)),
+        ebpf::RSH64_REG  => ("rsh64",  alu_reg(insn)),
+        ebpf::NEG64      => ("neg64",  alu_unary(insn)),
+        ebpf::MOD64_IMM  => ("mod64",  alu_imm(insn)),
+        ebpf::MOD64_REG  => ("mod64",  alu_reg(insn)),
+        ebpf::XOR64_IMM  => ("xor64",  alu_imm(insn)),
+        ebpf::XOR64_REG  => ("xor64",  alu_reg(insn)),
+        ebpf::MOV64_IMM  => ("mov64",  alu_imm(insn)),
+        ebpf::MOV64_REG  => ("mov64",  alu_reg(insn)),
+        ebpf::ARSH64_IMM => ("arsh64", alu_imm(insn)),
+        ebpf::ARSH64_REG => ("arsh64", alu_reg(insn)),
 
         // BPF_JMP class
-        ebpf::JA         => {
-            name = "ja";
-            let target_pc = (insn.ptr as isize + insn.off as isize + 1) as usize;
-            desc = format!("{} {}", name, resolve_label(analysis, target_pc));
-        },
-        ebpf::JEQ_IMM    => { name = "jeq";  desc = jmp_imm_str(name, insn, analysis); },
-        ebpf::JEQ_REG    => { name = "jeq";  desc = jmp_reg_str(name, insn, analysis); },
-        ebpf::JGT_IMM    => { name = "jgt";  desc = jmp_imm_str(name, insn, analysis); },
-        ebpf::JGT_REG    => { name = "jgt";  desc = jmp_reg_str(name, insn, analysis); },
-        ebpf::JGE_IMM    => { name = "jge";  desc = jmp_imm_str(name, insn, analysis); },
-        ebpf::JGE_REG    => { name = "jge";  desc = jmp_reg_str(name, insn, analysis); },
-        ebpf::JLT_IMM    => { name = "jlt";  desc = jmp_imm_str(name, insn, analysis); },
-        ebpf::JLT_REG    => { name = "jlt";  desc = jmp_reg_str(name, insn, analysis); },
-        ebpf::JLE_IMM    => { name = "jle";  desc = jmp_imm_str(name, insn, analysis); },
-        ebpf::JLE_REG    => { name = "jle";  desc = jmp_reg_str(name, insn, analysis); },
-        ebpf::JSET_IMM   => { name = "jset"; desc = jmp_imm_str(name, insn, analysis); },
-        ebpf::JSET_REG   => { name = "jset"; desc = jmp_reg_str(name, insn, analysis); },
-        ebpf::JNE_IMM    => { name = "jne";  desc = jmp_imm_str(name, insn, analysis); },
-        ebpf::JNE_REG    => { name = "jne";  desc = jmp_reg_str(name, insn, analysis); },
-        ebpf::JSGT_IMM   => { name = "jsgt"; desc = jmp_imm_str(name, insn, analysis); },
-        ebpf::JSGT_REG   => { name = "jsgt"; desc = jmp_reg_str(name, insn, analysis); },
-        ebpf::JSGE_IMM   => { name = "jsge"; desc = jmp_imm_str(name, insn, analysis); },
-        ebpf::JSGE_REG   => { name = "jsge"; desc = jmp_reg_str(name, insn, analysis); },
-        ebpf::JSLT_IMM   => { name = "jslt"; desc = jmp_imm_str(name, insn, analysis); },
-        ebpf::JSLT_REG   => { name = "jslt"; desc = jmp_reg_str(name, insn, analysis); },
-        ebpf::JSLE_IMM   => { name = "jsle"; desc = jmp_imm_str(name, insn, analysis); },
-        ebpf::JSLE_REG   => { name = "jsle"; desc = jmp_reg_str(name, insn, analysis); },
-        ebpf::CALL_IMM   => {
-            desc = if let Some(syscall_name) = analysis.executable.get_syscall_symbols().get(&(insn.imm as u32)) {
-                name = "syscall";
-                format!("{} {}", name, syscall_name)
-            } else {
-                name = "call";
-                if let Some(target_pc) = analysis
-                    .executable
-                    .lookup_bpf_function(insn.imm as u32) {
-                    format!("{} {}", name, resolve_label(analysis, target_pc))
+        ebpf::JA         => ("ja",   vec![Operand::Offset(insn.off)]),
+        ebpf::JEQ_IMM    => ("jeq",  jmp_imm(insn)),
+        ebpf::JEQ_REG    => ("jeq",  jmp_reg(insn)),
+        ebpf::JGT_IMM    => ("jgt",  jmp_imm(insn)),
+        ebpf::JGT_REG    => ("jgt",  jmp_reg(insn)),
+        ebpf::JGE_IMM    => ("jge",  jmp_imm(insn)),
+        ebpf::JGE_REG    => ("jge",  jmp_reg(insn)),
+        ebpf::JLT_IMM    => ("jlt",  jmp_imm(insn)),
+        ebpf::JLT_REG    => ("jlt",  jmp_reg(insn)),
+        ebpf::JLE_IMM    => ("jle",  jmp_imm(insn)),
+        ebpf::JLE_REG    => ("jle",  jmp_reg(insn)),
+        ebpf::JSET_IMM   => ("jset", jmp_imm(insn)),
+        ebpf::JSET_REG   => ("jset", jmp_reg(insn)),
+        ebpf::JNE_IMM    => ("jne",  jmp_imm(insn)),
+        ebpf::JNE_REG    => ("jne",  jmp_reg(insn)),
+        ebpf::JSGT_IMM   => ("jsgt", jmp_imm(insn)),
+        ebpf::JSGT_REG   => ("jsgt", jmp_reg(insn)),
+        ebpf::JSGE_IMM   => ("jsge", jmp_imm(insn)),
+        ebpf::JSGE_REG   => ("jsge", jmp_reg(insn)),
+        ebpf::JSLT_IMM   => ("jslt", jmp_imm(insn)),
+        ebpf::JSLT_REG   => ("jslt", jmp_reg(insn)),
+        ebpf::JSLE_IMM   => ("jsle", jmp_imm(insn)),
+        ebpf::JSLE_REG   => ("jsle", jmp_reg(insn)),
+        ebpf::CALL_IMM   => ("call",  vec![Operand::Immediate(insn.imm)]),
+        ebpf::CALL_REG   => ("callx", vec![Operand::Register(insn.imm as u8)]),
+        ebpf::EXIT       => ("exit",  vec![]),
+
+        // BPF_JMP32 class
+        ebpf::JEQ32_IMM  => ("jeq32",  jmp_imm(insn)),
+        ebpf::JEQ32_REG  => ("jeq32",  jmp_reg(insn)),
+        ebpf::JGT32_IMM  => ("jgt32",  jmp_imm(insn)),
+        ebpf::JGT32_REG  => ("jgt32",  jmp_reg(insn)),
+        ebpf::JGE32_IMM  => ("jge32",  jmp_imm(insn)),
+        ebpf::JGE32_REG  => ("jge32",  jmp_reg(insn)),
+        ebpf::JLT32_IMM  => ("jlt32",  jmp_imm(insn)),
+        ebpf::JLT32_REG  => ("jlt32",  jmp_reg(insn)),
+        ebpf::JLE32_IMM  => ("jle32",  jmp_imm(insn)),
+        ebpf::JLE32_REG  => ("jle32",  jmp_reg(insn)),
+        ebpf::JSET32_IMM => ("jset32", jmp_imm(insn)),
+        ebpf::JSET32_REG => ("jset32", jmp_reg(insn)),
+        ebpf::JNE32_IMM  => ("jne32",  jmp_imm(insn)),
+        ebpf::JNE32_REG  => ("jne32",  jmp_reg(insn)),
+        ebpf::JSGT32_IMM => ("jsgt32", jmp_imm(insn)),
+        ebpf::JSGT32_REG => ("jsgt32", jmp_reg(insn)),
+        ebpf::JSGE32_IMM => ("jsge32", jmp_imm(insn)),
+        ebpf::JSGE32_REG => ("jsge32", jmp_reg(insn)),
+        ebpf::JSLT32_IMM => ("jslt32", jmp_imm(insn)),
+        ebpf::JSLT32_REG => ("jslt32", jmp_reg(insn)),
+        ebpf::JSLE32_IMM => ("jsle32", jmp_imm(insn)),
+        ebpf::JSLE32_REG => ("jsle32", jmp_reg(insn)),
+
+        _                => ("unknown", vec![]),
+    };
+    let jump_target = operands.iter().find_map(|operand| match operand {
+        Operand::Offset(off) => {
+            let target_pc = insn.ptr as isize + *off as isize + 1;
+            (target_pc >= 0).then_some(target_pc as usize)
+        }
+        _ => None,
+    });
+    DisassembledInsn {
+        ptr: insn.ptr,
+        size: if insn.opc == ebpf::LD_DW_IMM { 2 } else { 1 },
+        opc: insn.opc,
+        mnemonic,
+        operands,
+        jump_target,
+    }
+}
+
+/// Disassemble raw bytecode into structured records, one per instruction
+///
+/// Two slot instructions (lddw) produce a single record carrying the full 64 bit immediate.
+pub fn disassemble_program(prog: &[u8]) -> Vec<DisassembledInsn> {
+    let mut result = Vec::new();
+    let mut pc = 0;
+    while (pc + 1) * ebpf::INSN_SIZE <= prog.len() {
+        let mut insn = ebpf::get_insn_unchecked(prog, pc);
+        if insn.opc == ebpf::LD_DW_IMM && (pc + 2) * ebpf::INSN_SIZE <= prog.len() {
+            ebpf::augment_lddw_unchecked(prog, &mut insn);
+        }
+        let record = disassemble_instruction(&insn);
+        pc += record.size;
+        result.push(record);
+    }
+    result
+}
+
+#[inline]
+fn signed_off_str(value: i16) -> String {
+    if value < 0 {
+        format!("-{:#x}", -value)
+    } else {
+        format!("+{:#x}", value)
+    }
+}
+
+/// Disassemble an eBPF instruction into a human-readable string
+pub fn disassemble_instruction_to_string<C: ContextObject>(
+    insn: &ebpf::Insn,
+    analysis: &Analysis<C>,
+) -> String {
+    disassemble_instruction_to_string_with_format(insn, analysis, JumpTargetFormat::Label)
+}
+
+/// Disassemble an eBPF instruction into a human-readable string, rendering the targets of jumps
+/// and calls as specified by `format`
+pub fn disassemble_instruction_to_string_with_format<C: ContextObject>(
+    insn: &ebpf::Insn,
+    analysis: &Analysis<C>,
+    format: JumpTargetFormat,
+) -> String {
+    let record = disassemble_instruction(insn);
+    match (record.opc, record.operands.as_slice()) {
+        (ebpf::LD_DW_IMM, [Operand::Register(dst), Operand::Immediate(imm)]) => {
+            format!("{} r{:}, {:#x}", record.mnemonic, dst, imm)
+        }
+        (ebpf::LE | ebpf::BE, [Operand::Register(dst), Operand::Immediate(imm)]) => {
+            match imm {
+                16 | 32 | 64 => {}
+                _ => println!(
+                    "[Disassembler] Warning: Invalid offset value for {} insn",
+                    record.mnemonic
+                ),
+            }
+            format!("{}{} r{}", record.mnemonic, imm, dst)
+        }
+        (ebpf::CALL_IMM, _) => {
+            if let Some(syscall_name) = analysis
+                .executable
+                .get_syscall_symbols()
+                .get(&(insn.imm as u32))
+            {
+                format!("syscall {}", syscall_name)
+            } else if let Some(target_pc) = analysis.executable.lookup_bpf_function(insn.imm as u32)
+            {
+                let label = resolve_label(analysis, target_pc);
+                if format == JumpTargetFormat::OffsetAndIndex {
+                    format!("{} {} <insn {}>", record.mnemonic, label, target_pc)
                 } else {
-                    format!("{} [invalid]", name)
+                    format!("{} {}", record.mnemonic, label)
                 }
-            };
-        },
-        ebpf::CALL_REG   => { name = "callx"; desc = format!("{} r{}", name, insn.imm); },
-        ebpf::EXIT       => { name = "exit"; desc = name.to_string(); },
+            } else {
+                format!("{} [invalid]", record.mnemonic)
+            }
+        }
+        (_, []) if record.mnemonic == "unknown" => {
+            format!("{} opcode={:#x}", record.mnemonic, record.opc)
+        }
+        (_, []) => record.mnemonic.to_string(),
+        (_, operands) => {
+            let operands = operands
+                .iter()
+                .map(|operand| match operand {
+                    Operand::Register(reg) => format!("r{}", reg),
+                    Operand::Immediate(imm) => format!("{}", imm),
+                    Operand::Memory(reg, off) => format!("[r{}{}]", reg, signed_off_str(*off)),
+                    Operand::Offset(off) => match format {
+                        JumpTargetFormat::Label => record
+                            .jump_target
+                            .map_or("[invalid]", |target_pc| resolve_label(analysis, target_pc))
+                            .to_string(),
+                        JumpTargetFormat::Offset => format!("{:+}", off),
+                        JumpTargetFormat::OffsetAndIndex => match record.jump_target {
+                            Some(target_pc) => format!("{:+} <insn {}>", off, target_pc),
+                            None => format!("{:+} <invalid>", off),
+                        },
+                    },
+                })
+                .collect::<Vec<_>>();
+            format!("{} {}", record.mnemonic, operands.join(", "))
+        }
+    }
+}
 
-        _                => { name = "unknown"; desc = format!("{} opcode={:#x}", name, insn.opc); },
-    };
-    desc
+/// Disassemble the program of an executable into assembler code
+///
+/// Jump targets and functions are labeled and syscalls are printed with their names.
+pub fn disassemble<C: ContextObject, W: std::io::Write>(
+    executable: &Executable<C>,
+    output: &mut W,
+) -> std::io::Result<()> {
+    let analysis = Analysis::from_executable(executable)
+        .map_err(|err| std::io::Error::new(std::io::ErrorKind::InvalidData, err.to_string()))?;
+    analysis.disassemble(output)
+}
+
+/// Disassemble the program of an executable and print it to stdout, see `disassemble()`
+pub fn disassemble_to_stdout<C: ContextObject>(executable: &Executable<C>) -> std::io::Result<()> {
+    let stdout = std::io::stdout();
+    disassemble(executable, &mut stdout.lock())
 }
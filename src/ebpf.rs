@@ -18,9 +18,10 @@
 //! <https://www.kernel.org/doc/Documentation/networking/filter.txt>, or for a shorter version of
 //! the list of the operation codes: <https://github.com/iovisor/bpf-docs/blob/master/eBPF.md>
 
+use alloc::vec::Vec;
 use byteorder::{ByteOrder, LittleEndian};
+use core::fmt;
 use hash32::{Hash, Hasher, Murmur3Hasher};
-use std::fmt;
 
 /// SBF version flag
 pub const EF_SBF_V2: u32 = 0x20;
@@ -42,6 +43,8 @@ pub const SCRATCH_REGS: usize = 4;
 pub const ELF_INSN_DUMP_OFFSET: usize = 29;
 /// Alignment of the memory regions in host address space in bytes
 pub const HOST_ALIGN: usize = 16;
+/// Smallest stack frame a function with a recorded stack size gets, which bounds the call depth
+pub const MIN_STACK_FRAME_SIZE: usize = 64;
 /// Upper half of a pointer is the region index, lower half the virtual address inside that region.
 pub const VIRTUAL_ADDRESS_BITS: usize = 32;
 
@@ -73,7 +76,8 @@ pub const BPF_STX: u8 = 0x03;
 pub const BPF_ALU: u8 = 0x04;
 /// BPF operation class: jump.
 pub const BPF_JMP: u8 = 0x05;
-// [ class 6 unused, reserved for future use ]
+/// BPF operation class: jump comparing only the low 32 bits of the operands.
+pub const BPF_JMP32: u8 = 0x06;
 /// BPF operation class: 64 bits arithmetic operation.
 pub const BPF_ALU64: u8 = 0x07;
 
@@ -107,6 +111,8 @@ pub const BPF_MEM: u8 = 0x60;
 // [ 0xa0 reserved ]
 /// BPF mode modifier: exclusive add.
 pub const BPF_XADD: u8 = 0xc0;
+/// BPF mode modifier: atomic read-modify-write, the operation is encoded in the immediate.
+pub const BPF_ATOMIC: u8 = 0xc0;
 
 // For arithmetic (BPF_ALU/BPF_ALU64) and jump (BPF_JMP) instructions:
 // +----------------+--------+--------+
@@ -152,6 +158,8 @@ pub const BPF_ARSH: u8 = 0xc0;
 pub const BPF_END: u8 = 0xd0;
 /// BPF ALU/ALU64 operation code: signed division.
 pub const BPF_SDIV: u8 = 0xe0;
+/// BPF ALU64 operation code: unsigned 128 bit multiplication into a register pair.
+pub const BPF_UHMUL: u8 = 0xf0;
 
 // Operation codes -- BPF_JMP class:
 /// BPF JMP operation code: jump.
@@ -235,6 +243,34 @@ pub const ST_DW_REG: u8 = BPF_STX | BPF_MEM | BPF_DW;
 pub const ST_W_XADD: u8 = BPF_STX | BPF_XADD | BPF_W;
 /// BPF opcode: `stxxadddw [dst + off], src`.
 pub const ST_DW_XADD: u8 = BPF_STX | BPF_XADD | BPF_DW;
+/// BPF opcode: 32-bit atomic operation on `(dst + off) as u32` with `src`, see `ATOMIC_*`.
+pub const ST_W_ATOMIC: u8 = BPF_STX | BPF_ATOMIC | BPF_W;
+/// BPF opcode: 64-bit atomic operation on `(dst + off) as u64` with `src`, see `ATOMIC_*`.
+pub const ST_DW_ATOMIC: u8 = BPF_STX | BPF_ATOMIC | BPF_DW;
+
+// Atomic operations, encoded in the immediate of ST_W_ATOMIC and ST_DW_ATOMIC:
+/// BPF atomic operation modifier: also load the previous value of the memory into `src`.
+pub const BPF_FETCH: i64 = 0x01;
+/// BPF atomic operation: `(dst + off) += src`.
+pub const ATOMIC_ADD: i64 = BPF_ADD as i64;
+/// BPF atomic operation: `(dst + off) |= src`.
+pub const ATOMIC_OR: i64 = BPF_OR as i64;
+/// BPF atomic operation: `(dst + off) &= src`.
+pub const ATOMIC_AND: i64 = BPF_AND as i64;
+/// BPF atomic operation: `(dst + off) ^= src`.
+pub const ATOMIC_XOR: i64 = BPF_XOR as i64;
+/// BPF atomic operation: `src = (dst + off); (dst + off) += src`.
+pub const ATOMIC_FETCH_ADD: i64 = ATOMIC_ADD | BPF_FETCH;
+/// BPF atomic operation: `src = (dst + off); (dst + off) |= src`.
+pub const ATOMIC_FETCH_OR: i64 = ATOMIC_OR | BPF_FETCH;
+/// BPF atomic operation: `src = (dst + off); (dst + off) &= src`.
+pub const ATOMIC_FETCH_AND: i64 = ATOMIC_AND | BPF_FETCH;
+/// BPF atomic operation: `src = (dst + off); (dst + off) ^= src`.
+pub const ATOMIC_FETCH_XOR: i64 = ATOMIC_XOR | BPF_FETCH;
+/// BPF atomic operation: swap `src` and `(dst + off)`.
+pub const ATOMIC_XCHG: i64 = 0xe0 | BPF_FETCH;
+/// BPF atomic operation: `r0 = (dst + off); if r0 == old r0 { (dst + off) = src }`.
+pub const ATOMIC_CMPXCHG: i64 = 0xf0 | BPF_FETCH;
 
 /// BPF opcode: `add32 dst, imm` /// `dst += imm`.
 pub const ADD32_IMM: u8 = BPF_ALU | BPF_K | BPF_ADD;
@@ -358,6 +394,14 @@ pub const ARSH64_REG: u8 = BPF_ALU64 | BPF_X | BPF_ARSH;
 pub const SDIV64_IMM: u8 = BPF_ALU64 | BPF_K | BPF_SDIV;
 /// BPF opcode: `sdiv64 dst, src` /// `dst s/= src`.
 pub const SDIV64_REG: u8 = BPF_ALU64 | BPF_X | BPF_SDIV;
+/// BPF opcode: `uhmul64 dst, imm` /// `(dst + 1, dst) = dst * imm` as u128.
+///
+/// The low half of the product is written to `dst` and the high half to the next register.
+pub const UHMUL64_IMM: u8 = BPF_ALU64 | BPF_K | BPF_UHMUL;
+/// BPF opcode: `uhmul64 dst, src` /// `(dst + 1, dst) = dst * src` as u128.
+///
+/// The low half of the product is written to `dst` and the high half to the next register.
+pub const UHMUL64_REG: u8 = BPF_ALU64 | BPF_X | BPF_UHMUL;
 
 /// BPF opcode: `ja +off` /// `PC += off`.
 pub const JA: u8 = BPF_JMP | BPF_JA;
@@ -406,6 +450,51 @@ pub const JSLE_IMM: u8 = BPF_JMP | BPF_K | BPF_JSLE;
 /// BPF opcode: `jsle dst, src, +off` /// `PC += off if dst <= src (signed)`.
 pub const JSLE_REG: u8 = BPF_JMP | BPF_X | BPF_JSLE;
 
+/// BPF opcode: `jeq32 dst, imm, +off` /// `PC += off if (dst as u32) == (imm as u32)`.
+pub const JEQ32_IMM: u8 = BPF_JMP32 | BPF_K | BPF_JEQ;
+/// BPF opcode: `jeq32 dst, src, +off` /// `PC += off if (dst as u32) == (src as u32)`.
+pub const JEQ32_REG: u8 = BPF_JMP32 | BPF_X | BPF_JEQ;
+/// BPF opcode: `jgt32 dst, imm, +off` /// `PC += off if (dst as u32) > (imm as u32)`.
+pub const JGT32_IMM: u8 = BPF_JMP32 | BPF_K | BPF_JGT;
+/// BPF opcode: `jgt32 dst, src, +off` /// `PC += off if (dst as u32) > (src as u32)`.
+pub const JGT32_REG: u8 = BPF_JMP32 | BPF_X | BPF_JGT;
+/// BPF opcode: `jge32 dst, imm, +off` /// `PC += off if (dst as u32) >= (imm as u32)`.
+pub const JGE32_IMM: u8 = BPF_JMP32 | BPF_K | BPF_JGE;
+/// BPF opcode: `jge32 dst, src, +off` /// `PC += off if (dst as u32) >= (src as u32)`.
+pub const JGE32_REG: u8 = BPF_JMP32 | BPF_X | BPF_JGE;
+/// BPF opcode: `jlt32 dst, imm, +off` /// `PC += off if (dst as u32) < (imm as u32)`.
+pub const JLT32_IMM: u8 = BPF_JMP32 | BPF_K | BPF_JLT;
+/// BPF opcode: `jlt32 dst, src, +off` /// `PC += off if (dst as u32) < (src as u32)`.
+pub const JLT32_REG: u8 = BPF_JMP32 | BPF_X | BPF_JLT;
+/// BPF opcode: `jle32 dst, imm, +off` /// `PC += off if (dst as u32) <= (imm as u32)`.
+pub const JLE32_IMM: u8 = BPF_JMP32 | BPF_K | BPF_JLE;
+/// BPF opcode: `jle32 dst, src, +off` /// `PC += off if (dst as u32) <= (src as u32)`.
+pub const JLE32_REG: u8 = BPF_JMP32 | BPF_X | BPF_JLE;
+/// BPF opcode: `jset32 dst, imm, +off` /// `PC += off if (dst as u32) & (imm as u32)`.
+pub const JSET32_IMM: u8 = BPF_JMP32 | BPF_K | BPF_JSET;
+/// BPF opcode: `jset32 dst, src, +off` /// `PC += off if (dst as u32) & (src as u32)`.
+pub const JSET32_REG: u8 = BPF_JMP32 | BPF_X | BPF_JSET;
+/// BPF opcode: `jne32 dst, imm, +off` /// `PC += off if (dst as u32) != (imm as u32)`.
+pub const JNE32_IMM: u8 = BPF_JMP32 | BPF_K | BPF_JNE;
+/// BPF opcode: `jne32 dst, src, +off` /// `PC += off if (dst as u32) != (src as u32)`.
+pub const JNE32_REG: u8 = BPF_JMP32 | BPF_X | BPF_JNE;
+/// BPF opcode: `jsgt32 dst, imm, +off` /// `PC += off if (dst as i32) > (imm as i32)`.
+pub const JSGT32_IMM: u8 = BPF_JMP32 | BPF_K | BPF_JSGT;
+/// BPF opcode: `jsgt32 dst, src, +off` /// `PC += off if (dst as i32) > (src as i32)`.
+pub const JSGT32_REG: u8 = BPF_JMP32 | BPF_X | BPF_JSGT;
+/// BPF opcode: `jsge32 dst, imm, +off` /// `PC += off if (dst as i32) >= (imm as i32)`.
+pub const JSGE32_IMM: u8 = BPF_JMP32 | BPF_K | BPF_JSGE;
+/// BPF opcode: `jsge32 dst, src, +off` /// `PC += off if (dst as i32) >= (src as i32)`.
+pub const JSGE32_REG: u8 = BPF_JMP32 | BPF_X | BPF_JSGE;
+/// BPF opcode: `jslt32 dst, imm, +off` /// `PC += off if (dst as i32) < (imm as i32)`.
+pub const JSLT32_IMM: u8 = BPF_JMP32 | BPF_K | BPF_JSLT;
+/// BPF opcode: `jslt32 dst, src, +off` /// `PC += off if (dst as i32) < (src as i32)`.
+pub const JSLT32_REG: u8 = BPF_JMP32 | BPF_X | BPF_JSLT;
+/// BPF opcode: `jsle32 dst, imm, +off` /// `PC += off if (dst as i32) <= (imm as i32)`.
+pub const JSLE32_IMM: u8 = BPF_JMP32 | BPF_K | BPF_JSLE;
+/// BPF opcode: `jsle32 dst, src, +off` /// `PC += off if (dst as i32) <= (src as i32)`.
+pub const JSLE32_REG: u8 = BPF_JMP32 | BPF_X | BPF_JSLE;
+
 /// BPF opcode: `call imm` /// syscall function call to syscall with key `imm`.
 pub const CALL_IMM: u8 = BPF_JMP | BPF_CALL;
 /// BPF opcode: tail call.
@@ -555,20 +644,108 @@ pub fn get_insn(prog: &[u8], pc: usize) -> Insn {
 }
 /// Same as `get_insn` except not checked
 pub fn get_insn_unchecked(prog: &[u8], pc: usize) -> Insn {
+    let insn = &prog[INSN_SIZE * pc..];
     Insn {
         ptr: pc,
-        opc: prog[INSN_SIZE * pc],
-        dst: prog[INSN_SIZE * pc + 1] & 0x0f,
-        src: (prog[INSN_SIZE * pc + 1] & 0xf0) >> 4,
-        off: LittleEndian::read_i16(&prog[(INSN_SIZE * pc + 2)..]),
-        imm: LittleEndian::read_i32(&prog[(INSN_SIZE * pc + 4)..]) as i64,
+        opc: insn[0],
+        dst: get_dst(insn),
+        src: get_src(insn),
+        off: get_offset(insn),
+        imm: get_immediate(insn),
     }
 }
 
+/// Get the destination register of the instruction which starts at `insn[0]`
+///
+/// # Examples
+///
+/// ```
+/// use solana_rbpf::ebpf;
+///
+/// let insn = &[0xbf, 0x21, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00]; // mov64 r1, r2
+/// assert_eq!(ebpf::get_dst(insn), 1);
+/// assert_eq!(ebpf::get_src(insn), 2);
+/// ```
+pub fn get_dst(insn: &[u8]) -> u8 {
+    insn[1] & 0x0f
+}
+
+/// Get the source register of the instruction which starts at `insn[0]`, see `get_dst()`
+pub fn get_src(insn: &[u8]) -> u8 {
+    (insn[1] & 0xf0) >> 4
+}
+
+/// Get the signed offset of the instruction which starts at `insn[0]`
+///
+/// # Examples
+///
+/// ```
+/// use solana_rbpf::ebpf;
+///
+/// let insn = &[0x05, 0x00, 0xfe, 0xff, 0x00, 0x00, 0x00, 0x00]; // ja -2
+/// assert_eq!(ebpf::get_offset(insn), -2);
+/// ```
+pub fn get_offset(insn: &[u8]) -> i16 {
+    LittleEndian::read_i16(&insn[2..])
+}
+
+/// Get the immediate of the instruction which starts at `insn[0]`, sign extended to 64 bits
+///
+/// # Examples
+///
+/// ```
+/// use solana_rbpf::ebpf;
+///
+/// let insn = &[0xb7, 0x01, 0x00, 0x00, 0xff, 0xff, 0xff, 0xff]; // mov64 r1, -1
+/// assert_eq!(ebpf::get_immediate(insn), -1);
+/// ```
+pub fn get_immediate(insn: &[u8]) -> i64 {
+    LittleEndian::read_i32(&insn[4..]) as i64
+}
+
+/// Get the 64 bit immediate of the LD_DW_IMM instruction which starts at `insn[0]`
+///
+/// Both slots must be present, the second one is not checked, see `is_valid_lddw_tail()`.
+///
+/// # Examples
+///
+/// ```
+/// use solana_rbpf::ebpf;
+///
+/// let insn = &[
+///     0x18, 0x00, 0x00, 0x00, 0x88, 0x77, 0x66, 0x55, // lddw r0, 0x1122334455667788
+///     0x00, 0x00, 0x00, 0x00, 0x44, 0x33, 0x22, 0x11,
+/// ];
+/// assert_eq!(ebpf::get_lddw_immediate(insn), 0x1122334455667788);
+/// ```
+pub fn get_lddw_immediate(insn: &[u8]) -> i64 {
+    let less_significant_half = get_immediate(insn) as u64 & 0xffffffff;
+    let more_significant_half = get_immediate(&insn[INSN_SIZE..]) as u64;
+    (less_significant_half | (more_significant_half << 32)) as i64
+}
+
+/// Check that the second slot of a LD_DW_IMM instruction only carries the upper half of the immediate
+pub fn is_valid_lddw_tail(insn: &Insn) -> bool {
+    insn.opc == 0 && insn.dst == 0 && insn.src == 0 && insn.off == 0
+}
+
 /// Merge the two halves of a LD_DW_IMM instruction
+///
+/// Returns false and leaves `insn` unchanged if the second slot is missing or
+/// fails `is_valid_lddw_tail()`.
+pub fn augment_lddw(prog: &[u8], insn: &mut Insn) -> bool {
+    if (insn.ptr + 2) * INSN_SIZE > prog.len()
+        || !is_valid_lddw_tail(&get_insn_unchecked(prog, insn.ptr + 1))
+    {
+        return false;
+    }
+    augment_lddw_unchecked(prog, insn);
+    true
+}
+
+/// Same as `augment_lddw` except the second slot is not checked
 pub fn augment_lddw_unchecked(prog: &[u8], insn: &mut Insn) {
-    let more_significant_half = LittleEndian::read_i32(&prog[((insn.ptr + 1) * INSN_SIZE + 4)..]);
-    insn.imm = ((insn.imm as u64 & 0xffffffff) | ((more_significant_half as u64) << 32)) as i64;
+    insn.imm = get_lddw_immediate(&prog[insn.ptr * INSN_SIZE..]);
 }
 
 /// Hash a symbol name
@@ -11,108 +11,502 @@ use crate::{
     ebpf::{self, EF_SBF_V2, HOST_ALIGN, INSN_SIZE},
     elf_parser::{
         consts::{
-            ELFCLASS64, ELFDATA2LSB, ELFOSABI_NONE, EM_BPF, EM_SBF, ET_DYN, R_X86_64_32,
-            R_X86_64_64, R_X86_64_NONE, R_X86_64_RELATIVE,
+            EI_DATA, ELFCLASS64, ELFDATA2LSB, ELFDATA2MSB, ELFMAG, ELFOSABI_NONE, EM_BPF, EM_SBF,
+            ET_DYN, R_X86_64_32, R_X86_64_64, R_X86_64_NONE, R_X86_64_RELATIVE,
         },
+        convert_to_little_endian,
         types::Elf64Word,
     },
     elf_parser_glue::{
         ElfParser, ElfProgramHeader, ElfRelocation, ElfSectionHeader, ElfSymbol, GoblinParser,
         NewParser,
     },
-    error::EbpfError,
+    error::{EbpfError, Error},
     memory_region::MemoryRegion,
-    vm::{Config, ContextObject, FunctionRegistry, SyscallRegistry},
+    sha256::Sha256,
+    vm::{Config, ContextObject, CostModel, FunctionRegistry, SyscallFunction, SyscallRegistry},
 };
 
+#[cfg(feature = "std")]
+use crate::elf_parser::{
+    consts::{EI_CLASS, SHF_ALLOC, SHT_NOBITS, SHT_NULL, SHT_PROGBITS},
+    types::{Elf64Ehdr, Elf64Phdr, Elf64Shdr},
+};
 #[cfg(feature = "jit")]
-use crate::jit::JitProgram;
+use crate::jit::{JitCompiler, JitProgram};
+use alloc::{
+    borrow::{Cow, ToOwned},
+    collections::{btree_map::Entry, BTreeMap, BTreeSet},
+    format,
+    string::{String, ToString},
+    sync::Arc,
+    vec,
+    vec::Vec,
+};
+#[cfg(feature = "std")]
+use byteorder::BigEndian;
 use byteorder::{ByteOrder, LittleEndian};
 #[cfg(not(feature = "jit"))]
-use std::marker::PhantomData;
-use std::{
-    collections::{btree_map::Entry, BTreeMap},
-    fmt::Debug,
+use core::marker::PhantomData;
+use core::{
+    fmt::{self, Debug},
     mem,
     ops::Range,
     str,
+    time::Duration,
+};
+#[cfg(feature = "std")]
+use std::{
+    collections::HashSet,
+    io::{Read, Seek, SeekFrom},
+    sync::Mutex,
+    time::Instant,
 };
 
 /// Error definitions
-#[derive(Debug, thiserror::Error, PartialEq, Eq)]
+#[derive(Debug, PartialEq, Eq)]
 pub enum ElfError {
     /// Failed to parse ELF file
-    #[error("Failed to parse ELF file: {0}")]
     FailedToParse(String),
     /// Entrypoint out of bounds
-    #[error("Entrypoint out of bounds")]
     EntrypointOutOfBounds,
     /// Invaid entrypoint
-    #[error("Invaid entrypoint")]
     InvalidEntrypoint,
     /// Failed to get section
-    #[error("Failed to get section {0}")]
     FailedToGetSection(String),
     /// Unresolved symbol
-    #[error("Unresolved symbol ({0}) at instruction #{1:?} (ELF file offset {2:#x})")]
     UnresolvedSymbol(String, usize, usize),
     /// Section not found
-    #[error("Section not found: {0}")]
     SectionNotFound(String),
     /// Relative jump out of bounds
-    #[error("Relative jump out of bounds at instruction #{0}")]
     RelativeJumpOutOfBounds(usize),
     /// Symbol hash collision
-    #[error("Symbol hash collision {0:#x}")]
     SymbolHashCollision(u32),
-    /// Incompatible ELF: wrong endianess
-    #[error("Incompatible ELF: wrong endianess")]
+    /// Deprecated, see UnsupportedEndianness
+    #[deprecated(note = "use UnsupportedEndianness")]
     WrongEndianess,
+    /// Incompatible ELF: unknown data encoding in e_ident
+    UnsupportedEndianness(u8),
     /// Incompatible ELF: wrong ABI
-    #[error("Incompatible ELF: wrong ABI")]
     WrongAbi,
     /// Incompatible ELF: wrong mchine
-    #[error("Incompatible ELF: wrong machine")]
     WrongMachine,
     /// Incompatible ELF: wrong class
-    #[error("Incompatible ELF: wrong class")]
     WrongClass,
     /// Not one text section
-    #[error("Multiple or no text sections, consider removing llc option: -function-sections")]
     NotOneTextSection,
     /// Read-write data not supported
-    #[error("Found .bss section in ELF, read-write data not supported")]
     BssNotSupported,
     /// Read-write data not supported
-    #[error("Found writable section ({0}) in ELF, read-write data not supported")]
     WritableSectionNotSupported(String),
     /// Relocation failed, no loadable section contains virtual address
-    #[error("Relocation failed, no loadable section contains virtual address {0:#x}")]
     AddressOutsideLoadableSection(u64),
     /// Relocation failed, invalid referenced virtual address
-    #[error("Relocation failed, invalid referenced virtual address {0:#x}")]
     InvalidVirtualAddress(u64),
-    /// Relocation failed, unknown type
-    #[error("Relocation failed, unknown type {0:?}")]
-    UnknownRelocation(u32),
+    /// Relocation failed, unsupported type, carries the type and the index of the relocated symbol
+    UnsupportedRelocationType(u32, u32),
     /// Failed to read relocation info
-    #[error("Failed to read relocation info")]
     FailedToReadRelocationInfo,
     /// Incompatible ELF: wrong type
-    #[error("Incompatible ELF: wrong type")]
     WrongType,
     /// Unknown symbol
-    #[error("Unknown symbol with index {0}")]
     UnknownSymbol(usize),
     /// Offset or value is out of bounds
-    #[error("Offset or value is out of bounds")]
     ValueOutOfBounds,
     /// Dynamic stack frames detected but not enabled
-    #[error("Dynamic stack frames detected but not enabled")]
     DynamicStackFramesDisabled,
     /// Invalid program header
-    #[error("Invalid ELF program header")]
     InvalidProgramHeader,
+    /// Section which is not in the allowlist of `Config::reject_unexpected_sections`
+    UnexpectedSection(String),
+    /// File is larger than `Config::max_elf_size`
+    FileTooLarge(usize),
+    /// File header declares more sections than `Config::max_section_count`
+    TooManySections(u64),
+    /// The text section contains no instructions
+    EmptyTextSection,
+}
+
+impl fmt::Display for ElfError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::FailedToParse(reason) => write!(f, "Failed to parse ELF file: {}", reason),
+            Self::EntrypointOutOfBounds => write!(f, "Entrypoint out of bounds"),
+            Self::InvalidEntrypoint => write!(f, "Invaid entrypoint"),
+            Self::FailedToGetSection(name) => write!(f, "Failed to get section {}", name),
+            Self::UnresolvedSymbol(name, pc, offset) => write!(
+                f,
+                "Unresolved symbol ({}) at instruction #{:?} (ELF file offset {:#x})",
+                name, pc, offset
+            ),
+            Self::SectionNotFound(name) => write!(f, "Section not found: {}", name),
+            Self::RelativeJumpOutOfBounds(pc) => {
+                write!(f, "Relative jump out of bounds at instruction #{}", pc)
+            }
+            Self::SymbolHashCollision(hash) => write!(f, "Symbol hash collision {:#x}", hash),
+            #[allow(deprecated)]
+            Self::WrongEndianess => write!(f, "Incompatible ELF: wrong endianess"),
+            Self::UnsupportedEndianness(ei_data) => {
+                write!(f, "Incompatible ELF: unsupported endianness {:#x}", ei_data)
+            }
+            Self::WrongAbi => write!(f, "Incompatible ELF: wrong ABI"),
+            Self::WrongMachine => write!(f, "Incompatible ELF: wrong machine"),
+            Self::WrongClass => write!(f, "Incompatible ELF: wrong class"),
+            Self::NotOneTextSection => write!(
+                f,
+                "Multiple or no text sections, consider removing llc option: -function-sections"
+            ),
+            Self::BssNotSupported => write!(
+                f,
+                "Found .bss section in ELF, read-write data not supported"
+            ),
+            Self::WritableSectionNotSupported(name) => write!(
+                f,
+                "Found writable section ({}) in ELF, read-write data not supported",
+                name
+            ),
+            Self::AddressOutsideLoadableSection(vm_addr) => write!(
+                f,
+                "Relocation failed, no loadable section contains virtual address {:#x}",
+                vm_addr
+            ),
+            Self::InvalidVirtualAddress(vm_addr) => write!(
+                f,
+                "Relocation failed, invalid referenced virtual address {:#x}",
+                vm_addr
+            ),
+            Self::UnsupportedRelocationType(r_type, symbol_index) => write!(
+                f,
+                "Relocation failed, unsupported type {} for symbol with index {}",
+                r_type, symbol_index
+            ),
+            Self::FailedToReadRelocationInfo => write!(f, "Failed to read relocation info"),
+            Self::WrongType => write!(f, "Incompatible ELF: wrong type"),
+            Self::UnknownSymbol(index) => write!(f, "Unknown symbol with index {}", index),
+            Self::ValueOutOfBounds => write!(f, "Offset or value is out of bounds"),
+            Self::DynamicStackFramesDisabled => {
+                write!(f, "Dynamic stack frames detected but not enabled")
+            }
+            Self::InvalidProgramHeader => write!(f, "Invalid ELF program header"),
+            Self::UnexpectedSection(name) => write!(f, "Unexpected section in ELF: {}", name),
+            Self::FileTooLarge(size) => write!(f, "ELF file of {} bytes is too large", size),
+            Self::TooManySections(count) => {
+                write!(f, "ELF file declares too many sections: {}", count)
+            }
+            Self::EmptyTextSection => write!(f, "Text section is empty"),
+        }
+    }
+}
+
+impl Error for ElfError {}
+
+/// Sections accepted when `Config::reject_unexpected_sections` is enabled
+///
+/// Besides the program itself these are the sections the dynamic linker
+/// emits, as well as the symbol and section name tables.
+const EXPECTED_SECTIONS: &[&str] = &[
+    "",
+    ".text",
+    ".dynamic",
+    ".dynsym",
+    ".dynstr",
+    ".gnu.hash",
+    ".hash",
+    ".rel.dyn",
+    ".symtab",
+    ".strtab",
+    ".shstrtab",
+    ".stack_sizes",
+];
+
+fn is_expected_section(name: &str) -> bool {
+    name.starts_with(".rodata") || is_data_rel_ro_section(name) || EXPECTED_SECTIONS.contains(&name)
+}
+
+/// `.data.rel.ro` and its variants such as `.data.rel.ro.local`
+///
+/// They hold read-only data which needs relocations, so they are writable while
+/// the ELF is relocated and part of the read-only region afterwards.
+fn is_data_rel_ro_section(name: &str) -> bool {
+    name == ".data.rel.ro" || name.starts_with(".data.rel.ro.")
+}
+
+fn round_stack_frame_size(size: usize) -> usize {
+    size.max(ebpf::MIN_STACK_FRAME_SIZE).saturating_add(7) & !7
+}
+
+/// Parses the `.stack_sizes` section LLVM emits with `-stack-size-section`
+///
+/// Every entry consists of the address of a function and its stack size as ULEB128.
+/// Entries of functions outside of the text section are ignored.
+fn parse_stack_sizes(
+    data: &[u8],
+    text_section_addr: u64,
+    text_section_size: u64,
+) -> Result<BTreeMap<usize, usize>, ElfError> {
+    let mut stack_frame_sizes = BTreeMap::new();
+    let mut offset = 0usize;
+    while offset < data.len() {
+        let address = data
+            .get(offset..offset.saturating_add(mem::size_of::<u64>()))
+            .map(LittleEndian::read_u64)
+            .ok_or(ElfError::ValueOutOfBounds)?;
+        offset = offset.saturating_add(mem::size_of::<u64>());
+        let mut size = 0u64;
+        let mut shift = 0u32;
+        loop {
+            let byte = *data.get(offset).ok_or(ElfError::ValueOutOfBounds)?;
+            offset = offset.saturating_add(1);
+            size |= u64::from(byte & 0x7f)
+                .checked_shl(shift)
+                .ok_or(ElfError::ValueOutOfBounds)?;
+            shift = shift.saturating_add(7);
+            if byte & 0x80 == 0 {
+                break;
+            }
+        }
+        let text_offset = address.wrapping_sub(text_section_addr);
+        if text_offset < text_section_size
+            && text_offset.checked_rem(ebpf::INSN_SIZE as u64) == Some(0)
+        {
+            if let Some(pc) = text_offset.checked_div(ebpf::INSN_SIZE as u64) {
+                stack_frame_sizes.insert(pc as usize, round_stack_frame_size(size as usize));
+            }
+        }
+    }
+    Ok(stack_frame_sizes)
+}
+
+/// Number of sections a little endian ELF64 file header declares, without parsing the section headers
+///
+/// With extended numbering `e_shnum` is zero and the count is stored in the
+/// `sh_size` of the first section header instead. Truncated files count as
+/// having no sections and are rejected by the parsers later on.
+fn declared_section_count(bytes: &[u8]) -> u64 {
+    let read = |offset: usize, size: usize| {
+        bytes
+            .get(offset..offset.saturating_add(size))
+            .map(|field| LittleEndian::read_uint(field, size))
+    };
+    let e_shoff = read(40, 8).unwrap_or(0);
+    match read(60, 2) {
+        Some(0) if e_shoff != 0 => read((e_shoff as usize).saturating_add(32), 8).unwrap_or(0),
+        Some(e_shnum) => e_shnum,
+        None => 0,
+    }
+}
+
+#[cfg(feature = "std")]
+fn read_exact_at<R: Read + Seek>(
+    reader: &mut R,
+    offset: u64,
+    buffer: &mut [u8],
+) -> Result<(), ElfError> {
+    let io_error = |error: std::io::Error| ElfError::FailedToParse(error.to_string());
+    reader.seek(SeekFrom::Start(offset)).map_err(io_error)?;
+    reader.read_exact(buffer).map_err(io_error)
+}
+
+/// Part of an ELF file which `compact_elf_layout()` moves
+#[cfg(feature = "std")]
+enum MovedPart {
+    ProgramHeaderTable,
+    SectionHeaderTable,
+    /// Index of the section header and whether the contents are kept
+    Section(usize, bool),
+}
+
+/// Reads the parts of an ELF64 file which loading needs into an image smaller than the file
+///
+/// Everything up to the end of the last segment or allocated section keeps its offset,
+/// as the loader relates section offsets to addresses. The header tables and the other
+/// sections behind that are packed in their original order and keep their offsets modulo
+/// eight, so that the parsers see the same order and alignment. Sections which are neither
+/// allocated nor anything but PROGBITS (e.g. debug info) become empty, as loading ignores
+/// their contents.
+///
+/// Returns `None` if the image would not be any smaller, or if the layout is one for which
+/// the parsers could come to a different verdict on the image than on the file, e.g. because
+/// parts overlap or exceed the file. Then the whole file has to be read instead.
+#[cfg(feature = "std")]
+fn compact_elf_layout<R: Read + Seek>(
+    config: &Config,
+    reader: &mut R,
+    file_size: u64,
+    header: &[u8],
+) -> Result<Option<AlignedMemory<{ HOST_ALIGN }>>, ElfError> {
+    let big_endian = match (header.get(EI_CLASS as usize), header.get(EI_DATA as usize)) {
+        (Some(&ELFCLASS64), Some(&ELFDATA2LSB)) if header.starts_with(&ELFMAG) => false,
+        (Some(&ELFCLASS64), Some(&ELFDATA2MSB)) if header.starts_with(&ELFMAG) => true,
+        _ => return Ok(None),
+    };
+    if header.len() < mem::size_of::<Elf64Ehdr>() {
+        return Ok(None);
+    }
+    let read = |bytes: &[u8], offset: usize, size: usize| {
+        let field = &bytes[offset..offset + size];
+        if big_endian {
+            BigEndian::read_uint(field, size)
+        } else {
+            LittleEndian::read_uint(field, size)
+        }
+    };
+    let write = |bytes: &mut [u8], offset: usize, size: usize, value: u64| {
+        let field = &mut bytes[offset..offset + size];
+        if big_endian {
+            BigEndian::write_uint(field, value, size)
+        } else {
+            LittleEndian::write_uint(field, value, size)
+        }
+    };
+    const PHDR_SIZE: usize = mem::size_of::<Elf64Phdr>();
+    const SHDR_SIZE: usize = mem::size_of::<Elf64Shdr>();
+    let e_phoff = read(header, 32, 8);
+    let e_shoff = read(header, 40, 8);
+    let e_phentsize = read(header, 54, 2);
+    let e_phnum = read(header, 56, 2);
+    let e_shentsize = read(header, 58, 2);
+    let mut section_count = read(header, 60, 2);
+    if e_phentsize != PHDR_SIZE as u64 || e_shentsize != SHDR_SIZE as u64 {
+        return Ok(None);
+    }
+    if section_count == 0 && e_shoff != 0 {
+        // Extended numbering, the count is stored in the first section header
+        if e_shoff.saturating_add(SHDR_SIZE as u64) > file_size {
+            return Ok(None);
+        }
+        let mut section_header = [0; SHDR_SIZE];
+        read_exact_at(reader, e_shoff, &mut section_header)?;
+        section_count = read(&section_header, 32, 8);
+    }
+    if section_count > config.max_section_count as u64 {
+        return Err(ElfError::TooManySections(section_count));
+    }
+    let table_range = |offset: u64, count: u64, entry_size: usize| {
+        count
+            .checked_mul(entry_size as u64)
+            .and_then(|size| offset.checked_add(size))
+            .filter(|end| *end <= file_size)
+            .map(|end| offset..end)
+    };
+    let (program_header_table_range, section_header_table_range) = match (
+        table_range(e_phoff, e_phnum, PHDR_SIZE),
+        table_range(e_shoff, section_count, SHDR_SIZE),
+    ) {
+        (Some(program_header_table), Some(section_header_table)) => {
+            (program_header_table, section_header_table)
+        }
+        _ => return Ok(None),
+    };
+    let mut program_header_table =
+        vec![0; (program_header_table_range.end - program_header_table_range.start) as usize];
+    read_exact_at(reader, e_phoff, &mut program_header_table)?;
+    let mut section_header_table =
+        vec![0; (section_header_table_range.end - section_header_table_range.start) as usize];
+    read_exact_at(reader, e_shoff, &mut section_header_table)?;
+
+    // Parts which keep their offset
+    let mut kept_ranges = std::iter::once(0..header.len() as u64).collect::<Vec<_>>();
+    let mut kept_end = header.len() as u64;
+    let mut moved_parts = Vec::new();
+    for program_header in program_header_table.chunks_exact(PHDR_SIZE) {
+        let p_offset = read(program_header, 8, 8);
+        let p_filesz = read(program_header, 32, 8);
+        match p_offset.checked_add(p_filesz) {
+            Some(end) => kept_ranges.push(p_offset..end),
+            None => return Ok(None),
+        }
+    }
+    for (index, section_header) in section_header_table.chunks_exact(SHDR_SIZE).enumerate() {
+        let sh_type = read(section_header, 4, 4) as Elf64Word;
+        let sh_flags = read(section_header, 8, 8);
+        let sh_offset = read(section_header, 24, 8);
+        let range = match sh_offset.checked_add(read(section_header, 32, 8)) {
+            Some(end) => sh_offset..end,
+            None => return Ok(None),
+        };
+        if sh_type == SHT_NULL || sh_type == SHT_NOBITS {
+            // Not read, but the loader checks that the range is inside of the file
+            kept_end = kept_end.max(range.end);
+        } else if sh_flags & SHF_ALLOC != 0 {
+            kept_ranges.push(range);
+        } else {
+            moved_parts.push((range, MovedPart::Section(index, sh_type != SHT_PROGBITS)));
+        }
+    }
+    kept_end = kept_ranges
+        .iter()
+        .fold(kept_end, |end, range| end.max(range.end));
+    if kept_end > file_size {
+        return Ok(None);
+    }
+    for (range, part) in [
+        (program_header_table_range, MovedPart::ProgramHeaderTable),
+        (section_header_table_range, MovedPart::SectionHeaderTable),
+    ] {
+        if range.start >= kept_end {
+            moved_parts.push((range, part));
+        } else if range.end > kept_end {
+            return Ok(None);
+        }
+    }
+    moved_parts.sort_by_key(|(range, _part)| (range.start, range.end));
+    let mut original_end = kept_end;
+    let mut image_size = kept_end;
+    let mut moved_offsets = Vec::with_capacity(moved_parts.len());
+    for (range, part) in moved_parts.iter() {
+        if range.start < original_end {
+            // Overlaps with a kept part or the previous moved part
+            return Ok(None);
+        }
+        original_end = range.end;
+        // Never behind the original offset, as the image only shrinks
+        let offset = image_size + (range.start - image_size) % 8;
+        moved_offsets.push(offset);
+        image_size = match part {
+            MovedPart::Section(_index, false) => offset,
+            _ => offset + (range.end - range.start),
+        };
+    }
+    if image_size >= file_size {
+        return Ok(None);
+    }
+
+    let mut elf_bytes = AlignedMemory::<{ HOST_ALIGN }>::zero_filled(image_size as usize);
+    let image = elf_bytes.as_slice_mut();
+    for range in kept_ranges {
+        read_exact_at(
+            reader,
+            range.start,
+            &mut image[range.start as usize..range.end as usize],
+        )?;
+    }
+    let (mut e_phoff, mut e_shoff) = (e_phoff, e_shoff);
+    for ((range, part), offset) in moved_parts.iter().zip(moved_offsets) {
+        match part {
+            MovedPart::ProgramHeaderTable => e_phoff = offset,
+            MovedPart::SectionHeaderTable => e_shoff = offset,
+            MovedPart::Section(index, keep_contents) => {
+                let section_header = &mut section_header_table[index * SHDR_SIZE..];
+                write(section_header, 24, 8, offset);
+                if *keep_contents {
+                    let end = offset + (range.end - range.start);
+                    read_exact_at(
+                        reader,
+                        range.start,
+                        &mut image[offset as usize..end as usize],
+                    )?;
+                } else {
+                    write(section_header, 32, 8, 0);
+                }
+            }
+        }
+    }
+    write(image, 32, 8, e_phoff);
+    write(image, 40, 8, e_shoff);
+    image[e_phoff as usize..][..program_header_table.len()].copy_from_slice(&program_header_table);
+    image[e_shoff as usize..][..section_header_table.len()].copy_from_slice(&section_header_table);
+    Ok(Some(elf_bytes))
 }
 
 /// Generates the hash by which a symbol can be called
@@ -129,7 +523,7 @@ pub fn hash_bpf_function(pc: usize, name: &str) -> u32 {
 /// Register a symbol or throw ElfError::SymbolHashCollision
 pub fn register_bpf_function<
     C: ContextObject,
-    T: AsRef<str> + ToString + std::cmp::PartialEq<&'static str>,
+    T: AsRef<str> + ToString + core::cmp::PartialEq<&'static str>,
 >(
     config: &Config,
     function_registry: &mut FunctionRegistry,
@@ -143,9 +537,7 @@ pub fn register_bpf_function<
         pc as u32
     } else {
         let hash = hash_bpf_function(pc, name.as_ref());
-        if config.syscall_bpf_function_hash_collision
-            && syscall_registry.lookup_syscall(hash).is_some()
-        {
+        if config.syscall_bpf_function_hash_collision && syscall_registry.contains_syscall(hash) {
             return Err(ElfError::SymbolHashCollision(hash));
         }
         hash
@@ -263,6 +655,67 @@ pub(crate) enum Section {
     /// second field an be used to index the input ELF buffer to retrieve the
     /// section data.
     Borrowed(usize, Range<usize>),
+    /// Section data shared with other executables through a `RodataInterner`.
+    ///
+    /// The first field is the offset of the section from MM_PROGRAM_START. The
+    /// second field is the actual section data.
+    Shared(usize, Arc<[u8]>),
+}
+
+/// Deduplicates the read-only sections of multiple executables
+///
+/// Identical read-only sections, compared byte by byte, are backed by the
+/// same allocation. Only the owned read-only sections are interned, borrowed
+/// ones are part of the ELF bytes of the executable already. Read-only
+/// sections are always mapped as readonly regions, so sharing them never
+/// makes a writable region shared.
+#[cfg(feature = "std")]
+#[derive(Debug, Default)]
+pub struct RodataInterner {
+    entries: Mutex<HashSet<Arc<[u8]>>>,
+}
+
+#[cfg(feature = "std")]
+impl RodataInterner {
+    /// Returns the shared allocation holding `data`, creating it if needed
+    pub fn intern(&self, data: &[u8]) -> Arc<[u8]> {
+        let mut entries = self.entries.lock().unwrap();
+        if let Some(entry) = entries.get(data) {
+            return entry.clone();
+        }
+        let entry: Arc<[u8]> = Arc::from(data);
+        entries.insert(entry.clone());
+        entry
+    }
+
+    /// Drops the allocations which are not used by any executable anymore
+    pub fn purge(&self) {
+        self.entries
+            .lock()
+            .unwrap()
+            .retain(|entry| Arc::strong_count(entry) > 1);
+    }
+
+    /// Number of distinct read-only sections
+    pub fn len(&self) -> usize {
+        self.entries.lock().unwrap().len()
+    }
+
+    /// Returns true if no read-only section was interned
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+/// Interning needs `std::sync::Mutex`, without `std` this can not be constructed
+#[cfg(not(feature = "std"))]
+pub(crate) enum RodataInterner {}
+
+#[cfg(not(feature = "std"))]
+impl RodataInterner {
+    fn intern(&self, _data: &[u8]) -> Arc<[u8]> {
+        match *self {}
+    }
 }
 
 /// Elf loader/relocator
@@ -280,15 +733,23 @@ pub struct Executable<C: ContextObject> {
     entry_pc: usize,
     /// Call resolution map (hash, pc, name)
     function_registry: FunctionRegistry,
+    /// First instruction of every function in `function_registry`
+    function_starts: BTreeSet<usize>,
     /// Syscall symbol map (hash, name)
     syscall_symbols: BTreeMap<u32, String>,
     /// Syscall resolution map
     syscall_registry: SyscallRegistry<C>,
+    /// Cost of every instruction slot, `None` if all instructions cost one unit
+    instruction_costs: Option<Vec<u64>>,
+    /// Stack frame size of the functions which have one recorded, by their first instruction
+    stack_frame_sizes: BTreeMap<usize, usize>,
+    /// Whether the text section contains BPF to BPF calls or callx
+    has_internal_calls: bool,
     /// Compiled program and argument
     #[cfg(feature = "jit")]
     compiled_program: Option<JitProgram<C>>,
     #[cfg(not(feature = "jit"))]
-    _marker: PhantomData<(I)>,
+    _marker: PhantomData<C>,
 }
 
 impl<C: ContextObject> Executable<C> {
@@ -304,12 +765,13 @@ impl<C: ContextObject> Executable<C> {
             Section::Borrowed(offset, byte_range) => {
                 (*offset, &self.elf_bytes.as_slice()[byte_range.clone()])
             }
+            Section::Shared(offset, data) => (*offset, &data[..]),
         };
 
         let offset = self
             .text_section_info
             .vaddr
-            .saturating_sub(ebpf::MM_PROGRAM_START)
+            .saturating_sub(self.config.rodata_vaddr_base)
             .saturating_sub(ro_offset as u64) as usize;
         (
             self.text_section_info.vaddr,
@@ -324,12 +786,17 @@ impl<C: ContextObject> Executable<C> {
             Section::Borrowed(_offset, byte_range) => {
                 &self.elf_bytes.as_slice()[byte_range.clone()]
             }
+            Section::Shared(_offset, data) => data,
         }
     }
 
     /// Get a memory region that can be used to access the merged readonly section
     pub fn get_ro_region(&self) -> MemoryRegion {
-        get_ro_region(&self.ro_section, self.elf_bytes.as_slice())
+        get_ro_region(
+            &self.ro_section,
+            self.elf_bytes.as_slice(),
+            self.config.rodata_vaddr_base,
+        )
     }
 
     /// Get the entry point offset into the text section
@@ -343,16 +810,302 @@ impl<C: ContextObject> Executable<C> {
         self.text_section_info.offset_range.start as u64
     }
 
+    /// Whether a function of the function registry starts at the given instruction
+    pub fn is_function_start(&self, pc: usize) -> bool {
+        self.function_starts.contains(&pc)
+    }
+
     /// Get a symbol's instruction offset
     pub fn lookup_bpf_function(&self, hash: u32) -> Option<usize> {
         self.function_registry.get(&hash).map(|(pc, _name)| *pc)
     }
 
+    /// Get the name and the start of the function which contains the given instruction
+    ///
+    /// Functions without a symbol name are named `function_<start>`.
+    pub fn function_containing(&self, insn_index: usize) -> Option<(Cow<'_, str>, usize)> {
+        if insn_index >= self.text_section_info.offset_range.len() / ebpf::INSN_SIZE {
+            return None;
+        }
+        let (start, name) = self
+            .function_registry
+            .values()
+            .filter(|(pc, _name)| *pc <= insn_index)
+            .max_by_key(|(pc, _name)| *pc)?;
+        let name = if name.is_empty() {
+            Cow::Owned(format!("function_{}", start))
+        } else {
+            Cow::Borrowed(name.as_str())
+        };
+        Some((name, *start))
+    }
+
+    /// Iterate over the decoded instructions of the text section along with their index
+    ///
+    /// A LD_DW_IMM is yielded once with its full 64 bit immediate and occupies two indices.
+    pub fn iter_instructions(&self) -> impl Iterator<Item = (usize, ebpf::Insn)> + '_ {
+        let program = self.get_text_bytes().1;
+        let mut insn_ptr = 0;
+        core::iter::from_fn(move || {
+            if (insn_ptr + 1) * INSN_SIZE > program.len() {
+                return None;
+            }
+            let mut insn = ebpf::get_insn_unchecked(program, insn_ptr);
+            let index = insn_ptr;
+            if insn.opc == ebpf::LD_DW_IMM && (insn_ptr + 2) * INSN_SIZE <= program.len() {
+                ebpf::augment_lddw_unchecked(program, &mut insn);
+                insn_ptr += 2;
+            } else {
+                insn_ptr += 1;
+            }
+            Some((index, insn))
+        })
+    }
+
     /// Get the syscall registry
     pub fn get_syscall_registry(&self) -> &SyscallRegistry<C> {
         &self.syscall_registry
     }
 
+    /// Replace the function of a syscall which is registered under the symbol hash
+    ///
+    /// Keeps the relocations, but discards the JIT compiled program as it calls the functions
+    /// directly, so it needs to be compiled again.
+    pub fn replace_syscall_handler(
+        &mut self,
+        hash: u32,
+        function: SyscallFunction<C>,
+    ) -> Result<(), EbpfError<C::UserError>> {
+        self.syscall_registry.replace_handler(hash, function)?;
+        #[cfg(feature = "jit")]
+        {
+            self.compiled_program = None;
+        }
+        Ok(())
+    }
+
+    /// Get the amount the instruction at `pc` consumes from the instruction meter
+    pub fn get_instruction_cost(&self, pc: usize) -> u64 {
+        self.instruction_costs
+            .as_ref()
+            .and_then(|instruction_costs| instruction_costs.get(pc).copied())
+            .unwrap_or(1)
+    }
+
+    /// Charge the instruction meter according to the given cost model
+    ///
+    /// The JIT encodes the pc plus the cost of an instruction as a 32 bit immediate, so a cost
+    /// which does not fit fails with `EbpfError::InstructionCostTooLarge` and nothing is changed.
+    /// Otherwise the JIT compiled program is discarded, so it needs to be compiled again.
+    pub fn set_cost_model(
+        &mut self,
+        cost_model: &dyn CostModel,
+    ) -> Result<(), EbpfError<C::UserError>> {
+        let program = self.get_text_bytes().1;
+        let mut instruction_costs = vec![1; program.len() / INSN_SIZE];
+        let mut pc = 0;
+        while pc < instruction_costs.len() {
+            let mut insn = ebpf::get_insn_unchecked(program, pc);
+            if insn.opc == ebpf::LD_DW_IMM && pc.saturating_add(1) < instruction_costs.len() {
+                ebpf::augment_lddw_unchecked(program, &mut insn);
+            }
+            let cost = cost_model.cost(&insn);
+            if (pc as u64).saturating_add(cost) > i32::MAX as u64 {
+                return Err(EbpfError::InstructionCostTooLarge(
+                    pc.saturating_add(ebpf::ELF_INSN_DUMP_OFFSET),
+                    cost,
+                ));
+            }
+            instruction_costs[pc] = cost;
+            pc = pc.saturating_add(if insn.opc == ebpf::LD_DW_IMM { 2 } else { 1 });
+        }
+        self.instruction_costs = if instruction_costs.iter().all(|cost| *cost == 1) {
+            None
+        } else {
+            Some(instruction_costs)
+        };
+        #[cfg(feature = "jit")]
+        {
+            self.compiled_program = None;
+        }
+        Ok(())
+    }
+
+    /// Get the stack frame size recorded for the function starting at `pc`
+    pub fn get_stack_frame_size(&self, pc: usize) -> Option<usize> {
+        self.stack_frame_sizes.get(&pc).copied()
+    }
+
+    /// Record the stack frame size of the function starting at `pc`
+    ///
+    /// ELF files carry these in their `.stack_sizes` section. With fixed stack frames and no
+    /// gaps between them, BPF to BPF calls give the callee a frame of its recorded size instead
+    /// of `Config::stack_frame_size` and the call depth is only limited by the total stack size.
+    /// Sizes are rounded up to a multiple of 8 and to at least `ebpf::MIN_STACK_FRAME_SIZE`.
+    ///
+    /// Discards the JIT compiled program, so it needs to be compiled again.
+    pub fn set_stack_frame_size(&mut self, pc: usize, size: usize) {
+        self.stack_frame_sizes
+            .insert(pc, round_stack_frame_size(size));
+        #[cfg(feature = "jit")]
+        {
+            self.compiled_program = None;
+        }
+    }
+
+    /// Whether BPF to BPF calls size the frames according to the recorded stack frame sizes
+    pub(crate) fn uses_stack_frame_sizes(&self) -> bool {
+        !self.stack_frame_sizes.is_empty()
+            && !self.config.dynamic_stack_frames
+            && !self.config.enable_stack_frame_gaps
+    }
+
+    /// Size of the frame a call to `target_pc` allocates when `uses_stack_frame_sizes()`
+    ///
+    /// The target of callx is not known in advance, so these get the largest frame any function could need.
+    pub(crate) fn call_frame_size(&self, target_pc: Option<usize>) -> usize {
+        match target_pc {
+            Some(target_pc) => self
+                .get_stack_frame_size(target_pc)
+                .unwrap_or(self.config.stack_frame_size),
+            None => self
+                .stack_frame_sizes
+                .values()
+                .copied()
+                .fold(self.config.stack_frame_size, usize::max),
+        }
+    }
+
+    /// Whether the program contains BPF to BPF calls or callx
+    ///
+    /// Calls which resolve to a registered syscall do not count, unresolvable ones do.
+    /// Programs without internal calls run on the root frame only, so the interpreter
+    /// skips the call frame bookkeeping for them.
+    pub fn has_internal_calls(&self) -> bool {
+        self.has_internal_calls
+    }
+
+    /// Hashes of the syscalls which the program calls, sorted and without duplicates
+    ///
+    /// Covers calls to relocated syscall symbols as well as calls which name the hash directly,
+    /// regardless of whether the syscall is registered. Use `ebpf::hash_symbol_name()` to
+    /// compare against syscall names.
+    pub fn referenced_syscalls(&self) -> Vec<u32> {
+        let syscalls: BTreeSet<u32> = self
+            .iter_instructions()
+            .filter(|(_pc, insn)| match insn.opc {
+                ebpf::CALL_IMM if self.config.static_syscalls => insn.src == 0,
+                ebpf::CALL_IMM => {
+                    self.syscall_registry
+                        .lookup_syscall(insn.imm as u32)
+                        .is_some()
+                        || self.lookup_bpf_function(insn.imm as u32).is_none()
+                }
+                _ => false,
+            })
+            .map(|(_pc, insn)| insn.imm as u32)
+            .collect();
+        syscalls.into_iter().collect()
+    }
+
+    /// Derives `has_internal_calls` and `function_starts` from the text section and the function registry
+    fn with_call_info_derived(mut self) -> Self {
+        let has_internal_calls = self.iter_instructions().any(|(_pc, insn)| match insn.opc {
+            ebpf::CALL_REG => true,
+            ebpf::CALL_IMM if self.config.static_syscalls => insn.src != 0,
+            ebpf::CALL_IMM => self
+                .syscall_registry
+                .lookup_syscall(insn.imm as u32)
+                .is_none(),
+            _ => false,
+        });
+        self.has_internal_calls = has_internal_calls;
+        self.function_starts = self
+            .function_registry
+            .values()
+            .map(|(pc, _name)| *pc)
+            .collect();
+        self
+    }
+
+    /// Hash of everything which determines how the executable behaves
+    ///
+    /// Covers the text and read-only sections with their addresses, the entrypoint, the function
+    /// registry, which calls resolve to syscalls, the instruction costs, the stack frame sizes and
+    /// the `Config` fields which affect execution or the JIT output. Not covered are the
+    /// implementations of the syscalls, the names of symbols and how the bytes were laid out in
+    /// the ELF file, so it is stable across loads of the same program and suited as a cache key.
+    pub fn fingerprint(&self) -> [u8; 32] {
+        let mut hasher = Sha256::default();
+        let mut hash_bytes = |bytes: &[u8]| {
+            hasher.update(&(bytes.len() as u64).to_le_bytes());
+            hasher.update(bytes);
+        };
+        // Settings which only affect loading or verification are left out
+        let config = &self.config;
+        let initial_stack_frames = config.initial_stack_frames.map_or(u64::MAX, |n| n as u64);
+        for value in [
+            config.max_call_depth as u64,
+            initial_stack_frames,
+            config.stack_frame_size as u64,
+            config.instruction_meter_checkpoint_distance as u64,
+            config.max_instructions,
+            config.noop_instruction_rate as u64,
+            config.jit_function_padding as u64,
+            config.div_by_zero_behavior as u64,
+            config.oob_read_behavior as u64,
+            config.max_syscall_buffer_len as u64,
+            config.rodata_vaddr_base,
+            config.heap_vaddr_base,
+        ] {
+            hash_bytes(&value.to_le_bytes());
+        }
+        hash_bytes(&[
+            config.enable_stack_frame_gaps as u8,
+            config.enable_instruction_meter as u8,
+            config.enable_instruction_tracing as u8,
+            config.sanitize_user_provided_values as u8,
+            config.encrypt_environment_registers as u8,
+            config.dynamic_stack_frames as u8,
+            config.clear_stack_on_entry as u8,
+            config.enable_sdiv as u8,
+            config.enable_uhmul as u8,
+            config.enable_atomics as u8,
+            config.enable_jmp32 as u8,
+            config.static_syscalls as u8,
+            config.aligned_memory_mapping as u8,
+            config.allow_opcode_extensions as u8,
+            config.enforce_aligned_access as u8,
+            config.reject_callx_to_unaligned as u8,
+            config.reject_callx_to_unknown_target as u8,
+            config.enable_syscall_sealing as u8,
+        ]);
+        let allowed_opcodes = (0..=u8::MAX)
+            .map(|opcode| config.allowed_opcodes.contains(opcode) as u8)
+            .collect::<Vec<u8>>();
+        hash_bytes(&allowed_opcodes);
+        let (text_vaddr, program) = self.get_text_bytes();
+        hash_bytes(&text_vaddr.to_le_bytes());
+        hash_bytes(program);
+        let ro_region = self.get_ro_region();
+        hash_bytes(&ro_region.vm_addr.to_le_bytes());
+        hash_bytes(self.get_ro_section());
+        hash_bytes(&(self.entry_pc as u64).to_le_bytes());
+        for (key, (pc, _name)) in &self.function_registry {
+            hash_bytes(&key.to_le_bytes());
+            hash_bytes(&(*pc as u64).to_le_bytes());
+        }
+        for pc in 0..program.len() / ebpf::INSN_SIZE {
+            hash_bytes(&self.get_instruction_cost(pc).to_le_bytes());
+            hash_bytes(&(self.get_stack_frame_size(pc).unwrap_or(0) as u64).to_le_bytes());
+            let insn = ebpf::get_insn_unchecked(program, pc);
+            if insn.opc == ebpf::CALL_IMM {
+                hash_bytes(&[self.syscall_registry.contains_syscall(insn.imm as u32) as u8]);
+            }
+        }
+        hasher.finalize()
+    }
+
     /// Get the JIT compiled program
     #[cfg(feature = "jit")]
     pub fn get_compiled_program(&self) -> Option<&JitProgram<C>> {
@@ -361,11 +1114,51 @@ impl<C: ContextObject> Executable<C> {
 
     /// JIT compile the executable
     #[cfg(feature = "jit")]
-    pub fn jit_compile(executable: &mut Self) -> Result<(), EbpfError> {
+    pub fn jit_compile(executable: &mut Self) -> Result<(), EbpfError<C::UserError>> {
         executable.compiled_program = Some(JitProgram::<C>::new(executable)?);
         Ok(())
     }
 
+    /// JIT compile the executable without checking loads and stores against the memory regions
+    ///
+    /// # Safety
+    ///
+    /// See `JitProgram::new_without_bounds_checks()`.
+    #[cfg(feature = "jit")]
+    pub unsafe fn jit_compile_without_bounds_checks(
+        executable: &mut Self,
+    ) -> Result<(), EbpfError<C::UserError>> {
+        executable.compiled_program = Some(JitProgram::<C>::new_without_bounds_checks(executable)?);
+        Ok(())
+    }
+
+    /// Loads machine code which `JitProgram::serialize()` produced for an identical executable
+    ///
+    /// This skips the compilation done by `jit_compile()`.
+    ///
+    /// # Safety
+    ///
+    /// The machine code in `bytes` is executed as is, see `JitProgram::deserialize()`.
+    #[cfg(feature = "jit")]
+    pub unsafe fn load_compiled_program(
+        executable: &mut Self,
+        bytes: &[u8],
+    ) -> Result<(), EbpfError<C::UserError>> {
+        executable.compiled_program = Some(JitProgram::<C>::deserialize(bytes, executable)?);
+        Ok(())
+    }
+
+    /// Length of the machine code `jit_compile()` would produce with the given config
+    ///
+    /// Runs the compiler on a scratch buffer, no executable memory is allocated.
+    #[cfg(feature = "jit")]
+    pub fn estimate_jit_code_size(
+        &self,
+        config: &Config,
+    ) -> Result<usize, EbpfError<C::UserError>> {
+        JitCompiler::estimate_code_size(self, config)
+    }
+
     /// Get normal functions (if debug symbols are not stripped)
     pub fn get_function_registry(&self) -> &FunctionRegistry {
         &self.function_registry
@@ -380,9 +1173,11 @@ impl<C: ContextObject> Executable<C> {
     pub fn new_from_text_bytes(
         config: Config,
         text_bytes: &[u8],
-        syscall_registry: SyscallRegistry<C>,
+        mut syscall_registry: SyscallRegistry<C>,
         mut function_registry: FunctionRegistry,
     ) -> Result<Self, ElfError> {
+        // There are no relocations, the syscall hashes are already in the instructions
+        let syscall_names = syscall_registry.bind_all_syscalls_with_names();
         let elf_bytes = AlignedMemory::from_slice(text_bytes);
         let enable_symbol_and_section_labels = config.enable_symbol_and_section_labels;
         let entry_pc = if let Some((pc, _name)) = function_registry
@@ -410,18 +1205,27 @@ impl<C: ContextObject> Executable<C> {
                 } else {
                     String::default()
                 },
-                vaddr: ebpf::MM_PROGRAM_START,
+                vaddr: config.rodata_vaddr_base,
                 offset_range: 0..text_bytes.len(),
             },
             entry_pc,
             function_registry,
-            syscall_symbols: BTreeMap::default(),
+            function_starts: BTreeSet::new(),
+            syscall_symbols: if enable_symbol_and_section_labels {
+                syscall_names
+            } else {
+                BTreeMap::default()
+            },
             syscall_registry,
+            instruction_costs: None,
+            stack_frame_sizes: BTreeMap::new(),
+            has_internal_calls: false,
             #[cfg(feature = "jit")]
             compiled_program: None,
             #[cfg(not(feature = "jit"))]
             _marker: PhantomData,
-        })
+        }
+        .with_call_info_derived())
     }
 
     /// Fully loads an ELF, including validation and relocation
@@ -430,6 +1234,93 @@ impl<C: ContextObject> Executable<C> {
         bytes: &[u8],
         syscall_registry: SyscallRegistry<C>,
     ) -> Result<Self, ElfError> {
+        Self::load_and_intern(config, bytes, syscall_registry, None, None)
+    }
+
+    /// Same as `load`, but shares the read-only section with other
+    /// executables loaded through the same interner
+    #[cfg(feature = "std")]
+    pub fn load_with_rodata_interner(
+        config: Config,
+        bytes: &[u8],
+        syscall_registry: SyscallRegistry<C>,
+        rodata_interner: Option<&RodataInterner>,
+    ) -> Result<Self, ElfError> {
+        Self::load_and_intern(config, bytes, syscall_registry, rodata_interner, None)
+    }
+
+    /// Same as `load`, but reads the ELF from a seekable source
+    ///
+    /// Only the headers, the segments and the sections which are loaded into
+    /// memory (text, rodata, dynamic and relocation data) are read at their
+    /// original offsets. The symbol and string tables as well as the header
+    /// tables are packed right behind them. The contents of other sections which
+    /// are not loaded into memory, e.g. debug info, are not read at all, see
+    /// `compact_elf_layout()`. `Config::max_elf_size` and `Config::max_section_count`
+    /// are checked before the corresponding parts are allocated.
+    #[cfg(feature = "std")]
+    pub fn from_elf_reader<R: Read + Seek>(
+        config: Config,
+        mut reader: R,
+        syscall_registry: SyscallRegistry<C>,
+    ) -> Result<Self, ElfError> {
+        let file_size = reader
+            .seek(SeekFrom::End(0))
+            .map_err(|error| ElfError::FailedToParse(error.to_string()))?;
+        if file_size > config.max_elf_size as u64 {
+            return Err(ElfError::FileTooLarge(file_size as usize));
+        }
+        let mut header = [0; mem::size_of::<Elf64Ehdr>()];
+        let header = &mut header[..(file_size as usize).min(mem::size_of::<Elf64Ehdr>())];
+        read_exact_at(&mut reader, 0, header)?;
+        let elf_bytes = match compact_elf_layout(&config, &mut reader, file_size, header)? {
+            Some(elf_bytes) => elf_bytes,
+            None => {
+                // Leave the errors of unusual layouts to the parsers
+                let mut elf_bytes =
+                    AlignedMemory::<{ HOST_ALIGN }>::zero_filled(file_size as usize);
+                read_exact_at(&mut reader, 0, elf_bytes.as_slice_mut())?;
+                elf_bytes
+            }
+        };
+        Self::load(config, elf_bytes.as_slice(), syscall_registry)
+    }
+
+    /// Same as `load`, optionally measures how long the relocation took
+    pub(crate) fn load_and_intern(
+        config: Config,
+        bytes: &[u8],
+        syscall_registry: SyscallRegistry<C>,
+        rodata_interner: Option<&RodataInterner>,
+        relocation_time: Option<&mut Duration>,
+    ) -> Result<Self, ElfError> {
+        if bytes.len() > config.max_elf_size {
+            return Err(ElfError::FileTooLarge(bytes.len()));
+        }
+        // The parsers reinterpret the file in host byte order
+        if cfg!(target_endian = "big") {
+            return Err(ElfError::UnsupportedEndianness(
+                bytes.get(EI_DATA as usize).copied().unwrap_or_default(),
+            ));
+        }
+        // Big endian files are converted upfront, so that the parsers and the
+        // relocation code only ever have to deal with little endian.
+        let converted;
+        let bytes = match bytes.get(EI_DATA as usize) {
+            Some(&ELFDATA2MSB) if bytes.starts_with(&ELFMAG) => {
+                converted = convert_to_little_endian(bytes)?;
+                converted.as_slice()
+            }
+            Some(&ei_data) if bytes.starts_with(&ELFMAG) && ei_data != ELFDATA2LSB => {
+                return Err(ElfError::UnsupportedEndianness(ei_data));
+            }
+            _ => bytes,
+        };
+        // Must happen before the parsers allocate anything based on the section count
+        let section_count = declared_section_count(bytes);
+        if section_count > config.max_section_count as u64 {
+            return Err(ElfError::TooManySections(section_count));
+        }
         if config.new_elf_parser {
             // The new parser creates references from the input byte slice, so
             // it must be properly aligned. We assume that HOST_ALIGN is a
@@ -441,13 +1332,22 @@ impl<C: ContextObject> Executable<C> {
                 aligned = AlignedMemory::<{ HOST_ALIGN }>::from_slice(bytes);
                 aligned.as_slice()
             };
-            Self::load_with_parser(&NewParser::parse(bytes)?, config, bytes, syscall_registry)
+            Self::load_with_parser(
+                &NewParser::parse(bytes)?,
+                config,
+                bytes,
+                syscall_registry,
+                rodata_interner,
+                relocation_time,
+            )
         } else {
             Self::load_with_parser(
                 &GoblinParser::parse(bytes)?,
                 config,
                 bytes,
                 syscall_registry,
+                rodata_interner,
+                relocation_time,
             )
         }
     }
@@ -456,7 +1356,11 @@ impl<C: ContextObject> Executable<C> {
         elf: &'a P,
         mut config: Config,
         bytes: &[u8],
-        syscall_registry: SyscallRegistry<C>,
+        mut syscall_registry: SyscallRegistry<C>,
+        rodata_interner: Option<&RodataInterner>,
+        #[cfg_attr(not(feature = "std"), allow(unused_variables))] relocation_time: Option<
+            &mut Duration,
+        >,
     ) -> Result<Self, ElfError> {
         let mut elf_bytes = AlignedMemory::from_slice(bytes);
 
@@ -464,7 +1368,7 @@ impl<C: ContextObject> Executable<C> {
 
         // calculate the text section info
         let text_section = elf.section(".text")?;
-        let text_section_info = SectionInfo {
+        let mut text_section_info = SectionInfo {
             name: if config.enable_symbol_and_section_labels {
                 elf.section_name(text_section.sh_name())
                     .unwrap_or(".text")
@@ -495,18 +1399,29 @@ impl<C: ContextObject> Executable<C> {
         {
             return Err(ElfError::ValueOutOfBounds);
         }
+        text_section_info.vaddr = rebase_vaddr(&config, text_section_info.vaddr);
 
         // relocate symbols
+        if config.static_syscalls {
+            // Static syscalls are not relocated, the hashes are already in the instructions
+            syscall_registry.bind_all_syscalls();
+        }
         let mut function_registry = FunctionRegistry::default();
         let mut syscall_symbols = BTreeMap::default();
+        #[cfg(feature = "std")]
+        let relocation_start = Instant::now();
         Self::relocate(
             &config,
             &mut function_registry,
             &mut syscall_symbols,
-            &syscall_registry,
+            &mut syscall_registry,
             elf,
             elf_bytes.as_slice_mut(),
         )?;
+        #[cfg(feature = "std")]
+        if let Some(relocation_time) = relocation_time {
+            *relocation_time = relocation_start.elapsed();
+        }
 
         // calculate entrypoint offset into the text section
         let offset = elf.header().e_entry.saturating_sub(text_section.sh_addr());
@@ -529,12 +1444,33 @@ impl<C: ContextObject> Executable<C> {
             return Err(ElfError::InvalidEntrypoint);
         };
 
+        // The relocations above patched the writable copy of the ELF, including
+        // .data.rel.ro and its variants. From here on they are part of the read-only
+        // region, so that the program can not modify the relocated values anymore.
         let ro_section = Self::parse_ro_sections(
             &config,
             elf.section_headers()
                 .map(|s| (elf.section_name(s.sh_name()), s)),
             elf_bytes.as_slice(),
         )?;
+        let ro_section = match (ro_section, rodata_interner) {
+            (Section::Owned(offset, data), Some(rodata_interner)) => {
+                Section::Shared(offset, rodata_interner.intern(&data))
+            }
+            (ro_section, _) => ro_section,
+        };
+
+        let stack_frame_sizes = match elf.section(".stack_sizes") {
+            Ok(section) => parse_stack_sizes(
+                elf_bytes
+                    .as_slice()
+                    .get(section.file_range().unwrap_or_default())
+                    .ok_or(ElfError::ValueOutOfBounds)?,
+                text_section.sh_addr(),
+                text_section.sh_size(),
+            )?,
+            Err(_) => BTreeMap::new(),
+        };
 
         Ok(Self {
             config,
@@ -543,13 +1479,18 @@ impl<C: ContextObject> Executable<C> {
             text_section_info,
             entry_pc,
             function_registry,
+            function_starts: BTreeSet::new(),
             syscall_symbols,
             syscall_registry,
+            instruction_costs: None,
+            stack_frame_sizes,
+            has_internal_calls: false,
             #[cfg(feature = "jit")]
             compiled_program: None,
             #[cfg(not(feature = "jit"))]
             _marker: PhantomData,
-        })
+        }
+        .with_call_info_derived())
     }
 
     /// Calculate the total memory size of the executable
@@ -562,7 +1503,8 @@ impl<C: ContextObject> Executable<C> {
             // ro section
             .saturating_add(match &self.ro_section {
                 Section::Owned(_, data) => data.capacity(),
-                Section::Borrowed(_, _) => 0,
+                // shared sections are accounted for by the interner
+                Section::Borrowed(_, _) | Section::Shared(_, _) => 0,
             })
             // text section info
             .saturating_add(self.text_section_info.mem_size())
@@ -574,6 +1516,8 @@ impl<C: ContextObject> Executable<C> {
                 .saturating_add(mem::size_of_val(&val)
                 .saturating_add(mem::size_of_val(&name)
                 .saturating_add(name.capacity())))))
+            // function starts
+            .saturating_add(self.function_starts.len().saturating_mul(mem::size_of::<usize>()))
             // syscall symbols
             .saturating_add(mem::size_of_val(&self.syscall_symbols))
             .saturating_add(self.syscall_symbols
@@ -583,7 +1527,13 @@ impl<C: ContextObject> Executable<C> {
                 .saturating_add(mem::size_of_val(&name)
                 .saturating_add(name.capacity())))))
             // syscall registry
-            .saturating_add(self.syscall_registry.mem_size());
+            .saturating_add(self.syscall_registry.mem_size())
+            // instruction costs
+            .saturating_add(self.instruction_costs
+            .as_ref()
+            .map_or(0, |instruction_costs| instruction_costs.capacity().saturating_mul(mem::size_of::<u64>())))
+            // stack frame sizes
+            .saturating_add(self.stack_frame_sizes.len().saturating_mul(mem::size_of::<(usize, usize)>()));
 
         #[cfg(feature = "jit")]
         {
@@ -656,7 +1606,7 @@ impl<C: ContextObject> Executable<C> {
             return Err(ElfError::WrongClass);
         }
         if header.e_ident.ei_data != ELFDATA2LSB {
-            return Err(ElfError::WrongEndianess);
+            return Err(ElfError::UnsupportedEndianness(header.e_ident.ei_data));
         }
         if header.e_ident.ei_osabi != ELFOSABI_NONE {
             return Err(ElfError::WrongAbi);
@@ -721,6 +1671,15 @@ impl<C: ContextObject> Executable<C> {
             }
         }
 
+        if config.reject_unexpected_sections {
+            for section_header in elf.section_headers() {
+                let name = elf.section_name(section_header.sh_name()).unwrap_or("");
+                if !is_expected_section(name) {
+                    return Err(ElfError::UnexpectedSection(name.to_owned()));
+                }
+            }
+        }
+
         for section_header in elf.section_headers() {
             let start = section_header.sh_offset() as usize;
             let end = section_header
@@ -732,6 +1691,9 @@ impl<C: ContextObject> Executable<C> {
                 .ok_or(ElfError::ValueOutOfBounds)?;
         }
         let text_section = elf.section(".text")?;
+        if text_section.sh_size() == 0 {
+            return Err(ElfError::EmptyTextSection);
+        }
         if !text_section.vm_range().contains(&header.e_entry) {
             return Err(ElfError::EntrypointOutOfBounds);
         }
@@ -772,7 +1734,7 @@ impl<C: ContextObject> Executable<C> {
                 Some(name)
                     if name == ".text"
                         || name == ".rodata"
-                        || name == ".data.rel.ro"
+                        || is_data_rel_ro_section(name)
                         || name == ".eh_frame" => {}
                 _ => continue,
             }
@@ -916,7 +1878,7 @@ impl<C: ContextObject> Executable<C> {
         config: &Config,
         function_registry: &mut FunctionRegistry,
         syscall_symbols: &mut BTreeMap<u32, String>,
-        syscall_registry: &SyscallRegistry<C>,
+        syscall_registry: &mut SyscallRegistry<C>,
         elf: &'a P,
         elf_bytes: &mut [u8],
     ) -> Result<(), ElfError> {
@@ -981,12 +1943,9 @@ impl<C: ContextObject> Executable<C> {
                     let mut addr = symbol.st_value().saturating_add(refd_addr);
 
                     // The "physical address" from the VM's perspective is rooted
-                    // at `MM_PROGRAM_START`. If the linker hasn't already put
-                    // the symbol within `MM_PROGRAM_START`, we need to do so
-                    // now.
-                    if addr < ebpf::MM_PROGRAM_START {
-                        addr = ebpf::MM_PROGRAM_START.saturating_add(addr);
-                    }
+                    // at `Config::rodata_vaddr_base`. The linker may or may not
+                    // have put the symbol within `MM_PROGRAM_START` already.
+                    addr = rebase_vaddr(config, addr);
 
                     // Write the low side of the relocate address
                     let imm_slice = elf_bytes
@@ -1049,11 +2008,9 @@ impl<C: ContextObject> Executable<C> {
                             return Err(ElfError::InvalidVirtualAddress(refd_addr));
                         }
 
-                        if refd_addr < ebpf::MM_PROGRAM_START {
-                            // The linker hasn't already placed rodata within
-                            // MM_PROGRAM_START, so we do so now
-                            refd_addr = ebpf::MM_PROGRAM_START.saturating_add(refd_addr);
-                        }
+                        // The linker may or may not have placed rodata within
+                        // MM_PROGRAM_START already
+                        refd_addr = rebase_vaddr(config, refd_addr);
 
                         // Write back the low half
                         let imm_slice = elf_bytes
@@ -1083,12 +2040,8 @@ impl<C: ContextObject> Executable<C> {
                             let addr_slice = elf_bytes
                                 .get(r_offset..r_offset.saturating_add(mem::size_of::<u64>()))
                                 .ok_or(ElfError::ValueOutOfBounds)?;
-                            let mut refd_addr = LittleEndian::read_u64(addr_slice);
-                            if refd_addr < ebpf::MM_PROGRAM_START {
-                                // Not within MM_PROGRAM_START, do it now
-                                refd_addr = ebpf::MM_PROGRAM_START.saturating_add(refd_addr);
-                            }
-                            refd_addr
+                            let refd_addr = LittleEndian::read_u64(addr_slice);
+                            rebase_vaddr(config, refd_addr)
                         } else {
                             // There used to be a bug in toolchains before
                             // https://github.com/solana-labs/llvm-project/pull/35 where for 64 bit
@@ -1099,7 +2052,7 @@ impl<C: ContextObject> Executable<C> {
                                 .get(imm_offset..imm_offset.saturating_add(BYTE_LENGTH_IMMEDIATE))
                                 .ok_or(ElfError::ValueOutOfBounds)?;
                             let refd_addr = LittleEndian::read_u32(addr_slice) as u64;
-                            ebpf::MM_PROGRAM_START.saturating_add(refd_addr)
+                            config.rodata_vaddr_base.saturating_add(refd_addr)
                         };
 
                         let addr_slice = elf_bytes
@@ -1138,26 +2091,41 @@ impl<C: ContextObject> Executable<C> {
                             name,
                         )?
                     } else {
-                        // Else it's a syscall
-                        let hash = syscall_cache
-                            .entry(symbol.st_name())
-                            .or_insert_with(|| (ebpf::hash_symbol_name(name.as_bytes()), name))
-                            .0;
-                        if config.reject_broken_elfs
-                            && syscall_registry.lookup_syscall(hash).is_none()
-                        {
-                            return Err(ElfError::UnresolvedSymbol(
-                                name.to_string(),
-                                r_offset
-                                    .checked_div(ebpf::INSN_SIZE)
-                                    .and_then(|offset| {
-                                        offset.checked_add(ebpf::ELF_INSN_DUMP_OFFSET)
-                                    })
-                                    .unwrap_or(ebpf::ELF_INSN_DUMP_OFFSET),
-                                r_offset,
-                            ));
+                        // Else it's a syscall, which gets bound to its hash now
+                        let syscall_name = config
+                            .syscall_symbol_prefix
+                            .and_then(|prefix| name.strip_prefix(prefix))
+                            .unwrap_or(name);
+                        let is_bound = syscall_registry
+                            .bind_syscall_by_name(syscall_name.as_bytes())
+                            .is_ok();
+                        if !is_bound && symbol.is_weak() {
+                            // Unresolved weak symbols are relocated to address 0
+                            0
+                        } else {
+                            let hash = syscall_cache
+                                .entry(symbol.st_name())
+                                .or_insert_with(|| {
+                                    (
+                                        ebpf::hash_symbol_name(syscall_name.as_bytes()),
+                                        syscall_name,
+                                    )
+                                })
+                                .0;
+                            if !is_bound && config.reject_broken_elfs {
+                                return Err(ElfError::UnresolvedSymbol(
+                                    name.to_string(),
+                                    r_offset
+                                        .checked_div(ebpf::INSN_SIZE)
+                                        .and_then(|offset| {
+                                            offset.checked_add(ebpf::ELF_INSN_DUMP_OFFSET)
+                                        })
+                                        .unwrap_or(ebpf::ELF_INSN_DUMP_OFFSET),
+                                    r_offset,
+                                ));
+                            }
+                            hash
                         }
-                        hash
                     };
 
                     let checked_slice = elf_bytes
@@ -1165,7 +2133,12 @@ impl<C: ContextObject> Executable<C> {
                         .ok_or(ElfError::ValueOutOfBounds)?;
                     LittleEndian::write_u32(checked_slice, key);
                 }
-                _ => return Err(ElfError::UnknownRelocation(relocation.r_type())),
+                _ => {
+                    return Err(ElfError::UnsupportedRelocationType(
+                        relocation.r_type(),
+                        relocation.r_sym(),
+                    ))
+                }
             }
         }
 
@@ -1204,6 +2177,7 @@ impl<C: ContextObject> Executable<C> {
     }
 
     #[allow(dead_code)]
+    #[cfg(feature = "std")]
     fn dump_data(name: &str, prog: &[u8]) {
         let mut eight_bytes: Vec<u8> = Vec::new();
         println!("{}", name);
@@ -1218,19 +2192,27 @@ impl<C: ContextObject> Executable<C> {
     }
 }
 
-pub(crate) fn get_ro_region(ro_section: &Section, elf: &[u8]) -> MemoryRegion {
+pub(crate) fn get_ro_region(ro_section: &Section, elf: &[u8], vm_addr: u64) -> MemoryRegion {
     let (offset, ro_data) = match ro_section {
         Section::Owned(offset, data) => (*offset, data.as_slice()),
         Section::Borrowed(offset, byte_range) => (*offset, &elf[byte_range.clone()]),
+        Section::Shared(offset, data) => (*offset, &data[..]),
     };
 
-    // If offset > 0, the region will start at MM_PROGRAM_START + the offset of
-    // the first read only byte. [MM_PROGRAM_START, MM_PROGRAM_START + offset)
+    // If offset > 0, the region will start at vm_addr + the offset of
+    // the first read only byte. [vm_addr, vm_addr + offset)
     // will be unmappable, see MemoryRegion::vm_to_host.
-    MemoryRegion::new_readonly(
-        ro_data,
-        ebpf::MM_PROGRAM_START.saturating_add(offset as u64),
-    )
+    MemoryRegion::new_readonly(ro_data, vm_addr.saturating_add(offset as u64))
+}
+
+/// Moves an address of the ELF file into the program region at `Config::rodata_vaddr_base`
+///
+/// The linker may or may not have placed the address within `MM_PROGRAM_START` already.
+fn rebase_vaddr(config: &Config, vaddr: u64) -> u64 {
+    vaddr
+        .checked_sub(ebpf::MM_PROGRAM_START)
+        .unwrap_or(vaddr)
+        .saturating_add(config.rodata_vaddr_base)
 }
 
 #[cfg(test)]
@@ -1240,12 +2222,14 @@ mod test {
         ebpf,
         elf_parser::{
             // FIXME consts::{ELFCLASS32, ELFDATA2MSB, ET_REL},
-            consts::{ELFCLASS32, ELFDATA2MSB, ET_REL},
+            consts::{ELFCLASS32, ET_REL},
+            convert_to_big_endian,
             types::{Elf64Ehdr, Elf64Shdr},
+            Elf64,
         },
         fuzz::fuzz,
         syscalls,
-        vm::{ProgramResult, TestContextObject},
+        vm::{ProgramResult, TestContextObject, TestUserError},
     };
     use rand::{distributions::Uniform, Rng};
     use std::{fs::File, io::Read};
@@ -1262,6 +2246,20 @@ mod test {
         syscall_registry
     }
 
+    /// Overwrites the entry `index` of the section header table in `elf_bytes`
+    fn write_section_header(
+        elf_bytes: &mut [u8],
+        header: &Elf64Ehdr,
+        index: usize,
+        section_header: Elf64Shdr,
+    ) {
+        let offset = header.e_shoff as usize + index * header.e_shentsize as usize;
+        let entry = &mut elf_bytes[offset..offset + mem::size_of::<Elf64Shdr>()];
+        unsafe {
+            std::ptr::write_unaligned(entry.as_mut_ptr() as *mut Elf64Shdr, section_header);
+        }
+    }
+
     #[test]
     fn test_validate() {
         let elf_bytes = std::fs::read("tests/elfs/noop.so").unwrap();
@@ -1393,6 +2391,146 @@ mod test {
             .expect("validation failed");
     }
 
+    #[test]
+    fn test_load_big_endian() {
+        for new_elf_parser in [false, true] {
+            let config = Config {
+                new_elf_parser,
+                ..Config::default()
+            };
+            for path in [
+                "tests/elfs/noop.so",
+                "tests/elfs/relative_call.so",
+                "tests/elfs/reloc_64_64.so",
+                "tests/elfs/reloc_64_relative_data.so",
+            ] {
+                let elf_bytes = std::fs::read(path).expect("failed to read elf file");
+                let big_endian_elf_bytes = convert_to_big_endian(&elf_bytes).unwrap();
+                assert_eq!(big_endian_elf_bytes[EI_DATA as usize], ELFDATA2MSB);
+                assert_eq!(
+                    convert_to_little_endian(&big_endian_elf_bytes).unwrap(),
+                    elf_bytes
+                );
+                let executable = ElfExecutable::load(config, &elf_bytes, syscall_registry())
+                    .expect("validation failed");
+                let big_endian_executable =
+                    ElfExecutable::load(config, &big_endian_elf_bytes, syscall_registry())
+                        .expect("validation failed");
+                assert_eq!(
+                    executable.get_text_bytes(),
+                    big_endian_executable.get_text_bytes()
+                );
+                assert_eq!(
+                    executable.get_ro_section(),
+                    big_endian_executable.get_ro_section()
+                );
+                assert_eq!(
+                    executable.get_function_registry(),
+                    big_endian_executable.get_function_registry()
+                );
+            }
+        }
+
+        // The addend of the relative relocation in .data.rel.ro is byte swapped as well
+        let elf_bytes =
+            std::fs::read("tests/elfs/reloc_64_relative_data.so").expect("failed to read elf file");
+        let big_endian_elf_bytes = convert_to_big_endian(&elf_bytes).unwrap();
+        let elf = Elf64::parse(&elf_bytes).unwrap();
+        let section_header = elf
+            .section_header_table()
+            .iter()
+            .find(|section_header| elf.section_name(section_header.sh_name) == Ok(".data.rel.ro"))
+            .unwrap();
+        let file_range = section_header.file_range().unwrap();
+        assert_ne!(LittleEndian::read_u64(&elf_bytes[file_range.clone()]), 0);
+        assert_eq!(
+            LittleEndian::read_u64(&elf_bytes[file_range.clone()]),
+            BigEndian::read_u64(&big_endian_elf_bytes[file_range])
+        );
+
+        let mut elf_bytes = std::fs::read("tests/elfs/noop.so").expect("failed to read elf file");
+        elf_bytes[EI_DATA as usize] = 3;
+        assert_eq!(
+            ElfExecutable::load(Config::default(), &elf_bytes, syscall_registry()).err(),
+            Some(ElfError::UnsupportedEndianness(3))
+        );
+    }
+
+    #[test]
+    fn test_fingerprint() {
+        let mut file = File::open("tests/elfs/noop.so").expect("file open failed");
+        let mut elf_bytes = Vec::new();
+        file.read_to_end(&mut elf_bytes)
+            .expect("failed to read elf file");
+        let fingerprint = |elf_bytes: &[u8], config: Config| {
+            ElfExecutable::load(config, elf_bytes, syscall_registry())
+                .expect("validation failed")
+                .fingerprint()
+        };
+        let expected = fingerprint(&elf_bytes, Config::default());
+        assert_eq!(expected, fingerprint(&elf_bytes, Config::default()));
+
+        // Moving .hash in front of .gnu.hash, in the file and in the section header table
+        let parsed_elf = NewParser::parse(&elf_bytes).unwrap();
+        let header = parsed_elf.header().clone();
+        let mut gnu_hash = parsed_elf.section(".gnu.hash").unwrap();
+        let mut hash = parsed_elf.section(".hash").unwrap();
+        let file_range = |section_header: &Elf64Shdr| {
+            section_header.sh_offset as usize
+                ..(section_header.sh_offset + section_header.sh_size) as usize
+        };
+        let contents = [
+            &elf_bytes[file_range(&hash)],
+            &elf_bytes[file_range(&gnu_hash)],
+        ]
+        .concat();
+        hash.sh_offset = gnu_hash.sh_offset;
+        hash.sh_addr = gnu_hash.sh_addr;
+        gnu_hash.sh_offset = hash.sh_offset + hash.sh_size;
+        gnu_hash.sh_addr = hash.sh_addr + hash.sh_size;
+        let mut reordered = elf_bytes.clone();
+        reordered[hash.sh_offset as usize..][..contents.len()].copy_from_slice(&contents);
+        for (index, section_header) in [(2, hash), (3, gnu_hash)] {
+            write_section_header(&mut reordered, &header, index, section_header);
+        }
+        assert_ne!(reordered, elf_bytes);
+        assert_eq!(expected, fingerprint(&reordered, Config::default()));
+
+        // Settings which only affect loading or verification are not covered
+        let config = Config {
+            enable_symbol_and_section_labels: true,
+            reject_falls_off_end: true,
+            ..Config::default()
+        };
+        assert_eq!(expected, fingerprint(&elf_bytes, config));
+        for config in [
+            Config {
+                enable_sdiv: false,
+                ..Config::default()
+            },
+            Config {
+                noop_instruction_rate: 0,
+                ..Config::default()
+            },
+            Config {
+                sanitize_user_provided_values: false,
+                ..Config::default()
+            },
+            Config {
+                jit_function_padding: 16,
+                ..Config::default()
+            },
+        ] {
+            assert_ne!(expected, fingerprint(&elf_bytes, config));
+        }
+
+        // Changing the immediate of "mov64 r2, 0xb"
+        let mut modified = elf_bytes.clone();
+        assert_eq!(modified[0x1010], 0xb7);
+        modified[0x1014] += 1;
+        assert_ne!(expected, fingerprint(&modified, Config::default()));
+    }
+
     #[test]
     fn test_entrypoint() {
         let mut file = File::open("tests/elfs/noop.so").expect("file open failed");
@@ -1450,6 +2588,37 @@ mod test {
         assert_eq!(0, executable.get_entrypoint_instruction_offset());
     }
 
+    #[test]
+    fn test_empty_text_section() {
+        let mut file = File::open("tests/elfs/noop.so").expect("file open failed");
+        let mut elf_bytes = Vec::new();
+        file.read_to_end(&mut elf_bytes)
+            .expect("failed to read elf file");
+        let parsed_elf = NewParser::parse(&elf_bytes).unwrap();
+        let header = parsed_elf.header().clone();
+        let index = parsed_elf
+            .section_headers()
+            .position(|section_header| {
+                parsed_elf.section_name(section_header.sh_name()) == Some(".text")
+            })
+            .unwrap();
+        let mut text_section = parsed_elf.section(".text").unwrap();
+        text_section.sh_size = 0;
+        let mut modified = elf_bytes.clone();
+        write_section_header(&mut modified, &header, index, text_section);
+
+        for new_elf_parser in [true, false] {
+            let config = Config {
+                new_elf_parser,
+                ..Config::default()
+            };
+            assert_eq!(
+                Err(ElfError::EmptyTextSection),
+                ElfExecutable::load(config, &modified, syscall_registry())
+            );
+        }
+    }
+
     #[test]
     fn test_fixup_relative_calls_back() {
         let config = Config {
@@ -1747,6 +2916,38 @@ mod test {
         ));
     }
 
+    #[test]
+    fn test_data_rel_ro_sections() {
+        let config = Config::default();
+        let elf_bytes = [0u8; 512];
+
+        let s1 = new_section(10, 10);
+        let s2 = new_section(20, 10);
+        let s3 = new_section(30, 10);
+
+        assert_eq!(
+            ElfExecutable::parse_ro_sections(
+                &config,
+                [
+                    (Some(".text"), &s1),
+                    (Some(".data.rel.ro"), &s2),
+                    (Some(".data.rel.ro.local"), &s3)
+                ],
+                &elf_bytes,
+            ),
+            Ok(Section::Borrowed(10, 10..40))
+        );
+        // Only the exact name or a dotted suffix counts
+        assert_eq!(
+            ElfExecutable::parse_ro_sections(
+                &config,
+                [(Some(".text"), &s1), (Some(".data.rel.rox"), &s2)],
+                &elf_bytes,
+            ),
+            Ok(Section::Borrowed(10, 10..20))
+        );
+    }
+
     #[test]
     fn test_owned_ro_sections_with_sh_offset() {
         let config = Config {
@@ -1886,7 +3087,7 @@ mod test {
             &elf_bytes,
         )
         .unwrap();
-        let ro_region = get_ro_region(&ro_section, &elf_bytes);
+        let ro_region = get_ro_region(&ro_section, &elf_bytes, ebpf::MM_PROGRAM_START);
         let owned_section = match &ro_section {
             Section::Owned(_offset, data) => data.as_slice(),
             _ => panic!(),
@@ -1894,13 +3095,13 @@ mod test {
 
         // [0..s3.sh_addr + s3.sh_size] is the valid ro memory area
         assert!(matches!(
-            ro_region.vm_to_host(ebpf::MM_PROGRAM_START, s3.sh_addr + s3.sh_size),
+            ro_region.vm_to_host::<TestUserError>(ebpf::MM_PROGRAM_START, s3.sh_addr + s3.sh_size),
             ProgramResult::Ok(ptr) if ptr == owned_section.as_ptr() as u64,
         ));
 
         // one byte past the ro section is not mappable
         assert!(matches!(
-            ro_region.vm_to_host(ebpf::MM_PROGRAM_START + s3.sh_addr + s3.sh_size, 1),
+            ro_region.vm_to_host::<TestUserError>(ebpf::MM_PROGRAM_START + s3.sh_addr + s3.sh_size, 1),
             ProgramResult::Err(EbpfError::InvalidVirtualAddress(
                 address
             )) if address == ebpf::MM_PROGRAM_START + s3.sh_addr + s3.sh_size,
@@ -1930,7 +3131,7 @@ mod test {
             &elf_bytes,
         )
         .unwrap();
-        let ro_region = get_ro_region(&ro_section, &elf_bytes);
+        let ro_region = get_ro_region(&ro_section, &elf_bytes, ebpf::MM_PROGRAM_START);
         let owned_section = match &ro_section {
             Section::Owned(_offset, data) => data.as_slice(),
             _ => panic!(),
@@ -1940,13 +3141,13 @@ mod test {
         // But for backwards compatibility (config.optimize_rodata=false)
         // [0..s1.sh_addr] is mappable too (and zeroed).
         assert!(matches!(
-            ro_region.vm_to_host(ebpf::MM_PROGRAM_START, s3.sh_addr + s3.sh_size),
+            ro_region.vm_to_host::<TestUserError>(ebpf::MM_PROGRAM_START, s3.sh_addr + s3.sh_size),
             ProgramResult::Ok(ptr) if ptr == owned_section.as_ptr() as u64,
         ));
 
         // one byte past the ro section is not mappable
         assert!(matches!(
-            ro_region.vm_to_host(ebpf::MM_PROGRAM_START + s3.sh_addr + s3.sh_size, 1),
+            ro_region.vm_to_host::<TestUserError>(ebpf::MM_PROGRAM_START + s3.sh_addr + s3.sh_size, 1),
             ProgramResult::Err(EbpfError::InvalidVirtualAddress(
                 address
             )) if address == ebpf::MM_PROGRAM_START + s3.sh_addr + s3.sh_size,
@@ -1977,25 +3178,25 @@ mod test {
             Section::Owned(_offset, data) => data.as_slice(),
             _ => panic!(),
         };
-        let ro_region = get_ro_region(&ro_section, &elf_bytes);
+        let ro_region = get_ro_region(&ro_section, &elf_bytes, ebpf::MM_PROGRAM_START);
 
         // s1 starts at sh_addr=10 so [MM_PROGRAM_START..MM_PROGRAM_START + 10] is not mappable
 
         // the low bound of the initial gap is not mappable
         assert!(matches!(
-            ro_region.vm_to_host(ebpf::MM_PROGRAM_START, 1),
+            ro_region.vm_to_host::<TestUserError>(ebpf::MM_PROGRAM_START, 1),
             ProgramResult::Err(EbpfError::InvalidVirtualAddress(address)) if address == ebpf::MM_PROGRAM_START,
         ));
 
         // the hi bound of the initial gap is not mappable
         assert!(matches!(
-            ro_region.vm_to_host(ebpf::MM_PROGRAM_START + s1.sh_addr - 1, 1),
+            ro_region.vm_to_host::<TestUserError>(ebpf::MM_PROGRAM_START + s1.sh_addr - 1, 1),
             ProgramResult::Err(EbpfError::InvalidVirtualAddress(address)) if address == ebpf::MM_PROGRAM_START + 9,
         ));
 
         // [s1.sh_addr..s3.sh_addr + s3.sh_size] is the valid ro memory area
         assert!(matches!(
-            ro_region.vm_to_host(
+            ro_region.vm_to_host::<TestUserError>(
                 ebpf::MM_PROGRAM_START + s1.sh_addr,
                 s3.sh_addr + s3.sh_size - s1.sh_addr
             ),
@@ -2004,7 +3205,7 @@ mod test {
 
         // one byte past the ro section is not mappable
         assert!(matches!(
-            ro_region.vm_to_host(ebpf::MM_PROGRAM_START + s3.sh_addr + s3.sh_size, 1),
+            ro_region.vm_to_host::<TestUserError>(ebpf::MM_PROGRAM_START + s3.sh_addr + s3.sh_size, 1),
             ProgramResult::Err(EbpfError::InvalidVirtualAddress(
                 address
             )) if address == ebpf::MM_PROGRAM_START + s3.sh_addr + s3.sh_size,
@@ -2078,18 +3279,18 @@ mod test {
             &elf_bytes,
         )
         .unwrap();
-        let ro_region = get_ro_region(&ro_section, &elf_bytes);
+        let ro_region = get_ro_region(&ro_section, &elf_bytes, ebpf::MM_PROGRAM_START);
 
         // s1 starts at sh_addr=0 so [0..s2.sh_addr + s2.sh_size] is the valid
         // ro memory area
         assert!(matches!(
-            ro_region.vm_to_host(ebpf::MM_PROGRAM_START, s2.sh_addr + s2.sh_size),
+            ro_region.vm_to_host::<TestUserError>(ebpf::MM_PROGRAM_START, s2.sh_addr + s2.sh_size),
             ProgramResult::Ok(ptr) if ptr == elf_bytes.as_ptr() as u64,
         ));
 
         // one byte past the ro section is not mappable
         assert!(matches!(
-            ro_region.vm_to_host(ebpf::MM_PROGRAM_START + s2.sh_addr + s2.sh_size, 1),
+            ro_region.vm_to_host::<TestUserError>(ebpf::MM_PROGRAM_START + s2.sh_addr + s2.sh_size, 1),
             ProgramResult::Err(EbpfError::InvalidVirtualAddress(
                 address
             )) if address == ebpf::MM_PROGRAM_START + s2.sh_addr + s2.sh_size,
@@ -2114,25 +3315,25 @@ mod test {
             &elf_bytes,
         )
         .unwrap();
-        let ro_region = get_ro_region(&ro_section, &elf_bytes);
+        let ro_region = get_ro_region(&ro_section, &elf_bytes, ebpf::MM_PROGRAM_START);
 
         // s2 starts at sh_addr=10 so [0..10] is not mappable
 
         // the low bound of the initial gap is not mappable
         assert!(matches!(
-            ro_region.vm_to_host(ebpf::MM_PROGRAM_START, 1),
+            ro_region.vm_to_host::<TestUserError>(ebpf::MM_PROGRAM_START, 1),
             ProgramResult::Err(EbpfError::InvalidVirtualAddress(address)) if address == ebpf::MM_PROGRAM_START,
         ));
 
         // the hi bound of the initial gap is not mappable
         assert!(matches!(
-            ro_region.vm_to_host(ebpf::MM_PROGRAM_START + s2.sh_addr - 1, 1),
+            ro_region.vm_to_host::<TestUserError>(ebpf::MM_PROGRAM_START + s2.sh_addr - 1, 1),
             ProgramResult::Err(EbpfError::InvalidVirtualAddress(address)) if address == ebpf::MM_PROGRAM_START + 9,
         ));
 
         // [s2.sh_addr..s3.sh_addr + s3.sh_size] is the valid ro memory area
         assert!(matches!(
-            ro_region.vm_to_host(
+            ro_region.vm_to_host::<TestUserError>(
                 ebpf::MM_PROGRAM_START + s2.sh_addr,
                 s3.sh_addr + s3.sh_size - s2.sh_addr
             ),
@@ -2141,7 +3342,7 @@ mod test {
 
         // one byte past the ro section is not mappable
         assert!(matches!(
-            ro_region.vm_to_host(ebpf::MM_PROGRAM_START + s3.sh_addr + s3.sh_size, 1),
+            ro_region.vm_to_host::<TestUserError>(ebpf::MM_PROGRAM_START + s3.sh_addr + s3.sh_size, 1),
             ProgramResult::Err(EbpfError::InvalidVirtualAddress(
                 address
             )) if address == ebpf::MM_PROGRAM_START + s3.sh_addr + s3.sh_size,
@@ -2209,6 +3410,79 @@ mod test {
             .expect("validation failed");
     }
 
+    #[test]
+    fn test_rodata_interner() {
+        let config = Config {
+            optimize_rodata: false,
+            ..Config::default()
+        };
+        let rodata_interner = RodataInterner::default();
+        let load = |file, rodata_interner| {
+            let elf_bytes = std::fs::read(file).expect("failed to read elf file");
+            ElfExecutable::load_with_rodata_interner(
+                config,
+                &elf_bytes,
+                syscall_registry(),
+                rodata_interner,
+            )
+            .expect("validation failed")
+        };
+        let executable_a = load("tests/elfs/rodata.so", Some(&rodata_interner));
+        let executable_b = load("tests/elfs/rodata.so", Some(&rodata_interner));
+        let executable_c = load("tests/elfs/noop.so", Some(&rodata_interner));
+        let executable_d = load("tests/elfs/rodata.so", None);
+        assert_eq!(rodata_interner.len(), 2);
+        assert_eq!(
+            executable_a.get_ro_section().as_ptr(),
+            executable_b.get_ro_section().as_ptr()
+        );
+        assert_ne!(
+            executable_a.get_ro_section().as_ptr(),
+            executable_c.get_ro_section().as_ptr()
+        );
+        assert_ne!(
+            executable_a.get_ro_section().as_ptr(),
+            executable_d.get_ro_section().as_ptr()
+        );
+        assert_eq!(executable_a.get_ro_section(), executable_d.get_ro_section());
+        assert_eq!(executable_a.get_text_bytes(), executable_d.get_text_bytes());
+        let region = executable_a.get_ro_region();
+        assert!(!region.is_writable);
+        assert_eq!(region.host_addr, executable_b.get_ro_region().host_addr);
+
+        drop(executable_c);
+        rodata_interner.purge();
+        assert_eq!(rodata_interner.len(), 1);
+        drop(executable_a);
+        drop(executable_b);
+        rodata_interner.purge();
+        assert!(rodata_interner.is_empty());
+    }
+
+    #[test]
+    fn test_reject_unexpected_sections() {
+        let config = Config {
+            reject_unexpected_sections: true,
+            ..Config::default()
+        };
+        for (file, unexpected_section) in [
+            ("tests/elfs/noop.so", Some(".comment")),
+            ("tests/elfs/strict_note.so", Some(".note.stray")),
+            ("tests/elfs/strict.so", None),
+        ] {
+            let elf_bytes = std::fs::read(file).expect("failed to read elf file");
+            assert!(ElfExecutable::load(Config::default(), &elf_bytes, syscall_registry()).is_ok());
+            let result = ElfExecutable::load(config, &elf_bytes, syscall_registry());
+            match unexpected_section {
+                Some(name) => assert_eq!(
+                    result.err(),
+                    Some(ElfError::UnexpectedSection(name.to_string()))
+                ),
+                None => assert!(result.is_ok()),
+            }
+        }
+    }
+
     #[test]
     #[should_panic(expected = r#"validation failed: RelativeJumpOutOfBounds(29)"#)]
     fn test_static_syscall_disabled() {
@@ -2237,6 +3511,301 @@ mod test {
             .expect("validation failed");
     }
 
+    #[test]
+    fn test_file_too_large() {
+        let elf_bytes = std::fs::read("tests/elfs/noop.so").expect("failed to read elf file");
+        for (max_elf_size, expected_error) in [
+            (elf_bytes.len(), None),
+            (
+                elf_bytes.len() - 1,
+                Some(ElfError::FileTooLarge(elf_bytes.len())),
+            ),
+        ] {
+            let config = Config {
+                max_elf_size,
+                ..Config::default()
+            };
+            let result = ElfExecutable::load(config, &elf_bytes, syscall_registry());
+            assert_eq!(result.err(), expected_error);
+        }
+    }
+
+    #[test]
+    fn test_too_many_sections() {
+        let mut elf_bytes = std::fs::read("tests/elfs/noop.so").expect("failed to read elf file");
+        let section_count = LittleEndian::read_u16(&elf_bytes[60..62]) as usize;
+        for (max_section_count, expected_error) in [
+            (section_count, None),
+            (
+                section_count - 1,
+                Some(ElfError::TooManySections(section_count as u64)),
+            ),
+        ] {
+            let config = Config {
+                max_section_count,
+                ..Config::default()
+            };
+            let result = ElfExecutable::load(config, &elf_bytes, syscall_registry());
+            assert_eq!(result.err(), expected_error);
+        }
+
+        // Claim billions of sections through extended numbering
+        let e_shoff = LittleEndian::read_u64(&elf_bytes[40..48]) as usize;
+        LittleEndian::write_u16(&mut elf_bytes[60..62], 0);
+        LittleEndian::write_u64(&mut elf_bytes[e_shoff + 32..e_shoff + 40], 0xffff_ffff_ffff);
+        for new_elf_parser in [false, true] {
+            let config = Config {
+                new_elf_parser,
+                ..Config::default()
+            };
+            assert_eq!(
+                ElfExecutable::load(config, &elf_bytes, syscall_registry()).err(),
+                Some(ElfError::TooManySections(0xffff_ffff_ffff))
+            );
+        }
+    }
+
+    #[test]
+    fn test_from_elf_reader() {
+        let mut paths = std::fs::read_dir("tests/elfs")
+            .expect("failed to read directory")
+            .map(|entry| entry.unwrap().path())
+            .filter(|path| path.extension().map(|ext| ext == "so").unwrap_or(false))
+            .collect::<Vec<_>>();
+        paths.sort();
+        for new_elf_parser in [false, true] {
+            let config = Config {
+                new_elf_parser,
+                ..Config::default()
+            };
+            for path in paths.iter() {
+                let elf_bytes = std::fs::read(path).expect("failed to read elf file");
+                for elf_bytes in [
+                    elf_bytes.clone(),
+                    convert_to_big_endian(&elf_bytes).unwrap(),
+                ] {
+                    let expected = ElfExecutable::load(config, &elf_bytes, syscall_registry());
+                    let streamed = ElfExecutable::from_elf_reader(
+                        config,
+                        std::io::Cursor::new(&elf_bytes),
+                        syscall_registry(),
+                    );
+                    match (expected, streamed) {
+                        (Ok(expected), Ok(streamed)) => {
+                            // Only the parts of the file which loading needs were read
+                            assert!(streamed.mem_size() <= expected.mem_size());
+                            assert_eq!(expected.get_text_bytes(), streamed.get_text_bytes());
+                            assert_eq!(expected.get_ro_section(), streamed.get_ro_section());
+                            assert_eq!(
+                                expected.get_entrypoint_instruction_offset(),
+                                streamed.get_entrypoint_instruction_offset()
+                            );
+                            assert_eq!(
+                                expected.get_function_registry(),
+                                streamed.get_function_registry()
+                            );
+                        }
+                        (expected, streamed) => assert_eq!(expected.err(), streamed.err()),
+                    }
+                }
+            }
+        }
+
+        // The 100 bytes of the .comment section of noop.so are not read
+        let elf_bytes = std::fs::read("tests/elfs/noop.so").expect("failed to read elf file");
+        let image = compact_elf_layout(
+            &Config::default(),
+            &mut std::io::Cursor::new(&elf_bytes),
+            elf_bytes.len() as u64,
+            &elf_bytes[..mem::size_of::<Elf64Ehdr>()],
+        )
+        .unwrap()
+        .unwrap();
+        assert!(image.len() <= elf_bytes.len() - 100);
+
+        let file = File::open("tests/elfs/noop.so").expect("failed to open elf file");
+        let file_size = file.metadata().unwrap().len() as usize;
+        let config = Config {
+            max_elf_size: file_size - 1,
+            ..Config::default()
+        };
+        assert_eq!(
+            ElfExecutable::from_elf_reader(config, file, syscall_registry()).err(),
+            Some(ElfError::FileTooLarge(file_size))
+        );
+
+        let mut elf_bytes = std::fs::read("tests/elfs/noop.so").expect("failed to read elf file");
+        let e_shoff = LittleEndian::read_u64(&elf_bytes[40..48]) as usize;
+        LittleEndian::write_u16(&mut elf_bytes[60..62], 0);
+        LittleEndian::write_u64(&mut elf_bytes[e_shoff + 32..e_shoff + 40], 0xffff_ffff_ffff);
+        assert_eq!(
+            ElfExecutable::from_elf_reader(
+                Config::default(),
+                std::io::Cursor::new(&elf_bytes),
+                syscall_registry()
+            )
+            .err(),
+            Some(ElfError::TooManySections(0xffff_ffff_ffff))
+        );
+    }
+
+    #[test]
+    fn test_function_containing() {
+        for enable_symbol_and_section_labels in [false, true] {
+            let config = Config {
+                enable_symbol_and_section_labels,
+                ..Config::default()
+            };
+            let executable = crate::assembler::assemble::<TestContextObject>(
+                "
+                lddw r1, 0x1
+                call function_foo
+                exit
+                function_foo:
+                mov r0, r1
+                exit",
+                config,
+                SyscallRegistry::default(),
+            )
+            .unwrap();
+            let foo_name = if enable_symbol_and_section_labels {
+                "function_foo"
+            } else {
+                "function_4"
+            };
+            for (insn_index, expected) in [
+                (0, Some(("entrypoint", 0))),
+                (3, Some(("entrypoint", 0))),
+                (4, Some((foo_name, 4))),
+                (5, Some((foo_name, 4))),
+                (6, None),
+            ] {
+                assert_eq!(
+                    executable
+                        .function_containing(insn_index)
+                        .map(|(name, start)| (name.into_owned(), start)),
+                    expected.map(|(name, start)| (name.to_string(), start))
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn test_iter_instructions() {
+        let executable = crate::assembler::assemble::<TestContextObject>(
+            "
+            mov r0, 1
+            lddw r1, 0x1122334455667788
+            add64 r0, r1
+            lddw r2, 0x5
+            exit",
+            Config::default(),
+            SyscallRegistry::default(),
+        )
+        .unwrap();
+        let instructions = executable
+            .iter_instructions()
+            .map(|(index, insn)| (index, insn.opc, insn.dst, insn.imm))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            instructions,
+            vec![
+                (0, ebpf::MOV64_IMM, 0, 1),
+                (1, ebpf::LD_DW_IMM, 1, 0x1122334455667788),
+                (3, ebpf::ADD64_REG, 0, 0),
+                (4, ebpf::LD_DW_IMM, 2, 5),
+                (6, ebpf::EXIT, 0, 0),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_unsupported_relocation_type() {
+        let mut elf_bytes =
+            std::fs::read("tests/elfs/reloc_64_64.so").expect("failed to read elf file");
+        let relocation_offset = NewParser::parse(&elf_bytes)
+            .unwrap()
+            .section(".rel.dyn")
+            .unwrap()
+            .sh_offset() as usize;
+        let r_sym =
+            LittleEndian::read_u32(&elf_bytes[relocation_offset + 12..relocation_offset + 16]);
+        // Replace the type in the lower half of r_info by one which is not handled
+        LittleEndian::write_u32(
+            &mut elf_bytes[relocation_offset + 8..relocation_offset + 12],
+            0xff,
+        );
+        for new_elf_parser in [false, true] {
+            let config = Config {
+                new_elf_parser,
+                ..Config::default()
+            };
+            assert_eq!(
+                ElfExecutable::load(config, &elf_bytes, syscall_registry()).err(),
+                Some(ElfError::UnsupportedRelocationType(0xff, r_sym))
+            );
+        }
+    }
+
+    #[test]
+    fn test_relocate_rodata_symbol() {
+        // The lddw at the entrypoint of rodata.so has a R_BPF_64_64 relocation
+        // against the symbol v2:
+        //   2: 0000000000000110     8 OBJECT  GLOBAL DEFAULT    2 v2
+        let elf_bytes = std::fs::read("tests/elfs/rodata.so").expect("failed to read elf file");
+        for (new_elf_parser, optimize_rodata) in [(false, false), (false, true), (true, true)] {
+            let config = Config {
+                new_elf_parser,
+                optimize_rodata,
+                ..Config::default()
+            };
+            let executable = ElfExecutable::load(config, &elf_bytes, syscall_registry())
+                .expect("validation failed");
+            let (_vaddr, text_bytes) = executable.get_text_bytes();
+            let pc = executable.get_entrypoint_instruction_offset();
+            let lddw = ebpf::get_insn(text_bytes, pc);
+            assert_eq!(lddw.opc, ebpf::LD_DW_IMM);
+            let vm_addr = (lddw.imm as u32 as u64)
+                | ((ebpf::get_insn(text_bytes, pc + 1).imm as u32 as u64) << 32);
+            assert_eq!(vm_addr, ebpf::MM_PROGRAM_START + 0x110);
+            let host_addr = match executable
+                .get_ro_region()
+                .vm_to_host::<TestUserError>(vm_addr, 8)
+            {
+                ProgramResult::Ok(host_addr) => host_addr,
+                ProgramResult::Err(err) => panic!("{:?}", err),
+            };
+            assert_eq!(unsafe { (host_addr as *const u64).read_unaligned() }, 42);
+        }
+    }
+
+    #[test]
+    fn test_parse_stack_sizes() {
+        let text_section_addr = 0x120;
+        let mut data = Vec::new();
+        // Function at the second instruction with a two byte ULEB128 stack size of 200
+        data.extend_from_slice(&(text_section_addr + 8u64).to_le_bytes());
+        data.extend_from_slice(&[0xc8, 0x01]);
+        // Function with an empty frame, rounded up to the minimum
+        data.extend_from_slice(&text_section_addr.to_le_bytes());
+        data.push(0);
+        // Function outside of the text section
+        data.extend_from_slice(&(text_section_addr + 0x100).to_le_bytes());
+        data.push(16);
+        assert_eq!(
+            parse_stack_sizes(&data, text_section_addr, 0x100),
+            Ok(BTreeMap::from([(0, ebpf::MIN_STACK_FRAME_SIZE), (1, 200)]))
+        );
+        assert_eq!(
+            parse_stack_sizes(&data[..9], text_section_addr, 0x100),
+            Err(ElfError::ValueOutOfBounds)
+        );
+        assert_eq!(
+            parse_stack_sizes(&data[..4], text_section_addr, 0x100),
+            Err(ElfError::ValueOutOfBounds)
+        );
+    }
+
     #[cfg(all(not(windows), target_arch = "x86_64"))]
     #[cfg(feature = "jit")]
     #[test]
@@ -2252,6 +3821,6 @@ mod test {
             Executable::jit_compile(&mut executable).unwrap();
         }
 
-        assert_eq!(18482, executable.mem_size());
+        assert_eq!(19644, executable.mem_size());
     }
 }
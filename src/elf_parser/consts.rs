@@ -12,6 +12,9 @@ pub const ELFDATANONE: u8 = 0;
 pub const ELFDATA2LSB: u8 = 1;
 pub const ELFDATA2MSB: u8 = 2;
 
+pub const EI_CLASS: u8 = 4;
+pub const EI_DATA: u8 = 5;
+
 pub const EI_OSABI: u8 = 7;
 pub const ELFOSABI_NONE: u8 = 0;
 
@@ -110,6 +113,10 @@ pub const DT_PREINIT_ARRAYSZ: Elf64Xword = 33;
 pub const DT_SYMTAB_SHNDX: Elf64Xword = 34;
 pub const DT_NUM: usize = 35;
 
+pub const STB_LOCAL: u8 = 0;
+pub const STB_GLOBAL: u8 = 1;
+pub const STB_WEAK: u8 = 2;
+
 pub const STT_NOTYPE: u8 = 0;
 pub const STT_OBJECT: u8 = 1;
 pub const STT_FUNC: u8 = 2;
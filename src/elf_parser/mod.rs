@@ -3,61 +3,71 @@
 pub mod consts;
 pub mod types;
 
-use std::{fmt, mem, ops::Range, slice};
+use alloc::vec::Vec;
+use core::{fmt, mem, ops::Range, slice};
 
-use crate::{ArithmeticOverflow, ErrCheckedArithmetic};
+use crate::{error::Error, ArithmeticOverflow, ErrCheckedArithmetic};
 use {consts::*, types::*};
 
 const SECTION_NAME_LENGTH_MAXIMUM: usize = 16;
 const SYMBOL_NAME_LENGTH_MAXIMUM: usize = 1024;
 
 /// Error definitions
-#[derive(Debug, PartialEq, Eq, thiserror::Error)]
+#[derive(Debug, PartialEq, Eq)]
 pub enum ElfParserError {
     /// ELF file header is inconsistent or unsupported
-    #[error("invalid file header")]
     InvalidFileHeader,
     /// Program header is inconsistent or unsupported
-    #[error("invalid program header")]
     InvalidProgramHeader,
     /// Section header is inconsistent or unsupported
-    #[error("invalid section header")]
     InvalidSectionHeader,
     /// Section or symbol name is not UTF8 or too long
-    #[error("invalid string")]
     InvalidString,
     /// An index or memory range does exeed its boundaries
-    #[error("value out of bounds")]
     OutOfBounds,
     /// The size isn't valid
-    #[error("invalid size")]
     InvalidSize,
     /// Headers, tables or sections do overlap in the file
-    #[error("values overlap")]
     Overlap,
     /// Sections are not sorted in ascending order
-    #[error("sections not in ascending order")]
     SectionNotInOrder,
     /// No section name string table present in the file
-    #[error("no section name string table found")]
     NoSectionNameStringTable,
     /// Invalid .dynamic section table
-    #[error("invalid dynamic section table")]
     InvalidDynamicSectionTable,
     /// Invalid relocation table
-    #[error("invalid relocation table")]
     InvalidRelocationTable,
     /// Invalid alignment
-    #[error("invalid alignment")]
     InvalidAlignment,
     /// No string table
-    #[error("no string table")]
     NoStringTable,
     /// No dynamic string table
-    #[error("no dynamic string table")]
     NoDynamicStringTable,
 }
 
+impl fmt::Display for ElfParserError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::InvalidFileHeader => write!(f, "invalid file header"),
+            Self::InvalidProgramHeader => write!(f, "invalid program header"),
+            Self::InvalidSectionHeader => write!(f, "invalid section header"),
+            Self::InvalidString => write!(f, "invalid string"),
+            Self::OutOfBounds => write!(f, "value out of bounds"),
+            Self::InvalidSize => write!(f, "invalid size"),
+            Self::Overlap => write!(f, "values overlap"),
+            Self::SectionNotInOrder => write!(f, "sections not in ascending order"),
+            Self::NoSectionNameStringTable => write!(f, "no section name string table found"),
+            Self::InvalidDynamicSectionTable => write!(f, "invalid dynamic section table"),
+            Self::InvalidRelocationTable => write!(f, "invalid relocation table"),
+            Self::InvalidAlignment => write!(f, "invalid alignment"),
+            Self::NoStringTable => write!(f, "no string table"),
+            Self::NoDynamicStringTable => write!(f, "no dynamic string table"),
+        }
+    }
+}
+
+impl Error for ElfParserError {}
+
 fn check_that_there_is_no_overlap(
     range_a: &Range<usize>,
     range_b: &Range<usize>,
@@ -396,7 +406,7 @@ impl<'a> Elf64<'a> {
             .iter()
             .position(|byte| *byte == 0x00)
             .and_then(|string_length| unterminated_string_bytes.get(0..string_length))
-            .and_then(|string_bytes| std::str::from_utf8(string_bytes).ok())
+            .and_then(|string_bytes| core::str::from_utf8(string_bytes).ok())
             .ok_or(ElfParserError::InvalidString)
     }
 
@@ -565,6 +575,177 @@ fn slice_from_bytes<T: 'static>(bytes: &[u8], range: Range<usize>) -> Result<&[T
     })
 }
 
+/// Converts a big endian ELF file into its little endian equivalent
+///
+/// Byte swaps the file header, the program and section header tables, the
+/// symbol, relocation and dynamic tables, the instructions of executable
+/// sections and the addends of relative relocations in data sections. The
+/// rest of the data sections is left untouched, as its layout is unknown.
+pub fn convert_to_little_endian(elf_bytes: &[u8]) -> Result<Vec<u8>, ElfParserError> {
+    swap_byte_order(elf_bytes, ELFDATA2MSB, ELFDATA2LSB)
+}
+
+/// Converts a little endian ELF file into its big endian equivalent
+///
+/// This is the inverse of [convert_to_little_endian].
+pub fn convert_to_big_endian(elf_bytes: &[u8]) -> Result<Vec<u8>, ElfParserError> {
+    swap_byte_order(elf_bytes, ELFDATA2LSB, ELFDATA2MSB)
+}
+
+fn swap_byte_order(
+    elf_bytes: &[u8],
+    source_ei_data: u8,
+    target_ei_data: u8,
+) -> Result<Vec<u8>, ElfParserError> {
+    const FILE_HEADER_FIELDS: [usize; 13] = [2, 2, 4, 8, 8, 8, 4, 2, 2, 2, 2, 2, 2];
+    const PROGRAM_HEADER_FIELDS: [usize; 8] = [4, 4, 8, 8, 8, 8, 8, 8];
+    const SECTION_HEADER_FIELDS: [usize; 10] = [4, 4, 8, 8, 8, 8, 4, 4, 8, 8];
+    const SYMBOL_FIELDS: [usize; 6] = [4, 1, 1, 2, 8, 8];
+    const REL_FIELDS: [usize; 2] = [8, 8];
+    const RELA_FIELDS: [usize; 3] = [8, 8, 8];
+    const DYN_FIELDS: [usize; 2] = [8, 8];
+    // opcode, registers, offset and immediate
+    const INSN_FIELDS: [usize; 4] = [1, 1, 2, 4];
+
+    fn swap_table(
+        bytes: &mut [u8],
+        range: Range<usize>,
+        entry_field_sizes: &[usize],
+    ) -> Result<(), ElfParserError> {
+        let entry_size = entry_field_sizes.iter().sum::<usize>();
+        if range.len().checked_rem(entry_size) != Some(0) {
+            return Err(ElfParserError::InvalidSize);
+        }
+        for entry_offset in range.step_by(entry_size) {
+            let mut field_offset = entry_offset;
+            for field_size in entry_field_sizes {
+                let field_end = field_offset.err_checked_add(*field_size)?;
+                bytes
+                    .get_mut(field_offset..field_end)
+                    .ok_or(ElfParserError::OutOfBounds)?
+                    .reverse();
+                field_offset = field_end;
+            }
+        }
+        Ok(())
+    }
+
+    // All values are read from the unmodified input in its source byte order
+    let read = |offset: usize, size: usize| -> Result<u64, ElfParserError> {
+        let field = elf_bytes
+            .get(offset..offset.err_checked_add(size)?)
+            .ok_or(ElfParserError::OutOfBounds)?;
+        let mut value = [0u8; mem::size_of::<u64>()];
+        if source_ei_data == ELFDATA2LSB {
+            value[..size].copy_from_slice(field);
+            Ok(u64::from_le_bytes(value))
+        } else {
+            value[mem::size_of::<u64>().err_checked_sub(size)?..].copy_from_slice(field);
+            Ok(u64::from_be_bytes(value))
+        }
+    };
+
+    if elf_bytes.get(EI_DATA as usize) != Some(&source_ei_data) {
+        return Err(ElfParserError::InvalidFileHeader);
+    }
+    let e_phoff = read(32, 8)? as usize;
+    let e_shoff = read(40, 8)? as usize;
+    let e_phentsize = read(54, 2)? as usize;
+    let e_phnum = read(56, 2)? as usize;
+    let e_shentsize = read(58, 2)? as usize;
+    let e_shnum = read(60, 2)? as usize;
+    if e_phentsize != mem::size_of::<Elf64Phdr>() || e_shentsize != mem::size_of::<Elf64Shdr>() {
+        return Err(ElfParserError::InvalidFileHeader);
+    }
+
+    let mut bytes = elf_bytes.to_vec();
+    // Virtual address range and file offset of each loaded data section
+    let mut data_sections = Vec::new();
+    let mut relocation_tables = Vec::new();
+    for index in 0..e_shnum {
+        let section_header_offset = e_shentsize
+            .err_checked_mul(index)?
+            .err_checked_add(e_shoff)?;
+        let sh_type = read(section_header_offset.err_checked_add(4)?, 4)? as Elf64Word;
+        let sh_flags = read(section_header_offset.err_checked_add(8)?, 8)?;
+        let sh_addr = read(section_header_offset.err_checked_add(16)?, 8)?;
+        let sh_offset = read(section_header_offset.err_checked_add(24)?, 8)? as usize;
+        let sh_size = read(section_header_offset.err_checked_add(32)?, 8)? as usize;
+        let is_text = sh_type == SHT_PROGBITS && sh_flags & SHF_EXECINSTR != 0;
+        if sh_type == SHT_PROGBITS && sh_flags & SHF_ALLOC != 0 && !is_text {
+            data_sections.push((sh_addr..sh_addr.err_checked_add(sh_size as u64)?, sh_offset));
+        }
+        let entry_field_sizes: &[usize] = match sh_type {
+            SHT_SYMTAB | SHT_DYNSYM => &SYMBOL_FIELDS,
+            SHT_REL => &REL_FIELDS,
+            SHT_RELA => &RELA_FIELDS,
+            SHT_DYNAMIC => &DYN_FIELDS,
+            _ if is_text => &INSN_FIELDS,
+            _ => continue,
+        };
+        let section_range = sh_offset..sh_offset.err_checked_add(sh_size)?;
+        if sh_type == SHT_REL {
+            relocation_tables.push(section_range.clone());
+        }
+        swap_table(&mut bytes, section_range.clone(), entry_field_sizes)?;
+        if is_text {
+            // Big endian objects store the dst register in the upper nibble
+            for insn_offset in section_range.step_by(mem::size_of::<u64>()) {
+                let registers = bytes
+                    .get_mut(insn_offset.err_checked_add(1)?)
+                    .ok_or(ElfParserError::OutOfBounds)?;
+                *registers = registers.rotate_left(4);
+            }
+        }
+    }
+    // Relative relocations in data sections read their addend from a u64 at r_offset
+    for relocation_table in relocation_tables {
+        for relocation_offset in relocation_table.step_by(mem::size_of::<Elf64Rel>()) {
+            let r_offset = read(relocation_offset, 8)?;
+            let r_info = read(relocation_offset.err_checked_add(8)?, 8)?;
+            if r_info as u32 != R_X86_64_RELATIVE {
+                continue;
+            }
+            let data_section = data_sections
+                .iter()
+                .find(|(vm_range, _)| vm_range.contains(&r_offset));
+            if let Some((vm_range, sh_offset)) = data_section {
+                let addend_offset =
+                    sh_offset.err_checked_add(r_offset.err_checked_sub(vm_range.start)? as usize)?;
+                swap_table(
+                    &mut bytes,
+                    addend_offset..addend_offset.err_checked_add(mem::size_of::<u64>())?,
+                    &[mem::size_of::<u64>()],
+                )?;
+            }
+        }
+    }
+    swap_table(
+        &mut bytes,
+        e_phoff
+            ..e_phentsize
+                .err_checked_mul(e_phnum)?
+                .err_checked_add(e_phoff)?,
+        &PROGRAM_HEADER_FIELDS,
+    )?;
+    swap_table(
+        &mut bytes,
+        e_shoff
+            ..e_shentsize
+                .err_checked_mul(e_shnum)?
+                .err_checked_add(e_shoff)?,
+        &SECTION_HEADER_FIELDS,
+    )?;
+    swap_table(
+        &mut bytes,
+        mem::size_of::<ElfIdent>()..mem::size_of::<Elf64Ehdr>(),
+        &FILE_HEADER_FIELDS,
+    )?;
+    bytes[EI_DATA as usize] = target_ei_data;
+
+    Ok(bytes)
+}
+
 impl From<ArithmeticOverflow> for ElfParserError {
     fn from(_: ArithmeticOverflow) -> ElfParserError {
         ElfParserError::OutOfBounds
@@ -1,5 +1,6 @@
 //! Internal ELF parser abstraction.
-use std::{borrow::Cow, convert::TryInto, iter, ops::Range, slice};
+use alloc::{borrow::Cow, format, string::ToString};
+use core::{convert::TryInto, iter, ops::Range, slice};
 
 use goblin::{
     elf::{Elf, Header, ProgramHeader, Reloc, SectionHeader, Sym},
@@ -14,14 +15,14 @@ use goblin::{
 use crate::{
     elf::ElfError,
     elf_parser::{
-        consts::{SHF_ALLOC, SHF_WRITE, SHT_NOBITS, STT_FUNC},
+        consts::{SHF_ALLOC, SHF_WRITE, SHT_NOBITS, STB_WEAK, STT_FUNC},
         types::{
             Elf64Addr, Elf64Ehdr, Elf64Off, Elf64Phdr, Elf64Rel, Elf64Shdr, Elf64Sym, Elf64Word,
             Elf64Xword, ElfIdent,
         },
         Elf64, ElfParserError,
     },
-    error::EbpfError,
+    error::{EbpfError, UserDefinedError},
 };
 
 /// The common trait implemented by LegacyParser and NewParser.
@@ -161,6 +162,11 @@ pub trait ElfSymbol: Clone {
     fn is_function(&self) -> bool {
         (self.st_info() & 0xF) == STT_FUNC
     }
+
+    /// Returns whether the symbol has weak binding.
+    fn is_weak(&self) -> bool {
+        (self.st_info() >> 4) == STB_WEAK
+    }
 }
 
 /// ELF relocation.
@@ -532,6 +538,7 @@ impl From<GoblinError> for ElfError {
             GoblinError::Malformed(string) => Self::FailedToParse(format!("malformed: {}", string)),
             GoblinError::BadMagic(magic) => Self::FailedToParse(format!("bad magic: {:#x}", magic)),
             GoblinError::Scroll(error) => Self::FailedToParse(format!("read-write: {}", error)),
+            #[cfg(feature = "std")]
             GoblinError::IO(error) => Self::FailedToParse(format!("io: {}", error)),
             GoblinError::BufferTooShort(n, error) => {
                 Self::FailedToParse(format!("buffer too short {} {}", n, error))
@@ -541,7 +548,7 @@ impl From<GoblinError> for ElfError {
     }
 }
 
-impl From<GoblinError> for EbpfError {
+impl<E: UserDefinedError> From<GoblinError> for EbpfError<E> {
     fn from(error: GoblinError) -> Self {
         ElfError::from(error).into()
     }
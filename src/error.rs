@@ -17,86 +17,254 @@
 //! <https://www.kernel.org/doc/Documentation/networking/filter.txt>, or for a shorter version of
 //! the list of the operation codes: <https://github.com/iovisor/bpf-docs/blob/master/eBPF.md>
 
-use crate::{elf::ElfError, memory_region::AccessType, verifier::VerifierError};
+use crate::{
+    elf::ElfError, interpreter::ResumeState, memory_region::AccessType, verifier::VerifierError,
+};
+use alloc::{boxed::Box, string::String, vec::Vec};
+use core::fmt;
+
+/// The error trait of the standard library
+#[cfg(feature = "std")]
+pub use std::error::Error;
+
+/// Stand-in for `std::error::Error` when building without the `std` feature
+#[cfg(not(feature = "std"))]
+pub trait Error: fmt::Debug + fmt::Display {
+    /// The lower-level source of this error, if any
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        None
+    }
+}
 
 /// User defined errors must implement this trait
-pub trait UserDefinedError: 'static + std::error::Error {}
+///
+/// The JIT writes the fields of an `EbpfError` at fixed offsets,
+/// so the type must not require an alignment of more than 8 bytes.
+pub trait UserDefinedError: 'static + Error {}
 
 /// Error definitions
-#[derive(Debug, thiserror::Error)]
+#[derive(Debug)]
 #[repr(u64)] // discriminant size, used in emit_exception_kind in JIT
-pub enum EbpfError {
+pub enum EbpfError<E: UserDefinedError> {
     /// User defined error
-    #[error("{0}")]
-    UserError(Box<dyn std::error::Error>),
+    UserError(E),
     /// ELF error
-    #[error("ELF error: {0}")]
-    ElfError(#[from] ElfError),
+    ElfError(ElfError),
     /// Syscall was already registered before
-    #[error("syscall #{0} was already registered before")]
     SyscallAlreadyRegistered(usize),
     /// Syscall was not registered before bind
-    #[error("syscall #{0} was not registered before bind")]
-    SyscallNotRegistered(usize),
+    SyscallNotRegistered(String),
     /// Syscall already has a bound context object
-    #[error("syscall #{0} already has a bound context object")]
     SyscallAlreadyBound(usize),
     /// Too many syscalls, increase SyscallRegistry::MAX_SYSCALLS.
-    #[error("too many syscalls")]
     TooManySyscalls,
     /// Exceeded max BPF to BPF call depth
-    #[error("exceeded max BPF to BPF call depth of {1} at instruction #{0}")]
     CallDepthExceeded(usize, usize),
     /// Attempt to exit from root call frame
-    #[error("attempted to exit root call frame")]
     ExitRootCallFrame,
     /// Divide by zero"
-    #[error("divide by zero at instruction {0}")]
     DivideByZero(usize),
     /// Divide overflow
-    #[error("division overflow at instruction {0}")]
     DivideOverflow(usize),
     /// Exceeded max instructions allowed
-    #[error("attempted to execute past the end of the text segment at instruction #{0}")]
     ExecutionOverrun(usize),
     /// Attempt to call to an address outside the text segment
-    #[error(
-        "callx at instruction {0} attempted to call outside of the text segment to addr 0x{1:x}"
-    )]
     CallOutsideTextSegment(usize, u64),
+    /// Attempt to callx an unaligned address or one which is not a function start
+    InvalidCallxTarget(usize, u64),
+    /// Attempt to callx an address which is not the start of a known function
+    CallxUnknownTarget(usize, u64),
     /// Exceeded max instructions allowed
-    #[error("exceeded maximum number of instructions allowed ({1}) at instruction #{0}")]
     ExceededMaxInstructions(usize, u64),
     /// Program has not been JIT-compiled
-    #[error("program has not been JIT-compiled")]
     JitNotCompiled,
+    /// Serialized JIT program is malformed or was compiled from a different executable
+    JitCacheMismatch,
     /// Invalid virtual address
-    #[error("invalid virtual address {0:x?}")]
     InvalidVirtualAddress(u64),
     /// Memory region index or virtual address space is invalid
-    #[error("Invalid memory region at index {0}")]
     InvalidMemoryRegion(usize),
-    /// Access violation (general)
-    #[error("Access violation in {4} section at address {2:#x} of size {3:?} by instruction #{0}")]
-    AccessViolation(usize, AccessType, u64, u64, &'static str),
+    /// Access violation (general), carries the index of the nearest memory region if there is any
+    AccessViolation(usize, AccessType, u64, u64, &'static str, Option<usize>),
     /// Access violation (stack specific)
-    #[error(
-        "Access violation in stack frame {4} at address {2:#x} of size {3:?} by instruction #{0}"
-    )]
     StackAccessViolation(usize, AccessType, u64, u64, i64),
+    /// Stack access below the lower bound of a stack frame
+    StackOverflowDuringMap(usize, AccessType, u64, u64, i64, u64),
+    /// Load or store whose address is not a multiple of its size, see `Config::enforce_aligned_access`
+    UnalignedMemoryAccess(usize, u64, u64),
+    /// Buffer which a syscall translates is larger than `Config::max_syscall_buffer_len`
+    SyscallBufferTooLarge(u64, u64),
+    /// Syscall after the syscalls were sealed, see `Config::enable_syscall_sealing`
+    SyscallsSealed(usize),
     /// Invalid instruction
-    #[error("invalid instruction at {0}")]
     InvalidInstruction(usize),
     /// Unsupported instruction
-    #[error("unsupported instruction at instruction {0}")]
     UnsupportedInstruction(usize),
+    /// The JIT has no backend for the architecture of the host, which is anything but x86_64
+    UnsupportedJitArch(&'static str),
     /// Compilation is too big to fit
-    #[error("Compilation exhausted text segment at instruction {0}")]
     ExhaustedTextSegment(usize),
     /// Libc function call returned an error
-    #[error("Libc calling {0} {1:?} returned error code {2}")]
     LibcInvocationFailed(&'static str, Vec<String>, i32),
     /// Verifier error
-    #[error("Verifier error: {0}")]
-    VerifierError(#[from] VerifierError),
+    VerifierError(VerifierError),
+    /// A snapshot does not match the memory region at the given virtual address
+    SnapshotMismatch(u64),
+    /// The interpreter yielded, the run can be continued with `Interpreter::resume`
+    Yielded(Box<ResumeState>),
+    /// The syscall at the given index differs from the recorded one, see `SyscallReplayer`
+    SyscallReplayMismatch(usize, Option<u32>, Option<u32>),
+    /// The cost model charges an instruction more than the JIT can encode, see `Executable::set_cost_model`
+    InstructionCostTooLarge(usize, u64),
+}
+
+impl<E: UserDefinedError> fmt::Display for EbpfError<E> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::UserError(error) => write!(f, "{}", error),
+            Self::ElfError(error) => write!(f, "ELF error: {}", error),
+            Self::SyscallAlreadyRegistered(hash) => {
+                write!(f, "syscall #{} was already registered before", hash)
+            }
+            Self::SyscallNotRegistered(name) => {
+                write!(f, "syscall {} was not registered before bind", name)
+            }
+            Self::SyscallAlreadyBound(hash) => {
+                write!(f, "syscall #{} already has a bound context object", hash)
+            }
+            Self::TooManySyscalls => write!(f, "too many syscalls"),
+            Self::CallDepthExceeded(pc, depth) => write!(
+                f,
+                "exceeded max BPF to BPF call depth of {} at instruction #{}",
+                depth, pc
+            ),
+            Self::ExitRootCallFrame => write!(f, "attempted to exit root call frame"),
+            Self::DivideByZero(pc) => write!(f, "divide by zero at instruction {}", pc),
+            Self::DivideOverflow(pc) => write!(f, "division overflow at instruction {}", pc),
+            Self::ExecutionOverrun(pc) => write!(
+                f,
+                "attempted to execute past the end of the text segment at instruction #{}",
+                pc
+            ),
+            Self::CallOutsideTextSegment(pc, target) => write!(
+                f,
+                "callx at instruction {} attempted to call outside of the text segment to addr 0x{:x}",
+                pc, target
+            ),
+            Self::InvalidCallxTarget(pc, target) => write!(
+                f,
+                "callx at instruction {} attempted to call an invalid target at addr 0x{:x}",
+                pc, target
+            ),
+            Self::CallxUnknownTarget(pc, target) => write!(
+                f,
+                "callx at instruction {} attempted to call addr 0x{:x} which is not the start of a known function",
+                pc, target
+            ),
+            Self::ExceededMaxInstructions(pc, limit) => write!(
+                f,
+                "exceeded maximum number of instructions allowed ({}) at instruction #{}",
+                limit, pc
+            ),
+            Self::JitNotCompiled => write!(f, "program has not been JIT-compiled"),
+            Self::JitCacheMismatch => {
+                write!(f, "serialized JIT program does not match the executable")
+            }
+            Self::InvalidVirtualAddress(vm_addr) => {
+                write!(f, "invalid virtual address {:x?}", vm_addr)
+            }
+            Self::InvalidMemoryRegion(index) => {
+                write!(f, "Invalid memory region at index {}", index)
+            }
+            Self::AccessViolation(pc, _access_type, vm_addr, len, section_name, _region) => write!(
+                f,
+                "Access violation in {} section at address {:#x} of size {:?} by instruction #{}",
+                section_name, vm_addr, len, pc
+            ),
+            Self::StackAccessViolation(pc, _access_type, vm_addr, len, frame) => write!(
+                f,
+                "Access violation in stack frame {} at address {:#x} of size {:?} by instruction #{}",
+                frame, vm_addr, len, pc
+            ),
+            Self::StackOverflowDuringMap(pc, _access_type, vm_addr, len, frame, below) => write!(
+                f,
+                "Stack overflow in stack frame {} at address {:#x} of size {:?}, {} bytes below the frame by instruction #{}",
+                frame, vm_addr, len, below, pc
+            ),
+            Self::UnalignedMemoryAccess(pc, vm_addr, len) => write!(
+                f,
+                "Unaligned memory access at address {:#x} of size {:?} by instruction #{}",
+                vm_addr, len, pc
+            ),
+            Self::SyscallBufferTooLarge(vm_addr, len) => write!(
+                f,
+                "syscall buffer at address {:#x} of {} bytes exceeds the limit",
+                vm_addr, len
+            ),
+            Self::SyscallsSealed(pc) => write!(
+                f,
+                "syscall at instruction {} after the syscalls were sealed",
+                pc
+            ),
+            Self::InvalidInstruction(pc) => write!(f, "invalid instruction at {}", pc),
+            Self::UnsupportedInstruction(pc) => {
+                write!(f, "unsupported instruction at instruction {}", pc)
+            }
+            Self::UnsupportedJitArch(arch) => {
+                write!(f, "the JIT does not support the {} architecture", arch)
+            }
+            Self::ExhaustedTextSegment(pc) => {
+                write!(f, "Compilation exhausted text segment at instruction {}", pc)
+            }
+            Self::LibcInvocationFailed(function, arguments, code) => write!(
+                f,
+                "Libc calling {} {:?} returned error code {}",
+                function, arguments, code
+            ),
+            Self::VerifierError(error) => write!(f, "Verifier error: {}", error),
+            Self::SnapshotMismatch(vm_addr) => write!(
+                f,
+                "snapshot does not match the memory region at {:#x}",
+                vm_addr
+            ),
+            Self::Yielded(state) => write!(
+                f,
+                "yielded before instruction #{}",
+                state.pc + crate::ebpf::ELF_INSN_DUMP_OFFSET
+            ),
+            Self::SyscallReplayMismatch(index, recorded, executed) => write!(
+                f,
+                "syscall #{} does not match the replay, recorded {:?} but executed {:?}",
+                index, recorded, executed
+            ),
+            Self::InstructionCostTooLarge(pc, cost) => write!(
+                f,
+                "cost {} of instruction #{} is too large for the instruction meter",
+                cost, pc
+            ),
+        }
+    }
+}
+
+impl<E: UserDefinedError> Error for EbpfError<E> {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            Self::UserError(error) => Some(error),
+            Self::ElfError(error) => Some(error),
+            Self::VerifierError(error) => Some(error),
+            _ => None,
+        }
+    }
+}
+
+impl<E: UserDefinedError> From<ElfError> for EbpfError<E> {
+    fn from(error: ElfError) -> Self {
+        Self::ElfError(error)
+    }
+}
+
+impl<E: UserDefinedError> From<VerifierError> for EbpfError<E> {
+    fn from(error: VerifierError) -> Self {
+        Self::VerifierError(error)
+    }
 }
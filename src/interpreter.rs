@@ -13,30 +13,77 @@
 //! Interpreter for eBPF programs.
 
 use crate::{
+    call_frames::{CallFrames, CallFramesSnapshot},
     ebpf,
     ebpf::STACK_PTR_REG,
     error::EbpfError,
     memory_region::AccessType,
     verifier::Verifier,
-    vm::{ContextObject, EbpfVm, ProgramResult},
+    vm::{ContextObject, DivByZeroBehavior, EbpfVm, ProgramResult},
 };
+use alloc::{boxed::Box, vec, vec::Vec};
+use core::sync::atomic::{AtomicBool, Ordering};
+
+/// Applies `Config::div_by_zero_behavior` to a division by zero or a signed division overflow
+macro_rules! div_by_zero {
+    ($self:ident, $config:ident, $dst:ident, $error:expr, $all_ones:expr) => {
+        $self.reg[$dst] = match $config.div_by_zero_behavior {
+            DivByZeroBehavior::Trap => return Err($error),
+            DivByZeroBehavior::ReturnZero => 0,
+            DivByZeroBehavior::ReturnAllOnes => $all_ones,
+        }
+    };
+}
+
+/// Arguments of a syscall and the result replacing it, see `Interpreter::enter_syscall()`
+pub(crate) type SyscallEntry<E> = ([u64; 5], Option<ProgramResult<E>>);
+
+/// Loads which fail to translate read from here, see `Config::oob_read_behavior`
+pub(crate) static OOB_READ_ZEROES: u64 = 0;
 
 /// Translates a vm_addr into a host_addr and sets the pc in the error if one occurs
 #[cfg_attr(feature = "debugger", macro_export)]
 macro_rules! translate_memory_access {
-    ($self:ident, $vm_addr:ident, $access_type:expr, $pc:ident, $T:ty) => {
-        match $self
+    ($self:ident, $vm_addr:ident, $access_type:expr, $pc:ident, $T:ty) => {{
+        if $self
             .vm
-            .memory_mapping
-            .map($access_type, $vm_addr, std::mem::size_of::<$T>() as u64)
+            .verified_executable
+            .get_executable()
+            .get_config()
+            .enforce_aligned_access
+            && $vm_addr % core::mem::size_of::<$T>() as u64 != 0
         {
+            return Err(EbpfError::UnalignedMemoryAccess(
+                $pc + ebpf::ELF_INSN_DUMP_OFFSET,
+                $vm_addr,
+                core::mem::size_of::<$T>() as u64,
+            ));
+        }
+        match $self.vm.memory_mapping.map::<C::UserError>(
+            $access_type,
+            $vm_addr,
+            core::mem::size_of::<$T>() as u64,
+        ) {
             ProgramResult::Ok(host_addr) => host_addr as *mut $T,
+            ProgramResult::Err(_)
+                if $access_type == AccessType::Load
+                    && $self
+                        .vm
+                        .verified_executable
+                        .get_executable()
+                        .get_config()
+                        .oob_read_behavior
+                        == $crate::vm::OobReadBehavior::ReturnZero =>
+            {
+                &$crate::interpreter::OOB_READ_ZEROES as *const u64 as *mut $T
+            }
             ProgramResult::Err(EbpfError::AccessViolation(
                 _pc,
                 access_type,
                 vm_addr,
                 len,
                 regions,
+                nearest_region,
             )) => {
                 return Err(EbpfError::AccessViolation(
                     $pc + ebpf::ELF_INSN_DUMP_OFFSET,
@@ -44,6 +91,7 @@ macro_rules! translate_memory_access {
                     vm_addr,
                     len,
                     regions,
+                    nearest_region,
                 ));
             }
             ProgramResult::Err(EbpfError::StackAccessViolation(
@@ -61,8 +109,55 @@ macro_rules! translate_memory_access {
                     stack_frame,
                 ));
             }
+            ProgramResult::Err(EbpfError::StackOverflowDuringMap(
+                _pc,
+                access_type,
+                vm_addr,
+                len,
+                stack_frame,
+                underflow,
+            )) => {
+                return Err(EbpfError::StackOverflowDuringMap(
+                    $pc + ebpf::ELF_INSN_DUMP_OFFSET,
+                    access_type,
+                    vm_addr,
+                    len,
+                    stack_frame,
+                    underflow,
+                ));
+            }
             _ => unreachable!(),
         }
+    }};
+}
+
+/// Performs the read-modify-write of an atomic instruction, see `ebpf::ATOMIC_*`
+macro_rules! atomic_memory_access {
+    ($self:ident, $insn:ident, $pc:ident, $T:ty) => {
+        let vm_addr = ($self.reg[$insn.dst as usize] as i64).wrapping_add($insn.off as i64) as u64;
+        let host_ptr = translate_memory_access!($self, vm_addr, AccessType::Store, $pc, $T);
+        let old_value = unsafe { *host_ptr };
+        let operand = $self.reg[$insn.src as usize] as $T;
+        let new_value = match $insn.imm {
+            ebpf::ATOMIC_ADD | ebpf::ATOMIC_FETCH_ADD => old_value.wrapping_add(operand),
+            ebpf::ATOMIC_OR | ebpf::ATOMIC_FETCH_OR => old_value | operand,
+            ebpf::ATOMIC_AND | ebpf::ATOMIC_FETCH_AND => old_value & operand,
+            ebpf::ATOMIC_XOR | ebpf::ATOMIC_FETCH_XOR => old_value ^ operand,
+            ebpf::ATOMIC_XCHG => operand,
+            ebpf::ATOMIC_CMPXCHG if old_value == $self.reg[0] as $T => operand,
+            ebpf::ATOMIC_CMPXCHG => old_value,
+            _ => {
+                return Err(EbpfError::InvalidInstruction(
+                    $pc + ebpf::ELF_INSN_DUMP_OFFSET,
+                ));
+            }
+        };
+        unsafe { *host_ptr = new_value };
+        if $insn.imm == ebpf::ATOMIC_CMPXCHG {
+            $self.reg[0] = old_value as u64;
+        } else if $insn.imm & ebpf::BPF_FETCH != 0 {
+            $self.reg[$insn.src as usize] = old_value as u64;
+        }
     };
 }
 
@@ -75,6 +170,156 @@ pub enum DebugState {
     Continue,
 }
 
+/// Pc and register file after an executed instruction
+#[cfg(feature = "instruction-trace")]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct TraceEntry {
+    /// Instruction index of the executed instruction
+    pub pc: usize,
+    /// General purpose registers after the instruction was executed
+    pub reg: [u64; 11],
+}
+
+/// Records every instruction executed by an [`Interpreter::with_trace`]
+#[cfg(feature = "instruction-trace")]
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct TraceCollector {
+    entries: Vec<TraceEntry>,
+}
+
+#[cfg(feature = "instruction-trace")]
+impl TraceCollector {
+    /// Creates a trace collector with space for `capacity` entries preallocated
+    pub fn with_capacity(capacity: usize) -> Self {
+        Self {
+            entries: Vec::with_capacity(capacity),
+        }
+    }
+
+    /// Returns the recorded entries in execution order
+    pub fn get_entries(&self) -> &[TraceEntry] {
+        &self.entries
+    }
+
+    /// Consumes the collector and returns the recorded entries
+    pub fn into_entries(self) -> Vec<TraceEntry> {
+        self.entries
+    }
+
+    /// Discards the recorded entries but keeps the allocation
+    pub fn clear(&mut self) {
+        self.entries.clear();
+    }
+}
+
+/// First position at which two traces differ, see [`diff_traces`]
+#[cfg(feature = "instruction-trace")]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct TraceDivergence {
+    /// Index into both traces
+    pub index: usize,
+    /// Entry of the first trace, `None` if it ended before
+    pub left: Option<TraceEntry>,
+    /// Entry of the second trace, `None` if it ended before
+    pub right: Option<TraceEntry>,
+}
+
+/// Compares two traces and reports the first divergent instruction
+///
+/// Returns `None` if both traces are identical.
+#[cfg(feature = "instruction-trace")]
+pub fn diff_traces(left: &[TraceEntry], right: &[TraceEntry]) -> Option<TraceDivergence> {
+    let index = left
+        .iter()
+        .zip(right.iter())
+        .position(|(left, right)| left != right)
+        .unwrap_or_else(|| left.len().min(right.len()));
+    if index == left.len() && index == right.len() {
+        return None;
+    }
+    Some(TraceDivergence {
+        index,
+        left: left.get(index).copied(),
+        right: right.get(index).copied(),
+    })
+}
+
+/// Number of executed instructions per class, see [`Interpreter::class_counts`]
+#[cfg(feature = "instruction-class-counters")]
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct ClassCounts {
+    /// Arithmetic and logic instructions (BPF_ALU, BPF_ALU64 and LD_DW_IMM)
+    pub alu: u64,
+    /// Loads and stores (BPF_LDX, BPF_ST and BPF_STX)
+    pub memory: u64,
+    /// Conditional and unconditional jumps (BPF_JMP and BPF_JMP32 except for calls and exit)
+    pub jump: u64,
+    /// Calls of functions and syscalls and exits (CALL_IMM, CALL_REG and EXIT)
+    pub call: u64,
+}
+
+#[cfg(feature = "instruction-class-counters")]
+impl ClassCounts {
+    #[inline]
+    fn increment(&mut self, opc: u8) {
+        let count = match opc {
+            ebpf::CALL_IMM | ebpf::CALL_REG | ebpf::EXIT => &mut self.call,
+            _ => match opc & ebpf::BPF_CLS_MASK {
+                ebpf::BPF_LD | ebpf::BPF_ALU | ebpf::BPF_ALU64 => &mut self.alu,
+                ebpf::BPF_LDX | ebpf::BPF_ST | ebpf::BPF_STX => &mut self.memory,
+                _ => &mut self.jump,
+            },
+        };
+        *count = count.saturating_add(1);
+    }
+}
+
+/// Outcome of a single interpreter step
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum InterpreterState {
+    /// The instruction was executed and the program continues in the same frame
+    Continued,
+    /// A BPF to BPF call pushed a new frame
+    Called,
+    /// A BPF to BPF call returned and its frame was popped
+    Returned,
+    /// The program exited from the root frame with the given return value
+    Exited(u64),
+}
+
+/// Registers of a run which yielded, see [`Interpreter::set_yield_signal`]
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ResumeState {
+    /// General purpose registers
+    pub reg: [u64; 11],
+    /// Instruction which is executed next
+    pub pc: usize,
+    /// Instructions executed since the instruction meter was last updated
+    pub due_insn_count: u64,
+}
+
+/// Layout of a memory region at the time a [`VmSnapshot`] was taken
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+struct RegionLayout {
+    host_addr: u64,
+    vm_addr: u64,
+    len: u64,
+    is_writable: bool,
+}
+
+/// Saved state of an interpreter, see [`Interpreter::snapshot`]
+///
+/// Contains the registers, the pc, the call frames and the contents of all writable
+/// memory regions. Readonly regions are only referenced, not copied.
+#[derive(Clone, Debug)]
+pub struct VmSnapshot {
+    reg: [u64; 11],
+    pc: usize,
+    call_frames: CallFramesSnapshot,
+    regions: Vec<RegionLayout>,
+    writable_memory: Vec<u8>,
+}
+
 /// State of an interpreter
 pub struct Interpreter<'a, 'b, V: Verifier, C: ContextObject> {
     pub(crate) vm: &'a mut EbpfVm<'b, V, C>,
@@ -82,8 +327,10 @@ pub struct Interpreter<'a, 'b, V: Verifier, C: ContextObject> {
     pub(crate) program_vm_addr: u64,
 
     pub(crate) initial_insn_count: u64,
+    insn_meter_offset: u64,
     remaining_insn_count: u64,
     pub(crate) due_insn_count: u64,
+    has_internal_calls: bool,
 
     /// General purpose self.registers
     pub reg: [u64; 11],
@@ -94,11 +341,18 @@ pub struct Interpreter<'a, 'b, V: Verifier, C: ContextObject> {
     pub(crate) debug_state: DebugState,
     #[cfg(feature = "debugger")]
     pub(crate) breakpoints: Vec<u64>,
+    #[cfg(feature = "instruction-trace")]
+    trace_collector: Option<&'a mut TraceCollector>,
+    yield_signal: Option<(&'a AtomicBool, u64)>,
+    yield_countdown: u64,
+    coverage: Option<Vec<u64>>,
+    #[cfg(feature = "instruction-class-counters")]
+    class_counts: ClassCounts,
 }
 
 impl<'a, 'b, V: Verifier, C: ContextObject> Interpreter<'a, 'b, V, C> {
     /// Creates a new interpreter state
-    pub fn new(vm: &'a mut EbpfVm<'b, V, C>) -> Result<Self, EbpfError> {
+    pub fn new(vm: &'a mut EbpfVm<'b, V, C>) -> Result<Self, EbpfError<C::UserError>> {
         let executable = vm.verified_executable.get_executable();
         let (program_vm_addr, program) = executable.get_text_bytes();
         let initial_insn_count = if executable.get_config().enable_instruction_meter {
@@ -106,6 +360,9 @@ impl<'a, 'b, V: Verifier, C: ContextObject> Interpreter<'a, 'b, V, C> {
         } else {
             0
         };
+        // The part of the budget which is cut off by Config::max_instructions
+        let insn_meter_offset =
+            initial_insn_count.saturating_sub(executable.get_config().max_instructions);
         // R1 points to beginning of input memory, R10 to the stack of the first frame
         let reg: [u64; 11] = [
             0,
@@ -126,18 +383,42 @@ impl<'a, 'b, V: Verifier, C: ContextObject> Interpreter<'a, 'b, V, C> {
             program,
             program_vm_addr,
             initial_insn_count,
-            remaining_insn_count: initial_insn_count,
+            insn_meter_offset,
+            remaining_insn_count: initial_insn_count - insn_meter_offset,
             due_insn_count: 0,
+            has_internal_calls: executable.has_internal_calls(),
             reg,
             pc,
             #[cfg(feature = "debugger")]
             debug_state: DebugState::Continue,
             #[cfg(feature = "debugger")]
             breakpoints: Vec::new(),
+            #[cfg(feature = "instruction-trace")]
+            trace_collector: None,
+            yield_signal: None,
+            yield_countdown: 0,
+            coverage: None,
+            #[cfg(feature = "instruction-class-counters")]
+            class_counts: ClassCounts::default(),
         })
     }
 
-    fn check_pc(&self, current_pc: usize, target_pc: usize) -> Result<usize, EbpfError> {
+    /// Creates a new interpreter state which records every executed instruction
+    #[cfg(feature = "instruction-trace")]
+    pub fn with_trace(
+        vm: &'a mut EbpfVm<'b, V, C>,
+        trace_collector: &'a mut TraceCollector,
+    ) -> Result<Self, EbpfError<C::UserError>> {
+        let mut interpreter = Self::new(vm)?;
+        interpreter.trace_collector = Some(trace_collector);
+        Ok(interpreter)
+    }
+
+    fn check_pc(
+        &self,
+        current_pc: usize,
+        target_pc: usize,
+    ) -> Result<usize, EbpfError<C::UserError>> {
         let offset =
             target_pc
                 .checked_mul(ebpf::INSN_SIZE)
@@ -154,6 +435,220 @@ impl<'a, 'b, V: Verifier, C: ContextObject> Interpreter<'a, 'b, V, C> {
         Ok(target_pc)
     }
 
+    /// Pushes the frame of a BPF to BPF call to `target_pc`, which callx does not know yet
+    fn push_frame(&mut self, target_pc: Option<usize>) -> Result<(), EbpfError<C::UserError>> {
+        let executable = self.vm.verified_executable.get_executable();
+        let saved_reg =
+            &self.reg[ebpf::FIRST_SCRATCH_REG..ebpf::FIRST_SCRATCH_REG + ebpf::SCRATCH_REGS];
+        self.reg[ebpf::FRAME_PTR_REG] = if executable.uses_stack_frame_sizes() {
+            self.vm
+                .stack
+                .push_sized(saved_reg, self.pc, executable.call_frame_size(target_pc))?
+        } else {
+            self.vm.stack.push(saved_reg, self.pc)?
+        };
+        self.vm.remap_resized_stack()
+    }
+
+    /// Returns the general purpose registers
+    pub fn get_registers(&self) -> &[u64; 11] {
+        &self.reg
+    }
+
+    /// Returns the program counter / instruction pointer
+    pub fn get_pc(&self) -> usize {
+        self.pc
+    }
+
+    /// Returns the call frames of the VM
+    pub fn get_call_frames(&self) -> &CallFrames<'b> {
+        &self.vm.stack
+    }
+
+    /// Most call frames which were active at once, including the root frame
+    ///
+    /// Comparable to `Config::max_call_depth`. Kept across runs of the VM until
+    /// `reset_stack_high_water_marks()` is called.
+    pub fn max_stack_depth(&self) -> usize {
+        self.vm.stack.get_max_frame_index() + 1
+    }
+
+    /// Most bytes of the stack region which were in use at once, see `CallFrames::get_stack_bytes()`
+    ///
+    /// Only tracked if `Config::enable_stack_usage_tracking` is set, zero otherwise.
+    pub fn max_stack_bytes(&self) -> usize {
+        self.vm.stack.get_max_stack_bytes()
+    }
+
+    /// Restarts `max_stack_depth()` and `max_stack_bytes()` from the current call frame
+    pub fn reset_stack_high_water_marks(&mut self) {
+        self.vm.stack.reset_high_water_marks();
+    }
+
+    /// Captures the registers, the pc, the call frames and all writable memory
+    ///
+    /// Readonly regions are not copied, [`Interpreter::restore`] requires them to be unchanged.
+    pub fn snapshot(&self) -> VmSnapshot {
+        let mut regions = Vec::new();
+        let mut writable_memory = Vec::new();
+        for region in self.vm.memory_mapping.get_regions() {
+            // The stack is part of the call frames snapshot
+            if region.len == 0 || region.vm_addr == ebpf::MM_STACK_START {
+                continue;
+            }
+            if region.is_writable {
+                // Safety: the memory mapping guarantees that writable regions point to live host memory
+                writable_memory.extend_from_slice(unsafe {
+                    core::slice::from_raw_parts(region.host_addr as *const u8, region.len as usize)
+                });
+            }
+            regions.push(RegionLayout {
+                host_addr: region.host_addr,
+                vm_addr: region.vm_addr,
+                len: region.len,
+                is_writable: region.is_writable,
+            });
+        }
+        VmSnapshot {
+            reg: self.reg,
+            pc: self.pc,
+            call_frames: self.vm.stack.snapshot(),
+            regions,
+            writable_memory,
+        }
+    }
+
+    /// Rewinds the interpreter to a snapshot taken by [`Interpreter::snapshot`]
+    ///
+    /// Fails without modifying any state if the memory regions were added, removed or moved
+    /// since the snapshot was taken. The instruction meter is not rewound.
+    pub fn restore(&mut self, snapshot: &VmSnapshot) -> Result<(), EbpfError<C::UserError>> {
+        let mut regions = self
+            .vm
+            .memory_mapping
+            .get_regions()
+            .iter()
+            .filter(|region| region.len != 0 && region.vm_addr != ebpf::MM_STACK_START);
+        for layout in snapshot.regions.iter() {
+            match regions.next() {
+                Some(region)
+                    if region.host_addr == layout.host_addr
+                        && region.vm_addr == layout.vm_addr
+                        && region.len == layout.len
+                        && region.is_writable == layout.is_writable => {}
+                Some(region) => return Err(EbpfError::SnapshotMismatch(region.vm_addr)),
+                None => return Err(EbpfError::SnapshotMismatch(layout.vm_addr)),
+            }
+        }
+        if let Some(region) = regions.next() {
+            return Err(EbpfError::SnapshotMismatch(region.vm_addr));
+        }
+        self.vm.stack.restore(&snapshot.call_frames)?;
+        self.vm.remap_resized_stack()?;
+        let mut writable_memory = snapshot.writable_memory.as_slice();
+        for layout in snapshot.regions.iter().filter(|layout| layout.is_writable) {
+            let (data, rest) = writable_memory.split_at(layout.len as usize);
+            // Safety: the region was verified to be mapped at the same host address as before
+            unsafe {
+                core::slice::from_raw_parts_mut(layout.host_addr as *mut u8, data.len())
+                    .copy_from_slice(data);
+            }
+            writable_memory = rest;
+        }
+        self.reg = snapshot.reg;
+        self.pc = snapshot.pc;
+        Ok(())
+    }
+
+    /// Runs the program until it exits or fails
+    ///
+    /// Shares the dispatch logic of [`Interpreter::step`].
+    /// Fails with `EbpfError::Yielded` if a yield signal is installed and raised, see [`Interpreter::set_yield_signal`].
+    pub fn run(&mut self) -> Result<u64, EbpfError<C::UserError>> {
+        if self.has_internal_calls {
+            self.run_loop::<true>()
+        } else {
+            self.run_loop::<false>()
+        }
+    }
+
+    fn run_loop<const INTERNAL_CALLS: bool>(&mut self) -> Result<u64, EbpfError<C::UserError>> {
+        loop {
+            if let Some((should_yield, check_interval)) = self.yield_signal {
+                if self.yield_countdown == 0 {
+                    self.yield_countdown = check_interval;
+                    if should_yield.swap(false, Ordering::Relaxed) {
+                        return Err(EbpfError::Yielded(Box::new(ResumeState {
+                            reg: self.reg,
+                            pc: self.pc,
+                            due_insn_count: self.due_insn_count,
+                        })));
+                    }
+                }
+                self.yield_countdown -= 1;
+            }
+            if let InterpreterState::Exited(value) = self.step_with::<INTERNAL_CALLS>()? {
+                return Ok(value);
+            }
+        }
+    }
+
+    /// Makes [`Interpreter::run`] check `should_yield` every `check_interval` instructions
+    ///
+    /// When it is set the flag is cleared and the run fails with `EbpfError::Yielded`
+    /// before executing the next instruction. Smaller intervals reduce the latency of
+    /// yielding at the cost of more checks.
+    pub fn set_yield_signal(&mut self, should_yield: &'a AtomicBool, check_interval: u64) {
+        self.yield_signal = Some((should_yield, check_interval.max(1)));
+        self.yield_countdown = 0;
+    }
+
+    /// Starts recording which instructions are executed, see [`Interpreter::coverage`]
+    pub fn enable_coverage(&mut self) {
+        if self.coverage.is_none() {
+            self.coverage = Some(vec![0; (self.program.len() / ebpf::INSN_SIZE + 63) / 64]);
+        }
+    }
+
+    /// Bitmap of the executed instructions, bit `pc % 64` of word `pc / 64` is set for each executed `pc`
+    ///
+    /// Only the first slot of a LD_DW_IMM is marked. Empty unless [`Interpreter::enable_coverage`]
+    /// was called. Bitmaps of several runs of the same executable can be combined with bitwise or.
+    pub fn coverage(&self) -> &[u64] {
+        self.coverage.as_deref().unwrap_or(&[])
+    }
+
+    /// Clears the bitmap of the executed instructions
+    pub fn reset_coverage(&mut self) {
+        if let Some(coverage) = self.coverage.as_mut() {
+            coverage.fill(0);
+        }
+    }
+
+    /// Number of instructions executed per class since the interpreter was created
+    /// or `reset_class_counts()` was called
+    #[cfg(feature = "instruction-class-counters")]
+    pub fn class_counts(&self) -> &ClassCounts {
+        &self.class_counts
+    }
+
+    /// Sets all instruction class counters to zero
+    #[cfg(feature = "instruction-class-counters")]
+    pub fn reset_class_counts(&mut self) {
+        self.class_counts = ClassCounts::default();
+    }
+
+    /// Continues a run which failed with `EbpfError::Yielded`
+    ///
+    /// The call frames and the memory are kept in the VM, so the interpreter of the run
+    /// or a new one for the same VM can resume it.
+    pub fn resume(&mut self, state: &ResumeState) -> Result<u64, EbpfError<C::UserError>> {
+        self.reg = state.reg;
+        self.pc = state.pc;
+        self.due_insn_count = state.due_insn_count;
+        self.run()
+    }
+
     /// Translate between the virtual machines' pc value and the pc value used by the debugger
     #[cfg(feature = "debugger")]
     pub fn get_dbg_pc(&self) -> u64 {
@@ -165,15 +660,141 @@ impl<'a, 'b, V: Verifier, C: ContextObject> Interpreter<'a, 'b, V, C> {
                 .get_text_section_offset()
     }
 
+    /// Returns the hash of the syscall which the next instruction invokes
+    pub(crate) fn next_syscall_hash(&self) -> Option<u32> {
+        if (self.pc + 1) * ebpf::INSN_SIZE > self.program.len() {
+            return None;
+        }
+        let insn = ebpf::get_insn_unchecked(self.program, self.pc);
+        let config = self.vm.verified_executable.get_executable().get_config();
+        (insn.opc == ebpf::CALL_IMM && (!config.static_syscalls || insn.src == 0))
+            .then_some(insn.imm as u32)
+    }
+
+    /// Accounts for the syscall instruction at pc and returns its arguments
+    ///
+    /// Must be followed by `leave_syscall()` with the result of the syscall,
+    /// or with `replacement` if the `SyscallObserver` provided one.
+    pub(crate) fn enter_syscall(
+        &mut self,
+    ) -> Result<SyscallEntry<C::UserError>, EbpfError<C::UserError>> {
+        let executable = self.vm.verified_executable.get_executable();
+        let config = executable.get_config();
+        self.due_insn_count += executable.get_instruction_cost(self.pc);
+        if config.enable_instruction_tracing {
+            let mut state = [0u64; 12];
+            state[0..11].copy_from_slice(&self.reg);
+            state[11] = self.pc as u64;
+            self.vm.context_object.trace(state);
+        }
+        if let Some(coverage) = self.coverage.as_mut() {
+            coverage[self.pc / 64] |= 1 << (self.pc % 64);
+        }
+        #[cfg(feature = "instruction-class-counters")]
+        self.class_counts.increment(ebpf::CALL_IMM);
+        if config.enable_syscall_sealing && self.vm.context_object.syscalls_sealed() {
+            return Err(EbpfError::SyscallsSealed(
+                self.pc + ebpf::ELF_INSN_DUMP_OFFSET,
+            ));
+        }
+        self.pc += 1;
+        if config.enable_instruction_meter {
+            self.vm
+                .context_object
+                .consume(self.due_insn_count.min(self.remaining_insn_count));
+        }
+        self.due_insn_count = 0;
+        let mut replacement = None;
+        if let Some(syscall_observer) = self.vm.syscall_observer.as_mut() {
+            let number = ebpf::get_insn_unchecked(self.program, self.pc - 1).imm as u32;
+            syscall_observer.on_syscall_enter(number);
+            replacement = syscall_observer.replace_syscall(number);
+        }
+        Ok((
+            [
+                self.reg[1],
+                self.reg[2],
+                self.reg[3],
+                self.reg[4],
+                self.reg[5],
+            ],
+            replacement,
+        ))
+    }
+
+    /// Resumes after the syscall started by `enter_syscall()`
+    pub(crate) fn leave_syscall(
+        &mut self,
+        result: Result<u64, EbpfError<C::UserError>>,
+    ) -> Result<InterpreterState, EbpfError<C::UserError>> {
+        let pc = self.pc - 1;
+        let result = ProgramResult::from(result);
+        if let Some(syscall_observer) = self.vm.syscall_observer.as_mut() {
+            let number = ebpf::get_insn_unchecked(self.program, pc).imm as u32;
+            syscall_observer.on_syscall_result(number, &result);
+            syscall_observer.on_syscall_exit(number);
+        }
+        self.reg[0] = Result::from(result)?;
+        let config = self.vm.verified_executable.get_executable().get_config();
+        if config.enable_instruction_meter {
+            self.remaining_insn_count = self
+                .vm
+                .context_object
+                .get_remaining()
+                .saturating_sub(self.insn_meter_offset);
+            if self.due_insn_count >= self.remaining_insn_count {
+                return Err(EbpfError::ExceededMaxInstructions(
+                    pc + 1 + ebpf::ELF_INSN_DUMP_OFFSET,
+                    self.initial_insn_count - self.insn_meter_offset,
+                ));
+            }
+        }
+        #[cfg(feature = "instruction-trace")]
+        if let Some(trace_collector) = self.trace_collector.as_mut() {
+            trace_collector
+                .entries
+                .push(TraceEntry { pc, reg: self.reg });
+        }
+        Ok(InterpreterState::Continued)
+    }
+
     /// Advances the interpreter state by one instruction
+    pub fn step(&mut self) -> Result<InterpreterState, EbpfError<C::UserError>> {
+        if self.has_internal_calls {
+            self.step_with::<true>()
+        } else {
+            self.step_with::<false>()
+        }
+    }
+
+    /// Advances by one instruction, `INTERNAL_CALLS` selects whether call frames are tracked
+    ///
+    /// Without internal calls the program never leaves the root frame, so exit always
+    /// terminates it and calls only need to resolve syscalls.
+    fn step_with<const INTERNAL_CALLS: bool>(
+        &mut self,
+    ) -> Result<InterpreterState, EbpfError<C::UserError>> {
+        #[cfg(feature = "instruction-trace")]
+        let pc = self.pc;
+        let result = self.execute_instruction::<INTERNAL_CALLS>();
+        #[cfg(feature = "instruction-trace")]
+        if let (Some(trace_collector), Ok(_)) = (self.trace_collector.as_mut(), &result) {
+            trace_collector
+                .entries
+                .push(TraceEntry { pc, reg: self.reg });
+        }
+        result
+    }
+
     #[rustfmt::skip]
-    pub fn step(&mut self) -> Result<Option<u64>, EbpfError> {
+    fn execute_instruction<const INTERNAL_CALLS: bool>(&mut self) -> Result<InterpreterState, EbpfError<C::UserError>> {
         let executable = self.vm.verified_executable.get_executable();
         let config = &executable.get_config();
 
         let mut instruction_width = 1;
-        self.due_insn_count += 1;
+        let mut state = InterpreterState::Continued;
         let pc = self.pc;
+        self.due_insn_count += executable.get_instruction_cost(pc);
         self.pc += instruction_width;
         if self.pc * ebpf::INSN_SIZE > self.program.len() {
             return Err(EbpfError::ExecutionOverrun(
@@ -183,6 +804,11 @@ impl<'a, 'b, V: Verifier, C: ContextObject> Interpreter<'a, 'b, V, C> {
         let mut insn = ebpf::get_insn_unchecked(self.program, pc);
         let dst = insn.dst as usize;
         let src = insn.src as usize;
+        if let Some(coverage) = self.coverage.as_mut() {
+            coverage[pc / 64] |= 1 << (pc % 64);
+        }
+        #[cfg(feature = "instruction-class-counters")]
+        self.class_counts.increment(insn.opc);
 
         if config.enable_instruction_tracing {
             let mut state = [0u64; 12];
@@ -204,7 +830,9 @@ impl<'a, 'b, V: Verifier, C: ContextObject> Interpreter<'a, 'b, V, C> {
             }
 
             ebpf::LD_DW_IMM  => {
-                ebpf::augment_lddw_unchecked(self.program, &mut insn);
+                if !ebpf::augment_lddw(self.program, &mut insn) {
+                    return Err(EbpfError::InvalidInstruction(pc + ebpf::ELF_INSN_DUMP_OFFSET));
+                }
                 instruction_width = 2;
                 self.pc += 1;
                 self.reg[dst] = insn.imm as u64;
@@ -275,6 +903,8 @@ impl<'a, 'b, V: Verifier, C: ContextObject> Interpreter<'a, 'b, V, C> {
                 let host_ptr = translate_memory_access!(self, vm_addr, AccessType::Store, pc, u64);
                 unsafe { *host_ptr = self.reg[src] };
             },
+            ebpf::ST_W_ATOMIC  => { atomic_memory_access!(self, insn, pc, u32); },
+            ebpf::ST_DW_ATOMIC => { atomic_memory_access!(self, insn, pc, u64); },
 
             // BPF_ALU class
             ebpf::ADD32_IMM  => self.reg[dst] = (self.reg[dst] as i32).wrapping_add(insn.imm as i32)      as u64,
@@ -286,47 +916,50 @@ impl<'a, 'b, V: Verifier, C: ContextObject> Interpreter<'a, 'b, V, C> {
             ebpf::DIV32_IMM  => self.reg[dst] = (self.reg[dst] as u32             / insn.imm as u32)      as u64,
             ebpf::DIV32_REG  => {
                 if self.reg[src] as u32 == 0 {
-                    return Err(EbpfError::DivideByZero(pc + ebpf::ELF_INSN_DUMP_OFFSET));
-                }
+                    div_by_zero!(self, config, dst, EbpfError::DivideByZero(pc + ebpf::ELF_INSN_DUMP_OFFSET), u32::MAX as u64);
+                } else {
                                 self.reg[dst] = (self.reg[dst] as u32             / self.reg[src] as u32) as u64;
+                }
             },
             ebpf::SDIV32_IMM  => {
                 if self.reg[dst] as i32 == i32::MIN && insn.imm == -1 {
-                    return Err(EbpfError::DivideOverflow(pc + ebpf::ELF_INSN_DUMP_OFFSET));
-                }
+                    div_by_zero!(self, config, dst, EbpfError::DivideOverflow(pc + ebpf::ELF_INSN_DUMP_OFFSET), u64::MAX);
+                } else {
                                 self.reg[dst] = (self.reg[dst] as i32             / insn.imm as i32)      as u64;
+                }
             }
             ebpf::SDIV32_REG  => {
                 if self.reg[src] as i32 == 0 {
-                    return Err(EbpfError::DivideByZero(pc + ebpf::ELF_INSN_DUMP_OFFSET));
-                }
-                if self.reg[dst] as i32 == i32::MIN && self.reg[src] as i32 == -1 {
-                    return Err(EbpfError::DivideOverflow(pc + ebpf::ELF_INSN_DUMP_OFFSET));
-                }
+                    div_by_zero!(self, config, dst, EbpfError::DivideByZero(pc + ebpf::ELF_INSN_DUMP_OFFSET), u64::MAX);
+                } else if self.reg[dst] as i32 == i32::MIN && self.reg[src] as i32 == -1 {
+                    div_by_zero!(self, config, dst, EbpfError::DivideOverflow(pc + ebpf::ELF_INSN_DUMP_OFFSET), u64::MAX);
+                } else {
                                 self.reg[dst] = (self.reg[dst] as i32             / self.reg[src] as i32) as u64;
+                }
             },
             ebpf::OR32_IMM   => self.reg[dst] = (self.reg[dst] as u32             | insn.imm as u32)      as u64,
             ebpf::OR32_REG   => self.reg[dst] = (self.reg[dst] as u32             | self.reg[src] as u32) as u64,
             ebpf::AND32_IMM  => self.reg[dst] = (self.reg[dst] as u32             & insn.imm as u32)      as u64,
             ebpf::AND32_REG  => self.reg[dst] = (self.reg[dst] as u32             & self.reg[src] as u32) as u64,
-            ebpf::LSH32_IMM  => self.reg[dst] = (self.reg[dst] as u32).wrapping_shl(insn.imm as u32)      as u64,
-            ebpf::LSH32_REG  => self.reg[dst] = (self.reg[dst] as u32).wrapping_shl(self.reg[src] as u32) as u64,
-            ebpf::RSH32_IMM  => self.reg[dst] = (self.reg[dst] as u32).wrapping_shr(insn.imm as u32)      as u64,
-            ebpf::RSH32_REG  => self.reg[dst] = (self.reg[dst] as u32).wrapping_shr(self.reg[src] as u32) as u64,
+            ebpf::LSH32_IMM  => self.reg[dst] = (self.reg[dst] as u32).wrapping_shl(insn.imm as u32 & 0x1f)      as u64,
+            ebpf::LSH32_REG  => self.reg[dst] = (self.reg[dst] as u32).wrapping_shl(self.reg[src] as u32 & 0x1f) as u64,
+            ebpf::RSH32_IMM  => self.reg[dst] = (self.reg[dst] as u32).wrapping_shr(insn.imm as u32 & 0x1f)      as u64,
+            ebpf::RSH32_REG  => self.reg[dst] = (self.reg[dst] as u32).wrapping_shr(self.reg[src] as u32 & 0x1f) as u64,
             ebpf::NEG32      => self.reg[dst] = (self.reg[dst] as i32).wrapping_neg()                     as u64 & (u32::MAX as u64),
             ebpf::MOD32_IMM  => self.reg[dst] = (self.reg[dst] as u32             % insn.imm as u32)      as u64,
             ebpf::MOD32_REG  => {
                 if self.reg[src] as u32 == 0 {
-                    return Err(EbpfError::DivideByZero(pc + ebpf::ELF_INSN_DUMP_OFFSET));
-                }
+                    div_by_zero!(self, config, dst, EbpfError::DivideByZero(pc + ebpf::ELF_INSN_DUMP_OFFSET), u32::MAX as u64);
+                } else {
                                 self.reg[dst] = (self.reg[dst] as u32             % self.reg[src] as u32) as u64;
+                }
             },
             ebpf::XOR32_IMM  => self.reg[dst] = (self.reg[dst] as u32             ^ insn.imm as u32)      as u64,
             ebpf::XOR32_REG  => self.reg[dst] = (self.reg[dst] as u32             ^ self.reg[src] as u32) as u64,
             ebpf::MOV32_IMM  => self.reg[dst] = insn.imm as u32 as u64,
             ebpf::MOV32_REG  => self.reg[dst] = (self.reg[src] as u32) as u64,
-            ebpf::ARSH32_IMM => self.reg[dst] = (self.reg[dst] as i32).wrapping_shr(insn.imm as u32)      as u64 & (u32::MAX as u64),
-            ebpf::ARSH32_REG => self.reg[dst] = (self.reg[dst] as i32).wrapping_shr(self.reg[src] as u32) as u64 & (u32::MAX as u64),
+            ebpf::ARSH32_IMM => self.reg[dst] = (self.reg[dst] as i32).wrapping_shr(insn.imm as u32 & 0x1f)      as u32 as u64,
+            ebpf::ARSH32_REG => self.reg[dst] = (self.reg[dst] as i32).wrapping_shr(self.reg[src] as u32 & 0x1f) as u32 as u64,
             ebpf::LE         => {
                 self.reg[dst] = match insn.imm {
                     16 => (self.reg[dst] as u16).to_le() as u64,
@@ -358,47 +991,60 @@ impl<'a, 'b, V: Verifier, C: ContextObject> Interpreter<'a, 'b, V, C> {
             ebpf::DIV64_IMM  => self.reg[dst] /= insn.imm as u64,
             ebpf::DIV64_REG  => {
                 if self.reg[src] == 0 {
-                    return Err(EbpfError::DivideByZero(pc + ebpf::ELF_INSN_DUMP_OFFSET));
-                }
+                    div_by_zero!(self, config, dst, EbpfError::DivideByZero(pc + ebpf::ELF_INSN_DUMP_OFFSET), u64::MAX);
+                } else {
                                 self.reg[dst] /= self.reg[src];
+                }
             },
             ebpf::SDIV64_IMM => {
                 if self.reg[dst] as i64 == i64::MIN && insn.imm == -1 {
-                    return Err(EbpfError::DivideOverflow(pc + ebpf::ELF_INSN_DUMP_OFFSET));
-                }
+                    div_by_zero!(self, config, dst, EbpfError::DivideOverflow(pc + ebpf::ELF_INSN_DUMP_OFFSET), u64::MAX);
+                } else {
                                 self.reg[dst] = (self.reg[dst] as i64 / insn.imm)                          as u64
+                }
             }
             ebpf::SDIV64_REG => {
                 if self.reg[src] == 0 {
-                    return Err(EbpfError::DivideByZero(pc + ebpf::ELF_INSN_DUMP_OFFSET));
-                }
-                if self.reg[dst] as i64 == i64::MIN && self.reg[src] as i64 == -1 {
-                    return Err(EbpfError::DivideOverflow(pc + ebpf::ELF_INSN_DUMP_OFFSET));
-                }
+                    div_by_zero!(self, config, dst, EbpfError::DivideByZero(pc + ebpf::ELF_INSN_DUMP_OFFSET), u64::MAX);
+                } else if self.reg[dst] as i64 == i64::MIN && self.reg[src] as i64 == -1 {
+                    div_by_zero!(self, config, dst, EbpfError::DivideOverflow(pc + ebpf::ELF_INSN_DUMP_OFFSET), u64::MAX);
+                } else {
                                 self.reg[dst] = (self.reg[dst] as i64 / self.reg[src] as i64)             as u64;
+                }
+            },
+            ebpf::UHMUL64_IMM if config.enable_uhmul => {
+                let product = self.reg[dst] as u128 * insn.imm as u64 as u128;
+                self.reg[dst] = product as u64;
+                self.reg[dst + 1] = (product >> 64) as u64;
+            },
+            ebpf::UHMUL64_REG if config.enable_uhmul => {
+                let product = self.reg[dst] as u128 * self.reg[src] as u128;
+                self.reg[dst] = product as u64;
+                self.reg[dst + 1] = (product >> 64) as u64;
             },
             ebpf::OR64_IMM   => self.reg[dst] |= insn.imm as u64,
             ebpf::OR64_REG   => self.reg[dst] |= self.reg[src],
             ebpf::AND64_IMM  => self.reg[dst] &= insn.imm as u64,
             ebpf::AND64_REG  => self.reg[dst] &= self.reg[src],
-            ebpf::LSH64_IMM  => self.reg[dst] =  self.reg[dst].wrapping_shl(insn.imm as u32),
-            ebpf::LSH64_REG  => self.reg[dst] =  self.reg[dst].wrapping_shl(self.reg[src] as u32),
-            ebpf::RSH64_IMM  => self.reg[dst] =  self.reg[dst].wrapping_shr(insn.imm as u32),
-            ebpf::RSH64_REG  => self.reg[dst] =  self.reg[dst].wrapping_shr(self.reg[src] as u32),
+            ebpf::LSH64_IMM  => self.reg[dst] =  self.reg[dst].wrapping_shl(insn.imm as u32 & 0x3f),
+            ebpf::LSH64_REG  => self.reg[dst] =  self.reg[dst].wrapping_shl(self.reg[src] as u32 & 0x3f),
+            ebpf::RSH64_IMM  => self.reg[dst] =  self.reg[dst].wrapping_shr(insn.imm as u32 & 0x3f),
+            ebpf::RSH64_REG  => self.reg[dst] =  self.reg[dst].wrapping_shr(self.reg[src] as u32 & 0x3f),
             ebpf::NEG64      => self.reg[dst] = (self.reg[dst] as i64).wrapping_neg() as u64,
             ebpf::MOD64_IMM  => self.reg[dst] %= insn.imm as u64,
             ebpf::MOD64_REG  => {
                 if self.reg[src] == 0 {
-                    return Err(EbpfError::DivideByZero(pc + ebpf::ELF_INSN_DUMP_OFFSET));
-                }
+                    div_by_zero!(self, config, dst, EbpfError::DivideByZero(pc + ebpf::ELF_INSN_DUMP_OFFSET), u64::MAX);
+                } else {
                                 self.reg[dst] %= self.reg[src];
+                }
             },
             ebpf::XOR64_IMM  => self.reg[dst] ^= insn.imm as u64,
             ebpf::XOR64_REG  => self.reg[dst] ^= self.reg[src],
             ebpf::MOV64_IMM  => self.reg[dst] =  insn.imm as u64,
             ebpf::MOV64_REG  => self.reg[dst] =  self.reg[src],
-            ebpf::ARSH64_IMM => self.reg[dst] = (self.reg[dst] as i64).wrapping_shr(insn.imm as u32)      as u64,
-            ebpf::ARSH64_REG => self.reg[dst] = (self.reg[dst] as i64).wrapping_shr(self.reg[src] as u32) as u64,
+            ebpf::ARSH64_IMM => self.reg[dst] = (self.reg[dst] as i64).wrapping_shr(insn.imm as u32 & 0x3f)      as u64,
+            ebpf::ARSH64_REG => self.reg[dst] = (self.reg[dst] as i64).wrapping_shr(self.reg[src] as u32 & 0x3f) as u64,
 
             // BPF_JMP class
             ebpf::JA         =>                                                   { self.pc = (self.pc as isize + insn.off as isize) as usize; },
@@ -427,17 +1073,32 @@ impl<'a, 'b, V: Verifier, C: ContextObject> Interpreter<'a, 'b, V, C> {
 
             ebpf::CALL_REG   => {
                 let target_address = self.reg[insn.imm as usize];
-                self.reg[ebpf::FRAME_PTR_REG] =
-                    self.vm.stack.push(&self.reg[ebpf::FIRST_SCRATCH_REG..ebpf::FIRST_SCRATCH_REG + ebpf::SCRATCH_REGS], self.pc)?;
+                self.push_frame(None)?;
+                if config.reject_callx_to_unaligned && target_address % ebpf::INSN_SIZE as u64 != 0 {
+                    return Err(EbpfError::InvalidCallxTarget(pc + ebpf::ELF_INSN_DUMP_OFFSET, target_address));
+                }
+                if config.reject_callx_to_unknown_target && target_address % ebpf::INSN_SIZE as u64 != 0 {
+                    return Err(EbpfError::CallxUnknownTarget(pc + ebpf::ELF_INSN_DUMP_OFFSET, target_address));
+                }
                 if target_address < self.program_vm_addr {
                     return Err(EbpfError::CallOutsideTextSegment(pc + ebpf::ELF_INSN_DUMP_OFFSET, target_address / ebpf::INSN_SIZE as u64 * ebpf::INSN_SIZE as u64));
                 }
                 let target_pc = (target_address - self.program_vm_addr) as usize / ebpf::INSN_SIZE;
                 self.pc = self.check_pc(pc, target_pc)?;
+                if (config.reject_callx_to_unaligned || config.reject_callx_to_unknown_target) && !executable.is_function_start(target_pc) {
+                    if config.reject_callx_to_unaligned {
+                        return Err(EbpfError::InvalidCallxTarget(pc + ebpf::ELF_INSN_DUMP_OFFSET, target_address));
+                    }
+                    return Err(EbpfError::CallxUnknownTarget(pc + ebpf::ELF_INSN_DUMP_OFFSET, target_address));
+                }
                 if config.static_syscalls && executable.lookup_bpf_function(target_pc as u32).is_none() {
                     self.due_insn_count += 1;
                     return Err(EbpfError::UnsupportedInstruction(target_pc + ebpf::ELF_INSN_DUMP_OFFSET));
                 }
+                state = InterpreterState::Called;
+                if let Some(call_observer) = self.vm.call_observer.as_mut() {
+                    call_observer.on_call_enter(target_pc);
+                }
             },
 
             // Do not delegate the check to the verifier, since self.registered functions can be
@@ -454,39 +1115,57 @@ impl<'a, 'b, V: Verifier, C: ContextObject> Interpreter<'a, 'b, V, C> {
                     if let Some(syscall) = executable.get_syscall_registry().lookup_syscall(insn.imm as u32) {
                         resolved = true;
 
+                        if config.enable_syscall_sealing && self.vm.context_object.syscalls_sealed() {
+                            return Err(EbpfError::SyscallsSealed(pc + ebpf::ELF_INSN_DUMP_OFFSET));
+                        }
                         if config.enable_instruction_meter {
-                            self.vm.context_object.consume(self.due_insn_count);
+                            self.vm.context_object.consume(self.due_insn_count.min(self.remaining_insn_count));
                         }
                         self.due_insn_count = 0;
-                        let mut result = ProgramResult::Ok(0);
-                        syscall(
-                            self.vm.context_object,
-                            self.reg[1],
-                            self.reg[2],
-                            self.reg[3],
-                            self.reg[4],
-                            self.reg[5],
-                            &mut self.vm.memory_mapping,
-                            &mut result,
-                        );
+                        let mut replacement = None;
+                        if let Some(syscall_observer) = self.vm.syscall_observer.as_mut() {
+                            syscall_observer.on_syscall_enter(insn.imm as u32);
+                            replacement = syscall_observer.replace_syscall(insn.imm as u32);
+                        }
+                        let result = replacement.unwrap_or_else(|| {
+                            let mut result = ProgramResult::Ok(0);
+                            syscall(
+                                self.vm.context_object,
+                                self.reg[1],
+                                self.reg[2],
+                                self.reg[3],
+                                self.reg[4],
+                                self.reg[5],
+                                &mut self.vm.memory_mapping,
+                                &mut result,
+                            );
+                            result
+                        });
+                        if let Some(syscall_observer) = self.vm.syscall_observer.as_mut() {
+                            syscall_observer.on_syscall_result(insn.imm as u32, &result);
+                            syscall_observer.on_syscall_exit(insn.imm as u32);
+                        }
                         self.reg[0] = match result {
                             ProgramResult::Ok(value) => value,
                             ProgramResult::Err(err) => return Err(err),
                         };
                         if config.enable_instruction_meter {
-                            self.remaining_insn_count = self.vm.context_object.get_remaining();
+                            self.remaining_insn_count = self.vm.context_object.get_remaining().saturating_sub(self.insn_meter_offset);
                         }
                     }
                 }
 
-                if calls && !resolved {
+                if INTERNAL_CALLS && calls && !resolved {
                     if let Some(target_pc) = executable.lookup_bpf_function(insn.imm as u32) {
                         resolved = true;
 
                         // make BPF to BPF call
-                        self.reg[ebpf::FRAME_PTR_REG] =
-                            self.vm.stack.push(&self.reg[ebpf::FIRST_SCRATCH_REG..ebpf::FIRST_SCRATCH_REG + ebpf::SCRATCH_REGS], self.pc)?;
+                        self.push_frame(Some(target_pc))?;
                         self.pc = self.check_pc(pc, target_pc)?;
+                        state = InterpreterState::Called;
+                        if let Some(call_observer) = self.vm.call_observer.as_mut() {
+                            call_observer.on_call_enter(target_pc);
+                        }
                     }
                 }
 
@@ -496,7 +1175,11 @@ impl<'a, 'b, V: Verifier, C: ContextObject> Interpreter<'a, 'b, V, C> {
             }
 
             ebpf::EXIT       => {
-                match self.vm.stack.pop() {
+                if !INTERNAL_CALLS {
+                    self.vm.last_registers = self.reg;
+                    return Ok(InterpreterState::Exited(self.reg[0]));
+                }
+                match self.vm.stack.pop::<C::UserError>() {
                     Ok((saved_reg, frame_ptr, ptr)) => {
                         // Return from BPF to BPF call
                         self.reg[ebpf::FIRST_SCRATCH_REG
@@ -504,20 +1187,55 @@ impl<'a, 'b, V: Verifier, C: ContextObject> Interpreter<'a, 'b, V, C> {
                             .copy_from_slice(&saved_reg);
                         self.reg[ebpf::FRAME_PTR_REG] = frame_ptr;
                         self.pc = self.check_pc(pc, ptr)?;
+                        state = InterpreterState::Returned;
+                        if let Some(call_observer) = self.vm.call_observer.as_mut() {
+                            call_observer.on_call_return();
+                        }
                     }
                     _ => {
-                        return Ok(Some(self.reg[0]));
+                        self.vm.last_registers = self.reg;
+                        return Ok(InterpreterState::Exited(self.reg[0]));
                     }
                 }
             }
-            _ => return Err(EbpfError::UnsupportedInstruction(pc + ebpf::ELF_INSN_DUMP_OFFSET)),
+
+            // BPF_JMP32 class
+            ebpf::JEQ32_IMM  => if (self.reg[dst] as u32) == insn.imm as u32           { self.pc = (self.pc as isize + insn.off as isize) as usize; },
+            ebpf::JEQ32_REG  => if (self.reg[dst] as u32) == self.reg[src] as u32      { self.pc = (self.pc as isize + insn.off as isize) as usize; },
+            ebpf::JGT32_IMM  => if (self.reg[dst] as u32) >  insn.imm as u32           { self.pc = (self.pc as isize + insn.off as isize) as usize; },
+            ebpf::JGT32_REG  => if (self.reg[dst] as u32) >  self.reg[src] as u32      { self.pc = (self.pc as isize + insn.off as isize) as usize; },
+            ebpf::JGE32_IMM  => if (self.reg[dst] as u32) >= insn.imm as u32           { self.pc = (self.pc as isize + insn.off as isize) as usize; },
+            ebpf::JGE32_REG  => if (self.reg[dst] as u32) >= self.reg[src] as u32      { self.pc = (self.pc as isize + insn.off as isize) as usize; },
+            ebpf::JLT32_IMM  => if (self.reg[dst] as u32) <  insn.imm as u32           { self.pc = (self.pc as isize + insn.off as isize) as usize; },
+            ebpf::JLT32_REG  => if (self.reg[dst] as u32) <  self.reg[src] as u32      { self.pc = (self.pc as isize + insn.off as isize) as usize; },
+            ebpf::JLE32_IMM  => if (self.reg[dst] as u32) <= insn.imm as u32           { self.pc = (self.pc as isize + insn.off as isize) as usize; },
+            ebpf::JLE32_REG  => if (self.reg[dst] as u32) <= self.reg[src] as u32      { self.pc = (self.pc as isize + insn.off as isize) as usize; },
+            ebpf::JSET32_IMM => if (self.reg[dst] as u32) &  insn.imm as u32 != 0      { self.pc = (self.pc as isize + insn.off as isize) as usize; },
+            ebpf::JSET32_REG => if (self.reg[dst] as u32) &  self.reg[src] as u32 != 0 { self.pc = (self.pc as isize + insn.off as isize) as usize; },
+            ebpf::JNE32_IMM  => if (self.reg[dst] as u32) != insn.imm as u32           { self.pc = (self.pc as isize + insn.off as isize) as usize; },
+            ebpf::JNE32_REG  => if (self.reg[dst] as u32) != self.reg[src] as u32      { self.pc = (self.pc as isize + insn.off as isize) as usize; },
+            ebpf::JSGT32_IMM => if (self.reg[dst] as i32) >  insn.imm as i32           { self.pc = (self.pc as isize + insn.off as isize) as usize; },
+            ebpf::JSGT32_REG => if (self.reg[dst] as i32) >  self.reg[src] as i32      { self.pc = (self.pc as isize + insn.off as isize) as usize; },
+            ebpf::JSGE32_IMM => if (self.reg[dst] as i32) >= insn.imm as i32           { self.pc = (self.pc as isize + insn.off as isize) as usize; },
+            ebpf::JSGE32_REG => if (self.reg[dst] as i32) >= self.reg[src] as i32      { self.pc = (self.pc as isize + insn.off as isize) as usize; },
+            ebpf::JSLT32_IMM => if (self.reg[dst] as i32) <  insn.imm as i32           { self.pc = (self.pc as isize + insn.off as isize) as usize; },
+            ebpf::JSLT32_REG => if (self.reg[dst] as i32) <  self.reg[src] as i32      { self.pc = (self.pc as isize + insn.off as isize) as usize; },
+            ebpf::JSLE32_IMM => if (self.reg[dst] as i32) <= insn.imm as i32           { self.pc = (self.pc as isize + insn.off as isize) as usize; },
+            ebpf::JSLE32_REG => if (self.reg[dst] as i32) <= self.reg[src] as i32      { self.pc = (self.pc as isize + insn.off as isize) as usize; },
+            _ => {
+                let handler = self.vm.opcode_extensions.get_mut(&insn.opc).filter(|_| config.allow_opcode_extensions);
+                match handler {
+                    Some(handler) => handler(&insn, &mut self.reg, &mut self.vm.memory_mapping)?,
+                    None => return Err(EbpfError::UnsupportedInstruction(pc + ebpf::ELF_INSN_DUMP_OFFSET)),
+                }
+            }
         }
 
         if config.enable_instruction_meter && self.due_insn_count >= self.remaining_insn_count {
             // Use `pc + instruction_width` instead of `self.pc` here because jumps and calls don't continue at the end of this instruction
-            return Err(EbpfError::ExceededMaxInstructions(pc + instruction_width + ebpf::ELF_INSN_DUMP_OFFSET, self.initial_insn_count));
+            return Err(EbpfError::ExceededMaxInstructions(pc + instruction_width + ebpf::ELF_INSN_DUMP_OFFSET, self.initial_insn_count - self.insn_meter_offset));
         }
 
-        Ok(None)
+        Ok(state)
     }
 }
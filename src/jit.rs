@@ -17,7 +17,10 @@
 extern crate libc;
 
 use std::{
+    collections::{BTreeMap, BTreeSet},
+    convert::{TryFrom, TryInto},
     fmt::{Debug, Error as FormatterError, Formatter}, mem,
+    io::Write,
     ops::{Index, IndexMut},
     ptr,
 };
@@ -25,16 +28,18 @@ use rand::{rngs::SmallRng, Rng, SeedableRng};
 
 use crate::{
     elf::Executable,
-    vm::{Config, ProgramResult, ContextObject, SyscallFunction},
+    vm::{Config, DivByZeroBehavior, OobReadBehavior, ProgramResult, ContextObject, SyscallObserver, CallObserver},
     ebpf::{self, INSN_SIZE, FIRST_SCRATCH_REG, SCRATCH_REGS, FRAME_PTR_REG, MM_STACK_START, STACK_PTR_REG},
-    error::EbpfError,
-    memory_region::{AccessType, MemoryMapping},
+    error::{EbpfError, UserDefinedError},
+    memory_region::{AccessType, MemoryMapping, MemoryRegion},
+    sha256::Sha256,
     x86::*,
 };
 
 const MAX_EMPTY_PROGRAM_MACHINE_CODE_LENGTH: usize = 4096;
 const MAX_MACHINE_CODE_LENGTH_PER_INSTRUCTION: usize = 110;
 const MACHINE_CODE_PER_INSTRUCTION_METER_CHECKPOINT: usize = 13;
+const MACHINE_CODE_PER_INSTRUCTION_COST: usize = 38;
 const ERR_KIND_OFFSET: usize = 1;
 
 struct JitProgramSections {
@@ -78,9 +83,34 @@ fn round_to_page_size(value: usize, page_size: usize) -> usize {
     (value + page_size - 1) / page_size * page_size
 }
 
+fn count_costly_instructions<C: ContextObject>(executable: &Executable<C>) -> usize {
+    let program = executable.get_text_bytes().1;
+    (0..program.len() / ebpf::INSN_SIZE)
+        .filter(|pc| executable.get_instruction_cost(*pc) != 1)
+        .count()
+}
+
+/// Instructions which start a function, `Config::jit_function_padding` is inserted in front of them
+fn padded_function_starts<C: ContextObject>(executable: &Executable<C>) -> BTreeSet<usize> {
+    let insn_count = executable.get_text_bytes().1.len() / ebpf::INSN_SIZE;
+    executable.get_function_registry().values()
+        .map(|(pc, _name)| *pc)
+        .chain(std::iter::once(executable.get_entrypoint_instruction_offset()))
+        .filter(|pc| *pc != 0 && *pc < insn_count)
+        .collect()
+}
+
+/// Length of all padding behind functions, the last function is always padded
+fn function_padding_length<C: ContextObject>(executable: &Executable<C>, config: &Config) -> usize {
+    if config.jit_function_padding == 0 {
+        return 0;
+    }
+    (padded_function_starts(executable).len() + 1) * config.jit_function_padding
+}
+
 #[allow(unused_variables)]
 impl JitProgramSections {
-    fn new(pc: usize, code_size: usize) -> Result<Self, EbpfError> {
+    fn new<E: UserDefinedError>(pc: usize, code_size: usize) -> Result<Self, EbpfError<E>> {
         #[cfg(target_os = "windows")]
         {
             Ok(Self {
@@ -104,7 +134,7 @@ impl JitProgramSections {
         }
     }
 
-    fn seal(&mut self, text_section_usage: usize) -> Result<(), EbpfError> {
+    fn seal<E: UserDefinedError>(&mut self, text_section_usage: usize) -> Result<(), EbpfError<E>> {
         if self.page_size > 0 {
             let raw = self.pc_section.as_ptr() as *mut u8;
             let pc_loc_table_size = round_to_page_size(self.pc_section.len() * 8, self.page_size);
@@ -133,6 +163,10 @@ impl JitProgramSections {
 
 impl Drop for JitProgramSections {
     fn drop(&mut self) {
+        if self.page_size == 0 {
+            // Not backed by pages, see JitCompiler::estimate_code_size()
+            return;
+        }
         let pc_loc_table_size = round_to_page_size(self.pc_section.len() * 8, self.page_size);
         let code_size = round_to_page_size(self.text_section.len(), self.page_size);
         if pc_loc_table_size + code_size > 0 {
@@ -144,12 +178,88 @@ impl Drop for JitProgramSections {
     }
 }
 
+/// Entry point of the machine code of a JitProgram
+pub type JitMain<C> = unsafe fn(&mut ProgramResult<<C as ContextObject>::UserError>, &mut MemoryMapping, &mut C, Option<&mut &mut dyn SyscallObserver<<C as ContextObject>::UserError>>, Option<&mut &mut dyn CallObserver>, &mut [u64; 11]) -> i64;
+
 /// eBPF JIT-compiled program
 pub struct JitProgram<C: ContextObject> {
     /// Holds and manages the protected memory
     sections: JitProgramSections,
     /// Call this to execute the compiled code
-    pub main: unsafe fn(&mut ProgramResult, &mut MemoryMapping, &mut C) -> i64,
+    pub main: JitMain<C>,
+    /// Offset into the text section where the code of each BPF instruction starts, plus the end of the last one
+    instruction_offsets: Vec<usize>,
+    /// Names of the BPF functions by their entry pc
+    function_names: BTreeMap<usize, String>,
+    /// Absolute addresses in the text section, see JitProgram::serialize()
+    relocations: Vec<(usize, Relocation)>,
+    /// Identifies the executable the machine code was compiled from, see JitProgram::deserialize()
+    fingerprint: [u8; 32],
+}
+
+/// Leading bytes of the output of JitProgram::serialize()
+const SERIALIZED_JIT_PROGRAM_MAGIC: [u8; 8] = *b"SBFJIT\0\x02";
+
+/// Length of the SHA-256 digests in the output of JitProgram::serialize()
+const SERIALIZED_JIT_PROGRAM_DIGEST_LENGTH: usize = 32;
+
+fn function_names<C: ContextObject>(executable: &Executable<C>) -> BTreeMap<usize, String> {
+    executable
+        .get_function_registry()
+        .values()
+        .map(|(pc, name)| (*pc, if name.is_empty() { format!("function_{}", pc) } else { name.clone() }))
+        .collect()
+}
+
+/// Hashes everything the machine code depends on, except for the addresses which are relocated
+///
+/// SHA-256 is used because the result is compared across processes, see JitProgram::deserialize().
+fn fingerprint<C: ContextObject>(executable: &Executable<C>, enable_bounds_checks: bool) -> [u8; 32] {
+    let mut hasher = Sha256::default();
+    let mut hash_bytes = |bytes: &[u8]| {
+        hasher.update(&(bytes.len() as u64).to_le_bytes());
+        hasher.update(bytes);
+    };
+    hash_bytes(env!("CARGO_PKG_VERSION").as_bytes());
+    hash_bytes(std::any::type_name::<C>().as_bytes());
+    hash_bytes(format!("{:?}", executable.get_config()).as_bytes());
+    hash_bytes(&[enable_bounds_checks as u8]);
+    let (program_vm_addr, program) = executable.get_text_bytes();
+    hash_bytes(&program_vm_addr.to_le_bytes());
+    hash_bytes(program);
+    hash_bytes(&(executable.get_entrypoint_instruction_offset() as u64).to_le_bytes());
+    for (key, (pc, name)) in executable.get_function_registry() {
+        hash_bytes(&key.to_le_bytes());
+        hash_bytes(&(*pc as u64).to_le_bytes());
+        hash_bytes(name.as_bytes());
+    }
+    for pc in 0..program.len() / ebpf::INSN_SIZE {
+        hash_bytes(&executable.get_instruction_cost(pc).to_le_bytes());
+        hash_bytes(&executable.get_stack_frame_size(pc).map_or(u64::MAX, |size| size as u64).to_le_bytes());
+        let insn = ebpf::get_insn_unchecked(program, pc);
+        if insn.opc == ebpf::CALL_IMM {
+            hash_bytes(&[executable.get_syscall_registry().contains_syscall(insn.imm as u32) as u8]);
+        }
+    }
+    hasher.finalize()
+}
+
+/// Digest of the serialized machine code and tables, see JitProgram::serialize()
+fn checksum(payload: &[u8]) -> [u8; 32] {
+    let mut hasher = Sha256::default();
+    hasher.update(payload);
+    hasher.finalize()
+}
+
+fn read_u64<E: UserDefinedError>(bytes: &[u8], offset: &mut usize) -> Result<u64, EbpfError<E>> {
+    let end = offset.checked_add(mem::size_of::<u64>()).ok_or(EbpfError::JitCacheMismatch)?;
+    let value = bytes.get(*offset..end).ok_or(EbpfError::JitCacheMismatch)?;
+    *offset = end;
+    Ok(u64::from_le_bytes(value.try_into().unwrap()))
+}
+
+fn read_usize<E: UserDefinedError>(bytes: &[u8], offset: &mut usize) -> Result<usize, EbpfError<E>> {
+    usize::try_from(read_u64(bytes, offset)?).map_err(|_| EbpfError::JitCacheMismatch)
 }
 
 impl<C: ContextObject> Debug for JitProgram<C> {
@@ -165,20 +275,249 @@ impl<C: ContextObject> PartialEq for JitProgram<C> {
 }
 
 impl<C: ContextObject> JitProgram<C> {
-    pub fn new(executable: &Executable<C>) -> Result<Self, EbpfError> {
+    pub fn new(executable: &Executable<C>) -> Result<Self, EbpfError<C::UserError>> {
+        Self::compile(executable, true)
+    }
+
+    /// Same as `new()`, but loads and stores are not checked against the memory regions
+    ///
+    /// The result can not be loaded by `JitProgram::deserialize()` after serialization.
+    ///
+    /// # Safety
+    ///
+    /// Addresses are translated by the region index (`vm_addr >> 32`) alone, without checking the
+    /// length, the permissions or even that the region exists. A program which accesses memory
+    /// outside of its regions then reads or corrupts arbitrary host memory. Only use this for
+    /// programs which are proven to stay within their regions by other means.
+    ///
+    /// Unchecked accesses are not seen by the access counters and the memory observer. The checks
+    /// stay on unless `aligned_memory_mapping` is set, the stack has no gaps, `enforce_aligned_access`
+    /// is off and `oob_read_behavior` is `Fault`.
+    pub unsafe fn new_without_bounds_checks(executable: &Executable<C>) -> Result<Self, EbpfError<C::UserError>> {
+        Self::compile(executable, false)
+    }
+
+    fn compile(executable: &Executable<C>, enable_bounds_checks: bool) -> Result<Self, EbpfError<C::UserError>> {
+        // The machine code accesses the ProgramResult at fixed offsets, see UserDefinedError
+        debug_assert!(mem::align_of::<ProgramResult<C::UserError>>() <= mem::size_of::<u64>());
         let program = executable.get_text_bytes().1;
-        let mut jit = JitCompiler::new(program, executable.get_config())?;
+        let costly_instructions = count_costly_instructions(executable);
+        let mut jit = JitCompiler::new(program, executable.get_config(), costly_instructions, function_padding_length(executable, executable.get_config()))?;
+        jit.enable_bounds_checks = enable_bounds_checks;
         jit.compile::<C>(executable)?;
         let main = unsafe { mem::transmute(jit.result.text_section.as_ptr()) };
         Ok(Self {
             sections: jit.result,
             main,
+            instruction_offsets: jit.instruction_offsets,
+            function_names: function_names(executable),
+            relocations: jit.relocations,
+            fingerprint: fingerprint(executable, enable_bounds_checks),
         })
     }
 
     pub fn mem_size(&self) -> usize {
         mem::size_of::<Self>() +
-        self.sections.mem_size()
+        self.sections.mem_size() +
+        self.instruction_offsets.capacity() * mem::size_of::<usize>() +
+        self.function_names.values().fold(0, |state, name| state + mem::size_of::<(usize, String)>() + name.capacity()) +
+        self.relocations.capacity() * mem::size_of::<(usize, Relocation)>()
+    }
+
+    /// Serializes the machine code so that it can be loaded again by `JitProgram::deserialize()`
+    ///
+    /// The absolute addresses in the machine code are replaced by placeholders which
+    /// `deserialize()` fills in again, so the result does not depend on where it was loaded.
+    /// The fingerprint of the executable and a checksum of everything that follows it
+    /// are put in front, so that `deserialize()` can reject stale or corrupted bytes.
+    pub fn serialize(&self) -> Vec<u8> {
+        let text_section = &self.sections.text_section;
+        let text_section_base = text_section.as_ptr() as usize;
+        let mut text = text_section.to_vec();
+        for (offset, relocation) in self.relocations.iter() {
+            let slot = &mut text[*offset..*offset + mem::size_of::<u64>()];
+            let value = match relocation {
+                Relocation::TextSection => u64::from_le_bytes((&*slot).try_into().unwrap()) - text_section_base as u64,
+                _ => 0,
+            };
+            slot.copy_from_slice(&value.to_le_bytes());
+        }
+        let header_length = SERIALIZED_JIT_PROGRAM_MAGIC.len() + 2 * SERIALIZED_JIT_PROGRAM_DIGEST_LENGTH;
+        let mut result = Vec::with_capacity(
+            header_length +
+            mem::size_of::<u64>() * (4 + self.sections.pc_section.len() + self.instruction_offsets.len() + 3 * self.relocations.len()) +
+            text.len()
+        );
+        result.extend_from_slice(&SERIALIZED_JIT_PROGRAM_MAGIC);
+        result.extend_from_slice(&self.fingerprint);
+        result.extend_from_slice(&[0; SERIALIZED_JIT_PROGRAM_DIGEST_LENGTH]);
+        for value in [text.len() as u64, self.sections.pc_section.len() as u64, self.instruction_offsets.len() as u64, self.relocations.len() as u64] {
+            result.extend_from_slice(&value.to_le_bytes());
+        }
+        result.extend_from_slice(&text);
+        for address in self.sections.pc_section.iter() {
+            result.extend_from_slice(&((address - text_section_base) as u64).to_le_bytes());
+        }
+        for offset in self.instruction_offsets.iter() {
+            result.extend_from_slice(&(*offset as u64).to_le_bytes());
+        }
+        for (offset, relocation) in self.relocations.iter() {
+            let (kind, argument) = relocation.encode();
+            for value in [*offset as u64, kind, argument] {
+                result.extend_from_slice(&value.to_le_bytes());
+            }
+        }
+        let checksum = checksum(&result[header_length..]);
+        result[header_length - SERIALIZED_JIT_PROGRAM_DIGEST_LENGTH..header_length].copy_from_slice(&checksum);
+        result
+    }
+
+    /// Loads machine code which `JitProgram::serialize()` produced for an identical executable
+    ///
+    /// Fails with `EbpfError::JitCacheMismatch` if the bytes are malformed, corrupted or were
+    /// produced for a different program, config, context object, set of syscalls or crate version.
+    ///
+    /// # Safety
+    ///
+    /// The machine code in `bytes` is executed as is. The fingerprint and checksum only detect
+    /// stale and accidentally corrupted bytes, not deliberately crafted ones. So `bytes` must be
+    /// the unmodified output of `serialize()`, kept where no untrusted party could write to it.
+    pub unsafe fn deserialize(bytes: &[u8], executable: &Executable<C>) -> Result<Self, EbpfError<C::UserError>> {
+        #[cfg(target_os = "windows")]
+        {
+            let _ = bytes;
+            let _ = executable;
+            panic!("JIT not supported on windows");
+        }
+
+        #[cfg(not(target_arch = "x86_64"))]
+        {
+            let _ = bytes;
+            let _ = executable;
+            return Err(EbpfError::UnsupportedJitArch(std::env::consts::ARCH));
+        }
+
+        if bytes.get(..SERIALIZED_JIT_PROGRAM_MAGIC.len()) != Some(&SERIALIZED_JIT_PROGRAM_MAGIC[..]) {
+            return Err(EbpfError::JitCacheMismatch);
+        }
+        let mut offset = SERIALIZED_JIT_PROGRAM_MAGIC.len();
+        let fingerprint = self::fingerprint(executable, true);
+        if bytes.get(offset..offset + SERIALIZED_JIT_PROGRAM_DIGEST_LENGTH) != Some(&fingerprint[..]) {
+            return Err(EbpfError::JitCacheMismatch);
+        }
+        offset += SERIALIZED_JIT_PROGRAM_DIGEST_LENGTH;
+        let checksum = bytes.get(offset..offset + SERIALIZED_JIT_PROGRAM_DIGEST_LENGTH).ok_or(EbpfError::JitCacheMismatch)?;
+        offset += SERIALIZED_JIT_PROGRAM_DIGEST_LENGTH;
+        if checksum != self::checksum(&bytes[offset..]) {
+            return Err(EbpfError::JitCacheMismatch);
+        }
+        let text_length = read_usize(bytes, &mut offset)?;
+        let pc = read_usize(bytes, &mut offset)?;
+        let instruction_offsets_length = read_usize(bytes, &mut offset)?;
+        let relocations_length = read_usize(bytes, &mut offset)?;
+        let (expected_pc, _code_length_estimate) = JitCompiler::code_length_estimate(executable.get_text_bytes().1, executable.get_config(), 0, 0);
+        if pc != expected_pc || text_length == 0 || instruction_offsets_length != pc + 1 {
+            return Err(EbpfError::JitCacheMismatch);
+        }
+        let text = offset.checked_add(text_length)
+            .and_then(|end| bytes.get(offset..end))
+            .ok_or(EbpfError::JitCacheMismatch)?;
+        offset += text_length;
+
+        let mut sections = JitProgramSections::new(pc, text_length)?;
+        sections.text_section[..text_length].copy_from_slice(text);
+        let text_section_base = sections.text_section.as_ptr() as usize;
+        let pc_section_base = sections.pc_section.as_ptr() as usize;
+        for address in sections.pc_section.iter_mut() {
+            let text_offset = read_usize(bytes, &mut offset)?;
+            if text_offset >= text_length {
+                return Err(EbpfError::JitCacheMismatch);
+            }
+            *address = text_section_base + text_offset;
+        }
+        let instruction_offsets = (0..instruction_offsets_length)
+            .map(|_| read_usize(bytes, &mut offset).and_then(|text_offset| if text_offset <= text_length { Ok(text_offset) } else { Err(EbpfError::JitCacheMismatch) }))
+            .collect::<Result<Vec<_>, _>>()?;
+        let mut relocations = Vec::with_capacity(relocations_length.min(text_length));
+        for _ in 0..relocations_length {
+            let slot_offset = read_usize(bytes, &mut offset)?;
+            let kind = read_u64(bytes, &mut offset)?;
+            let argument = read_u64(bytes, &mut offset)?;
+            let relocation = Relocation::decode(kind, argument).ok_or(EbpfError::JitCacheMismatch)?;
+            let slot = slot_offset.checked_add(mem::size_of::<u64>())
+                .filter(|end| *end <= text_length)
+                .map(|end| &mut sections.text_section[slot_offset..end])
+                .ok_or(EbpfError::JitCacheMismatch)?;
+            let value = match relocation {
+                Relocation::TextSection => {
+                    let text_offset = u64::from_le_bytes((&*slot).try_into().unwrap());
+                    if text_offset >= text_length as u64 {
+                        return Err(EbpfError::JitCacheMismatch);
+                    }
+                    text_section_base as u64 + text_offset
+                },
+                Relocation::PcSection => pc_section_base as u64,
+                Relocation::HostFunction(function) => function.address::<C>() as u64,
+                Relocation::Syscall(hash) => executable.get_syscall_registry()
+                    .lookup_syscall(hash)
+                    .ok_or(EbpfError::JitCacheMismatch)? as *const u8 as u64,
+            };
+            slot.copy_from_slice(&value.to_le_bytes());
+            relocations.push((slot_offset, relocation));
+        }
+        if offset != bytes.len() {
+            return Err(EbpfError::JitCacheMismatch);
+        }
+        sections.seal(text_length)?;
+
+        let main = unsafe { mem::transmute(sections.text_section.as_ptr()) };
+        Ok(Self {
+            sections,
+            main,
+            instruction_offsets,
+            function_names: function_names(executable),
+            relocations,
+            fingerprint,
+        })
+    }
+
+    /// Writes a perf map of the machine code, one `START SIZE SYMBOL` line per BPF instruction
+    ///
+    /// The symbol is the name of the surrounding BPF function and the instruction index.
+    /// The code of the runtime (prologue, subroutines and epilogue) is reported as `ebpf_runtime`.
+    pub fn write_perf_map<W: Write>(&self, output: &mut W) -> std::io::Result<()> {
+        let text_section_base = self.sections.text_section.as_ptr() as usize;
+        let (first_offset, last_offset) = match (self.instruction_offsets.first(), self.instruction_offsets.last()) {
+            (Some(first_offset), Some(last_offset)) => (*first_offset, *last_offset),
+            _ => (self.sections.text_section.len(), self.sections.text_section.len()),
+        };
+        writeln!(output, "{:x} {:x} ebpf_runtime", text_section_base, first_offset)?;
+        for (pc, range) in self.instruction_offsets.windows(2).enumerate() {
+            if range[1] == range[0] {
+                continue;
+            }
+            let function_name = self.function_names
+                .range(..=pc)
+                .next_back()
+                .map(|(_pc, name)| name.as_str())
+                .unwrap_or("ebpf_program");
+            writeln!(output, "{:x} {:x} {}:{}", text_section_base + range[0], range[1] - range[0], function_name, pc)?;
+        }
+        if last_offset < self.sections.text_section.len() {
+            writeln!(output, "{:x} {:x} ebpf_runtime", text_section_base + last_offset, self.sections.text_section.len() - last_offset)?;
+        }
+        Ok(())
+    }
+
+    /// Appends the perf map of the machine code to `/tmp/perf-<pid>.map`, where `perf` looks up the symbols of JIT code
+    pub fn register_perf_map(&self) -> std::io::Result<()> {
+        let mut file = std::fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(format!("/tmp/perf-{}.map", std::process::id()))?;
+        let mut perf_map = Vec::new();
+        self.write_perf_map(&mut perf_map)?;
+        file.write_all(&perf_map)
     }
 
     pub fn machine_code_length(&self) -> usize {
@@ -203,10 +542,43 @@ const ANCHOR_EXIT: usize = 11;
 const ANCHOR_SYSCALL: usize = 12;
 const ANCHOR_BPF_CALL_PROLOGUE: usize = 13;
 const ANCHOR_BPF_CALL_REG: usize = 14;
+const ANCHOR_CALLX_NOT_A_FUNCTION: usize = 15;
+const ANCHOR_CALLX_INVALID_TARGET: usize = 16;
+const ANCHOR_CALL_OBSERVER_ENTER: usize = 17;
+const ANCHOR_CALL_OBSERVER_RETURN: usize = 18;
+const ANCHOR_OOB_READ_ZEROES: usize = 19;
+const ANCHOR_BPF_CALL_SIZED_FRAME: usize = 20;
+const ANCHOR_CALLX_UNKNOWN_TARGET: usize = 21;
 const ANCHOR_TRANSLATE_MEMORY_ADDRESS: usize = 22;
-const ANCHOR_COUNT: usize = 31; // Update me when adding or removing anchors
-
-const REGISTER_MAP: [u8; 11] = [
+const ANCHOR_SYSCALLS_SEALED: usize = 30;
+const ANCHOR_CALLX_OBSERVER_ENTER: usize = 31;
+const ANCHOR_COUNT: usize = 32; // Update me when adding or removing anchors
+
+/// Host register assigned to each eBPF register, indexed by the eBPF register number
+///
+/// Host registers are given in the x86-64 encoding (RAX = 0, RCX = 1, RDX = 2, RBX = 3,
+/// RSP = 4, RBP = 5, RSI = 6, RDI = 7 and R8 to R15 = 8 to 15):
+///
+/// | eBPF                | Host | System V     |
+/// |---------------------|------|--------------|
+/// | r0                  | RAX  | caller-saved |
+/// | r1                  | RSI  | caller-saved |
+/// | r2                  | RDX  | caller-saved |
+/// | r3                  | RCX  | caller-saved |
+/// | r4                  | R8   | caller-saved |
+/// | r5                  | R9   | caller-saved |
+/// | r6                  | R12  | callee-saved |
+/// | r7                  | R13  | callee-saved |
+/// | r8                  | R14  | callee-saved |
+/// | r9                  | R15  | callee-saved |
+/// | r10 (frame pointer) | RBX  | callee-saved |
+///
+/// Thus r0 to r5 are spilled around calls into Rust (syscalls, the instruction meter and the
+/// memory translation), while r6 to r10 survive them. RSP, RBP, RDI, R10 and R11 are reserved
+/// by the JIT for its environment, the instruction meter and scratch values. They must not
+/// appear in the table, which is checked at compile time.
+/// The code generation for multiplication, division and shifts relies on RAX, RDX and RCX.
+pub const REGISTER_MAP: [u8; 11] = [
     CALLER_SAVED_REGISTERS[0],
     ARGUMENT_REGISTERS[1],
     ARGUMENT_REGISTERS[2],
@@ -225,6 +597,35 @@ const REGISTER_MAP: [u8; 11] = [
 // CALLER_SAVED_REGISTERS[8]  R11  Scratch register
 // CALLER_SAVED_REGISTERS[7]  R10  Unused for the most part, scratch register for exception handling
 // CALLEE_SAVED_REGISTERS[0]  RBP  Constant pointer to initial RSP - 8
+const RESERVED_REGISTERS: [u8; 5] = [RSP, RBP, ARGUMENT_REGISTERS[0], R10, R11];
+
+const _: () = assert!(is_valid_register_map(&REGISTER_MAP), "invalid REGISTER_MAP");
+
+/// Every eBPF register needs its own host register which is not reserved by the JIT
+const fn is_valid_register_map(register_map: &[u8; 11]) -> bool {
+    let mut i = 0;
+    while i < register_map.len() {
+        if register_map[i] > R15 {
+            return false;
+        }
+        let mut j = 0;
+        while j < RESERVED_REGISTERS.len() {
+            if register_map[i] == RESERVED_REGISTERS[j] {
+                return false;
+            }
+            j += 1;
+        }
+        let mut j = i + 1;
+        while j < register_map.len() {
+            if register_map[i] == register_map[j] {
+                return false;
+            }
+            j += 1;
+        }
+        i += 1;
+    }
+    true
+}
 
 #[inline]
 pub fn emit<T>(jit: &mut JitCompiler, data: T) {
@@ -304,6 +705,16 @@ fn emit_sanitized_load_immediate(jit: &mut JitCompiler, size: OperandSize, desti
     }
 }
 
+/// Whether loads and stores skip `MemoryMapping::map()`, see `JitProgram::new_without_bounds_checks()`
+#[inline]
+fn should_skip_bounds_checks(jit: &JitCompiler) -> bool {
+    !jit.enable_bounds_checks
+        && jit.config.aligned_memory_mapping
+        && (jit.config.dynamic_stack_frames || !jit.config.enable_stack_frame_gaps)
+        && !jit.config.enforce_aligned_access
+        && jit.config.oob_read_behavior == OobReadBehavior::Fault
+}
+
 #[inline]
 fn should_sanitize_constant(jit: &JitCompiler, value: i64) -> bool {
     if !jit.config.sanitize_user_provided_values {
@@ -359,14 +770,22 @@ enum EnvironmentStackSlot {
     MemoryMapping = 10,
     /// Pointer to ContextObject
     ContextObject = 11,
-    /// Unused
-    _Padding = 12,
+    /// Pointer to optional SyscallObserver
+    SyscallObserver = 12,
+    /// Pointer to optional CallObserver
+    CallObserver = 13,
+    /// Pointer to the registers which are spilled on exit
+    LastRegisters = 14,
     /// Last return value of instruction_meter.get_remaining()
-    PrevInsnMeter = 13,
+    PrevInsnMeter = 15,
     /// CPU cycles accumulated by the stop watch
-    StopwatchNumerator = 14,
+    StopwatchNumerator = 16,
     /// Number of times the stop watch was used
-    StopwatchDenominator = 15,
+    StopwatchDenominator = 17,
+    /// Part of the instruction meter which is cut off by config.max_instructions
+    InstructionMeterOffset = 18,
+    /// Pointer to the regions of the MemoryMapping. Only used without bounds checks.
+    MemoryRegions = 19,
 }
 
 fn slot_on_environment_stack(jit: &JitCompiler, slot: EnvironmentStackSlot) -> i32 {
@@ -500,12 +919,36 @@ fn emit_profile_instruction_count_finalize(jit: &mut JitCompiler, store_pc_in_ex
 }
 
 #[inline]
-fn emit_conditional_branch_reg(jit: &mut JitCompiler, op: u8, bitwise: bool, first_operand: u8, second_operand: u8, target_pc: usize) {
+fn emit_instruction_cost<E: UserDefinedError>(jit: &mut JitCompiler, cost: u64) -> Result<(), EbpfError<E>> {
+    // Update `MACHINE_CODE_PER_INSTRUCTION_COST` if you change the code generation here
+    if !jit.config.enable_instruction_meter || cost == 1 {
+        return Ok(());
+    }
+    if jit.pc as u64 + cost > i32::MAX as u64 {
+        return Err(EbpfError::InstructionCostTooLarge(jit.pc + ebpf::ELF_INSN_DUMP_OFFSET, cost));
+    }
+    // The budget might have run out before this instruction, while the instruction meter still holds that exact pc
+    emit_ins(jit, X86Instruction::cmp_immediate(OperandSize::S64, ARGUMENT_REGISTERS[0], jit.pc as i64, None));
+    emit_ins(jit, X86Instruction::conditional_jump_immediate(0x86, jit.relative_to_anchor(ANCHOR_CALL_EXCEEDED_MAX_INSTRUCTIONS, 6)));
+    if cost == 0 {
+        emit_ins(jit, X86Instruction::alu(OperandSize::S64, 0x81, 0, ARGUMENT_REGISTERS[0], 1, None)); // instruction_meter += 1;
+    } else {
+        // Charge the units beyond the first one, but saturate at this instruction so that the next validation reports the pc behind it
+        emit_ins(jit, X86Instruction::load_immediate(OperandSize::S64, R11, jit.pc as i64 + 1));
+        emit_ins(jit, X86Instruction::cmp_immediate(OperandSize::S64, ARGUMENT_REGISTERS[0], (jit.pc as u64 + cost) as i64, None));
+        emit_ins(jit, X86Instruction::lea(OperandSize::S64, ARGUMENT_REGISTERS[0], ARGUMENT_REGISTERS[0], Some(X86IndirectAccess::Offset(1 - cost as i32)))); // instruction_meter -= cost - 1;
+        emit_ins(jit, X86Instruction::cmov(OperandSize::S64, 0x42, R11, ARGUMENT_REGISTERS[0])); // if instruction_meter < jit.pc + cost { instruction_meter = jit.pc + 1; }
+    }
+    Ok(())
+}
+
+#[inline]
+fn emit_conditional_branch_reg(jit: &mut JitCompiler, size: OperandSize, op: u8, bitwise: bool, first_operand: u8, second_operand: u8, target_pc: usize) {
     emit_validate_and_profile_instruction_count(jit, false, Some(target_pc));
     if bitwise { // Logical
-        emit_ins(jit, X86Instruction::test(OperandSize::S64, first_operand, second_operand, None));
+        emit_ins(jit, X86Instruction::test(size, first_operand, second_operand, None));
     } else { // Arithmetic
-        emit_ins(jit, X86Instruction::cmp(OperandSize::S64, first_operand, second_operand, None));
+        emit_ins(jit, X86Instruction::cmp(size, first_operand, second_operand, None));
     }
     emit_ins(jit, X86Instruction::load_immediate(OperandSize::S64, R11, target_pc as i64));
     let jump_offset = jit.relative_to_target_pc(target_pc, 6);
@@ -514,19 +957,19 @@ fn emit_conditional_branch_reg(jit: &mut JitCompiler, op: u8, bitwise: bool, fir
 }
 
 #[inline]
-fn emit_conditional_branch_imm(jit: &mut JitCompiler, op: u8, bitwise: bool, immediate: i64, second_operand: u8, target_pc: usize) {
+fn emit_conditional_branch_imm(jit: &mut JitCompiler, size: OperandSize, op: u8, bitwise: bool, immediate: i64, second_operand: u8, target_pc: usize) {
     emit_validate_and_profile_instruction_count(jit, false, Some(target_pc));
     if should_sanitize_constant(jit, immediate) {
         emit_sanitized_load_immediate(jit, OperandSize::S64, R11, immediate);
         if bitwise { // Logical
-            emit_ins(jit, X86Instruction::test(OperandSize::S64, R11, second_operand, None));
+            emit_ins(jit, X86Instruction::test(size, R11, second_operand, None));
         } else { // Arithmetic
-            emit_ins(jit, X86Instruction::cmp(OperandSize::S64, R11, second_operand, None));
+            emit_ins(jit, X86Instruction::cmp(size, R11, second_operand, None));
         }
     } else if bitwise { // Logical
-        emit_ins(jit, X86Instruction::test_immediate(OperandSize::S64, second_operand, immediate, None));
+        emit_ins(jit, X86Instruction::test_immediate(size, second_operand, immediate, None));
     } else { // Arithmetic
-        emit_ins(jit, X86Instruction::cmp_immediate(OperandSize::S64, second_operand, immediate, None));
+        emit_ins(jit, X86Instruction::cmp_immediate(size, second_operand, immediate, None));
     }
     emit_ins(jit, X86Instruction::load_immediate(OperandSize::S64, R11, target_pc as i64));
     let jump_offset = jit.relative_to_target_pc(target_pc, 6);
@@ -540,14 +983,195 @@ enum Value {
     // RegisterPlusConstant32(u8, i32, bool),
     RegisterPlusConstant64(u8, i64, bool),
     Constant64(i64, bool),
+    HostFunction(HostFunction),
+}
+
+/// Functions of the runtime which the machine code calls by their absolute address
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum HostFunction {
+    GetRemaining,
+    Consume,
+    Trace,
+    StopwatchResult,
+    ClearStackFrame,
+    SyscallObserverEnter,
+    SyscallObserverExit,
+    MemoryMappingMap,
+    CallObserverEnter,
+    CallObserverReturn,
+    MemoryMappingRegions,
+    SyscallsSealed,
+}
+
+const HOST_FUNCTIONS: [HostFunction; 12] = [
+    HostFunction::GetRemaining,
+    HostFunction::Consume,
+    HostFunction::Trace,
+    HostFunction::StopwatchResult,
+    HostFunction::ClearStackFrame,
+    HostFunction::SyscallObserverEnter,
+    HostFunction::SyscallObserverExit,
+    HostFunction::MemoryMappingMap,
+    HostFunction::CallObserverEnter,
+    HostFunction::CallObserverReturn,
+    HostFunction::MemoryMappingRegions,
+    HostFunction::SyscallsSealed,
+];
+
+impl HostFunction {
+    fn address<C: ContextObject>(self) -> i64 {
+        match self {
+            HostFunction::GetRemaining => C::get_remaining as *const u8 as i64,
+            HostFunction::Consume => C::consume as *const u8 as i64,
+            HostFunction::Trace => C::trace as *const u8 as i64,
+            HostFunction::StopwatchResult => stopwatch_result as *const u8 as i64,
+            HostFunction::ClearStackFrame => clear_stack_frame::<C::UserError> as *const u8 as i64,
+            HostFunction::SyscallObserverEnter => syscall_observer_enter::<C::UserError> as *const u8 as i64,
+            HostFunction::SyscallObserverExit => syscall_observer_exit::<C::UserError> as *const u8 as i64,
+            HostFunction::MemoryMappingMap => MemoryMapping::map::<C::UserError> as *const u8 as i64,
+            HostFunction::CallObserverEnter => call_observer_enter as *const u8 as i64,
+            HostFunction::CallObserverReturn => call_observer_return as *const u8 as i64,
+            HostFunction::MemoryMappingRegions => memory_mapping_regions as *const u8 as i64,
+            HostFunction::SyscallsSealed => syscalls_sealed::<C> as *const u8 as i64,
+        }
+    }
+}
+
+/// Absolute address in the machine code, which JitProgram::deserialize() adjusts to the new location
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Relocation {
+    /// Address inside the text section
+    TextSection,
+    /// Address of the pc section
+    PcSection,
+    /// Address of a function of the runtime
+    HostFunction(HostFunction),
+    /// Address of the syscall with the given hash
+    Syscall(u32),
+}
+
+impl Relocation {
+    /// Returns the kind and the argument as stored by JitProgram::serialize()
+    fn encode(self) -> (u64, u64) {
+        match self {
+            Relocation::TextSection => (0, 0),
+            Relocation::PcSection => (1, 0),
+            Relocation::HostFunction(function) => (2, function as u64),
+            Relocation::Syscall(hash) => (3, hash as u64),
+        }
+    }
+
+    fn decode(kind: u64, argument: u64) -> Option<Self> {
+        match (kind, argument) {
+            (0, 0) => Some(Relocation::TextSection),
+            (1, 0) => Some(Relocation::PcSection),
+            (2, _) => HOST_FUNCTIONS.get(argument as usize).map(|function| Relocation::HostFunction(*function)),
+            (3, _) => u32::try_from(argument).ok().map(Relocation::Syscall),
+            _ => None,
+        }
+    }
+}
+
+/// Loads an absolute address and records where it is, see Relocation
+#[inline]
+fn emit_relocatable_address(jit: &mut JitCompiler, destination: u8, address: i64, relocation: Relocation) {
+    // The 64 bit immediate follows the REX prefix and the opcode
+    jit.relocations.push((jit.offset_in_text_section + 2, relocation));
+    emit_ins(jit, X86Instruction::load_immediate_wide(destination, address));
 }
 
+fn syscall_observer_enter<E: UserDefinedError>(syscall_observer: &mut &mut dyn SyscallObserver<E>, number: u64, result: &mut ProgramResult<E>) -> u64 {
+    syscall_observer.on_syscall_enter(number as u32);
+    match syscall_observer.replace_syscall(number as u32) {
+        Some(replacement) => {
+            *result = replacement;
+            1
+        }
+        None => 0,
+    }
+}
+
+fn syscall_observer_exit<E: UserDefinedError>(syscall_observer: &mut &mut dyn SyscallObserver<E>, number: u64, result: &ProgramResult<E>) {
+    syscall_observer.on_syscall_result(number as u32, result);
+    syscall_observer.on_syscall_exit(number as u32);
+}
+
+fn call_observer_enter(call_observer: &mut &mut dyn CallObserver, target_pc: u64) {
+    call_observer.on_call_enter(target_pc as usize);
+}
+
+fn call_observer_return(call_observer: &mut &mut dyn CallObserver) {
+    call_observer.on_call_return();
+}
+
+/// Address of the regions, which `MemoryMapping::replace_region()` overwrites in place
+fn memory_mapping_regions(memory_mapping: &MemoryMapping) -> *const MemoryRegion {
+    memory_mapping.get_regions().as_ptr()
+}
+
+/// Widens `ContextObject::syscalls_sealed()` to a full register
+fn syscalls_sealed<C: ContextObject>(context_object: &C) -> u64 {
+    context_object.syscalls_sealed() as u64
+}
+
+fn stopwatch_result(numerator: u64, denominator: u64) {
+    println!("Stop watch: {} / {} = {}", numerator, denominator, if denominator == 0 { 0.0 } else { numerator as f64 / denominator as f64 });
+}
+
+/// Zeroes up to `frame_size` bytes below `frame_ptr`, see `Config::clear_stack_on_entry`
+fn clear_stack_frame<E: UserDefinedError>(memory_mapping: &mut MemoryMapping, frame_ptr: u64, frame_size: u64) {
+    let len = frame_size.min(frame_ptr.saturating_sub(MM_STACK_START));
+    if let ProgramResult::<E>::Ok(host_addr) = memory_mapping.map(AccessType::Store, frame_ptr - len, len) {
+        unsafe { ptr::write_bytes(host_addr as *mut u8, 0, len as usize); }
+    }
+}
+
+/// Calls `function(SyscallObserver, RAX, OptRetValPtr)` unless no SyscallObserver is installed
+///
+/// If `result_reg` is given, it receives the return value of `function` and is left untouched otherwise.
 #[inline]
-fn emit_bpf_call(jit: &mut JitCompiler, dst: Value) {
+fn emit_syscall_observer_call(jit: &mut JitCompiler, function: HostFunction, result_reg: Option<u8>) {
+    let syscall_observer_slot = slot_on_environment_stack(jit, EnvironmentStackSlot::SyscallObserver);
+    // if(SyscallObserver == 0) skip the call
+    emit_ins(jit, X86Instruction::cmp_immediate(OperandSize::S64, RBP, 0, Some(X86IndirectAccess::Offset(syscall_observer_slot))));
+    // The conditional jump is 6 bytes long, but emit_ins() can append a noop behind it
+    let jump_location = jit.offset_in_text_section;
+    emit_ins(jit, X86Instruction::conditional_jump_immediate(0x84, 0));
+    emit_rust_call(jit, Value::HostFunction(function), &[
+        Argument { index: 2, value: Value::RegisterIndirect(RBP, slot_on_environment_stack(jit, EnvironmentStackSlot::OptRetValPtr), false) },
+        Argument { index: 1, value: Value::Register(REGISTER_MAP[0]) },
+        Argument { index: 0, value: Value::RegisterIndirect(RBP, syscall_observer_slot, false) },
+    ], result_reg);
+    // Patch the forward jump now that the length of the call is known
+    let offset_value = (jit.offset_in_text_section - (jump_location + 6)) as i32;
+    unsafe { ptr::write_unaligned(jit.result.text_section.as_ptr().add(jump_location + 2) as *mut i32, offset_value); }
+}
+
+/// Calls the subroutine at `anchor` unless no CallObserver is installed
+#[inline]
+fn emit_call_observer_call(jit: &mut JitCompiler, anchor: usize) {
+    // if(CallObserver == 0) skip the call
+    emit_ins(jit, X86Instruction::cmp_immediate(OperandSize::S64, RBP, 0, Some(X86IndirectAccess::Offset(slot_on_environment_stack(jit, EnvironmentStackSlot::CallObserver)))));
+    // The conditional jump is 6 bytes long, but emit_ins() can append a noop behind it
+    let jump_location = jit.offset_in_text_section;
+    emit_ins(jit, X86Instruction::conditional_jump_immediate(0x84, 0));
+    emit_ins(jit, X86Instruction::call_immediate(jit.relative_to_anchor(anchor, 5)));
+    // Patch the forward jump now that the length of the call is known
+    let offset_value = (jit.offset_in_text_section - (jump_location + 6)) as i32;
+    unsafe { ptr::write_unaligned(jit.result.text_section.as_ptr().add(jump_location + 2) as *mut i32, offset_value); }
+}
+
+#[inline]
+/// `frame_size` is only given if the frames are sized per function, see `Executable::set_stack_frame_size()`
+fn emit_bpf_call(jit: &mut JitCompiler, dst: Value, frame_size: Option<usize>) {
     // Store PC in case the bounds check fails
     emit_ins(jit, X86Instruction::load_immediate(OperandSize::S64, R11, jit.pc as i64));
 
     emit_ins(jit, X86Instruction::call_immediate(jit.relative_to_anchor(ANCHOR_BPF_CALL_PROLOGUE, 5)));
+    if let Some(frame_size) = frame_size {
+        emit_ins(jit, X86Instruction::load_immediate(OperandSize::S64, REGISTER_MAP[FRAME_PTR_REG], frame_size as i64));
+        emit_ins(jit, X86Instruction::call_immediate(jit.relative_to_anchor(ANCHOR_BPF_CALL_SIZED_FRAME, 5)));
+    }
 
     match dst {
         Value::Register(reg) => {
@@ -560,6 +1184,7 @@ fn emit_bpf_call(jit: &mut JitCompiler, dst: Value) {
             emit_ins(jit, X86Instruction::call_immediate(jit.relative_to_anchor(ANCHOR_BPF_CALL_REG, 5)));
 
             emit_validate_and_profile_instruction_count(jit, false, None);
+            emit_call_observer_call(jit, ANCHOR_CALLX_OBSERVER_ENTER);
             emit_ins(jit, X86Instruction::mov(OperandSize::S64, REGISTER_MAP[0], R11)); // Save target_pc
             emit_ins(jit, X86Instruction::pop(REGISTER_MAP[0])); // Restore RAX
             emit_ins(jit, X86Instruction::call_reg(R11, None)); // callq *%r11
@@ -568,6 +1193,7 @@ fn emit_bpf_call(jit: &mut JitCompiler, dst: Value) {
             debug_assert!(!user_provided);
             emit_validate_and_profile_instruction_count(jit, false, Some(target_pc as usize));
             emit_ins(jit, X86Instruction::load_immediate(OperandSize::S64, R11, target_pc));
+            emit_call_observer_call(jit, ANCHOR_CALL_OBSERVER_ENTER);
             let jump_offset = jit.relative_to_target_pc(target_pc as usize, 5);
             emit_ins(jit, X86Instruction::call_immediate(jump_offset));
         },
@@ -657,6 +1283,10 @@ fn emit_rust_call(jit: &mut JitCompiler, dst: Value, arguments: &[Argument], res
                 debug_assert!(!user_provided && !is_stack_argument);
                 emit_ins(jit, X86Instruction::load_immediate(OperandSize::S64, dst, value));
             },
+            Value::HostFunction(function) => {
+                debug_assert!(!is_stack_argument);
+                emit_relocatable_address(jit, dst, jit.host_function_addresses[function as usize], Relocation::HostFunction(function));
+            },
         }
     }
 
@@ -669,6 +1299,10 @@ fn emit_rust_call(jit: &mut JitCompiler, dst: Value, arguments: &[Argument], res
             emit_ins(jit, X86Instruction::load_immediate(OperandSize::S64, RAX, value));
             emit_ins(jit, X86Instruction::call_reg(RAX, None));
         },
+        Value::HostFunction(function) => {
+            emit_relocatable_address(jit, RAX, jit.host_function_addresses[function as usize], Relocation::HostFunction(function));
+            emit_ins(jit, X86Instruction::call_reg(RAX, None));
+        },
         _ => {
             #[cfg(debug_assertions)]
             unreachable!();
@@ -687,6 +1321,9 @@ fn emit_rust_call(jit: &mut JitCompiler, dst: Value, arguments: &[Argument], res
     }
 }
 
+// emit_address_translation() indexes the regions by shifting instead of multiplying
+const _: () = assert!(mem::size_of::<MemoryRegion>().is_power_of_two(), "the size of MemoryRegion must be a power of two");
+
 #[inline]
 fn emit_address_translation(jit: &mut JitCompiler, host_addr: u8, vm_addr: Value, len: u64, access_type: AccessType) {
     match vm_addr {
@@ -710,12 +1347,59 @@ fn emit_address_translation(jit: &mut JitCompiler, host_addr: u8, vm_addr: Value
             unreachable!();
         },
     }
+    if should_skip_bounds_checks(jit) {
+        // R10 = &regions[R11 >> 32]; R11 = R11 - R10.vm_addr + R10.host_addr;
+        emit_ins(jit, X86Instruction::mov(OperandSize::S64, R11, R10));
+        emit_ins(jit, X86Instruction::alu(OperandSize::S64, 0xc1, 5, R10, ebpf::VIRTUAL_ADDRESS_BITS as i64, None));
+        emit_ins(jit, X86Instruction::alu(OperandSize::S64, 0xc1, 4, R10, mem::size_of::<MemoryRegion>().trailing_zeros() as i64, None));
+        emit_ins(jit, X86Instruction::alu(OperandSize::S64, 0x03, R10, RBP, 0, Some(X86IndirectAccess::Offset(slot_on_environment_stack(jit, EnvironmentStackSlot::MemoryRegions)))));
+        emit_ins(jit, X86Instruction::alu(OperandSize::S64, 0x2B, R11, R10, 0, Some(X86IndirectAccess::Offset(mem::offset_of!(MemoryRegion, vm_addr) as i32))));
+        emit_ins(jit, X86Instruction::alu(OperandSize::S64, 0x03, R11, R10, 0, Some(X86IndirectAccess::Offset(mem::offset_of!(MemoryRegion, host_addr) as i32))));
+        emit_ins(jit, X86Instruction::mov(OperandSize::S64, R11, host_addr));
+        return;
+    }
     let anchor = ANCHOR_TRANSLATE_MEMORY_ADDRESS + len.trailing_zeros() as usize + 4 * (access_type as usize);
     emit_ins(jit, X86Instruction::push_immediate(OperandSize::S64, jit.pc as i32));
     emit_ins(jit, X86Instruction::call_immediate(jit.relative_to_anchor(anchor, 5)));
     emit_ins(jit, X86Instruction::mov(OperandSize::S64, R11, host_addr));
 }
 
+/// Read-modify-write of the host address in R11, see `ebpf::ATOMIC_*`
+fn emit_atomic<E: UserDefinedError>(jit: &mut JitCompiler, size: OperandSize, src: u8, imm: i64) -> Result<(), EbpfError<E>> {
+    let memory = Some(X86IndirectAccess::Offset(0));
+    let opcode = match imm & !ebpf::BPF_FETCH {
+        ebpf::ATOMIC_OR => 0x09,
+        ebpf::ATOMIC_AND => 0x21,
+        ebpf::ATOMIC_XOR => 0x31,
+        _ => 0x01,
+    };
+    match imm {
+        ebpf::ATOMIC_ADD | ebpf::ATOMIC_OR | ebpf::ATOMIC_AND | ebpf::ATOMIC_XOR => {
+            emit_ins(jit, X86Instruction::alu(size, opcode, src, R11, 0, memory));
+        },
+        ebpf::ATOMIC_FETCH_ADD => emit_ins(jit, X86Instruction::xadd(size, src, R11, memory)),
+        ebpf::ATOMIC_FETCH_OR | ebpf::ATOMIC_FETCH_AND | ebpf::ATOMIC_FETCH_XOR => {
+            // x86 has no fetching variant of these, so keep the previous value in a temporary register
+            let temp = if src == REGISTER_MAP[0] { REGISTER_MAP[1] } else { REGISTER_MAP[0] };
+            emit_ins(jit, X86Instruction::push(temp, None));
+            emit_ins(jit, X86Instruction::load(size, R11, temp, X86IndirectAccess::Offset(0)));
+            emit_ins(jit, X86Instruction::alu(size, opcode, src, R11, 0, memory));
+            emit_ins(jit, X86Instruction::mov(OperandSize::S64, temp, src));
+            emit_ins(jit, X86Instruction::pop(temp));
+        },
+        ebpf::ATOMIC_XCHG => emit_ins(jit, X86Instruction::xchg(size, src, R11, memory)),
+        ebpf::ATOMIC_CMPXCHG => {
+            emit_ins(jit, X86Instruction::cmpxchg(size, src, R11, memory));
+            if let OperandSize::S32 = size {
+                // A successful cmpxchg does not write EAX, so clear the upper half of r0 explicitly
+                emit_ins(jit, X86Instruction::alu(OperandSize::S32, 0x81, 4, REGISTER_MAP[0], -1, None));
+            }
+        },
+        _ => return Err(EbpfError::InvalidInstruction(jit.pc + ebpf::ELF_INSN_DUMP_OFFSET)),
+    }
+    Ok(())
+}
+
 fn emit_shift(jit: &mut JitCompiler, size: OperandSize, opcode_extension: u8, source: u8, destination: u8, immediate: Option<i64>) {
     if let Some(immediate) = immediate {
         if should_sanitize_constant(jit, immediate) {
@@ -761,11 +1445,22 @@ fn emit_muldivmod(jit: &mut JitCompiler, opc: u8, src: u8, dst: u8, imm: Option<
     let modrm = (opc & ebpf::BPF_ALU_OP_MASK) == (ebpf::MOD32_IMM & ebpf::BPF_ALU_OP_MASK);
     let size = if (opc & ebpf::BPF_CLS_MASK) == ebpf::BPF_ALU64 { OperandSize::S64 } else { OperandSize::S32 };
 
+    // Unless the config traps, the exception cases jump forward to where dst is set to a defined value
+    let trap = jit.config.div_by_zero_behavior == DivByZeroBehavior::Trap;
+    let mut exception_case_jumps = Vec::new();
+
     if !mul && imm.is_none() {
-        // Save pc
-        emit_ins(jit, X86Instruction::load_immediate(OperandSize::S64, R11, jit.pc as i64));
+        if trap {
+            // Save pc
+            emit_ins(jit, X86Instruction::load_immediate(OperandSize::S64, R11, jit.pc as i64));
+        }
         emit_ins(jit, X86Instruction::test(size, src, src, None)); // src == 0
-        emit_ins(jit, X86Instruction::conditional_jump_immediate(0x84, jit.relative_to_anchor(ANCHOR_DIV_BY_ZERO, 6)));
+        if trap {
+            emit_ins(jit, X86Instruction::conditional_jump_immediate(0x84, jit.relative_to_anchor(ANCHOR_DIV_BY_ZERO, 6)));
+        } else {
+            exception_case_jumps.push(jit.offset_in_text_section);
+            emit_ins(jit, X86Instruction::conditional_jump_immediate(0x84, 0));
+        }
     }
 
     // sdiv overflows with MIN / -1. If we have an immediate and it's not -1, we
@@ -785,9 +1480,14 @@ fn emit_muldivmod(jit: &mut JitCompiler, opc: u8, src: u8, dst: u8, imm: Option<
             emit_ins(jit, X86Instruction::test(size, R11, R11, None)); // r11 == 0
         }
         
-        // MIN / -1, raise EbpfError::DivideOverflow(pc)
-        emit_ins(jit, X86Instruction::load_immediate(OperandSize::S64, R11, jit.pc as i64));
-        emit_ins(jit, X86Instruction::conditional_jump_immediate(0x84, jit.relative_to_anchor(ANCHOR_DIV_OVERFLOW, 6)));
+        if trap {
+            // MIN / -1, raise EbpfError::DivideOverflow(pc)
+            emit_ins(jit, X86Instruction::load_immediate(OperandSize::S64, R11, jit.pc as i64));
+            emit_ins(jit, X86Instruction::conditional_jump_immediate(0x84, jit.relative_to_anchor(ANCHOR_DIV_OVERFLOW, 6)));
+        } else {
+            exception_case_jumps.push(jit.offset_in_text_section);
+            emit_ins(jit, X86Instruction::conditional_jump_immediate(0x84, 0));
+        }
     }
 
     if dst != RAX {
@@ -837,17 +1537,75 @@ fn emit_muldivmod(jit: &mut JitCompiler, opc: u8, src: u8, dst: u8, imm: Option<
             emit_ins(jit, X86Instruction::alu(OperandSize::S64, 0x63, dst, dst, 0, None)); // sign extend i32 to i64
         }
     }
+
+    if !exception_case_jumps.is_empty() {
+        // The jumps are 6 bytes (5 for the unconditional one) long, but emit_ins() can append a noop behind them
+        let end_jump_location = jit.offset_in_text_section;
+        emit_ins(jit, X86Instruction::jump_immediate(0));
+        for jump_location in exception_case_jumps {
+            let offset_value = (jit.offset_in_text_section - (jump_location + 6)) as i32;
+            unsafe { ptr::write_unaligned(jit.result.text_section.as_ptr().add(jump_location + 2) as *mut i32, offset_value); }
+        }
+        let value = match (jit.config.div_by_zero_behavior, size) {
+            (DivByZeroBehavior::ReturnAllOnes, OperandSize::S32) if !sdiv => u32::MAX as i64,
+            (DivByZeroBehavior::ReturnAllOnes, _) => -1,
+            _ => 0,
+        };
+        emit_ins(jit, X86Instruction::load_immediate(OperandSize::S64, dst, value));
+        let offset_value = (jit.offset_in_text_section - (end_jump_location + 5)) as i32;
+        unsafe { ptr::write_unaligned(jit.result.text_section.as_ptr().add(end_jump_location + 1) as *mut i32, offset_value); }
+    }
+}
+
+fn emit_uhmul(jit: &mut JitCompiler, src: u8, dst: u8, dst_high: u8, imm: Option<i64>) {
+    // mul writes the 128 bit product to (RDX, RAX), so save them unless they receive a half
+    let save_rax = dst != RAX && dst_high != RAX;
+    let save_rdx = dst != RDX && dst_high != RDX;
+    if save_rax {
+        emit_ins(jit, X86Instruction::push(RAX, None));
+    }
+    if save_rdx {
+        emit_ins(jit, X86Instruction::push(RDX, None));
+    }
+
+    if let Some(imm) = imm {
+        if should_sanitize_constant(jit, imm) {
+            emit_sanitized_load_immediate(jit, OperandSize::S64, R11, imm);
+        } else {
+            emit_ins(jit, X86Instruction::load_immediate(OperandSize::S64, R11, imm));
+        }
+    } else {
+        emit_ins(jit, X86Instruction::mov(OperandSize::S64, src, R11));
+    }
+    if dst != RAX {
+        emit_ins(jit, X86Instruction::mov(OperandSize::S64, dst, RAX));
+    }
+    emit_ins(jit, X86Instruction::alu(OperandSize::S64, 0xf7, 4, R11, 0, None)); // (RDX, RAX) = RAX * R11
+
+    // Move the high half out of the way first, as dst might be RDX
+    emit_ins(jit, X86Instruction::mov(OperandSize::S64, RDX, R11));
+    if dst != RAX {
+        emit_ins(jit, X86Instruction::mov(OperandSize::S64, RAX, dst));
+    }
+    emit_ins(jit, X86Instruction::mov(OperandSize::S64, R11, dst_high));
+
+    if save_rdx {
+        emit_ins(jit, X86Instruction::pop(RDX));
+    }
+    if save_rax {
+        emit_ins(jit, X86Instruction::pop(RAX));
+    }
 }
 
-fn emit_set_exception_kind(jit: &mut JitCompiler, err: EbpfError) {
-    let err = ProgramResult::Err(err);
+fn emit_set_exception_kind<C: ContextObject>(jit: &mut JitCompiler, err: EbpfError<C::UserError>) {
+    let err = ProgramResult::<C::UserError>::Err(err);
     let err_kind = unsafe { *(&err as *const _ as *const u64).add(ERR_KIND_OFFSET) };
     emit_ins(jit, X86Instruction::load(OperandSize::S64, RBP, R10, X86IndirectAccess::Offset(slot_on_environment_stack(jit, EnvironmentStackSlot::OptRetValPtr))));
     emit_ins(jit, X86Instruction::store_immediate(OperandSize::S64, R10, X86IndirectAccess::Offset((std::mem::size_of::<u64>() * ERR_KIND_OFFSET) as i32), err_kind as i64));
 }
 
-fn emit_result_is_err(jit: &mut JitCompiler, source: u8, destination: u8, indirect: X86IndirectAccess) {
-    let ok = ProgramResult::Ok(0);
+fn emit_result_is_err<C: ContextObject>(jit: &mut JitCompiler, source: u8, destination: u8, indirect: X86IndirectAccess) {
+    let ok = ProgramResult::<C::UserError>::Ok(0);
     let err_kind = unsafe { *(&ok as *const _ as *const u64).add(ERR_KIND_OFFSET) };
     emit_ins(jit, X86Instruction::load(OperandSize::S64, source, destination, indirect));
     emit_ins(jit, X86Instruction::cmp_immediate(OperandSize::S64, destination, err_kind as i64, Some(X86IndirectAccess::Offset(0))));
@@ -861,6 +1619,7 @@ struct Jump {
 
 pub struct JitCompiler {
     result: JitProgramSections,
+    instruction_offsets: Vec<usize>,
     text_section_jumps: Vec<Jump>,
     offset_in_text_section: usize,
     pc: usize,
@@ -872,6 +1631,10 @@ pub struct JitCompiler {
     diversification_rng: SmallRng,
     stopwatch_is_active: bool,
     environment_stack_key: i32,
+    host_function_addresses: [i64; HOST_FUNCTIONS.len()],
+    relocations: Vec<(usize, Relocation)>,
+    uses_stack_frame_sizes: bool,
+    enable_bounds_checks: bool,
 }
 
 impl Index<usize> for JitCompiler {
@@ -908,7 +1671,7 @@ impl std::fmt::Debug for JitCompiler {
 
 impl JitCompiler {
     // Arguments are unused on windows
-    fn new(program: &[u8], config: &Config) -> Result<Self, EbpfError> {
+    fn new<E: UserDefinedError>(program: &[u8], config: &Config, costly_instructions: usize, function_padding_length: usize) -> Result<Self, EbpfError<E>> {
         #[cfg(target_os = "windows")]
         {
             let _ = program;
@@ -920,9 +1683,46 @@ impl JitCompiler {
         {
             let _ = program;
             let _ = config;
-            panic!("JIT is only supported on x86_64");
+            return Err(EbpfError::UnsupportedJitArch(std::env::consts::ARCH));
         }
 
+        let (pc, code_length_estimate) = Self::code_length_estimate(program, config, costly_instructions, function_padding_length);
+        let result = JitProgramSections::new(pc, code_length_estimate)?;
+        Ok(Self::with_sections(result, pc, config))
+    }
+
+    /// Returns the exact length of the machine code the executable compiles to, without allocating any pages
+    ///
+    /// The machine code is only reproducible if `noop_instruction_rate` is zero and both
+    /// `sanitize_user_provided_values` and `encrypt_environment_registers` are disabled.
+    /// Otherwise every compilation is randomized and the length of one sample is returned.
+    pub fn estimate_code_size<C: ContextObject>(executable: &Executable<C>, config: &Config) -> Result<usize, EbpfError<C::UserError>> {
+        #[cfg(not(target_arch = "x86_64"))]
+        {
+            let _ = executable;
+            let _ = config;
+            return Err(EbpfError::UnsupportedJitArch(std::env::consts::ARCH));
+        }
+
+        let program = executable.get_text_bytes().1;
+        let (pc, code_length_estimate) = Self::code_length_estimate(program, config, count_costly_instructions(executable), function_padding_length(executable, config));
+        let mut pc_section = vec![0usize; pc];
+        let mut text_section = vec![0u8; code_length_estimate];
+        // The heap buffers outlive the compiler, which is dropped first. A page_size of zero skips sealing and unmapping.
+        let result = unsafe {
+            JitProgramSections {
+                page_size: 0,
+                pc_section: std::slice::from_raw_parts_mut(pc_section.as_mut_ptr(), pc_section.len()),
+                text_section: std::slice::from_raw_parts_mut(text_section.as_mut_ptr(), text_section.len()),
+            }
+        };
+        let mut jit = Self::with_sections(result, pc, config);
+        jit.compile::<C>(executable)?;
+        Ok(jit.offset_in_text_section)
+    }
+
+    /// Returns the number of instructions and an upper bound for the length of the machine code
+    fn code_length_estimate(program: &[u8], config: &Config, costly_instructions: usize, function_padding_length: usize) -> (usize, usize) {
         // Scan through program to find actual number of instructions
         let mut pc = 0;
         while (pc + 1) * ebpf::INSN_SIZE <= program.len() {
@@ -940,16 +1740,21 @@ impl JitCompiler {
         if config.instruction_meter_checkpoint_distance != 0 {
             code_length_estimate += pc / config.instruction_meter_checkpoint_distance * MACHINE_CODE_PER_INSTRUCTION_METER_CHECKPOINT;
         }
-        let result = JitProgramSections::new(pc, code_length_estimate)?;
+        code_length_estimate += costly_instructions * MACHINE_CODE_PER_INSTRUCTION_COST;
+        code_length_estimate += function_padding_length;
+        (pc, code_length_estimate)
+    }
 
+    fn with_sections(result: JitProgramSections, pc: usize, config: &Config) -> Self {
         let mut diversification_rng = SmallRng::from_rng(rand::thread_rng()).unwrap();
         let environment_stack_key =
             if config.encrypt_environment_registers {
                 diversification_rng.gen::<i32>() / 16 // -3 bits for 8 Byte alignment, and -1 bit to have encoding space for EnvironmentStackSlot::SlotCount
             } else { 0 };
 
-        Ok(Self {
+        Self {
             result,
+            instruction_offsets: Vec::with_capacity(pc + 1),
             text_section_jumps: vec![],
             offset_in_text_section: 0,
             pc: 0,
@@ -961,24 +1766,39 @@ impl JitCompiler {
             diversification_rng,
             stopwatch_is_active: false,
             environment_stack_key,
-        })
+            host_function_addresses: [0; HOST_FUNCTIONS.len()],
+            relocations: Vec::new(),
+            uses_stack_frame_sizes: false,
+            enable_bounds_checks: true,
+        }
     }
 
     fn compile<C: ContextObject>(&mut self,
-            executable: &Executable<C>) -> Result<(), EbpfError> {
+            executable: &Executable<C>) -> Result<(), EbpfError<C::UserError>> {
         let text_section_base = self.result.text_section.as_ptr();
         let (program_vm_addr, program) = executable.get_text_bytes();
         self.program_vm_addr = program_vm_addr;
+        self.uses_stack_frame_sizes = executable.uses_stack_frame_sizes();
+        for function in HOST_FUNCTIONS {
+            self.host_function_addresses[function as usize] = function.address::<C>();
+        }
 
         self.generate_prologue::<C>(executable)?;
         self.generate_subroutines::<C>()?;
 
+        let padded_function_starts = if self.config.jit_function_padding == 0 { BTreeSet::new() } else { padded_function_starts(executable) };
         while self.pc * ebpf::INSN_SIZE < program.len() {
-            if self.offset_in_text_section + MAX_MACHINE_CODE_LENGTH_PER_INSTRUCTION > self.result.text_section.len() {
+            if padded_function_starts.contains(&self.pc) {
+                self.emit_function_padding()?;
+            }
+            let instruction_cost = executable.get_instruction_cost(self.pc);
+            let machine_code_length = MAX_MACHINE_CODE_LENGTH_PER_INSTRUCTION + if instruction_cost == 1 { 0 } else { MACHINE_CODE_PER_INSTRUCTION_COST };
+            if self.offset_in_text_section + machine_code_length > self.result.text_section.len() {
                 return Err(EbpfError::ExhaustedTextSegment(self.pc));
             }
             let mut insn = ebpf::get_insn_unchecked(program, self.pc);
             self.result.pc_section[self.pc] = unsafe { text_section_base.add(self.offset_in_text_section) } as usize;
+            self.instruction_offsets.resize(self.pc + 1, self.offset_in_text_section);
 
             // Regular instruction meter checkpoints to prevent long linear runs from exceeding their budget
             if self.last_instruction_meter_validation_pc + self.config.instruction_meter_checkpoint_distance <= self.pc {
@@ -991,6 +1811,8 @@ impl JitCompiler {
                 emit_ins(self, X86Instruction::load_immediate(OperandSize::S64, R11, 0));
             }
 
+            emit_instruction_cost(self, instruction_cost)?;
+
             let dst = if insn.dst == STACK_PTR_REG as u8 { u8::MAX } else { REGISTER_MAP[insn.dst as usize] };
             let src = REGISTER_MAP[insn.src as usize];
             let target_pc = (self.pc as isize + insn.off as isize + 1) as usize;
@@ -1009,10 +1831,12 @@ impl JitCompiler {
                 }
 
                 ebpf::LD_DW_IMM  => {
+                    if !ebpf::augment_lddw(program, &mut insn) {
+                        return Err(EbpfError::InvalidInstruction(self.pc + ebpf::ELF_INSN_DUMP_OFFSET));
+                    }
                     emit_validate_and_profile_instruction_count(self, true, Some(self.pc + 2));
                     self.pc += 1;
                     self.result.pc_section[self.pc] = self.anchors[ANCHOR_CALL_UNSUPPORTED_INSTRUCTION] as usize;
-                    ebpf::augment_lddw_unchecked(program, &mut insn);
                     if should_sanitize_constant(self, insn.imm) {
                         emit_sanitized_load_immediate(self, OperandSize::S64, dst, insn.imm);
                     } else {
@@ -1073,6 +1897,14 @@ impl JitCompiler {
                     emit_address_translation(self, R11, Value::RegisterPlusConstant64(dst, insn.off as i64, true), 8, AccessType::Store);
                     emit_ins(self, X86Instruction::store(OperandSize::S64, src, R11, X86IndirectAccess::Offset(0)));
                 },
+                ebpf::ST_W_ATOMIC  => {
+                    emit_address_translation(self, R11, Value::RegisterPlusConstant64(dst, insn.off as i64, true), 4, AccessType::Store);
+                    emit_atomic(self, OperandSize::S32, src, insn.imm)?;
+                },
+                ebpf::ST_DW_ATOMIC => {
+                    emit_address_translation(self, R11, Value::RegisterPlusConstant64(dst, insn.off as i64, true), 8, AccessType::Store);
+                    emit_atomic(self, OperandSize::S64, src, insn.imm)?;
+                },
 
                 // BPF_ALU class
                 ebpf::ADD32_IMM  => {
@@ -1153,6 +1985,10 @@ impl JitCompiler {
                     emit_muldivmod(self, insn.opc, dst, dst, Some(insn.imm)),
                 ebpf::MUL64_REG | ebpf::DIV64_REG | ebpf::SDIV64_REG | ebpf::MOD64_REG  =>
                     emit_muldivmod(self, insn.opc, src, dst, None),
+                ebpf::UHMUL64_IMM if self.config.enable_uhmul =>
+                    emit_uhmul(self, dst, dst, REGISTER_MAP[insn.dst as usize + 1], Some(insn.imm)),
+                ebpf::UHMUL64_REG if self.config.enable_uhmul =>
+                    emit_uhmul(self, src, dst, REGISTER_MAP[insn.dst as usize + 1], None),
                 ebpf::OR64_IMM   => emit_sanitized_alu(self, OperandSize::S64, 0x09, 1, dst, insn.imm),
                 ebpf::OR64_REG   => emit_ins(self, X86Instruction::alu(OperandSize::S64, 0x09, src, dst, 0, None)),
                 ebpf::AND64_IMM  => emit_sanitized_alu(self, OperandSize::S64, 0x21, 4, dst, insn.imm),
@@ -1182,28 +2018,28 @@ impl JitCompiler {
                     let jump_offset = self.relative_to_target_pc(target_pc, 5);
                     emit_ins(self, X86Instruction::jump_immediate(jump_offset));
                 },
-                ebpf::JEQ_IMM    => emit_conditional_branch_imm(self, 0x84, false, insn.imm, dst, target_pc),
-                ebpf::JEQ_REG    => emit_conditional_branch_reg(self, 0x84, false, src, dst, target_pc),
-                ebpf::JGT_IMM    => emit_conditional_branch_imm(self, 0x87, false, insn.imm, dst, target_pc),
-                ebpf::JGT_REG    => emit_conditional_branch_reg(self, 0x87, false, src, dst, target_pc),
-                ebpf::JGE_IMM    => emit_conditional_branch_imm(self, 0x83, false, insn.imm, dst, target_pc),
-                ebpf::JGE_REG    => emit_conditional_branch_reg(self, 0x83, false, src, dst, target_pc),
-                ebpf::JLT_IMM    => emit_conditional_branch_imm(self, 0x82, false, insn.imm, dst, target_pc),
-                ebpf::JLT_REG    => emit_conditional_branch_reg(self, 0x82, false, src, dst, target_pc),
-                ebpf::JLE_IMM    => emit_conditional_branch_imm(self, 0x86, false, insn.imm, dst, target_pc),
-                ebpf::JLE_REG    => emit_conditional_branch_reg(self, 0x86, false, src, dst, target_pc),
-                ebpf::JSET_IMM   => emit_conditional_branch_imm(self, 0x85, true, insn.imm, dst, target_pc),
-                ebpf::JSET_REG   => emit_conditional_branch_reg(self, 0x85, true, src, dst, target_pc),
-                ebpf::JNE_IMM    => emit_conditional_branch_imm(self, 0x85, false, insn.imm, dst, target_pc),
-                ebpf::JNE_REG    => emit_conditional_branch_reg(self, 0x85, false, src, dst, target_pc),
-                ebpf::JSGT_IMM   => emit_conditional_branch_imm(self, 0x8f, false, insn.imm, dst, target_pc),
-                ebpf::JSGT_REG   => emit_conditional_branch_reg(self, 0x8f, false, src, dst, target_pc),
-                ebpf::JSGE_IMM   => emit_conditional_branch_imm(self, 0x8d, false, insn.imm, dst, target_pc),
-                ebpf::JSGE_REG   => emit_conditional_branch_reg(self, 0x8d, false, src, dst, target_pc),
-                ebpf::JSLT_IMM   => emit_conditional_branch_imm(self, 0x8c, false, insn.imm, dst, target_pc),
-                ebpf::JSLT_REG   => emit_conditional_branch_reg(self, 0x8c, false, src, dst, target_pc),
-                ebpf::JSLE_IMM   => emit_conditional_branch_imm(self, 0x8e, false, insn.imm, dst, target_pc),
-                ebpf::JSLE_REG   => emit_conditional_branch_reg(self, 0x8e, false, src, dst, target_pc),
+                ebpf::JEQ_IMM    => emit_conditional_branch_imm(self, OperandSize::S64, 0x84, false, insn.imm, dst, target_pc),
+                ebpf::JEQ_REG    => emit_conditional_branch_reg(self, OperandSize::S64, 0x84, false, src, dst, target_pc),
+                ebpf::JGT_IMM    => emit_conditional_branch_imm(self, OperandSize::S64, 0x87, false, insn.imm, dst, target_pc),
+                ebpf::JGT_REG    => emit_conditional_branch_reg(self, OperandSize::S64, 0x87, false, src, dst, target_pc),
+                ebpf::JGE_IMM    => emit_conditional_branch_imm(self, OperandSize::S64, 0x83, false, insn.imm, dst, target_pc),
+                ebpf::JGE_REG    => emit_conditional_branch_reg(self, OperandSize::S64, 0x83, false, src, dst, target_pc),
+                ebpf::JLT_IMM    => emit_conditional_branch_imm(self, OperandSize::S64, 0x82, false, insn.imm, dst, target_pc),
+                ebpf::JLT_REG    => emit_conditional_branch_reg(self, OperandSize::S64, 0x82, false, src, dst, target_pc),
+                ebpf::JLE_IMM    => emit_conditional_branch_imm(self, OperandSize::S64, 0x86, false, insn.imm, dst, target_pc),
+                ebpf::JLE_REG    => emit_conditional_branch_reg(self, OperandSize::S64, 0x86, false, src, dst, target_pc),
+                ebpf::JSET_IMM   => emit_conditional_branch_imm(self, OperandSize::S64, 0x85, true, insn.imm, dst, target_pc),
+                ebpf::JSET_REG   => emit_conditional_branch_reg(self, OperandSize::S64, 0x85, true, src, dst, target_pc),
+                ebpf::JNE_IMM    => emit_conditional_branch_imm(self, OperandSize::S64, 0x85, false, insn.imm, dst, target_pc),
+                ebpf::JNE_REG    => emit_conditional_branch_reg(self, OperandSize::S64, 0x85, false, src, dst, target_pc),
+                ebpf::JSGT_IMM   => emit_conditional_branch_imm(self, OperandSize::S64, 0x8f, false, insn.imm, dst, target_pc),
+                ebpf::JSGT_REG   => emit_conditional_branch_reg(self, OperandSize::S64, 0x8f, false, src, dst, target_pc),
+                ebpf::JSGE_IMM   => emit_conditional_branch_imm(self, OperandSize::S64, 0x8d, false, insn.imm, dst, target_pc),
+                ebpf::JSGE_REG   => emit_conditional_branch_reg(self, OperandSize::S64, 0x8d, false, src, dst, target_pc),
+                ebpf::JSLT_IMM   => emit_conditional_branch_imm(self, OperandSize::S64, 0x8c, false, insn.imm, dst, target_pc),
+                ebpf::JSLT_REG   => emit_conditional_branch_reg(self, OperandSize::S64, 0x8c, false, src, dst, target_pc),
+                ebpf::JSLE_IMM   => emit_conditional_branch_imm(self, OperandSize::S64, 0x8e, false, insn.imm, dst, target_pc),
+                ebpf::JSLE_REG   => emit_conditional_branch_reg(self, OperandSize::S64, 0x8e, false, src, dst, target_pc),
                 ebpf::CALL_IMM   => {
                     // For JIT, syscalls MUST be registered at compile time. They can be
                     // updated later, but not created after compiling (we need the address of the
@@ -1218,10 +2054,19 @@ impl JitCompiler {
 
                     if syscalls {
                         if let Some(syscall) = executable.get_syscall_registry().lookup_syscall(insn.imm as u32) {
+                            if self.config.enable_syscall_sealing {
+                                emit_rust_call(self, Value::HostFunction(HostFunction::SyscallsSealed), &[
+                                    Argument { index: 0, value: Value::RegisterIndirect(RBP, slot_on_environment_stack(self, EnvironmentStackSlot::ContextObject), false) },
+                                ], Some(R11));
+                                emit_ins(self, X86Instruction::test(OperandSize::S64, R11, R11, None));
+                                emit_ins(self, X86Instruction::load_immediate(OperandSize::S64, R11, self.pc as i64));
+                                emit_ins(self, X86Instruction::conditional_jump_immediate(0x85, self.relative_to_anchor(ANCHOR_SYSCALLS_SEALED, 6)));
+                            }
                             if self.config.enable_instruction_meter {
                                 emit_validate_and_profile_instruction_count(self, true, Some(0));
                             }
-                            emit_ins(self, X86Instruction::load_immediate(OperandSize::S64, R11, syscall as *const SyscallFunction<*mut ()> as i64));
+                            emit_relocatable_address(self, R11, syscall as *const u8 as i64, Relocation::Syscall(insn.imm as u32));
+                            emit_ins(self, X86Instruction::load_immediate(OperandSize::S64, REGISTER_MAP[0], insn.imm as u32 as i64)); // Syscall number for the SyscallObserver
                             emit_ins(self, X86Instruction::call_immediate(self.relative_to_anchor(ANCHOR_SYSCALL, 5)));
                             if self.config.enable_instruction_meter {
                                 emit_undo_profile_instruction_count(self, 0);
@@ -1232,7 +2077,8 @@ impl JitCompiler {
 
                     if calls {
                         if let Some(target_pc) = executable.lookup_bpf_function(insn.imm as u32) {
-                            emit_bpf_call(self, Value::Constant64(target_pc as i64, false));
+                            let frame_size = executable.uses_stack_frame_sizes().then(|| executable.call_frame_size(Some(target_pc)));
+                            emit_bpf_call(self, Value::Constant64(target_pc as i64, false), frame_size);
                             resolved = true;
                         }
                     }
@@ -1243,7 +2089,8 @@ impl JitCompiler {
                     }
                 },
                 ebpf::CALL_REG  => {
-                    emit_bpf_call(self, Value::Register(REGISTER_MAP[insn.imm as usize]));
+                    let frame_size = executable.uses_stack_frame_sizes().then(|| executable.call_frame_size(None));
+                    emit_bpf_call(self, Value::Register(REGISTER_MAP[insn.imm as usize]), frame_size);
                 },
                 ebpf::EXIT      => {
                     let call_depth_access = X86IndirectAccess::Offset(slot_on_environment_stack(self, EnvironmentStackSlot::CallDepth));
@@ -1263,22 +2110,52 @@ impl JitCompiler {
 
                     // and return
                     emit_validate_and_profile_instruction_count(self, false, Some(0));
+                    emit_call_observer_call(self, ANCHOR_CALL_OBSERVER_RETURN);
                     emit_ins(self, X86Instruction::return_near());
                 },
 
+                // BPF_JMP32 class
+                ebpf::JEQ32_IMM  => emit_conditional_branch_imm(self, OperandSize::S32, 0x84, false, insn.imm, dst, target_pc),
+                ebpf::JEQ32_REG  => emit_conditional_branch_reg(self, OperandSize::S32, 0x84, false, src, dst, target_pc),
+                ebpf::JGT32_IMM  => emit_conditional_branch_imm(self, OperandSize::S32, 0x87, false, insn.imm, dst, target_pc),
+                ebpf::JGT32_REG  => emit_conditional_branch_reg(self, OperandSize::S32, 0x87, false, src, dst, target_pc),
+                ebpf::JGE32_IMM  => emit_conditional_branch_imm(self, OperandSize::S32, 0x83, false, insn.imm, dst, target_pc),
+                ebpf::JGE32_REG  => emit_conditional_branch_reg(self, OperandSize::S32, 0x83, false, src, dst, target_pc),
+                ebpf::JLT32_IMM  => emit_conditional_branch_imm(self, OperandSize::S32, 0x82, false, insn.imm, dst, target_pc),
+                ebpf::JLT32_REG  => emit_conditional_branch_reg(self, OperandSize::S32, 0x82, false, src, dst, target_pc),
+                ebpf::JLE32_IMM  => emit_conditional_branch_imm(self, OperandSize::S32, 0x86, false, insn.imm, dst, target_pc),
+                ebpf::JLE32_REG  => emit_conditional_branch_reg(self, OperandSize::S32, 0x86, false, src, dst, target_pc),
+                ebpf::JSET32_IMM => emit_conditional_branch_imm(self, OperandSize::S32, 0x85, true, insn.imm, dst, target_pc),
+                ebpf::JSET32_REG => emit_conditional_branch_reg(self, OperandSize::S32, 0x85, true, src, dst, target_pc),
+                ebpf::JNE32_IMM  => emit_conditional_branch_imm(self, OperandSize::S32, 0x85, false, insn.imm, dst, target_pc),
+                ebpf::JNE32_REG  => emit_conditional_branch_reg(self, OperandSize::S32, 0x85, false, src, dst, target_pc),
+                ebpf::JSGT32_IMM => emit_conditional_branch_imm(self, OperandSize::S32, 0x8f, false, insn.imm, dst, target_pc),
+                ebpf::JSGT32_REG => emit_conditional_branch_reg(self, OperandSize::S32, 0x8f, false, src, dst, target_pc),
+                ebpf::JSGE32_IMM => emit_conditional_branch_imm(self, OperandSize::S32, 0x8d, false, insn.imm, dst, target_pc),
+                ebpf::JSGE32_REG => emit_conditional_branch_reg(self, OperandSize::S32, 0x8d, false, src, dst, target_pc),
+                ebpf::JSLT32_IMM => emit_conditional_branch_imm(self, OperandSize::S32, 0x8c, false, insn.imm, dst, target_pc),
+                ebpf::JSLT32_REG => emit_conditional_branch_reg(self, OperandSize::S32, 0x8c, false, src, dst, target_pc),
+                ebpf::JSLE32_IMM => emit_conditional_branch_imm(self, OperandSize::S32, 0x8e, false, insn.imm, dst, target_pc),
+                ebpf::JSLE32_REG => emit_conditional_branch_reg(self, OperandSize::S32, 0x8e, false, src, dst, target_pc),
+
                 _               => return Err(EbpfError::UnsupportedInstruction(self.pc + ebpf::ELF_INSN_DUMP_OFFSET)),
             }
 
             self.pc += 1;
         }
 
+        if self.config.jit_function_padding != 0 {
+            self.emit_function_padding()?;
+        }
+        self.instruction_offsets.resize(self.pc + 1, self.offset_in_text_section);
+
         // Bumper in case there was no final exit
         if self.offset_in_text_section + MAX_MACHINE_CODE_LENGTH_PER_INSTRUCTION > self.result.text_section.len() {
             return Err(EbpfError::ExhaustedTextSegment(self.pc));
         }        
         emit_validate_and_profile_instruction_count(self, true, Some(self.pc + 2));
         emit_ins(self, X86Instruction::load_immediate(OperandSize::S64, R11, self.pc as i64));
-        emit_set_exception_kind(self, EbpfError::ExecutionOverrun(0));
+        emit_set_exception_kind::<C>(self, EbpfError::ExecutionOverrun(0));
         emit_ins(self, X86Instruction::jump_immediate(self.relative_to_anchor(ANCHOR_EXCEPTION_AT, 5)));
 
         self.resolve_jumps(executable);
@@ -1290,7 +2167,20 @@ impl JitCompiler {
         Ok(())
     }
 
-    fn generate_prologue<C: ContextObject>(&mut self, executable: &Executable<C>) -> Result<(), EbpfError> {
+    /// Reserves `Config::jit_function_padding` bytes of no-ops behind a function
+    ///
+    /// No-ops and not int3 because a function may fall through into the next one.
+    fn emit_function_padding<E: UserDefinedError>(&mut self) -> Result<(), EbpfError<E>> {
+        if self.offset_in_text_section + self.config.jit_function_padding > self.result.text_section.len() {
+            return Err(EbpfError::ExhaustedTextSegment(self.pc));
+        }
+        for _ in 0..self.config.jit_function_padding {
+            emit::<u8>(self, 0x90);
+        }
+        Ok(())
+    }
+
+    fn generate_prologue<C: ContextObject>(&mut self, executable: &Executable<C>) -> Result<(), EbpfError<C::UserError>> {
         // Place the environment on the stack according to EnvironmentStackSlot
 
         // Save CALLEE_SAVED_REGISTERS
@@ -1333,13 +2223,26 @@ impl JitCompiler {
         // Save ContextObject
         emit_ins(self, X86Instruction::push(ARGUMENT_REGISTERS[2], None));
 
-        // Padding / unused
+        // Save SyscallObserver
         emit_ins(self, X86Instruction::push(ARGUMENT_REGISTERS[3], None));
 
+        // Save CallObserver
+        emit_ins(self, X86Instruction::push(ARGUMENT_REGISTERS[4], None));
+
+        // Save LastRegisters
+        emit_ins(self, X86Instruction::push(ARGUMENT_REGISTERS[5], None));
+
         // Save initial value of context_object.get_remaining()
-        emit_rust_call(self, Value::Constant64(C::get_remaining as *const u8 as i64, false), &[
+        emit_rust_call(self, Value::HostFunction(HostFunction::GetRemaining), &[
             Argument { index: 0, value: Value::RegisterIndirect(RBP, slot_on_environment_stack(self, EnvironmentStackSlot::ContextObject), false) },
         ], Some(ARGUMENT_REGISTERS[0]));
+        // Limit the instruction meter to config.max_instructions
+        emit_ins(self, X86Instruction::mov(OperandSize::S64, ARGUMENT_REGISTERS[0], R10));
+        emit_ins(self, X86Instruction::load_immediate(OperandSize::S64, R11, self.config.max_instructions as i64));
+        emit_ins(self, X86Instruction::alu(OperandSize::S64, 0x29, R11, R10, 0, None)); // R10 -= config.max_instructions;
+        emit_ins(self, X86Instruction::load_immediate(OperandSize::S64, R11, 0));
+        emit_ins(self, X86Instruction::cmov(OperandSize::S64, 0x42, R11, R10)); // if R10 < 0 { R10 = 0; }
+        emit_ins(self, X86Instruction::alu(OperandSize::S64, 0x29, R10, ARGUMENT_REGISTERS[0], 0, None)); // instruction_meter -= R10;
         emit_ins(self, X86Instruction::push(ARGUMENT_REGISTERS[0], None));
 
         // Initialize stop watch
@@ -1347,6 +2250,19 @@ impl JitCompiler {
         emit_ins(self, X86Instruction::push(R11, None));
         emit_ins(self, X86Instruction::push(R11, None));
 
+        // Save InstructionMeterOffset
+        emit_ins(self, X86Instruction::push(R10, None));
+
+        if should_skip_bounds_checks(self) {
+            // Save MemoryRegions
+            emit_rust_call(self, Value::HostFunction(HostFunction::MemoryMappingRegions), &[
+                Argument { index: 0, value: Value::RegisterIndirect(RBP, slot_on_environment_stack(self, EnvironmentStackSlot::MemoryMapping), false) },
+            ], Some(R11));
+            emit_ins(self, X86Instruction::push(R11, None));
+        } else {
+            emit_ins(self, X86Instruction::push(R10, None)); // Padding for stack alignment
+        }
+
         // Zero BPF registers
         for reg in REGISTER_MAP.iter() {
             if *reg != REGISTER_MAP[1] && *reg != REGISTER_MAP[FRAME_PTR_REG] {
@@ -1367,15 +2283,12 @@ impl JitCompiler {
         Ok(())
     }
 
-    fn generate_subroutines<C: ContextObject>(&mut self) -> Result<(), EbpfError> {
+    fn generate_subroutines<C: ContextObject>(&mut self) -> Result<(), EbpfError<C::UserError>> {
         // Epilogue
         self.set_anchor(ANCHOR_EPILOGUE);
         // Print stop watch value
-        fn stopwatch_result(numerator: u64, denominator: u64) {
-            println!("Stop watch: {} / {} = {}", numerator, denominator, if denominator == 0 { 0.0 } else { numerator as f64 / denominator as f64 });
-        }
         if self.stopwatch_is_active {
-            emit_rust_call(self, Value::Constant64(stopwatch_result as *const u8 as i64, false), &[
+            emit_rust_call(self, Value::HostFunction(HostFunction::StopwatchResult), &[
                 Argument { index: 1, value: Value::RegisterIndirect(RBP, slot_on_environment_stack(self, EnvironmentStackSlot::StopwatchDenominator), false) },
                 Argument { index: 0, value: Value::RegisterIndirect(RBP, slot_on_environment_stack(self, EnvironmentStackSlot::StopwatchNumerator), false) },
             ], None);
@@ -1400,7 +2313,7 @@ impl JitCompiler {
             }
             emit_ins(self, X86Instruction::mov(OperandSize::S64, RSP, REGISTER_MAP[0]));
             emit_ins(self, X86Instruction::alu(OperandSize::S64, 0x81, 0, RSP, - 8 * 3, None)); // RSP -= 8 * 3;
-            emit_rust_call(self, Value::Constant64(C::trace as *const u8 as i64, false), &[
+            emit_rust_call(self, Value::HostFunction(HostFunction::Trace), &[
                 Argument { index: 1, value: Value::Register(REGISTER_MAP[0]) }, // registers
                 Argument { index: 0, value: Value::RegisterIndirect(RBP, slot_on_environment_stack(self, EnvironmentStackSlot::ContextObject), false) },
             ], None);
@@ -1419,7 +2332,7 @@ impl JitCompiler {
 
         // Handler for EbpfError::ExceededMaxInstructions
         self.set_anchor(ANCHOR_CALL_EXCEEDED_MAX_INSTRUCTIONS);
-        emit_set_exception_kind(self, EbpfError::ExceededMaxInstructions(0, 0));
+        emit_set_exception_kind::<C>(self, EbpfError::ExceededMaxInstructions(0, 0));
         emit_ins(self, X86Instruction::mov(OperandSize::S64, ARGUMENT_REGISTERS[0], R11)); // R11 = instruction_meter;
         emit_profile_instruction_count_finalize(self, true);
         emit_ins(self, X86Instruction::jump_immediate(self.relative_to_anchor(ANCHOR_EPILOGUE, 5)));
@@ -1435,24 +2348,53 @@ impl JitCompiler {
 
         // Handler for EbpfError::CallDepthExceeded
         self.set_anchor(ANCHOR_CALL_DEPTH_EXCEEDED);
-        emit_set_exception_kind(self, EbpfError::CallDepthExceeded(0, 0));
-        emit_ins(self, X86Instruction::store_immediate(OperandSize::S64, R10, X86IndirectAccess::Offset((std::mem::size_of::<u64>() * (ERR_KIND_OFFSET + 2)) as i32), self.config.max_call_depth as i64)); // depth = jit.config.max_call_depth;
+        emit_set_exception_kind::<C>(self, EbpfError::CallDepthExceeded(0, 0));
+        emit_ins(self, X86Instruction::store(OperandSize::S64, REGISTER_MAP[FRAME_PTR_REG], R10, X86IndirectAccess::Offset((std::mem::size_of::<u64>() * (ERR_KIND_OFFSET + 2)) as i32))); // depth = CallDepth;
         emit_ins(self, X86Instruction::jump_immediate(self.relative_to_anchor(ANCHOR_EXCEPTION_AT, 5)));
 
         // Handler for EbpfError::CallOutsideTextSegment
         self.set_anchor(ANCHOR_CALL_OUTSIDE_TEXT_SEGMENT);
-        emit_set_exception_kind(self, EbpfError::CallOutsideTextSegment(0, 0));
+        emit_set_exception_kind::<C>(self, EbpfError::CallOutsideTextSegment(0, 0));
+        emit_ins(self, X86Instruction::store(OperandSize::S64, REGISTER_MAP[0], R10, X86IndirectAccess::Offset((std::mem::size_of::<u64>() * (ERR_KIND_OFFSET + 2)) as i32))); // target_address = RAX;
+        emit_ins(self, X86Instruction::jump_immediate(self.relative_to_anchor(ANCHOR_EXCEPTION_AT, 5)));
+
+        // Handler for EbpfError::CallxUnknownTarget
+        self.set_anchor(ANCHOR_CALLX_UNKNOWN_TARGET);
+        emit_set_exception_kind::<C>(self, EbpfError::CallxUnknownTarget(0, 0));
         emit_ins(self, X86Instruction::store(OperandSize::S64, REGISTER_MAP[0], R10, X86IndirectAccess::Offset((std::mem::size_of::<u64>() * (ERR_KIND_OFFSET + 2)) as i32))); // target_address = RAX;
         emit_ins(self, X86Instruction::jump_immediate(self.relative_to_anchor(ANCHOR_EXCEPTION_AT, 5)));
 
+        // Recover the target address and the pc of callx (which were saved in ANCHOR_BPF_CALL_REG)
+        self.set_anchor(ANCHOR_CALLX_NOT_A_FUNCTION);
+        emit_ins(self, X86Instruction::load(OperandSize::S64, RSP, REGISTER_MAP[0], X86IndirectAccess::OffsetIndexShift(-8, RSP, 0))); // RAX = RSP[-8];
+        emit_ins(self, X86Instruction::alu(OperandSize::S64, 0xc1, 4, REGISTER_MAP[0], INSN_SIZE.trailing_zeros() as i64, None)); // RAX *= INSN_SIZE;
+        emit_ins(self, X86Instruction::load_immediate(OperandSize::S64, R11, self.program_vm_addr as i64));
+        emit_ins(self, X86Instruction::alu(OperandSize::S64, 0x01, R11, REGISTER_MAP[0], 0, None)); // RAX += self.program_vm_addr;
+        emit_ins(self, X86Instruction::load(OperandSize::S64, RSP, R11, X86IndirectAccess::OffsetIndexShift(-16, RSP, 0))); // R11 = RSP[-16];
+        if !self.config.reject_callx_to_unaligned {
+            emit_ins(self, X86Instruction::jump_immediate(self.relative_to_anchor(ANCHOR_CALLX_UNKNOWN_TARGET, 5)));
+        }
+        // emit_ins(self, X86Instruction::jump_immediate(self.relative_to_anchor(ANCHOR_CALLX_INVALID_TARGET, 5))); // Fall-through
+
+        // Handler for EbpfError::InvalidCallxTarget
+        self.set_anchor(ANCHOR_CALLX_INVALID_TARGET);
+        emit_set_exception_kind::<C>(self, EbpfError::InvalidCallxTarget(0, 0));
+        emit_ins(self, X86Instruction::store(OperandSize::S64, REGISTER_MAP[0], R10, X86IndirectAccess::Offset((std::mem::size_of::<u64>() * (ERR_KIND_OFFSET + 2)) as i32))); // target_address = RAX;
+        emit_ins(self, X86Instruction::jump_immediate(self.relative_to_anchor(ANCHOR_EXCEPTION_AT, 5)));
+
+        // Handler for EbpfError::SyscallsSealed
+        self.set_anchor(ANCHOR_SYSCALLS_SEALED);
+        emit_set_exception_kind::<C>(self, EbpfError::SyscallsSealed(0));
+        emit_ins(self, X86Instruction::jump_immediate(self.relative_to_anchor(ANCHOR_EXCEPTION_AT, 5)));
+
         // Handler for EbpfError::DivideByZero
         self.set_anchor(ANCHOR_DIV_BY_ZERO);
-        emit_set_exception_kind(self, EbpfError::DivideByZero(0));
+        emit_set_exception_kind::<C>(self, EbpfError::DivideByZero(0));
         emit_ins(self, X86Instruction::jump_immediate(self.relative_to_anchor(ANCHOR_EXCEPTION_AT, 5)));
 
         // Handler for EbpfError::DivideOverflow
         self.set_anchor(ANCHOR_DIV_OVERFLOW);
-        emit_set_exception_kind(self, EbpfError::DivideOverflow(0));
+        emit_set_exception_kind::<C>(self, EbpfError::DivideOverflow(0));
         emit_ins(self, X86Instruction::jump_immediate(self.relative_to_anchor(ANCHOR_EXCEPTION_AT, 5)));
 
         // Handler for EbpfError::UnsupportedInstruction
@@ -1466,13 +2408,18 @@ impl JitCompiler {
         if self.config.enable_instruction_tracing {
             emit_ins(self, X86Instruction::call_immediate(self.relative_to_anchor(ANCHOR_TRACE, 5)));
         }
-        emit_set_exception_kind(self, EbpfError::UnsupportedInstruction(0));
+        emit_set_exception_kind::<C>(self, EbpfError::UnsupportedInstruction(0));
         emit_ins(self, X86Instruction::jump_immediate(self.relative_to_anchor(ANCHOR_EXCEPTION_AT, 5)));
 
         // Quit gracefully
         self.set_anchor(ANCHOR_EXIT);
         emit_validate_instruction_count(self, false, None);
         emit_profile_instruction_count_finalize(self, false);
+        // Spill the BPF registers into LastRegisters
+        emit_ins(self, X86Instruction::load(OperandSize::S64, RBP, R10, X86IndirectAccess::Offset(slot_on_environment_stack(self, EnvironmentStackSlot::LastRegisters))));
+        for (i, reg) in REGISTER_MAP.iter().enumerate() {
+            emit_ins(self, X86Instruction::store(OperandSize::S64, *reg, R10, X86IndirectAccess::Offset((std::mem::size_of::<u64>() * i) as i32))); // last_registers[i] = reg;
+        }
         emit_ins(self, X86Instruction::load(OperandSize::S64, RBP, R10, X86IndirectAccess::Offset(slot_on_environment_stack(self, EnvironmentStackSlot::OptRetValPtr))));
         emit_ins(self, X86Instruction::store(OperandSize::S64, REGISTER_MAP[0], R10, X86IndirectAccess::Offset(8))); // result.return_value = R0;
         emit_ins(self, X86Instruction::load_immediate(OperandSize::S64, REGISTER_MAP[0], 0));
@@ -1480,16 +2427,24 @@ impl JitCompiler {
 
         // Routine for syscall
         self.set_anchor(ANCHOR_SYSCALL);
-        emit_ins(self, X86Instruction::push(R11, None)); // Padding for stack alignment
+        emit_ins(self, X86Instruction::push(R11, None)); // Save the syscall function, also padding for stack alignment
         if self.config.enable_instruction_meter {
             // RDI = *PrevInsnMeter - RDI;
             emit_ins(self, X86Instruction::alu(OperandSize::S64, 0x2B, ARGUMENT_REGISTERS[0], RBP, 0, Some(X86IndirectAccess::Offset(slot_on_environment_stack(self, EnvironmentStackSlot::PrevInsnMeter))))); // RDI -= *PrevInsnMeter;
             emit_ins(self, X86Instruction::alu(OperandSize::S64, 0xf7, 3, ARGUMENT_REGISTERS[0], 0, None)); // RDI = -RDI;
-            emit_rust_call(self, Value::Constant64(C::consume as *const u8 as i64, false), &[
+            emit_rust_call(self, Value::HostFunction(HostFunction::Consume), &[
                 Argument { index: 1, value: Value::Register(ARGUMENT_REGISTERS[0]) },
                 Argument { index: 0, value: Value::RegisterIndirect(RBP, slot_on_environment_stack(self, EnvironmentStackSlot::ContextObject), false) },
             ], None);
         }
+        // if(SyscallObserver.replace_syscall()) skip the syscall function
+        emit_ins(self, X86Instruction::load_immediate(OperandSize::S64, R11, 0));
+        emit_syscall_observer_call(self, HostFunction::SyscallObserverEnter, Some(R11));
+        emit_ins(self, X86Instruction::test(OperandSize::S64, R11, R11, None));
+        emit_ins(self, X86Instruction::load(OperandSize::S64, RSP, R11, X86IndirectAccess::OffsetIndexShift(0, RSP, 0))); // Restore the syscall function
+        // The conditional jump is 6 bytes long, but emit_ins() can append a noop behind it
+        let replaced_jump_location = self.offset_in_text_section;
+        emit_ins(self, X86Instruction::conditional_jump_immediate(0x85, 0));
         emit_rust_call(self, Value::Register(R11), &[
             Argument { index: 7, value: Value::RegisterIndirect(RBP, slot_on_environment_stack(self, EnvironmentStackSlot::OptRetValPtr), false) },
             Argument { index: 6, value: Value::RegisterIndirect(RBP, slot_on_environment_stack(self, EnvironmentStackSlot::MemoryMapping), false) },
@@ -1500,15 +2455,22 @@ impl JitCompiler {
             Argument { index: 1, value: Value::Register(ARGUMENT_REGISTERS[1]) },
             Argument { index: 0, value: Value::RegisterIndirect(RBP, slot_on_environment_stack(self, EnvironmentStackSlot::ContextObject), false) },
         ], None);
+        // Patch the forward jump now that the length of the call is known
+        let offset_value = (self.offset_in_text_section - (replaced_jump_location + 6)) as i32;
+        unsafe { ptr::write_unaligned(self.result.text_section.as_ptr().add(replaced_jump_location + 2) as *mut i32, offset_value); }
         if self.config.enable_instruction_meter {
-            emit_rust_call(self, Value::Constant64(C::get_remaining as *const u8 as i64, false), &[
+            emit_rust_call(self, Value::HostFunction(HostFunction::GetRemaining), &[
                 Argument { index: 0, value: Value::RegisterIndirect(RBP, slot_on_environment_stack(self, EnvironmentStackSlot::ContextObject), false) },
             ], Some(ARGUMENT_REGISTERS[0]));
+            emit_ins(self, X86Instruction::alu(OperandSize::S64, 0x2B, ARGUMENT_REGISTERS[0], RBP, 0, Some(X86IndirectAccess::Offset(slot_on_environment_stack(self, EnvironmentStackSlot::InstructionMeterOffset))))); // RDI -= *InstructionMeterOffset;
+            emit_ins(self, X86Instruction::load_immediate(OperandSize::S64, R11, 0));
+            emit_ins(self, X86Instruction::cmov(OperandSize::S64, 0x42, R11, ARGUMENT_REGISTERS[0])); // if RDI < 0 { RDI = 0; }
             emit_ins(self, X86Instruction::store(OperandSize::S64, ARGUMENT_REGISTERS[0], RBP, X86IndirectAccess::Offset(slot_on_environment_stack(self, EnvironmentStackSlot::PrevInsnMeter))));
         }
+        emit_syscall_observer_call(self, HostFunction::SyscallObserverExit, None);
 
         // Test if result indicates that an error occured
-        emit_result_is_err(self, RBP, R11, X86IndirectAccess::Offset(slot_on_environment_stack(self, EnvironmentStackSlot::OptRetValPtr)));
+        emit_result_is_err::<C>(self, RBP, R11, X86IndirectAccess::Offset(slot_on_environment_stack(self, EnvironmentStackSlot::OptRetValPtr)));
         emit_ins(self, X86Instruction::conditional_jump_immediate(0x85, self.relative_to_anchor(ANCHOR_RUST_EXCEPTION, 6)));
         // Store Ok value in result register
         emit_ins(self, X86Instruction::pop(R11));
@@ -1533,12 +2495,16 @@ impl JitCompiler {
         emit_ins(self, X86Instruction::alu(OperandSize::S64, 0x81, 0, RBP, 1, Some(call_depth_access)));
         emit_ins(self, X86Instruction::load(OperandSize::S64, RBP, REGISTER_MAP[FRAME_PTR_REG], call_depth_access));
         // If CallDepth == self.config.max_call_depth, stop and return CallDepthExceeded
-        emit_ins(self, X86Instruction::cmp_immediate(OperandSize::S32, REGISTER_MAP[FRAME_PTR_REG], self.config.max_call_depth as i64, None));
-        emit_ins(self, X86Instruction::conditional_jump_immediate(0x83, self.relative_to_anchor(ANCHOR_CALL_DEPTH_EXCEEDED, 6)));
+        if !self.uses_stack_frame_sizes {
+            emit_ins(self, X86Instruction::cmp_immediate(OperandSize::S32, REGISTER_MAP[FRAME_PTR_REG], self.config.max_call_depth as i64, None));
+            emit_ins(self, X86Instruction::conditional_jump_immediate(0x83, self.relative_to_anchor(ANCHOR_CALL_DEPTH_EXCEEDED, 6)));
+        }
 
         // Setup the frame pointer for the new frame. What we do depends on whether we're using dynamic or fixed frames.
         let frame_ptr_access = X86IndirectAccess::Offset(slot_on_environment_stack(self, EnvironmentStackSlot::BpfFramePtr));
-        if self.config.dynamic_stack_frames {
+        if self.uses_stack_frame_sizes {
+            // Frames sized per function are set up by ANCHOR_BPF_CALL_SIZED_FRAME, which is only limited by the stack size
+        } else if self.config.dynamic_stack_frames {
             // When dynamic frames are on, the next frame starts at the end of the current frame
             let stack_ptr_access = X86IndirectAccess::Offset(slot_on_environment_stack(self, EnvironmentStackSlot::BpfStackPtr));
             emit_ins(self, X86Instruction::load(OperandSize::S64, RBP, REGISTER_MAP[FRAME_PTR_REG], stack_ptr_access));
@@ -1549,10 +2515,59 @@ impl JitCompiler {
             emit_ins(self, X86Instruction::alu(OperandSize::S64, 0x81, 0, RBP, stack_frame_size, Some(frame_ptr_access))); // frame_ptr += stack_frame_size;
             emit_ins(self, X86Instruction::load(OperandSize::S64, RBP, REGISTER_MAP[FRAME_PTR_REG], frame_ptr_access)); // Load BpfFramePtr
         }
+        if self.config.clear_stack_on_entry && !self.uses_stack_frame_sizes {
+            // Dynamic frames have no fixed size, so everything below the frame pointer is cleared
+            let frame_size = if self.config.dynamic_stack_frames { self.config.stack_size() } else { self.config.stack_frame_size };
+            emit_rust_call(self, Value::HostFunction(HostFunction::ClearStackFrame), &[
+                Argument { index: 2, value: Value::Constant64(frame_size as i64, false) },
+                Argument { index: 1, value: Value::Register(REGISTER_MAP[FRAME_PTR_REG]) },
+                Argument { index: 0, value: Value::RegisterIndirect(RBP, slot_on_environment_stack(self, EnvironmentStackSlot::MemoryMapping), false) },
+            ], None);
+        }
         emit_ins(self, X86Instruction::return_near());
 
+        // Routine for frames sized per function, REGISTER_MAP[FRAME_PTR_REG] holds the frame size when entering
+        self.set_anchor(ANCHOR_BPF_CALL_SIZED_FRAME);
+        emit_ins(self, X86Instruction::push(R11, None)); // Save the pc
+        emit_ins(self, X86Instruction::push(REGISTER_MAP[FRAME_PTR_REG], None)); // Save the frame size
+        emit_ins(self, X86Instruction::mov(OperandSize::S64, REGISTER_MAP[FRAME_PTR_REG], R11));
+        emit_ins(self, X86Instruction::load(OperandSize::S64, RBP, REGISTER_MAP[FRAME_PTR_REG], frame_ptr_access));
+        emit_ins(self, X86Instruction::alu(OperandSize::S64, 0x01, R11, REGISTER_MAP[FRAME_PTR_REG], 0, None)); // frame_ptr += frame_size;
+        // if(frame_ptr > MM_STACK_START + self.config.stack_size()) throw CALL_DEPTH_EXCEEDED;
+        emit_ins(self, X86Instruction::load_immediate(OperandSize::S64, R11, MM_STACK_START as i64 + self.config.stack_size() as i64));
+        emit_ins(self, X86Instruction::cmp(OperandSize::S64, R11, REGISTER_MAP[FRAME_PTR_REG], None));
+        emit_ins(self, X86Instruction::load(OperandSize::S64, RSP, R11, X86IndirectAccess::OffsetIndexShift(8, RSP, 0))); // Restore the pc
+        let stack_overflow = self.offset_in_text_section;
+        emit_ins(self, X86Instruction::conditional_jump_immediate(0x87, 0));
+        emit_ins(self, X86Instruction::store(OperandSize::S64, REGISTER_MAP[FRAME_PTR_REG], RBP, frame_ptr_access));
+        if self.config.clear_stack_on_entry {
+            emit_ins(self, X86Instruction::load(OperandSize::S64, RSP, R11, X86IndirectAccess::OffsetIndexShift(0, RSP, 0))); // Load the frame size
+            emit_rust_call(self, Value::HostFunction(HostFunction::ClearStackFrame), &[
+                Argument { index: 2, value: Value::Register(R11) },
+                Argument { index: 1, value: Value::Register(REGISTER_MAP[FRAME_PTR_REG]) },
+                Argument { index: 0, value: Value::RegisterIndirect(RBP, slot_on_environment_stack(self, EnvironmentStackSlot::MemoryMapping), false) },
+            ], None);
+            emit_ins(self, X86Instruction::load(OperandSize::S64, RSP, R11, X86IndirectAccess::OffsetIndexShift(8, RSP, 0))); // Restore the pc
+        }
+        emit_ins(self, X86Instruction::alu(OperandSize::S64, 0x81, 0, RSP, 16, None)); // Discard the saved frame size and pc
+        emit_ins(self, X86Instruction::return_near());
+        let jump_offset = (self.offset_in_text_section - stack_overflow - 6) as i32;
+        unsafe { ptr::write_unaligned(self.result.text_section.as_mut_ptr().add(stack_overflow + 2) as *mut i32, jump_offset); }
+        emit_ins(self, X86Instruction::load(OperandSize::S64, RBP, REGISTER_MAP[FRAME_PTR_REG], call_depth_access));
+        emit_ins(self, X86Instruction::jump_immediate(self.relative_to_anchor(ANCHOR_CALL_DEPTH_EXCEEDED, 5)));
+
         // Routine for emit_bpf_call(Value::Register())
         self.set_anchor(ANCHOR_BPF_CALL_REG);
+        let check_callx_target = self.config.reject_callx_to_unaligned || self.config.reject_callx_to_unknown_target;
+        if check_callx_target {
+            // if(RAX & (INSN_SIZE - 1) != 0) throw INVALID_CALLX_TARGET or CALLX_UNKNOWN_TARGET;
+            let anchor = if self.config.reject_callx_to_unaligned { ANCHOR_CALLX_INVALID_TARGET } else { ANCHOR_CALLX_UNKNOWN_TARGET };
+            emit_ins(self, X86Instruction::mov(OperandSize::S64, REGISTER_MAP[0], REGISTER_MAP[FRAME_PTR_REG]));
+            emit_ins(self, X86Instruction::alu(OperandSize::S64, 0x81, 4, REGISTER_MAP[FRAME_PTR_REG], INSN_SIZE as i64 - 1, None));
+            emit_ins(self, X86Instruction::conditional_jump_immediate(0x85, self.relative_to_anchor(anchor, 6)));
+            // Save the pc of callx for ANCHOR_CALLX_NOT_A_FUNCTION
+            emit_ins(self, X86Instruction::store(OperandSize::S64, R11, RSP, X86IndirectAccess::OffsetIndexShift(-16, RSP, 0))); // RSP[-16] = R11;
+        }
         // Force alignment of RAX
         emit_ins(self, X86Instruction::alu(OperandSize::S64, 0x81, 4, REGISTER_MAP[0], !(INSN_SIZE as i64 - 1), None)); // RAX &= !(INSN_SIZE - 1);
         // Upper bound check
@@ -1577,13 +2592,55 @@ impl JitCompiler {
         emit_ins(self, X86Instruction::store(OperandSize::S64, R11, RSP, X86IndirectAccess::OffsetIndexShift(-8, RSP, 0))); // RSP[-8] = R11;
         // Load host target_address from self.result.pc_section
         debug_assert_eq!(INSN_SIZE, 8); // Because the instruction size is also the slot size we do not need to shift the offset
-        emit_ins(self, X86Instruction::load_immediate(OperandSize::S64, REGISTER_MAP[FRAME_PTR_REG], self.result.pc_section.as_ptr() as i64));
+        emit_relocatable_address(self, REGISTER_MAP[FRAME_PTR_REG], self.result.pc_section.as_ptr() as i64, Relocation::PcSection);
         emit_ins(self, X86Instruction::alu(OperandSize::S64, 0x01, REGISTER_MAP[FRAME_PTR_REG], REGISTER_MAP[0], 0, None)); // RAX += self.result.pc_section;
         emit_ins(self, X86Instruction::load(OperandSize::S64, REGISTER_MAP[0], REGISTER_MAP[0], X86IndirectAccess::Offset(0))); // RAX = self.result.pc_section[RAX / 8];
+        if check_callx_target {
+            // Targets which are not a function start are patched to ANCHOR_CALLX_UNSUPPORTED_INSTRUCTION in resolve_jumps()
+            // if(RAX == ANCHOR_CALLX_UNSUPPORTED_INSTRUCTION) throw INVALID_CALLX_TARGET or CALLX_UNKNOWN_TARGET;
+            emit_relocatable_address(self, REGISTER_MAP[FRAME_PTR_REG], self.anchors[ANCHOR_CALLX_UNSUPPORTED_INSTRUCTION] as i64, Relocation::TextSection);
+            emit_ins(self, X86Instruction::cmp(OperandSize::S64, REGISTER_MAP[FRAME_PTR_REG], REGISTER_MAP[0], None));
+            emit_ins(self, X86Instruction::conditional_jump_immediate(0x84, self.relative_to_anchor(ANCHOR_CALLX_NOT_A_FUNCTION, 6)));
+        }
         // Load the frame pointer again since we've clobbered REGISTER_MAP[FRAME_PTR_REG]
         emit_ins(self, X86Instruction::load(OperandSize::S64, RBP, REGISTER_MAP[FRAME_PTR_REG], X86IndirectAccess::Offset(slot_on_environment_stack(self, EnvironmentStackSlot::BpfFramePtr))));
         emit_ins(self, X86Instruction::return_near());
 
+        // Routines for CallObserver, R11 holds the target pc when entering a call
+        self.set_anchor(ANCHOR_CALL_OBSERVER_ENTER);
+        emit_rust_call(self, Value::HostFunction(HostFunction::CallObserverEnter), &[
+            Argument { index: 1, value: Value::Register(R11) },
+            Argument { index: 0, value: Value::RegisterIndirect(RBP, slot_on_environment_stack(self, EnvironmentStackSlot::CallObserver), false) },
+        ], None);
+        emit_ins(self, X86Instruction::return_near());
+        // Same as ANCHOR_CALL_OBSERVER_ENTER, but RAX holds the host target address of callx on top of the saved RAX
+        self.set_anchor(ANCHOR_CALLX_OBSERVER_ENTER);
+        if self.config.static_syscalls && !check_callx_target {
+            // Targets which are not a function start throw before the call is observed
+            // if(RAX == ANCHOR_CALLX_UNSUPPORTED_INSTRUCTION) throw UNSUPPORTED_INSTRUCTION;
+            emit_relocatable_address(self, R10, self.anchors[ANCHOR_CALLX_UNSUPPORTED_INSTRUCTION] as i64, Relocation::TextSection);
+            emit_ins(self, X86Instruction::cmp(OperandSize::S64, R10, REGISTER_MAP[0], None));
+            emit_ins(self, X86Instruction::conditional_jump_immediate(0x85, self.relative_to_anchor(ANCHOR_CALL_OBSERVER_ENTER, 6)));
+            // Restore RAX and leave the same stack layout as a call to the target would
+            emit_ins(self, X86Instruction::pop(R10));
+            emit_ins(self, X86Instruction::pop(REGISTER_MAP[0]));
+            emit_ins(self, X86Instruction::push(R10, None));
+            emit_ins(self, X86Instruction::jump_immediate(self.relative_to_anchor(ANCHOR_CALL_UNSUPPORTED_INSTRUCTION, 5)));
+        } else {
+            emit_ins(self, X86Instruction::jump_immediate(self.relative_to_anchor(ANCHOR_CALL_OBSERVER_ENTER, 5)));
+        }
+        self.set_anchor(ANCHOR_CALL_OBSERVER_RETURN);
+        emit_rust_call(self, Value::HostFunction(HostFunction::CallObserverReturn), &[
+            Argument { index: 0, value: Value::RegisterIndirect(RBP, slot_on_environment_stack(self, EnvironmentStackSlot::CallObserver), false) },
+        ], None);
+        emit_ins(self, X86Instruction::return_near());
+
+        // Loads which fail to translate read from here
+        if self.config.oob_read_behavior == OobReadBehavior::ReturnZero {
+            self.set_anchor(ANCHOR_OOB_READ_ZEROES);
+            emit::<u64>(self, 0);
+        }
+
         // Translates a vm memory address to a host memory address
         for (access_type, len) in &[
             (AccessType::Load, 1i32),
@@ -1597,8 +2654,23 @@ impl JitCompiler {
         ] {
             let target_offset = len.trailing_zeros() as usize + 4 * (*access_type as usize);
             self.set_anchor(ANCHOR_TRANSLATE_MEMORY_ADDRESS + target_offset);
+            if self.config.enforce_aligned_access && *len > 1 {
+                // if(R11 % len == 0) skip the exception
+                emit_ins(self, X86Instruction::test_immediate(OperandSize::S64, R11, *len as i64 - 1, None));
+                // The conditional jump is 6 bytes long, but emit_ins() can append a noop behind it
+                let jump_location = self.offset_in_text_section;
+                emit_ins(self, X86Instruction::conditional_jump_immediate(0x84, 0));
+                emit_set_exception_kind::<C>(self, EbpfError::UnalignedMemoryAccess(0, 0, 0));
+                emit_ins(self, X86Instruction::store(OperandSize::S64, R11, R10, X86IndirectAccess::Offset((std::mem::size_of::<u64>() * (ERR_KIND_OFFSET + 2)) as i32))); // vm_addr = R11;
+                emit_ins(self, X86Instruction::store_immediate(OperandSize::S64, R10, X86IndirectAccess::Offset((std::mem::size_of::<u64>() * (ERR_KIND_OFFSET + 3)) as i32), *len as i64)); // len = len;
+                emit_ins(self, X86Instruction::pop(R11)); // R11 = return address
+                emit_ins(self, X86Instruction::xchg(OperandSize::S64, R11, RSP, Some(X86IndirectAccess::OffsetIndexShift(0, RSP, 0)))); // Swap return address and self.pc
+                emit_ins(self, X86Instruction::jump_immediate(self.relative_to_anchor(ANCHOR_EXCEPTION_AT, 5)));
+                let offset_value = (self.offset_in_text_section - (jump_location + 6)) as i32;
+                unsafe { ptr::write_unaligned(self.result.text_section.as_ptr().add(jump_location + 2) as *mut i32, offset_value); }
+            }
             // call MemoryMapping::map() storing the result in EnvironmentStackSlot::OptRetValPtr
-            emit_rust_call(self, Value::Constant64(MemoryMapping::map as *const u8 as i64, false), &[
+            emit_rust_call(self, Value::HostFunction(HostFunction::MemoryMappingMap), &[
                 Argument { index: 3, value: Value::Register(R11) }, // Specify first as the src register could be overwritten by other arguments
                 Argument { index: 4, value: Value::Constant64(*len as i64, false) },
                 Argument { index: 2, value: Value::Constant64(*access_type as i64, false) },
@@ -1607,10 +2679,23 @@ impl JitCompiler {
             ], None);
 
             // Throw error if the result indicates one
-            emit_result_is_err(self, RBP, R11, X86IndirectAccess::Offset(slot_on_environment_stack(self, EnvironmentStackSlot::OptRetValPtr)));
+            emit_result_is_err::<C>(self, RBP, R11, X86IndirectAccess::Offset(slot_on_environment_stack(self, EnvironmentStackSlot::OptRetValPtr)));
             emit_ins(self, X86Instruction::pop(R11)); // R11 = self.pc
             emit_ins(self, X86Instruction::xchg(OperandSize::S64, R11, RSP, Some(X86IndirectAccess::OffsetIndexShift(0, RSP, 0)))); // Swap return address and self.pc
-            emit_ins(self, X86Instruction::conditional_jump_immediate(0x85, self.relative_to_anchor(ANCHOR_EXCEPTION_AT, 6)));
+            if *access_type == AccessType::Load && self.config.oob_read_behavior == OobReadBehavior::ReturnZero {
+                // The conditional jump is 6 bytes long, but emit_ins() can append a noop behind it
+                let jump_location = self.offset_in_text_section;
+                emit_ins(self, X86Instruction::conditional_jump_immediate(0x84, 0));
+                // Discard the error and read from ANCHOR_OOB_READ_ZEROES instead
+                emit_ins(self, X86Instruction::load(OperandSize::S64, RBP, R11, X86IndirectAccess::Offset(slot_on_environment_stack(self, EnvironmentStackSlot::OptRetValPtr))));
+                emit_ins(self, X86Instruction::store_immediate(OperandSize::S64, R11, X86IndirectAccess::Offset(0), 0)); // result.is_err = false;
+                emit_relocatable_address(self, R11, self.anchors[ANCHOR_OOB_READ_ZEROES] as i64, Relocation::TextSection);
+                emit_ins(self, X86Instruction::return_near());
+                let offset_value = (self.offset_in_text_section - (jump_location + 6)) as i32;
+                unsafe { ptr::write_unaligned(self.result.text_section.as_ptr().add(jump_location + 2) as *mut i32, offset_value); }
+            } else {
+                emit_ins(self, X86Instruction::conditional_jump_immediate(0x85, self.relative_to_anchor(ANCHOR_EXCEPTION_AT, 6)));
+            }
 
             // unwrap() the host addr into R11
             emit_ins(self, X86Instruction::load(OperandSize::S64, RBP, R11, X86IndirectAccess::Offset(slot_on_environment_stack(self, EnvironmentStackSlot::OptRetValPtr))));
@@ -1659,10 +2744,16 @@ impl JitCompiler {
                 - mem::size_of::<i32>() as i32; // Jump from end of instruction
             unsafe { ptr::write_unaligned(jump.location as *mut i32, offset_value); }
         }
-        // There is no `VerifierError::JumpToMiddleOfLDDW` for `call imm` so patch it here
+        // `VerifierError::JumpToMiddleOfLDDW` can not cover `callx` so patch it here
         let call_unsupported_instruction = self.anchors[ANCHOR_CALL_UNSUPPORTED_INSTRUCTION] as usize;
         let callx_unsupported_instruction = self.anchors[ANCHOR_CALLX_UNSUPPORTED_INSTRUCTION] as usize;
-        if self.config.static_syscalls {
+        if self.config.reject_callx_to_unaligned || self.config.reject_callx_to_unknown_target {
+            for (pc, offset) in self.result.pc_section.iter_mut().enumerate() {
+                if !executable.is_function_start(pc) {
+                    *offset = callx_unsupported_instruction;
+                }
+            }
+        } else if self.config.static_syscalls {
             let mut prev_pc = 0;
             for current_pc in executable.get_function_registry().keys() {
                 if *current_pc as usize >= self.result.pc_section.len() {
@@ -1689,7 +2780,7 @@ impl JitCompiler {
 #[cfg(all(test, target_arch = "x86_64", not(target_os = "windows")))]
 mod tests {
     use super::*;
-    use crate::{syscalls, vm::{SyscallRegistry, TestContextObject, FunctionRegistry}};
+    use crate::{syscalls, vm::{SyscallRegistry, TestContextObject, FunctionRegistry, CostModel}};
     use byteorder::{LittleEndian, ByteOrder};
 
     fn create_mockup_executable(program: &[u8]) -> Executable::<TestContextObject> {
@@ -1715,6 +2806,21 @@ mod tests {
         .unwrap()
     }
 
+    #[test]
+    fn test_register_map_validation() {
+        assert!(is_valid_register_map(&REGISTER_MAP));
+        let mut register_map = REGISTER_MAP;
+        register_map[FRAME_PTR_REG] = REGISTER_MAP[0];
+        assert!(!is_valid_register_map(&register_map));
+        for reserved in RESERVED_REGISTERS {
+            let mut register_map = REGISTER_MAP;
+            register_map[6] = reserved;
+            assert!(!is_valid_register_map(&register_map));
+        }
+        register_map[FRAME_PTR_REG] = 16;
+        assert!(!is_valid_register_map(&register_map));
+    }
+
     #[test]
     fn test_code_length_estimate() {
         const INSTRUCTION_COUNT: usize = 256;
@@ -1737,8 +2843,13 @@ mod tests {
                 LittleEndian::write_u32(&mut prog[pc * ebpf::INSN_SIZE + 4..], match opcode {
                     0x8D => 8,
                     0xD4 | 0xDC => 16,
+                    0xC3 | 0xDB => ebpf::ATOMIC_FETCH_OR as u32,
                     _ => 0xFFFFFFFF,
                 });
+                if opcode == ebpf::LD_DW_IMM && pc % 2 == 1 {
+                    // The second slot only carries the upper half of the immediate
+                    prog[pc * ebpf::INSN_SIZE..pc * ebpf::INSN_SIZE + 4].fill(0);
+                }
             }
             let mut executable = create_mockup_executable(&prog);
             let result = Executable::<TestContextObject>::jit_compile(&mut executable);
@@ -1752,4 +2863,139 @@ mod tests {
             assert!(machine_code_length_per_instruction <= MAX_MACHINE_CODE_LENGTH_PER_INSTRUCTION);
         }
     }
+
+    #[test]
+    fn test_estimate_code_size() {
+        let config = Config {
+            noop_instruction_rate: 0,
+            sanitize_user_provided_values: false,
+            encrypt_environment_registers: false,
+            ..Config::default()
+        };
+        let mut executable = crate::assembler::assemble::<TestContextObject>("
+            lddw r1, 0x123456789
+            mov r2, 7
+            call function_foo
+            ldxdw r0, [r10-8]
+            div64 r0, r2
+            exit
+            function_foo:
+            stxdw [r10-8], r1
+            add64 r1, r2
+            mov r0, r1
+            exit", config, SyscallRegistry::default()).unwrap();
+        let estimate = executable.estimate_jit_code_size(&config).unwrap();
+        assert!(executable.get_compiled_program().is_none());
+        Executable::<TestContextObject>::jit_compile(&mut executable).unwrap();
+        assert_eq!(estimate, executable.get_compiled_program().unwrap().machine_code_length());
+
+        // A config with instruction meter checkpoints after every instruction needs more code
+        let dense_config = Config {
+            instruction_meter_checkpoint_distance: 1,
+            ..config
+        };
+        assert!(executable.estimate_jit_code_size(&dense_config).unwrap() > estimate);
+    }
+
+    #[test]
+    fn test_function_padding() {
+        let config = Config {
+            noop_instruction_rate: 0,
+            sanitize_user_provided_values: false,
+            encrypt_environment_registers: false,
+            ..Config::default()
+        };
+        let padded_config = Config {
+            jit_function_padding: 32,
+            ..config
+        };
+        let source = "
+            lddw r1, 0x123456789
+            mov r2, 7
+            call function_foo
+            exit
+            function_foo:
+            add64 r1, r2
+            mov r0, r1
+            exit";
+        let compile = |config: Config| {
+            let mut executable = crate::assembler::assemble::<TestContextObject>(source, config, SyscallRegistry::default()).unwrap();
+            let estimate = executable.estimate_jit_code_size(&config).unwrap();
+            Executable::<TestContextObject>::jit_compile(&mut executable).unwrap();
+            let jit_program = executable.get_compiled_program().unwrap();
+            assert_eq!(estimate, jit_program.machine_code_length());
+            let text_section = &jit_program.sections.text_section;
+            let function_foo_offset = jit_program.sections.pc_section[5] - text_section.as_ptr() as usize;
+            (text_section.to_vec(), function_foo_offset)
+        };
+        let (text_section, function_foo_offset) = compile(config);
+        let (padded_text_section, padded_function_foo_offset) = compile(padded_config);
+
+        // Both functions are followed by the padding and the instruction offsets account for it
+        assert_eq!(padded_text_section.len(), text_section.len() + 2 * 32);
+        assert_eq!(padded_function_foo_offset, function_foo_offset + 32);
+        assert!(padded_text_section[function_foo_offset..padded_function_foo_offset].iter().all(|byte| *byte == 0x90));
+    }
+
+    #[test]
+    fn test_perf_map() {
+        let config = Config {
+            enable_symbol_and_section_labels: true,
+            noop_instruction_rate: 0,
+            ..Config::default()
+        };
+        let mut executable = crate::assembler::assemble::<TestContextObject>("
+            lddw r1, 0x1
+            call function_foo
+            exit
+            function_foo:
+            mov r0, r1
+            exit", config, SyscallRegistry::default()).unwrap();
+        Executable::<TestContextObject>::jit_compile(&mut executable).unwrap();
+        let jit_program = executable.get_compiled_program().unwrap();
+        let mut perf_map = Vec::new();
+        jit_program.write_perf_map(&mut perf_map).unwrap();
+        let lines = String::from_utf8(perf_map).unwrap();
+        let entries = lines.lines().map(|line| {
+            let mut columns = line.splitn(3, ' ');
+            let start = usize::from_str_radix(columns.next().unwrap(), 16).unwrap();
+            let size = usize::from_str_radix(columns.next().unwrap(), 16).unwrap();
+            (start, size, columns.next().unwrap().to_string())
+        }).collect::<Vec<_>>();
+        let symbols = entries.iter().map(|(_start, _size, symbol)| symbol.as_str()).collect::<Vec<_>>();
+        assert_eq!(symbols, vec!["ebpf_runtime", "entrypoint:0", "entrypoint:2", "entrypoint:3", "function_foo:4", "function_foo:5", "ebpf_runtime"]);
+        let text_section = &jit_program.sections.text_section;
+        let mut end = text_section.as_ptr() as usize;
+        for (start, size, _symbol) in entries.iter() {
+            assert_eq!(*start, end);
+            end = start + size;
+        }
+        assert_eq!(end, text_section.as_ptr() as usize + text_section.len());
+        assert_eq!(entries[4].0, jit_program.sections.pc_section[4]);
+    }
+
+    #[test]
+    fn test_instruction_cost_code_length() {
+        struct ExpensiveCostModel;
+        impl CostModel for ExpensiveCostModel {
+            fn cost(&self, _insn: &ebpf::Insn) -> u64 {
+                0x10000000
+            }
+        }
+
+        const INSTRUCTION_COUNT: usize = 256;
+        let mut prog = [0; ebpf::INSN_SIZE * INSTRUCTION_COUNT];
+        for pc in 0..INSTRUCTION_COUNT {
+            prog[pc * ebpf::INSN_SIZE] = ebpf::MOV64_IMM;
+        }
+        prog[(INSTRUCTION_COUNT - 1) * ebpf::INSN_SIZE] = ebpf::EXIT;
+        let mut executable = create_mockup_executable(&prog);
+        Executable::<TestContextObject>::jit_compile(&mut executable).unwrap();
+        let uniform_machine_code_length = executable.get_compiled_program().unwrap().machine_code_length();
+        executable.set_cost_model(&ExpensiveCostModel).unwrap();
+        assert!(executable.get_compiled_program().is_none());
+        Executable::<TestContextObject>::jit_compile(&mut executable).unwrap();
+        let machine_code_length = executable.get_compiled_program().unwrap().machine_code_length() - uniform_machine_code_length;
+        assert!(machine_code_length <= MACHINE_CODE_PER_INSTRUCTION_COST * INSTRUCTION_COUNT);
+    }
 }
@@ -0,0 +1,167 @@
+#![allow(clippy::integer_arithmetic)]
+//! Keccak-256 (the original padding, as used by Ethereum, not SHA3-256)
+
+const ROUND_CONSTANTS: [u64; 24] = [
+    0x0000000000000001,
+    0x0000000000008082,
+    0x800000000000808a,
+    0x8000000080008000,
+    0x000000000000808b,
+    0x0000000080000001,
+    0x8000000080008081,
+    0x8000000000008009,
+    0x000000000000008a,
+    0x0000000000000088,
+    0x0000000080008009,
+    0x000000008000000a,
+    0x000000008000808b,
+    0x800000000000008b,
+    0x8000000000008089,
+    0x8000000000008003,
+    0x8000000000008002,
+    0x8000000000000080,
+    0x000000000000800a,
+    0x800000008000000a,
+    0x8000000080008081,
+    0x8000000000008080,
+    0x0000000080000001,
+    0x8000000080008008,
+];
+
+const ROTATIONS: [u32; 24] = [
+    1, 3, 6, 10, 15, 21, 28, 36, 45, 55, 2, 14, 27, 41, 56, 8, 25, 43, 62, 18, 39, 61, 20, 44,
+];
+
+const LANES: [usize; 24] = [
+    10, 7, 11, 17, 18, 3, 5, 16, 8, 21, 24, 4, 15, 23, 19, 13, 12, 2, 20, 14, 22, 9, 6, 1,
+];
+
+/// Bytes absorbed per permutation: 1600 bits minus twice the digest size
+const RATE: usize = 136;
+
+/// Incremental Keccak-256 hasher
+#[derive(Clone)]
+pub(crate) struct Keccak256 {
+    state: [u64; 25],
+    block: [u8; RATE],
+    block_len: usize,
+}
+
+impl Default for Keccak256 {
+    fn default() -> Self {
+        Self {
+            state: [0; 25],
+            block: [0; RATE],
+            block_len: 0,
+        }
+    }
+}
+
+impl Keccak256 {
+    /// Appends bytes to the message
+    pub(crate) fn update(&mut self, mut bytes: &[u8]) {
+        while !bytes.is_empty() {
+            let len = bytes.len().min(RATE - self.block_len);
+            self.block[self.block_len..self.block_len + len].copy_from_slice(&bytes[..len]);
+            self.block_len += len;
+            bytes = &bytes[len..];
+            if self.block_len == RATE {
+                self.absorb();
+                self.block_len = 0;
+            }
+        }
+    }
+
+    /// Pads the message and returns its digest
+    pub(crate) fn finalize(mut self) -> [u8; 32] {
+        self.block[self.block_len..].fill(0);
+        self.block[self.block_len] |= 0x01;
+        self.block[RATE - 1] |= 0x80;
+        self.absorb();
+        let mut digest = [0; 32];
+        for (chunk, lane) in digest.chunks_exact_mut(8).zip(self.state.iter()) {
+            chunk.copy_from_slice(&lane.to_le_bytes());
+        }
+        digest
+    }
+
+    fn absorb(&mut self) {
+        for (lane, chunk) in self.state.iter_mut().zip(self.block.chunks_exact(8)) {
+            let mut bytes = [0; 8];
+            bytes.copy_from_slice(chunk);
+            *lane ^= u64::from_le_bytes(bytes);
+        }
+        self.permute();
+    }
+
+    fn permute(&mut self) {
+        let a = &mut self.state;
+        for round_constant in ROUND_CONSTANTS {
+            // Theta
+            let mut c = [0u64; 5];
+            for (x, column) in c.iter_mut().enumerate() {
+                *column = a[x] ^ a[x + 5] ^ a[x + 10] ^ a[x + 15] ^ a[x + 20];
+            }
+            for x in 0..5 {
+                let d = c[(x + 4) % 5] ^ c[(x + 1) % 5].rotate_left(1);
+                for y in 0..5 {
+                    a[x + 5 * y] ^= d;
+                }
+            }
+            // Rho and pi
+            let mut last = a[1];
+            for (rotation, lane) in ROTATIONS.iter().zip(LANES.iter()) {
+                let current = a[*lane];
+                a[*lane] = last.rotate_left(*rotation);
+                last = current;
+            }
+            // Chi
+            for y in 0..5 {
+                let row = [
+                    a[5 * y],
+                    a[5 * y + 1],
+                    a[5 * y + 2],
+                    a[5 * y + 3],
+                    a[5 * y + 4],
+                ];
+                for x in 0..5 {
+                    a[x + 5 * y] = row[x] ^ (!row[(x + 1) % 5] & row[(x + 2) % 5]);
+                }
+            }
+            // Iota
+            a[0] ^= round_constant;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn hex(digest: [u8; 32]) -> String {
+        digest.iter().map(|byte| format!("{:02x}", byte)).collect()
+    }
+
+    #[test]
+    fn test_known_digests() {
+        assert_eq!(
+            hex(Keccak256::default().finalize()),
+            "c5d2460186f7233c927e7db2dcc703c0e500b653ca82273b7bfad8045d85a470"
+        );
+        let mut hasher = Keccak256::default();
+        hasher.update(b"abc");
+        assert_eq!(
+            hex(hasher.finalize()),
+            "4e03657aea45a94fc7d47ba826c8d667c0d1e6e33a64a036ec44f58fa12d6c45"
+        );
+        // Spans more than one block
+        let mut hasher = Keccak256::default();
+        for _ in 0..200 {
+            hasher.update(b"a");
+        }
+        assert_eq!(
+            hex(hasher.finalize()),
+            "96ea54061def936c4be90b518992fdc6f12f535068a256229aca54267b4d084d"
+        );
+    }
+}
@@ -10,49 +10,71 @@
 // copied, modified, or distributed except according to those terms.
 
 //! Virtual machine and JIT compiler for eBPF programs.
+//!
+//! Without the default `std` feature the crate builds under `#![no_std]` with
+//! `alloc`, providing the ELF loader, memory mapping, verifier and
+//! interpreter. The assembler, disassembler, static analysis, example
+//! syscalls and the JIT compiler require `std`.
+#![cfg_attr(not(feature = "std"), no_std)]
 #![warn(missing_docs)]
 #![doc(
     html_logo_url = "https://raw.githubusercontent.com/qmonnet/rbpf/master/misc/rbpf.png",
     html_favicon_url = "https://raw.githubusercontent.com/qmonnet/rbpf/master/misc/rbpf.ico"
 )]
 
+extern crate alloc;
 extern crate byteorder;
+#[cfg(feature = "std")]
 extern crate combine;
 extern crate hash32;
 extern crate log;
+#[cfg(feature = "std")]
 extern crate rand;
-extern crate thiserror;
 
 pub mod aligned_memory;
+pub mod allocator;
+#[cfg(feature = "std")]
 mod asm_parser;
+#[cfg(feature = "std")]
 pub mod assembler;
+pub mod async_interpreter;
 pub mod call_frames;
 #[cfg(feature = "debugger")]
 pub mod debugger;
+#[cfg(feature = "std")]
 pub mod disassembler;
 pub mod ebpf;
 pub mod elf;
 pub mod elf_parser;
 pub mod elf_parser_glue;
 pub mod error;
+#[cfg(feature = "std")]
 pub mod fuzz;
 pub mod insn_builder;
 pub mod interpreter;
 #[cfg(feature = "jit")]
 mod jit;
+#[cfg(feature = "std")]
+mod keccak;
 pub mod memory_region;
+mod sha256;
+#[cfg(feature = "std")]
 pub mod static_analysis;
+#[cfg(feature = "std")]
 pub mod syscalls;
 pub mod verifier;
 pub mod vm;
+pub mod vm_pool;
 #[cfg(feature = "jit")]
 mod x86;
 
+#[cfg(feature = "jit")]
+pub use jit::REGISTER_MAP as JIT_REGISTER_MAP;
+
 trait ErrCheckedArithmetic: Sized {
     fn err_checked_add(self, other: Self) -> Result<Self, ArithmeticOverflow>;
     fn err_checked_sub(self, other: Self) -> Result<Self, ArithmeticOverflow>;
     fn err_checked_mul(self, other: Self) -> Result<Self, ArithmeticOverflow>;
-    fn err_checked_div(self, other: Self) -> Result<Self, ArithmeticOverflow>;
 }
 struct ArithmeticOverflow;
 
@@ -71,10 +93,6 @@ macro_rules! impl_err_checked_arithmetic {
                 fn err_checked_mul(self, other: $ty) -> Result<Self, ArithmeticOverflow> {
                     self.checked_mul(other).ok_or(ArithmeticOverflow)
                 }
-
-                fn err_checked_div(self, other: $ty) -> Result<Self, ArithmeticOverflow> {
-                    self.checked_div(other).ok_or(ArithmeticOverflow)
-                }
             }
         )*
     }
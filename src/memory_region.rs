@@ -1,11 +1,19 @@
 //! This module defines memory regions
 
 use crate::{
-    ebpf,
-    error::EbpfError,
+    aligned_memory::AlignedMemory,
+    ebpf::{self, HOST_ALIGN},
+    error::{EbpfError, UserDefinedError},
     vm::{Config, ProgramResult},
 };
-use std::{array, cell::UnsafeCell, fmt, ops::Range};
+use alloc::{
+    boxed::Box,
+    collections::BTreeMap,
+    string::{String, ToString},
+    vec,
+    vec::Vec,
+};
+use core::{array, cell::UnsafeCell, convert::TryFrom, fmt, ops::Range};
 
 /* Explaination of the Gapped Memory
 
@@ -42,7 +50,7 @@ pub struct MemoryRegion {
 
 impl MemoryRegion {
     fn new(slice: &[u8], vm_addr: u64, vm_gap_size: u64, is_writable: bool) -> Self {
-        let mut vm_gap_shift = (std::mem::size_of::<u64>() as u8)
+        let mut vm_gap_shift = (core::mem::size_of::<u64>() as u8)
             .saturating_mul(8)
             .saturating_sub(1);
         if vm_gap_size > 0 {
@@ -78,13 +86,21 @@ impl MemoryRegion {
         Self::new(slice, vm_addr, 0, true)
     }
 
+    /// Creates a new readonly MemoryRegion backed by a memory-mapped file
+    ///
+    /// The region is translated like any other, while the OS pages the bytes in lazily.
+    #[cfg(all(feature = "memory-mapped-files", unix))]
+    pub fn new_from_mapped_file(mapped_file: &MappedFile, vm_addr: u64) -> Self {
+        Self::new_readonly(mapped_file.as_slice(), vm_addr)
+    }
+
     /// Creates a new writable gapped MemoryRegion from a mutable slice
     pub fn new_writable_gapped(slice: &mut [u8], vm_addr: u64, vm_gap_size: u64) -> Self {
         Self::new(slice, vm_addr, vm_gap_size, true)
     }
 
     /// Convert a virtual machine address into a host address
-    pub fn vm_to_host(&self, vm_addr: u64, len: u64) -> ProgramResult {
+    pub fn vm_to_host<E: UserDefinedError>(&self, vm_addr: u64, len: u64) -> ProgramResult<E> {
         // This can happen if a region starts at an offset from the base region
         // address, eg with rodata regions if config.optimize_rodata = true, see
         // Elf::get_ro_region.
@@ -101,13 +117,31 @@ impl MemoryRegion {
         let gap_mask = (-1i64).checked_shl(self.vm_gap_shift as u32).unwrap_or(0) as u64;
         let gapped_offset =
             (begin_offset & gap_mask).checked_shr(1).unwrap_or(0) | (begin_offset & !gap_mask);
+        // The access must also end before the gap which follows its frame, as the
+        // frames are contiguous on the host and it would spill into the next one
+        let spills_into_gap = (begin_offset & !gap_mask)
+            .checked_add(len)
+            .is_none_or(|frame_end_offset| frame_end_offset > (!gap_mask).saturating_add(1));
         if let Some(end_offset) = gapped_offset.checked_add(len) {
-            if end_offset <= self.len && !is_in_gap {
+            if end_offset <= self.len && !is_in_gap && !spills_into_gap {
                 return ProgramResult::Ok(self.host_addr.saturating_add(gapped_offset));
             }
         }
         ProgramResult::Err(EbpfError::InvalidVirtualAddress(vm_addr))
     }
+
+    /// Convert a host address into a virtual machine address, the inverse of `vm_to_host()`
+    ///
+    /// Returns `None` if the host address is outside of this region.
+    pub fn host_to_vm(&self, host_addr: u64) -> Option<u64> {
+        let gapped_offset = host_addr
+            .checked_sub(self.host_addr)
+            .filter(|offset| *offset < self.len)?;
+        let gap_mask = (-1i64).checked_shl(self.vm_gap_shift as u32).unwrap_or(0) as u64;
+        let begin_offset =
+            (gapped_offset & gap_mask).checked_shl(1).unwrap_or(0) | (gapped_offset & !gap_mask);
+        self.vm_addr.checked_add(begin_offset)
+    }
 }
 
 impl fmt::Debug for MemoryRegion {
@@ -123,17 +157,125 @@ impl fmt::Debug for MemoryRegion {
         )
     }
 }
-impl std::cmp::PartialOrd for MemoryRegion {
-    fn partial_cmp(&self, other: &Self) -> Option<std::cmp::Ordering> {
+impl core::cmp::PartialOrd for MemoryRegion {
+    fn partial_cmp(&self, other: &Self) -> Option<core::cmp::Ordering> {
         Some(self.cmp(other))
     }
 }
-impl std::cmp::Ord for MemoryRegion {
-    fn cmp(&self, other: &Self) -> std::cmp::Ordering {
+impl core::cmp::Ord for MemoryRegion {
+    fn cmp(&self, other: &Self) -> core::cmp::Ordering {
         self.vm_addr.cmp(&other.vm_addr)
     }
 }
 
+/// Readonly memory mapping of a range of a file, see [`MemoryRegion::new_from_mapped_file`]
+#[cfg(all(feature = "memory-mapped-files", unix))]
+pub struct MappedFile {
+    /// Start of the mapping, aligned to the page size
+    mapping: *mut libc::c_void,
+    /// Length of the mapping, including the bytes before the requested offset
+    mapping_len: usize,
+    /// Where the requested offset is inside the mapping
+    offset_in_mapping: usize,
+    /// Length of the mapped range in bytes
+    len: usize,
+}
+
+#[cfg(all(feature = "memory-mapped-files", unix))]
+impl MappedFile {
+    /// Maps `len` bytes of `file` starting at `offset` as readonly
+    ///
+    /// The part of the range beyond the end of the file is not mapped,
+    /// so that accessing it results in an `EbpfError::AccessViolation`
+    /// instead of a SIGBUS.
+    ///
+    /// # Safety
+    ///
+    /// The file must not be truncated or modified while it is mapped, neither by
+    /// this process nor by any other. Truncation turns accesses to the mapped range
+    /// into a SIGBUS and modifications change the bytes behind `as_slice()`.
+    pub unsafe fn new(file: &std::fs::File, offset: u64, len: u64) -> std::io::Result<Self> {
+        use std::os::unix::io::AsRawFd;
+        let len = len.min(file.metadata()?.len().saturating_sub(offset)) as usize;
+        if len == 0 {
+            return Ok(Self {
+                mapping: core::ptr::null_mut(),
+                mapping_len: 0,
+                offset_in_mapping: 0,
+                len: 0,
+            });
+        }
+        let page_size = libc::sysconf(libc::_SC_PAGESIZE);
+        if page_size <= 0 {
+            return Err(std::io::Error::last_os_error());
+        }
+        let page_size = page_size as u64;
+        let mapping_offset = offset / page_size * page_size;
+        let offset_in_mapping = (offset - mapping_offset) as usize;
+        let mapping_len = offset_in_mapping + len;
+        let mapping = libc::mmap(
+            core::ptr::null_mut(),
+            mapping_len,
+            libc::PROT_READ,
+            libc::MAP_PRIVATE,
+            file.as_raw_fd(),
+            libc::off_t::try_from(mapping_offset)
+                .map_err(|_| std::io::Error::from(std::io::ErrorKind::InvalidInput))?,
+        );
+        if mapping == libc::MAP_FAILED {
+            return Err(std::io::Error::last_os_error());
+        }
+        Ok(Self {
+            mapping,
+            mapping_len,
+            offset_in_mapping,
+            len,
+        })
+    }
+
+    /// The mapped bytes of the file
+    pub fn as_slice(&self) -> &[u8] {
+        if self.len == 0 {
+            return &[];
+        }
+        unsafe {
+            core::slice::from_raw_parts(
+                (self.mapping as *const u8).add(self.offset_in_mapping),
+                self.len,
+            )
+        }
+    }
+}
+
+// The mapping is readonly and owned by MappedFile
+#[cfg(all(feature = "memory-mapped-files", unix))]
+unsafe impl Send for MappedFile {}
+#[cfg(all(feature = "memory-mapped-files", unix))]
+unsafe impl Sync for MappedFile {}
+
+#[cfg(all(feature = "memory-mapped-files", unix))]
+impl Drop for MappedFile {
+    fn drop(&mut self) {
+        if self.mapping_len > 0 {
+            unsafe {
+                libc::munmap(self.mapping, self.mapping_len);
+            }
+        }
+    }
+}
+
+#[cfg(all(feature = "memory-mapped-files", unix))]
+impl fmt::Debug for MappedFile {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "MappedFile {{ host_addr: {:#x?}, len: {} }}",
+            self.as_slice().as_ptr(),
+            self.len
+        )
+    }
+}
+
 /// Type of memory access
 #[derive(Clone, Copy, PartialEq, Eq, Debug)]
 pub enum AccessType {
@@ -143,6 +285,15 @@ pub enum AccessType {
     Store,
 }
 
+/// Hook invoked by `MemoryMapping::map()` before every load and store, see `MemoryMapping::set_memory_observer()`
+#[cfg(feature = "memory-observer")]
+pub trait MemoryObserver {
+    /// Called with the type, the virtual address and the length of an access before it is performed
+    ///
+    /// Returning `false` vetoes the access, which then fails with `EbpfError::AccessViolation`.
+    fn on_access(&mut self, access_type: AccessType, vm_addr: u64, len: u64) -> bool;
+}
+
 /// Memory mapping based on eytzinger search.
 #[derive(Debug)]
 pub struct UnalignedMemoryMapping<'a> {
@@ -152,6 +303,16 @@ pub struct UnalignedMemoryMapping<'a> {
     region_addresses: Box<[u64]>,
     /// Cache of the last `MappingCache::SIZE` vm_addr => region_index lookups
     cache: UnsafeCell<MappingCache>,
+    /// Keeps the writable regions duplicated by `try_clone()` alive
+    _cloned_memory: Vec<AlignedMemory<HOST_ALIGN>>,
+    /// Successful accesses per region
+    #[cfg(feature = "access-counters")]
+    access_counts: AccessCounters,
+    /// Hook invoked before every access
+    #[cfg(feature = "memory-observer")]
+    memory_observer: MemoryObserverSlot<'a>,
+    /// Index into `regions` by name, see `MemoryMapping::new_with_names()`
+    region_names: BTreeMap<String, usize>,
     /// VM configuration
     config: &'a Config,
 }
@@ -181,7 +342,10 @@ impl<'a> UnalignedMemoryMapping<'a> {
     }
 
     /// Creates a new MemoryMapping structure from the given regions
-    pub fn new(mut regions: Vec<MemoryRegion>, config: &'a Config) -> Result<Self, EbpfError> {
+    pub fn new<E: UserDefinedError>(
+        mut regions: Vec<MemoryRegion>,
+        config: &'a Config,
+    ) -> Result<Self, EbpfError<E>> {
         regions.sort();
         for index in 1..regions.len() {
             let first = &regions[index.saturating_sub(1)];
@@ -195,6 +359,12 @@ impl<'a> UnalignedMemoryMapping<'a> {
             regions: vec![MemoryRegion::default(); regions.len()].into_boxed_slice(),
             region_addresses: vec![0; regions.len()].into_boxed_slice(),
             cache: UnsafeCell::new(MappingCache::new()),
+            _cloned_memory: Vec::new(),
+            #[cfg(feature = "access-counters")]
+            access_counts: AccessCounters::new(regions.len()),
+            #[cfg(feature = "memory-observer")]
+            memory_observer: MemoryObserverSlot::default(),
+            region_names: BTreeMap::new(),
             config,
         };
         result.construct_eytzinger_order(&regions, 0, 0);
@@ -203,15 +373,24 @@ impl<'a> UnalignedMemoryMapping<'a> {
 
     /// Given a list of regions translate from virtual machine to host address
     #[allow(clippy::integer_arithmetic)]
-    pub fn map(&self, access_type: AccessType, vm_addr: u64, len: u64) -> ProgramResult {
+    pub fn map<E: UserDefinedError>(
+        &self,
+        access_type: AccessType,
+        vm_addr: u64,
+        len: u64,
+    ) -> ProgramResult<E> {
+        #[cfg(feature = "memory-observer")]
+        if let Err(err) = self.memory_observer.notify(access_type, vm_addr, len) {
+            return ProgramResult::Err(err);
+        }
         // Safety:
         // &mut references to the mapping cache are only created internally here
         // and in replace_region(). The methods never invoke each other and
         // UnalignedMemoryMapping is !Sync, so the cache reference below is
         // guaranteed to be unique.
         let cache = unsafe { &mut *self.cache.get() };
-        let (cache_miss, index) = if let Some(region) = cache.find(vm_addr) {
-            (false, region)
+        let (cache_miss, index, search_path) = if let Some(region) = cache.find(vm_addr) {
+            (false, region, 0)
         } else {
             let mut index = 1;
             while index <= self.region_addresses.len() {
@@ -222,11 +401,18 @@ impl<'a> UnalignedMemoryMapping<'a> {
                     + unsafe { *self.region_addresses.get_unchecked(index - 1) <= vm_addr }
                         as usize;
             }
+            let search_path = index;
             index >>= index.trailing_zeros() + 1;
             if index == 0 {
-                return generate_access_violation(self.config, access_type, vm_addr, len);
+                return generate_access_violation(
+                    self.config,
+                    access_type,
+                    vm_addr,
+                    len,
+                    self.find_nearest_region(search_path, vm_addr, len),
+                );
             }
-            (true, index)
+            (true, index, search_path)
         };
 
         // Safety:
@@ -234,18 +420,52 @@ impl<'a> UnalignedMemoryMapping<'a> {
         // must be contained in region
         let region = unsafe { self.regions.get_unchecked(index - 1) };
         if access_type == AccessType::Load || region.is_writable {
-            if let ProgramResult::Ok(host_addr) = region.vm_to_host(vm_addr, len) {
+            if let ProgramResult::Ok(host_addr) = region.vm_to_host::<E>(vm_addr, len) {
                 if cache_miss {
                     cache.insert(
                         region.vm_addr..region.vm_addr.saturating_add(region.len),
                         index,
                     );
                 }
+                #[cfg(feature = "access-counters")]
+                self.access_counts.increment(index - 1);
                 return ProgramResult::Ok(host_addr);
             }
         }
 
-        generate_access_violation(self.config, access_type, vm_addr, len)
+        let nearest_region = if cache_miss {
+            self.find_nearest_region(search_path, vm_addr, len)
+        } else {
+            // A cache hit means vm_addr lies within this region
+            Some(index - 1)
+        };
+        generate_access_violation(self.config, access_type, vm_addr, len, nearest_region)
+    }
+
+    /// Determines the region closest to a faulting access from the path of the eytzinger search
+    ///
+    /// The bits of the final search index encode the decisions taken while descending the tree,
+    /// so the last right turn leads to the predecessor and the last left turn to the successor.
+    #[allow(clippy::integer_arithmetic)]
+    fn find_nearest_region(&self, search_path: usize, vm_addr: u64, len: u64) -> Option<usize> {
+        let predecessor = search_path >> (search_path.trailing_zeros() + 1);
+        let successor = search_path >> (search_path.trailing_ones() + 1);
+        match (predecessor, successor) {
+            (0, 0) => None,
+            (index, 0) | (0, index) => Some(index - 1),
+            (predecessor, successor) => {
+                let below = &self.regions[predecessor - 1];
+                let above = &self.regions[successor - 1];
+                let distance_below =
+                    vm_addr.saturating_sub(below.vm_addr.saturating_add(below.len));
+                let distance_above = above.vm_addr.saturating_sub(vm_addr.saturating_add(len));
+                Some(if distance_below <= distance_above {
+                    predecessor - 1
+                } else {
+                    successor - 1
+                })
+            }
+        }
     }
 
     /// Returns the `MemoryRegion`s in this mapping
@@ -254,7 +474,11 @@ impl<'a> UnalignedMemoryMapping<'a> {
     }
 
     /// Replaces the `MemoryRegion` at the given index
-    pub fn replace_region(&mut self, index: usize, region: MemoryRegion) -> Result<(), EbpfError> {
+    pub fn replace_region<E: UserDefinedError>(
+        &mut self,
+        index: usize,
+        region: MemoryRegion,
+    ) -> Result<(), EbpfError<E>> {
         if index >= self.regions.len() || self.regions[index].vm_addr != region.vm_addr {
             return Err(EbpfError::InvalidMemoryRegion(index));
         }
@@ -262,6 +486,52 @@ impl<'a> UnalignedMemoryMapping<'a> {
         self.cache.get_mut().flush();
         Ok(())
     }
+
+    /// Changes whether the `MemoryRegion` at the given index accepts stores
+    pub fn set_region_writable<E: UserDefinedError>(
+        &mut self,
+        index: usize,
+        writable: bool,
+    ) -> Result<(), EbpfError<E>> {
+        self.regions
+            .get_mut(index)
+            .ok_or(EbpfError::InvalidMemoryRegion(index))?
+            .is_writable = writable;
+        Ok(())
+    }
+
+    /// Duplicates the writable regions and shares the readonly ones
+    pub fn try_clone<E: UserDefinedError>(&self) -> Result<Self, EbpfError<E>> {
+        let (regions, cloned_memory) = clone_regions(&self.regions)?;
+        Ok(Self {
+            regions,
+            region_addresses: self.region_addresses.clone(),
+            cache: UnsafeCell::new(MappingCache::new()),
+            _cloned_memory: cloned_memory,
+            #[cfg(feature = "access-counters")]
+            access_counts: AccessCounters::new(self.regions.len()),
+            #[cfg(feature = "memory-observer")]
+            memory_observer: MemoryObserverSlot::default(),
+            region_names: self.region_names.clone(),
+            config: self.config,
+        })
+    }
+
+    /// Moves the regions into a mapping which refers to `config` and has no observer
+    fn rebind<'c>(self, config: &'c Config) -> UnalignedMemoryMapping<'c> {
+        UnalignedMemoryMapping {
+            regions: self.regions,
+            region_addresses: self.region_addresses,
+            cache: self.cache,
+            _cloned_memory: self._cloned_memory,
+            #[cfg(feature = "access-counters")]
+            access_counts: self.access_counts,
+            #[cfg(feature = "memory-observer")]
+            memory_observer: MemoryObserverSlot::default(),
+            region_names: self.region_names,
+            config,
+        }
+    }
 }
 
 /// Memory mapping that uses the upper half of an address to identify the
@@ -270,13 +540,26 @@ impl<'a> UnalignedMemoryMapping<'a> {
 pub struct AlignedMemoryMapping<'a> {
     /// Mapped memory regions
     regions: Box<[MemoryRegion]>,
+    /// Keeps the writable regions duplicated by `try_clone()` alive
+    _cloned_memory: Vec<AlignedMemory<HOST_ALIGN>>,
+    /// Successful accesses per region
+    #[cfg(feature = "access-counters")]
+    access_counts: AccessCounters,
+    /// Hook invoked before every access
+    #[cfg(feature = "memory-observer")]
+    memory_observer: MemoryObserverSlot<'a>,
+    /// Index into `regions` by name, see `MemoryMapping::new_with_names()`
+    region_names: BTreeMap<String, usize>,
     /// VM configuration
     config: &'a Config,
 }
 
 impl<'a> AlignedMemoryMapping<'a> {
     /// Creates a new MemoryMapping structure from the given regions
-    pub fn new(mut regions: Vec<MemoryRegion>, config: &'a Config) -> Result<Self, EbpfError> {
+    pub fn new<E: UserDefinedError>(
+        mut regions: Vec<MemoryRegion>,
+        config: &'a Config,
+    ) -> Result<Self, EbpfError<E>> {
         regions.insert(0, MemoryRegion::new_readonly(&[], 0));
         regions.sort();
         for (index, region) in regions.iter().enumerate() {
@@ -290,25 +573,45 @@ impl<'a> AlignedMemoryMapping<'a> {
             }
         }
         Ok(Self {
+            #[cfg(feature = "access-counters")]
+            access_counts: AccessCounters::new(regions.len()),
             regions: regions.into_boxed_slice(),
+            _cloned_memory: Vec::new(),
+            #[cfg(feature = "memory-observer")]
+            memory_observer: MemoryObserverSlot::default(),
+            region_names: BTreeMap::new(),
             config,
         })
     }
 
     /// Given a list of regions translate from virtual machine to host address
-    pub fn map(&self, access_type: AccessType, vm_addr: u64, len: u64) -> ProgramResult {
+    pub fn map<E: UserDefinedError>(
+        &self,
+        access_type: AccessType,
+        vm_addr: u64,
+        len: u64,
+    ) -> ProgramResult<E> {
+        #[cfg(feature = "memory-observer")]
+        if let Err(err) = self.memory_observer.notify(access_type, vm_addr, len) {
+            return ProgramResult::Err(err);
+        }
         let index = vm_addr
             .checked_shr(ebpf::VIRTUAL_ADDRESS_BITS as u32)
             .unwrap_or(0) as usize;
         if (1..self.regions.len()).contains(&index) {
             let region = &self.regions[index];
             if access_type == AccessType::Load || region.is_writable {
-                if let ProgramResult::Ok(host_addr) = region.vm_to_host(vm_addr, len) {
+                if let ProgramResult::Ok(host_addr) = region.vm_to_host::<E>(vm_addr, len) {
+                    #[cfg(feature = "access-counters")]
+                    self.access_counts.increment(index);
                     return ProgramResult::Ok(host_addr);
                 }
             }
         }
-        generate_access_violation(self.config, access_type, vm_addr, len)
+        // Index 0 is the null region, so clamp into the range of the mapped regions
+        let nearest_region =
+            (self.regions.len() > 1).then(|| index.clamp(1, self.regions.len().saturating_sub(1)));
+        generate_access_violation(self.config, access_type, vm_addr, len, nearest_region)
     }
 
     /// Returns the `MemoryRegion`s in this mapping
@@ -317,7 +620,11 @@ impl<'a> AlignedMemoryMapping<'a> {
     }
 
     /// Replaces the `MemoryRegion` at the given index
-    pub fn replace_region(&mut self, index: usize, region: MemoryRegion) -> Result<(), EbpfError> {
+    pub fn replace_region<E: UserDefinedError>(
+        &mut self,
+        index: usize,
+        region: MemoryRegion,
+    ) -> Result<(), EbpfError<E>> {
         if index >= self.regions.len() {
             return Err(EbpfError::InvalidMemoryRegion(index));
         }
@@ -336,8 +643,74 @@ impl<'a> AlignedMemoryMapping<'a> {
         self.regions[index] = region;
         Ok(())
     }
+
+    /// Changes whether the `MemoryRegion` at the given index accepts stores
+    pub fn set_region_writable<E: UserDefinedError>(
+        &mut self,
+        index: usize,
+        writable: bool,
+    ) -> Result<(), EbpfError<E>> {
+        // Index 0 is the null region
+        if index == 0 {
+            return Err(EbpfError::InvalidMemoryRegion(index));
+        }
+        self.regions
+            .get_mut(index)
+            .ok_or(EbpfError::InvalidMemoryRegion(index))?
+            .is_writable = writable;
+        Ok(())
+    }
+
+    /// Duplicates the writable regions and shares the readonly ones
+    pub fn try_clone<E: UserDefinedError>(&self) -> Result<Self, EbpfError<E>> {
+        let (regions, cloned_memory) = clone_regions(&self.regions)?;
+        Ok(Self {
+            #[cfg(feature = "access-counters")]
+            access_counts: AccessCounters::new(regions.len()),
+            regions,
+            _cloned_memory: cloned_memory,
+            #[cfg(feature = "memory-observer")]
+            memory_observer: MemoryObserverSlot::default(),
+            region_names: self.region_names.clone(),
+            config: self.config,
+        })
+    }
+
+    /// Moves the regions into a mapping which refers to `config` and has no observer
+    fn rebind<'c>(self, config: &'c Config) -> AlignedMemoryMapping<'c> {
+        AlignedMemoryMapping {
+            regions: self.regions,
+            _cloned_memory: self._cloned_memory,
+            #[cfg(feature = "access-counters")]
+            access_counts: self.access_counts,
+            #[cfg(feature = "memory-observer")]
+            memory_observer: MemoryObserverSlot::default(),
+            region_names: self.region_names,
+            config,
+        }
+    }
 }
 
+/// Types which guest memory can be viewed as, see `MemoryMapping::translate_slice()`
+///
+/// # Safety
+///
+/// Every bit pattern of `size_of::<Self>()` bytes must be a valid value of the type,
+/// which rules out `bool`, enums, references and types with padding.
+pub unsafe trait AnyBitPattern {}
+
+unsafe impl AnyBitPattern for u8 {}
+unsafe impl AnyBitPattern for u16 {}
+unsafe impl AnyBitPattern for u32 {}
+unsafe impl AnyBitPattern for u64 {}
+unsafe impl AnyBitPattern for u128 {}
+unsafe impl AnyBitPattern for i8 {}
+unsafe impl AnyBitPattern for i16 {}
+unsafe impl AnyBitPattern for i32 {}
+unsafe impl AnyBitPattern for i64 {}
+unsafe impl AnyBitPattern for i128 {}
+unsafe impl<T: AnyBitPattern, const N: usize> AnyBitPattern for [T; N] {}
+
 /// Maps virtual memory to host memory.
 #[derive(Debug)]
 pub enum MemoryMapping<'a> {
@@ -353,7 +726,10 @@ impl<'a> MemoryMapping<'a> {
     ///
     /// Uses aligned or unaligned memory mapping depending on the value of
     /// `config.aligned_memory_mapping=true`.
-    pub fn new(regions: Vec<MemoryRegion>, config: &'a Config) -> Result<Self, EbpfError> {
+    pub fn new<E: UserDefinedError>(
+        regions: Vec<MemoryRegion>,
+        config: &'a Config,
+    ) -> Result<Self, EbpfError<E>> {
         if config.aligned_memory_mapping {
             AlignedMemoryMapping::new(regions, config).map(MemoryMapping::Aligned)
         } else {
@@ -361,14 +737,127 @@ impl<'a> MemoryMapping<'a> {
         }
     }
 
+    /// Creates a new memory mapping of named regions, see `region_by_name()`.
+    ///
+    /// Fails with `EbpfError::InvalidMemoryRegion` carrying the position in
+    /// `regions` if a name is used twice.
+    pub fn new_with_names<E: UserDefinedError>(
+        regions: Vec<(&str, MemoryRegion)>,
+        config: &'a Config,
+    ) -> Result<Self, EbpfError<E>> {
+        let mut result = Self::new(
+            regions
+                .iter()
+                .map(|(_name, region)| region.clone())
+                .collect(),
+            config,
+        )?;
+        let mut region_names = BTreeMap::new();
+        for (position, (name, region)) in regions.into_iter().enumerate() {
+            let index = result
+                .get_regions()
+                .iter()
+                .position(|mapped_region| *mapped_region == region)
+                .ok_or(EbpfError::InvalidMemoryRegion(position))?;
+            if region_names.insert(name.to_string(), index).is_some() {
+                return Err(EbpfError::InvalidMemoryRegion(position));
+            }
+        }
+        match &mut result {
+            MemoryMapping::Aligned(m) => m.region_names = region_names,
+            MemoryMapping::Unaligned(m) => m.region_names = region_names,
+        }
+        Ok(result)
+    }
+
+    /// Returns the region which was given the name in `new_with_names()`
+    pub fn region_by_name(&self, name: &str) -> Option<&MemoryRegion> {
+        let region_names = match self {
+            MemoryMapping::Aligned(m) => &m.region_names,
+            MemoryMapping::Unaligned(m) => &m.region_names,
+        };
+        region_names
+            .get(name)
+            .and_then(|index| self.get_regions().get(*index))
+    }
+
     /// Map virtual memory to host memory.
-    pub fn map(&self, access_type: AccessType, vm_addr: u64, len: u64) -> ProgramResult {
+    pub fn map<E: UserDefinedError>(
+        &self,
+        access_type: AccessType,
+        vm_addr: u64,
+        len: u64,
+    ) -> ProgramResult<E> {
         match self {
             MemoryMapping::Aligned(m) => m.map(access_type, vm_addr, len),
             MemoryMapping::Unaligned(m) => m.map(access_type, vm_addr, len),
         }
     }
 
+    /// Map host memory back to virtual memory, the inverse of `map()`.
+    ///
+    /// Returns `None` if the host pointer is not inside of any region.
+    pub fn host_to_vm_addr(&self, host_ptr: *const u8) -> Option<u64> {
+        self.get_regions()
+            .iter()
+            .find_map(|region| region.host_to_vm(host_ptr as u64))
+    }
+
+    /// Translates `len` elements of type `T` at `vm_addr` to a host slice.
+    ///
+    /// The whole range is checked against the regions for the given access
+    /// type. Fails with `EbpfError::InvalidVirtualAddress` if the size in
+    /// bytes overflows or the host address is not aligned for `T`, and with
+    /// `EbpfError::SyscallBufferTooLarge` if it exceeds `Config::max_syscall_buffer_len`.
+    pub fn translate_slice<T: AnyBitPattern, E: UserDefinedError>(
+        &self,
+        vm_addr: u64,
+        len: u64,
+        access_type: AccessType,
+    ) -> Result<&[T], EbpfError<E>> {
+        let host_addr = self.translate_slice_addr::<T, E>(access_type, vm_addr, len)?;
+        Ok(unsafe { core::slice::from_raw_parts(host_addr as *const T, len as usize) })
+    }
+
+    /// Same as `translate_slice` for a store, returning a mutable host slice.
+    pub fn translate_slice_mut<T: AnyBitPattern, E: UserDefinedError>(
+        &mut self,
+        vm_addr: u64,
+        len: u64,
+    ) -> Result<&mut [T], EbpfError<E>> {
+        let host_addr = self.translate_slice_addr::<T, E>(AccessType::Store, vm_addr, len)?;
+        Ok(unsafe { core::slice::from_raw_parts_mut(host_addr as *mut T, len as usize) })
+    }
+
+    fn translate_slice_addr<T: AnyBitPattern, E: UserDefinedError>(
+        &self,
+        access_type: AccessType,
+        vm_addr: u64,
+        len: u64,
+    ) -> Result<u64, EbpfError<E>> {
+        let size_in_bytes = len
+            .checked_mul(core::mem::size_of::<T>() as u64)
+            .filter(|size| usize::try_from(*size).is_ok())
+            .ok_or(EbpfError::InvalidVirtualAddress(vm_addr))?;
+        if size_in_bytes > self.get_config().max_syscall_buffer_len as u64 {
+            return Err(EbpfError::SyscallBufferTooLarge(vm_addr, size_in_bytes));
+        }
+        let host_addr: Result<u64, EbpfError<E>> =
+            self.map(access_type, vm_addr, size_in_bytes).into();
+        let host_addr = host_addr?;
+        if host_addr % core::mem::align_of::<T>() as u64 != 0 {
+            return Err(EbpfError::InvalidVirtualAddress(vm_addr));
+        }
+        Ok(host_addr)
+    }
+
+    fn get_config(&self) -> &Config {
+        match self {
+            MemoryMapping::Aligned(m) => m.config,
+            MemoryMapping::Unaligned(m) => m.config,
+        }
+    }
+
     /// Returns the `MemoryRegion`s in this mapping.
     pub fn get_regions(&self) -> &[MemoryRegion] {
         match self {
@@ -378,21 +867,186 @@ impl<'a> MemoryMapping<'a> {
     }
 
     /// Replaces the `MemoryRegion` at the given index
-    pub fn replace_region(&mut self, index: usize, region: MemoryRegion) -> Result<(), EbpfError> {
+    pub fn replace_region<E: UserDefinedError>(
+        &mut self,
+        index: usize,
+        region: MemoryRegion,
+    ) -> Result<(), EbpfError<E>> {
         match self {
             MemoryMapping::Aligned(m) => m.replace_region(index, region),
             MemoryMapping::Unaligned(m) => m.replace_region(index, region),
         }
     }
+
+    /// Changes whether the `MemoryRegion` at the given index accepts stores
+    ///
+    /// The permission is checked on every access by both the interpreter and
+    /// the JIT, so this can be called while a program is running, e.g. from
+    /// a syscall. Subsequent stores to a readonly region fail with
+    /// `EbpfError::AccessViolation`.
+    pub fn set_region_writable<E: UserDefinedError>(
+        &mut self,
+        index: usize,
+        writable: bool,
+    ) -> Result<(), EbpfError<E>> {
+        match self {
+            MemoryMapping::Aligned(m) => m.set_region_writable(index, writable),
+            MemoryMapping::Unaligned(m) => m.set_region_writable(index, writable),
+        }
+    }
+
+    /// Returns a copy of the number of successful loads and stores per region.
+    ///
+    /// Indexed the same way as `get_regions()`. Counts accumulate across runs
+    /// until `reset_access_counts()` is called.
+    #[cfg(feature = "access-counters")]
+    pub fn access_counts(&self) -> Vec<u64> {
+        match self {
+            MemoryMapping::Aligned(m) => m.access_counts.get(),
+            MemoryMapping::Unaligned(m) => m.access_counts.get(),
+        }
+    }
+
+    /// Sets all access counts to zero
+    #[cfg(feature = "access-counters")]
+    pub fn reset_access_counts(&mut self) {
+        match self {
+            MemoryMapping::Aligned(m) => m.access_counts.reset(),
+            MemoryMapping::Unaligned(m) => m.access_counts.reset(),
+        }
+    }
+
+    /// Installs a hook which is invoked before every load and store, see `MemoryObserver`
+    ///
+    /// This includes the accesses of syscalls which translate guest memory.
+    /// The copy made by `try_clone()` has no observer installed.
+    #[cfg(feature = "memory-observer")]
+    pub fn set_memory_observer(&mut self, memory_observer: &'a mut dyn MemoryObserver) {
+        match self {
+            MemoryMapping::Aligned(m) => m.memory_observer.set(memory_observer),
+            MemoryMapping::Unaligned(m) => m.memory_observer.set(memory_observer),
+        }
+    }
+
+    /// Creates a copy of this mapping with the same virtual address layout.
+    ///
+    /// The backing store of every writable region is duplicated and owned by
+    /// the returned mapping, while readonly regions keep pointing to the same
+    /// host memory. Fails with `EbpfError::InvalidMemoryRegion` if a writable
+    /// region aliases the host memory of another region, as the copy would
+    /// not preserve the aliasing.
+    pub fn try_clone<E: UserDefinedError>(&self) -> Result<Self, EbpfError<E>> {
+        match self {
+            MemoryMapping::Aligned(m) => m.try_clone().map(MemoryMapping::Aligned),
+            MemoryMapping::Unaligned(m) => m.try_clone().map(MemoryMapping::Unaligned),
+        }
+    }
+
+    /// Keeps the regions but refers to `config` instead, which ends the borrow of the old one
+    ///
+    /// Drops the `MemoryObserver` as it may not live as long as `config`, see `VmPool`.
+    pub(crate) fn rebind<'c>(self, config: &'c Config) -> MemoryMapping<'c> {
+        match self {
+            MemoryMapping::Aligned(m) => MemoryMapping::Aligned(m.rebind(config)),
+            MemoryMapping::Unaligned(m) => MemoryMapping::Unaligned(m.rebind(config)),
+        }
+    }
+}
+
+/// Regions of a cloned mapping and the copies of the writable memory they point to
+type ClonedRegions = (Box<[MemoryRegion]>, Vec<AlignedMemory<HOST_ALIGN>>);
+
+/// Helper for try_clone to duplicate the writable regions
+fn clone_regions<E: UserDefinedError>(
+    regions: &[MemoryRegion],
+) -> Result<ClonedRegions, EbpfError<E>> {
+    let host_range =
+        |region: &MemoryRegion| region.host_addr..region.host_addr.saturating_add(region.len);
+    for (index, region) in regions.iter().enumerate() {
+        if !region.is_writable || region.len == 0 {
+            continue;
+        }
+        let range = host_range(region);
+        let is_aliased = regions.iter().enumerate().any(|(other_index, other)| {
+            let other_range = host_range(other);
+            other_index != index
+                && other.len > 0
+                && range.start < other_range.end
+                && other_range.start < range.end
+        });
+        if is_aliased {
+            return Err(EbpfError::InvalidMemoryRegion(index));
+        }
+    }
+    let mut cloned_memory = Vec::new();
+    let regions = regions
+        .iter()
+        .map(|region| {
+            if !region.is_writable {
+                return region.clone();
+            }
+            // Safety:
+            // The regions of a mapping must stay valid for as long as the
+            // mapping is in use, the same requirement map() relies on.
+            let slice = unsafe {
+                core::slice::from_raw_parts(region.host_addr as *const u8, region.len as usize)
+            };
+            let memory = AlignedMemory::<HOST_ALIGN>::from_slice(slice);
+            let region = MemoryRegion {
+                host_addr: memory.as_slice().as_ptr() as u64,
+                ..region.clone()
+            };
+            cloned_memory.push(memory);
+            region
+        })
+        .collect();
+    Ok((regions, cloned_memory))
+}
+
+/// Helper for generate_access_violation to detect accesses below a stack frame
+///
+/// Returns the index of the stack frame and by how many bytes its lower bound
+/// was underflowed. That is the case if the address lies right below the
+/// stack region or, with stack frame gaps, inside the gap below a frame.
+fn stack_underflow(config: &Config, vm_addr: u64) -> Option<(i64, u64)> {
+    let stack_frame_size = config.stack_frame_size as u64;
+    if vm_addr < ebpf::MM_STACK_START {
+        let underflow = ebpf::MM_STACK_START.saturating_sub(vm_addr);
+        return (underflow <= stack_frame_size).then_some((0, underflow));
+    }
+    if config.dynamic_stack_frames || !config.enable_stack_frame_gaps || stack_frame_size == 0 {
+        return None;
+    }
+    let stride = stack_frame_size.saturating_mul(2);
+    let offset = vm_addr.saturating_sub(ebpf::MM_STACK_START);
+    let stack_frame = (offset / stride).saturating_add(1);
+    if offset % stride < stack_frame_size || stack_frame >= config.max_call_depth as u64 {
+        return None;
+    }
+    Some((
+        stack_frame as i64,
+        stack_frame.saturating_mul(stride).saturating_sub(offset),
+    ))
 }
 
 /// Helper for map to generate errors
-fn generate_access_violation(
+fn generate_access_violation<E: UserDefinedError>(
     config: &Config,
     access_type: AccessType,
     vm_addr: u64,
     len: u64,
-) -> ProgramResult {
+    nearest_region: Option<usize>,
+) -> ProgramResult<E> {
+    if let Some((stack_frame, underflow)) = stack_underflow(config, vm_addr) {
+        return ProgramResult::Err(EbpfError::StackOverflowDuringMap(
+            0, // Filled out later
+            access_type,
+            vm_addr,
+            len,
+            stack_frame,
+            underflow,
+        ));
+    }
     let stack_frame = (vm_addr as i64)
         .saturating_sub(ebpf::MM_STACK_START as i64)
         .checked_div(config.stack_frame_size as i64)
@@ -408,23 +1062,108 @@ fn generate_access_violation(
             stack_frame,
         ))
     } else {
-        let region_name = match vm_addr & (!ebpf::MM_PROGRAM_START.saturating_sub(1)) {
-            ebpf::MM_PROGRAM_START => "program",
-            ebpf::MM_STACK_START => "stack",
-            ebpf::MM_HEAP_START => "heap",
-            ebpf::MM_INPUT_START => "input",
-            _ => "unknown",
-        };
         ProgramResult::Err(EbpfError::AccessViolation(
             0, // Filled out later
             access_type,
             vm_addr,
             len,
-            region_name,
+            region_name(vm_addr),
+            nearest_region,
         ))
     }
 }
 
+/// Helper for generate_access_violation to name the region an address points into
+fn region_name(vm_addr: u64) -> &'static str {
+    match vm_addr & (!ebpf::MM_PROGRAM_START.saturating_sub(1)) {
+        ebpf::MM_PROGRAM_START => "program",
+        ebpf::MM_STACK_START => "stack",
+        ebpf::MM_HEAP_START => "heap",
+        ebpf::MM_INPUT_START => "input",
+        _ => "unknown",
+    }
+}
+
+/// The optional `MemoryObserver` of a memory mapping
+#[cfg(feature = "memory-observer")]
+#[derive(Default)]
+struct MemoryObserverSlot<'a>(UnsafeCell<Option<&'a mut dyn MemoryObserver>>);
+
+#[cfg(feature = "memory-observer")]
+impl<'a> MemoryObserverSlot<'a> {
+    fn set(&mut self, memory_observer: &'a mut dyn MemoryObserver) {
+        *self.0.get_mut() = Some(memory_observer);
+    }
+
+    #[inline]
+    fn notify<E: UserDefinedError>(
+        &self,
+        access_type: AccessType,
+        vm_addr: u64,
+        len: u64,
+    ) -> Result<(), EbpfError<E>> {
+        // Safety:
+        // The observer is only borrowed mutably here and through &mut self in
+        // set(). Memory mappings are !Sync, so this is the only reference
+        // while it lives.
+        let vetoed = match unsafe { &mut *self.0.get() } {
+            Some(memory_observer) => !memory_observer.on_access(access_type, vm_addr, len),
+            None => false,
+        };
+        if vetoed {
+            return Err(EbpfError::AccessViolation(
+                0, // Filled out later
+                access_type,
+                vm_addr,
+                len,
+                region_name(vm_addr),
+                None,
+            ));
+        }
+        Ok(())
+    }
+}
+
+#[cfg(feature = "memory-observer")]
+impl<'a> fmt::Debug for MemoryObserverSlot<'a> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        // Safety: see notify()
+        let is_set = unsafe { (*self.0.get()).is_some() };
+        f.debug_tuple("MemoryObserverSlot").field(&is_set).finish()
+    }
+}
+
+/// Per region counters of successful memory accesses
+#[cfg(feature = "access-counters")]
+#[derive(Debug)]
+struct AccessCounters(UnsafeCell<Box<[u64]>>);
+
+#[cfg(feature = "access-counters")]
+impl AccessCounters {
+    fn new(region_count: usize) -> Self {
+        Self(UnsafeCell::new(vec![0; region_count].into_boxed_slice()))
+    }
+
+    #[inline]
+    fn increment(&self, index: usize) {
+        // Safety:
+        // The counters are only borrowed here, in get() and through &mut self in
+        // reset(), and none of these borrows outlive the call. Memory mappings
+        // are !Sync, so this is the only reference while it lives.
+        let counts = unsafe { &mut *self.0.get() };
+        counts[index] = counts[index].saturating_add(1);
+    }
+
+    fn get(&self) -> Vec<u64> {
+        // Safety: see increment()
+        unsafe { (*self.0.get()).to_vec() }
+    }
+
+    fn reset(&mut self) {
+        self.0.get_mut().fill(0);
+    }
+}
+
 /// Fast, small linear cache used to speed up unaligned memory mapping.
 #[derive(Debug)]
 struct MappingCache {
@@ -482,6 +1221,7 @@ impl MappingCache {
 #[cfg(test)]
 mod test {
     use super::*;
+    use crate::vm::TestUserError;
 
     #[test]
     fn test_mapping_cache() {
@@ -544,15 +1284,15 @@ mod test {
     #[test]
     fn test_map_empty() {
         let config = Config::default();
-        let m = UnalignedMemoryMapping::new(vec![], &config).unwrap();
+        let m = UnalignedMemoryMapping::new::<TestUserError>(vec![], &config).unwrap();
         assert!(matches!(
-            m.map(AccessType::Load, ebpf::MM_INPUT_START, 8),
+            m.map::<TestUserError>(AccessType::Load, ebpf::MM_INPUT_START, 8),
             ProgramResult::Err(EbpfError::AccessViolation(..))
         ));
 
-        let m = AlignedMemoryMapping::new(vec![], &config).unwrap();
+        let m = AlignedMemoryMapping::new::<TestUserError>(vec![], &config).unwrap();
         assert!(matches!(
-            m.map(AccessType::Load, ebpf::MM_INPUT_START, 8),
+            m.map::<TestUserError>(AccessType::Load, ebpf::MM_INPUT_START, 8),
             ProgramResult::Err(EbpfError::AccessViolation(..))
         ));
     }
@@ -563,7 +1303,7 @@ mod test {
         let mem1 = [1, 2, 3, 4];
         let mem2 = [5, 6];
         assert!(matches!(
-            UnalignedMemoryMapping::new(
+            UnalignedMemoryMapping::new::<TestUserError>(
                 vec![
                     MemoryRegion::new_readonly(&mem1, ebpf::MM_INPUT_START),
                     MemoryRegion::new_readonly(&mem2, ebpf::MM_INPUT_START + mem1.len() as u64 - 1),
@@ -573,7 +1313,7 @@ mod test {
             .unwrap_err(),
             EbpfError::InvalidMemoryRegion(1),
         ));
-        assert!(UnalignedMemoryMapping::new(
+        assert!(UnalignedMemoryMapping::new::<TestUserError>(
             vec![
                 MemoryRegion::new_readonly(&mem1, ebpf::MM_INPUT_START),
                 MemoryRegion::new_readonly(&mem2, ebpf::MM_INPUT_START + mem1.len() as u64),
@@ -590,7 +1330,7 @@ mod test {
         let mem2 = [22, 22];
         let mem3 = [33];
         let mem4 = [44, 44];
-        let m = UnalignedMemoryMapping::new(
+        let m = UnalignedMemoryMapping::new::<TestUserError>(
             vec![
                 MemoryRegion::new_readonly(&mem1, ebpf::MM_INPUT_START),
                 MemoryRegion::new_readonly(&mem2, ebpf::MM_INPUT_START + mem1.len() as u64),
@@ -608,12 +1348,13 @@ mod test {
         .unwrap();
 
         assert_eq!(
-            m.map(AccessType::Load, ebpf::MM_INPUT_START, 1).unwrap(),
+            m.map::<TestUserError>(AccessType::Load, ebpf::MM_INPUT_START, 1)
+                .unwrap(),
             mem1.as_ptr() as u64
         );
 
         assert_eq!(
-            m.map(
+            m.map::<TestUserError>(
                 AccessType::Load,
                 ebpf::MM_INPUT_START + mem1.len() as u64,
                 1
@@ -623,7 +1364,7 @@ mod test {
         );
 
         assert_eq!(
-            m.map(
+            m.map::<TestUserError>(
                 AccessType::Load,
                 ebpf::MM_INPUT_START + (mem1.len() + mem2.len()) as u64,
                 1
@@ -633,7 +1374,7 @@ mod test {
         );
 
         assert_eq!(
-            m.map(
+            m.map::<TestUserError>(
                 AccessType::Load,
                 ebpf::MM_INPUT_START + (mem1.len() + mem2.len() + mem3.len()) as u64,
                 1
@@ -643,7 +1384,7 @@ mod test {
         );
 
         assert!(matches!(
-            m.map(
+            m.map::<TestUserError>(
                 AccessType::Load,
                 ebpf::MM_INPUT_START + (mem1.len() + mem2.len() + mem3.len() + mem4.len()) as u64,
                 1
@@ -652,13 +1393,144 @@ mod test {
         ));
     }
 
+    #[test]
+    fn test_nearest_region() {
+        let config = Config::default();
+        let mem1 = [11; 8];
+        let mem2 = [22; 8];
+        let mem3 = [33; 8];
+        let m = UnalignedMemoryMapping::new::<TestUserError>(
+            vec![
+                MemoryRegion::new_readonly(&mem1, ebpf::MM_INPUT_START),
+                MemoryRegion::new_readonly(&mem2, ebpf::MM_INPUT_START + 0x100),
+                MemoryRegion::new_readonly(&mem3, ebpf::MM_INPUT_START + 0x200),
+            ],
+            &config,
+        )
+        .unwrap();
+        let nearest_vm_addr = |vm_addr| match m.map::<TestUserError>(AccessType::Load, vm_addr, 8) {
+            ProgramResult::Err(EbpfError::AccessViolation(_, _, _, _, _, nearest_region)) => {
+                nearest_region.map(|index| m.get_regions()[index].vm_addr)
+            }
+            _ => panic!(),
+        };
+        // Below the first region
+        assert_eq!(
+            nearest_vm_addr(ebpf::MM_INPUT_START - 0x10),
+            Some(ebpf::MM_INPUT_START)
+        );
+        // Running off the end of a region
+        assert_eq!(
+            nearest_vm_addr(ebpf::MM_INPUT_START + 0x108),
+            Some(ebpf::MM_INPUT_START + 0x100)
+        );
+        // In between two regions, closer to the second one
+        assert_eq!(
+            nearest_vm_addr(ebpf::MM_INPUT_START + 0x1f0),
+            Some(ebpf::MM_INPUT_START + 0x200)
+        );
+        // Above the last region
+        assert_eq!(
+            nearest_vm_addr(ebpf::MM_INPUT_START + 0x1000),
+            Some(ebpf::MM_INPUT_START + 0x200)
+        );
+        // Writing to a readonly region after a cached read
+        m.map::<TestUserError>(AccessType::Load, ebpf::MM_INPUT_START, 1)
+            .unwrap();
+        assert!(matches!(
+            m.map::<TestUserError>(AccessType::Store, ebpf::MM_INPUT_START, 1),
+            ProgramResult::Err(EbpfError::AccessViolation(_, _, _, _, _, Some(index)))
+                if m.get_regions()[index].vm_addr == ebpf::MM_INPUT_START
+        ));
+
+        let m = UnalignedMemoryMapping::new::<TestUserError>(vec![], &config).unwrap();
+        assert!(matches!(
+            m.map::<TestUserError>(AccessType::Load, ebpf::MM_INPUT_START, 8),
+            ProgramResult::Err(EbpfError::AccessViolation(_, _, _, _, _, None))
+        ));
+
+        let m = AlignedMemoryMapping::new::<TestUserError>(
+            vec![
+                MemoryRegion::new_readonly(&mem1, ebpf::MM_PROGRAM_START),
+                MemoryRegion::new_readonly(&mem2, ebpf::MM_STACK_START),
+            ],
+            &config,
+        )
+        .unwrap();
+        assert!(matches!(
+            m.map::<TestUserError>(AccessType::Load, ebpf::MM_STACK_START + 8, 8),
+            ProgramResult::Err(EbpfError::AccessViolation(_, _, _, _, _, Some(2)))
+        ));
+        assert!(matches!(
+            m.map::<TestUserError>(AccessType::Load, 0, 8),
+            ProgramResult::Err(EbpfError::AccessViolation(_, _, _, _, _, Some(1)))
+        ));
+        assert!(matches!(
+            m.map::<TestUserError>(AccessType::Load, ebpf::MM_INPUT_START, 8),
+            ProgramResult::Err(EbpfError::AccessViolation(_, _, _, _, _, Some(2)))
+        ));
+    }
+
+    #[test]
+    fn test_try_clone() {
+        for aligned_memory_mapping in [true, false] {
+            let config = Config {
+                aligned_memory_mapping,
+                ..Config::default()
+            };
+            let program = [11u8; 8];
+            let mut heap = [22u8; 8];
+            let m = MemoryMapping::new::<TestUserError>(
+                vec![
+                    MemoryRegion::new_readonly(&program, ebpf::MM_PROGRAM_START),
+                    MemoryRegion::new_writable(&mut heap, ebpf::MM_STACK_START),
+                ],
+                &config,
+            )
+            .unwrap();
+            let clone = m.try_clone::<TestUserError>().unwrap();
+            assert_eq!(
+                clone
+                    .map::<TestUserError>(AccessType::Load, ebpf::MM_PROGRAM_START, 8)
+                    .unwrap(),
+                program.as_ptr() as u64
+            );
+            let host_addr = clone
+                .map::<TestUserError>(AccessType::Store, ebpf::MM_STACK_START, 8)
+                .unwrap();
+            assert_ne!(host_addr, heap.as_ptr() as u64);
+            unsafe { *(host_addr as *mut u64) = 0 };
+            assert_eq!(heap, [22u8; 8]);
+            for (region, cloned_region) in m.get_regions().iter().zip(clone.get_regions()) {
+                assert_eq!(region.vm_addr, cloned_region.vm_addr);
+                assert_eq!(region.len, cloned_region.len);
+            }
+        }
+
+        let config = Config::default();
+        let mut mem = [0u8; 8];
+        let aliased = MemoryRegion::new_writable(&mut mem, ebpf::MM_INPUT_START);
+        let m = UnalignedMemoryMapping::new::<TestUserError>(
+            vec![
+                MemoryRegion::new_readonly(&mem, ebpf::MM_HEAP_START),
+                aliased,
+            ],
+            &config,
+        )
+        .unwrap();
+        assert!(matches!(
+            m.try_clone::<TestUserError>(),
+            Err(EbpfError::InvalidMemoryRegion(_))
+        ));
+    }
+
     #[test]
     fn test_unaligned_map_replace_region() {
         let config = Config::default();
         let mem1 = [11];
         let mem2 = [22, 22];
         let mem3 = [33];
-        let mut m = UnalignedMemoryMapping::new(
+        let mut m = UnalignedMemoryMapping::new::<TestUserError>(
             vec![
                 MemoryRegion::new_readonly(&mem1, ebpf::MM_INPUT_START),
                 MemoryRegion::new_readonly(&mem2, ebpf::MM_INPUT_START + mem1.len() as u64),
@@ -668,12 +1540,13 @@ mod test {
         .unwrap();
 
         assert_eq!(
-            m.map(AccessType::Load, ebpf::MM_INPUT_START, 1).unwrap(),
+            m.map::<TestUserError>(AccessType::Load, ebpf::MM_INPUT_START, 1)
+                .unwrap(),
             mem1.as_ptr() as u64
         );
 
         assert_eq!(
-            m.map(
+            m.map::<TestUserError>(
                 AccessType::Load,
                 ebpf::MM_INPUT_START + mem1.len() as u64,
                 1
@@ -683,7 +1556,7 @@ mod test {
         );
 
         assert!(matches!(
-            m.replace_region(
+            m.replace_region::<TestUserError>(
                 2,
                 MemoryRegion::new_readonly(&mem3, ebpf::MM_INPUT_START + mem1.len() as u64)
             ),
@@ -698,21 +1571,21 @@ mod test {
 
         // old.vm_addr != new.vm_addr
         assert!(matches!(
-            m.replace_region(
+            m.replace_region::<TestUserError>(
                 region_index,
                 MemoryRegion::new_readonly(&mem3, ebpf::MM_INPUT_START + mem1.len() as u64 + 1)
             ),
             Err(EbpfError::InvalidMemoryRegion(i)) if i == region_index
         ));
 
-        m.replace_region(
+        m.replace_region::<TestUserError>(
             region_index,
             MemoryRegion::new_readonly(&mem3, ebpf::MM_INPUT_START + mem1.len() as u64),
         )
         .unwrap();
 
         assert_eq!(
-            m.map(
+            m.map::<TestUserError>(
                 AccessType::Load,
                 ebpf::MM_INPUT_START + mem1.len() as u64,
                 1
@@ -728,7 +1601,7 @@ mod test {
         let mem1 = [11];
         let mem2 = [22, 22];
         let mem3 = [33, 33];
-        let mut m = AlignedMemoryMapping::new(
+        let mut m = AlignedMemoryMapping::new::<TestUserError>(
             vec![
                 MemoryRegion::new_readonly(&mem1, ebpf::MM_PROGRAM_START),
                 MemoryRegion::new_readonly(&mem2, ebpf::MM_STACK_START),
@@ -738,37 +1611,452 @@ mod test {
         .unwrap();
 
         assert_eq!(
-            m.map(AccessType::Load, ebpf::MM_STACK_START, 1).unwrap(),
+            m.map::<TestUserError>(AccessType::Load, ebpf::MM_STACK_START, 1)
+                .unwrap(),
             mem2.as_ptr() as u64
         );
 
         // index > regions.len()
         assert!(matches!(
-            m.replace_region(3, MemoryRegion::new_readonly(&mem3, ebpf::MM_STACK_START)),
+            m.replace_region::<TestUserError>(
+                3,
+                MemoryRegion::new_readonly(&mem3, ebpf::MM_STACK_START)
+            ),
             Err(EbpfError::InvalidMemoryRegion(3))
         ));
 
         // index != addr >> VIRTUAL_ADDRESS_BITS
         assert!(matches!(
-            m.replace_region(2, MemoryRegion::new_readonly(&mem3, ebpf::MM_HEAP_START)),
+            m.replace_region::<TestUserError>(
+                2,
+                MemoryRegion::new_readonly(&mem3, ebpf::MM_HEAP_START)
+            ),
             Err(EbpfError::InvalidMemoryRegion(2))
         ));
 
         // index + len != addr >> VIRTUAL_ADDRESS_BITS
         assert!(matches!(
-            m.replace_region(
+            m.replace_region::<TestUserError>(
                 2,
                 MemoryRegion::new_readonly(&mem3, ebpf::MM_HEAP_START - 1)
             ),
             Err(EbpfError::InvalidMemoryRegion(2))
         ));
 
-        m.replace_region(2, MemoryRegion::new_readonly(&mem3, ebpf::MM_STACK_START))
-            .unwrap();
+        m.replace_region::<TestUserError>(
+            2,
+            MemoryRegion::new_readonly(&mem3, ebpf::MM_STACK_START),
+        )
+        .unwrap();
 
         assert_eq!(
-            m.map(AccessType::Load, ebpf::MM_STACK_START, 1).unwrap(),
+            m.map::<TestUserError>(AccessType::Load, ebpf::MM_STACK_START, 1)
+                .unwrap(),
             mem3.as_ptr() as u64
         );
     }
+
+    #[test]
+    fn test_set_region_writable() {
+        for aligned_memory_mapping in [true, false] {
+            let config = Config {
+                aligned_memory_mapping,
+                ..Config::default()
+            };
+            let mem0 = [0];
+            let mut mem1 = [11];
+            let mut mem2 = [22, 22];
+            let mut m = MemoryMapping::new::<TestUserError>(
+                vec![
+                    MemoryRegion::new_readonly(&mem0, ebpf::MM_PROGRAM_START),
+                    MemoryRegion::new_writable(&mut mem1, ebpf::MM_STACK_START),
+                    MemoryRegion::new_writable(&mut mem2, ebpf::MM_HEAP_START),
+                ],
+                &config,
+            )
+            .unwrap();
+            let index = m
+                .get_regions()
+                .iter()
+                .position(|region| region.vm_addr == ebpf::MM_HEAP_START)
+                .unwrap();
+            let regions_len = m.get_regions().len();
+            assert!(matches!(
+                m.set_region_writable::<TestUserError>(regions_len, false),
+                Err(EbpfError::InvalidMemoryRegion(index)) if index == regions_len
+            ));
+
+            assert!(m
+                .map::<TestUserError>(AccessType::Store, ebpf::MM_HEAP_START, 2)
+                .is_ok());
+            m.set_region_writable::<TestUserError>(index, false)
+                .unwrap();
+            assert!(matches!(
+                m.map::<TestUserError>(AccessType::Store, ebpf::MM_HEAP_START, 2),
+                ProgramResult::Err(EbpfError::AccessViolation(
+                    0,
+                    AccessType::Store,
+                    ebpf::MM_HEAP_START,
+                    2,
+                    "heap",
+                    _
+                ))
+            ));
+            assert_eq!(
+                m.map::<TestUserError>(AccessType::Load, ebpf::MM_HEAP_START, 2)
+                    .unwrap(),
+                mem2.as_ptr() as u64
+            );
+            assert!(m
+                .map::<TestUserError>(AccessType::Store, ebpf::MM_STACK_START, 1)
+                .is_ok());
+            m.set_region_writable::<TestUserError>(index, true).unwrap();
+            assert!(m
+                .map::<TestUserError>(AccessType::Store, ebpf::MM_HEAP_START, 2)
+                .is_ok());
+        }
+    }
+
+    #[test]
+    fn test_region_by_name() {
+        for aligned_memory_mapping in [true, false] {
+            let config = Config {
+                aligned_memory_mapping,
+                ..Config::default()
+            };
+            let input = [1; 4];
+            let mut scratch = [2; 8];
+            let mut output = [3; 2];
+            let m = MemoryMapping::new_with_names::<TestUserError>(
+                vec![
+                    (
+                        "input",
+                        MemoryRegion::new_readonly(&input, ebpf::MM_PROGRAM_START),
+                    ),
+                    (
+                        "scratch",
+                        MemoryRegion::new_writable(&mut scratch, ebpf::MM_STACK_START),
+                    ),
+                    (
+                        "output",
+                        MemoryRegion::new_writable(&mut output, ebpf::MM_HEAP_START),
+                    ),
+                ],
+                &config,
+            )
+            .unwrap();
+            let region = m.region_by_name("scratch").unwrap();
+            assert_eq!(region.vm_addr, ebpf::MM_STACK_START);
+            assert_eq!(region.host_addr, scratch.as_ptr() as u64);
+            assert_eq!(
+                m.region_by_name("input").unwrap().vm_addr,
+                ebpf::MM_PROGRAM_START
+            );
+            assert!(!m.region_by_name("input").unwrap().is_writable);
+            assert_eq!(m.region_by_name("output").unwrap().len, 2);
+            assert!(m.region_by_name("heap").is_none());
+
+            // Each region keeps its own bounds
+            assert!(m
+                .map::<TestUserError>(AccessType::Store, ebpf::MM_STACK_START + 4, 4)
+                .is_ok());
+            assert!(matches!(
+                m.map::<TestUserError>(AccessType::Store, ebpf::MM_STACK_START + 4, 5),
+                ProgramResult::Err(EbpfError::AccessViolation(..))
+            ));
+            assert_eq!(
+                m.try_clone::<TestUserError>()
+                    .unwrap()
+                    .region_by_name("output")
+                    .unwrap()
+                    .vm_addr,
+                ebpf::MM_HEAP_START
+            );
+
+            assert!(matches!(
+                MemoryMapping::new_with_names::<TestUserError>(
+                    vec![
+                        (
+                            "input",
+                            MemoryRegion::new_readonly(&input, ebpf::MM_PROGRAM_START)
+                        ),
+                        (
+                            "input",
+                            MemoryRegion::new_readonly(&input, ebpf::MM_STACK_START)
+                        ),
+                    ],
+                    &config,
+                ),
+                Err(EbpfError::InvalidMemoryRegion(1))
+            ));
+        }
+    }
+
+    #[test]
+    fn test_host_to_vm_addr() {
+        for aligned_memory_mapping in [true, false] {
+            let config = Config {
+                aligned_memory_mapping,
+                ..Config::default()
+            };
+            // Only the first half is mapped, the rest is host memory right behind the region
+            let input = [1; 8];
+            let mut stack = [2; 8];
+            let outside = [3; 2];
+            let m = MemoryMapping::new::<TestUserError>(
+                vec![
+                    MemoryRegion::new_readonly(&input[..4], ebpf::MM_PROGRAM_START),
+                    MemoryRegion::new_writable_gapped(&mut stack, ebpf::MM_STACK_START, 4),
+                ],
+                &config,
+            )
+            .unwrap();
+            for vm_addr in [
+                ebpf::MM_PROGRAM_START,
+                ebpf::MM_PROGRAM_START + 3,
+                ebpf::MM_STACK_START,
+                ebpf::MM_STACK_START + 3,
+                ebpf::MM_STACK_START + 8,
+                ebpf::MM_STACK_START + 11,
+            ] {
+                let host_addr = m
+                    .map::<TestUserError>(AccessType::Load, vm_addr, 1)
+                    .unwrap();
+                assert_eq!(m.host_to_vm_addr(host_addr as *const u8), Some(vm_addr));
+            }
+            assert_eq!(
+                m.host_to_vm_addr(stack.as_ptr().wrapping_add(5)),
+                Some(ebpf::MM_STACK_START + 9)
+            );
+            assert_eq!(m.host_to_vm_addr(&input[4]), None);
+            assert_eq!(m.host_to_vm_addr(outside.as_ptr()), None);
+            assert_eq!(m.host_to_vm_addr(core::ptr::null()), None);
+        }
+    }
+
+    #[test]
+    fn test_translate_slice() {
+        let config = Config {
+            aligned_memory_mapping: false,
+            ..Config::default()
+        };
+        let mut mem0 = [0u64, 1, 2, 3];
+        let host_addr = mem0.as_ptr() as u64;
+        let mem1 = [0u8; 4];
+        let mut m = MemoryMapping::new::<TestUserError>(
+            vec![
+                MemoryRegion::new_writable(
+                    unsafe { std::slice::from_raw_parts_mut(mem0.as_mut_ptr() as *mut u8, 32) },
+                    ebpf::MM_INPUT_START,
+                ),
+                MemoryRegion::new_readonly(&mem1, ebpf::MM_HEAP_START),
+            ],
+            &config,
+        )
+        .unwrap();
+
+        let slice = m
+            .translate_slice::<u64, TestUserError>(ebpf::MM_INPUT_START + 8, 3, AccessType::Load)
+            .unwrap();
+        assert_eq!(slice, &[1, 2, 3]);
+        assert!(m
+            .translate_slice::<u8, TestUserError>(ebpf::MM_HEAP_START, 0, AccessType::Load)
+            .unwrap()
+            .is_empty());
+        m.translate_slice_mut::<u64, TestUserError>(ebpf::MM_INPUT_START, 1)
+            .unwrap()[0] = 42;
+        assert_eq!(
+            m.map::<TestUserError>(AccessType::Load, ebpf::MM_INPUT_START, 8)
+                .unwrap(),
+            host_addr
+        );
+        assert_eq!(unsafe { *(host_addr as *const u64) }, 42);
+
+        // Past the end of the region
+        assert!(matches!(
+            m.translate_slice::<u64, TestUserError>(ebpf::MM_INPUT_START + 8, 4, AccessType::Load),
+            Err(EbpfError::AccessViolation(..))
+        ));
+        // Readonly region
+        assert!(matches!(
+            m.translate_slice_mut::<u8, TestUserError>(ebpf::MM_HEAP_START, 4),
+            Err(EbpfError::AccessViolation(..))
+        ));
+        // Misaligned
+        assert!(matches!(
+            m.translate_slice::<u64, TestUserError>(ebpf::MM_INPUT_START + 1, 1, AccessType::Load),
+            Err(EbpfError::InvalidVirtualAddress(vm_addr)) if vm_addr == ebpf::MM_INPUT_START + 1
+        ));
+        // Size overflow
+        assert!(matches!(
+            m.translate_slice::<u64, TestUserError>(
+                ebpf::MM_INPUT_START,
+                u64::MAX / 4,
+                AccessType::Load
+            ),
+            Err(EbpfError::InvalidVirtualAddress(ebpf::MM_INPUT_START))
+        ));
+    }
+
+    #[test]
+    fn test_translate_slice_max_syscall_buffer_len() {
+        let config = Config {
+            aligned_memory_mapping: false,
+            max_syscall_buffer_len: 64 * 1024,
+            ..Config::default()
+        };
+        let mut mem0 = vec![0u8; 64 * 1024 + 1];
+        let m = MemoryMapping::new::<TestUserError>(
+            vec![MemoryRegion::new_writable(&mut mem0, ebpf::MM_INPUT_START)],
+            &config,
+        )
+        .unwrap();
+
+        assert_eq!(
+            m.translate_slice::<u8, TestUserError>(
+                ebpf::MM_INPUT_START,
+                64 * 1024,
+                AccessType::Load
+            )
+            .unwrap()
+            .len(),
+            64 * 1024
+        );
+        assert!(matches!(
+            m.translate_slice::<u8, TestUserError>(
+                ebpf::MM_INPUT_START,
+                64 * 1024 + 1,
+                AccessType::Load
+            ),
+            Err(EbpfError::SyscallBufferTooLarge(ebpf::MM_INPUT_START, len)) if len == 64 * 1024 + 1
+        ));
+        assert!(matches!(
+            m.translate_slice::<u8, TestUserError>(
+                ebpf::MM_INPUT_START,
+                2 * 1024 * 1024 * 1024,
+                AccessType::Load
+            ),
+            Err(EbpfError::SyscallBufferTooLarge(ebpf::MM_INPUT_START, len)) if len == 2 * 1024 * 1024 * 1024
+        ));
+        // The limit applies to the size in bytes
+        assert!(matches!(
+            m.translate_slice::<u64, TestUserError>(
+                ebpf::MM_INPUT_START,
+                8 * 1024 + 1,
+                AccessType::Load
+            ),
+            Err(EbpfError::SyscallBufferTooLarge(..))
+        ));
+    }
+
+    #[test]
+    #[cfg(all(feature = "memory-mapped-files", unix))]
+    fn test_mapped_file_region() {
+        use std::io::Write;
+        let path = std::env::temp_dir().join(format!("rbpf_mapped_file_{}", std::process::id()));
+        let mut file = std::fs::File::create(&path).unwrap();
+        file.write_all(&(0..=255).collect::<Vec<u8>>()).unwrap();
+        drop(file);
+        let file = std::fs::File::open(&path).unwrap();
+        // The range is neither page aligned nor fully backed by the file
+        // Safety: the file is neither truncated nor modified until it is unmapped
+        let mapped_file = unsafe { MappedFile::new(&file, 250, 16) }.unwrap();
+        assert_eq!(mapped_file.as_slice(), &[250, 251, 252, 253, 254, 255]);
+        assert!(unsafe { MappedFile::new(&file, 300, 16) }
+            .unwrap()
+            .as_slice()
+            .is_empty());
+        std::fs::remove_file(&path).unwrap();
+
+        for aligned_memory_mapping in [true, false] {
+            let config = Config {
+                aligned_memory_mapping,
+                ..Config::default()
+            };
+            let mem0 = [0];
+            let m = MemoryMapping::new::<TestUserError>(
+                vec![
+                    MemoryRegion::new_readonly(&mem0, ebpf::MM_PROGRAM_START),
+                    MemoryRegion::new_readonly(&[], ebpf::MM_STACK_START),
+                    MemoryRegion::new_readonly(&[], ebpf::MM_HEAP_START),
+                    MemoryRegion::new_from_mapped_file(&mapped_file, ebpf::MM_INPUT_START),
+                ],
+                &config,
+            )
+            .unwrap();
+            assert_eq!(
+                m.translate_slice::<u8, TestUserError>(
+                    ebpf::MM_INPUT_START + 2,
+                    4,
+                    AccessType::Load
+                )
+                .unwrap(),
+                &[252, 253, 254, 255]
+            );
+            assert!(matches!(
+                m.map::<TestUserError>(AccessType::Store, ebpf::MM_INPUT_START, 1),
+                ProgramResult::Err(EbpfError::AccessViolation(..))
+            ));
+            assert!(matches!(
+                m.map::<TestUserError>(AccessType::Load, ebpf::MM_INPUT_START + 4, 4),
+                ProgramResult::Err(EbpfError::AccessViolation(..))
+            ));
+        }
+    }
+
+    #[test]
+    #[cfg(feature = "access-counters")]
+    fn test_access_counts() {
+        for aligned_memory_mapping in [true, false] {
+            let config = Config {
+                aligned_memory_mapping,
+                ..Config::default()
+            };
+            let mem0 = [0];
+            let mut mem1 = [0; 8];
+            let mut mem2 = [0; 8];
+            let mut m = MemoryMapping::new::<TestUserError>(
+                vec![
+                    MemoryRegion::new_readonly(&mem0, ebpf::MM_PROGRAM_START),
+                    MemoryRegion::new_writable(&mut mem1, ebpf::MM_STACK_START),
+                    MemoryRegion::new_writable(&mut mem2, ebpf::MM_HEAP_START),
+                ],
+                &config,
+            )
+            .unwrap();
+            let position = |m: &MemoryMapping, vm_addr| {
+                m.get_regions()
+                    .iter()
+                    .position(|region| region.vm_addr == vm_addr)
+                    .unwrap()
+            };
+            let stack_index = position(&m, ebpf::MM_STACK_START);
+            let heap_index = position(&m, ebpf::MM_HEAP_START);
+
+            for _ in 0..3 {
+                assert!(m
+                    .map::<TestUserError>(AccessType::Store, ebpf::MM_STACK_START, 8)
+                    .is_ok());
+            }
+            assert!(m
+                .map::<TestUserError>(AccessType::Load, ebpf::MM_HEAP_START + 4, 4)
+                .is_ok());
+            // Failed accesses are not counted
+            assert!(m
+                .map::<TestUserError>(AccessType::Load, ebpf::MM_HEAP_START + 4, 8)
+                .is_err());
+            assert!(m
+                .map::<TestUserError>(AccessType::Load, ebpf::MM_INPUT_START, 1)
+                .is_err());
+            let regions_len = m.get_regions().len();
+            let counts = m.access_counts();
+            assert_eq!(counts.len(), regions_len);
+            assert_eq!(counts[stack_index], 3);
+            assert_eq!(counts[heap_index], 1);
+            assert_eq!(counts.iter().sum::<u64>(), 4);
+
+            m.reset_access_counts();
+            assert!(m.access_counts().iter().all(|count| *count == 0));
+        }
+    }
 }
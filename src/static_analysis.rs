@@ -1,7 +1,10 @@
 #![allow(clippy::integer_arithmetic)]
 //! Static Byte Code Analysis
 
-use crate::disassembler::disassemble_instruction;
+use crate::disassembler::{
+    disassemble_instruction_to_string, disassemble_instruction_to_string_with_format,
+    JumpTargetFormat,
+};
 use crate::{
     ebpf,
     elf::{self, Executable},
@@ -104,6 +107,121 @@ pub struct DfgEdge {
     pub resource: DataResource,
 }
 
+/// A typed edge leaving a basic block of the [`ControlFlowGraph`]
+///
+/// Targets are instruction indices of basic block starts.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Edge {
+    /// Execution continues with the next basic block
+    Fallthrough(usize),
+    /// Taken branch of a conditional jump
+    ConditionalJump(usize),
+    /// Unconditional jump
+    UnconditionalJump(usize),
+    /// BPF to BPF call, the call returns to the fallthrough edge
+    Call(usize),
+    /// Return to the caller
+    Return,
+    /// Indirect call (callx), the call returns to the fallthrough edge
+    IndirectCall,
+    /// Jump or call to a target which is not a basic block
+    Unknown,
+}
+
+impl Edge {
+    /// Name of the edge kind as used in the JSON output
+    pub fn kind(&self) -> &'static str {
+        match self {
+            Edge::Fallthrough(_) => "fallthrough",
+            Edge::ConditionalJump(_) => "conditional_jump",
+            Edge::UnconditionalJump(_) => "unconditional_jump",
+            Edge::Call(_) => "call",
+            Edge::Return => "return",
+            Edge::IndirectCall => "indirect_call",
+            Edge::Unknown => "unknown",
+        }
+    }
+
+    /// Instruction index the edge leads to, if it is statically known
+    pub fn target(&self) -> Option<usize> {
+        match self {
+            Edge::Fallthrough(target)
+            | Edge::ConditionalJump(target)
+            | Edge::UnconditionalJump(target)
+            | Edge::Call(target) => Some(*target),
+            Edge::Return | Edge::IndirectCall | Edge::Unknown => None,
+        }
+    }
+}
+
+/// A basic block of the [`ControlFlowGraph`]
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct BasicBlock {
+    /// Instruction index of the first instruction
+    pub start: usize,
+    /// Instruction index after the last instruction (exclusive)
+    pub end: usize,
+    /// Edges leaving the end of this basic block
+    pub edges: Vec<Edge>,
+    /// Instruction index and hash of every syscall made from within this basic block
+    pub syscalls: Vec<(usize, u32)>,
+}
+
+/// Control-flow graph of an executable, see [`analyze_control_flow`]
+#[derive(Clone, PartialEq, Eq, Debug, Default)]
+pub struct ControlFlowGraph {
+    /// Instruction index where the execution starts
+    pub entrypoint: usize,
+    /// Basic blocks ordered by their start
+    pub blocks: Vec<BasicBlock>,
+}
+
+impl ControlFlowGraph {
+    /// Serializes the control-flow graph as JSON
+    ///
+    /// The schema is `{"entrypoint":pc,"blocks":[{"start":pc,"end":pc,"edges":[{"kind":kind,"target":pc}],"syscalls":[{"pc":pc,"hash":hash}]}]}`
+    /// where `target` is omitted for edges without a statically known target, see [`Edge::kind`].
+    pub fn write_json<W: std::io::Write>(&self, output: &mut W) -> std::io::Result<()> {
+        write!(output, "{{\"entrypoint\":{},\"blocks\":[", self.entrypoint)?;
+        for (index, block) in self.blocks.iter().enumerate() {
+            if index > 0 {
+                write!(output, ",")?;
+            }
+            write!(
+                output,
+                "{{\"start\":{},\"end\":{},\"edges\":[",
+                block.start, block.end
+            )?;
+            for (index, edge) in block.edges.iter().enumerate() {
+                if index > 0 {
+                    write!(output, ",")?;
+                }
+                write!(output, "{{\"kind\":\"{}\"", edge.kind())?;
+                if let Some(target) = edge.target() {
+                    write!(output, ",\"target\":{}", target)?;
+                }
+                write!(output, "}}")?;
+            }
+            write!(output, "],\"syscalls\":[")?;
+            for (index, (pc, hash)) in block.syscalls.iter().enumerate() {
+                if index > 0 {
+                    write!(output, ",")?;
+                }
+                write!(output, "{{\"pc\":{},\"hash\":{}}}", pc, hash)?;
+            }
+            write!(output, "]}}")?;
+        }
+        write!(output, "]}}")
+    }
+
+    /// Serializes the control-flow graph as a JSON string, see [`ControlFlowGraph::write_json`]
+    pub fn to_json(&self) -> String {
+        let mut output = Vec::new();
+        self.write_json(&mut output).unwrap();
+        String::from_utf8(output).unwrap()
+    }
+}
+
 impl Default for CfgNode {
     fn default() -> Self {
         Self {
@@ -142,7 +260,7 @@ pub struct Analysis<'a, C: ContextObject> {
 
 impl<'a, C: ContextObject> Analysis<'a, C> {
     /// Analyze an executable statically
-    pub fn from_executable(executable: &'a Executable<C>) -> Result<Self, EbpfError> {
+    pub fn from_executable(executable: &'a Executable<C>) -> Result<Self, EbpfError<C::UserError>> {
         let (_program_vm_addr, program) = executable.get_text_bytes();
         let mut functions = BTreeMap::new();
         for (key, (pc, name)) in executable.get_function_registry().iter() {
@@ -292,7 +410,29 @@ impl<'a, C: ContextObject> Analysis<'a, C> {
                 | ebpf::JSGT_REG
                 | ebpf::JSGE_REG
                 | ebpf::JSLT_REG
-                | ebpf::JSLE_REG => {
+                | ebpf::JSLE_REG
+                | ebpf::JEQ32_IMM
+                | ebpf::JGT32_IMM
+                | ebpf::JGE32_IMM
+                | ebpf::JLT32_IMM
+                | ebpf::JLE32_IMM
+                | ebpf::JSET32_IMM
+                | ebpf::JNE32_IMM
+                | ebpf::JSGT32_IMM
+                | ebpf::JSGE32_IMM
+                | ebpf::JSLT32_IMM
+                | ebpf::JSLE32_IMM
+                | ebpf::JEQ32_REG
+                | ebpf::JGT32_REG
+                | ebpf::JGE32_REG
+                | ebpf::JLT32_REG
+                | ebpf::JLE32_REG
+                | ebpf::JSET32_REG
+                | ebpf::JNE32_REG
+                | ebpf::JSGT32_REG
+                | ebpf::JSGE32_REG
+                | ebpf::JSLT32_REG
+                | ebpf::JSLE32_REG => {
                     self.cfg_nodes
                         .entry(insn.ptr + 1)
                         .or_insert_with(CfgNode::default);
@@ -440,6 +580,16 @@ impl<'a, C: ContextObject> Analysis<'a, C> {
 
     /// Generates assembler code for the analyzed executable
     pub fn disassemble<W: std::io::Write>(&self, output: &mut W) -> std::io::Result<()> {
+        self.disassemble_with_format(output, JumpTargetFormat::Label)
+    }
+
+    /// Generates assembler code for the analyzed executable, rendering the targets of jumps and
+    /// calls as specified by `format`
+    pub fn disassemble_with_format<W: std::io::Write>(
+        &self,
+        output: &mut W,
+        format: JumpTargetFormat,
+    ) -> std::io::Result<()> {
         let mut last_basic_block = usize::MAX;
         for insn in self.instructions.iter() {
             self.disassemble_label(
@@ -448,11 +598,112 @@ impl<'a, C: ContextObject> Analysis<'a, C> {
                 insn.ptr,
                 &mut last_basic_block,
             )?;
-            writeln!(output, "    {}", disassemble_instruction(insn, self))?;
+            writeln!(
+                output,
+                "    {}",
+                disassemble_instruction_to_string_with_format(insn, self, format)
+            )?;
         }
         Ok(())
     }
 
+    /// Builds the public control-flow graph from the basic blocks
+    pub fn control_flow_graph(&self) -> ControlFlowGraph {
+        let mut blocks = Vec::with_capacity(self.cfg_nodes.len());
+        let program_end = self
+            .instructions
+            .last()
+            .map(|insn| insn.ptr + if insn.opc == ebpf::LD_DW_IMM { 2 } else { 1 })
+            .unwrap_or(0);
+        for (start, cfg_node) in self.cfg_nodes.iter() {
+            if cfg_node.instructions.is_empty() {
+                continue;
+            }
+            let last_insn = &self.instructions[cfg_node.instructions.end - 1];
+            let end = if cfg_node.instructions.end < self.instructions.len() {
+                self.instructions[cfg_node.instructions.end].ptr
+            } else {
+                program_end
+            };
+            let block_edge =
+                |target_pc: usize| Some(target_pc).filter(|pc| self.cfg_nodes.contains_key(pc));
+            let fallthrough = (end < program_end).then_some(Edge::Fallthrough(end));
+            let jump_target = (last_insn.ptr as isize + last_insn.off as isize + 1) as usize;
+            let edges = match last_insn.opc {
+                ebpf::EXIT => vec![Edge::Return],
+                ebpf::JA => vec![block_edge(jump_target)
+                    .map(Edge::UnconditionalJump)
+                    .unwrap_or(Edge::Unknown)],
+                ebpf::CALL_REG => vec![Some(Edge::IndirectCall), fallthrough]
+                    .into_iter()
+                    .flatten()
+                    .collect(),
+                ebpf::CALL_IMM
+                    if self
+                        .executable
+                        .get_syscall_symbols()
+                        .get(&(last_insn.imm as u32))
+                        .map(|name| name == "abort")
+                        .unwrap_or(false) =>
+                {
+                    Vec::new()
+                }
+                ebpf::CALL_IMM => {
+                    let call = self
+                        .executable
+                        .lookup_bpf_function(last_insn.imm as u32)
+                        .filter(|_| {
+                            !self
+                                .executable
+                                .get_syscall_symbols()
+                                .contains_key(&(last_insn.imm as u32))
+                        })
+                        .map(|target_pc| {
+                            block_edge(target_pc)
+                                .map(Edge::Call)
+                                .unwrap_or(Edge::Unknown)
+                        });
+                    vec![call, fallthrough].into_iter().flatten().collect()
+                }
+                opc if matches!(opc & ebpf::BPF_CLS_MASK, ebpf::BPF_JMP | ebpf::BPF_JMP32) => vec![
+                    Some(
+                        block_edge(jump_target)
+                            .map(Edge::ConditionalJump)
+                            .unwrap_or(Edge::Unknown),
+                    ),
+                    fallthrough,
+                ]
+                .into_iter()
+                .flatten()
+                .collect(),
+                _ => fallthrough.into_iter().collect(),
+            };
+            let config = self.executable.get_config();
+            let syscalls = self.instructions[cfg_node.instructions.clone()]
+                .iter()
+                .filter(|insn| {
+                    insn.opc == ebpf::CALL_IMM
+                        && (!config.static_syscalls || insn.src == 0)
+                        && self
+                            .executable
+                            .get_syscall_registry()
+                            .contains_syscall(insn.imm as u32)
+                })
+                .map(|insn| (insn.ptr, insn.imm as u32))
+                .collect();
+            blocks.push(BasicBlock {
+                start: *start,
+                end,
+                edges,
+                syscalls,
+            });
+        }
+        ControlFlowGraph {
+            entrypoint: self.entrypoint,
+            blocks,
+        }
+    }
+
     /// Iterates over the cfg_nodes while providing the PC range of the function they belong to.
     pub fn iter_cfg_by_function(
         &self,
@@ -500,7 +751,7 @@ impl<'a, C: ContextObject> Analysis<'a, C> {
                 cfg_node_start,
                 analysis.instructions[cfg_node.instructions.clone()].iter()
                 .map(|insn| {
-                    let desc = disassemble_instruction(insn, analysis);
+                    let desc = disassemble_instruction_to_string(insn, analysis);
                     if let Some(split_index) = desc.find(' ') {
                         let mut rest = desc[split_index+1..].to_string();
                         if rest.len() > MAX_CELL_CONTENT_LENGTH + 1 {
@@ -933,6 +1184,18 @@ impl<'a, C: ContextObject> Analysis<'a, C> {
                             bind(&mut state, insn, false, DataResource::Register(insn.dst));
                             bind(&mut state, insn, true, DataResource::Memory);
                         }
+                        ebpf::ST_W_ATOMIC | ebpf::ST_DW_ATOMIC => {
+                            bind(&mut state, insn, false, DataResource::Register(insn.src));
+                            bind(&mut state, insn, false, DataResource::Register(insn.dst));
+                            bind(&mut state, insn, false, DataResource::Memory);
+                            if insn.imm == ebpf::ATOMIC_CMPXCHG {
+                                bind(&mut state, insn, false, DataResource::Register(0));
+                                bind(&mut state, insn, true, DataResource::Register(0));
+                            } else if insn.imm & ebpf::BPF_FETCH != 0 {
+                                bind(&mut state, insn, true, DataResource::Register(insn.src));
+                            }
+                            bind(&mut state, insn, true, DataResource::Memory);
+                        }
                         ebpf::ADD32_IMM
                         | ebpf::SUB32_IMM
                         | ebpf::MUL32_IMM
@@ -964,6 +1227,11 @@ impl<'a, C: ContextObject> Analysis<'a, C> {
                             bind(&mut state, insn, false, DataResource::Register(insn.dst));
                             bind(&mut state, insn, true, DataResource::Register(insn.dst));
                         }
+                        ebpf::UHMUL64_IMM => {
+                            bind(&mut state, insn, false, DataResource::Register(insn.dst));
+                            bind(&mut state, insn, true, DataResource::Register(insn.dst));
+                            bind(&mut state, insn, true, DataResource::Register(insn.dst + 1));
+                        }
                         ebpf::MOV32_IMM | ebpf::MOV64_IMM => {
                             bind(&mut state, insn, true, DataResource::Register(insn.dst));
                         }
@@ -995,6 +1263,12 @@ impl<'a, C: ContextObject> Analysis<'a, C> {
                             bind(&mut state, insn, false, DataResource::Register(insn.dst));
                             bind(&mut state, insn, true, DataResource::Register(insn.dst));
                         }
+                        ebpf::UHMUL64_REG => {
+                            bind(&mut state, insn, false, DataResource::Register(insn.src));
+                            bind(&mut state, insn, false, DataResource::Register(insn.dst));
+                            bind(&mut state, insn, true, DataResource::Register(insn.dst));
+                            bind(&mut state, insn, true, DataResource::Register(insn.dst + 1));
+                        }
                         ebpf::MOV32_REG | ebpf::MOV64_REG => {
                             bind(&mut state, insn, false, DataResource::Register(insn.src));
                             bind(&mut state, insn, true, DataResource::Register(insn.dst));
@@ -1009,7 +1283,18 @@ impl<'a, C: ContextObject> Analysis<'a, C> {
                         | ebpf::JSGT_IMM
                         | ebpf::JSGE_IMM
                         | ebpf::JSLT_IMM
-                        | ebpf::JSLE_IMM => {
+                        | ebpf::JSLE_IMM
+                        | ebpf::JEQ32_IMM
+                        | ebpf::JGT32_IMM
+                        | ebpf::JGE32_IMM
+                        | ebpf::JLT32_IMM
+                        | ebpf::JLE32_IMM
+                        | ebpf::JSET32_IMM
+                        | ebpf::JNE32_IMM
+                        | ebpf::JSGT32_IMM
+                        | ebpf::JSGE32_IMM
+                        | ebpf::JSLT32_IMM
+                        | ebpf::JSLE32_IMM => {
                             bind(&mut state, insn, false, DataResource::Register(insn.dst));
                         }
                         ebpf::JEQ_REG
@@ -1022,7 +1307,18 @@ impl<'a, C: ContextObject> Analysis<'a, C> {
                         | ebpf::JSGT_REG
                         | ebpf::JSGE_REG
                         | ebpf::JSLT_REG
-                        | ebpf::JSLE_REG => {
+                        | ebpf::JSLE_REG
+                        | ebpf::JEQ32_REG
+                        | ebpf::JGT32_REG
+                        | ebpf::JGE32_REG
+                        | ebpf::JLT32_REG
+                        | ebpf::JLE32_REG
+                        | ebpf::JSET32_REG
+                        | ebpf::JNE32_REG
+                        | ebpf::JSGT32_REG
+                        | ebpf::JSGE32_REG
+                        | ebpf::JSLT32_REG
+                        | ebpf::JSLE32_REG => {
                             bind(&mut state, insn, false, DataResource::Register(insn.src));
                             bind(&mut state, insn, false, DataResource::Register(insn.dst));
                         }
@@ -1141,3 +1437,12 @@ impl<'a, C: ContextObject> Analysis<'a, C> {
         }
     }
 }
+
+/// Analyzes the control-flow of an executable
+///
+/// Returns the basic blocks and the typed edges between them.
+pub fn analyze_control_flow<C: ContextObject>(
+    executable: &Executable<C>,
+) -> Result<ControlFlowGraph, EbpfError<C::UserError>> {
+    Ok(Analysis::from_executable(executable)?.control_flow_graph())
+}
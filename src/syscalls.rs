@@ -22,10 +22,13 @@
 //! respect this convention.
 
 use crate::{
+    error::EbpfError,
+    keccak::Keccak256,
     memory_region::{AccessType, MemoryMapping},
-    vm::{ProgramResult, TestContextObject},
+    sha256::Sha256,
+    vm::{ContextObject, ProgramResult, TestContextObject, TestUserError},
 };
-use std::{slice::from_raw_parts, str::from_utf8};
+use std::str::from_utf8;
 
 /// Error handling for syscall methods
 macro_rules! question_mark {
@@ -58,11 +61,11 @@ pub const BPF_TRACE_PRINTK_IDX: u32 = 6;
 /// ```
 /// use solana_rbpf::syscalls::bpf_trace_printf;
 /// use solana_rbpf::memory_region::{MemoryRegion, MemoryMapping};
-/// use solana_rbpf::vm::{Config, ProgramResult, TestContextObject};
+/// use solana_rbpf::vm::{Config, ProgramResult, TestContextObject, TestUserError};
 ///
 /// let mut result = ProgramResult::Ok(0);
 /// let config = Config::default();
-/// let mut memory_mapping = MemoryMapping::new(vec![], &config).unwrap();
+/// let mut memory_mapping = MemoryMapping::new::<TestUserError>(vec![], &config).unwrap();
 /// bpf_trace_printf(&mut TestContextObject::default(), 0, 0, 1, 15, 32, &mut memory_mapping, &mut result);
 /// assert_eq!(result.unwrap() as usize, "bpf_trace_printf: 0x1, 0xf, 0x20\n".len());
 /// ```
@@ -95,7 +98,7 @@ pub fn bpf_trace_printf(
     arg4: u64,
     arg5: u64,
     _memory_mapping: &mut MemoryMapping,
-    result: &mut ProgramResult,
+    result: &mut ProgramResult<TestUserError>,
 ) {
     println!("bpf_trace_printf: {:#x}, {:#x}, {:#x}", arg3, arg4, arg5);
     let size_arg = |x| {
@@ -123,11 +126,11 @@ pub fn bpf_trace_printf(
 /// ```
 /// use solana_rbpf::syscalls::bpf_gather_bytes;
 /// use solana_rbpf::memory_region::{MemoryRegion, MemoryMapping};
-/// use solana_rbpf::vm::{Config, ProgramResult, TestContextObject};
+/// use solana_rbpf::vm::{Config, ProgramResult, TestContextObject, TestUserError};
 ///
 /// let mut result = ProgramResult::Ok(0);
 /// let config = Config::default();
-/// let mut memory_mapping = MemoryMapping::new(vec![], &config).unwrap();
+/// let mut memory_mapping = MemoryMapping::new::<TestUserError>(vec![], &config).unwrap();
 /// bpf_gather_bytes(&mut TestContextObject::default(), 0x11, 0x22, 0x33, 0x44, 0x55, &mut memory_mapping, &mut result);
 /// assert_eq!(result.unwrap(), 0x1122334455);
 /// ```
@@ -139,7 +142,7 @@ pub fn bpf_gather_bytes(
     arg4: u64,
     arg5: u64,
     _memory_mapping: &mut MemoryMapping,
-    result: &mut ProgramResult,
+    result: &mut ProgramResult<TestUserError>,
 ) {
     *result = ProgramResult::Ok(
         arg1.wrapping_shl(32)
@@ -159,14 +162,14 @@ pub fn bpf_gather_bytes(
 /// ```
 /// use solana_rbpf::syscalls::bpf_mem_frob;
 /// use solana_rbpf::memory_region::{MemoryRegion, MemoryMapping};
-/// use solana_rbpf::vm::{Config, ProgramResult, TestContextObject};
+/// use solana_rbpf::vm::{Config, ProgramResult, TestContextObject, TestUserError};
 ///
 /// let mut val = &mut [0x00, 0x00, 0x00, 0x00, 0x00, 0x11, 0x22, 0x33];
 /// let val_va = 0x100000000;
 ///
 /// let mut result = ProgramResult::Ok(0);
 /// let config = Config::default();
-/// let mut memory_mapping = MemoryMapping::new(vec![MemoryRegion::new_writable(val, val_va)], &config).unwrap();
+/// let mut memory_mapping = MemoryMapping::new::<TestUserError>(vec![MemoryRegion::new_writable(val, val_va)], &config).unwrap();
 /// bpf_mem_frob(&mut TestContextObject::default(), val_va, 8, 0, 0, 0, &mut memory_mapping, &mut result);
 /// assert_eq!(val, &[0x2a, 0x2a, 0x2a, 0x2a, 0x2a, 0x3b, 0x08, 0x19]);
 /// bpf_mem_frob(&mut TestContextObject::default(), val_va, 8, 0, 0, 0, &mut memory_mapping, &mut result);
@@ -180,18 +183,203 @@ pub fn bpf_mem_frob(
     _arg4: u64,
     _arg5: u64,
     memory_mapping: &mut MemoryMapping,
-    result: &mut ProgramResult,
+    result: &mut ProgramResult<TestUserError>,
 ) {
-    let host_addr = question_mark!(memory_mapping.map(AccessType::Store, vm_addr, len), result);
-    for i in 0..len {
-        unsafe {
-            let p = (host_addr + i) as *mut u8;
-            *p ^= 0b101010;
+    let slice = match memory_mapping.translate_slice_mut::<u8, _>(vm_addr, len) {
+        Ok(slice) => slice,
+        Err(err) => {
+            *result = ProgramResult::Err(err);
+            return;
         }
+    };
+    for byte in slice.iter_mut() {
+        *byte ^= 0b101010;
     }
     *result = ProgramResult::Ok(0);
 }
 
+/// Returned by `bpf_probe_read()` if the source can not be read, `-EFAULT` like in Linux
+pub const BPF_PROBE_READ_EFAULT: u64 = -14i64 as u64;
+
+/// Index of syscall `bpf_probe_read()` in Linux kernel.
+pub const BPF_PROBE_READ_IDX: u32 = 4;
+
+/// Copies `size` bytes from `unsafe_ptr` to `dst`, like `bpf_probe_read()` in Linux.
+///
+/// Returns 0 on success. If the source range can not be read, `dst` is zero filled and
+/// `BPF_PROBE_READ_EFAULT` is returned instead of aborting the program, so that it can handle
+/// bad pointers. A destination which can not be written aborts the program as usual.
+///
+/// # Examples
+///
+/// ```
+/// use solana_rbpf::syscalls::{bpf_probe_read, BPF_PROBE_READ_EFAULT};
+/// use solana_rbpf::memory_region::{MemoryRegion, MemoryMapping};
+/// use solana_rbpf::vm::{Config, ProgramResult, TestContextObject, TestUserError};
+///
+/// let src = [0x11, 0x22, 0x33, 0x44];
+/// let dst = &mut [0xff; 4];
+/// let src_va = 0x100000000;
+/// let dst_va = 0x200000000;
+///
+/// let config = Config {
+///     aligned_memory_mapping: false,
+///     ..Config::default()
+/// };
+/// let mut memory_mapping = MemoryMapping::new::<TestUserError>(vec![MemoryRegion::new_readonly(&src, src_va), MemoryRegion::new_writable(dst, dst_va)], &config).unwrap();
+/// let mut result = ProgramResult::Ok(0);
+/// bpf_probe_read(&mut TestContextObject::default(), dst_va, 4, src_va, 0, 0, &mut memory_mapping, &mut result);
+/// assert_eq!(result.unwrap(), 0);
+/// assert_eq!(dst, &src);
+/// let mut result = ProgramResult::Ok(0);
+/// bpf_probe_read(&mut TestContextObject::default(), dst_va, 4, src_va + 2, 0, 0, &mut memory_mapping, &mut result);
+/// assert_eq!(result.unwrap(), BPF_PROBE_READ_EFAULT);
+/// assert_eq!(dst, &[0; 4]);
+/// ```
+pub fn bpf_probe_read<C: ContextObject>(
+    _context_object: &mut C,
+    dst: u64,
+    size: u64,
+    unsafe_ptr: u64,
+    _arg4: u64,
+    _arg5: u64,
+    memory_mapping: &mut MemoryMapping,
+    result: &mut ProgramResult<C::UserError>,
+) {
+    let dst = match memory_mapping.translate_slice_mut::<u8, _>(dst, size) {
+        Ok(slice) => slice.as_mut_ptr(),
+        Err(err) => {
+            *result = ProgramResult::Err(err);
+            return;
+        }
+    };
+    *result = match memory_mapping.translate_slice::<u8, C::UserError>(
+        unsafe_ptr,
+        size,
+        AccessType::Load,
+    ) {
+        Ok(src) => {
+            // The ranges may overlap
+            unsafe { std::ptr::copy(src.as_ptr(), dst, src.len()) };
+            ProgramResult::Ok(0)
+        }
+        Err(_) => {
+            unsafe { std::ptr::write_bytes(dst, 0, size as usize) };
+            ProgramResult::Ok(BPF_PROBE_READ_EFAULT)
+        }
+    };
+}
+
+/// Size in bytes of the digests written by `bpf_sha256()` and `bpf_keccak256()`
+pub const HASH_RESULT_LEN: u64 = 32;
+
+/// Translates the input and output buffers of a hashing syscall and writes the digest
+fn hash_syscall<C: ContextObject>(
+    input_addr: u64,
+    input_len: u64,
+    output_addr: u64,
+    memory_mapping: &mut MemoryMapping,
+    result: &mut ProgramResult<C::UserError>,
+    digest: impl FnOnce(&[u8]) -> [u8; HASH_RESULT_LEN as usize],
+) {
+    let output = match memory_mapping.translate_slice_mut::<u8, _>(output_addr, HASH_RESULT_LEN) {
+        Ok(slice) => slice.as_mut_ptr(),
+        Err(err) => {
+            *result = ProgramResult::Err(err);
+            return;
+        }
+    };
+    *result = match memory_mapping.translate_slice::<u8, C::UserError>(
+        input_addr,
+        input_len,
+        AccessType::Load,
+    ) {
+        Ok(input) => {
+            let digest = digest(input);
+            // The buffers may overlap, so the output is only written once the input was consumed
+            unsafe { std::ptr::copy_nonoverlapping(digest.as_ptr(), output, digest.len()) };
+            ProgramResult::Ok(0)
+        }
+        Err(err) => ProgramResult::Err(err),
+    };
+}
+
+/// Writes the SHA-256 digest of `input_len` bytes at `input_addr` to the 32 bytes at `output_addr`.
+///
+/// Returns 0. Both buffers are checked against the memory mapping, an invalid one aborts the
+/// program. The digest is computed in portable code, so it is the same on every platform.
+///
+/// # Examples
+///
+/// ```
+/// use solana_rbpf::syscalls::bpf_sha256;
+/// use solana_rbpf::memory_region::{MemoryRegion, MemoryMapping};
+/// use solana_rbpf::vm::{Config, ProgramResult, TestContextObject, TestUserError};
+///
+/// let input = b"abc";
+/// let output = &mut [0; 32];
+/// let input_va = 0x100000000;
+/// let output_va = 0x200000000;
+///
+/// let config = Config {
+///     aligned_memory_mapping: false,
+///     ..Config::default()
+/// };
+/// let mut memory_mapping = MemoryMapping::new::<TestUserError>(vec![MemoryRegion::new_readonly(input, input_va), MemoryRegion::new_writable(output, output_va)], &config).unwrap();
+/// let mut result = ProgramResult::Ok(0);
+/// bpf_sha256(&mut TestContextObject::default(), input_va, 3, output_va, 0, 0, &mut memory_mapping, &mut result);
+/// assert_eq!(result.unwrap(), 0);
+/// assert_eq!(output[..4], [0xba, 0x78, 0x16, 0xbf]);
+/// ```
+pub fn bpf_sha256<C: ContextObject>(
+    _context_object: &mut C,
+    input_addr: u64,
+    input_len: u64,
+    output_addr: u64,
+    _arg4: u64,
+    _arg5: u64,
+    memory_mapping: &mut MemoryMapping,
+    result: &mut ProgramResult<C::UserError>,
+) {
+    hash_syscall::<C>(
+        input_addr,
+        input_len,
+        output_addr,
+        memory_mapping,
+        result,
+        |input| {
+            let mut hasher = Sha256::default();
+            hasher.update(input);
+            hasher.finalize()
+        },
+    );
+}
+
+/// Same as `bpf_sha256()` but computes the Keccak-256 digest, as used by Ethereum.
+pub fn bpf_keccak256<C: ContextObject>(
+    _context_object: &mut C,
+    input_addr: u64,
+    input_len: u64,
+    output_addr: u64,
+    _arg4: u64,
+    _arg5: u64,
+    memory_mapping: &mut MemoryMapping,
+    result: &mut ProgramResult<C::UserError>,
+) {
+    hash_syscall::<C>(
+        input_addr,
+        input_len,
+        output_addr,
+        memory_mapping,
+        result,
+        |input| {
+            let mut hasher = Keccak256::default();
+            hasher.update(input);
+            hasher.finalize()
+        },
+    );
+}
+
 /// C-like `strcmp`, return 0 if the strings are equal, and a non-null value otherwise.
 ///
 /// # Examples
@@ -199,7 +387,7 @@ pub fn bpf_mem_frob(
 /// ```
 /// use solana_rbpf::syscalls::bpf_str_cmp;
 /// use solana_rbpf::memory_region::{MemoryRegion, MemoryMapping};
-/// use solana_rbpf::vm::{Config, ProgramResult, TestContextObject};
+/// use solana_rbpf::vm::{Config, ProgramResult, TestContextObject, TestUserError};
 ///
 /// let foo = "This is a string.";
 /// let bar = "This is another sting.";
@@ -208,11 +396,11 @@ pub fn bpf_mem_frob(
 ///
 /// let mut result = ProgramResult::Ok(0);
 /// let config = Config::default();
-/// let mut memory_mapping = MemoryMapping::new(vec![MemoryRegion::new_readonly(foo.as_bytes(), va_foo)], &config).unwrap();
+/// let mut memory_mapping = MemoryMapping::new::<TestUserError>(vec![MemoryRegion::new_readonly(foo.as_bytes(), va_foo)], &config).unwrap();
 /// bpf_str_cmp(&mut TestContextObject::default(), va_foo, va_foo, 0, 0, 0, &mut memory_mapping, &mut result);
 /// assert!(result.unwrap() == 0);
 /// let mut result = ProgramResult::Ok(0);
-/// let mut memory_mapping = MemoryMapping::new(vec![MemoryRegion::new_readonly(foo.as_bytes(), va_foo), MemoryRegion::new_readonly(bar.as_bytes(), va_bar)], &config).unwrap();
+/// let mut memory_mapping = MemoryMapping::new::<TestUserError>(vec![MemoryRegion::new_readonly(foo.as_bytes(), va_foo), MemoryRegion::new_readonly(bar.as_bytes(), va_bar)], &config).unwrap();
 /// bpf_str_cmp(&mut TestContextObject::default(), va_foo, va_bar, 0, 0, 0, &mut memory_mapping, &mut result);
 /// assert!(result.unwrap() != 0);
 /// ```
@@ -224,7 +412,7 @@ pub fn bpf_str_cmp(
     _arg4: u64,
     _arg5: u64,
     memory_mapping: &mut MemoryMapping,
-    result: &mut ProgramResult,
+    result: &mut ProgramResult<TestUserError>,
 ) {
     // C-like strcmp, maybe shorter than converting the bytes to string and comparing?
     if arg1 == 0 || arg2 == 0 {
@@ -252,6 +440,163 @@ pub fn bpf_str_cmp(
 
 // Some additional syscalls
 
+/// Bump allocator over the heap region of the context object, `sol_alloc_free_` style.
+///
+/// Allocates `size` bytes aligned to 8 and returns their virtual address if `free_addr` is 0,
+/// returns 0 if the heap is exhausted or the allocation limit of the allocator is reached.
+/// Freeing is a no-op which always returns 0.
+///
+/// # Examples
+///
+/// ```
+/// use solana_rbpf::allocator::BpfAllocator;
+/// use solana_rbpf::ebpf::MM_HEAP_START;
+/// use solana_rbpf::syscalls::bpf_alloc_free;
+/// use solana_rbpf::memory_region::{MemoryRegion, MemoryMapping};
+/// use solana_rbpf::vm::{Config, ProgramResult, TestContextObject, TestUserError};
+///
+/// let mut context_object = TestContextObject::default();
+/// context_object.allocator = BpfAllocator::new(MM_HEAP_START, 16);
+/// let config = Config::default();
+/// let mut memory_mapping = MemoryMapping::new::<TestUserError>(vec![], &config).unwrap();
+/// let mut result = ProgramResult::Ok(0);
+/// bpf_alloc_free(&mut context_object, 12, 0, 0, 0, 0, &mut memory_mapping, &mut result);
+/// assert_eq!(result.unwrap(), MM_HEAP_START);
+/// let mut result = ProgramResult::Ok(0);
+/// bpf_alloc_free(&mut context_object, 8, 0, 0, 0, 0, &mut memory_mapping, &mut result);
+/// assert_eq!(result.unwrap(), 0);
+/// ```
+pub fn bpf_alloc_free(
+    context_object: &mut TestContextObject,
+    size: u64,
+    free_addr: u64,
+    _arg3: u64,
+    _arg4: u64,
+    _arg5: u64,
+    _memory_mapping: &mut MemoryMapping,
+    result: &mut ProgramResult<TestUserError>,
+) {
+    *result = ProgramResult::Ok(if free_addr == 0 {
+        context_object.allocator.alloc(size).unwrap_or(0)
+    } else {
+        0
+    });
+}
+
+/// Turns the memory region containing `vm_addr` readonly.
+///
+/// Only downgrades are possible, so that programs can not gain write access to memory which the
+/// host mapped readonly. Returns 0 on success.
+///
+/// # Examples
+///
+/// ```
+/// use solana_rbpf::ebpf::MM_HEAP_START;
+/// use solana_rbpf::syscalls::bpf_set_region_readonly;
+/// use solana_rbpf::memory_region::{AccessType, MemoryRegion, MemoryMapping};
+/// use solana_rbpf::vm::{Config, ProgramResult, TestContextObject, TestUserError};
+///
+/// let mut heap = [0u8; 8];
+/// let regions = vec![MemoryRegion::new_writable(&mut heap, MM_HEAP_START)];
+/// let config = Config {
+///     aligned_memory_mapping: false,
+///     ..Config::default()
+/// };
+/// let mut memory_mapping = MemoryMapping::new::<TestUserError>(regions, &config).unwrap();
+/// assert!(memory_mapping.map::<TestUserError>(AccessType::Store, MM_HEAP_START + 4, 4).is_ok());
+/// let mut result = ProgramResult::Ok(0);
+/// bpf_set_region_readonly(&mut TestContextObject::default(), MM_HEAP_START + 4, 0, 0, 0, 0, &mut memory_mapping, &mut result);
+/// assert_eq!(result.unwrap(), 0);
+/// assert!(memory_mapping.map::<TestUserError>(AccessType::Store, MM_HEAP_START + 4, 4).is_err());
+/// assert!(memory_mapping.map::<TestUserError>(AccessType::Load, MM_HEAP_START + 4, 4).is_ok());
+/// ```
+pub fn bpf_set_region_readonly(
+    _context_object: &mut TestContextObject,
+    vm_addr: u64,
+    _arg2: u64,
+    _arg3: u64,
+    _arg4: u64,
+    _arg5: u64,
+    memory_mapping: &mut MemoryMapping,
+    result: &mut ProgramResult<TestUserError>,
+) {
+    let index = memory_mapping.get_regions().iter().position(|region| {
+        (region.vm_addr..region.vm_addr.saturating_add(region.len)).contains(&vm_addr)
+    });
+    *result = match index {
+        Some(index) => match memory_mapping.set_region_writable(index, false) {
+            Ok(()) => ProgramResult::Ok(0),
+            Err(err) => ProgramResult::Err(err),
+        },
+        None => ProgramResult::Err(EbpfError::InvalidVirtualAddress(vm_addr)),
+    };
+}
+
+/// Returns the number of instructions the program can still execute, `sol_remaining_compute_units` style.
+///
+/// Both the interpreter and the JIT charge the instructions executed so far to the context object
+/// before dispatching a syscall, so this is the live state of the instruction meter.
+///
+/// # Examples
+///
+/// ```
+/// use solana_rbpf::syscalls::bpf_remaining_compute_units;
+/// use solana_rbpf::memory_region::MemoryMapping;
+/// use solana_rbpf::vm::{Config, ProgramResult, TestContextObject, TestUserError};
+///
+/// let config = Config::default();
+/// let mut memory_mapping = MemoryMapping::new::<TestUserError>(vec![], &config).unwrap();
+/// let mut result = ProgramResult::Ok(0);
+/// bpf_remaining_compute_units(&mut TestContextObject::new(42), 0, 0, 0, 0, 0, &mut memory_mapping, &mut result);
+/// assert_eq!(result.unwrap(), 42);
+/// ```
+pub fn bpf_remaining_compute_units<C: ContextObject>(
+    context_object: &mut C,
+    _arg1: u64,
+    _arg2: u64,
+    _arg3: u64,
+    _arg4: u64,
+    _arg5: u64,
+    _memory_mapping: &mut MemoryMapping,
+    result: &mut ProgramResult<C::UserError>,
+) {
+    *result = ProgramResult::Ok(context_object.get_remaining());
+}
+
+/// Blocks all further syscalls of the program, returns 0.
+///
+/// Only has an effect if `Config::enable_syscall_sealing` is set, then every later syscall
+/// (including this one) fails with `EbpfError::SyscallsSealed`.
+///
+/// # Examples
+///
+/// ```
+/// use solana_rbpf::syscalls::bpf_seal_syscalls;
+/// use solana_rbpf::memory_region::MemoryMapping;
+/// use solana_rbpf::vm::{Config, ContextObject, ProgramResult, TestContextObject, TestUserError};
+///
+/// let config = Config::default();
+/// let mut memory_mapping = MemoryMapping::new::<TestUserError>(vec![], &config).unwrap();
+/// let mut result = ProgramResult::Ok(1);
+/// let mut context_object = TestContextObject::default();
+/// bpf_seal_syscalls(&mut context_object, 0, 0, 0, 0, 0, &mut memory_mapping, &mut result);
+/// assert_eq!(result.unwrap(), 0);
+/// assert!(context_object.syscalls_sealed());
+/// ```
+pub fn bpf_seal_syscalls<C: ContextObject>(
+    context_object: &mut C,
+    _arg1: u64,
+    _arg2: u64,
+    _arg3: u64,
+    _arg4: u64,
+    _arg5: u64,
+    _memory_mapping: &mut MemoryMapping,
+    result: &mut ProgramResult<C::UserError>,
+) {
+    context_object.seal_syscalls();
+    *result = ProgramResult::Ok(0);
+}
+
 /// Prints a NULL-terminated UTF-8 string.
 pub fn bpf_syscall_string(
     _context_object: &mut TestContextObject,
@@ -261,21 +606,17 @@ pub fn bpf_syscall_string(
     _arg4: u64,
     _arg5: u64,
     memory_mapping: &mut MemoryMapping,
-    result: &mut ProgramResult,
+    result: &mut ProgramResult<TestUserError>,
 ) {
-    let host_addr = question_mark!(memory_mapping.map(AccessType::Load, vm_addr, len), result);
-    let c_buf: *const i8 = host_addr as *const i8;
-    unsafe {
-        for i in 0..len {
-            let c = std::ptr::read(c_buf.offset(i as isize));
-            if c == 0 {
-                break;
-            }
+    let slice = match memory_mapping.translate_slice::<u8, _>(vm_addr, len, AccessType::Load) {
+        Ok(slice) => slice,
+        Err(err) => {
+            *result = ProgramResult::Err(err);
+            return;
         }
-        let message = from_utf8(from_raw_parts(host_addr as *const u8, len as usize))
-            .unwrap_or("Invalid UTF-8 String");
-        println!("log: {}", message);
-    }
+    };
+    let message = from_utf8(slice).unwrap_or("Invalid UTF-8 String");
+    println!("log: {}", message);
     *result = ProgramResult::Ok(0);
 }
 
@@ -288,7 +629,7 @@ pub fn bpf_syscall_u64(
     arg4: u64,
     arg5: u64,
     memory_mapping: &mut MemoryMapping,
-    result: &mut ProgramResult,
+    result: &mut ProgramResult<TestUserError>,
 ) {
     println!(
         "dump_64: {:#x}, {:#x}, {:#x}, {:#x}, {:#x}, {:?}",
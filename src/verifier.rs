@@ -25,63 +25,154 @@
 
 use crate::{
     ebpf,
-    vm::{Config, FunctionRegistry},
+    elf::hash_bpf_function,
+    error::Error,
+    vm::{Config, ContextObject, FunctionRegistry, SyscallRegistry},
 };
-use thiserror::Error;
+use alloc::{string::String, vec, vec::Vec};
+use core::{fmt, ops::Range};
 
 /// Error definitions
-#[derive(Debug, Error, Eq, PartialEq)]
+#[derive(Debug, Eq, PartialEq)]
 pub enum VerifierError {
     /// ProgramLengthNotMultiple
-    #[error("program length must be a multiple of {} octets", ebpf::INSN_SIZE)]
     ProgramLengthNotMultiple,
     /// Deprecated
-    #[error("Deprecated")]
     ProgramTooLarge(usize),
     /// NoProgram
-    #[error("no program set, call prog_set() to load one")]
     NoProgram,
     /// Division by zero
-    #[error("division by 0 (insn #{0})")]
     DivisionByZero(usize),
     /// UnsupportedLEBEArgument
-    #[error("unsupported argument for LE/BE (insn #{0})")]
     UnsupportedLEBEArgument(usize),
     /// LDDWCannotBeLast
-    #[error("LD_DW instruction cannot be last in program")]
     LDDWCannotBeLast,
     /// IncompleteLDDW
-    #[error("incomplete LD_DW instruction (insn #{0})")]
     IncompleteLDDW(usize),
     /// InfiniteLoop
-    #[error("infinite loop (insn #{0})")]
     InfiniteLoop(usize),
-    /// JumpOutOfCode
-    #[error("jump out of code to #{0} (insn #{1})")]
+    /// Deprecated, see JumpOutOfBounds
+    #[deprecated(note = "use JumpOutOfBounds")]
     JumpOutOfCode(usize, usize),
-    /// JumpToMiddleOfLDDW
-    #[error("jump to middle of LD_DW at #{0} (insn #{1})")]
+    /// Jump whose target lies before the first or after the last instruction, carries the target index
+    JumpOutOfBounds(usize, i64),
+    /// Jump or call into the second slot of a LD_DW, carries the target index
     JumpToMiddleOfLDDW(usize, usize),
     /// InvalidSourceRegister
-    #[error("invalid source register (insn #{0})")]
     InvalidSourceRegister(usize),
     /// CannotWriteR10
-    #[error("cannot write into register r10 (insn #{0})")]
     CannotWriteR10(usize),
     /// InvalidDestinationRegister
-    #[error("invalid destination register (insn #{0})")]
     InvalidDestinationRegister(usize),
     /// UnknownOpCode
-    #[error("unknown eBPF opcode {0:#2x} (insn #{1:?})")]
     UnknownOpCode(u8, usize),
     /// Shift with overflow
-    #[error("Shift with overflow of {0}-bit value by {1} (insn #{2:?})")]
     ShiftWithOverflow(u64, u64, usize),
     /// Invalid register specified
-    #[error("Invalid register specified at instruction {0}")]
     InvalidRegister(usize),
+    /// StackDepthExceeded
+    StackDepthExceeded(usize, usize),
+    /// UnboundedStackDepth
+    UnboundedStackDepth(usize),
+    /// UnsupportedAtomicOperation
+    UnsupportedAtomicOperation(i64, usize),
+    /// UnknownSyscall
+    UnknownSyscall(u32, usize),
+    /// InvalidWideImmediate
+    InvalidWideImmediate(usize),
+    /// RegisterNotInitialized
+    RegisterNotInitialized(usize, usize),
+    /// FallsOffEnd
+    FallsOffEnd(usize),
+    /// OpcodeNotAllowed, see `Config::allowed_opcodes`
+    OpcodeNotAllowed(usize, u8),
 }
 
+impl fmt::Display for VerifierError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::ProgramLengthNotMultiple => write!(
+                f,
+                "program length must be a multiple of {} octets",
+                ebpf::INSN_SIZE
+            ),
+            Self::ProgramTooLarge(_) => write!(f, "Deprecated"),
+            Self::NoProgram => write!(f, "no program set, call prog_set() to load one"),
+            Self::DivisionByZero(pc) => write!(f, "division by 0 (insn #{})", pc),
+            Self::UnsupportedLEBEArgument(pc) => {
+                write!(f, "unsupported argument for LE/BE (insn #{})", pc)
+            }
+            Self::LDDWCannotBeLast => write!(f, "LD_DW instruction cannot be last in program"),
+            Self::IncompleteLDDW(pc) => write!(f, "incomplete LD_DW instruction (insn #{})", pc),
+            Self::InfiniteLoop(pc) => write!(f, "infinite loop (insn #{})", pc),
+            #[allow(deprecated)]
+            Self::JumpOutOfCode(target, pc) => {
+                write!(f, "jump out of code to #{} (insn #{})", target, pc)
+            }
+            Self::JumpOutOfBounds(pc, target) => {
+                write!(f, "jump out of bounds to #{} (insn #{})", target, pc)
+            }
+            Self::JumpToMiddleOfLDDW(target, pc) => {
+                write!(f, "jump to middle of LD_DW at #{} (insn #{})", target, pc)
+            }
+            Self::InvalidSourceRegister(pc) => write!(f, "invalid source register (insn #{})", pc),
+            Self::CannotWriteR10(pc) => write!(f, "cannot write into register r10 (insn #{})", pc),
+            Self::InvalidDestinationRegister(pc) => {
+                write!(f, "invalid destination register (insn #{})", pc)
+            }
+            Self::UnknownOpCode(opcode, pc) => {
+                write!(f, "unknown eBPF opcode {:#2x} (insn #{:?})", opcode, pc)
+            }
+            Self::ShiftWithOverflow(width, shift, pc) => write!(
+                f,
+                "Shift with overflow of {}-bit value by {} (insn #{:?})",
+                width, shift, pc
+            ),
+            Self::InvalidRegister(pc) => {
+                write!(f, "Invalid register specified at instruction {}", pc)
+            }
+            Self::StackDepthExceeded(depth, pc) => write!(
+                f,
+                "stack depth of {} bytes exceeds the stack size (insn #{})",
+                depth, pc
+            ),
+            Self::UnboundedStackDepth(pc) => write!(
+                f,
+                "stack depth can not be bounded because of recursion or callx (insn #{})",
+                pc
+            ),
+            Self::UnsupportedAtomicOperation(operation, pc) => write!(
+                f,
+                "unsupported atomic operation {:#x} (insn #{})",
+                operation, pc
+            ),
+            Self::UnknownSyscall(hash, pc) => {
+                write!(f, "unknown syscall {:#x} (insn #{})", hash, pc)
+            }
+            Self::InvalidWideImmediate(pc) => {
+                write!(f, "invalid second slot of LD_DW instruction (insn #{})", pc)
+            }
+            Self::RegisterNotInitialized(pc, reg) => write!(
+                f,
+                "register r{} may be read before it is initialized (insn #{})",
+                reg, pc
+            ),
+            Self::FallsOffEnd(pc) => write!(
+                f,
+                "execution continues past the end of the program (insn #{})",
+                pc
+            ),
+            Self::OpcodeNotAllowed(pc, opcode) => write!(
+                f,
+                "eBPF opcode {:#2x} is not allowed (insn #{})",
+                opcode, pc
+            ),
+        }
+    }
+}
+
+impl Error for VerifierError {}
+
 /// eBPF Verifier
 pub trait Verifier {
     /// eBPF verification function that returns an error if the program does not meet its requirements.
@@ -138,25 +229,71 @@ fn check_load_dw(prog: &[u8], insn_ptr: usize) -> Result<(), VerifierError> {
     if next_insn.opc != 0 {
         return Err(VerifierError::IncompleteLDDW(adj_insn_ptr(insn_ptr)));
     }
+    if !ebpf::is_valid_lddw_tail(&next_insn) {
+        return Err(VerifierError::InvalidWideImmediate(adj_insn_ptr(insn_ptr)));
+    }
     Ok(())
 }
 
-fn check_jmp_offset(prog: &[u8], insn_ptr: usize) -> Result<(), VerifierError> {
+/// Checks that the last instruction does not continue with the one behind the program, see `Config::reject_falls_off_end`
+fn check_last_instruction(prog: &[u8], is_lddw_tail: bool) -> Result<(), VerifierError> {
+    let insn_ptr = prog.len() / ebpf::INSN_SIZE - 1;
+    if is_lddw_tail {
+        return Err(VerifierError::LDDWCannotBeLast);
+    }
+    match ebpf::get_insn(prog, insn_ptr).opc {
+        ebpf::EXIT | ebpf::JA => Ok(()),
+        _ => Err(VerifierError::FallsOffEnd(adj_insn_ptr(insn_ptr))),
+    }
+}
+
+/// Checks that the jump stays inside the program and records it for check_jump_targets
+fn check_jmp_offset(
+    prog: &[u8],
+    insn_ptr: usize,
+    jump_targets: &mut Vec<(usize, usize)>,
+) -> Result<(), VerifierError> {
     let insn = ebpf::get_insn(prog, insn_ptr);
 
     let dst_insn_ptr = insn_ptr as isize + 1 + insn.off as isize;
     if dst_insn_ptr < 0 || dst_insn_ptr as usize * ebpf::INSN_SIZE >= prog.len() {
-        return Err(VerifierError::JumpOutOfCode(
-            dst_insn_ptr as usize,
+        return Err(VerifierError::JumpOutOfBounds(
             adj_insn_ptr(insn_ptr),
+            dst_insn_ptr as i64,
         ));
     }
-    let dst_insn = ebpf::get_insn(prog, dst_insn_ptr as usize);
-    if dst_insn.opc == 0 {
-        return Err(VerifierError::JumpToMiddleOfLDDW(
-            dst_insn_ptr as usize,
-            adj_insn_ptr(insn_ptr),
-        ));
+    jump_targets.push((insn_ptr, dst_insn_ptr as usize));
+    Ok(())
+}
+
+/// Records the target of a BPF to BPF call for check_jump_targets
+fn check_call_target(
+    insn: &ebpf::Insn,
+    insn_ptr: usize,
+    config: &Config,
+    function_registry: &FunctionRegistry,
+    jump_targets: &mut Vec<(usize, usize)>,
+) {
+    if config.static_syscalls && insn.src == 0 {
+        return;
+    }
+    if let Some((target_pc, _name)) = function_registry.get(&(insn.imm as u32)) {
+        jump_targets.push((insn_ptr, *target_pc));
+    }
+}
+
+/// Check that no jump or call lands on the second slot of a LD_DW instruction
+fn check_jump_targets(
+    jump_targets: &[(usize, usize)],
+    lddw_tails: &[bool],
+) -> Result<(), VerifierError> {
+    for (insn_ptr, target_pc) in jump_targets {
+        if lddw_tails.get(*target_pc).copied().unwrap_or(false) {
+            return Err(VerifierError::JumpToMiddleOfLDDW(
+                *target_pc,
+                adj_insn_ptr(*insn_ptr),
+            ));
+        }
     }
     Ok(())
 }
@@ -185,6 +322,38 @@ fn check_registers(
     }
 }
 
+/// Check that the high half of a register pair destination is writable
+fn check_register_pair(insn: &ebpf::Insn, insn_ptr: usize) -> Result<(), VerifierError> {
+    if insn.dst >= 9 {
+        return Err(VerifierError::InvalidDestinationRegister(adj_insn_ptr(
+            insn_ptr,
+        )));
+    }
+    Ok(())
+}
+
+/// Check that the imm is a valid atomic operation and that the fetched value is not written to r10
+fn check_imm_atomic(insn: &ebpf::Insn, insn_ptr: usize) -> Result<(), VerifierError> {
+    match insn.imm {
+        ebpf::ATOMIC_ADD | ebpf::ATOMIC_OR | ebpf::ATOMIC_AND | ebpf::ATOMIC_XOR => Ok(()),
+        ebpf::ATOMIC_CMPXCHG => Ok(()),
+        ebpf::ATOMIC_FETCH_ADD
+        | ebpf::ATOMIC_FETCH_OR
+        | ebpf::ATOMIC_FETCH_AND
+        | ebpf::ATOMIC_FETCH_XOR
+        | ebpf::ATOMIC_XCHG => {
+            if insn.src == ebpf::FRAME_PTR_REG as u8 {
+                return Err(VerifierError::CannotWriteR10(adj_insn_ptr(insn_ptr)));
+            }
+            Ok(())
+        }
+        _ => Err(VerifierError::UnsupportedAtomicOperation(
+            insn.imm,
+            adj_insn_ptr(insn_ptr),
+        )),
+    }
+}
+
 /// Check that the imm is a valid shift operand
 fn check_imm_shift(insn: &ebpf::Insn, insn_ptr: usize, imm_bits: u64) -> Result<(), VerifierError> {
     let shift_by = insn.imm as u64;
@@ -210,159 +379,730 @@ fn check_imm_register(
     Ok(())
 }
 
+/// Check that no chain of BPF to BPF calls can use more stack than available
+///
+/// Recursion and callx can not be bounded statically. Depending on
+/// `config.reject_unbounded_stack_depth` they are either rejected or left to
+/// the runtime call depth check.
+fn check_stack_depth(
+    prog: &[u8],
+    config: &Config,
+    function_registry: &FunctionRegistry,
+) -> Result<(), VerifierError> {
+    #[derive(Clone, Copy, PartialEq, Eq)]
+    enum VisitState {
+        Unvisited,
+        Active,
+        Done,
+    }
+
+    let insn_count = prog.len() / ebpf::INSN_SIZE;
+    let mut function_starts: Vec<usize> = function_registry
+        .values()
+        .map(|(pc, _name)| *pc)
+        .filter(|pc| *pc < insn_count)
+        .chain(core::iter::once(0))
+        .collect();
+    function_starts.sort_unstable();
+    function_starts.dedup();
+    let function_index = |insn_ptr: usize| {
+        function_starts
+            .partition_point(|start| *start <= insn_ptr)
+            .saturating_sub(1)
+    };
+
+    // Frame size and outgoing calls (call insn_ptr, callee) of every function
+    let mut frame_sizes = vec![
+        if config.dynamic_stack_frames {
+            0
+        } else {
+            config.stack_frame_size
+        };
+        function_starts.len()
+    ];
+    let mut calls: Vec<Vec<(usize, Option<usize>)>> = vec![Vec::new(); function_starts.len()];
+    let mut stack_ptr_offset: i64 = 0;
+    let mut insn_ptr: usize = 0;
+    while insn_ptr < insn_count {
+        let insn = ebpf::get_insn(prog, insn_ptr);
+        let function = function_index(insn_ptr);
+        if function_starts[function] == insn_ptr {
+            stack_ptr_offset = 0;
+        }
+        match insn.opc {
+            ebpf::LD_DW_IMM => insn_ptr += 1,
+            ebpf::SUB64_IMM | ebpf::ADD64_IMM
+                if config.dynamic_stack_frames && insn.dst as usize == ebpf::STACK_PTR_REG =>
+            {
+                stack_ptr_offset = if insn.opc == ebpf::SUB64_IMM {
+                    stack_ptr_offset.saturating_add(insn.imm)
+                } else {
+                    stack_ptr_offset.saturating_sub(insn.imm)
+                };
+                frame_sizes[function] = frame_sizes[function].max(stack_ptr_offset.max(0) as usize);
+            }
+            ebpf::CALL_IMM if !config.static_syscalls || insn.src != 0 => {
+                if let Some((target_pc, _name)) = function_registry.get(&(insn.imm as u32)) {
+                    if let Ok(callee) = function_starts.binary_search(target_pc) {
+                        calls[function].push((insn_ptr, Some(callee)));
+                    }
+                }
+            }
+            ebpf::CALL_REG => calls[function].push((insn_ptr, None)),
+            _ => {}
+        }
+        insn_ptr += 1;
+    }
+
+    // Longest chain of frames starting at every function and the call it begins with
+    let mut depths = frame_sizes.clone();
+    let mut deepest_calls: Vec<Option<usize>> = vec![None; function_starts.len()];
+    let mut visit_states = vec![VisitState::Unvisited; function_starts.len()];
+    for root in 0..function_starts.len() {
+        if visit_states[root] != VisitState::Unvisited {
+            continue;
+        }
+        visit_states[root] = VisitState::Active;
+        let mut stack = vec![(root, 0)];
+        while let Some((function, next_call)) = stack.last_mut() {
+            let function = *function;
+            let callee_depth = match calls[function].get(*next_call) {
+                Some((call_insn_ptr, callee)) => {
+                    *next_call += 1;
+                    match callee.map(|callee| (callee, visit_states[callee])) {
+                        Some((callee, VisitState::Unvisited)) => {
+                            visit_states[callee] = VisitState::Active;
+                            stack.push((callee, 0));
+                            continue;
+                        }
+                        Some((callee, VisitState::Done)) => Some((*call_insn_ptr, depths[callee])),
+                        _ if config.reject_unbounded_stack_depth => {
+                            return Err(VerifierError::UnboundedStackDepth(adj_insn_ptr(
+                                *call_insn_ptr,
+                            )));
+                        }
+                        _ => None,
+                    }
+                }
+                None => {
+                    visit_states[function] = VisitState::Done;
+                    stack.pop();
+                    stack.last().map(|(caller, next_call)| {
+                        (calls[*caller][*next_call - 1].0, depths[function])
+                    })
+                }
+            };
+            if let (Some((caller, _)), Some((call_insn_ptr, callee_depth))) =
+                (stack.last(), callee_depth)
+            {
+                let depth = frame_sizes[*caller].saturating_add(callee_depth);
+                if depth > depths[*caller] {
+                    depths[*caller] = depth;
+                    deepest_calls[*caller] = Some(call_insn_ptr);
+                }
+            }
+        }
+    }
+
+    for (function, depth) in depths.iter().enumerate() {
+        if *depth > config.stack_size() {
+            return Err(VerifierError::StackDepthExceeded(
+                *depth,
+                adj_insn_ptr(deepest_calls[function].unwrap_or(function_starts[function])),
+            ));
+        }
+    }
+    Ok(())
+}
+
+/// Check that an exit instruction is reachable from every basic block
+///
+/// Calls are assumed to return and syscalls are assumed not to abort, so loops
+/// which only terminate through a syscall are rejected as well. Running off the
+/// end of the program is not considered an infinite loop.
+fn check_infinite_loops(prog: &[u8]) -> Result<(), VerifierError> {
+    let insn_count = prog.len() / ebpf::INSN_SIZE;
+    let mut predecessors: Vec<Vec<usize>> = vec![Vec::new(); insn_count];
+    let mut lddw_tails = vec![false; insn_count];
+    let mut terminators = Vec::new();
+    let mut insn_ptr: usize = 0;
+    while insn_ptr < insn_count {
+        let insn = ebpf::get_insn(prog, insn_ptr);
+        let next_insn_ptr = if insn.opc == ebpf::LD_DW_IMM {
+            lddw_tails[insn_ptr + 1] = true;
+            insn_ptr + 2
+        } else {
+            insn_ptr + 1
+        };
+        let is_jump = matches!(
+            insn.opc & ebpf::BPF_CLS_MASK,
+            ebpf::BPF_JMP | ebpf::BPF_JMP32
+        ) && !matches!(insn.opc, ebpf::CALL_IMM | ebpf::CALL_REG | ebpf::EXIT);
+        if is_jump {
+            predecessors[(insn_ptr as isize + 1 + insn.off as isize) as usize].push(insn_ptr);
+        }
+        if insn.opc == ebpf::EXIT {
+            terminators.push(insn_ptr);
+        } else if insn.opc != ebpf::JA {
+            if next_insn_ptr < insn_count {
+                predecessors[next_insn_ptr].push(insn_ptr);
+            } else {
+                terminators.push(insn_ptr);
+            }
+        }
+        insn_ptr = next_insn_ptr;
+    }
+
+    // Reverse reachability search starting at every exit
+    let mut reaches_exit = vec![false; insn_count];
+    for insn_ptr in terminators.iter() {
+        reaches_exit[*insn_ptr] = true;
+    }
+    while let Some(insn_ptr) = terminators.pop() {
+        for predecessor in predecessors[insn_ptr].iter() {
+            if !reaches_exit[*predecessor] {
+                reaches_exit[*predecessor] = true;
+                terminators.push(*predecessor);
+            }
+        }
+    }
+
+    // All instructions of a basic block share the same reachability,
+    // so the first one which can not reach an exit starts a basic block.
+    match (0..insn_count).find(|insn_ptr| !reaches_exit[*insn_ptr] && !lddw_tails[*insn_ptr]) {
+        Some(block_start) => Err(VerifierError::InfiniteLoop(adj_insn_ptr(block_start))),
+        None => Ok(()),
+    }
+}
+
+/// Registers read and written by an instruction as bit masks
+fn register_usage(insn: &ebpf::Insn) -> (u16, u16) {
+    let dst = 1u16 << insn.dst;
+    let src = 1u16 << insn.src;
+    let operand = if insn.opc & ebpf::BPF_X != 0 { src } else { 0 };
+    match insn.opc & ebpf::BPF_CLS_MASK {
+        ebpf::BPF_ALU | ebpf::BPF_ALU64 => match insn.opc {
+            ebpf::LE | ebpf::BE => (dst, dst),
+            ebpf::UHMUL64_IMM | ebpf::UHMUL64_REG => (dst | operand, dst | dst << 1),
+            opc if opc & ebpf::BPF_ALU_OP_MASK == ebpf::BPF_MOV => (operand, dst),
+            _ => (dst | operand, dst),
+        },
+        ebpf::BPF_LD => (0, dst),
+        ebpf::BPF_LDX => (src, dst),
+        ebpf::BPF_ST => (dst, 0),
+        ebpf::BPF_STX => match insn.opc {
+            ebpf::ST_W_ATOMIC | ebpf::ST_DW_ATOMIC if insn.imm == ebpf::ATOMIC_CMPXCHG => {
+                (dst | src | 1, 1)
+            }
+            ebpf::ST_W_ATOMIC | ebpf::ST_DW_ATOMIC if insn.imm & ebpf::BPF_FETCH != 0 => {
+                (dst | src, src)
+            }
+            _ => (dst | src, 0),
+        },
+        ebpf::BPF_JMP => match insn.opc {
+            ebpf::JA => (0, 0),
+            ebpf::CALL_IMM => (0, 1),
+            ebpf::CALL_REG => (1u16.checked_shl(insn.imm as u32).unwrap_or(0), 1),
+            ebpf::EXIT => (1, 0),
+            _ => (dst | operand, 0),
+        },
+        ebpf::BPF_JMP32 => (dst | operand, 0),
+        _ => (0, 0),
+    }
+}
+
+/// Check that no register is read before it is written on every path leading there
+///
+/// r1 and r10 are initialized at the entrypoint and r1 to r5 and r10 at the start of every
+/// other function. Like in the Linux kernel calls initialize r0 and clobber r1 to r5, and
+/// exit reads r0. Only instructions which are reachable from a function start are checked.
+fn check_uninitialized_registers(
+    prog: &[u8],
+    function_registry: &FunctionRegistry,
+) -> Result<(), VerifierError> {
+    const ALWAYS_INITIALIZED: u16 = 1 << ebpf::FRAME_PTR_REG | 1 << ebpf::STACK_PTR_REG;
+    const ARGUMENTS: u16 = 0b11_1110;
+
+    fn merge(
+        initialized: &mut [Option<u16>],
+        worklist: &mut Vec<usize>,
+        insn_ptr: usize,
+        registers: u16,
+    ) {
+        if insn_ptr >= initialized.len() {
+            return;
+        }
+        let merged = initialized[insn_ptr].map_or(registers, |previous| previous & registers);
+        if initialized[insn_ptr] != Some(merged) {
+            initialized[insn_ptr] = Some(merged);
+            worklist.push(insn_ptr);
+        }
+    }
+
+    // Registers which are initialized on every path leading to an instruction
+    let mut initialized: Vec<Option<u16>> = vec![None; prog.len() / ebpf::INSN_SIZE];
+    let mut worklist = Vec::new();
+    merge(
+        &mut initialized,
+        &mut worklist,
+        0,
+        1 << 1 | ALWAYS_INITIALIZED,
+    );
+    for (pc, _name) in function_registry.values() {
+        merge(
+            &mut initialized,
+            &mut worklist,
+            *pc,
+            ARGUMENTS | ALWAYS_INITIALIZED,
+        );
+    }
+    while let Some(insn_ptr) = worklist.pop() {
+        let insn = ebpf::get_insn(prog, insn_ptr);
+        let (_reads, writes) = register_usage(&insn);
+        let registers = initialized[insn_ptr].unwrap_or(0) | writes;
+        let next_insn_ptr = insn_ptr + if insn.opc == ebpf::LD_DW_IMM { 2 } else { 1 };
+        let target_insn_ptr = (insn_ptr as isize + 1 + insn.off as isize) as usize;
+        match insn.opc {
+            ebpf::EXIT => {}
+            ebpf::JA => merge(&mut initialized, &mut worklist, target_insn_ptr, registers),
+            ebpf::CALL_IMM | ebpf::CALL_REG => merge(
+                &mut initialized,
+                &mut worklist,
+                next_insn_ptr,
+                registers & !ARGUMENTS,
+            ),
+            opc if matches!(opc & ebpf::BPF_CLS_MASK, ebpf::BPF_JMP | ebpf::BPF_JMP32) => {
+                merge(&mut initialized, &mut worklist, target_insn_ptr, registers);
+                merge(&mut initialized, &mut worklist, next_insn_ptr, registers);
+            }
+            _ => merge(&mut initialized, &mut worklist, next_insn_ptr, registers),
+        }
+    }
+
+    let mut insn_ptr: usize = 0;
+    while insn_ptr < initialized.len() {
+        let insn = ebpf::get_insn(prog, insn_ptr);
+        if let Some(registers) = initialized[insn_ptr] {
+            let (reads, _writes) = register_usage(&insn);
+            let uninitialized = reads & !registers;
+            if uninitialized != 0 {
+                return Err(VerifierError::RegisterNotInitialized(
+                    adj_insn_ptr(insn_ptr),
+                    uninitialized.trailing_zeros() as usize,
+                ));
+            }
+        }
+        insn_ptr += if insn.opc == ebpf::LD_DW_IMM { 2 } else { 1 };
+    }
+    Ok(())
+}
+
+/// Checks a single instruction and returns the index of the next one
+///
+/// Jumps and calls are recorded in `jump_targets` for check_jump_targets.
+#[rustfmt::skip]
+fn check_instruction(
+    prog: &[u8],
+    mut insn_ptr: usize,
+    config: &Config,
+    function_registry: &FunctionRegistry,
+    jump_targets: &mut Vec<(usize, usize)>,
+) -> Result<usize, VerifierError> {
+    let insn = ebpf::get_insn(prog, insn_ptr);
+    let mut store = false;
+
+    if !config.allowed_opcodes.contains(insn.opc) {
+        return Err(VerifierError::OpcodeNotAllowed(adj_insn_ptr(insn_ptr), insn.opc));
+    }
+
+    match insn.opc {
+        ebpf::LD_DW_IMM  => {
+            check_load_dw(prog, insn_ptr)?;
+            insn_ptr += 1;
+        },
+
+        // BPF_LDX class
+        ebpf::LD_B_REG   => {},
+        ebpf::LD_H_REG   => {},
+        ebpf::LD_W_REG   => {},
+        ebpf::LD_DW_REG  => {},
+
+        // BPF_ST class
+        ebpf::ST_B_IMM   => store = true,
+        ebpf::ST_H_IMM   => store = true,
+        ebpf::ST_W_IMM   => store = true,
+        ebpf::ST_DW_IMM  => store = true,
+
+        // BPF_STX class
+        ebpf::ST_B_REG   => store = true,
+        ebpf::ST_H_REG   => store = true,
+        ebpf::ST_W_REG   => store = true,
+        ebpf::ST_DW_REG  => store = true,
+        ebpf::ST_W_ATOMIC if config.enable_atomics => { store = true; check_imm_atomic(&insn, insn_ptr)?; },
+        ebpf::ST_DW_ATOMIC if config.enable_atomics => { store = true; check_imm_atomic(&insn, insn_ptr)?; },
+
+        // BPF_ALU class
+        ebpf::ADD32_IMM  => {},
+        ebpf::ADD32_REG  => {},
+        ebpf::SUB32_IMM  => {},
+        ebpf::SUB32_REG  => {},
+        ebpf::MUL32_IMM  => {},
+        ebpf::MUL32_REG  => {},
+        ebpf::DIV32_IMM  => { check_imm_nonzero(&insn, insn_ptr)?; },
+        ebpf::DIV32_REG  => {},
+        ebpf::SDIV32_IMM if config.enable_sdiv => { check_imm_nonzero(&insn, insn_ptr)?; },
+        ebpf::SDIV32_REG if config.enable_sdiv => {},
+        ebpf::OR32_IMM   => {},
+        ebpf::OR32_REG   => {},
+        ebpf::AND32_IMM  => {},
+        ebpf::AND32_REG  => {},
+        ebpf::LSH32_IMM  => { check_imm_shift(&insn, insn_ptr, 32)?; },
+        ebpf::LSH32_REG  => {},
+        ebpf::RSH32_IMM  => { check_imm_shift(&insn, insn_ptr, 32)?; },
+        ebpf::RSH32_REG  => {},
+        ebpf::NEG32      => {},
+        ebpf::MOD32_IMM  => { check_imm_nonzero(&insn, insn_ptr)?; },
+        ebpf::MOD32_REG  => {},
+        ebpf::XOR32_IMM  => {},
+        ebpf::XOR32_REG  => {},
+        ebpf::MOV32_IMM  => {},
+        ebpf::MOV32_REG  => {},
+        ebpf::ARSH32_IMM => { check_imm_shift(&insn, insn_ptr, 32)?; },
+        ebpf::ARSH32_REG => {},
+        ebpf::LE         => { check_imm_endian(&insn, insn_ptr)?; },
+        ebpf::BE         => { check_imm_endian(&insn, insn_ptr)?; },
+
+        // BPF_ALU64 class
+        ebpf::ADD64_IMM  => {},
+        ebpf::ADD64_REG  => {},
+        ebpf::SUB64_IMM  => {},
+        ebpf::SUB64_REG  => {},
+        ebpf::MUL64_IMM  => {},
+        ebpf::MUL64_REG  => {},
+        ebpf::DIV64_IMM  => { check_imm_nonzero(&insn, insn_ptr)?; },
+        ebpf::DIV64_REG  => {},
+        ebpf::SDIV64_IMM if config.enable_sdiv => { check_imm_nonzero(&insn, insn_ptr)?; },
+        ebpf::SDIV64_REG if config.enable_sdiv => {},
+        ebpf::UHMUL64_IMM if config.enable_uhmul => { check_register_pair(&insn, insn_ptr)?; },
+        ebpf::UHMUL64_REG if config.enable_uhmul => { check_register_pair(&insn, insn_ptr)?; },
+        ebpf::OR64_IMM   => {},
+        ebpf::OR64_REG   => {},
+        ebpf::AND64_IMM  => {},
+        ebpf::AND64_REG  => {},
+        ebpf::LSH64_IMM  => { check_imm_shift(&insn, insn_ptr, 64)?; },
+        ebpf::LSH64_REG  => {},
+        ebpf::RSH64_IMM  => { check_imm_shift(&insn, insn_ptr, 64)?; },
+        ebpf::RSH64_REG  => {},
+        ebpf::NEG64      => {},
+        ebpf::MOD64_IMM  => { check_imm_nonzero(&insn, insn_ptr)?; },
+        ebpf::MOD64_REG  => {},
+        ebpf::XOR64_IMM  => {},
+        ebpf::XOR64_REG  => {},
+        ebpf::MOV64_IMM  => {},
+        ebpf::MOV64_REG  => {},
+        ebpf::ARSH64_IMM => { check_imm_shift(&insn, insn_ptr, 64)?; },
+        ebpf::ARSH64_REG => {},
+
+        // BPF_JMP class
+        ebpf::JA         => { check_jmp_offset(prog, insn_ptr, jump_targets)?; },
+        ebpf::JEQ_IMM    => { check_jmp_offset(prog, insn_ptr, jump_targets)?; },
+        ebpf::JEQ_REG    => { check_jmp_offset(prog, insn_ptr, jump_targets)?; },
+        ebpf::JGT_IMM    => { check_jmp_offset(prog, insn_ptr, jump_targets)?; },
+        ebpf::JGT_REG    => { check_jmp_offset(prog, insn_ptr, jump_targets)?; },
+        ebpf::JGE_IMM    => { check_jmp_offset(prog, insn_ptr, jump_targets)?; },
+        ebpf::JGE_REG    => { check_jmp_offset(prog, insn_ptr, jump_targets)?; },
+        ebpf::JLT_IMM    => { check_jmp_offset(prog, insn_ptr, jump_targets)?; },
+        ebpf::JLT_REG    => { check_jmp_offset(prog, insn_ptr, jump_targets)?; },
+        ebpf::JLE_IMM    => { check_jmp_offset(prog, insn_ptr, jump_targets)?; },
+        ebpf::JLE_REG    => { check_jmp_offset(prog, insn_ptr, jump_targets)?; },
+        ebpf::JSET_IMM   => { check_jmp_offset(prog, insn_ptr, jump_targets)?; },
+        ebpf::JSET_REG   => { check_jmp_offset(prog, insn_ptr, jump_targets)?; },
+        ebpf::JNE_IMM    => { check_jmp_offset(prog, insn_ptr, jump_targets)?; },
+        ebpf::JNE_REG    => { check_jmp_offset(prog, insn_ptr, jump_targets)?; },
+        ebpf::JSGT_IMM   => { check_jmp_offset(prog, insn_ptr, jump_targets)?; },
+        ebpf::JSGT_REG   => { check_jmp_offset(prog, insn_ptr, jump_targets)?; },
+        ebpf::JSGE_IMM   => { check_jmp_offset(prog, insn_ptr, jump_targets)?; },
+        ebpf::JSGE_REG   => { check_jmp_offset(prog, insn_ptr, jump_targets)?; },
+        ebpf::JSLT_IMM   => { check_jmp_offset(prog, insn_ptr, jump_targets)?; },
+        ebpf::JSLT_REG   => { check_jmp_offset(prog, insn_ptr, jump_targets)?; },
+        ebpf::JSLE_IMM   => { check_jmp_offset(prog, insn_ptr, jump_targets)?; },
+        ebpf::JSLE_REG   => { check_jmp_offset(prog, insn_ptr, jump_targets)?; },
+        ebpf::CALL_IMM   => { check_call_target(&insn, insn_ptr, config, function_registry, jump_targets); },
+        ebpf::CALL_REG   => { check_imm_register(&insn, insn_ptr, config)?; },
+        ebpf::EXIT       => {},
+
+        // BPF_JMP32 class
+        ebpf::JEQ32_IMM if config.enable_jmp32  => { check_jmp_offset(prog, insn_ptr, jump_targets)?; },
+        ebpf::JEQ32_REG if config.enable_jmp32  => { check_jmp_offset(prog, insn_ptr, jump_targets)?; },
+        ebpf::JGT32_IMM if config.enable_jmp32  => { check_jmp_offset(prog, insn_ptr, jump_targets)?; },
+        ebpf::JGT32_REG if config.enable_jmp32  => { check_jmp_offset(prog, insn_ptr, jump_targets)?; },
+        ebpf::JGE32_IMM if config.enable_jmp32  => { check_jmp_offset(prog, insn_ptr, jump_targets)?; },
+        ebpf::JGE32_REG if config.enable_jmp32  => { check_jmp_offset(prog, insn_ptr, jump_targets)?; },
+        ebpf::JLT32_IMM if config.enable_jmp32  => { check_jmp_offset(prog, insn_ptr, jump_targets)?; },
+        ebpf::JLT32_REG if config.enable_jmp32  => { check_jmp_offset(prog, insn_ptr, jump_targets)?; },
+        ebpf::JLE32_IMM if config.enable_jmp32  => { check_jmp_offset(prog, insn_ptr, jump_targets)?; },
+        ebpf::JLE32_REG if config.enable_jmp32  => { check_jmp_offset(prog, insn_ptr, jump_targets)?; },
+        ebpf::JSET32_IMM if config.enable_jmp32 => { check_jmp_offset(prog, insn_ptr, jump_targets)?; },
+        ebpf::JSET32_REG if config.enable_jmp32 => { check_jmp_offset(prog, insn_ptr, jump_targets)?; },
+        ebpf::JNE32_IMM if config.enable_jmp32  => { check_jmp_offset(prog, insn_ptr, jump_targets)?; },
+        ebpf::JNE32_REG if config.enable_jmp32  => { check_jmp_offset(prog, insn_ptr, jump_targets)?; },
+        ebpf::JSGT32_IMM if config.enable_jmp32 => { check_jmp_offset(prog, insn_ptr, jump_targets)?; },
+        ebpf::JSGT32_REG if config.enable_jmp32 => { check_jmp_offset(prog, insn_ptr, jump_targets)?; },
+        ebpf::JSGE32_IMM if config.enable_jmp32 => { check_jmp_offset(prog, insn_ptr, jump_targets)?; },
+        ebpf::JSGE32_REG if config.enable_jmp32 => { check_jmp_offset(prog, insn_ptr, jump_targets)?; },
+        ebpf::JSLT32_IMM if config.enable_jmp32 => { check_jmp_offset(prog, insn_ptr, jump_targets)?; },
+        ebpf::JSLT32_REG if config.enable_jmp32 => { check_jmp_offset(prog, insn_ptr, jump_targets)?; },
+        ebpf::JSLE32_IMM if config.enable_jmp32 => { check_jmp_offset(prog, insn_ptr, jump_targets)?; },
+        ebpf::JSLE32_REG if config.enable_jmp32 => { check_jmp_offset(prog, insn_ptr, jump_targets)?; },
+
+        opc if config.allow_opcode_extensions && config.valid_opcode_extensions.contains(opc) => {},
+
+        _                => {
+            return Err(VerifierError::UnknownOpCode(insn.opc, adj_insn_ptr(insn_ptr)));
+        }
+    }
+
+    check_registers(&insn, store, insn_ptr, config.dynamic_stack_frames)?;
+
+    Ok(insn_ptr + 1)
+}
+
 /// Mandatory verifier for solana programs to run on-chain
 #[derive(Debug)]
 pub struct RequisiteVerifier {}
 impl Verifier for RequisiteVerifier {
     /// Check the program against the verifier's rules
-    #[rustfmt::skip]
-    fn verify(prog: &[u8], config: &Config, _function_registry: &FunctionRegistry) -> Result<(), VerifierError> {
+    fn verify(
+        prog: &[u8],
+        config: &Config,
+        function_registry: &FunctionRegistry,
+    ) -> Result<(), VerifierError> {
         check_prog_len(prog)?;
+        let mut errors = Vec::new();
+        check_program(prog, config, function_registry, &mut errors);
+        errors.into_iter().next().map_or(Ok(()), Err)
+    }
+}
 
-        let mut insn_ptr: usize = 0;
-        while (insn_ptr + 1) * ebpf::INSN_SIZE <= prog.len() {
-            let insn = ebpf::get_insn(prog, insn_ptr);
-            let mut store = false;
-
-            match insn.opc {
-                ebpf::LD_DW_IMM  => {
-                    check_load_dw(prog, insn_ptr)?;
-                    insn_ptr += 1;
-                },
-
-                // BPF_LDX class
-                ebpf::LD_B_REG   => {},
-                ebpf::LD_H_REG   => {},
-                ebpf::LD_W_REG   => {},
-                ebpf::LD_DW_REG  => {},
-
-                // BPF_ST class
-                ebpf::ST_B_IMM   => store = true,
-                ebpf::ST_H_IMM   => store = true,
-                ebpf::ST_W_IMM   => store = true,
-                ebpf::ST_DW_IMM  => store = true,
-
-                // BPF_STX class
-                ebpf::ST_B_REG   => store = true,
-                ebpf::ST_H_REG   => store = true,
-                ebpf::ST_W_REG   => store = true,
-                ebpf::ST_DW_REG  => store = true,
-
-                // BPF_ALU class
-                ebpf::ADD32_IMM  => {},
-                ebpf::ADD32_REG  => {},
-                ebpf::SUB32_IMM  => {},
-                ebpf::SUB32_REG  => {},
-                ebpf::MUL32_IMM  => {},
-                ebpf::MUL32_REG  => {},
-                ebpf::DIV32_IMM  => { check_imm_nonzero(&insn, insn_ptr)?; },
-                ebpf::DIV32_REG  => {},
-                ebpf::SDIV32_IMM if config.enable_sdiv => { check_imm_nonzero(&insn, insn_ptr)?; },
-                ebpf::SDIV32_REG if config.enable_sdiv => {},
-                ebpf::OR32_IMM   => {},
-                ebpf::OR32_REG   => {},
-                ebpf::AND32_IMM  => {},
-                ebpf::AND32_REG  => {},
-                ebpf::LSH32_IMM  => { check_imm_shift(&insn, insn_ptr, 32)?; },
-                ebpf::LSH32_REG  => {},
-                ebpf::RSH32_IMM  => { check_imm_shift(&insn, insn_ptr, 32)?; },
-                ebpf::RSH32_REG  => {},
-                ebpf::NEG32      => {},
-                ebpf::MOD32_IMM  => { check_imm_nonzero(&insn, insn_ptr)?; },
-                ebpf::MOD32_REG  => {},
-                ebpf::XOR32_IMM  => {},
-                ebpf::XOR32_REG  => {},
-                ebpf::MOV32_IMM  => {},
-                ebpf::MOV32_REG  => {},
-                ebpf::ARSH32_IMM => { check_imm_shift(&insn, insn_ptr, 32)?; },
-                ebpf::ARSH32_REG => {},
-                ebpf::LE         => { check_imm_endian(&insn, insn_ptr)?; },
-                ebpf::BE         => { check_imm_endian(&insn, insn_ptr)?; },
-
-                // BPF_ALU64 class
-                ebpf::ADD64_IMM  => {},
-                ebpf::ADD64_REG  => {},
-                ebpf::SUB64_IMM  => {},
-                ebpf::SUB64_REG  => {},
-                ebpf::MUL64_IMM  => {},
-                ebpf::MUL64_REG  => {},
-                ebpf::DIV64_IMM  => { check_imm_nonzero(&insn, insn_ptr)?; },
-                ebpf::DIV64_REG  => {},
-                ebpf::SDIV64_IMM if config.enable_sdiv => { check_imm_nonzero(&insn, insn_ptr)?; },
-                ebpf::SDIV64_REG if config.enable_sdiv => {},
-                ebpf::OR64_IMM   => {},
-                ebpf::OR64_REG   => {},
-                ebpf::AND64_IMM  => {},
-                ebpf::AND64_REG  => {},
-                ebpf::LSH64_IMM  => { check_imm_shift(&insn, insn_ptr, 64)?; },
-                ebpf::LSH64_REG  => {},
-                ebpf::RSH64_IMM  => { check_imm_shift(&insn, insn_ptr, 64)?; },
-                ebpf::RSH64_REG  => {},
-                ebpf::NEG64      => {},
-                ebpf::MOD64_IMM  => { check_imm_nonzero(&insn, insn_ptr)?; },
-                ebpf::MOD64_REG  => {},
-                ebpf::XOR64_IMM  => {},
-                ebpf::XOR64_REG  => {},
-                ebpf::MOV64_IMM  => {},
-                ebpf::MOV64_REG  => {},
-                ebpf::ARSH64_IMM => { check_imm_shift(&insn, insn_ptr, 64)?; },
-                ebpf::ARSH64_REG => {},
-
-                // BPF_JMP class
-                ebpf::JA         => { check_jmp_offset(prog, insn_ptr)?; },
-                ebpf::JEQ_IMM    => { check_jmp_offset(prog, insn_ptr)?; },
-                ebpf::JEQ_REG    => { check_jmp_offset(prog, insn_ptr)?; },
-                ebpf::JGT_IMM    => { check_jmp_offset(prog, insn_ptr)?; },
-                ebpf::JGT_REG    => { check_jmp_offset(prog, insn_ptr)?; },
-                ebpf::JGE_IMM    => { check_jmp_offset(prog, insn_ptr)?; },
-                ebpf::JGE_REG    => { check_jmp_offset(prog, insn_ptr)?; },
-                ebpf::JLT_IMM    => { check_jmp_offset(prog, insn_ptr)?; },
-                ebpf::JLT_REG    => { check_jmp_offset(prog, insn_ptr)?; },
-                ebpf::JLE_IMM    => { check_jmp_offset(prog, insn_ptr)?; },
-                ebpf::JLE_REG    => { check_jmp_offset(prog, insn_ptr)?; },
-                ebpf::JSET_IMM   => { check_jmp_offset(prog, insn_ptr)?; },
-                ebpf::JSET_REG   => { check_jmp_offset(prog, insn_ptr)?; },
-                ebpf::JNE_IMM    => { check_jmp_offset(prog, insn_ptr)?; },
-                ebpf::JNE_REG    => { check_jmp_offset(prog, insn_ptr)?; },
-                ebpf::JSGT_IMM   => { check_jmp_offset(prog, insn_ptr)?; },
-                ebpf::JSGT_REG   => { check_jmp_offset(prog, insn_ptr)?; },
-                ebpf::JSGE_IMM   => { check_jmp_offset(prog, insn_ptr)?; },
-                ebpf::JSGE_REG   => { check_jmp_offset(prog, insn_ptr)?; },
-                ebpf::JSLT_IMM   => { check_jmp_offset(prog, insn_ptr)?; },
-                ebpf::JSLT_REG   => { check_jmp_offset(prog, insn_ptr)?; },
-                ebpf::JSLE_IMM   => { check_jmp_offset(prog, insn_ptr)?; },
-                ebpf::JSLE_REG   => { check_jmp_offset(prog, insn_ptr)?; },
-                ebpf::CALL_IMM   => {},
-                ebpf::CALL_REG   => { check_imm_register(&insn, insn_ptr, config)?; },
-                ebpf::EXIT       => {},
-
-                _                => {
-                    return Err(VerifierError::UnknownOpCode(insn.opc, adj_insn_ptr(insn_ptr)));
+/// Appends the errors of a program of valid length to `errors`, see [`verify_collect`]
+///
+/// The first one is the error which [`RequisiteVerifier`] reports.
+fn check_program(
+    prog: &[u8],
+    config: &Config,
+    function_registry: &FunctionRegistry,
+    errors: &mut Vec<VerifierError>,
+) {
+    let insn_count = prog.len() / ebpf::INSN_SIZE;
+    let mut lddw_tails = vec![false; insn_count];
+    let mut jump_targets = Vec::new();
+    let mut insn_ptr = 0;
+    while insn_ptr < insn_count {
+        let next_insn_ptr =
+            match check_instruction(prog, insn_ptr, config, function_registry, &mut jump_targets) {
+                Ok(next_insn_ptr) => next_insn_ptr,
+                Err(error) => {
+                    errors.push(error);
+                    // Skip the second slot of a LD_DW_IMM, it would be reported as unknown opcode
+                    if ebpf::get_insn(prog, insn_ptr).opc == ebpf::LD_DW_IMM {
+                        insn_ptr + 2
+                    } else {
+                        insn_ptr + 1
+                    }
                 }
-            }
-
-            check_registers(&insn, store, insn_ptr, config.dynamic_stack_frames)?;
-
-            insn_ptr += 1;
+            };
+        if next_insn_ptr == insn_ptr + 2 && next_insn_ptr <= insn_count {
+            lddw_tails[insn_ptr + 1] = true;
         }
+        insn_ptr = next_insn_ptr;
+    }
+    errors.extend(
+        jump_targets
+            .chunks(1)
+            .filter_map(|jump_target| check_jump_targets(jump_target, &lddw_tails).err()),
+    );
+    if config.reject_falls_off_end {
+        errors.extend(check_last_instruction(prog, lddw_tails[insn_count - 1]).err());
+    }
 
-        // insn_ptr should now be equal to number of instructions.
-        if insn_ptr != prog.len() / ebpf::INSN_SIZE {
-            return Err(VerifierError::JumpOutOfCode(adj_insn_ptr(insn_ptr), adj_insn_ptr(insn_ptr)));
+    if errors.is_empty() {
+        if config.verify_stack_depth {
+            errors.extend(check_stack_depth(prog, config, function_registry).err());
         }
+        if config.reject_infinite_loops {
+            errors.extend(check_infinite_loops(prog).err());
+        }
+        if config.reject_uninitialized_registers {
+            errors.extend(check_uninitialized_registers(prog, function_registry).err());
+        }
+    }
+}
+
+/// Verifies raw bytecode without building an `Executable` first
+///
+/// Calls which are not static syscalls are relative, as in the text section of an ELF file
+/// before `Executable::fixup_relative_calls()`, and are resolved here. Static syscalls must be
+/// registered in `syscalls`. The checks are the ones of [`RequisiteVerifier`].
+pub fn verify<C: ContextObject>(
+    prog: &[u8],
+    config: &Config,
+    syscalls: &SyscallRegistry<C>,
+) -> Result<(), VerifierError> {
+    verify_collect(prog, config, syscalls)
+        .into_iter()
+        .next()
+        .map_or(Ok(()), Err)
+}
 
-        Ok(())
+/// Like [`verify`], but reports every error instead of stopping at the first one
+///
+/// An invalid instruction does not stop the checks of the ones behind it. A program of
+/// invalid length is not checked any further, and the checks of the whole program enabled
+/// by `verify_stack_depth`, `reject_infinite_loops` and `reject_uninitialized_registers`
+/// only run if no other error was found. The result is empty if the program is valid.
+pub fn verify_collect<C: ContextObject>(
+    prog: &[u8],
+    config: &Config,
+    syscalls: &SyscallRegistry<C>,
+) -> Vec<VerifierError> {
+    if let Err(error) = check_prog_len(prog) {
+        return vec![error];
     }
+    let mut errors = Vec::new();
+    let (prog, function_registry) = resolve_relative_calls(prog, config, syscalls, &mut errors);
+    check_program(&prog, config, &function_registry, &mut errors);
+    errors
 }
 
-// Warning: For test purposes only
-pub(crate) struct TautologyVerifier {}
-impl Verifier for TautologyVerifier {
-    fn verify(
-        _prog: &[u8],
-        _config: &Config,
-        _function_registry: &FunctionRegistry,
-    ) -> std::result::Result<(), VerifierError> {
-        Ok(())
+/// Resolves the relative calls of raw bytecode into a copy of it, see [`verify`]
+///
+/// Calls to unregistered syscalls or out of the program are reported in `errors` and left as they are.
+fn resolve_relative_calls<C: ContextObject>(
+    prog: &[u8],
+    config: &Config,
+    syscalls: &SyscallRegistry<C>,
+    errors: &mut Vec<VerifierError>,
+) -> (Vec<u8>, FunctionRegistry) {
+    let mut prog = prog.to_vec();
+    let mut function_registry = FunctionRegistry::new();
+    let insn_count = prog.len() / ebpf::INSN_SIZE;
+    let mut insn_ptr = 0;
+    while insn_ptr < insn_count {
+        let mut insn = ebpf::get_insn(&prog, insn_ptr);
+        if insn.opc == ebpf::LD_DW_IMM {
+            insn_ptr += 2;
+            continue;
+        }
+        if insn.opc == ebpf::CALL_IMM && config.static_syscalls && insn.src == 0 {
+            if !syscalls.contains_syscall(insn.imm as u32) {
+                errors.push(VerifierError::UnknownSyscall(
+                    insn.imm as u32,
+                    adj_insn_ptr(insn_ptr),
+                ));
+            }
+        } else if insn.opc == ebpf::CALL_IMM && insn.imm != -1 {
+            let target_pc = insn_ptr as isize + 1 + insn.imm as isize;
+            if target_pc < 0 || target_pc as usize >= insn_count {
+                errors.push(VerifierError::JumpOutOfBounds(
+                    adj_insn_ptr(insn_ptr),
+                    target_pc as i64,
+                ));
+            } else {
+                let hash = hash_bpf_function(target_pc as usize, "");
+                function_registry.insert(hash, (target_pc as usize, String::new()));
+                insn.imm = hash as i64;
+                let offset = insn_ptr * ebpf::INSN_SIZE;
+                prog[offset..offset + ebpf::INSN_SIZE].copy_from_slice(&insn.to_array());
+            }
+        }
+        insn_ptr += 1;
+    }
+    (prog, function_registry)
+}
+
+/// Re-verifies a program after the instruction slots in `changed_range` were patched
+///
+/// `prog` must have passed [`RequisiteVerifier`] with the same `config` and `function_registry`
+/// before and only the slots in `changed_range` may differ. The checked range is widened to
+/// cover LD_DW_IMM instructions which straddle its bounds. Jumps and calls outside of it are
+/// only revisited if it now contains the second slot of a LD_DW_IMM, which they could land on.
+/// The checks of the whole program enabled by `verify_stack_depth`, `reject_infinite_loops` and
+/// `reject_uninitialized_registers` can not be done locally, so these fall back to full verification.
+pub fn reverify_range(
+    prog: &[u8],
+    changed_range: Range<usize>,
+    config: &Config,
+    function_registry: &FunctionRegistry,
+) -> Result<(), VerifierError> {
+    check_prog_len(prog)?;
+    let insn_count = prog.len() / ebpf::INSN_SIZE;
+    if config.verify_stack_depth
+        || config.reject_infinite_loops
+        || config.reject_uninitialized_registers
+        || changed_range.end > insn_count
+    {
+        return RequisiteVerifier::verify(prog, config, function_registry);
+    }
+    if changed_range.is_empty() {
+        return Ok(());
+    }
+
+    // Outside of the changed range the second slot of a LD_DW_IMM is the only one with
+    // opcode 0, as any other instruction with it would have been rejected before
+    let is_lddw_tail = |insn_ptr: usize| ebpf::get_insn(prog, insn_ptr).opc == 0;
+    let mut insn_ptr = changed_range.start;
+    if insn_ptr > 0 && ebpf::get_insn(prog, insn_ptr - 1).opc == ebpf::LD_DW_IMM {
+        insn_ptr -= 1;
+    }
+    let checked_start = insn_ptr;
+    let mut lddw_tails = Vec::new();
+    let mut jump_targets = Vec::new();
+    // A slot behind the range which was the second slot of a LD_DW_IMM might not be anymore
+    while insn_ptr < changed_range.end || (insn_ptr < insn_count && is_lddw_tail(insn_ptr)) {
+        let next_insn_ptr =
+            check_instruction(prog, insn_ptr, config, function_registry, &mut jump_targets)?;
+        if next_insn_ptr == insn_ptr + 2 {
+            lddw_tails.push(insn_ptr + 1);
+        }
+        insn_ptr = next_insn_ptr;
     }
+    let checked_range = checked_start..insn_ptr;
+    if config.reject_falls_off_end && checked_range.end == insn_count {
+        check_last_instruction(prog, lddw_tails.last() == Some(&(insn_count - 1)))?;
+    }
+
+    // Only new LD_DW_IMM can be the target of jumps and calls which were fine before
+    if !lddw_tails.is_empty() {
+        for insn_ptr in (0..insn_count).filter(|insn_ptr| !checked_range.contains(insn_ptr)) {
+            let insn = ebpf::get_insn(prog, insn_ptr);
+            match insn.opc {
+                ebpf::CALL_IMM => check_call_target(
+                    &insn,
+                    insn_ptr,
+                    config,
+                    function_registry,
+                    &mut jump_targets,
+                ),
+                ebpf::CALL_REG | ebpf::EXIT => {}
+                _ if matches!(
+                    insn.opc & ebpf::BPF_CLS_MASK,
+                    ebpf::BPF_JMP | ebpf::BPF_JMP32
+                ) =>
+                {
+                    let target_pc = (insn_ptr as isize + 1 + insn.off as isize) as usize;
+                    jump_targets.push((insn_ptr, target_pc));
+                }
+                _ => {}
+            }
+        }
+    }
+    for (insn_ptr, target_pc) in jump_targets {
+        if target_pc < insn_count && is_lddw_tail(target_pc) {
+            return Err(VerifierError::JumpToMiddleOfLDDW(
+                target_pc,
+                adj_insn_ptr(insn_ptr),
+            ));
+        }
+    }
+    Ok(())
 }
@@ -12,23 +12,42 @@
 
 //! Virtual machine for eBPF programs.
 
+#[cfg(feature = "memory-observer")]
+use crate::memory_region::MemoryObserver;
 use crate::{
+    allocator::BpfAllocator,
     call_frames::CallFrames,
-    disassembler::disassemble_instruction,
     ebpf,
-    elf::Executable,
-    error::EbpfError,
+    elf::{ElfError, Executable},
+    error::{EbpfError, Error, UserDefinedError},
     interpreter::Interpreter,
     memory_region::{MemoryMapping, MemoryRegion},
-    static_analysis::Analysis,
     verifier::Verifier,
 };
-use std::{
-    collections::{BTreeMap, HashMap},
-    fmt::Debug,
+#[cfg(feature = "std")]
+use crate::{disassembler::disassemble_instruction_to_string, static_analysis::Analysis};
+use alloc::{
+    collections::BTreeMap,
+    format,
+    string::{String, ToString},
+    vec,
+    vec::Vec,
+};
+#[cfg(feature = "jit")]
+use core::time::Duration;
+use core::{
+    fmt::{self, Debug},
     marker::PhantomData,
     mem,
 };
+#[cfg(feature = "jit")]
+use std::time::Instant;
+
+/// Map of the bound syscalls, without `std` there is no `HashMap`
+#[cfg(feature = "std")]
+type SyscallMap<K, V> = std::collections::HashMap<K, V>;
+#[cfg(not(feature = "std"))]
+type SyscallMap<K, V> = BTreeMap<K, V>;
 
 /// Same as `Result` but provides a stable memory layout
 #[derive(Debug)]
@@ -93,19 +112,29 @@ impl<T, E> From<Result<T, E>> for StableResult<T, E> {
 }
 
 /// Return value of programs and syscalls
-pub type ProgramResult = StableResult<u64, EbpfError>;
+pub type ProgramResult<E> = StableResult<u64, EbpfError<E>>;
 
 /// Holds the function symbols of an Executable
 pub type FunctionRegistry = BTreeMap<u32, (usize, String)>;
 
 /// Syscall function without context
-pub type SyscallFunction<C> =
-    fn(&mut C, u64, u64, u64, u64, u64, &mut MemoryMapping, &mut ProgramResult);
+pub type SyscallFunction<C> = fn(
+    &mut C,
+    u64,
+    u64,
+    u64,
+    u64,
+    u64,
+    &mut MemoryMapping,
+    &mut ProgramResult<<C as ContextObject>::UserError>,
+);
 
 /// Holds the syscall function pointers of an Executable
 pub struct SyscallRegistry<C: ContextObject> {
-    /// Function pointers by symbol
-    entries: HashMap<u32, SyscallFunction<C>>,
+    /// Function pointers by symbol hash
+    entries: SyscallMap<u32, SyscallFunction<C>>,
+    /// Function pointers by symbol name, which are not bound to their hash yet
+    unbound_entries: BTreeMap<Vec<u8>, SyscallFunction<C>>,
 }
 
 impl<C: ContextObject> SyscallRegistry<C> {
@@ -116,25 +145,99 @@ impl<C: ContextObject> SyscallRegistry<C> {
         &mut self,
         hash: u32,
         function: SyscallFunction<C>,
-    ) -> Result<(), EbpfError> {
-        let context_object_slot = self.entries.len();
+    ) -> Result<(), EbpfError<C::UserError>> {
+        let context_object_slot = self.get_number_of_syscalls();
         if context_object_slot == Self::MAX_SYSCALLS {
             return Err(EbpfError::TooManySyscalls);
         }
-        if self.entries.insert(hash, function).is_some() {
-            Err(EbpfError::SyscallAlreadyRegistered(hash as usize))
-        } else {
-            Ok(())
+        if self.contains_syscall(hash) {
+            return Err(EbpfError::SyscallAlreadyRegistered(hash as usize));
         }
+        self.entries.insert(hash, function);
+        Ok(())
     }
 
     /// Register a syscall function by its symbol name
+    ///
+    /// The hash of the name is only bound once an ELF relocation references the symbol,
+    /// or when the executable is created from text bytes or uses static syscalls.
     pub fn register_syscall_by_name(
         &mut self,
         name: &[u8],
         function: SyscallFunction<C>,
-    ) -> Result<(), EbpfError> {
-        self.register_syscall_by_hash(ebpf::hash_symbol_name(name), function)
+    ) -> Result<(), EbpfError<C::UserError>> {
+        let context_object_slot = self.get_number_of_syscalls();
+        if context_object_slot == Self::MAX_SYSCALLS {
+            return Err(EbpfError::TooManySyscalls);
+        }
+        let hash = ebpf::hash_symbol_name(name);
+        if self.contains_syscall(hash) {
+            return Err(EbpfError::SyscallAlreadyRegistered(hash as usize));
+        }
+        self.unbound_entries.insert(name.to_vec(), function);
+        Ok(())
+    }
+
+    /// Bind a syscall registered by name to its symbol hash
+    pub fn bind_syscall_by_name(&mut self, name: &[u8]) -> Result<u32, EbpfError<C::UserError>> {
+        let hash = ebpf::hash_symbol_name(name);
+        if let Some(function) = self.unbound_entries.remove(name) {
+            self.entries.insert(hash, function);
+        } else if !self.entries.contains_key(&hash) {
+            return Err(EbpfError::SyscallNotRegistered(
+                String::from_utf8_lossy(name).to_string(),
+            ));
+        }
+        Ok(hash)
+    }
+
+    /// Replace the function of a syscall which is registered under the symbol hash, bound or not
+    ///
+    /// The symbol hash stays the same, so executables relocated against this registry remain valid.
+    pub fn replace_handler(
+        &mut self,
+        hash: u32,
+        function: SyscallFunction<C>,
+    ) -> Result<(), EbpfError<C::UserError>> {
+        if let Some(entry) = self.entries.get_mut(&hash) {
+            *entry = function;
+        } else if let Some(entry) = self
+            .unbound_entries
+            .iter_mut()
+            .find_map(|(name, entry)| (ebpf::hash_symbol_name(name) == hash).then_some(entry))
+        {
+            *entry = function;
+        } else {
+            return Err(EbpfError::SyscallNotRegistered(format!("{:#x}", hash)));
+        }
+        Ok(())
+    }
+
+    /// Bind all syscalls registered by name to their symbol hashes
+    pub fn bind_all_syscalls(&mut self) {
+        self.bind_all_syscalls_with_names();
+    }
+
+    /// Bind all syscalls registered by name to their symbol hashes
+    ///
+    /// Returns the names of the newly bound syscalls by their hashes.
+    pub fn bind_all_syscalls_with_names(&mut self) -> BTreeMap<u32, String> {
+        let mut names = BTreeMap::new();
+        for (name, function) in mem::take(&mut self.unbound_entries) {
+            let hash = ebpf::hash_symbol_name(&name);
+            self.entries.insert(hash, function);
+            names.insert(hash, String::from_utf8_lossy(&name).to_string());
+        }
+        names
+    }
+
+    /// Check if a syscall is registered under the symbol hash, bound or not
+    pub fn contains_syscall(&self, hash: u32) -> bool {
+        self.entries.contains_key(&hash)
+            || self
+                .unbound_entries
+                .keys()
+                .any(|name| ebpf::hash_symbol_name(name) == hash)
     }
 
     /// Get a symbol's function pointer
@@ -144,29 +247,45 @@ impl<C: ContextObject> SyscallRegistry<C> {
 
     /// Get the number of registered syscalls
     pub fn get_number_of_syscalls(&self) -> usize {
-        self.entries.len()
+        self.entries.len() + self.unbound_entries.len()
     }
 
     /// Calculate memory size
     pub fn mem_size(&self) -> usize {
+        #[cfg(feature = "std")]
+        let entries_capacity = self.entries.capacity();
+        #[cfg(not(feature = "std"))]
+        let entries_capacity = self.entries.len();
         mem::size_of::<Self>()
-            + self.entries.capacity() * mem::size_of::<(u32, SyscallFunction<C>)>()
+            + entries_capacity * mem::size_of::<(u32, SyscallFunction<C>)>()
+            + self.unbound_entries.keys().fold(0, |state, name| {
+                state + name.capacity() + mem::size_of::<(Vec<u8>, SyscallFunction<C>)>()
+            })
     }
 }
 
 impl<C: ContextObject> Default for SyscallRegistry<C> {
     fn default() -> Self {
         Self {
-            entries: HashMap::new(),
+            entries: SyscallMap::new(),
+            unbound_entries: BTreeMap::new(),
         }
     }
 }
 
 impl<C: ContextObject> Debug for SyscallRegistry<C> {
-    fn fmt(&self, f: &mut std::fmt::Formatter) -> Result<(), std::fmt::Error> {
+    fn fmt(&self, f: &mut core::fmt::Formatter) -> Result<(), core::fmt::Error> {
         writeln!(f, "{:?}", unsafe {
-            std::mem::transmute::<_, &HashMap<u32, *const u8>>(&self.entries)
+            mem::transmute::<_, &SyscallMap<u32, *const u8>>(&self.entries)
         })?;
+        writeln!(
+            f,
+            "{:?}",
+            self.unbound_entries
+                .keys()
+                .map(|name| String::from_utf8_lossy(name))
+                .collect::<Vec<_>>()
+        )?;
         Ok(())
     }
 }
@@ -181,15 +300,95 @@ impl<C: ContextObject> PartialEq for SyscallRegistry<C> {
                 return false;
             }
         }
+        for ((a_name, a_function), (b_name, b_function)) in self
+            .unbound_entries
+            .iter()
+            .zip(other.unbound_entries.iter())
+        {
+            if a_name != b_name || *a_function as usize != *b_function as usize {
+                return false;
+            }
+        }
         true
     }
 }
 
+/// Outcome of an integer division or modulo by zero and of a signed division overflow (`MIN / -1`)
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DivByZeroBehavior {
+    /// Abort with `EbpfError::DivideByZero` or `EbpfError::DivideOverflow`
+    Trap,
+    /// The destination register is set to zero
+    ReturnZero,
+    /// The destination register is set to all ones of the operand size, sign extended for signed 32 bit divisions
+    ReturnAllOnes,
+}
+
+/// Outcome of a load from a virtual address which is not mapped or not readable
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OobReadBehavior {
+    /// Abort with the error of the memory mapping, e.g. `EbpfError::AccessViolation`
+    Fault,
+    /// The load reads zero. Stores still abort.
+    ReturnZero,
+}
+
+/// Set of opcodes, see `Config::valid_opcode_extensions` and `Config::allowed_opcodes`
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct OpcodeSet([u64; 4]);
+
+impl OpcodeSet {
+    /// Returns the set of all 256 opcodes
+    pub fn all() -> Self {
+        Self([u64::MAX; 4])
+    }
+
+    /// Removes an opcode from the set
+    pub fn remove(&mut self, opcode: u8) {
+        self.0[opcode as usize / 64] &= !(1 << (opcode % 64));
+    }
+
+    /// Removes all opcodes of an instruction class (e.g. `ebpf::BPF_STX`) from the set
+    pub fn remove_class(&mut self, class: u8) {
+        for opcode in 0..=u8::MAX {
+            if opcode & ebpf::BPF_CLS_MASK == class {
+                self.remove(opcode);
+            }
+        }
+    }
+
+    /// Adds an opcode to the set
+    pub fn insert(&mut self, opcode: u8) {
+        self.0[opcode as usize / 64] |= 1 << (opcode % 64);
+    }
+
+    /// Returns true if the set contains the opcode
+    pub fn contains(&self, opcode: u8) -> bool {
+        self.0[opcode as usize / 64] & 1 << (opcode % 64) != 0
+    }
+}
+
+/// Handler of an opcode which is not part of the instruction set, see `EbpfVm::register_opcode_extension()`
+///
+/// Receives the instruction, the registers r0 to r10 and the memory mapping.
+/// Returning an error aborts the program.
+pub type OpcodeExtension<'a, E> =
+    dyn FnMut(&ebpf::Insn, &mut [u64; 11], &mut MemoryMapping) -> Result<(), EbpfError<E>> + 'a;
+
 /// VM configuration settings
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub struct Config {
-    /// Maximum call depth
+    /// Maximum call depth, including the frame of the entrypoint
+    ///
+    /// The stack region holds this many frames, see `stack_size()`. Exceeding it
+    /// raises `EbpfError::CallDepthExceeded` in both the interpreter and the JIT.
     pub max_call_depth: usize,
+    /// Number of stack frames allocated up front, the stack grows on demand up to `max_call_depth`.
+    ///
+    /// `None` allocates all frames up front. Only applies to fixed stack frames
+    /// (`dynamic_stack_frames = false`) and the interpreter, the JIT always allocates all frames.
+    /// Accesses to frames which are not allocated yet are access violations.
+    pub initial_stack_frames: Option<usize>,
     /// Size of a stack frame in bytes, must match the size specified in the LLVM BPF backend
     pub stack_frame_size: usize,
     /// Enables gaps in VM address space between the stack frames
@@ -198,14 +397,23 @@ pub struct Config {
     pub instruction_meter_checkpoint_distance: usize,
     /// Enable instruction meter and limiting
     pub enable_instruction_meter: bool,
+    /// Upper bound of the instructions a single run may execute
+    ///
+    /// The effective limit is the smaller one of this and the remaining budget of
+    /// the instruction meter, so a larger budget at runtime can not raise it.
+    pub max_instructions: u64,
     /// Enable instruction tracing
     pub enable_instruction_tracing: bool,
+    /// Track the most bytes of the stack in use at once, see `CallFrames::get_max_stack_bytes()`
+    pub enable_stack_usage_tracking: bool,
     /// Enable dynamic string allocation for labels
     pub enable_symbol_and_section_labels: bool,
     /// Reject ELF files containing issues that the verifier did not catch before (up to v0.2.21)
     pub reject_broken_elfs: bool,
     /// Ratio of native host instructions per random no-op in JIT (0 = OFF)
     pub noop_instruction_rate: u32,
+    /// Number of no-op bytes the JIT reserves behind each function, e.g. to patch them later
+    pub jit_function_padding: usize,
     /// Enable disinfection of immediate values and offsets provided by the user in JIT
     pub sanitize_user_provided_values: bool,
     /// Encrypt the environment registers in JIT
@@ -216,8 +424,21 @@ pub struct Config {
     pub reject_callx_r10: bool,
     /// Use dynamic stack frame sizes
     pub dynamic_stack_frames: bool,
+    /// Zero the stack frame of the callee on every BPF to BPF call, so that it can not observe
+    /// data left behind by previous calls. With dynamic stack frames all of the stack below the
+    /// stack pointer is zeroed.
+    pub clear_stack_on_entry: bool,
     /// Enable native signed division
     pub enable_sdiv: bool,
+    /// Enable the uhmul64 instruction, which writes the 128 bit product of `dst` and `src` to
+    /// the register pair `dst` (low half) and `dst + 1` (high half)
+    pub enable_uhmul: bool,
+    /// Enable the BPF_JMP32 class of conditional jumps, which compare only the low 32 bits
+    pub enable_jmp32: bool,
+    /// What div, mod and sdiv do when dividing by zero or overflowing
+    pub div_by_zero_behavior: DivByZeroBehavior,
+    /// Enable the atomic read-modify-write instructions (BPF_STX | BPF_ATOMIC)
+    pub enable_atomics: bool,
     /// Avoid copying read only sections when possible
     pub optimize_rodata: bool,
     /// Support syscalls via pseudo calls (insn.src = 0)
@@ -232,6 +453,66 @@ pub struct Config {
     pub reject_rodata_stack_overlap: bool,
     /// Use aligned memory mapping
     pub aligned_memory_mapping: bool,
+    /// Have the verifier statically bound the stack usage of BPF to BPF calls
+    pub verify_stack_depth: bool,
+    /// Have the verifier reject recursion and callx it can not bound the stack usage of,
+    /// instead of leaving them to the runtime call depth check
+    pub reject_unbounded_stack_depth: bool,
+    /// Have the verifier reject basic blocks from which no exit instruction is reachable,
+    /// see `VerifierError::InfiniteLoop`
+    pub reject_infinite_loops: bool,
+    /// Have the verifier reject reads of registers which are not initialized on every path leading
+    /// there, see `VerifierError::RegisterNotInitialized`. Conservative, so it may reject valid programs.
+    pub reject_uninitialized_registers: bool,
+    /// Have the verifier reject programs whose last instruction is neither exit nor ja,
+    /// see `VerifierError::FallsOffEnd`. Such programs may only fail at runtime with
+    /// `EbpfError::ExecutionOverrun`, e.g. if the last instruction is a call.
+    pub reject_falls_off_end: bool,
+    /// Let the interpreter hand opcodes it does not implement to the handlers registered with
+    /// `EbpfVm::register_opcode_extension()`. The JIT does not support them.
+    pub allow_opcode_extensions: bool,
+    /// Opcodes the verifier accepts in addition to the instruction set, if `allow_opcode_extensions` is set
+    pub valid_opcode_extensions: OpcodeSet,
+    /// Require loads and stores of N bytes to use a virtual address which is a multiple of N,
+    /// see `EbpfError::UnalignedMemoryAccess`
+    pub enforce_aligned_access: bool,
+    /// What loads from addresses outside of the readable regions do
+    pub oob_read_behavior: OobReadBehavior,
+    /// Prefix which is stripped from the names of relocated syscall symbols before they are bound
+    pub syscall_symbol_prefix: Option<&'static str>,
+    /// Reject ELF files containing sections which are not needed to load a program, see `ElfError::UnexpectedSection`
+    pub reject_unexpected_sections: bool,
+    /// Reject callx targets which are not aligned to an instruction or are not the start of a known function
+    pub reject_callx_to_unaligned: bool,
+    /// Reject callx targets which are not the start of a known function, see `EbpfError::CallxUnknownTarget`
+    ///
+    /// Unlike `reject_callx_to_unaligned` this has its own error, so that it can be told apart from
+    /// `EbpfError::CallOutsideTextSegment`. Off by default as some programs compute targets inside of functions.
+    pub reject_callx_to_unknown_target: bool,
+    /// Largest ELF file in bytes which is loaded, see `ElfError::FileTooLarge`
+    pub max_elf_size: usize,
+    /// Largest number of sections an ELF file may declare, see `ElfError::TooManySections`
+    pub max_section_count: usize,
+    /// Largest buffer in bytes which `MemoryMapping::translate_slice()` translates for a syscall,
+    /// see `EbpfError::SyscallBufferTooLarge`
+    pub max_syscall_buffer_len: usize,
+    /// Opcodes the verifier accepts, see `VerifierError::OpcodeNotAllowed`
+    ///
+    /// Defaults to all opcodes. Removing some restricts programs to a subset of the instruction set.
+    pub allowed_opcodes: OpcodeSet,
+    /// Fail syscalls with `EbpfError::SyscallsSealed` once `ContextObject::syscalls_sealed()` returns true
+    ///
+    /// The context object can be sealed by the host or by the program, see `syscalls::bpf_seal_syscalls`.
+    pub enable_syscall_sealing: bool,
+    /// Virtual address of the program region, which holds the read-only sections including the text
+    ///
+    /// ELF files are linked against `ebpf::MM_PROGRAM_START`, the loader moves the sections and all
+    /// relocated addresses to this base instead. With `aligned_memory_mapping` it must stay within
+    /// the 4 GiB slot of the region index, `EbpfVm::new()` fails with `EbpfError::InvalidMemoryRegion`
+    /// if the region overlaps another one.
+    pub rodata_vaddr_base: u64,
+    /// Virtual address at which `EbpfVm::new()` maps the heap region
+    pub heap_vaddr_base: u64,
 }
 
 impl Config {
@@ -245,30 +526,67 @@ impl Default for Config {
     fn default() -> Self {
         Self {
             max_call_depth: 20,
+            initial_stack_frames: None,
             stack_frame_size: 4_096,
             enable_stack_frame_gaps: true,
             instruction_meter_checkpoint_distance: 10000,
             enable_instruction_meter: true,
+            max_instructions: u64::MAX,
             enable_instruction_tracing: false,
+            enable_stack_usage_tracking: false,
             enable_symbol_and_section_labels: false,
             reject_broken_elfs: false,
             noop_instruction_rate: 256,
+            jit_function_padding: 0,
             sanitize_user_provided_values: true,
             encrypt_environment_registers: true,
             syscall_bpf_function_hash_collision: true,
             reject_callx_r10: true,
             dynamic_stack_frames: true,
+            clear_stack_on_entry: false,
             enable_sdiv: true,
+            enable_uhmul: false,
+            enable_jmp32: false,
+            div_by_zero_behavior: DivByZeroBehavior::Trap,
+            enable_atomics: false,
             optimize_rodata: true,
             static_syscalls: true,
             enable_elf_vaddr: true,
             new_elf_parser: true,
             reject_rodata_stack_overlap: true,
             aligned_memory_mapping: true,
+            verify_stack_depth: false,
+            reject_unbounded_stack_depth: false,
+            reject_infinite_loops: false,
+            reject_uninitialized_registers: false,
+            reject_falls_off_end: false,
+            allow_opcode_extensions: false,
+            valid_opcode_extensions: OpcodeSet::default(),
+            enforce_aligned_access: false,
+            oob_read_behavior: OobReadBehavior::Fault,
+            syscall_symbol_prefix: None,
+            reject_unexpected_sections: false,
+            reject_callx_to_unaligned: false,
+            reject_callx_to_unknown_target: false,
+            max_elf_size: usize::MAX,
+            max_section_count: u16::MAX as usize,
+            max_syscall_buffer_len: usize::MAX,
+            allowed_opcodes: OpcodeSet::all(),
+            enable_syscall_sealing: false,
+            rodata_vaddr_base: ebpf::MM_PROGRAM_START,
+            heap_vaddr_base: ebpf::MM_HEAP_START,
         }
     }
 }
 
+/// Converts an error of `Executable::load()` like `Executable::from_elf()` does
+fn elf_load_error<E: UserDefinedError>(err: ElfError) -> EbpfError<E> {
+    match err {
+        ElfError::UnresolvedSymbol(name, _pc, _offset) => EbpfError::SyscallNotRegistered(name),
+        err => EbpfError::ElfError(err),
+    }
+}
+
 /// Static constructors for Executable
 impl<C: ContextObject> Executable<C> {
     /// Creates an executable from an ELF file
@@ -276,8 +594,9 @@ impl<C: ContextObject> Executable<C> {
         elf_bytes: &[u8],
         config: Config,
         syscall_registry: SyscallRegistry<C>,
-    ) -> Result<Self, EbpfError> {
-        let executable = Executable::load(config, elf_bytes, syscall_registry)?;
+    ) -> Result<Self, EbpfError<C::UserError>> {
+        let executable =
+            Executable::load(config, elf_bytes, syscall_registry).map_err(elf_load_error)?;
         Ok(executable)
     }
     /// Creates an executable from machine code
@@ -286,12 +605,26 @@ impl<C: ContextObject> Executable<C> {
         config: Config,
         syscall_registry: SyscallRegistry<C>,
         function_registry: FunctionRegistry,
-    ) -> Result<Self, EbpfError> {
+    ) -> Result<Self, EbpfError<C::UserError>> {
         Executable::new_from_text_bytes(config, text_bytes, syscall_registry, function_registry)
             .map_err(EbpfError::ElfError)
     }
 }
 
+/// Duration of each phase of `VerifiedExecutable::load_and_jit()`
+#[cfg(feature = "jit")]
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct LoadTimings {
+    /// Parsing and validating the ELF, excluding the relocation
+    pub elf_parse: Duration,
+    /// Applying the relocations and binding the syscalls
+    pub relocation: Duration,
+    /// Running the verifier
+    pub verification: Duration,
+    /// Compiling to machine code
+    pub jit_compilation: Duration,
+}
+
 /// Verified executable
 #[derive(Debug, PartialEq)]
 #[repr(transparent)]
@@ -302,7 +635,7 @@ pub struct VerifiedExecutable<V: Verifier, C: ContextObject> {
 
 impl<V: Verifier, C: ContextObject> VerifiedExecutable<V, C> {
     /// Verify an executable
-    pub fn from_executable(executable: Executable<C>) -> Result<Self, EbpfError> {
+    pub fn from_executable(executable: Executable<C>) -> Result<Self, EbpfError<C::UserError>> {
         <V as Verifier>::verify(
             executable.get_text_bytes().1,
             executable.get_config(),
@@ -314,12 +647,90 @@ impl<V: Verifier, C: ContextObject> VerifiedExecutable<V, C> {
         })
     }
 
+    /// Creates and verifies an executable from machine code, without any ELF wrapping
+    ///
+    /// The instruction at pc 0 becomes the entrypoint and the text is the only mapped program region.
+    pub fn from_text_bytes(
+        text_bytes: &[u8],
+        config: Config,
+        syscall_registry: SyscallRegistry<C>,
+    ) -> Result<Self, EbpfError<C::UserError>> {
+        let executable = Executable::from_text_bytes(
+            text_bytes,
+            config,
+            syscall_registry,
+            FunctionRegistry::default(),
+        )?;
+        Self::from_executable(executable)
+    }
+
     /// JIT compile the executable
     #[cfg(feature = "jit")]
-    pub fn jit_compile(&mut self) -> Result<(), EbpfError> {
+    pub fn jit_compile(&mut self) -> Result<(), EbpfError<C::UserError>> {
         Executable::<C>::jit_compile(&mut self.executable)
     }
 
+    /// JIT compile the executable without checking loads and stores against the memory regions
+    ///
+    /// # Safety
+    ///
+    /// See `JitProgram::new_without_bounds_checks()`.
+    #[cfg(feature = "jit")]
+    pub unsafe fn jit_compile_without_bounds_checks(
+        &mut self,
+    ) -> Result<(), EbpfError<C::UserError>> {
+        Executable::<C>::jit_compile_without_bounds_checks(&mut self.executable)
+    }
+
+    /// Loads, verifies and JIT compiles an ELF, measuring how long each phase took
+    #[cfg(feature = "jit")]
+    pub fn load_and_jit(
+        elf_bytes: &[u8],
+        config: Config,
+        syscall_registry: SyscallRegistry<C>,
+    ) -> Result<(Self, LoadTimings), EbpfError<C::UserError>> {
+        let mut timings = LoadTimings::default();
+        let start = Instant::now();
+        let executable = Executable::load_and_intern(
+            config,
+            elf_bytes,
+            syscall_registry,
+            None,
+            Some(&mut timings.relocation),
+        )
+        .map_err(elf_load_error)?;
+        timings.elf_parse = start.elapsed().saturating_sub(timings.relocation);
+        let start = Instant::now();
+        let mut verified_executable = Self::from_executable(executable)?;
+        timings.verification = start.elapsed();
+        let start = Instant::now();
+        verified_executable.jit_compile()?;
+        timings.jit_compilation = start.elapsed();
+        Ok((verified_executable, timings))
+    }
+
+    /// Loads machine code which `JitProgram::serialize()` produced for an identical executable
+    ///
+    /// # Safety
+    ///
+    /// The machine code in `bytes` is executed as is, see `JitProgram::deserialize()`.
+    #[cfg(feature = "jit")]
+    pub unsafe fn load_compiled_program(
+        &mut self,
+        bytes: &[u8],
+    ) -> Result<(), EbpfError<C::UserError>> {
+        Executable::<C>::load_compiled_program(&mut self.executable, bytes)
+    }
+
+    /// Replace the function of a registered syscall, see `Executable::replace_syscall_handler()`
+    pub fn replace_syscall_handler(
+        &mut self,
+        hash: u32,
+        function: SyscallFunction<C>,
+    ) -> Result<(), EbpfError<C::UserError>> {
+        self.executable.replace_syscall_handler(hash, function)
+    }
+
     /// Get a reference to the underlying executable
     pub fn get_executable(&self) -> &Executable<C> {
         &self.executable
@@ -328,14 +739,124 @@ impl<V: Verifier, C: ContextObject> VerifiedExecutable<V, C> {
 
 /// Runtime context
 pub trait ContextObject {
+    /// Error which syscalls return in `EbpfError::UserError`
+    type UserError: UserDefinedError;
     /// Called for every instruction executed when tracing is enabled
     fn trace(&mut self, state: [u64; 12]);
     /// Consume instructions from meter
     fn consume(&mut self, amount: u64);
     /// Get the number of remaining instructions allowed
     fn get_remaining(&self) -> u64;
+    /// Whether syscalls are blocked, see `Config::enable_syscall_sealing`
+    fn syscalls_sealed(&self) -> bool {
+        false
+    }
+    /// Blocks all further syscalls, see `Config::enable_syscall_sealing`
+    fn seal_syscalls(&mut self) {}
+    /// Releases the heap allocations of a previous run, see `VmPool::get()`
+    fn reset_allocator(&mut self) {}
 }
 
+/// Instrumentation hook invoked around every syscall by the interpreter and the JIT
+pub trait SyscallObserver<E: UserDefinedError> {
+    /// Called before the syscall with the given number (hash or static id) is dispatched
+    fn on_syscall_enter(&mut self, number: u32);
+    /// Called after the syscall with the given number returned, even if it failed
+    fn on_syscall_exit(&mut self, number: u32);
+    /// Called after `on_syscall_enter()`, returning a result skips the registered syscall function
+    fn replace_syscall(&mut self, _number: u32) -> Option<ProgramResult<E>> {
+        None
+    }
+    /// Called before `on_syscall_exit()` with the result of the syscall or its replacement
+    fn on_syscall_result(&mut self, _number: u32, _result: &ProgramResult<E>) {}
+}
+
+/// Records the return values of all syscalls, see `SyscallReplayer`
+///
+/// A syscall which fails is not recorded.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct SyscallRecorder {
+    /// Syscall numbers and their return values in order of execution
+    pub log: Vec<(u32, u64)>,
+}
+
+impl<E: UserDefinedError> SyscallObserver<E> for SyscallRecorder {
+    fn on_syscall_enter(&mut self, _number: u32) {}
+    fn on_syscall_exit(&mut self, _number: u32) {}
+    fn on_syscall_result(&mut self, number: u32, result: &ProgramResult<E>) {
+        if let ProgramResult::Ok(value) = result {
+            self.log.push((number, *value));
+        }
+    }
+}
+
+/// Replays the return values recorded by a `SyscallRecorder` instead of invoking the syscall functions
+///
+/// A syscall which differs from the recorded one fails with `EbpfError::SyscallReplayMismatch`.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct SyscallReplayer {
+    log: Vec<(u32, u64)>,
+    next: usize,
+}
+
+impl SyscallReplayer {
+    /// Creates a replayer for the syscall numbers and return values in `log`
+    pub fn new(log: Vec<(u32, u64)>) -> Self {
+        Self { log, next: 0 }
+    }
+
+    /// Fails with `EbpfError::SyscallReplayMismatch` if not all recorded syscalls were replayed
+    pub fn finish<E: UserDefinedError>(&self) -> Result<(), EbpfError<E>> {
+        match self.log.get(self.next) {
+            Some((recorded, _)) => Err(EbpfError::SyscallReplayMismatch(
+                self.next,
+                Some(*recorded),
+                None,
+            )),
+            None => Ok(()),
+        }
+    }
+}
+
+impl<E: UserDefinedError> SyscallObserver<E> for SyscallReplayer {
+    fn on_syscall_enter(&mut self, _number: u32) {}
+    fn on_syscall_exit(&mut self, _number: u32) {}
+    fn replace_syscall(&mut self, number: u32) -> Option<ProgramResult<E>> {
+        let index = self.next;
+        self.next = self.next.saturating_add(1);
+        Some(match self.log.get(index) {
+            Some((recorded, value)) if *recorded == number => ProgramResult::Ok(*value),
+            recorded => ProgramResult::Err(EbpfError::SyscallReplayMismatch(
+                index,
+                recorded.map(|(recorded, _)| *recorded),
+                Some(number),
+            )),
+        })
+    }
+}
+
+/// Instrumentation hook invoked around every BPF to BPF call by the interpreter and the JIT
+pub trait CallObserver {
+    /// Called when a call to the function starting at the instruction index `target_pc` is entered
+    fn on_call_enter(&mut self, target_pc: usize);
+    /// Called when the innermost called function returns
+    fn on_call_return(&mut self);
+}
+
+/// User defined error which syscalls used in tests return
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TestUserError(pub String);
+
+impl fmt::Display for TestUserError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+impl Error for TestUserError {}
+
+impl UserDefinedError for TestUserError {}
+
 /// Simple instruction meter for testing
 #[derive(Debug, Clone, Default, PartialEq, Eq)]
 pub struct TestContextObject {
@@ -343,9 +864,15 @@ pub struct TestContextObject {
     pub trace_log: Vec<[u64; 12]>,
     /// Maximal amount of instructions which still can be executed
     pub remaining: u64,
+    /// Heap allocator used by the `bpf_alloc_free` syscall
+    pub allocator: BpfAllocator,
+    /// Set by `seal_syscalls()`
+    pub syscalls_sealed: bool,
 }
 
 impl ContextObject for TestContextObject {
+    type UserError = TestUserError;
+
     fn trace(&mut self, state: [u64; 12]) {
         self.trace_log.push(state);
     }
@@ -358,6 +885,18 @@ impl ContextObject for TestContextObject {
     fn get_remaining(&self) -> u64 {
         self.remaining
     }
+
+    fn syscalls_sealed(&self) -> bool {
+        self.syscalls_sealed
+    }
+
+    fn seal_syscalls(&mut self) {
+        self.syscalls_sealed = true;
+    }
+
+    fn reset_allocator(&mut self) {
+        self.allocator.reset();
+    }
 }
 
 impl TestContextObject {
@@ -366,10 +905,13 @@ impl TestContextObject {
         Self {
             trace_log: Vec::new(),
             remaining,
+            allocator: BpfAllocator::default(),
+            syscalls_sealed: false,
         }
     }
 
     /// Use this method to print the trace log
+    #[cfg(feature = "std")]
     pub fn write_trace_log<W: std::io::Write, C: ContextObject>(
         &self,
         output: &mut W,
@@ -396,7 +938,7 @@ impl TestContextObject {
                 index,
                 &entry[0..11],
                 pc + ebpf::ELF_INSN_DUMP_OFFSET,
-                disassemble_instruction(insn, analysis),
+                disassemble_instruction_to_string(insn, analysis),
             )?;
         }
         Ok(())
@@ -415,7 +957,24 @@ impl TestContextObject {
     }
 }
 
+/// Assigns the amount to be consumed from the instruction meter to each instruction
+pub trait CostModel {
+    /// Cost of executing the given instruction (lddw is passed with its full 64 bit immediate)
+    fn cost(&self, insn: &ebpf::Insn) -> u64;
+}
+
+/// Every instruction costs one unit, so that the meter counts instructions
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct UniformCostModel;
+
+impl CostModel for UniformCostModel {
+    fn cost(&self, _insn: &ebpf::Insn) -> u64 {
+        1
+    }
+}
+
 /// Statistic of taken branches (from a recorded trace)
+#[cfg(feature = "std")]
 pub struct DynamicAnalysis {
     /// Maximal edge counter value
     pub edge_counter_max: usize,
@@ -423,6 +982,7 @@ pub struct DynamicAnalysis {
     pub edges: BTreeMap<usize, BTreeMap<usize, usize>>,
 }
 
+#[cfg(feature = "std")]
 impl DynamicAnalysis {
     /// Accumulates a trace
     pub fn new<C: ContextObject>(trace_log: &[[u64; 12]], analysis: &Analysis<C>) -> Self {
@@ -483,6 +1043,14 @@ pub struct EbpfVm<'a, V: Verifier, C: ContextObject> {
     /// Pointer to the context object of syscalls
     pub context_object: &'a mut C,
     pub(crate) stack: CallFrames<'a>,
+    /// Optional hook invoked around every syscall
+    pub(crate) syscall_observer: Option<&'a mut dyn SyscallObserver<C::UserError>>,
+    /// Optional hook invoked around every BPF to BPF call
+    pub(crate) call_observer: Option<&'a mut dyn CallObserver>,
+    /// Handlers of opcodes which are not part of the instruction set, by opcode
+    pub(crate) opcode_extensions: BTreeMap<u8, &'a mut OpcodeExtension<'a, C::UserError>>,
+    /// The registers at the `exit` instruction of the last successful run
+    pub(crate) last_registers: [u64; 11],
 }
 
 impl<'a, V: Verifier, C: ContextObject> EbpfVm<'a, V, C> {
@@ -492,51 +1060,183 @@ impl<'a, V: Verifier, C: ContextObject> EbpfVm<'a, V, C> {
         context_object: &'a mut C,
         heap_region: &mut [u8],
         additional_regions: Vec<MemoryRegion>,
-    ) -> Result<EbpfVm<'a, V, C>, EbpfError> {
-        let executable = verified_executable.get_executable();
-        let config = executable.get_config();
-        let mut stack = CallFrames::new(config);
+    ) -> Result<EbpfVm<'a, V, C>, EbpfError<C::UserError>> {
+        let config = verified_executable.get_executable().get_config();
+        Self::new_with_call_frames(
+            verified_executable,
+            context_object,
+            CallFrames::new(config),
+            heap_region,
+            additional_regions,
+        )
+    }
+
+    /// Create a new virtual machine instance which uses the given `stack`
+    pub(crate) fn new_with_call_frames(
+        verified_executable: &'a VerifiedExecutable<V, C>,
+        context_object: &'a mut C,
+        mut stack: CallFrames<'a>,
+        heap_region: &mut [u8],
+        additional_regions: Vec<MemoryRegion>,
+    ) -> Result<EbpfVm<'a, V, C>, EbpfError<C::UserError>> {
+        let config = verified_executable.get_executable().get_config();
         let regions: Vec<MemoryRegion> = vec![
             verified_executable.get_executable().get_ro_region(),
             stack.get_memory_region(),
-            MemoryRegion::new_writable(heap_region, ebpf::MM_HEAP_START),
+            MemoryRegion::new_writable(heap_region, config.heap_vaddr_base),
         ]
         .into_iter()
         .chain(additional_regions.into_iter())
         .collect();
-        let vm = EbpfVm {
+        let memory_mapping = MemoryMapping::new(regions, config)?;
+        Ok(Self::new_with_memory_mapping(
             verified_executable,
-            memory_mapping: MemoryMapping::new(regions, config)?,
             context_object,
             stack,
-        };
-        Ok(vm)
+            memory_mapping,
+        ))
+    }
+
+    /// Create a new virtual machine instance which uses the given `stack` and `memory_mapping`
+    ///
+    /// The `memory_mapping` must map the regions of the executable and of `stack`.
+    pub(crate) fn new_with_memory_mapping(
+        verified_executable: &'a VerifiedExecutable<V, C>,
+        context_object: &'a mut C,
+        stack: CallFrames<'a>,
+        memory_mapping: MemoryMapping<'a>,
+    ) -> EbpfVm<'a, V, C> {
+        EbpfVm {
+            verified_executable,
+            memory_mapping,
+            context_object,
+            stack,
+            syscall_observer: None,
+            call_observer: None,
+            opcode_extensions: BTreeMap::new(),
+            last_registers: [0; 11],
+        }
+    }
+
+    /// Create a new virtual machine instance with one memory region per input buffer
+    ///
+    /// Each `(bytes, writable)` buffer is mapped without copying at
+    /// `MM_INPUT_START + index * (1 << VIRTUAL_ADDRESS_BITS)`, so r1 points at the first one.
+    pub fn new_with_inputs(
+        verified_executable: &'a VerifiedExecutable<V, C>,
+        context_object: &'a mut C,
+        heap_region: &mut [u8],
+        inputs: Vec<(&mut [u8], bool)>,
+    ) -> Result<EbpfVm<'a, V, C>, EbpfError<C::UserError>> {
+        let regions = inputs
+            .into_iter()
+            .enumerate()
+            .map(|(index, (bytes, writable))| {
+                let vm_addr = ebpf::MM_INPUT_START
+                    .saturating_add((index as u64) << ebpf::VIRTUAL_ADDRESS_BITS);
+                if writable {
+                    MemoryRegion::new_writable(bytes, vm_addr)
+                } else {
+                    MemoryRegion::new_readonly(bytes, vm_addr)
+                }
+            })
+            .collect();
+        Self::new(verified_executable, context_object, heap_region, regions)
+    }
+
+    /// Gives up the call frames, including the stack memory, and the memory mapping, see `VmPool`
+    pub(crate) fn into_parts(self) -> (CallFrames<'a>, MemoryMapping<'a>) {
+        (self.stack, self.memory_mapping)
+    }
+
+    /// Remaps the stack region after `CallFrames` reallocated it, see `Config::initial_stack_frames`
+    pub(crate) fn remap_resized_stack(&mut self) -> Result<(), EbpfError<C::UserError>> {
+        if let Some(stack_region) = self.stack.take_resized_memory_region() {
+            let index = self
+                .memory_mapping
+                .get_regions()
+                .iter()
+                .position(|region| region.vm_addr == ebpf::MM_STACK_START)
+                .ok_or(EbpfError::InvalidMemoryRegion(0))?;
+            self.memory_mapping.replace_region(index, stack_region)?;
+        }
+        Ok(())
+    }
+
+    /// Installs a hook which is invoked around every syscall, see `SyscallObserver`
+    pub fn set_syscall_observer(
+        &mut self,
+        syscall_observer: &'a mut dyn SyscallObserver<C::UserError>,
+    ) {
+        self.syscall_observer = Some(syscall_observer);
+    }
+
+    /// Installs a hook which is invoked around every BPF to BPF call, see `CallObserver`
+    pub fn set_call_observer(&mut self, call_observer: &'a mut dyn CallObserver) {
+        self.call_observer = Some(call_observer);
+    }
+
+    /// Registers the handler the interpreter calls for `opcode`, see `Config::allow_opcode_extensions`
+    ///
+    /// Opcodes the interpreter implements never reach their handler. The verifier
+    /// still rejects `opcode` unless it is also in `Config::valid_opcode_extensions`.
+    pub fn register_opcode_extension(
+        &mut self,
+        opcode: u8,
+        handler: &'a mut OpcodeExtension<'a, C::UserError>,
+    ) {
+        self.opcode_extensions.insert(opcode, handler);
+    }
+
+    /// Returns the registers r0 to r10 as they were at the `exit` instruction
+    ///
+    /// Only successful runs update them, all registers are zero after a failed run.
+    pub fn last_registers(&self) -> &[u64; 11] {
+        &self.last_registers
+    }
+
+    /// Returns the number of successful loads and stores per region, see `MemoryMapping::access_counts`
+    #[cfg(feature = "access-counters")]
+    pub fn access_counts(&self) -> Vec<u64> {
+        self.memory_mapping.access_counts()
+    }
+
+    /// Sets all access counts to zero, e.g. before executing the program again
+    #[cfg(feature = "access-counters")]
+    pub fn reset_access_counts(&mut self) {
+        self.memory_mapping.reset_access_counts();
+    }
+
+    /// Installs a hook which is invoked before every load and store, see `MemoryMapping::set_memory_observer`
+    #[cfg(feature = "memory-observer")]
+    pub fn set_memory_observer(&mut self, memory_observer: &'a mut dyn MemoryObserver) {
+        self.memory_mapping.set_memory_observer(memory_observer);
     }
 
     /// Execute the program
     ///
     /// If interpreted = `false` then the JIT compiled executable is used.
-    pub fn execute_program(&mut self, interpreted: bool) -> (u64, ProgramResult) {
+    pub fn execute_program(&mut self, interpreted: bool) -> (u64, ProgramResult<C::UserError>) {
         let executable = self.verified_executable.get_executable();
         let initial_insn_count = if executable.get_config().enable_instruction_meter {
             self.context_object.get_remaining()
         } else {
             0
         };
+        // The part of the budget which is cut off by Config::max_instructions
+        let insn_meter_offset =
+            initial_insn_count.saturating_sub(executable.get_config().max_instructions);
+        self.last_registers = [0; 11];
         let (due_insn_count, result) = if interpreted {
-            let mut result = Ok(None);
             let mut interpreter = match Interpreter::new(self) {
                 Ok(interpreter) => interpreter,
                 Err(error) => return (0, ProgramResult::Err(error)),
             };
-            while let Ok(None) = result {
-                result = interpreter.step();
-            }
+            let result = interpreter.run();
             (
                 interpreter.due_insn_count,
                 match result {
-                    Ok(None) => unreachable!(),
-                    Ok(Some(value)) => ProgramResult::Ok(value),
+                    Ok(value) => ProgramResult::Ok(value),
                     Err(error) => ProgramResult::Err(error),
                 },
             )
@@ -544,6 +1244,11 @@ impl<'a, V: Verifier, C: ContextObject> EbpfVm<'a, V, C> {
             #[cfg(feature = "jit")]
             {
                 let mut result = ProgramResult::Ok(0);
+                // The JIT manages the call frames itself, so it can not grow the stack on demand
+                self.stack.allocate_all_frames();
+                if let Err(error) = self.remap_resized_stack() {
+                    return (0, ProgramResult::Err(error));
+                }
                 let compiled_program = match executable
                     .get_compiled_program()
                     .ok_or(EbpfError::JitNotCompiled)
@@ -556,18 +1261,22 @@ impl<'a, V: Verifier, C: ContextObject> EbpfVm<'a, V, C> {
                         &mut result,
                         &mut self.memory_mapping,
                         self.context_object,
+                        self.syscall_observer.as_mut(),
+                        self.call_observer.as_mut(),
+                        &mut self.last_registers,
                     )
                 }
                 .max(0) as u64;
                 (
                     self.context_object
                         .get_remaining()
+                        .saturating_sub(insn_meter_offset)
                         .saturating_sub(instruction_meter_final),
                     match result {
                         ProgramResult::Err(EbpfError::ExceededMaxInstructions(pc, _)) => {
                             ProgramResult::Err(EbpfError::ExceededMaxInstructions(
                                 pc,
-                                initial_insn_count,
+                                initial_insn_count - insn_meter_offset,
                             ))
                         }
                         x => x,
@@ -578,7 +1287,14 @@ impl<'a, V: Verifier, C: ContextObject> EbpfVm<'a, V, C> {
             (0, ProgramResult::Err(EbpfError::JitNotCompiled))
         };
         let instruction_count = if executable.get_config().enable_instruction_meter {
-            self.context_object.consume(due_insn_count);
+            // Instructions costing more than one unit can overshoot the remaining budget,
+            // which is also cut off by Config::max_instructions
+            let remaining_insn_count = self
+                .context_object
+                .get_remaining()
+                .saturating_sub(insn_meter_offset);
+            self.context_object
+                .consume(due_insn_count.min(remaining_insn_count));
             initial_insn_count.saturating_sub(self.context_object.get_remaining())
         } else {
             0
@@ -593,9 +1309,38 @@ mod tests {
 
     #[test]
     fn test_program_result_is_stable() {
-        let ok = ProgramResult::Ok(42);
+        let ok = ProgramResult::<TestUserError>::Ok(42);
         assert_eq!(unsafe { *(&ok as *const _ as *const u64) }, 0);
-        let err = ProgramResult::Err(EbpfError::JitNotCompiled);
+        let err = ProgramResult::<TestUserError>::Err(EbpfError::JitNotCompiled);
         assert_eq!(unsafe { *(&err as *const _ as *const u64) }, 1);
     }
+
+    #[test]
+    fn test_syscall_late_binding() {
+        let mut syscall_registry = SyscallRegistry::<TestContextObject>::default();
+        syscall_registry
+            .register_syscall_by_name(b"bpf_syscall_u64", crate::syscalls::bpf_syscall_u64)
+            .unwrap();
+        assert!(matches!(
+            syscall_registry
+                .register_syscall_by_name(b"bpf_syscall_u64", crate::syscalls::bpf_syscall_u64),
+            Err(EbpfError::SyscallAlreadyRegistered(_))
+        ));
+        let hash = ebpf::hash_symbol_name(b"bpf_syscall_u64");
+        assert!(syscall_registry.contains_syscall(hash));
+        assert!(syscall_registry.lookup_syscall(hash).is_none());
+        assert_eq!(syscall_registry.get_number_of_syscalls(), 1);
+        assert_eq!(
+            syscall_registry
+                .bind_syscall_by_name(b"bpf_syscall_u64")
+                .unwrap(),
+            hash
+        );
+        assert!(syscall_registry.lookup_syscall(hash).is_some());
+        assert_eq!(syscall_registry.get_number_of_syscalls(), 1);
+        assert!(matches!(
+            syscall_registry.bind_syscall_by_name(b"bpf_syscall_string"),
+            Err(EbpfError::SyscallNotRegistered(name)) if name == "bpf_syscall_string"
+        ));
+    }
 }
@@ -0,0 +1,224 @@
+//! Pool of reusable VM memory for executing one program many times
+
+use crate::{
+    aligned_memory::AlignedMemory,
+    call_frames::CallFrames,
+    ebpf::HOST_ALIGN,
+    error::EbpfError,
+    memory_region::{MemoryMapping, MemoryRegion},
+    verifier::Verifier,
+    vm::{ContextObject, EbpfVm, VerifiedExecutable},
+};
+use alloc::vec::Vec;
+use core::ops::{Deref, DerefMut};
+
+/// Stack, heap and memory mapping of an idle VM
+struct VmMemory<'a> {
+    stack: AlignedMemory<{ HOST_ALIGN }>,
+    heap: AlignedMemory<{ HOST_ALIGN }>,
+    /// Maps `stack`, `heap` and the regions of the last run, built by the first `get()`
+    memory_mapping: Option<MemoryMapping<'a>>,
+    /// Virtual address and length of each additional region of the last run
+    additional_layout: Vec<(u64, u64)>,
+}
+
+/// Hands out VMs of one program without allocating their stack and heap again
+///
+/// A VM is borrowed with `get()` and handed back with `recycle()`, which zero fills its
+/// stack and heap so that the next execution starts from the same state as a fresh VM.
+/// The registers are not part of the VM and start zeroed on every execution anyway.
+/// Read-only regions are never written by the program, so they need no reset.
+///
+/// The memory mapping is kept as well. If the additional regions passed to `get()` have
+/// the same addresses and lengths as in the last run, all regions are swapped in place,
+/// otherwise the mapping is built again.
+///
+/// # Examples
+///
+/// ```
+/// use solana_rbpf::{
+///     verifier::RequisiteVerifier,
+///     vm::{Config, SyscallRegistry, TestContextObject, VerifiedExecutable},
+///     vm_pool::VmPool,
+/// };
+///
+/// let prog = &[
+///     0xb7, 0x00, 0x00, 0x00, 0x2a, 0x00, 0x00, 0x00, // mov r0, 42
+///     0x95, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, // exit
+/// ];
+/// let verified_executable =
+///     VerifiedExecutable::<RequisiteVerifier, TestContextObject>::from_text_bytes(
+///         prog,
+///         Config::default(),
+///         SyscallRegistry::default(),
+///     )
+///     .unwrap();
+/// let mut pool = VmPool::new(&verified_executable, 0x1000, 2);
+/// for _ in 0..4 {
+///     let mut context_object = TestContextObject::new(2);
+///     let mut vm = pool.get(&mut context_object, Vec::new()).unwrap();
+///     assert_eq!(vm.execute_program(true).1.unwrap(), 42);
+///     pool.recycle(vm);
+/// }
+/// assert_eq!(pool.idle_count(), 2);
+/// ```
+pub struct VmPool<'a, V: Verifier, C: ContextObject> {
+    verified_executable: &'a VerifiedExecutable<V, C>,
+    heap_size: usize,
+    idle: Vec<VmMemory<'a>>,
+}
+
+impl<'a, V: Verifier, C: ContextObject> VmPool<'a, V, C> {
+    /// Creates a pool which starts out with `capacity` idle VMs with a heap of `heap_size` bytes
+    pub fn new(
+        verified_executable: &'a VerifiedExecutable<V, C>,
+        heap_size: usize,
+        capacity: usize,
+    ) -> Self {
+        let mut pool = Self {
+            verified_executable,
+            heap_size,
+            idle: Vec::with_capacity(capacity),
+        };
+        for _ in 0..capacity {
+            let memory = pool.allocate();
+            pool.idle.push(memory);
+        }
+        pool
+    }
+
+    /// Number of VMs which are ready to be handed out without allocating
+    pub fn idle_count(&self) -> usize {
+        self.idle.len()
+    }
+
+    /// Hands out a VM, allocating a new one only if the pool is empty
+    ///
+    /// The heap starts out empty, so the allocator of `context_object` is reset as well.
+    pub fn get<'b>(
+        &mut self,
+        context_object: &'b mut C,
+        additional_regions: Vec<MemoryRegion>,
+    ) -> Result<PooledVm<'b, V, C>, EbpfError<C::UserError>>
+    where
+        'a: 'b,
+    {
+        context_object.reset_allocator();
+        let VmMemory {
+            stack,
+            mut heap,
+            memory_mapping,
+            mut additional_layout,
+        } = self.idle.pop().unwrap_or_else(|| self.allocate());
+        let executable = self.verified_executable.get_executable();
+        let config = executable.get_config();
+        let mut stack = CallFrames::new_with_stack(config, stack);
+        let same_layout = additional_regions
+            .iter()
+            .map(|region| (region.vm_addr, region.len))
+            .eq(additional_layout.iter().copied());
+        let vm = match memory_mapping {
+            Some(memory_mapping) if same_layout => {
+                // Also undoes changes of the last run, e.g. by `MemoryMapping::set_region_writable()`
+                let regions = [
+                    executable.get_ro_region(),
+                    stack.get_memory_region(),
+                    MemoryRegion::new_writable(heap.as_slice_mut(), config.heap_vaddr_base),
+                ];
+                let mut memory_mapping = memory_mapping.rebind(config);
+                for region in IntoIterator::into_iter(regions).chain(additional_regions) {
+                    let index = memory_mapping
+                        .get_regions()
+                        .iter()
+                        .position(|mapped_region| mapped_region.vm_addr == region.vm_addr)
+                        .ok_or(EbpfError::InvalidVirtualAddress(region.vm_addr))?;
+                    memory_mapping.replace_region(index, region)?;
+                }
+                EbpfVm::new_with_memory_mapping(
+                    self.verified_executable,
+                    context_object,
+                    stack,
+                    memory_mapping,
+                )
+            }
+            _ => {
+                additional_layout = additional_regions
+                    .iter()
+                    .map(|region| (region.vm_addr, region.len))
+                    .collect();
+                EbpfVm::new_with_call_frames(
+                    self.verified_executable,
+                    context_object,
+                    stack,
+                    heap.as_slice_mut(),
+                    additional_regions,
+                )?
+            }
+        };
+        Ok(PooledVm {
+            vm,
+            heap,
+            additional_layout,
+        })
+    }
+
+    /// Resets the stack and heap of `vm` and keeps them for the next `get()`
+    ///
+    /// Both are zeroed as a whole, because the program can store to any address of their
+    /// regions, not only to the frames it called into or the memory it allocated.
+    pub fn recycle(&mut self, vm: PooledVm<'_, V, C>) {
+        let PooledVm {
+            vm,
+            mut heap,
+            additional_layout,
+        } = vm;
+        let (stack, memory_mapping) = vm.into_parts();
+        #[allow(unused_mut)]
+        let mut memory_mapping =
+            memory_mapping.rebind(self.verified_executable.get_executable().get_config());
+        #[cfg(feature = "access-counters")]
+        memory_mapping.reset_access_counts();
+        let mut stack = stack.into_stack();
+        stack.as_slice_mut().fill(0);
+        heap.as_slice_mut().fill(0);
+        self.idle.push(VmMemory {
+            stack,
+            heap,
+            memory_mapping: Some(memory_mapping),
+            additional_layout,
+        });
+    }
+
+    fn allocate(&self) -> VmMemory<'a> {
+        let stack = CallFrames::new(self.verified_executable.get_executable().get_config());
+        VmMemory {
+            stack: stack.into_stack(),
+            heap: AlignedMemory::zero_filled(self.heap_size),
+            memory_mapping: None,
+            additional_layout: Vec::new(),
+        }
+    }
+}
+
+/// A VM handed out by `VmPool::get()`, dereferences to the `EbpfVm`
+pub struct PooledVm<'b, V: Verifier, C: ContextObject> {
+    vm: EbpfVm<'b, V, C>,
+    /// Backs the heap region of `vm`
+    heap: AlignedMemory<{ HOST_ALIGN }>,
+    /// Virtual address and length of each additional region of `vm`
+    additional_layout: Vec<(u64, u64)>,
+}
+
+impl<'b, V: Verifier, C: ContextObject> Deref for PooledVm<'b, V, C> {
+    type Target = EbpfVm<'b, V, C>;
+
+    fn deref(&self) -> &Self::Target {
+        &self.vm
+    }
+}
+
+impl<'b, V: Verifier, C: ContextObject> DerefMut for PooledVm<'b, V, C> {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        &mut self.vm
+    }
+}
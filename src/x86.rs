@@ -238,10 +238,7 @@ impl X86Instruction {
         destination: u8,
         indirect: Option<X86IndirectAccess>,
     ) -> Self {
-        exclude_operand_sizes!(
-            size,
-            OperandSize::S0 | OperandSize::S8 | OperandSize::S16 | OperandSize::S32,
-        );
+        exclude_operand_sizes!(size, OperandSize::S0 | OperandSize::S8 | OperandSize::S16);
         Self {
             size,
             opcode: 0x87,
@@ -252,6 +249,46 @@ impl X86Instruction {
         }
     }
 
+    /// Store the sum of source and destination in destination and the previous destination in source
+    #[inline]
+    pub const fn xadd(
+        size: OperandSize,
+        source: u8,
+        destination: u8,
+        indirect: Option<X86IndirectAccess>,
+    ) -> Self {
+        exclude_operand_sizes!(size, OperandSize::S0 | OperandSize::S8 | OperandSize::S16);
+        Self {
+            size,
+            opcode_escape_sequence: 1,
+            opcode: 0xc1,
+            first_operand: source,
+            second_operand: destination,
+            indirect,
+            ..Self::DEFAULT
+        }
+    }
+
+    /// Store source in destination if RAX equals destination, otherwise load destination into RAX
+    #[inline]
+    pub const fn cmpxchg(
+        size: OperandSize,
+        source: u8,
+        destination: u8,
+        indirect: Option<X86IndirectAccess>,
+    ) -> Self {
+        exclude_operand_sizes!(size, OperandSize::S0 | OperandSize::S8 | OperandSize::S16);
+        Self {
+            size,
+            opcode_escape_sequence: 1,
+            opcode: 0xb1,
+            first_operand: source,
+            second_operand: destination,
+            indirect,
+            ..Self::DEFAULT
+        }
+    }
+
     /// Swap byte order of destination
     #[inline]
     pub const fn bswap(size: OperandSize, destination: u8) -> Self {
@@ -499,6 +536,20 @@ impl X86Instruction {
         }
     }
 
+    /// Load destination from 64 bit immediate, always using the long encoding so that it can be patched later
+    #[inline]
+    pub const fn load_immediate_wide(destination: u8, immediate: i64) -> Self {
+        Self {
+            size: OperandSize::S64,
+            opcode: 0xb8 | (destination & 0b111),
+            modrm: false,
+            second_operand: destination,
+            immediate_size: OperandSize::S64,
+            immediate,
+            ..Self::DEFAULT
+        }
+    }
+
     /// Store sign-extended immediate in destination
     #[inline]
     pub const fn store_immediate(
@@ -443,6 +443,27 @@ fn test_store_reg() {
     );
 }
 
+// Test all supported Atomic mnemonics.
+#[test]
+fn test_atomic() {
+    assert_eq!(
+        asm("stxxaddw [r1+2], r3
+             stxxordw [r1+2], r3
+             stxfetchandw [r1+2], r3
+             stxfetchxordw [r1+2], r3
+             stxxchgw [r1+2], r3
+             stxcmpxchgdw [r1+2], r3"),
+        Ok(vec![
+            insn(0, ebpf::ST_W_ATOMIC, 1, 3, 2, ebpf::ATOMIC_ADD),
+            insn(1, ebpf::ST_DW_ATOMIC, 1, 3, 2, ebpf::ATOMIC_OR),
+            insn(2, ebpf::ST_W_ATOMIC, 1, 3, 2, ebpf::ATOMIC_FETCH_AND),
+            insn(3, ebpf::ST_DW_ATOMIC, 1, 3, 2, ebpf::ATOMIC_FETCH_XOR),
+            insn(4, ebpf::ST_W_ATOMIC, 1, 3, 2, ebpf::ATOMIC_XCHG),
+            insn(5, ebpf::ST_DW_ATOMIC, 1, 3, 2, ebpf::ATOMIC_CMPXCHG)
+        ])
+    );
+}
+
 // Test all supported JumpConditional mnemonics.
 #[test]
 fn test_jump_conditional() {
@@ -501,6 +522,64 @@ fn test_jump_conditional() {
     );
 }
 
+// Test all supported JumpConditional mnemonics of the BPF_JMP32 class.
+#[test]
+fn test_jump_conditional_32() {
+    assert_eq!(
+        asm("jeq32 r1, r2, +3
+             jgt32 r1, r2, +3
+             jge32 r1, r2, +3
+             jlt32 r1, r2, +3
+             jle32 r1, r2, +3
+             jset32 r1, r2, +3
+             jne32 r1, r2, +3
+             jsgt32 r1, r2, +3
+             jsge32 r1, r2, +3
+             jslt32 r1, r2, +3
+             jsle32 r1, r2, +3"),
+        Ok(vec![
+            insn(0, ebpf::JEQ32_REG, 1, 2, 3, 0),
+            insn(1, ebpf::JGT32_REG, 1, 2, 3, 0),
+            insn(2, ebpf::JGE32_REG, 1, 2, 3, 0),
+            insn(3, ebpf::JLT32_REG, 1, 2, 3, 0),
+            insn(4, ebpf::JLE32_REG, 1, 2, 3, 0),
+            insn(5, ebpf::JSET32_REG, 1, 2, 3, 0),
+            insn(6, ebpf::JNE32_REG, 1, 2, 3, 0),
+            insn(7, ebpf::JSGT32_REG, 1, 2, 3, 0),
+            insn(8, ebpf::JSGE32_REG, 1, 2, 3, 0),
+            insn(9, ebpf::JSLT32_REG, 1, 2, 3, 0),
+            insn(10, ebpf::JSLE32_REG, 1, 2, 3, 0)
+        ])
+    );
+
+    assert_eq!(
+        asm("jeq32 r1, 2, +3
+             jgt32 r1, 2, +3
+             jge32 r1, 2, +3
+             jlt32 r1, 2, +3
+             jle32 r1, 2, +3
+             jset32 r1, 2, +3
+             jne32 r1, 2, +3
+             jsgt32 r1, 2, +3
+             jsge32 r1, 2, +3
+             jslt32 r1, 2, +3
+             jsle32 r1, 2, +3"),
+        Ok(vec![
+            insn(0, ebpf::JEQ32_IMM, 1, 0, 3, 2),
+            insn(1, ebpf::JGT32_IMM, 1, 0, 3, 2),
+            insn(2, ebpf::JGE32_IMM, 1, 0, 3, 2),
+            insn(3, ebpf::JLT32_IMM, 1, 0, 3, 2),
+            insn(4, ebpf::JLE32_IMM, 1, 0, 3, 2),
+            insn(5, ebpf::JSET32_IMM, 1, 0, 3, 2),
+            insn(6, ebpf::JNE32_IMM, 1, 0, 3, 2),
+            insn(7, ebpf::JSGT32_IMM, 1, 0, 3, 2),
+            insn(8, ebpf::JSGE32_IMM, 1, 0, 3, 2),
+            insn(9, ebpf::JSLT32_IMM, 1, 0, 3, 2),
+            insn(10, ebpf::JSLE32_IMM, 1, 0, 3, 2)
+        ])
+    );
+}
+
 // Test all supported Endian mnemonics.
 #[test]
 fn test_endian() {
@@ -543,6 +622,42 @@ fn test_tcp_sack() {
     assert_eq!(program, TCP_SACK_BIN.to_vec());
 }
 
+#[test]
+fn test_labels() {
+    assert_eq!(
+        asm("
+            ja forward
+            backward:
+            jeq r1, 0, forward
+            forward:
+            jeq r1, r2, backward
+            ja backward
+            exit"),
+        Ok(vec![
+            insn(0, ebpf::JA, 0, 0, 1, 0),
+            insn(1, ebpf::JEQ_IMM, 1, 0, 0, 0),
+            insn(2, ebpf::JEQ_REG, 1, 2, -2, 0),
+            insn(3, ebpf::JA, 0, 0, -3, 0),
+            insn(4, ebpf::EXIT, 0, 0, 0, 0),
+        ])
+    );
+}
+
+#[test]
+fn test_error_label_not_found() {
+    assert_eq!(
+        asm("
+            mov64 r0, 0
+            jeq r0, 0, missing
+            exit"),
+        Err("Label not found missing at line 3".to_string())
+    );
+    assert_eq!(
+        asm("call missing"),
+        Err("Label not found missing at line 1".to_string())
+    );
+}
+
 #[test]
 fn test_error_invalid_instruction() {
     assert_eq!(asm("abcd"), Err("Invalid instruction \"abcd\"".to_string()));
@@ -9,7 +9,10 @@
 extern crate solana_rbpf;
 use solana_rbpf::{
     assembler::assemble,
-    static_analysis::Analysis,
+    disassembler::{disassemble, disassemble_program, DisassembledInsn, JumpTargetFormat, Operand},
+    ebpf,
+    static_analysis::{analyze_control_flow, Analysis, BasicBlock, Edge},
+    syscalls,
     vm::{Config, SyscallRegistry, TestContextObject},
 };
 
@@ -313,6 +316,35 @@ fn test_store_reg() {
     );
 }
 
+// Test all supported Atomic mnemonics.
+#[test]
+fn test_atomic() {
+    disasm!(
+        "entrypoint:
+    stxxaddw [r1+0x2], r3
+    stxxorw [r1+0x2], r3
+    stxxandw [r1+0x2], r3
+    stxxxorw [r1+0x2], r3
+    stxfetchaddw [r1+0x2], r3
+    stxfetchorw [r1+0x2], r3
+    stxfetchandw [r1+0x2], r3
+    stxfetchxorw [r1+0x2], r3
+    stxxchgw [r1+0x2], r3
+    stxcmpxchgw [r1+0x2], r3
+    stxxadddw [r1+0x2], r3
+    stxxordw [r1+0x2], r3
+    stxxanddw [r1+0x2], r3
+    stxxxordw [r1+0x2], r3
+    stxfetchadddw [r1+0x2], r3
+    stxfetchordw [r1+0x2], r3
+    stxfetchanddw [r1+0x2], r3
+    stxfetchxordw [r1+0x2], r3
+    stxxchgdw [r1+0x2], r3
+    stxcmpxchgdw [r1+0x2], r3
+"
+    );
+}
+
 // Test all supported JumpConditional mnemonics.
 #[test]
 fn test_jump_conditional() {
@@ -353,6 +385,46 @@ lbb_11:
     );
 }
 
+// Test all supported JumpConditional mnemonics of the BPF_JMP32 class.
+#[test]
+fn test_jump_conditional_32() {
+    disasm!(
+        "entrypoint:
+    jeq32 r1, r2, lbb_11
+    jgt32 r1, r2, lbb_11
+    jge32 r1, r2, lbb_11
+    jlt32 r1, r2, lbb_11
+    jle32 r1, r2, lbb_11
+    jset32 r1, r2, lbb_11
+    jne32 r1, r2, lbb_11
+    jsgt32 r1, r2, lbb_11
+    jsge32 r1, r2, lbb_11
+    jslt32 r1, r2, lbb_11
+    jsle32 r1, r2, lbb_11
+lbb_11:
+    exit
+"
+    );
+
+    disasm!(
+        "entrypoint:
+    jeq32 r1, 2, lbb_11
+    jgt32 r1, 2, lbb_11
+    jge32 r1, 2, lbb_11
+    jlt32 r1, 2, lbb_11
+    jle32 r1, 2, lbb_11
+    jset32 r1, 2, lbb_11
+    jne32 r1, 2, lbb_11
+    jsgt32 r1, 2, lbb_11
+    jsge32 r1, 2, lbb_11
+    jslt32 r1, 2, lbb_11
+    jsle32 r1, 2, lbb_11
+lbb_11:
+    exit
+"
+    );
+}
+
 // Test all supported Endian mnemonics.
 #[test]
 fn test_endian() {
@@ -373,3 +445,210 @@ fn test_large_immediate() {
     disasm!("entrypoint:\n    add64 r1, -1\n");
     disasm!("entrypoint:\n    add64 r1, -1\n");
 }
+
+#[test]
+fn test_structured_records() {
+    let executable = assemble::<TestContextObject>(
+        "
+        lddw r1, 0x1122334455667788
+        ldxh r2, [r1-0x4]
+        jeq r2, 7, -3
+        exit",
+        Config::default(),
+        SyscallRegistry::default(),
+    )
+    .unwrap();
+    let (_program_vm_addr, program) = executable.get_text_bytes();
+    assert_eq!(
+        disassemble_program(program),
+        vec![
+            DisassembledInsn {
+                ptr: 0,
+                size: 2,
+                opc: ebpf::LD_DW_IMM,
+                mnemonic: "lddw",
+                operands: vec![Operand::Register(1), Operand::Immediate(0x1122334455667788),],
+                jump_target: None,
+            },
+            DisassembledInsn {
+                ptr: 2,
+                size: 1,
+                opc: ebpf::LD_H_REG,
+                mnemonic: "ldxh",
+                operands: vec![Operand::Register(2), Operand::Memory(1, -4)],
+                jump_target: None,
+            },
+            DisassembledInsn {
+                ptr: 3,
+                size: 1,
+                opc: ebpf::JEQ_IMM,
+                mnemonic: "jeq",
+                operands: vec![
+                    Operand::Register(2),
+                    Operand::Immediate(7),
+                    Operand::Offset(-3),
+                ],
+                jump_target: Some(1),
+            },
+            DisassembledInsn {
+                ptr: 4,
+                size: 1,
+                opc: ebpf::EXIT,
+                mnemonic: "exit",
+                operands: vec![],
+                jump_target: None,
+            },
+        ]
+    );
+}
+
+#[test]
+fn test_control_flow_graph() {
+    let mut syscall_registry = SyscallRegistry::default();
+    syscall_registry
+        .register_syscall_by_name(b"bpf_syscall_u64", syscalls::bpf_syscall_u64)
+        .unwrap();
+    let executable = assemble::<TestContextObject>(
+        "
+        mov64 r1, 2
+        jeq r1, 0, +2
+        call function_foo
+        ja +1
+        callx r1
+        exit
+        function_foo:
+        syscall bpf_syscall_u64
+        lddw r0, 0x1122334455667788
+        exit",
+        Config::default(),
+        syscall_registry,
+    )
+    .unwrap();
+    let syscall_hash = ebpf::hash_symbol_name(b"bpf_syscall_u64");
+    let cfg = analyze_control_flow(&executable).unwrap();
+    assert_eq!(cfg.entrypoint, 0);
+    assert_eq!(
+        cfg.blocks,
+        vec![
+            BasicBlock {
+                start: 0,
+                end: 2,
+                edges: vec![Edge::ConditionalJump(4), Edge::Fallthrough(2)],
+                syscalls: vec![],
+            },
+            BasicBlock {
+                start: 2,
+                end: 3,
+                edges: vec![Edge::Call(6), Edge::Fallthrough(3)],
+                syscalls: vec![],
+            },
+            BasicBlock {
+                start: 3,
+                end: 4,
+                edges: vec![Edge::UnconditionalJump(5)],
+                syscalls: vec![],
+            },
+            BasicBlock {
+                start: 4,
+                end: 5,
+                edges: vec![Edge::IndirectCall, Edge::Fallthrough(5)],
+                syscalls: vec![],
+            },
+            BasicBlock {
+                start: 5,
+                end: 6,
+                edges: vec![Edge::Return],
+                syscalls: vec![],
+            },
+            BasicBlock {
+                start: 6,
+                end: 10,
+                edges: vec![Edge::Return],
+                syscalls: vec![(6, syscall_hash)],
+            },
+        ]
+    );
+    let json = cfg.to_json();
+    assert!(json.starts_with(
+        "{\"entrypoint\":0,\"blocks\":[{\"start\":0,\"end\":2,\"edges\":[{\"kind\":\"conditional_jump\",\"target\":4},{\"kind\":\"fallthrough\",\"target\":2}],\"syscalls\":[]},"
+    ));
+    assert!(json.contains("{\"kind\":\"indirect_call\"},{\"kind\":\"fallthrough\",\"target\":5}"));
+    assert!(json.ends_with(&format!(
+        "{{\"start\":6,\"end\":10,\"edges\":[{{\"kind\":\"return\"}}],\"syscalls\":[{{\"pc\":6,\"hash\":{}}}]}}]}}",
+        syscall_hash
+    )));
+    let parsed = json::parse(&json).unwrap();
+    assert_eq!(parsed["blocks"].len(), cfg.blocks.len());
+}
+
+#[test]
+fn test_disassemble_to_writer() {
+    let mut syscall_registry = SyscallRegistry::default();
+    syscall_registry
+        .register_syscall_by_name(b"bpf_syscall_u64", syscalls::bpf_syscall_u64)
+        .unwrap();
+    let config = Config {
+        enable_symbol_and_section_labels: true,
+        ..Config::default()
+    };
+    let src = "entrypoint:
+    jeq r1, 0, lbb_3
+    syscall bpf_syscall_u64
+    call function_foo
+lbb_3:
+    exit
+
+function_foo:
+    exit
+";
+    let executable = assemble::<TestContextObject>(src, config, syscall_registry).unwrap();
+    let mut output = Vec::new();
+    disassemble(&executable, &mut output).unwrap();
+    assert_eq!(String::from_utf8(output).unwrap(), src);
+}
+
+#[test]
+fn test_jump_target_format() {
+    let config = Config {
+        enable_symbol_and_section_labels: true,
+        ..Config::default()
+    };
+    let src = "entrypoint:
+    jeq r1, 0, lbb_3
+    add64 r1, -1
+    ja entrypoint
+lbb_3:
+    call function_foo
+    exit
+
+function_foo:
+    exit
+";
+    let executable =
+        assemble::<TestContextObject>(src, config, SyscallRegistry::default()).unwrap();
+    let analysis = Analysis::from_executable(&executable).unwrap();
+    let mut output = Vec::new();
+    analysis
+        .disassemble_with_format(&mut output, JumpTargetFormat::Label)
+        .unwrap();
+    assert_eq!(String::from_utf8(output).unwrap(), src);
+    let mut output = Vec::new();
+    analysis
+        .disassemble_with_format(&mut output, JumpTargetFormat::Offset)
+        .unwrap();
+    assert_eq!(
+        String::from_utf8(output).unwrap(),
+        src.replace("lbb_3\n", "+2\n")
+            .replace("ja entrypoint", "ja -3")
+    );
+    let mut output = Vec::new();
+    analysis
+        .disassemble_with_format(&mut output, JumpTargetFormat::OffsetAndIndex)
+        .unwrap();
+    assert_eq!(
+        String::from_utf8(output).unwrap(),
+        src.replace("lbb_3\n", "+2 <insn 3>\n")
+            .replace("ja entrypoint", "ja -3 <insn 0>")
+            .replace("call function_foo", "call function_foo <insn 5>")
+    );
+}
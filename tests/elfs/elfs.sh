@@ -9,6 +9,11 @@ LLVM_DIR=../../../solana/sdk/bpf/dependencies/llvm-native/bin/
 "$LLVM_DIR"ld.lld -z notext -shared --Bdynamic -o noop.so noop.o
 rm noop.o
 
+"$LLVM_DIR"llvm-objcopy -R .comment noop.so strict.so
+echo "stray note" > note.txt
+"$LLVM_DIR"llvm-objcopy --add-section .note.stray=note.txt strict.so strict_note.so
+rm note.txt
+
 "$LLVM_DIR"clang -Werror -target bpf -O2 -fno-builtin -fPIC -o noro.o -c noro.c
 "$LLVM_DIR"ld.lld -z notext -shared --Bdynamic elf.ld -o noro.so noro.o
 rm noro.o
@@ -16,20 +16,26 @@ use byteorder::{ByteOrder, LittleEndian};
 #[cfg(all(not(windows), target_arch = "x86_64"))]
 use rand::{rngs::SmallRng, RngCore, SeedableRng};
 use solana_rbpf::{
+    allocator::BpfAllocator,
     assembler::assemble,
     ebpf,
     elf::{ElfError, Executable},
     error::EbpfError,
+    interpreter::{Interpreter, InterpreterState},
     memory_region::{AccessType, MemoryMapping, MemoryRegion},
     syscalls,
-    verifier::RequisiteVerifier,
+    verifier::{RequisiteVerifier, VerifierError},
     vm::{
-        Config, ContextObject, EbpfVm, FunctionRegistry, ProgramResult, SyscallRegistry,
-        TestContextObject, VerifiedExecutable,
+        CallObserver, Config, ContextObject, CostModel, DivByZeroBehavior, EbpfVm,
+        FunctionRegistry, OobReadBehavior, ProgramResult, SyscallObserver, SyscallRecorder,
+        SyscallRegistry, SyscallReplayer, TestContextObject, TestUserError, VerifiedExecutable,
     },
+    vm_pool::VmPool,
+};
+use std::{fs::File, io::Read, time::Duration};
+use test_utils::{
+    TautologyVerifier, PROG_TCP_PORT_80, TCP_SACK_ASM, TCP_SACK_MATCH, TCP_SACK_NOMATCH,
 };
-use std::{fs::File, io::Read};
-use test_utils::{PROG_TCP_PORT_80, TCP_SACK_ASM, TCP_SACK_MATCH, TCP_SACK_NOMATCH};
 
 macro_rules! test_interpreter_and_jit {
     (register, $syscall_registry:expr, $location:expr => $syscall_function:expr) => {
@@ -171,7 +177,7 @@ fn test_mov() {
         [],
         (),
         TestContextObject::new(3),
-        { |_vm, res: ProgramResult| { res.unwrap() == 0x1 } },
+        { |_vm, res: ProgramResult<TestUserError>| { res.unwrap() == 0x1 } },
     );
 }
 
@@ -184,7 +190,7 @@ fn test_mov32_imm_large() {
         [],
         (),
         TestContextObject::new(2),
-        { |_vm, res: ProgramResult| { res.unwrap() == 0xffffffff } },
+        { |_vm, res: ProgramResult<TestUserError>| { res.unwrap() == 0xffffffff } },
     );
 }
 
@@ -198,7 +204,7 @@ fn test_mov_large() {
         [],
         (),
         TestContextObject::new(3),
-        { |_vm, res: ProgramResult| { res.unwrap() == 0xffffffff } },
+        { |_vm, res: ProgramResult<TestUserError>| { res.unwrap() == 0xffffffff } },
     );
 }
 
@@ -216,7 +222,7 @@ fn test_bounce() {
         [],
         (),
         TestContextObject::new(7),
-        { |_vm, res: ProgramResult| { res.unwrap() == 0x1 } },
+        { |_vm, res: ProgramResult<TestUserError>| { res.unwrap() == 0x1 } },
     );
 }
 
@@ -232,7 +238,7 @@ fn test_add32() {
         [],
         (),
         TestContextObject::new(5),
-        { |_vm, res: ProgramResult| { res.unwrap() == 0x3 } },
+        { |_vm, res: ProgramResult<TestUserError>| { res.unwrap() == 0x3 } },
     );
 }
 
@@ -246,7 +252,7 @@ fn test_neg32() {
         [],
         (),
         TestContextObject::new(3),
-        { |_vm, res: ProgramResult| { res.unwrap() == 0xfffffffe } },
+        { |_vm, res: ProgramResult<TestUserError>| { res.unwrap() == 0xfffffffe } },
     );
 }
 
@@ -260,7 +266,7 @@ fn test_neg64() {
         [],
         (),
         TestContextObject::new(3),
-        { |_vm, res: ProgramResult| { res.unwrap() == 0xfffffffffffffffe } },
+        { |_vm, res: ProgramResult<TestUserError>| { res.unwrap() == 0xfffffffffffffffe } },
     );
 }
 
@@ -290,7 +296,7 @@ fn test_alu32_arithmetic() {
         [],
         (),
         TestContextObject::new(19),
-        { |_vm, res: ProgramResult| { res.unwrap() == 0x2a } },
+        { |_vm, res: ProgramResult<TestUserError>| { res.unwrap() == 0x2a } },
     );
 }
 
@@ -320,7 +326,7 @@ fn test_alu64_arithmetic() {
         [],
         (),
         TestContextObject::new(19),
-        { |_vm, res: ProgramResult| { res.unwrap() == 0x2a } },
+        { |_vm, res: ProgramResult<TestUserError>| { res.unwrap() == 0x2a } },
     );
 }
 
@@ -373,7 +379,7 @@ fn test_mul128() {
         [0; 16],
         (),
         TestContextObject::new(42),
-        { |_vm, res: ProgramResult| { res.unwrap() == 600 } },
+        { |_vm, res: ProgramResult<TestUserError>| { res.unwrap() == 600 } },
     );
 }
 
@@ -405,7 +411,7 @@ fn test_alu32_logic() {
         [],
         (),
         TestContextObject::new(21),
-        { |_vm, res: ProgramResult| { res.unwrap() == 0x11 } },
+        { |_vm, res: ProgramResult<TestUserError>| { res.unwrap() == 0x11 } },
     );
 }
 
@@ -439,7 +445,7 @@ fn test_alu64_logic() {
         [],
         (),
         TestContextObject::new(23),
-        { |_vm, res: ProgramResult| { res.unwrap() == 0x11 } },
+        { |_vm, res: ProgramResult<TestUserError>| { res.unwrap() == 0x11 } },
     );
 }
 
@@ -454,7 +460,7 @@ fn test_arsh32_high_shift() {
         [],
         (),
         TestContextObject::new(4),
-        { |_vm, res: ProgramResult| { res.unwrap() == 0x4 } },
+        { |_vm, res: ProgramResult<TestUserError>| { res.unwrap() == 0x4 } },
     );
 }
 
@@ -469,7 +475,7 @@ fn test_arsh32_imm() {
         [],
         (),
         TestContextObject::new(4),
-        { |_vm, res: ProgramResult| { res.unwrap() == 0xffff8000 } },
+        { |_vm, res: ProgramResult<TestUserError>| { res.unwrap() == 0xffff8000 } },
     );
 }
 
@@ -485,7 +491,7 @@ fn test_arsh32_reg() {
         [],
         (),
         TestContextObject::new(5),
-        { |_vm, res: ProgramResult| { res.unwrap() == 0xffff8000 } },
+        { |_vm, res: ProgramResult<TestUserError>| { res.unwrap() == 0xffff8000 } },
     );
 }
 
@@ -502,7 +508,7 @@ fn test_arsh64() {
         [],
         (),
         TestContextObject::new(6),
-        { |_vm, res: ProgramResult| { res.unwrap() == 0xfffffffffffffff8 } },
+        { |_vm, res: ProgramResult<TestUserError>| { res.unwrap() == 0xfffffffffffffff8 } },
     );
 }
 
@@ -517,7 +523,7 @@ fn test_lsh64_reg() {
         [],
         (),
         TestContextObject::new(4),
-        { |_vm, res: ProgramResult| { res.unwrap() == 0x10 } },
+        { |_vm, res: ProgramResult<TestUserError>| { res.unwrap() == 0x10 } },
     );
 }
 
@@ -532,7 +538,7 @@ fn test_rhs32_imm() {
         [],
         (),
         TestContextObject::new(4),
-        { |_vm, res: ProgramResult| { res.unwrap() == 0x00ffffff } },
+        { |_vm, res: ProgramResult<TestUserError>| { res.unwrap() == 0x00ffffff } },
     );
 }
 
@@ -547,8 +553,40 @@ fn test_rsh64_reg() {
         [],
         (),
         TestContextObject::new(4),
-        { |_vm, res: ProgramResult| { res.unwrap() == 0x1 } },
-    );
+        { |_vm, res: ProgramResult<TestUserError>| { res.unwrap() == 0x1 } },
+    );
+}
+
+#[test]
+fn test_shift_amount_masked() {
+    // Expected values as computed by the Linux kernel, which masks the shift amount
+    for (op, dst, shift, expected) in [
+        ("lsh32", 0xffffffff11223344u64, 32, 0x11223344u64),
+        ("lsh32", 0xffffffff11223344, 33, 0x22446688),
+        ("rsh32", 0xffffffff80000000, 32, 0x80000000),
+        ("rsh32", 0xffffffff80000000, 33, 0x40000000),
+        ("arsh32", 0xffffffff80000000, 32, 0x80000000),
+        ("arsh32", 0xffffffff80000000, 33, 0xc0000000),
+        ("lsh64", 0x8000000000000001, 64, 0x8000000000000001),
+        ("lsh64", 0x8000000000000001, 65, 0x2),
+        ("rsh64", 0x8000000000000001, 64, 0x8000000000000001),
+        ("rsh64", 0x8000000000000001, 65, 0x4000000000000000),
+        ("arsh64", 0x8000000000000001, 64, 0x8000000000000001),
+        ("arsh64", 0x8000000000000001, 65, 0xc000000000000000),
+    ] {
+        let source = format!(
+            "
+            lddw r0, {:#x}
+            mov r1, {}
+            {} r0, r1
+            exit",
+            dst, shift, op
+        );
+        let source = source.as_str();
+        test_interpreter_and_jit_asm!(source, [], (), TestContextObject::new(4), {
+            |_vm, res: ProgramResult<TestUserError>| res.unwrap() == expected
+        },);
+    }
 }
 
 #[test]
@@ -561,7 +599,7 @@ fn test_be16() {
         [0x11, 0x22],
         (),
         TestContextObject::new(3),
-        { |_vm, res: ProgramResult| { res.unwrap() == 0x1122 } },
+        { |_vm, res: ProgramResult<TestUserError>| { res.unwrap() == 0x1122 } },
     );
 }
 
@@ -575,7 +613,7 @@ fn test_be16_high() {
         [0x11, 0x22, 0x33, 0x44, 0x55, 0x66, 0x77, 0x88],
         (),
         TestContextObject::new(3),
-        { |_vm, res: ProgramResult| { res.unwrap() == 0x1122 } },
+        { |_vm, res: ProgramResult<TestUserError>| { res.unwrap() == 0x1122 } },
     );
 }
 
@@ -589,7 +627,7 @@ fn test_be32() {
         [0x11, 0x22, 0x33, 0x44],
         (),
         TestContextObject::new(3),
-        { |_vm, res: ProgramResult| { res.unwrap() == 0x11223344 } },
+        { |_vm, res: ProgramResult<TestUserError>| { res.unwrap() == 0x11223344 } },
     );
 }
 
@@ -603,7 +641,7 @@ fn test_be32_high() {
         [0x11, 0x22, 0x33, 0x44, 0x55, 0x66, 0x77, 0x88],
         (),
         TestContextObject::new(3),
-        { |_vm, res: ProgramResult| { res.unwrap() == 0x11223344 } },
+        { |_vm, res: ProgramResult<TestUserError>| { res.unwrap() == 0x11223344 } },
     );
 }
 
@@ -617,7 +655,7 @@ fn test_be64() {
         [0x11, 0x22, 0x33, 0x44, 0x55, 0x66, 0x77, 0x88],
         (),
         TestContextObject::new(3),
-        { |_vm, res: ProgramResult| { res.unwrap() == 0x1122334455667788 } },
+        { |_vm, res: ProgramResult<TestUserError>| { res.unwrap() == 0x1122334455667788 } },
     );
 }
 
@@ -631,7 +669,7 @@ fn test_le16() {
         [0x22, 0x11],
         (),
         TestContextObject::new(3),
-        { |_vm, res: ProgramResult| { res.unwrap() == 0x1122 } },
+        { |_vm, res: ProgramResult<TestUserError>| { res.unwrap() == 0x1122 } },
     );
 }
 
@@ -645,7 +683,7 @@ fn test_le16_high() {
         [0x11, 0x22, 0x33, 0x44, 0x55, 0x66, 0x77, 0x88],
         (),
         TestContextObject::new(3),
-        { |_vm, res: ProgramResult| { res.unwrap() == 0x2211 } },
+        { |_vm, res: ProgramResult<TestUserError>| { res.unwrap() == 0x2211 } },
     );
 }
 
@@ -659,7 +697,7 @@ fn test_le32() {
         [0x44, 0x33, 0x22, 0x11],
         (),
         TestContextObject::new(3),
-        { |_vm, res: ProgramResult| { res.unwrap() == 0x11223344 } },
+        { |_vm, res: ProgramResult<TestUserError>| { res.unwrap() == 0x11223344 } },
     );
 }
 
@@ -673,7 +711,7 @@ fn test_le32_high() {
         [0x11, 0x22, 0x33, 0x44, 0x55, 0x66, 0x77, 0x88],
         (),
         TestContextObject::new(3),
-        { |_vm, res: ProgramResult| { res.unwrap() == 0x44332211 } },
+        { |_vm, res: ProgramResult<TestUserError>| { res.unwrap() == 0x44332211 } },
     );
 }
 
@@ -687,10 +725,99 @@ fn test_le64() {
         [0x88, 0x77, 0x66, 0x55, 0x44, 0x33, 0x22, 0x11],
         (),
         TestContextObject::new(3),
-        { |_vm, res: ProgramResult| { res.unwrap() == 0x1122334455667788 } },
+        { |_vm, res: ProgramResult<TestUserError>| { res.unwrap() == 0x1122334455667788 } },
+    );
+}
+
+#[test]
+fn test_be32_clears_upper_bits() {
+    test_interpreter_and_jit_asm!(
+        "
+        lddw r0, 0xffffffff11223344
+        be32 r0
+        exit",
+        [],
+        (),
+        TestContextObject::new(3),
+        { |_vm, res: ProgramResult<TestUserError>| { res.unwrap() == 0x44332211 } },
     );
 }
 
+#[test]
+fn test_err_endian_size_unverified() {
+    let prog = &[
+        0xdc, 0x00, 0x00, 0x00, 0x08, 0x00, 0x00, 0x00, // be8 r0
+        0x95, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, //
+    ];
+    let executable = Executable::<TestContextObject>::from_text_bytes(
+        prog,
+        Config::default(),
+        SyscallRegistry::default(),
+        FunctionRegistry::default(),
+    )
+    .unwrap();
+    #[allow(unused_mut)]
+    let mut verified_executable =
+        VerifiedExecutable::<TautologyVerifier, TestContextObject>::from_executable(executable)
+            .unwrap();
+    let mut context_object = TestContextObject::new(2);
+    let mut vm = EbpfVm::new(
+        &verified_executable,
+        &mut context_object,
+        &mut [],
+        Vec::new(),
+    )
+    .unwrap();
+    let (_instruction_count, result) = vm.execute_program(true);
+    assert!(matches!(
+        result,
+        ProgramResult::Err(EbpfError::InvalidInstruction(29))
+    ));
+    #[cfg(all(not(windows), target_arch = "x86_64"))]
+    assert!(matches!(
+        verified_executable.jit_compile(),
+        Err(EbpfError::InvalidInstruction(29))
+    ));
+}
+
+#[test]
+fn test_err_lddw_tail_unverified() {
+    let prog = &[
+        0x18, 0x00, 0x00, 0x00, 0x88, 0x77, 0x66, 0x55, // lddw r0, 0x1122334455667788
+        0x00, 0x01, 0x00, 0x00, 0x44, 0x33, 0x22, 0x11, // with a corrupted dst register
+        0x95, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, //
+    ];
+    let executable = Executable::<TestContextObject>::from_text_bytes(
+        prog,
+        Config::default(),
+        SyscallRegistry::default(),
+        FunctionRegistry::default(),
+    )
+    .unwrap();
+    #[allow(unused_mut)]
+    let mut verified_executable =
+        VerifiedExecutable::<TautologyVerifier, TestContextObject>::from_executable(executable)
+            .unwrap();
+    let mut context_object = TestContextObject::new(2);
+    let mut vm = EbpfVm::new(
+        &verified_executable,
+        &mut context_object,
+        &mut [],
+        Vec::new(),
+    )
+    .unwrap();
+    let (_instruction_count, result) = vm.execute_program(true);
+    assert!(matches!(
+        result,
+        ProgramResult::Err(EbpfError::InvalidInstruction(29))
+    ));
+    #[cfg(all(not(windows), target_arch = "x86_64"))]
+    assert!(matches!(
+        verified_executable.jit_compile(),
+        Err(EbpfError::InvalidInstruction(29))
+    ));
+}
+
 #[test]
 fn test_mul32_imm() {
     test_interpreter_and_jit_asm!(
@@ -701,7 +828,7 @@ fn test_mul32_imm() {
         [],
         (),
         TestContextObject::new(3),
-        { |_vm, res: ProgramResult| { res.unwrap() == 0xc } },
+        { |_vm, res: ProgramResult<TestUserError>| { res.unwrap() == 0xc } },
     );
 }
 
@@ -716,7 +843,7 @@ fn test_mul32_reg() {
         [],
         (),
         TestContextObject::new(4),
-        { |_vm, res: ProgramResult| { res.unwrap() == 0xc } },
+        { |_vm, res: ProgramResult<TestUserError>| { res.unwrap() == 0xc } },
     );
 }
 
@@ -731,7 +858,7 @@ fn test_mul32_reg_overflow() {
         [],
         (),
         TestContextObject::new(4),
-        { |_vm, res: ProgramResult| { res.unwrap() == 0x4 } },
+        { |_vm, res: ProgramResult<TestUserError>| { res.unwrap() == 0x4 } },
     );
 }
 
@@ -745,7 +872,7 @@ fn test_mul64_imm() {
         [],
         (),
         TestContextObject::new(3),
-        { |_vm, res: ProgramResult| { res.unwrap() == 0x100000004 } },
+        { |_vm, res: ProgramResult<TestUserError>| { res.unwrap() == 0x100000004 } },
     );
 }
 
@@ -760,7 +887,7 @@ fn test_mul64_reg() {
         [],
         (),
         TestContextObject::new(4),
-        { |_vm, res: ProgramResult| { res.unwrap() == 0x100000004 } },
+        { |_vm, res: ProgramResult<TestUserError>| { res.unwrap() == 0x100000004 } },
     );
 }
 
@@ -775,7 +902,7 @@ fn test_div32_high_divisor() {
         [],
         (),
         TestContextObject::new(4),
-        { |_vm, res: ProgramResult| { res.unwrap() == 0x3 } },
+        { |_vm, res: ProgramResult<TestUserError>| { res.unwrap() == 0x3 } },
     );
 }
 
@@ -789,7 +916,7 @@ fn test_div32_imm() {
         [],
         (),
         TestContextObject::new(3),
-        { |_vm, res: ProgramResult| { res.unwrap() == 0x3 } },
+        { |_vm, res: ProgramResult<TestUserError>| { res.unwrap() == 0x3 } },
     );
 }
 
@@ -804,7 +931,7 @@ fn test_div32_reg() {
         [],
         (),
         TestContextObject::new(4),
-        { |_vm, res: ProgramResult| { res.unwrap() == 0x3 } },
+        { |_vm, res: ProgramResult<TestUserError>| { res.unwrap() == 0x3 } },
     );
 }
 
@@ -818,7 +945,7 @@ fn test_sdiv32_imm() {
         [],
         (),
         TestContextObject::new(3),
-        { |_vm, res: ProgramResult| { res.unwrap() == 0xFFFFFFFFE0000000 } },
+        { |_vm, res: ProgramResult<TestUserError>| { res.unwrap() == 0xFFFFFFFFE0000000 } },
     );
 }
 
@@ -832,7 +959,7 @@ fn test_sdiv32_neg_imm() {
         [],
         (),
         TestContextObject::new(3),
-        { |_vm, res: ProgramResult| { res.unwrap() as i64 == -0xc } },
+        { |_vm, res: ProgramResult<TestUserError>| { res.unwrap() as i64 == -0xc } },
     );
 }
 
@@ -847,7 +974,7 @@ fn test_sdiv32_reg() {
         [],
         (),
         TestContextObject::new(4),
-        { |_vm, res: ProgramResult| { res.unwrap() == 0xFFFFFFFFE0000000 } },
+        { |_vm, res: ProgramResult<TestUserError>| { res.unwrap() == 0xFFFFFFFFE0000000 } },
     );
 }
 
@@ -862,7 +989,7 @@ fn test_sdiv32_neg_reg() {
         [],
         (),
         TestContextObject::new(4),
-        { |_vm, res: ProgramResult| { res.unwrap() as i64 == -0xc } },
+        { |_vm, res: ProgramResult<TestUserError>| { res.unwrap() as i64 == -0xc } },
     );
 }
 
@@ -877,7 +1004,7 @@ fn test_div64_imm() {
         [],
         (),
         TestContextObject::new(4),
-        { |_vm, res: ProgramResult| { res.unwrap() == 0x300000000 } },
+        { |_vm, res: ProgramResult<TestUserError>| { res.unwrap() == 0x300000000 } },
     );
 }
 
@@ -893,7 +1020,7 @@ fn test_div64_reg() {
         [],
         (),
         TestContextObject::new(5),
-        { |_vm, res: ProgramResult| { res.unwrap() == 0x300000000 } },
+        { |_vm, res: ProgramResult<TestUserError>| { res.unwrap() == 0x300000000 } },
     );
 }
 
@@ -908,7 +1035,7 @@ fn test_sdiv64_imm() {
         [],
         (),
         TestContextObject::new(4),
-        { |_vm, res: ProgramResult| { res.unwrap() == 0x300000000 } },
+        { |_vm, res: ProgramResult<TestUserError>| { res.unwrap() == 0x300000000 } },
     );
 }
 
@@ -924,10 +1051,61 @@ fn test_sdiv64_reg() {
         [],
         (),
         TestContextObject::new(5),
-        { |_vm, res: ProgramResult| { res.unwrap() == 0x300000000 } },
+        { |_vm, res: ProgramResult<TestUserError>| { res.unwrap() == 0x300000000 } },
+    );
+}
+
+#[test]
+fn test_uhmul64_imm() {
+    let config = Config {
+        enable_uhmul: true,
+        enable_instruction_tracing: true,
+        ..Config::default()
+    };
+    test_interpreter_and_jit_asm!(
+        "
+        mov r1, 1
+        lsh r1, 63
+        uhmul64 r1, 0x35
+        mov r0, r2
+        exit",
+        config,
+        [],
+        (),
+        TestContextObject::new(5),
+        { |_vm, res: ProgramResult<TestUserError>| { res.unwrap() == 0x1a } },
     );
 }
 
+#[test]
+fn test_uhmul64_max() {
+    // The register pairs cover the JIT mapping both halves to RAX and RDX
+    for low in [0, 1, 2, 3, 8] {
+        let high = low + 1;
+        for operand in ["-1", "r9"] {
+            let config = Config {
+                enable_uhmul: true,
+                enable_instruction_tracing: true,
+                ..Config::default()
+            };
+            let source = format!(
+                "
+                mov r{low}, -1
+                mov r9, -1
+                uhmul64 r{low}, {operand}
+                jne r{low}, 1, +2
+                mov r0, r{high}
+                exit
+                mov r0, 0
+                exit",
+            );
+            test_interpreter_and_jit_asm!((&source), config, [], (), TestContextObject::new(6), {
+                |_vm, res: ProgramResult<TestUserError>| res.unwrap() == 0xFFFFFFFFFFFFFFFE
+            },);
+        }
+    }
+}
+
 #[test]
 fn test_err_div64_by_zero_reg() {
     test_interpreter_and_jit_asm!(
@@ -940,7 +1118,7 @@ fn test_err_div64_by_zero_reg() {
         (),
         TestContextObject::new(3),
         {
-            |_vm, res: ProgramResult| matches!(res.unwrap_err(), EbpfError::DivideByZero(pc) if pc == 31)
+            |_vm, res: ProgramResult<TestUserError>| matches!(res.unwrap_err(), EbpfError::DivideByZero(pc) if pc == 31)
         },
     );
 }
@@ -957,7 +1135,7 @@ fn test_err_div32_by_zero_reg() {
         (),
         TestContextObject::new(3),
         {
-            |_vm, res: ProgramResult| matches!(res.unwrap_err(), EbpfError::DivideByZero(pc) if pc == 31)
+            |_vm, res: ProgramResult<TestUserError>| matches!(res.unwrap_err(), EbpfError::DivideByZero(pc) if pc == 31)
         },
     );
 }
@@ -974,7 +1152,7 @@ fn test_err_sdiv64_by_zero_reg() {
         (),
         TestContextObject::new(3),
         {
-            |_vm, res: ProgramResult| matches!(res.unwrap_err(), EbpfError::DivideByZero(pc) if pc == 31)
+            |_vm, res: ProgramResult<TestUserError>| matches!(res.unwrap_err(), EbpfError::DivideByZero(pc) if pc == 31)
         },
     );
 }
@@ -991,7 +1169,7 @@ fn test_err_sdiv32_by_zero_reg() {
         (),
         TestContextObject::new(3),
         {
-            |_vm, res: ProgramResult| matches!(res.unwrap_err(), EbpfError::DivideByZero(pc) if pc == 31)
+            |_vm, res: ProgramResult<TestUserError>| matches!(res.unwrap_err(), EbpfError::DivideByZero(pc) if pc == 31)
         },
     );
 }
@@ -1008,7 +1186,7 @@ fn test_err_sdiv64_overflow_imm() {
         (),
         TestContextObject::new(3),
         {
-            |_vm, res: ProgramResult| matches!(res.unwrap_err(), EbpfError::DivideOverflow(pc) if pc == 31)
+            |_vm, res: ProgramResult<TestUserError>| matches!(res.unwrap_err(), EbpfError::DivideOverflow(pc) if pc == 31)
         },
     );
 }
@@ -1026,7 +1204,7 @@ fn test_err_sdiv64_overflow_reg() {
         (),
         TestContextObject::new(4),
         {
-            |_vm, res: ProgramResult| matches!(res.unwrap_err(), EbpfError::DivideOverflow(pc) if pc == 32)
+            |_vm, res: ProgramResult<TestUserError>| matches!(res.unwrap_err(), EbpfError::DivideOverflow(pc) if pc == 32)
         },
     );
 }
@@ -1043,7 +1221,7 @@ fn test_err_sdiv32_overflow_imm() {
         (),
         TestContextObject::new(3),
         {
-            |_vm, res: ProgramResult| matches!(res.unwrap_err(), EbpfError::DivideOverflow(pc) if pc == 31)
+            |_vm, res: ProgramResult<TestUserError>| matches!(res.unwrap_err(), EbpfError::DivideOverflow(pc) if pc == 31)
         },
     );
 }
@@ -1061,7 +1239,7 @@ fn test_err_sdiv32_overflow_reg() {
         (),
         TestContextObject::new(4),
         {
-            |_vm, res: ProgramResult| matches!(res.unwrap_err(), EbpfError::DivideOverflow(pc) if pc == 32)
+            |_vm, res: ProgramResult<TestUserError>| matches!(res.unwrap_err(), EbpfError::DivideOverflow(pc) if pc == 32)
         },
     );
 }
@@ -1078,7 +1256,7 @@ fn test_mod32() {
         [],
         (),
         TestContextObject::new(5),
-        { |_vm, res: ProgramResult| { res.unwrap() == 0x5 } },
+        { |_vm, res: ProgramResult<TestUserError>| { res.unwrap() == 0x5 } },
     );
 }
 
@@ -1092,7 +1270,7 @@ fn test_mod32_imm() {
         [],
         (),
         TestContextObject::new(3),
-        { |_vm, res: ProgramResult| { res.unwrap() == 0x0 } },
+        { |_vm, res: ProgramResult<TestUserError>| { res.unwrap() == 0x0 } },
     );
 }
 
@@ -1112,7 +1290,7 @@ fn test_mod64() {
         [],
         (),
         TestContextObject::new(9),
-        { |_vm, res: ProgramResult| { res.unwrap() == 0x30ba5a04 } },
+        { |_vm, res: ProgramResult<TestUserError>| { res.unwrap() == 0x30ba5a04 } },
     );
 }
 
@@ -1128,7 +1306,7 @@ fn test_err_mod64_by_zero_reg() {
         (),
         TestContextObject::new(3),
         {
-            |_vm, res: ProgramResult| matches!(res.unwrap_err(), EbpfError::DivideByZero(pc) if pc == 31)
+            |_vm, res: ProgramResult<TestUserError>| matches!(res.unwrap_err(), EbpfError::DivideByZero(pc) if pc == 31)
         },
     );
 }
@@ -1145,11 +1323,128 @@ fn test_err_mod_by_zero_reg() {
         (),
         TestContextObject::new(3),
         {
-            |_vm, res: ProgramResult| matches!(res.unwrap_err(), EbpfError::DivideByZero(pc) if pc == 31)
+            |_vm, res: ProgramResult<TestUserError>| matches!(res.unwrap_err(), EbpfError::DivideByZero(pc) if pc == 31)
         },
     );
 }
 
+#[test]
+fn test_div_by_zero_behavior() {
+    for (div_by_zero_behavior, all_ones_32, all_ones_64) in [
+        (DivByZeroBehavior::ReturnZero, 0, 0),
+        (DivByZeroBehavior::ReturnAllOnes, u32::MAX as u64, u64::MAX),
+    ] {
+        let config = Config {
+            div_by_zero_behavior,
+            enable_instruction_tracing: true,
+            ..Config::default()
+        };
+        test_interpreter_and_jit_asm!(
+            "
+            mov32 r0, 5
+            mov32 r1, 0
+            div32 r0, r1
+            exit",
+            config,
+            [],
+            (),
+            TestContextObject::new(4),
+            { |_vm, res: ProgramResult<TestUserError>| res.unwrap() == all_ones_32 },
+        );
+        test_interpreter_and_jit_asm!(
+            "
+            mov r0, 5
+            mov r1, 0
+            div r0, r1
+            add r0, 1
+            exit",
+            config,
+            [],
+            (),
+            TestContextObject::new(5),
+            {
+                |_vm, res: ProgramResult<TestUserError>| res.unwrap() == all_ones_64.wrapping_add(1)
+            },
+        );
+        test_interpreter_and_jit_asm!(
+            "
+            mov r2, 5
+            mov r1, 0
+            mod32 r2, r1
+            mov r0, r2
+            exit",
+            config,
+            [],
+            (),
+            TestContextObject::new(5),
+            { |_vm, res: ProgramResult<TestUserError>| res.unwrap() == all_ones_32 },
+        );
+        test_interpreter_and_jit_asm!(
+            "
+            mov r0, 5
+            mov r1, 0
+            mod r0, r1
+            exit",
+            config,
+            [],
+            (),
+            TestContextObject::new(4),
+            { |_vm, res: ProgramResult<TestUserError>| res.unwrap() == all_ones_64 },
+        );
+        test_interpreter_and_jit_asm!(
+            "
+            mov32 r0, 5
+            mov32 r1, 0
+            sdiv32 r0, r1
+            exit",
+            config,
+            [],
+            (),
+            TestContextObject::new(4),
+            { |_vm, res: ProgramResult<TestUserError>| res.unwrap() == all_ones_64 },
+        );
+        // Signed division overflows behave like a division by zero
+        test_interpreter_and_jit_asm!(
+            "
+            mov32 r0, 1
+            lsh32 r0, 31
+            sdiv32 r0, -1
+            exit",
+            config,
+            [],
+            (),
+            TestContextObject::new(4),
+            { |_vm, res: ProgramResult<TestUserError>| res.unwrap() == all_ones_64 },
+        );
+        test_interpreter_and_jit_asm!(
+            "
+            mov r0, 0x80
+            lsh r0, 56
+            mov r1, -1
+            sdiv r0, r1
+            exit",
+            config,
+            [],
+            (),
+            TestContextObject::new(5),
+            { |_vm, res: ProgramResult<TestUserError>| res.unwrap() == all_ones_64 },
+        );
+        // Regular divisions are unaffected
+        test_interpreter_and_jit_asm!(
+            "
+            mov r0, -6
+            mov r1, 3
+            sdiv r0, r1
+            exit",
+            config,
+            [],
+            (),
+            TestContextObject::new(4),
+            { |_vm, res: ProgramResult<TestUserError>| res.unwrap() == -2i64 as u64 },
+        );
+    }
+}
+
 // BPF_LD : Loads
 
 #[test]
@@ -1161,7 +1456,7 @@ fn test_ldxb() {
         [0xaa, 0xbb, 0x11, 0xcc, 0xdd],
         (),
         TestContextObject::new(2),
-        { |_vm, res: ProgramResult| { res.unwrap() == 0x11 } },
+        { |_vm, res: ProgramResult<TestUserError>| { res.unwrap() == 0x11 } },
     );
 }
 
@@ -1174,7 +1469,7 @@ fn test_ldxh() {
         [0xaa, 0xbb, 0x11, 0x22, 0xcc, 0xdd],
         (),
         TestContextObject::new(2),
-        { |_vm, res: ProgramResult| { res.unwrap() == 0x2211 } },
+        { |_vm, res: ProgramResult<TestUserError>| { res.unwrap() == 0x2211 } },
     );
 }
 
@@ -1189,7 +1484,7 @@ fn test_ldxw() {
         ],
         (),
         TestContextObject::new(2),
-        { |_vm, res: ProgramResult| { res.unwrap() == 0x44332211 } },
+        { |_vm, res: ProgramResult<TestUserError>| { res.unwrap() == 0x44332211 } },
     );
 }
 
@@ -1204,7 +1499,7 @@ fn test_ldxh_same_reg() {
         [0xff, 0xff],
         (),
         TestContextObject::new(4),
-        { |_vm, res: ProgramResult| { res.unwrap() == 0x1234 } },
+        { |_vm, res: ProgramResult<TestUserError>| { res.unwrap() == 0x1234 } },
     );
 }
 
@@ -1220,7 +1515,7 @@ fn test_lldxdw() {
         ],
         (),
         TestContextObject::new(2),
-        { |_vm, res: ProgramResult| { res.unwrap() == 0x8877665544332211 } },
+        { |_vm, res: ProgramResult<TestUserError>| { res.unwrap() == 0x8877665544332211 } },
     );
 }
 
@@ -1237,9 +1532,9 @@ fn test_err_ldxdw_oob() {
         (),
         TestContextObject::new(1),
         {
-            |_vm, res: ProgramResult| {
+            |_vm, res: ProgramResult<TestUserError>| {
                 matches!(res.unwrap_err(),
-                    EbpfError::AccessViolation(pc, access_type, vm_addr, len, name)
+                    EbpfError::AccessViolation(pc, access_type, vm_addr, len, name, _)
                     if access_type == AccessType::Load && pc == 29 && vm_addr == 0x400000006 && len == 8 && name == "input"
                 )
             }
@@ -1257,9 +1552,9 @@ fn test_err_ldxdw_nomem() {
         (),
         TestContextObject::new(1),
         {
-            |_vm, res: ProgramResult| {
+            |_vm, res: ProgramResult<TestUserError>| {
                 matches!(res.unwrap_err(),
-                    EbpfError::AccessViolation(pc, access_type, vm_addr, len, name)
+                    EbpfError::AccessViolation(pc, access_type, vm_addr, len, name, _)
                     if access_type == AccessType::Load && pc == 29 && vm_addr == 0x400000006 && len == 8 && name == "input"
                 )
             }
@@ -1308,7 +1603,7 @@ fn test_ldxb_all() {
         ],
         (),
         TestContextObject::new(31),
-        { |_vm, res: ProgramResult| { res.unwrap() == 0x9876543210 } },
+        { |_vm, res: ProgramResult<TestUserError>| { res.unwrap() == 0x9876543210 } },
     );
 }
 
@@ -1364,7 +1659,7 @@ fn test_ldxh_all() {
         ],
         (),
         TestContextObject::new(41),
-        { |_vm, res: ProgramResult| { res.unwrap() == 0x9876543210 } },
+        { |_vm, res: ProgramResult<TestUserError>| { res.unwrap() == 0x9876543210 } },
     );
 }
 
@@ -1410,7 +1705,7 @@ fn test_ldxh_all2() {
         ],
         (),
         TestContextObject::new(31),
-        { |_vm, res: ProgramResult| { res.unwrap() == 0x3ff } },
+        { |_vm, res: ProgramResult<TestUserError>| { res.unwrap() == 0x3ff } },
     );
 }
 
@@ -1458,7 +1753,7 @@ fn test_ldxw_all() {
         ],
         (),
         TestContextObject::new(31),
-        { |_vm, res: ProgramResult| { res.unwrap() == 0x030f0f } },
+        { |_vm, res: ProgramResult<TestUserError>| { res.unwrap() == 0x030f0f } },
     );
 }
 
@@ -1471,7 +1766,7 @@ fn test_lddw() {
         [],
         (),
         TestContextObject::new(2),
-        { |_vm, res: ProgramResult| { res.unwrap() == 0x1122334455667788 } },
+        { |_vm, res: ProgramResult<TestUserError>| { res.unwrap() == 0x1122334455667788 } },
     );
     test_interpreter_and_jit_asm!(
         "
@@ -1480,7 +1775,7 @@ fn test_lddw() {
         [],
         (),
         TestContextObject::new(2),
-        { |_vm, res: ProgramResult| { res.unwrap() == 0x80000000 } },
+        { |_vm, res: ProgramResult<TestUserError>| { res.unwrap() == 0x80000000 } },
     );
 }
 
@@ -1494,7 +1789,7 @@ fn test_stb() {
         [0xaa, 0xbb, 0xff, 0xcc, 0xdd],
         (),
         TestContextObject::new(3),
-        { |_vm, res: ProgramResult| { res.unwrap() == 0x11 } },
+        { |_vm, res: ProgramResult<TestUserError>| { res.unwrap() == 0x11 } },
     );
 }
 
@@ -1510,7 +1805,7 @@ fn test_sth() {
         ],
         (),
         TestContextObject::new(3),
-        { |_vm, res: ProgramResult| { res.unwrap() == 0x2211 } },
+        { |_vm, res: ProgramResult<TestUserError>| { res.unwrap() == 0x2211 } },
     );
 }
 
@@ -1526,7 +1821,7 @@ fn test_stw() {
         ],
         (),
         TestContextObject::new(3),
-        { |_vm, res: ProgramResult| { res.unwrap() == 0x44332211 } },
+        { |_vm, res: ProgramResult<TestUserError>| { res.unwrap() == 0x44332211 } },
     );
 }
 
@@ -1543,7 +1838,7 @@ fn test_stdw() {
         ],
         (),
         TestContextObject::new(3),
-        { |_vm, res: ProgramResult| { res.unwrap() == 0x44332211 } },
+        { |_vm, res: ProgramResult<TestUserError>| { res.unwrap() == 0x44332211 } },
     );
 }
 
@@ -1560,7 +1855,7 @@ fn test_stxb() {
         ],
         (),
         TestContextObject::new(4),
-        { |_vm, res: ProgramResult| { res.unwrap() == 0x11 } },
+        { |_vm, res: ProgramResult<TestUserError>| { res.unwrap() == 0x11 } },
     );
 }
 
@@ -1577,7 +1872,7 @@ fn test_stxh() {
         ],
         (),
         TestContextObject::new(4),
-        { |_vm, res: ProgramResult| { res.unwrap() == 0x2211 } },
+        { |_vm, res: ProgramResult<TestUserError>| { res.unwrap() == 0x2211 } },
     );
 }
 
@@ -1594,7 +1889,7 @@ fn test_stxw() {
         ],
         (),
         TestContextObject::new(4),
-        { |_vm, res: ProgramResult| { res.unwrap() == 0x44332211 } },
+        { |_vm, res: ProgramResult<TestUserError>| { res.unwrap() == 0x44332211 } },
     );
 }
 
@@ -1614,7 +1909,7 @@ fn test_stxdw() {
         ],
         (),
         TestContextObject::new(6),
-        { |_vm, res: ProgramResult| { res.unwrap() == 0x8877665544332211 } },
+        { |_vm, res: ProgramResult<TestUserError>| { res.unwrap() == 0x8877665544332211 } },
     );
 }
 
@@ -1646,7 +1941,7 @@ fn test_stxb_all() {
         ],
         (),
         TestContextObject::new(19),
-        { |_vm, res: ProgramResult| { res.unwrap() == 0xf0f2f3f4f5f6f7f8 } },
+        { |_vm, res: ProgramResult<TestUserError>| { res.unwrap() == 0xf0f2f3f4f5f6f7f8 } },
     );
 }
 
@@ -1665,7 +1960,7 @@ fn test_stxb_all2() {
         [0xff, 0xff],
         (),
         TestContextObject::new(8),
-        { |_vm, res: ProgramResult| { res.unwrap() == 0xf1f9 } },
+        { |_vm, res: ProgramResult<TestUserError>| { res.unwrap() == 0xf1f9 } },
     );
 }
 
@@ -1700,126 +1995,333 @@ fn test_stxb_chain() {
         ],
         (),
         TestContextObject::new(21),
-        { |_vm, res: ProgramResult| { res.unwrap() == 0x2a } },
+        { |_vm, res: ProgramResult<TestUserError>| { res.unwrap() == 0x2a } },
     );
 }
 
-// BPF_JMP : Branches
-
 #[test]
-fn test_exit_without_value() {
+fn test_atomic_add() {
+    let config = Config {
+        enable_atomics: true,
+        enable_instruction_tracing: true,
+        ..Config::default()
+    };
     test_interpreter_and_jit_asm!(
         "
+        mov r1, 1
+        stdw [r10-8], 5
+        lddw r2, 0x100000002
+        stxxadddw [r10-8], r2
+        stxxaddw [r10-8], r1
+        ldxdw r0, [r10-8]
+        add r0, r2
         exit",
+        config,
         [],
         (),
-        TestContextObject::new(1),
-        { |_vm, res: ProgramResult| { res.unwrap() == 0x0 } },
+        TestContextObject::new(8),
+        { |_vm, res: ProgramResult<TestUserError>| { res.unwrap() == 0x20000000a } },
     );
 }
 
 #[test]
-fn test_exit() {
+fn test_atomic_fetch_add() {
+    let config = Config {
+        enable_atomics: true,
+        enable_instruction_tracing: true,
+        ..Config::default()
+    };
     test_interpreter_and_jit_asm!(
         "
-        mov r0, 0
+        lddw r1, -1
+        stxdw [r10-8], r1
+        lddw r2, 0xffffffff00000001
+        stxfetchaddw [r10-8], r2
+        ldxdw r0, [r10-8]
+        mov r3, 3
+        stxfetchadddw [r10-8], r3
+        sub r0, r3
+        add r0, r2
         exit",
+        config,
         [],
         (),
-        TestContextObject::new(2),
-        { |_vm, res: ProgramResult| { res.unwrap() == 0x0 } },
+        TestContextObject::new(10),
+        { |_vm, res: ProgramResult<TestUserError>| { res.unwrap() == 0xffffffff } },
     );
 }
 
 #[test]
-fn test_early_exit() {
+fn test_atomic_fetch_bitwise() {
+    let config = Config {
+        enable_atomics: true,
+        enable_instruction_tracing: true,
+        ..Config::default()
+    };
     test_interpreter_and_jit_asm!(
         "
-        mov r0, 3
-        exit
-        mov r0, 4
+        stdw [r10-8], 0x0f
+        mov r1, 0x33
+        stxfetchordw [r10-8], r1
+        mov r2, 0x1c
+        stxfetchanddw [r10-8], r2
+        mov r0, 0x0f
+        stxfetchxordw [r10-8], r0
+        ldxdw r3, [r10-8]
+        lsh r0, 24
+        lsh r2, 16
+        lsh r1, 8
+        or r0, r2
+        or r0, r1
+        or r0, r3
         exit",
+        config,
         [],
         (),
-        TestContextObject::new(2),
-        { |_vm, res: ProgramResult| { res.unwrap() == 0x3 } },
+        TestContextObject::new(15),
+        { |_vm, res: ProgramResult<TestUserError>| { res.unwrap() == 0x1c3f0f13 } },
     );
 }
 
 #[test]
-fn test_ja() {
+fn test_atomic_xchg() {
+    let config = Config {
+        enable_atomics: true,
+        enable_instruction_tracing: true,
+        ..Config::default()
+    };
     test_interpreter_and_jit_asm!(
         "
-        mov r0, 1
-        ja +1
-        mov r0, 2
+        stdw [r10-8], 0x11
+        lddw r1, 0x2200000033
+        stxxchgw [r10-8], r1
+        ldxdw r0, [r10-8]
+        lsh r0, 8
+        or r0, r1
+        mov r2, 0x44
+        stxxchgdw [r10-8], r2
+        ldxdw r3, [r10-8]
+        lsh r0, 8
+        or r0, r2
+        lsh r0, 8
+        or r0, r3
         exit",
+        config,
         [],
         (),
-        TestContextObject::new(3),
-        { |_vm, res: ProgramResult| { res.unwrap() == 0x1 } },
+        TestContextObject::new(14),
+        { |_vm, res: ProgramResult<TestUserError>| { res.unwrap() == 0x33113344 } },
     );
 }
 
 #[test]
-fn test_jeq_imm() {
+fn test_atomic_cmpxchg_success() {
+    let config = Config {
+        enable_atomics: true,
+        enable_instruction_tracing: true,
+        ..Config::default()
+    };
     test_interpreter_and_jit_asm!(
         "
-        mov32 r0, 0
-        mov32 r1, 0xa
-        jeq r1, 0xb, +4
-        mov32 r0, 1
-        mov32 r1, 0xb
-        jeq r1, 0xb, +1
-        mov32 r0, 2
+        stdw [r10-8], 0x10
+        lddw r0, 0x100000010
+        mov r1, 0x20
+        stxcmpxchgw [r10-8], r1
+        ldxdw r2, [r10-8]
+        mov r4, r0
+        mov r0, 0x20
+        mov r3, 0x30
+        stxcmpxchgdw [r10-8], r3
+        ldxdw r5, [r10-8]
+        lsh r4, 24
+        lsh r0, 16
+        lsh r2, 8
+        or r0, r4
+        or r0, r2
+        or r0, r5
         exit",
+        config,
         [],
         (),
-        TestContextObject::new(7),
-        { |_vm, res: ProgramResult| { res.unwrap() == 0x1 } },
+        TestContextObject::new(17),
+        { |_vm, res: ProgramResult<TestUserError>| { res.unwrap() == 0x10202030 } },
     );
 }
 
 #[test]
-fn test_jeq_reg() {
+fn test_atomic_cmpxchg_failure() {
+    let config = Config {
+        enable_atomics: true,
+        enable_instruction_tracing: true,
+        ..Config::default()
+    };
     test_interpreter_and_jit_asm!(
         "
-        mov32 r0, 0
-        mov32 r1, 0xa
-        mov32 r2, 0xb
-        jeq r1, r2, +4
-        mov32 r0, 1
-        mov32 r1, 0xb
-        jeq r1, r2, +1
-        mov32 r0, 2
+        stdw [r10-8], 0x10
+        lddw r0, 0x100000011
+        mov r1, 0x20
+        stxcmpxchgw [r10-8], r1
+        mov r4, r0
+        lddw r0, 0x100000010
+        stxcmpxchgdw [r10-8], r1
+        ldxdw r2, [r10-8]
+        lsh r4, 16
+        lsh r0, 8
+        or r0, r4
+        or r0, r2
         exit",
+        config,
         [],
         (),
-        TestContextObject::new(8),
-        { |_vm, res: ProgramResult| { res.unwrap() == 0x1 } },
+        TestContextObject::new(13),
+        { |_vm, res: ProgramResult<TestUserError>| { res.unwrap() == 0x101010 } },
     );
 }
 
 #[test]
-fn test_jge_imm() {
+fn test_err_atomic_access_violation() {
+    let config = Config {
+        enable_atomics: true,
+        enable_instruction_tracing: true,
+        ..Config::default()
+    };
     test_interpreter_and_jit_asm!(
         "
-        mov32 r0, 0
-        mov32 r1, 0xa
-        jge r1, 0xb, +4
-        mov32 r0, 1
-        mov32 r1, 0xc
-        jge r1, 0xb, +1
-        mov32 r0, 2
+        mov r0, 0
+        mov r1, 1
+        stxxaddw [r1], r0
         exit",
+        config,
         [],
         (),
-        TestContextObject::new(7),
-        { |_vm, res: ProgramResult| { res.unwrap() == 0x1 } },
+        TestContextObject::new(3),
+        {
+            |_vm, res: ProgramResult<TestUserError>| {
+                matches!(res.unwrap_err(),
+                    EbpfError::AccessViolation(pc, access_type, vm_addr, len, name, _)
+                    if access_type == AccessType::Store && pc == 31 && vm_addr == 1 && len == 4 && name == "unknown"
+                )
+            }
+        },
     );
 }
 
-#[test]
+// BPF_JMP : Branches
+
+#[test]
+fn test_exit_without_value() {
+    test_interpreter_and_jit_asm!(
+        "
+        exit",
+        [],
+        (),
+        TestContextObject::new(1),
+        { |_vm, res: ProgramResult<TestUserError>| { res.unwrap() == 0x0 } },
+    );
+}
+
+#[test]
+fn test_exit() {
+    test_interpreter_and_jit_asm!(
+        "
+        mov r0, 0
+        exit",
+        [],
+        (),
+        TestContextObject::new(2),
+        { |_vm, res: ProgramResult<TestUserError>| { res.unwrap() == 0x0 } },
+    );
+}
+
+#[test]
+fn test_early_exit() {
+    test_interpreter_and_jit_asm!(
+        "
+        mov r0, 3
+        exit
+        mov r0, 4
+        exit",
+        [],
+        (),
+        TestContextObject::new(2),
+        { |_vm, res: ProgramResult<TestUserError>| { res.unwrap() == 0x3 } },
+    );
+}
+
+#[test]
+fn test_ja() {
+    test_interpreter_and_jit_asm!(
+        "
+        mov r0, 1
+        ja +1
+        mov r0, 2
+        exit",
+        [],
+        (),
+        TestContextObject::new(3),
+        { |_vm, res: ProgramResult<TestUserError>| { res.unwrap() == 0x1 } },
+    );
+}
+
+#[test]
+fn test_jeq_imm() {
+    test_interpreter_and_jit_asm!(
+        "
+        mov32 r0, 0
+        mov32 r1, 0xa
+        jeq r1, 0xb, +4
+        mov32 r0, 1
+        mov32 r1, 0xb
+        jeq r1, 0xb, +1
+        mov32 r0, 2
+        exit",
+        [],
+        (),
+        TestContextObject::new(7),
+        { |_vm, res: ProgramResult<TestUserError>| { res.unwrap() == 0x1 } },
+    );
+}
+
+#[test]
+fn test_jeq_reg() {
+    test_interpreter_and_jit_asm!(
+        "
+        mov32 r0, 0
+        mov32 r1, 0xa
+        mov32 r2, 0xb
+        jeq r1, r2, +4
+        mov32 r0, 1
+        mov32 r1, 0xb
+        jeq r1, r2, +1
+        mov32 r0, 2
+        exit",
+        [],
+        (),
+        TestContextObject::new(8),
+        { |_vm, res: ProgramResult<TestUserError>| { res.unwrap() == 0x1 } },
+    );
+}
+
+#[test]
+fn test_jge_imm() {
+    test_interpreter_and_jit_asm!(
+        "
+        mov32 r0, 0
+        mov32 r1, 0xa
+        jge r1, 0xb, +4
+        mov32 r0, 1
+        mov32 r1, 0xc
+        jge r1, 0xb, +1
+        mov32 r0, 2
+        exit",
+        [],
+        (),
+        TestContextObject::new(7),
+        { |_vm, res: ProgramResult<TestUserError>| { res.unwrap() == 0x1 } },
+    );
+}
+
+#[test]
 fn test_jge_reg() {
     test_interpreter_and_jit_asm!(
         "
@@ -1835,7 +2337,7 @@ fn test_jge_reg() {
         [],
         (),
         TestContextObject::new(8),
-        { |_vm, res: ProgramResult| { res.unwrap() == 0x1 } },
+        { |_vm, res: ProgramResult<TestUserError>| { res.unwrap() == 0x1 } },
     );
 }
 
@@ -1855,7 +2357,7 @@ fn test_jle_imm() {
         [],
         (),
         TestContextObject::new(7),
-        { |_vm, res: ProgramResult| { res.unwrap() == 0x1 } },
+        { |_vm, res: ProgramResult<TestUserError>| { res.unwrap() == 0x1 } },
     );
 }
 
@@ -1877,7 +2379,7 @@ fn test_jle_reg() {
         [],
         (),
         TestContextObject::new(9),
-        { |_vm, res: ProgramResult| { res.unwrap() == 0x1 } },
+        { |_vm, res: ProgramResult<TestUserError>| { res.unwrap() == 0x1 } },
     );
 }
 
@@ -1896,7 +2398,7 @@ fn test_jgt_imm() {
         [],
         (),
         TestContextObject::new(7),
-        { |_vm, res: ProgramResult| { res.unwrap() == 0x1 } },
+        { |_vm, res: ProgramResult<TestUserError>| { res.unwrap() == 0x1 } },
     );
 }
 
@@ -1917,7 +2419,7 @@ fn test_jgt_reg() {
         [],
         (),
         TestContextObject::new(9),
-        { |_vm, res: ProgramResult| { res.unwrap() == 0x1 } },
+        { |_vm, res: ProgramResult<TestUserError>| { res.unwrap() == 0x1 } },
     );
 }
 
@@ -1936,7 +2438,7 @@ fn test_jlt_imm() {
         [],
         (),
         TestContextObject::new(7),
-        { |_vm, res: ProgramResult| { res.unwrap() == 0x1 } },
+        { |_vm, res: ProgramResult<TestUserError>| { res.unwrap() == 0x1 } },
     );
 }
 
@@ -1957,7 +2459,7 @@ fn test_jlt_reg() {
         [],
         (),
         TestContextObject::new(9),
-        { |_vm, res: ProgramResult| { res.unwrap() == 0x1 } },
+        { |_vm, res: ProgramResult<TestUserError>| { res.unwrap() == 0x1 } },
     );
 }
 
@@ -1976,7 +2478,7 @@ fn test_jne_imm() {
         [],
         (),
         TestContextObject::new(7),
-        { |_vm, res: ProgramResult| { res.unwrap() == 0x1 } },
+        { |_vm, res: ProgramResult<TestUserError>| { res.unwrap() == 0x1 } },
     );
 }
 
@@ -1996,7 +2498,7 @@ fn test_jne_reg() {
         [],
         (),
         TestContextObject::new(8),
-        { |_vm, res: ProgramResult| { res.unwrap() == 0x1 } },
+        { |_vm, res: ProgramResult<TestUserError>| { res.unwrap() == 0x1 } },
     );
 }
 
@@ -2015,7 +2517,7 @@ fn test_jset_imm() {
         [],
         (),
         TestContextObject::new(7),
-        { |_vm, res: ProgramResult| { res.unwrap() == 0x1 } },
+        { |_vm, res: ProgramResult<TestUserError>| { res.unwrap() == 0x1 } },
     );
 }
 
@@ -2035,7 +2537,7 @@ fn test_jset_reg() {
         [],
         (),
         TestContextObject::new(8),
-        { |_vm, res: ProgramResult| { res.unwrap() == 0x1 } },
+        { |_vm, res: ProgramResult<TestUserError>| { res.unwrap() == 0x1 } },
     );
 }
 
@@ -2055,7 +2557,7 @@ fn test_jsge_imm() {
         [],
         (),
         TestContextObject::new(8),
-        { |_vm, res: ProgramResult| { res.unwrap() == 0x1 } },
+        { |_vm, res: ProgramResult<TestUserError>| { res.unwrap() == 0x1 } },
     );
 }
 
@@ -2077,7 +2579,7 @@ fn test_jsge_reg() {
         [],
         (),
         TestContextObject::new(10),
-        { |_vm, res: ProgramResult| { res.unwrap() == 0x1 } },
+        { |_vm, res: ProgramResult<TestUserError>| { res.unwrap() == 0x1 } },
     );
 }
 
@@ -2097,7 +2599,7 @@ fn test_jsle_imm() {
         [],
         (),
         TestContextObject::new(7),
-        { |_vm, res: ProgramResult| { res.unwrap() == 0x1 } },
+        { |_vm, res: ProgramResult<TestUserError>| { res.unwrap() == 0x1 } },
     );
 }
 
@@ -2120,7 +2622,152 @@ fn test_jsle_reg() {
         [],
         (),
         TestContextObject::new(10),
-        { |_vm, res: ProgramResult| { res.unwrap() == 0x1 } },
+        { |_vm, res: ProgramResult<TestUserError>| { res.unwrap() == 0x1 } },
+    );
+}
+
+#[test]
+fn test_jeq32_imm() {
+    let config = Config {
+        enable_jmp32: true,
+        enable_instruction_tracing: true,
+        ..Config::default()
+    };
+    test_interpreter_and_jit_asm!(
+        "
+        mov32 r0, 0
+        mov r1, 1
+        lsh r1, 32
+        or r1, 0xb
+        jeq r1, 0xb, +4
+        jeq32 r1, 0xc, +3
+        mov32 r0, 1
+        jeq32 r1, 0xb, +1
+        mov32 r0, 2
+        exit",
+        config,
+        [],
+        (),
+        TestContextObject::new(9),
+        { |_vm, res: ProgramResult<TestUserError>| { res.unwrap() == 0x1 } },
+    );
+}
+
+#[test]
+fn test_jeq32_reg() {
+    let config = Config {
+        enable_jmp32: true,
+        enable_instruction_tracing: true,
+        ..Config::default()
+    };
+    test_interpreter_and_jit_asm!(
+        "
+        mov32 r0, 0
+        mov r1, 1
+        lsh r1, 32
+        or r1, 0xb
+        mov32 r2, 0xb
+        jeq r1, r2, +3
+        mov32 r0, 1
+        jeq32 r1, r2, +1
+        mov32 r0, 2
+        exit",
+        config,
+        [],
+        (),
+        TestContextObject::new(9),
+        { |_vm, res: ProgramResult<TestUserError>| { res.unwrap() == 0x1 } },
+    );
+}
+
+#[test]
+fn test_jne32() {
+    let config = Config {
+        enable_jmp32: true,
+        enable_instruction_tracing: true,
+        ..Config::default()
+    };
+    test_interpreter_and_jit_asm!(
+        "
+        mov32 r0, 0
+        mov r1, 1
+        lsh r1, 32
+        or r1, 0xb
+        mov32 r2, 0xb
+        jne32 r1, r2, +3
+        jne32 r1, 0xb, +2
+        jne r1, r2, +1
+        exit
+        mov32 r0, 1
+        exit",
+        config,
+        [],
+        (),
+        TestContextObject::new(10),
+        { |_vm, res: ProgramResult<TestUserError>| { res.unwrap() == 0x1 } },
+    );
+}
+
+#[test]
+fn test_jset32() {
+    let config = Config {
+        enable_jmp32: true,
+        enable_instruction_tracing: true,
+        ..Config::default()
+    };
+    test_interpreter_and_jit_asm!(
+        "
+        mov32 r0, 0
+        mov r1, 1
+        lsh r1, 32
+        or r1, 0x2
+        mov r2, r1
+        jset32 r1, 0x1, +4
+        xor r2, 0x2
+        jset32 r1, r2, +2
+        jset r1, r2, +1
+        exit
+        mov32 r0, 1
+        exit",
+        config,
+        [],
+        (),
+        TestContextObject::new(11),
+        { |_vm, res: ProgramResult<TestUserError>| { res.unwrap() == 0x1 } },
+    );
+}
+
+#[test]
+fn test_jmp32_signedness() {
+    let config = Config {
+        enable_jmp32: true,
+        enable_instruction_tracing: true,
+        ..Config::default()
+    };
+    test_interpreter_and_jit_asm!(
+        "
+        mov32 r0, 0
+        lddw r1, 0x1fffffffe
+        mov32 r2, -1
+        jgt32 r1, 5, +1
+        exit
+        jsgt32 r1, -1, +10
+        jsge32 r1, r2, +9
+        jslt32 r1, -1, +1
+        exit
+        jsle32 r1, r2, +1
+        exit
+        jlt32 r1, r2, +1
+        exit
+        jsgt r1, 5, +1
+        exit
+        mov32 r0, 1
+        exit",
+        config,
+        [],
+        (),
+        TestContextObject::new(12),
+        { |_vm, res: ProgramResult<TestUserError>| { res.unwrap() == 0x1 } },
     );
 }
 
@@ -2139,7 +2786,7 @@ fn test_jsgt_imm() {
         [],
         (),
         TestContextObject::new(7),
-        { |_vm, res: ProgramResult| { res.unwrap() == 0x1 } },
+        { |_vm, res: ProgramResult<TestUserError>| { res.unwrap() == 0x1 } },
     );
 }
 
@@ -2159,7 +2806,7 @@ fn test_jsgt_reg() {
         [],
         (),
         TestContextObject::new(8),
-        { |_vm, res: ProgramResult| { res.unwrap() == 0x1 } },
+        { |_vm, res: ProgramResult<TestUserError>| { res.unwrap() == 0x1 } },
     );
 }
 
@@ -2178,7 +2825,7 @@ fn test_jslt_imm() {
         [],
         (),
         TestContextObject::new(7),
-        { |_vm, res: ProgramResult| { res.unwrap() == 0x1 } },
+        { |_vm, res: ProgramResult<TestUserError>| { res.unwrap() == 0x1 } },
     );
 }
 
@@ -2199,10 +2846,54 @@ fn test_jslt_reg() {
         [],
         (),
         TestContextObject::new(9),
-        { |_vm, res: ProgramResult| { res.unwrap() == 0x1 } },
+        { |_vm, res: ProgramResult<TestUserError>| { res.unwrap() == 0x1 } },
     );
 }
 
+#[test]
+fn test_signed_jumps_sign_boundary() {
+    // Values straddling zero and i64::MIN, plus some which are only negative in their low 32 bits
+    for value in [
+        0i64,
+        1,
+        -1,
+        -2,
+        i64::MIN,
+        i64::MIN + 1,
+        i64::MAX,
+        0x80000000,
+        0xFFFFFFFF,
+    ] {
+        for (mnemonic, expected) in [
+            ("jsgt", value > -1),
+            ("jsge", value >= -1),
+            ("jslt", value < -1),
+            ("jsle", value <= -1),
+        ] {
+            for operand in ["-1", "r2"] {
+                let source = format!(
+                    "
+                    lddw r1, {:#x}
+                    mov r2, -1
+                    mov r0, 0
+                    {} r1, {}, +1
+                    exit
+                    mov r0, 1
+                    exit",
+                    value as u64, mnemonic, operand,
+                );
+                test_interpreter_and_jit_asm!(
+                    (&source),
+                    [],
+                    (),
+                    TestContextObject::new(if expected { 6 } else { 5 }),
+                    { |_vm, res: ProgramResult<TestUserError>| res.unwrap() == expected as u64 },
+                );
+            }
+        }
+    }
+}
+
 // Call Stack
 
 #[test]
@@ -2221,7 +2912,7 @@ fn test_stack1() {
         [],
         (),
         TestContextObject::new(9),
-        { |_vm, res: ProgramResult| { res.unwrap() == 0xcd } },
+        { |_vm, res: ProgramResult<TestUserError>| { res.unwrap() == 0xcd } },
     );
 }
 
@@ -2251,7 +2942,7 @@ fn test_stack2() {
             b"bpf_gather_bytes" => syscalls::bpf_gather_bytes,
         ),
         TestContextObject::new(16),
-        { |_vm, res: ProgramResult| { res.unwrap() == 0x01020304 } },
+        { |_vm, res: ProgramResult<TestUserError>| { res.unwrap() == 0x01020304 } },
     );
 }
 
@@ -2292,7 +2983,7 @@ fn test_string_stack() {
             b"bpf_str_cmp" => syscalls::bpf_str_cmp,
         ),
         TestContextObject::new(28),
-        { |_vm, res: ProgramResult| { res.unwrap() == 0x0 } },
+        { |_vm, res: ProgramResult<TestUserError>| { res.unwrap() == 0x0 } },
     );
 }
 
@@ -2312,9 +3003,9 @@ fn test_err_fixed_stack_out_of_bound() {
         (),
         TestContextObject::new(1),
         {
-            |_vm, res: ProgramResult| {
+            |_vm, res: ProgramResult<TestUserError>| {
                 matches!(res.unwrap_err(),
-                    EbpfError::AccessViolation(pc, access_type, vm_addr, len, name)
+                    EbpfError::AccessViolation(pc, access_type, vm_addr, len, name, _)
                     if access_type == AccessType::Store && pc == 29 && vm_addr == 0x1FFFFD000 && len == 1 && name == "program"
                 )
             }
@@ -2323,48 +3014,49 @@ fn test_err_fixed_stack_out_of_bound() {
 }
 
 #[test]
-fn test_err_dynamic_stack_out_of_bound() {
+fn test_err_fixed_stack_overflow_during_map() {
     let config = Config {
-        dynamic_stack_frames: true,
+        dynamic_stack_frames: false,
         max_call_depth: 3,
         ..Config::default()
     };
 
-    // The stack goes from MM_STACK_START + config.stack_size() to MM_STACK_START
-
-    // Check that accessing MM_STACK_START - 1 fails
+    // Below the first stack frame
     test_interpreter_and_jit_asm!(
         "
-        stb [r10-0x3001], 0
+        stb [r10-0x1004], 0
         exit",
         config,
         [],
         (),
         TestContextObject::new(1),
         {
-            |_vm, res: ProgramResult| {
+            |_vm, res: ProgramResult<TestUserError>| {
                 matches!(res.unwrap_err(),
-                    EbpfError::AccessViolation(pc, access_type, vm_addr, len, region)
-                    if access_type == AccessType::Store && pc == 29 && vm_addr == ebpf::MM_STACK_START - 1 && len == 1 && region == "program"
+                    EbpfError::StackOverflowDuringMap(pc, access_type, vm_addr, len, stack_frame, underflow)
+                    if access_type == AccessType::Store && pc == 29 && vm_addr == ebpf::MM_STACK_START - 4 && len == 1 && stack_frame == 0 && underflow == 4
                 )
             }
         },
     );
 
-    // Check that accessing MM_STACK_START + expected_stack_len fails
+    // In the gap below the second stack frame
     test_interpreter_and_jit_asm!(
         "
-        stb [r10], 0
+        call function_foo
+        exit
+        function_foo:
+        stb [r10-0x1001], 0
         exit",
         config,
         [],
         (),
-        TestContextObject::new(1),
+        TestContextObject::new(2),
         {
-            |_vm, res: ProgramResult| {
+            |_vm, res: ProgramResult<TestUserError>| {
                 matches!(res.unwrap_err(),
-                    EbpfError::AccessViolation(pc, access_type, vm_addr, len, region)
-                    if access_type == AccessType::Store && pc == 29 && vm_addr == ebpf::MM_STACK_START + config.stack_size() as u64 && len == 1 && region == "stack"
+                    EbpfError::StackOverflowDuringMap(pc, access_type, vm_addr, len, stack_frame, underflow)
+                    if access_type == AccessType::Store && pc == 29 + 2 && vm_addr == ebpf::MM_STACK_START + 0x1FFF && len == 1 && stack_frame == 1 && underflow == 1
                 )
             }
         },
@@ -2372,36 +3064,85 @@ fn test_err_dynamic_stack_out_of_bound() {
 }
 
 #[test]
-fn test_err_dynamic_stack_ptr_overflow() {
+fn test_err_dynamic_stack_out_of_bound() {
     let config = Config {
         dynamic_stack_frames: true,
+        max_call_depth: 3,
         ..Config::default()
     };
 
-    // See the comment in CallFrames::resize_stack() for the reason why it's
-    // safe to let the stack pointer overflow
+    // The stack goes from MM_STACK_START + config.stack_size() to MM_STACK_START
 
-    // stack_ptr -= stack_ptr + 1
+    // Check that accessing MM_STACK_START - 1 fails
     test_interpreter_and_jit_asm!(
         "
-        sub r11, 0x7FFFFFFF
-        sub r11, 0x7FFFFFFF
-        sub r11, 0x7FFFFFFF
-        sub r11, 0x7FFFFFFF
-        sub r11, 0x14005
-        call function_foo
-        exit
-        function_foo:
-        stb [r10], 0
+        stb [r10-0x3001], 0
+        exit",
+        config,
+        [],
+        (),
+        TestContextObject::new(1),
+        {
+            |_vm, res: ProgramResult<TestUserError>| {
+                matches!(res.unwrap_err(),
+                    EbpfError::StackOverflowDuringMap(pc, access_type, vm_addr, len, stack_frame, underflow)
+                    if access_type == AccessType::Store && pc == 29 && vm_addr == ebpf::MM_STACK_START - 1 && len == 1 && stack_frame == 0 && underflow == 1
+                )
+            }
+        },
+    );
+
+    // Check that accessing MM_STACK_START + expected_stack_len fails
+    test_interpreter_and_jit_asm!(
+        "
+        stb [r10], 0
+        exit",
+        config,
+        [],
+        (),
+        TestContextObject::new(1),
+        {
+            |_vm, res: ProgramResult<TestUserError>| {
+                matches!(res.unwrap_err(),
+                    EbpfError::AccessViolation(pc, access_type, vm_addr, len, region, _)
+                    if access_type == AccessType::Store && pc == 29 && vm_addr == ebpf::MM_STACK_START + config.stack_size() as u64 && len == 1 && region == "stack"
+                )
+            }
+        },
+    );
+}
+
+#[test]
+fn test_err_dynamic_stack_ptr_overflow() {
+    let config = Config {
+        dynamic_stack_frames: true,
+        ..Config::default()
+    };
+
+    // See the comment in CallFrames::resize_stack() for the reason why it's
+    // safe to let the stack pointer overflow
+
+    // stack_ptr -= stack_ptr + 1
+    test_interpreter_and_jit_asm!(
+        "
+        sub r11, 0x7FFFFFFF
+        sub r11, 0x7FFFFFFF
+        sub r11, 0x7FFFFFFF
+        sub r11, 0x7FFFFFFF
+        sub r11, 0x14005
+        call function_foo
+        exit
+        function_foo:
+        stb [r10], 0
         exit",
         config,
         [],
         (),
         TestContextObject::new(7),
         {
-            |_vm, res: ProgramResult| {
+            |_vm, res: ProgramResult<TestUserError>| {
                 matches!(res.unwrap_err(),
-                    EbpfError::AccessViolation(pc, access_type, vm_addr, len, region)
+                    EbpfError::AccessViolation(pc, access_type, vm_addr, len, region, _)
                     if access_type == AccessType::Store && pc == 29 + 7 && vm_addr == u64::MAX && len == 1 && region == "unknown"
                 )
             }
@@ -2429,7 +3170,7 @@ fn test_dynamic_stack_frames_empty() {
         (),
         TestContextObject::new(4),
         {
-            |_vm, res: ProgramResult| {
+            |_vm, res: ProgramResult<TestUserError>| {
                 res.unwrap() == ebpf::MM_STACK_START + config.stack_size() as u64
             }
         },
@@ -2458,7 +3199,7 @@ fn test_dynamic_frame_ptr() {
         (),
         TestContextObject::new(5),
         {
-            |_vm, res: ProgramResult| {
+            |_vm, res: ProgramResult<TestUserError>| {
                 res.unwrap() == ebpf::MM_STACK_START + config.stack_size() as u64 - 8
             }
         },
@@ -2480,7 +3221,7 @@ fn test_dynamic_frame_ptr() {
         (),
         TestContextObject::new(5),
         {
-            |_vm, res: ProgramResult| {
+            |_vm, res: ProgramResult<TestUserError>| {
                 res.unwrap() == ebpf::MM_STACK_START + config.stack_size() as u64
             }
         },
@@ -2516,7 +3257,7 @@ fn test_entrypoint_exit() {
             [],
             (),
             TestContextObject::new(5),
-            { |_vm, res: ProgramResult| { res.unwrap() == 42 } },
+            { |_vm, res: ProgramResult<TestUserError>| { res.unwrap() == 42 } },
         );
     }
 }
@@ -2546,7 +3287,7 @@ fn test_stack_call_depth_tracking() {
             [],
             (),
             TestContextObject::new(5),
-            { |_vm, res: ProgramResult| { res.is_ok() } },
+            { |_vm, res: ProgramResult<TestUserError>| { res.is_ok() } },
         );
 
         // two nested calls should trigger CallDepthExceeded instead
@@ -2566,7 +3307,7 @@ fn test_stack_call_depth_tracking() {
             (),
             TestContextObject::new(2),
             {
-                |_vm, res: ProgramResult| {
+                |_vm, res: ProgramResult<TestUserError>| {
                     matches!(res.unwrap_err(),
                         EbpfError::CallDepthExceeded(pc, depth)
                         if pc == 29 + 2 && depth == config.max_call_depth
@@ -2577,6 +3318,204 @@ fn test_stack_call_depth_tracking() {
     }
 }
 
+#[test]
+fn test_max_call_depth() {
+    for dynamic_stack_frames in [false, true] {
+        // Three nested calls need four frames, including the one of the entrypoint
+        for (max_call_depth, expected_instruction_count) in [(3, 3), (4, 8)] {
+            let config = Config {
+                dynamic_stack_frames,
+                max_call_depth,
+                enable_instruction_tracing: true,
+                ..Config::default()
+            };
+            assert_eq!(
+                config.stack_size(),
+                max_call_depth * config.stack_frame_size
+            );
+            test_interpreter_and_jit_asm!(
+                "
+                call function_a
+                exit
+                function_a:
+                call function_b
+                exit
+                function_b:
+                call function_c
+                exit
+                function_c:
+                mov r0, 42
+                exit",
+                config,
+                [],
+                (),
+                TestContextObject::new(expected_instruction_count),
+                {
+                    |_vm, res: ProgramResult<TestUserError>| {
+                        if max_call_depth == 3 {
+                            matches!(res.unwrap_err(),
+                                EbpfError::CallDepthExceeded(pc, depth)
+                                if pc == 29 + 4 && depth == 3
+                            )
+                        } else {
+                            res.unwrap() == 42
+                        }
+                    }
+                },
+            );
+        }
+    }
+}
+
+#[test]
+fn test_stack_frame_boundary() {
+    let config = Config {
+        dynamic_stack_frames: false,
+        enable_stack_frame_gaps: true,
+        ..Config::default()
+    };
+
+    // The last slots of the frame
+    test_interpreter_and_jit_asm!(
+        "
+        mov64 r1, 0x11
+        stxdw [r10-8], r1
+        stxb [r10-1], r1
+        ldxdw r0, [r10-8]
+        exit",
+        config,
+        [],
+        (),
+        TestContextObject::new(5),
+        { |_vm, res: ProgramResult<TestUserError>| { res.unwrap() == 0x1100000000000011 } },
+    );
+
+    // Stores which reach past the frame pointer into the gap
+    for (store, vm_addr, len) in [
+        ("stxdw [r10-7], r1", 0xff9, 8),
+        ("stxw [r10-1], r1", 0xfff, 4),
+    ] {
+        test_interpreter_and_jit_asm!(
+            (&format!(
+                "
+                mov64 r1, 0x11
+                {}
+                exit",
+                store
+            )),
+            config,
+            [],
+            (),
+            TestContextObject::new(2),
+            {
+                |_vm, res: ProgramResult<TestUserError>| {
+                    matches!(res.unwrap_err(),
+                        EbpfError::StackAccessViolation(pc, access_type, addr, access_len, frame)
+                        if pc == 30 && access_type == AccessType::Store && addr == ebpf::MM_STACK_START + vm_addr && access_len == len && frame == 0
+                    )
+                }
+            },
+        );
+    }
+
+    // A load which reaches into the gap of the callee frame
+    test_interpreter_and_jit_asm!(
+        "
+        call function_foo
+        exit
+        function_foo:
+        ldxdw r0, [r10-4]
+        exit",
+        config,
+        [],
+        (),
+        TestContextObject::new(2),
+        {
+            |_vm, res: ProgramResult<TestUserError>| {
+                matches!(res.unwrap_err(),
+                    EbpfError::StackAccessViolation(pc, access_type, addr, access_len, frame)
+                    if pc == 31 && access_type == AccessType::Load && addr == ebpf::MM_STACK_START + 0x2ffc && access_len == 8 && frame == 2
+                )
+            }
+        },
+    );
+}
+
+#[test]
+fn test_stack_frame_sizes() {
+    let config = Config {
+        max_call_depth: 8,
+        stack_frame_size: 512,
+        dynamic_stack_frames: false,
+        enable_stack_frame_gaps: false,
+        enable_instruction_tracing: true,
+        ..Config::default()
+    };
+    // Sums up the depths, which every frame keeps on its own stack
+    let source = |depth: u64| {
+        format!(
+            "
+            mov64 r1, {}
+            call function_foo
+            exit
+            function_foo:
+            stxdw [r10-0x8], r1
+            mov64 r0, 0
+            jeq r1, 0, +4
+            sub64 r1, 1
+            call function_foo
+            ldxdw r1, [r10-0x8]
+            add64 r0, r1
+            exit",
+            depth
+        )
+    };
+
+    // Uniform frames are limited by max_call_depth
+    let executable =
+        assemble::<TestContextObject>(&source(20), config, SyscallRegistry::default()).unwrap();
+    test_interpreter_and_jit!(executable, [], TestContextObject::new(37), {
+        |_vm, res: ProgramResult<TestUserError>| {
+            matches!(res.unwrap_err(),
+                EbpfError::CallDepthExceeded(pc, depth)
+                if pc == 29 + 7 && depth == 8
+            )
+        }
+    },);
+
+    // Small frames fit 20 nested calls into the stack of 8 uniform frames
+    let mut executable =
+        assemble::<TestContextObject>(&source(20), config, SyscallRegistry::default()).unwrap();
+    executable.set_stack_frame_size(3, 16);
+    assert_eq!(
+        executable.get_stack_frame_size(3),
+        Some(ebpf::MIN_STACK_FRAME_SIZE)
+    );
+    test_interpreter_and_jit!(executable, [], TestContextObject::new(167), {
+        |_vm, res: ProgramResult<TestUserError>| res.unwrap() == 210
+    },);
+
+    // The root frame of 512 bytes and 56 frames of 64 bytes exhaust the stack
+    let mut executable = assemble::<TestContextObject>(
+        &source(100),
+        Config {
+            clear_stack_on_entry: true,
+            ..config
+        },
+        SyscallRegistry::default(),
+    )
+    .unwrap();
+    executable.set_stack_frame_size(3, 16);
+    test_interpreter_and_jit!(executable, [], TestContextObject::new(282), {
+        |_vm, res: ProgramResult<TestUserError>| {
+            matches!(res.unwrap_err(),
+                EbpfError::CallDepthExceeded(pc, depth)
+                if pc == 29 + 7 && depth == 57
+            )
+        }
+    },);
+}
+
 #[test]
 fn test_err_mem_access_out_of_bound() {
     let mem = [0; 512];
@@ -2597,9 +3536,9 @@ fn test_err_mem_access_out_of_bound() {
         )
         .unwrap();
         test_interpreter_and_jit!(executable, mem, TestContextObject::new(2), {
-            |_vm, res: ProgramResult| {
+            |_vm, res: ProgramResult<TestUserError>| {
                 matches!(res.unwrap_err(),
-                    EbpfError::AccessViolation(pc, access_type, vm_addr, len, name)
+                    EbpfError::AccessViolation(pc, access_type, vm_addr, len, name, _)
                     if access_type == AccessType::Store && pc == 31 && vm_addr == address && len == 1 && name == "unknown"
                 )
             }
@@ -2607,6 +3546,207 @@ fn test_err_mem_access_out_of_bound() {
     }
 }
 
+#[test]
+fn test_bounds_checks() {
+    // On by default, so an access past the end of the input traps
+    test_interpreter_and_jit_asm!(
+        "
+        ldxb r0, [r1+4]
+        exit",
+        [0x11, 0x22, 0x33, 0x44],
+        (),
+        TestContextObject::new(1),
+        {
+            |_vm, res: ProgramResult<TestUserError>| {
+                matches!(res.unwrap_err(),
+                    EbpfError::AccessViolation(pc, access_type, vm_addr, len, _name, _)
+                    if access_type == AccessType::Load && pc == 29 && vm_addr == ebpf::MM_INPUT_START + 4 && len == 1
+                )
+            }
+        },
+    );
+
+    // Programs which stay within their regions behave the same without the checks
+    #[cfg(all(not(windows), target_arch = "x86_64"))]
+    {
+        let executable = assemble::<TestContextObject>(
+            "
+            ldxw r2, [r1]
+            stxw [r10-4], r2
+            ldxb r0, [r10-2]
+            stb [r1+3], 0x55
+            ldxb r3, [r1+3]
+            add64 r0, r3
+            exit",
+            Config::default(),
+            SyscallRegistry::default(),
+        )
+        .unwrap();
+        let mut verified_executable =
+            VerifiedExecutable::<RequisiteVerifier, _>::from_executable(executable).unwrap();
+        unsafe { verified_executable.jit_compile_without_bounds_checks() }.unwrap();
+        let mut mem = [0x11, 0x22, 0x33, 0x44];
+        let mem_region = MemoryRegion::new_writable(&mut mem, ebpf::MM_INPUT_START);
+        let mut context_object = TestContextObject::new(7);
+        let mut vm = EbpfVm::new(
+            &verified_executable,
+            &mut context_object,
+            &mut [],
+            vec![mem_region],
+        )
+        .unwrap();
+        let (instruction_count, result) = vm.execute_program(false);
+        assert_eq!(result.unwrap(), 0x33 + 0x55);
+        assert_eq!(instruction_count, 7);
+    }
+}
+
+#[test]
+fn test_enforce_aligned_access() {
+    let config = Config {
+        enforce_aligned_access: true,
+        ..Config::default()
+    };
+    for (instruction, len) in [
+        ("ldxh r0, [r1+{}]", 2u64),
+        ("ldxw r0, [r1+{}]", 4),
+        ("ldxdw r0, [r1+{}]", 8),
+        ("stxh [r1+{}], r0", 2),
+        ("stw [r1+{}], 1", 4),
+        ("stxdw [r1+{}], r0", 8),
+    ] {
+        for offset in 1..len {
+            let source = format!(
+                "
+                mov r0, 0
+                ldxb r0, [r1+{}]
+                {}
+                exit",
+                offset,
+                instruction.replace("{}", &(len + offset).to_string()),
+            );
+            test_interpreter_and_jit_asm!(
+                (&source),
+                config,
+                [0; 16],
+                (),
+                TestContextObject::new(3),
+                {
+                    |_vm, res: ProgramResult<TestUserError>| {
+                        matches!(res.unwrap_err(),
+                            EbpfError::UnalignedMemoryAccess(pc, vm_addr, access_len)
+                            if pc == 31 && vm_addr == ebpf::MM_INPUT_START + len + offset && access_len == len
+                        )
+                    }
+                },
+            );
+        }
+        let source = format!(
+            "
+            mov r0, 0
+            {}
+            exit",
+            instruction.replace("{}", &len.to_string()),
+        );
+        test_interpreter_and_jit_asm!((&source), config, [0; 16], (), TestContextObject::new(3), {
+            |_vm, res: ProgramResult<TestUserError>| res.unwrap() == 0
+        },);
+    }
+}
+
+#[test]
+fn test_last_registers() {
+    test_interpreter_and_jit_asm!(
+        "
+        mov r6, 0x1234
+        mov r9, r6
+        add r9, 1
+        mov r0, 2
+        exit",
+        [],
+        (),
+        TestContextObject::new(5),
+        {
+            |vm: &EbpfVm<RequisiteVerifier, TestContextObject>,
+             res: ProgramResult<TestUserError>| {
+                let registers = vm.last_registers();
+                res.unwrap() == 2
+                    && registers[0] == 2
+                    && registers[6] == 0x1234
+                    && registers[9] == 0x1235
+            }
+        },
+    );
+}
+
+#[test]
+fn test_oob_read_behavior() {
+    let run = |source: &str, oob_read_behavior: OobReadBehavior, instruction_count: u64| {
+        let config = Config {
+            oob_read_behavior,
+            ..Config::default()
+        };
+        let executable =
+            assemble::<TestContextObject>(source, config, SyscallRegistry::default()).unwrap();
+        #[allow(unused_mut)]
+        let mut verified_executable =
+            VerifiedExecutable::<RequisiteVerifier, _>::from_executable(executable).unwrap();
+        #[cfg(all(not(windows), target_arch = "x86_64"))]
+        verified_executable.jit_compile().unwrap();
+        let mut results = Vec::new();
+        for interpreted in [
+            true,
+            #[cfg(all(not(windows), target_arch = "x86_64"))]
+            false,
+        ] {
+            let mut heap = [0xFF; 8];
+            let mut context_object = TestContextObject::new(instruction_count);
+            let mut vm = EbpfVm::new(
+                &verified_executable,
+                &mut context_object,
+                &mut heap,
+                Vec::new(),
+            )
+            .unwrap();
+            let (count, result) = vm.execute_program(interpreted);
+            assert_eq!(count, instruction_count);
+            results.push(result);
+        }
+        results
+    };
+
+    // One byte past the heap and a load which straddles its end
+    let load = "
+        lddw r1, 0x300000000
+        ldxb r0, [r1+8]
+        ldxdw r2, [r1+4]
+        add r0, r2
+        exit";
+    for result in run(load, OobReadBehavior::ReturnZero, 5) {
+        assert_eq!(result.unwrap(), 0);
+    }
+    for result in run(load, OobReadBehavior::Fault, 2) {
+        assert!(matches!(
+            result.unwrap_err(),
+            EbpfError::AccessViolation(pc, AccessType::Load, vm_addr, 1, _, _)
+            if pc == 31 && vm_addr == ebpf::MM_HEAP_START + 8
+        ));
+    }
+
+    // Stores fault regardless
+    let store = "
+        lddw r1, 0x300000000
+        stb [r1+8], 1
+        exit";
+    for result in run(store, OobReadBehavior::ReturnZero, 2) {
+        assert!(matches!(
+            result.unwrap_err(),
+            EbpfError::AccessViolation(pc, AccessType::Store, vm_addr, 1, _, _)
+            if pc == 31 && vm_addr == ebpf::MM_HEAP_START + 8
+        ));
+    }
+}
+
 // CALL_IMM & CALL_REG : Procedure Calls
 
 #[test]
@@ -2618,7 +3758,7 @@ fn test_relative_call() {
             b"log" => syscalls::bpf_syscall_string,
         ),
         TestContextObject::new(14),
-        { |_vm, res: ProgramResult| { res.unwrap() == 2 } },
+        { |_vm, res: ProgramResult<TestUserError>| { res.unwrap() == 2 } },
     );
 }
 
@@ -2631,7 +3771,7 @@ fn test_bpf_to_bpf_scratch_registers() {
             b"log_64" => syscalls::bpf_syscall_u64,
         ),
         TestContextObject::new(41),
-        { |_vm, res: ProgramResult| { res.unwrap() == 112 } },
+        { |_vm, res: ProgramResult<TestUserError>| { res.unwrap() == 112 } },
     );
 }
 
@@ -2642,10 +3782,68 @@ fn test_bpf_to_bpf_pass_stack_reference() {
         [],
         (),
         TestContextObject::new(29),
-        { |_vm, res: ProgramResult| res.unwrap() == 42 },
+        { |_vm, res: ProgramResult<TestUserError>| res.unwrap() == 42 },
     );
 }
 
+#[test]
+fn test_clear_stack_on_entry() {
+    for (clear_stack_on_entry, expected_result) in [(false, 0x5C), (true, 0x7)] {
+        let config = Config {
+            clear_stack_on_entry,
+            enable_instruction_tracing: true,
+            ..Config::default()
+        };
+        test_interpreter_and_jit_asm!(
+            "
+            stdw [r10-0x8], 0x7
+            sub r11, 0x8
+            call function_write
+            call function_read
+            ldxdw r1, [r10-0x8]
+            add64 r0, r1
+            exit
+            function_write:
+            stdw [r10-0x8], 0x55
+            exit
+            function_read:
+            ldxdw r0, [r10-0x8]
+            exit",
+            config,
+            [],
+            (),
+            TestContextObject::new(11),
+            { |_vm, res: ProgramResult<TestUserError>| res.unwrap() == expected_result },
+        );
+        let config = Config {
+            clear_stack_on_entry,
+            dynamic_stack_frames: false,
+            enable_instruction_tracing: true,
+            ..Config::default()
+        };
+        test_interpreter_and_jit_asm!(
+            "
+            stdw [r10-0x8], 0x7
+            call function_write
+            call function_read
+            ldxdw r1, [r10-0x8]
+            add64 r0, r1
+            exit
+            function_write:
+            stdw [r10-0x8], 0x55
+            exit
+            function_read:
+            ldxdw r0, [r10-0x8]
+            exit",
+            config,
+            [],
+            (),
+            TestContextObject::new(10),
+            { |_vm, res: ProgramResult<TestUserError>| res.unwrap() == expected_result },
+        );
+    }
+}
+
 #[test]
 fn test_syscall_parameter_on_stack() {
     test_interpreter_and_jit_asm!(
@@ -2661,7 +3859,7 @@ fn test_syscall_parameter_on_stack() {
             b"bpf_syscall_string" => syscalls::bpf_syscall_string,
         ),
         TestContextObject::new(6),
-        { |_vm, res: ProgramResult| { res.unwrap() == 0 } },
+        { |_vm, res: ProgramResult<TestUserError>| { res.unwrap() == 0 } },
     );
 }
 
@@ -2681,19 +3879,76 @@ fn test_callx() {
         [],
         (),
         TestContextObject::new(8),
-        { |_vm, res: ProgramResult| { res.unwrap() == 42 } },
+        { |_vm, res: ProgramResult<TestUserError>| { res.unwrap() == 42 } },
     );
 }
 
 #[test]
-fn test_err_callx_unregistered() {
+fn test_jit_function_padding() {
+    // The entrypoint falls through into function_foo, so the padding in between is executed
+    let config = Config {
+        jit_function_padding: 64,
+        ..Config::default()
+    };
     test_interpreter_and_jit_asm!(
         "
-        mov64 r0, 0x0
-        mov64 r8, 0x1
-        lsh64 r8, 0x20
-        or64 r8, 0x30
-        callx r8
+        mov r0, 1
+        call function_foo
+        add64 r0, 2
+        function_foo:
+        add64 r0, 3
+        exit",
+        config,
+        [],
+        (),
+        TestContextObject::new(7),
+        { |_vm, res: ProgramResult<TestUserError>| { res.unwrap() == 9 } },
+    );
+}
+
+#[test]
+fn test_callx_jump_table() {
+    // The first input byte selects one of two functions of two instructions each
+    const SOURCE: &str = "
+        ldxb r2, [r1]
+        mov64 r8, 0x1
+        lsh64 r8, 0x20
+        mul64 r2, 0x10
+        add64 r8, r2
+        add64 r8, 0x40
+        callx r8
+        exit
+        function_foo:
+        mov64 r0, 0x2A
+        exit
+        function_bar:
+        mov64 r0, 0x2B
+        exit";
+    for (selector, expected_result) in [(0x00, 0x2A), (0x01, 0x2B)] {
+        test_interpreter_and_jit_asm!(SOURCE, [selector], (), TestContextObject::new(10), {
+            |_vm, res: ProgramResult<TestUserError>| res.unwrap() == expected_result
+        },);
+    }
+    // A selector past the end of the table leaves the text segment
+    test_interpreter_and_jit_asm!(SOURCE, [0x02], (), TestContextObject::new(7), {
+        |_vm, res: ProgramResult<TestUserError>| {
+            matches!(res.unwrap_err(),
+                EbpfError::CallOutsideTextSegment(pc, target_pc)
+                if pc == 35 && target_pc == 0x100000060
+            )
+        }
+    },);
+}
+
+#[test]
+fn test_err_callx_unregistered() {
+    test_interpreter_and_jit_asm!(
+        "
+        mov64 r0, 0x0
+        mov64 r8, 0x1
+        lsh64 r8, 0x20
+        or64 r8, 0x30
+        callx r8
         exit
         mov64 r0, 0x2A
         exit",
@@ -2701,7 +3956,7 @@ fn test_err_callx_unregistered() {
         (),
         TestContextObject::new(6),
         {
-            |_vm, res: ProgramResult| matches!(res.unwrap_err(), EbpfError::UnsupportedInstruction(pc) if pc == 35)
+            |_vm, res: ProgramResult<TestUserError>| matches!(res.unwrap_err(), EbpfError::UnsupportedInstruction(pc) if pc == 35)
         },
     );
 }
@@ -2717,7 +3972,7 @@ fn test_err_callx_oob_low() {
         (),
         TestContextObject::new(2),
         {
-            |_vm, res: ProgramResult| {
+            |_vm, res: ProgramResult<TestUserError>| {
                 matches!(res.unwrap_err(),
                     EbpfError::CallOutsideTextSegment(pc, target_pc)
                     if pc == 30 && target_pc == 0
@@ -2740,7 +3995,7 @@ fn test_err_callx_oob_high() {
         (),
         TestContextObject::new(4),
         {
-            |_vm, res: ProgramResult| {
+            |_vm, res: ProgramResult<TestUserError>| {
                 matches!(res.unwrap_err(),
                     EbpfError::CallOutsideTextSegment(pc, target_pc)
                     if pc == 32 && target_pc == 0xffffffff00000000
@@ -2750,6 +4005,179 @@ fn test_err_callx_oob_high() {
     );
 }
 
+#[test]
+fn test_callx_reject_unaligned() {
+    let config = Config {
+        reject_callx_to_unaligned: true,
+        ..Config::default()
+    };
+    test_interpreter_and_jit_asm!(
+        "
+        mov64 r0, 0x0
+        mov64 r8, 0x1
+        lsh64 r8, 0x20
+        or64 r8, 0x30
+        callx r8
+        exit
+        function_foo:
+        mov64 r0, 0x2A
+        exit",
+        config,
+        [],
+        (),
+        TestContextObject::new(8),
+        { |_vm, res: ProgramResult<TestUserError>| { res.unwrap() == 42 } },
+    );
+}
+
+#[test]
+fn test_err_callx_reject_unaligned() {
+    let config = Config {
+        reject_callx_to_unaligned: true,
+        ..Config::default()
+    };
+    test_interpreter_and_jit_asm!(
+        "
+        mov64 r0, 0x0
+        mov64 r8, 0x1
+        lsh64 r8, 0x20
+        or64 r8, 0x33
+        callx r8
+        exit
+        function_foo:
+        mov64 r0, 0x2A
+        exit",
+        config,
+        [],
+        (),
+        TestContextObject::new(5),
+        {
+            |_vm, res: ProgramResult<TestUserError>| {
+                matches!(res.unwrap_err(),
+                    EbpfError::InvalidCallxTarget(pc, target_address)
+                    if pc == 33 && target_address == 0x100000033
+                )
+            }
+        },
+    );
+}
+
+#[test]
+fn test_err_callx_reject_not_a_function() {
+    let config = Config {
+        reject_callx_to_unaligned: true,
+        ..Config::default()
+    };
+    test_interpreter_and_jit_asm!(
+        "
+        mov64 r0, 0x0
+        mov64 r8, 0x1
+        lsh64 r8, 0x20
+        or64 r8, 0x38
+        callx r8
+        exit
+        function_foo:
+        mov64 r0, 0x2A
+        exit",
+        config,
+        [],
+        (),
+        TestContextObject::new(5),
+        {
+            |_vm, res: ProgramResult<TestUserError>| {
+                matches!(res.unwrap_err(),
+                    EbpfError::InvalidCallxTarget(pc, target_address)
+                    if pc == 33 && target_address == 0x100000038
+                )
+            }
+        },
+    );
+}
+
+#[test]
+fn test_callx_reject_unknown_target() {
+    let config = Config {
+        reject_callx_to_unknown_target: true,
+        ..Config::default()
+    };
+    test_interpreter_and_jit_asm!(
+        "
+        mov64 r0, 0x0
+        mov64 r8, 0x1
+        lsh64 r8, 0x20
+        or64 r8, 0x30
+        callx r8
+        exit
+        function_foo:
+        mov64 r0, 0x2A
+        exit",
+        config,
+        [],
+        (),
+        TestContextObject::new(8),
+        { |_vm, res: ProgramResult<TestUserError>| { res.unwrap() == 42 } },
+    );
+}
+
+#[test]
+fn test_err_callx_reject_unknown_target() {
+    let config = Config {
+        reject_callx_to_unknown_target: true,
+        ..Config::default()
+    };
+    // In the middle of function_foo
+    test_interpreter_and_jit_asm!(
+        "
+        mov64 r0, 0x0
+        mov64 r8, 0x1
+        lsh64 r8, 0x20
+        or64 r8, 0x38
+        callx r8
+        exit
+        function_foo:
+        mov64 r0, 0x2A
+        exit",
+        config,
+        [],
+        (),
+        TestContextObject::new(5),
+        {
+            |_vm, res: ProgramResult<TestUserError>| {
+                matches!(res.unwrap_err(),
+                    EbpfError::CallxUnknownTarget(pc, target_address)
+                    if pc == 33 && target_address == 0x100000038
+                )
+            }
+        },
+    );
+
+    // Not aligned to an instruction
+    test_interpreter_and_jit_asm!(
+        "
+        mov64 r0, 0x0
+        mov64 r8, 0x1
+        lsh64 r8, 0x20
+        or64 r8, 0x33
+        callx r8
+        exit
+        function_foo:
+        mov64 r0, 0x2A
+        exit",
+        config,
+        [],
+        (),
+        TestContextObject::new(5),
+        {
+            |_vm, res: ProgramResult<TestUserError>| {
+                matches!(res.unwrap_err(),
+                    EbpfError::CallxUnknownTarget(pc, target_address)
+                    if pc == 33 && target_address == 0x100000033
+                )
+            }
+        },
+    );
+}
+
 #[test]
 fn test_err_static_jmp_lddw() {
     test_interpreter_and_jit_asm!(
@@ -2768,9 +4196,10 @@ fn test_err_static_jmp_lddw() {
         [],
         (),
         TestContextObject::new(9),
-        { |_vm, res: ProgramResult| { res.unwrap() == 0x2 } },
+        { |_vm, res: ProgramResult<TestUserError>| { res.unwrap() == 0x2 } },
     );
-    test_interpreter_and_jit_asm!(
+    // Calls into the middle of a lddw are rejected by the verifier
+    let executable = assemble::<TestContextObject>(
         "
         call 3
         mov r0, r0
@@ -2778,17 +4207,16 @@ fn test_err_static_jmp_lddw() {
         lddw r0, 0x1122334455667788
         exit
         ",
-        [],
-        (),
-        TestContextObject::new(2),
-        {
-            |_vm, res: ProgramResult| {
-                matches!(res.unwrap_err(),
-                    EbpfError::UnsupportedInstruction(pc) if pc == 33
-                )
-            }
-        },
-    );
+        Config::default(),
+        SyscallRegistry::default(),
+    )
+    .unwrap();
+    assert!(matches!(
+        VerifiedExecutable::<RequisiteVerifier, _>::from_executable(executable),
+        Err(EbpfError::VerifierError(VerifierError::JumpToMiddleOfLDDW(
+            4, 29
+        )))
+    ));
 }
 
 #[test]
@@ -2810,7 +4238,7 @@ fn test_err_dynamic_jmp_lddw() {
         (),
         TestContextObject::new(4),
         {
-            |_vm, res: ProgramResult| {
+            |_vm, res: ProgramResult<TestUserError>| {
                 matches!(res.unwrap_err(),
                     EbpfError::ExceededMaxInstructions(pc, initial_insn_count)
                     if pc == 33 && initial_insn_count == 4
@@ -2830,7 +4258,7 @@ fn test_err_dynamic_jmp_lddw() {
         (),
         TestContextObject::new(5),
         {
-            |_vm, res: ProgramResult| {
+            |_vm, res: ProgramResult<TestUserError>| {
                 matches!(res.unwrap_err(),
                     EbpfError::UnsupportedInstruction(pc) if pc == 34
                 )
@@ -2853,7 +4281,7 @@ fn test_err_dynamic_jmp_lddw() {
         (),
         TestContextObject::new(5),
         {
-            |_vm, res: ProgramResult| {
+            |_vm, res: ProgramResult<TestUserError>| {
                 matches!(res.unwrap_err(),
                     EbpfError::UnsupportedInstruction(pc) if pc == 36
                 )
@@ -2875,7 +4303,7 @@ fn test_err_dynamic_jmp_lddw() {
         (),
         TestContextObject::new(3),
         {
-            |_vm, res: ProgramResult| {
+            |_vm, res: ProgramResult<TestUserError>| {
                 matches!(res.unwrap_err(),
                     EbpfError::UnsupportedInstruction(pc) if pc == 36
                 )
@@ -2896,11 +4324,125 @@ fn test_bpf_to_bpf_depth() {
                 b"log" => syscalls::bpf_syscall_string,
             ),
             TestContextObject::new(if i == 0 { 4 } else { 3 + 10 * i as u64 }),
-            { |_vm, res: ProgramResult| { res.unwrap() == 0 } },
+            { |_vm, res: ProgramResult<TestUserError>| { res.unwrap() == 0 } },
         );
     }
 }
 
+#[test]
+fn test_internal_calls_detection() {
+    for (source, static_syscalls, has_internal_calls, expected_result) in [
+        (
+            "
+            mov64 r1, 0x1
+            syscall bpf_syscall_u64
+            mov64 r0, 0x2A
+            exit",
+            false,
+            false,
+            0x2A,
+        ),
+        (
+            "
+            mov64 r1, 0x1
+            syscall bpf_syscall_u64
+            mov64 r0, 0x2A
+            exit",
+            true,
+            false,
+            0x2A,
+        ),
+        (
+            "
+            call function_foo
+            exit
+            function_foo:
+            mov64 r0, 0x2A
+            exit",
+            true,
+            true,
+            0x2A,
+        ),
+        (
+            "
+            mov64 r8, 0x1
+            lsh64 r8, 0x20
+            or64 r8, 0x28
+            callx r8
+            exit
+            function_foo:
+            mov64 r0, 0x2A
+            exit",
+            false,
+            true,
+            0x2A,
+        ),
+    ] {
+        let config = Config {
+            static_syscalls,
+            ..Config::default()
+        };
+        let mut syscall_registry = SyscallRegistry::default();
+        test_interpreter_and_jit!(register, syscall_registry, b"bpf_syscall_u64" => syscalls::bpf_syscall_u64);
+        let executable = assemble::<TestContextObject>(source, config, syscall_registry).unwrap();
+        assert_eq!(executable.has_internal_calls(), has_internal_calls);
+        let verified_executable =
+            VerifiedExecutable::<TautologyVerifier, _>::from_executable(executable).unwrap();
+        let mut context_object = TestContextObject::new(10);
+        let mut vm = EbpfVm::new(
+            &verified_executable,
+            &mut context_object,
+            &mut [],
+            Vec::new(),
+        )
+        .unwrap();
+        assert_eq!(vm.execute_program(true).1.unwrap(), expected_result);
+    }
+}
+
+#[test]
+fn test_referenced_syscalls() {
+    // Syscalls named directly, one of them twice and one which is not registered
+    let mut syscall_registry = SyscallRegistry::default();
+    test_interpreter_and_jit!(register, syscall_registry, b"bpf_syscall_u64" => syscalls::bpf_syscall_u64);
+    let executable = assemble::<TestContextObject>(
+        "
+        syscall bpf_syscall_u64
+        call function_foo
+        syscall bpf_unknown
+        syscall bpf_syscall_u64
+        exit
+        function_foo:
+        exit",
+        Config::default(),
+        syscall_registry,
+    )
+    .unwrap();
+    let mut expected = vec![
+        ebpf::hash_symbol_name(b"bpf_syscall_u64"),
+        ebpf::hash_symbol_name(b"bpf_unknown"),
+    ];
+    expected.sort_unstable();
+    assert_eq!(executable.referenced_syscalls(), expected);
+
+    // Syscalls resolved by relocations
+    let mut file = File::open("tests/elfs/noop.so").unwrap();
+    let mut elf = Vec::new();
+    file.read_to_end(&mut elf).unwrap();
+    let mut syscall_registry = SyscallRegistry::default();
+    test_interpreter_and_jit!(register, syscall_registry, b"log" => syscalls::bpf_syscall_string);
+    test_interpreter_and_jit!(register, syscall_registry, b"log_64" => syscalls::bpf_syscall_u64);
+    let executable =
+        Executable::<TestContextObject>::from_elf(&elf, Config::default(), syscall_registry)
+            .unwrap();
+    let mut expected = vec![
+        ebpf::hash_symbol_name(b"log"),
+        ebpf::hash_symbol_name(b"log_64"),
+    ];
+    expected.sort_unstable();
+    assert_eq!(executable.referenced_syscalls(), expected);
+}
+
 #[test]
 fn test_err_bpf_to_bpf_too_deep() {
     let config = Config::default();
@@ -2913,7 +4455,7 @@ fn test_err_bpf_to_bpf_too_deep() {
         ),
         TestContextObject::new(176),
         {
-            |_vm, res: ProgramResult| {
+            |_vm, res: ProgramResult<TestUserError>| {
                 matches!(res.unwrap_err(),
                     EbpfError::CallDepthExceeded(pc, depth)
                     if pc == 55 && depth == config.max_call_depth
@@ -2924,19 +4466,76 @@ fn test_err_bpf_to_bpf_too_deep() {
 }
 
 #[test]
-fn test_err_reg_stack_depth() {
-    let config = Config::default();
+fn test_growable_stack() {
+    let config = Config {
+        dynamic_stack_frames: false,
+        initial_stack_frames: Some(1),
+        ..Config::default()
+    };
     test_interpreter_and_jit_asm!(
         "
-        mov64 r0, 0x1
-        lsh64 r0, 0x20
-        callx r0
-        exit",
-        [],
-        (),
+        mov64 r1, 4
+        call function_foo
+        exit
+        function_foo:
+        stxdw [r10-8], r1
+        mov64 r0, 0
+        jeq r1, 0, +3
+        sub64 r1, 1
+        call function_foo
+        ldxdw r1, [r10-8]
+        add64 r0, r1
+        exit",
+        config,
+        [],
+        (),
+        TestContextObject::new(40),
+        { |_vm, res: ProgramResult<TestUserError>| { res.unwrap() == 10 } },
+    );
+}
+
+#[test]
+fn test_err_growable_stack_too_deep() {
+    let config = Config {
+        dynamic_stack_frames: false,
+        initial_stack_frames: Some(1),
+        ..Config::default()
+    };
+    test_interpreter_and_jit_asm!(
+        "
+        mov64 r0, 0x1
+        lsh64 r0, 0x20
+        callx r0
+        exit",
+        config,
+        [],
+        (),
+        TestContextObject::new(60),
+        {
+            |_vm, res: ProgramResult<TestUserError>| {
+                matches!(res.unwrap_err(),
+                    EbpfError::CallDepthExceeded(pc, depth)
+                    if pc == 31 && depth == config.max_call_depth
+                )
+            }
+        },
+    );
+}
+
+#[test]
+fn test_err_reg_stack_depth() {
+    let config = Config::default();
+    test_interpreter_and_jit_asm!(
+        "
+        mov64 r0, 0x1
+        lsh64 r0, 0x20
+        callx r0
+        exit",
+        [],
+        (),
         TestContextObject::new(60),
         {
-            |_vm, res: ProgramResult| {
+            |_vm, res: ProgramResult<TestUserError>| {
                 matches!(res.unwrap_err(),
                     EbpfError::CallDepthExceeded(pc, depth)
                     if pc == 31 && depth == config.max_call_depth
@@ -2969,7 +4568,7 @@ fn test_call_save() {
         (
             0 => syscalls::trash_registers,
         ),
-        { |_vm, res: ProgramResult| { res.unwrap() == 0 } }
+        { |_vm, res: ProgramResult<TestUserError>| { res.unwrap() == 0 } }
     );
 }*/
 
@@ -2987,9 +4586,9 @@ fn test_err_syscall_string() {
         ),
         TestContextObject::new(2),
         {
-            |_vm, res: ProgramResult| {
+            |_vm, res: ProgramResult<TestUserError>| {
                 matches!(res.unwrap_err(),
-                    EbpfError::AccessViolation(pc, access_type, vm_addr, len, name)
+                    EbpfError::AccessViolation(pc, access_type, vm_addr, len, name, _)
                     if access_type == AccessType::Load && pc == 0 && vm_addr == 0 && len == 0 && name == "unknown"
                 )
             }
@@ -2997,6 +4596,46 @@ fn test_err_syscall_string() {
     );
 }
 
+#[test]
+fn test_err_syscall_user_error() {
+    #[allow(clippy::too_many_arguments)]
+    fn bpf_fail(
+        _context_object: &mut TestContextObject,
+        arg1: u64,
+        _arg2: u64,
+        _arg3: u64,
+        _arg4: u64,
+        _arg5: u64,
+        _memory_mapping: &mut MemoryMapping,
+        result: &mut ProgramResult<TestUserError>,
+    ) {
+        *result = ProgramResult::Err(EbpfError::UserError(TestUserError(format!(
+            "failed with {}",
+            arg1
+        ))));
+    }
+
+    test_interpreter_and_jit_asm!(
+        "
+        mov64 r1, 0x2a
+        syscall bpf_fail
+        exit",
+        [],
+        (
+            b"bpf_fail" => bpf_fail,
+        ),
+        TestContextObject::new(2),
+        {
+            |_vm, res: ProgramResult<TestUserError>| {
+                matches!(res.unwrap_err(),
+                    EbpfError::UserError(TestUserError(message))
+                    if message == "failed with 42"
+                )
+            }
+        },
+    );
+}
+
 #[test]
 fn test_syscall_string() {
     test_interpreter_and_jit_asm!(
@@ -3010,7 +4649,7 @@ fn test_syscall_string() {
             b"bpf_syscall_string" => syscalls::bpf_syscall_string,
         ),
         TestContextObject::new(4),
-        { |_vm, res: ProgramResult| { res.unwrap() == 0 } },
+        { |_vm, res: ProgramResult<TestUserError>| { res.unwrap() == 0 } },
     );
 }
 
@@ -3031,7 +4670,295 @@ fn test_syscall() {
             b"bpf_syscall_u64" => syscalls::bpf_syscall_u64,
         ),
         TestContextObject::new(8),
-        { |_vm, res: ProgramResult| { res.unwrap() == 0 } },
+        { |_vm, res: ProgramResult<TestUserError>| { res.unwrap() == 0 } },
+    );
+}
+
+#[test]
+fn test_replace_syscall_handler() {
+    let mut syscall_registry = SyscallRegistry::default();
+    test_interpreter_and_jit!(register, syscall_registry, b"bpf_syscall_u64" => syscalls::bpf_gather_bytes);
+    let executable = assemble::<TestContextObject>(
+        "
+        mov64 r1, 0x1
+        mov64 r2, 0x2
+        mov64 r3, 0x3
+        mov64 r4, 0x4
+        mov64 r5, 0x5
+        syscall bpf_syscall_u64
+        exit",
+        Config::default(),
+        syscall_registry,
+    )
+    .unwrap();
+    let mut verified_executable =
+        VerifiedExecutable::<RequisiteVerifier, _>::from_executable(executable).unwrap();
+    let run = |verified_executable: &VerifiedExecutable<RequisiteVerifier, TestContextObject>,
+               interpreted: bool| {
+        let mut context_object = TestContextObject::new(7);
+        let mut vm = EbpfVm::new(
+            verified_executable,
+            &mut context_object,
+            &mut [],
+            Vec::new(),
+        )
+        .unwrap();
+        vm.execute_program(interpreted).1
+    };
+    assert_eq!(run(&verified_executable, true).unwrap(), 0x0102030405);
+    #[cfg(all(not(windows), target_arch = "x86_64"))]
+    {
+        verified_executable.jit_compile().unwrap();
+        assert_eq!(run(&verified_executable, false).unwrap(), 0x0102030405);
+    }
+
+    let hash = ebpf::hash_symbol_name(b"bpf_syscall_u64");
+    verified_executable
+        .replace_syscall_handler(hash, syscalls::bpf_syscall_u64)
+        .unwrap();
+    assert!(matches!(
+        verified_executable.replace_syscall_handler(
+            ebpf::hash_symbol_name(b"bpf_syscall_string"),
+            syscalls::bpf_syscall_u64
+        ),
+        Err(EbpfError::SyscallNotRegistered(_))
+    ));
+    assert_eq!(run(&verified_executable, true).unwrap(), 0);
+    #[cfg(all(not(windows), target_arch = "x86_64"))]
+    {
+        assert!(matches!(
+            run(&verified_executable, false).unwrap_err(),
+            EbpfError::JitNotCompiled
+        ));
+        verified_executable.jit_compile().unwrap();
+        assert_eq!(run(&verified_executable, false).unwrap(), 0);
+    }
+}
+
+#[test]
+fn test_remaining_compute_units() {
+    // Loops until only the three instructions needed to exit are left
+    test_interpreter_and_jit_asm!(
+        "
+        mov64 r6, 0
+        add64 r6, 1
+        syscall bpf_remaining_compute_units
+        jgt r0, 3, -3
+        mov64 r0, r6
+        exit",
+        [],
+        (
+            b"bpf_remaining_compute_units" => syscalls::bpf_remaining_compute_units,
+        ),
+        TestContextObject::new(300),
+        { |_vm, res: ProgramResult<TestUserError>| { res.unwrap() == 99 } },
+    );
+}
+
+#[test]
+fn test_seal_syscalls() {
+    let config = Config {
+        enable_syscall_sealing: true,
+        ..Config::default()
+    };
+    test_interpreter_and_jit_asm!(
+        "
+        syscall bpf_gather_bytes
+        syscall bpf_seal_syscalls
+        syscall bpf_gather_bytes
+        exit",
+        config,
+        [],
+        (
+            b"bpf_gather_bytes" => syscalls::bpf_gather_bytes,
+            b"bpf_seal_syscalls" => syscalls::bpf_seal_syscalls,
+        ),
+        TestContextObject::new(3),
+        {
+            |vm: &EbpfVm<RequisiteVerifier, TestContextObject>, res: ProgramResult<TestUserError>| {
+                vm.context_object.syscalls_sealed
+                    && matches!(res.unwrap_err(), EbpfError::SyscallsSealed(pc) if pc == 31)
+            }
+        },
+    );
+
+    // Sealed by the host before the program runs
+    test_interpreter_and_jit_asm!(
+        "
+        mov64 r0, 1
+        syscall bpf_gather_bytes
+        exit",
+        config,
+        [],
+        (
+            b"bpf_gather_bytes" => syscalls::bpf_gather_bytes,
+        ),
+        TestContextObject {
+            syscalls_sealed: true,
+            ..TestContextObject::new(2)
+        },
+        { |_vm, res: ProgramResult<TestUserError>| matches!(res.unwrap_err(), EbpfError::SyscallsSealed(pc) if pc == 30) },
+    );
+
+    // Without enable_syscall_sealing the seal has no effect
+    test_interpreter_and_jit_asm!(
+        "
+        syscall bpf_seal_syscalls
+        syscall bpf_gather_bytes
+        exit",
+        [],
+        (
+            b"bpf_gather_bytes" => syscalls::bpf_gather_bytes,
+            b"bpf_seal_syscalls" => syscalls::bpf_seal_syscalls,
+        ),
+        TestContextObject::new(3),
+        { |_vm, res: ProgramResult<TestUserError>| { res.unwrap() == 0 } },
+    );
+}
+
+#[test]
+fn test_probe_read() {
+    test_interpreter_and_jit_asm!(
+        "
+        mov64 r3, r1
+        mov64 r1, r10
+        sub64 r1, 8
+        mov64 r2, 4
+        syscall bpf_probe_read
+        ldxw r6, [r10-8]
+        add64 r0, r6
+        exit",
+        [0x11, 0x22, 0x33, 0x44],
+        (
+            b"bpf_probe_read" => syscalls::bpf_probe_read,
+        ),
+        TestContextObject::new(8),
+        { |_vm, res: ProgramResult<TestUserError>| { res.unwrap() == 0x44332211 } },
+    );
+}
+
+#[test]
+fn test_probe_read_invalid_source() {
+    // Returns an error code and zero fills the destination
+    test_interpreter_and_jit_asm!(
+        "
+        mov64 r3, r1
+        add64 r3, 2
+        stw [r10-8], -1
+        mov64 r1, r10
+        sub64 r1, 8
+        mov64 r2, 4
+        syscall bpf_probe_read
+        ldxw r6, [r10-8]
+        add64 r0, r6
+        exit",
+        [0x11, 0x22, 0x33, 0x44],
+        (
+            b"bpf_probe_read" => syscalls::bpf_probe_read,
+        ),
+        TestContextObject::new(10),
+        { |_vm, res: ProgramResult<TestUserError>| { res.unwrap() == syscalls::BPF_PROBE_READ_EFAULT } },
+    );
+}
+
+#[test]
+fn test_err_probe_read_invalid_destination() {
+    test_interpreter_and_jit_asm!(
+        "
+        mov64 r3, r1
+        mov64 r1, 0
+        mov64 r2, 4
+        syscall bpf_probe_read
+        exit",
+        [0x11, 0x22, 0x33, 0x44],
+        (
+            b"bpf_probe_read" => syscalls::bpf_probe_read,
+        ),
+        TestContextObject::new(4),
+        {
+            |_vm, res: ProgramResult<TestUserError>| {
+                matches!(res.unwrap_err(),
+                    EbpfError::AccessViolation(_pc, access_type, vm_addr, len, _region, _nearest)
+                    if access_type == AccessType::Store && vm_addr == 0 && len == 4
+                )
+            }
+        },
+    );
+}
+
+#[test]
+fn test_hash_syscalls() {
+    test_interpreter_and_jit_asm!(
+        "
+        mov64 r3, r10
+        sub64 r3, 32
+        mov64 r2, 3
+        syscall bpf_sha256
+        ldxdw r0, [r10-32]
+        exit",
+        [b'a', b'b', b'c'],
+        (
+            b"bpf_sha256" => syscalls::bpf_sha256,
+        ),
+        TestContextObject::new(6),
+        { |_vm, res: ProgramResult<TestUserError>| { res.unwrap() == 0xeacf018fbf1678ba } },
+    );
+    test_interpreter_and_jit_asm!(
+        "
+        mov64 r3, r10
+        sub64 r3, 32
+        mov64 r2, 3
+        syscall bpf_keccak256
+        ldxdw r0, [r10-32]
+        exit",
+        [b'a', b'b', b'c'],
+        (
+            b"bpf_keccak256" => syscalls::bpf_keccak256,
+        ),
+        TestContextObject::new(6),
+        { |_vm, res: ProgramResult<TestUserError>| { res.unwrap() == 0x4fa945ea7a65034e } },
+    );
+    // The output may overlap with the input
+    test_interpreter_and_jit_asm!(
+        "
+        stw [r10-32], 0x636261
+        mov64 r1, r10
+        sub64 r1, 32
+        mov64 r3, r1
+        mov64 r2, 3
+        syscall bpf_sha256
+        ldxdw r0, [r10-32]
+        exit",
+        [],
+        (
+            b"bpf_sha256" => syscalls::bpf_sha256,
+        ),
+        TestContextObject::new(8),
+        { |_vm, res: ProgramResult<TestUserError>| { res.unwrap() == 0xeacf018fbf1678ba } },
+    );
+}
+
+#[test]
+fn test_err_hash_syscall_invalid_output() {
+    test_interpreter_and_jit_asm!(
+        "
+        mov64 r3, 0
+        mov64 r2, 3
+        syscall bpf_sha256
+        exit",
+        [b'a', b'b', b'c'],
+        (
+            b"bpf_sha256" => syscalls::bpf_sha256,
+        ),
+        TestContextObject::new(3),
+        {
+            |_vm, res: ProgramResult<TestUserError>| {
+                matches!(res.unwrap_err(),
+                    EbpfError::AccessViolation(_pc, access_type, vm_addr, len, _region, _nearest)
+                    if access_type == AccessType::Store && vm_addr == 0 && len == 32
+                )
+            }
+        },
     );
 }
 
@@ -3051,7 +4978,7 @@ fn test_call_gather_bytes() {
             b"bpf_gather_bytes" => syscalls::bpf_gather_bytes,
         ),
         TestContextObject::new(7),
-        { |_vm, res: ProgramResult| { res.unwrap() == 0x0102030405 } },
+        { |_vm, res: ProgramResult<TestUserError>| { res.unwrap() == 0x0102030405 } },
     );
 }
 
@@ -3073,7 +5000,7 @@ fn test_call_memfrob() {
             b"bpf_mem_frob" => syscalls::bpf_mem_frob,
         ),
         TestContextObject::new(7),
-        { |_vm, res: ProgramResult| { res.unwrap() == 0x102292e2f2c0708 } },
+        { |_vm, res: ProgramResult<TestUserError>| { res.unwrap() == 0x102292e2f2c0708 } },
     );
 }
 
@@ -3086,7 +5013,7 @@ fn nested_vm_syscall(
     _arg4: u64,
     _arg5: u64,
     _memory_mapping: &mut MemoryMapping,
-    result: &mut ProgramResult,
+    result: &mut ProgramResult<TestUserError>,
 ) {
     #[allow(unused_mut)]
     if depth > 0 {
@@ -3110,7 +5037,7 @@ fn nested_vm_syscall(
             mem,
             TestContextObject::new(if throw == 0 { 4 } else { 3 }),
             {
-                |_vm, res: ProgramResult| {
+                |_vm, res: ProgramResult<TestUserError>| {
                     *result = res;
                     true
                 }
@@ -3129,7 +5056,7 @@ fn nested_vm_syscall(
 fn test_nested_vm_syscall() {
     let config = Config::default();
     let mut context_object = TestContextObject::default();
-    let mut memory_mapping = MemoryMapping::new(vec![], &config).unwrap();
+    let mut memory_mapping = MemoryMapping::new::<TestUserError>(vec![], &config).unwrap();
     let mut result = ProgramResult::Ok(0);
     nested_vm_syscall(
         &mut context_object,
@@ -3171,7 +5098,7 @@ fn test_load_elf() {
             b"log_64" => syscalls::bpf_syscall_u64,
         ),
         TestContextObject::new(11),
-        { |_vm, res: ProgramResult| { res.unwrap() == 0 } },
+        { |_vm, res: ProgramResult<TestUserError>| { res.unwrap() == 0 } },
     );
 }
 
@@ -3184,7 +5111,7 @@ fn test_load_elf_empty_noro() {
             b"log_64" => syscalls::bpf_syscall_u64,
         ),
         TestContextObject::new(8),
-        { |_vm, res: ProgramResult| { res.unwrap() == 0 } },
+        { |_vm, res: ProgramResult<TestUserError>| { res.unwrap() == 0 } },
     );
 }
 
@@ -3197,7 +5124,7 @@ fn test_load_elf_empty_rodata() {
             b"log_64" => syscalls::bpf_syscall_u64,
         ),
         TestContextObject::new(8),
-        { |_vm, res: ProgramResult| { res.unwrap() == 0 } },
+        { |_vm, res: ProgramResult<TestUserError>| { res.unwrap() == 0 } },
     );
 }
 
@@ -3216,7 +5143,7 @@ fn test_load_elf_rodata() {
             [],
             (),
             TestContextObject::new(3),
-            { |_vm, res: ProgramResult| { res.unwrap() == 42 } },
+            { |_vm, res: ProgramResult<TestUserError>| { res.unwrap() == 42 } },
         );
     }
 }
@@ -3228,7 +5155,7 @@ fn test_load_elf_rodata_high_vaddr() {
         [1],
         (),
         TestContextObject::new(3),
-        { |_vm, res: ProgramResult| { res.unwrap() == 42 } },
+        { |_vm, res: ProgramResult<TestUserError>| { res.unwrap() == 42 } },
     );
 }
 
@@ -3250,7 +5177,7 @@ fn test_custom_entrypoint() {
     let mut executable =
         Executable::<TestContextObject>::from_elf(&elf, config, syscall_registry).unwrap();
     test_interpreter_and_jit!(executable, [], TestContextObject::new(2), {
-        |_vm, res: ProgramResult| res.unwrap() == 0
+        |_vm, res: ProgramResult<TestUserError>| res.unwrap() == 0
     });
 }
 
@@ -3266,7 +5193,7 @@ fn test_tight_infinite_loop_conditional() {
         (),
         TestContextObject::new(4),
         {
-            |_vm, res: ProgramResult| {
+            |_vm, res: ProgramResult<TestUserError>| {
                 matches!(res.unwrap_err(),
                     EbpfError::ExceededMaxInstructions(pc, initial_insn_count)
                     if pc == 30 && initial_insn_count == 4
@@ -3286,7 +5213,7 @@ fn test_tight_infinite_loop_unconditional() {
         (),
         TestContextObject::new(4),
         {
-            |_vm, res: ProgramResult| {
+            |_vm, res: ProgramResult<TestUserError>| {
                 matches!(res.unwrap_err(),
                     EbpfError::ExceededMaxInstructions(pc, initial_insn_count)
                     if pc == 30 && initial_insn_count == 4
@@ -3308,7 +5235,7 @@ fn test_tight_infinite_recursion() {
         (),
         TestContextObject::new(4),
         {
-            |_vm, res: ProgramResult| {
+            |_vm, res: ProgramResult<TestUserError>| {
                 matches!(res.unwrap_err(),
                     EbpfError::ExceededMaxInstructions(pc, initial_insn_count)
                     if pc == 31 && initial_insn_count == 4
@@ -3333,7 +5260,7 @@ fn test_tight_infinite_recursion_callx() {
         (),
         TestContextObject::new(7),
         {
-            |_vm, res: ProgramResult| {
+            |_vm, res: ProgramResult<TestUserError>| {
                 matches!(res.unwrap_err(),
                     EbpfError::ExceededMaxInstructions(pc, initial_insn_count)
                     if pc == 34 && initial_insn_count == 7
@@ -3344,37 +5271,128 @@ fn test_tight_infinite_recursion_callx() {
 }
 
 #[test]
-fn test_instruction_count_syscall() {
-    test_interpreter_and_jit_asm!(
+fn test_max_instructions() {
+    let config = Config {
+        max_instructions: 6,
+        ..Config::default()
+    };
+    let mut syscall_registry = SyscallRegistry::default();
+    syscall_registry
+        .register_syscall_by_name(b"bpf_syscall_string", syscalls::bpf_syscall_string)
+        .unwrap();
+    let executable = assemble::<TestContextObject>(
         "
         mov64 r2, 0x5
         syscall bpf_syscall_string
-        mov64 r0, 0x0
-        exit",
-        [72, 101, 108, 108, 111],
-        (
-            b"bpf_syscall_string" => syscalls::bpf_syscall_string,
-        ),
-        TestContextObject::new(4),
-        { |_vm, res: ProgramResult| { res.unwrap() == 0 } },
-    );
-}
-
-#[test]
-fn test_err_instruction_count_syscall_capped() {
-    test_interpreter_and_jit_asm!(
-        "
-        mov64 r2, 0x5
-        call 0
-        mov64 r0, 0x0
+        ja -1
         exit",
-        [72, 101, 108, 108, 111],
-        (
-            b"bpf_syscall_string" => syscalls::bpf_syscall_string,
-        ),
-        TestContextObject::new(3),
+        config,
+        syscall_registry,
+    )
+    .unwrap();
+    #[allow(unused_mut)]
+    let mut verified_executable =
+        VerifiedExecutable::<RequisiteVerifier, _>::from_executable(executable).unwrap();
+    #[cfg(all(not(windows), target_arch = "x86_64"))]
+    verified_executable.jit_compile().unwrap();
+    let mut modes = vec![true];
+    #[cfg(all(not(windows), target_arch = "x86_64"))]
+    modes.push(false);
+    // The binding limit is the config ceiling for the larger budget and the budget otherwise
+    for (budget, limit) in [(100, 6), (5, 5)] {
+        for interpreted in modes.iter() {
+            let mut mem = [72, 101, 108, 108, 111];
+            let mem_region = MemoryRegion::new_writable(&mut mem, ebpf::MM_INPUT_START);
+            let mut context_object = TestContextObject::new(budget);
+            let mut vm = EbpfVm::new(
+                &verified_executable,
+                &mut context_object,
+                &mut [],
+                vec![mem_region],
+            )
+            .unwrap();
+            let (instruction_count, result) = vm.execute_program(*interpreted);
+            assert!(matches!(
+                result.unwrap_err(),
+                EbpfError::ExceededMaxInstructions(pc, initial_insn_count)
+                if pc == 32 && initial_insn_count == limit
+            ));
+            assert_eq!(instruction_count, limit);
+            assert_eq!(vm.context_object.get_remaining(), budget - limit);
+        }
+    }
+    // An instruction costing more than the ceiling does not consume beyond it
+    let config = Config {
+        max_instructions: 3,
+        ..Config::default()
+    };
+    let mut executable = assemble::<TestContextObject>(
+        "
+        mov64 r0, 3
+        mul64 r0, 5
+        exit",
+        config,
+        SyscallRegistry::default(),
+    )
+    .unwrap();
+    executable.set_cost_model(&TestCostModel).unwrap();
+    #[allow(unused_mut)]
+    let mut verified_executable =
+        VerifiedExecutable::<RequisiteVerifier, _>::from_executable(executable).unwrap();
+    #[cfg(all(not(windows), target_arch = "x86_64"))]
+    verified_executable.jit_compile().unwrap();
+    for interpreted in modes.iter() {
+        let mut context_object = TestContextObject::new(100);
+        let mut vm = EbpfVm::new(
+            &verified_executable,
+            &mut context_object,
+            &mut [],
+            Vec::new(),
+        )
+        .unwrap();
+        let (instruction_count, result) = vm.execute_program(*interpreted);
+        assert!(matches!(
+            result.unwrap_err(),
+            EbpfError::ExceededMaxInstructions(pc, initial_insn_count)
+            if pc == 31 && initial_insn_count == 3
+        ));
+        assert_eq!(instruction_count, 3);
+        assert_eq!(vm.context_object.get_remaining(), 97);
+    }
+}
+
+#[test]
+fn test_instruction_count_syscall() {
+    test_interpreter_and_jit_asm!(
+        "
+        mov64 r2, 0x5
+        syscall bpf_syscall_string
+        mov64 r0, 0x0
+        exit",
+        [72, 101, 108, 108, 111],
+        (
+            b"bpf_syscall_string" => syscalls::bpf_syscall_string,
+        ),
+        TestContextObject::new(4),
+        { |_vm, res: ProgramResult<TestUserError>| { res.unwrap() == 0 } },
+    );
+}
+
+#[test]
+fn test_err_instruction_count_syscall_capped() {
+    test_interpreter_and_jit_asm!(
+        "
+        mov64 r2, 0x5
+        call 0
+        mov64 r0, 0x0
+        exit",
+        [72, 101, 108, 108, 111],
+        (
+            b"bpf_syscall_string" => syscalls::bpf_syscall_string,
+        ),
+        TestContextObject::new(3),
         {
-            |_vm, res: ProgramResult| {
+            |_vm, res: ProgramResult<TestUserError>| {
                 matches!(res.unwrap_err(),
                     EbpfError::ExceededMaxInstructions(pc, initial_insn_count)
                     if pc == 32 && initial_insn_count == 3
@@ -3397,7 +5415,7 @@ fn test_err_instruction_count_lddw_capped() {
         (),
         TestContextObject::new(2),
         {
-            |_vm, res: ProgramResult| {
+            |_vm, res: ProgramResult<TestUserError>| {
                 matches!(res.unwrap_err(),
                     EbpfError::ExceededMaxInstructions(pc, initial_insn_count)
                     if pc == 32 && initial_insn_count == 2
@@ -3407,6 +5425,139 @@ fn test_err_instruction_count_lddw_capped() {
     );
 }
 
+struct TestCostModel;
+
+impl CostModel for TestCostModel {
+    fn cost(&self, insn: &ebpf::Insn) -> u64 {
+        match insn.opc {
+            ebpf::MUL64_IMM => 4,
+            ebpf::CALL_IMM => 10,
+            ebpf::JA => 0,
+            _ => 1,
+        }
+    }
+}
+
+macro_rules! test_interpreter_and_jit_asm_with_cost_model {
+    ($source:tt, ($($location:expr => $syscall_function:expr),* $(,)?), $context_object:expr, $check:block $(,)?) => {
+        #[allow(unused_mut)]
+        {
+            let config = Config {
+                enable_instruction_tracing: true,
+                ..Config::default()
+            };
+            let mut syscall_registry = SyscallRegistry::default();
+            $(test_interpreter_and_jit!(register, syscall_registry, $location => $syscall_function);)*
+            let mut executable = assemble($source, config, syscall_registry).unwrap();
+            executable.set_cost_model(&TestCostModel).unwrap();
+            test_interpreter_and_jit!(executable, [72, 101, 108, 108, 111], $context_object, $check);
+        }
+    };
+}
+
+#[test]
+fn test_instruction_cost_model() {
+    test_interpreter_and_jit_asm_with_cost_model!(
+        "
+        mov64 r0, 3
+        mul64 r0, 5
+        ja +0
+        lddw r1, 0x1
+        add64 r0, r1
+        exit",
+        (),
+        TestContextObject::new(8),
+        { |_vm, res: ProgramResult<TestUserError>| { res.unwrap() == 16 } },
+    );
+    test_interpreter_and_jit_asm_with_cost_model!(
+        "
+        mov64 r2, 0x5
+        syscall bpf_syscall_string
+        mov64 r0, 0x0
+        exit",
+        (
+            b"bpf_syscall_string" => syscalls::bpf_syscall_string,
+        ),
+        TestContextObject::new(13),
+        { |_vm, res: ProgramResult<TestUserError>| { res.unwrap() == 0 } },
+    );
+}
+
+#[test]
+fn test_err_instruction_cost_too_large() {
+    struct ExpensiveMulCostModel;
+    impl CostModel for ExpensiveMulCostModel {
+        fn cost(&self, insn: &ebpf::Insn) -> u64 {
+            if insn.opc == ebpf::MUL64_IMM {
+                i32::MAX as u64
+            } else {
+                1
+            }
+        }
+    }
+
+    let mut executable = assemble::<TestContextObject>(
+        "
+        mov64 r0, 3
+        mul64 r0, 5
+        exit",
+        Config::default(),
+        SyscallRegistry::default(),
+    )
+    .unwrap();
+    assert!(matches!(
+        executable.set_cost_model(&ExpensiveMulCostModel),
+        Err(EbpfError::InstructionCostTooLarge(pc, cost)) if pc == 30 && cost == i32::MAX as u64
+    ));
+    assert_eq!(executable.get_instruction_cost(1), 1);
+}
+
+#[test]
+fn test_err_instruction_cost_model_exceeded() {
+    for remaining in [4, 5] {
+        test_interpreter_and_jit_asm_with_cost_model!(
+            "
+            mov64 r0, 3
+            mul64 r0, 5
+            ja +0
+            lddw r1, 0x1
+            add64 r0, r1
+            exit",
+            (),
+            TestContextObject::new(remaining),
+            {
+                |_vm, res: ProgramResult<TestUserError>| {
+                    matches!(res.unwrap_err(),
+                        EbpfError::ExceededMaxInstructions(pc, initial_insn_count)
+                        if pc == 31 && initial_insn_count == remaining
+                    )
+                }
+            },
+        );
+    }
+    for remaining in [2, 6, 11] {
+        test_interpreter_and_jit_asm_with_cost_model!(
+            "
+            mov64 r2, 0x5
+            syscall bpf_syscall_string
+            mov64 r0, 0x0
+            exit",
+            (
+                b"bpf_syscall_string" => syscalls::bpf_syscall_string,
+            ),
+            TestContextObject::new(remaining),
+            {
+                |_vm, res: ProgramResult<TestUserError>| {
+                    matches!(res.unwrap_err(),
+                        EbpfError::ExceededMaxInstructions(pc, initial_insn_count)
+                        if pc == 31 && initial_insn_count == remaining
+                    )
+                }
+            },
+        );
+    }
+}
+
 #[test]
 fn test_non_terminate_early() {
     test_interpreter_and_jit_asm!(
@@ -3425,7 +5576,7 @@ fn test_non_terminate_early() {
         (),
         TestContextObject::new(7),
         {
-            |_vm, res: ProgramResult| {
+            |_vm, res: ProgramResult<TestUserError>| {
                 matches!(res.unwrap_err(),
                     EbpfError::UnsupportedInstruction(pc)
                     if pc == 35
@@ -3455,7 +5606,7 @@ fn test_err_non_terminate_capped() {
         ),
         TestContextObject::new(6),
         {
-            |_vm, res: ProgramResult| {
+            |_vm, res: ProgramResult<TestUserError>| {
                 matches!(res.unwrap_err(),
                     EbpfError::ExceededMaxInstructions(pc, initial_insn_count)
                     if pc == 35 && initial_insn_count == 6
@@ -3481,7 +5632,7 @@ fn test_err_non_terminate_capped() {
         ),
         TestContextObject::new(1000),
         {
-            |_vm, res: ProgramResult| {
+            |_vm, res: ProgramResult<TestUserError>| {
                 matches!(res.unwrap_err(),
                     EbpfError::ExceededMaxInstructions(pc, initial_insn_count)
                     if pc == 37 && initial_insn_count == 1000
@@ -3506,7 +5657,7 @@ fn test_err_capped_before_exception() {
         (),
         TestContextObject::new(2),
         {
-            |_vm, res: ProgramResult| {
+            |_vm, res: ProgramResult<TestUserError>| {
                 matches!(res.unwrap_err(),
                     EbpfError::ExceededMaxInstructions(pc, initial_insn_count)
                     if pc == 31 && initial_insn_count == 2
@@ -3527,7 +5678,7 @@ fn test_err_capped_before_exception() {
         (),
         TestContextObject::new(4),
         {
-            |_vm, res: ProgramResult| {
+            |_vm, res: ProgramResult<TestUserError>| {
                 matches!(res.unwrap_err(),
                     EbpfError::ExceededMaxInstructions(pc, initial_insn_count)
                     if pc == 33 && initial_insn_count == 4
@@ -3552,7 +5703,7 @@ fn test_err_exit_capped() {
         (),
         TestContextObject::new(5),
         {
-            |_vm, res: ProgramResult| {
+            |_vm, res: ProgramResult<TestUserError>| {
                 matches!(res.unwrap_err(),
                     EbpfError::ExceededMaxInstructions(pc, initial_insn_count) if pc == 34 && initial_insn_count == 5
                 )
@@ -3573,7 +5724,7 @@ fn test_err_exit_capped() {
         (),
         TestContextObject::new(6),
         {
-            |_vm, res: ProgramResult| {
+            |_vm, res: ProgramResult<TestUserError>| {
                 matches!(res.unwrap_err(),
                     EbpfError::ExceededMaxInstructions(pc, initial_insn_count) if pc == 35 && initial_insn_count == 6
                 )
@@ -3590,7 +5741,7 @@ fn test_err_exit_capped() {
         (),
         TestContextObject::new(3),
         {
-            |_vm, res: ProgramResult| {
+            |_vm, res: ProgramResult<TestUserError>| {
                 matches!(res.unwrap_err(),
                     EbpfError::ExceededMaxInstructions(pc, initial_insn_count) if pc == 32 && initial_insn_count == 3
                 )
@@ -3616,7 +5767,7 @@ fn test_symbol_relocation() {
             b"bpf_syscall_string" => syscalls::bpf_syscall_string
         ),
         TestContextObject::new(6),
-        { |_vm, res: ProgramResult| { res.unwrap() == 0 } },
+        { |_vm, res: ProgramResult<TestUserError>| { res.unwrap() == 0 } },
     );
 }
 
@@ -3636,24 +5787,138 @@ fn test_err_call_unresolved() {
         (),
         TestContextObject::new(6),
         {
-            |_vm, res: ProgramResult| matches!(res.unwrap_err(), EbpfError::UnsupportedInstruction(pc) if pc == 34)
+            |_vm, res: ProgramResult<TestUserError>| matches!(res.unwrap_err(), EbpfError::UnsupportedInstruction(pc) if pc == 34)
         },
     );
 }
 
 #[test]
 fn test_err_unresolved_elf() {
+    let syscall_registry = || {
+        let mut syscall_registry = SyscallRegistry::default();
+        test_interpreter_and_jit!(register, syscall_registry, b"log" => syscalls::bpf_syscall_string);
+        syscall_registry
+    };
+    let mut file = File::open("tests/elfs/unresolved_syscall.so").unwrap();
+    let mut elf = Vec::new();
+    file.read_to_end(&mut elf).unwrap();
+    let config = Config {
+        reject_broken_elfs: true,
+        ..Config::default()
+    };
+    assert!(
+        matches!(Executable::<TestContextObject>::load(config, &elf, syscall_registry()), Err(ElfError::UnresolvedSymbol(symbol, pc, offset)) if symbol == "log_64" && pc == 550 && offset == 4168)
+    );
+    assert!(
+        matches!(Executable::<TestContextObject>::from_elf(&elf, config, syscall_registry()), Err(EbpfError::SyscallNotRegistered(symbol)) if symbol == "log_64")
+    );
+}
+
+#[test]
+fn test_weak_unresolved_syscall() {
     let mut syscall_registry = SyscallRegistry::default();
     test_interpreter_and_jit!(register, syscall_registry, b"log" => syscalls::bpf_syscall_string);
     let mut file = File::open("tests/elfs/unresolved_syscall.so").unwrap();
     let mut elf = Vec::new();
     file.read_to_end(&mut elf).unwrap();
+    elf[0x1fc] = 0x20; // Change the binding of the log_64 dynamic symbol to STB_WEAK
     let config = Config {
         reject_broken_elfs: true,
         ..Config::default()
     };
-    assert!(
-        matches!(Executable::<TestContextObject>::from_elf(&elf, config, syscall_registry), Err(EbpfError::ElfError(ElfError::UnresolvedSymbol(symbol, pc, offset))) if symbol == "log_64" && pc == 550 && offset == 4168)
+    #[allow(unused_mut)]
+    let mut executable =
+        Executable::<TestContextObject>::from_elf(&elf, config, syscall_registry).unwrap();
+    let (_program_vm_addr, text_bytes) = executable.get_text_bytes();
+    assert_eq!(
+        ebpf::get_insn(text_bytes, 3).imm as u32,
+        ebpf::hash_symbol_name(b"log")
+    );
+    assert_eq!(ebpf::get_insn(text_bytes, 9).imm, 0);
+    test_interpreter_and_jit!(executable, [], TestContextObject::new(9), {
+        |_vm, res: ProgramResult<TestUserError>| matches!(res.unwrap_err(), EbpfError::UnsupportedInstruction(pc) if pc == 38)
+    });
+}
+
+#[test]
+fn test_syscall_bound_by_relocation() {
+    let mut syscall_registry = SyscallRegistry::default();
+    test_interpreter_and_jit!(register, syscall_registry, b"log" => syscalls::bpf_syscall_string);
+    test_interpreter_and_jit!(register, syscall_registry, b"log_u64" => syscalls::bpf_syscall_u64);
+    let mut file = File::open("tests/elfs/unresolved_syscall.so").unwrap();
+    let mut elf = Vec::new();
+    file.read_to_end(&mut elf).unwrap();
+    let executable =
+        Executable::<TestContextObject>::from_elf(&elf, Config::default(), syscall_registry)
+            .unwrap();
+    let syscall_registry = executable.get_syscall_registry();
+    assert!(syscall_registry
+        .lookup_syscall(ebpf::hash_symbol_name(b"log"))
+        .is_some());
+    assert!(syscall_registry
+        .lookup_syscall(ebpf::hash_symbol_name(b"log_u64"))
+        .is_none());
+    assert!(syscall_registry.contains_syscall(ebpf::hash_symbol_name(b"log_u64")));
+}
+
+#[test]
+fn test_syscall_symbol_prefix() {
+    let config = Config {
+        reject_broken_elfs: true,
+        syscall_symbol_prefix: Some("log_"),
+        ..Config::default()
+    };
+    let mut file = File::open("tests/elfs/unresolved_syscall.so").unwrap();
+    let mut elf = Vec::new();
+    file.read_to_end(&mut elf).unwrap();
+
+    // "log" does not carry the prefix and is bound unchanged, "log_64" is bound as "64"
+    let mut syscall_registry = SyscallRegistry::default();
+    test_interpreter_and_jit!(register, syscall_registry, b"log" => syscalls::bpf_syscall_string);
+    test_interpreter_and_jit!(register, syscall_registry, b"64" => syscalls::bpf_syscall_u64);
+    let executable =
+        Executable::<TestContextObject>::from_elf(&elf, config, syscall_registry).unwrap();
+    let syscall_registry = executable.get_syscall_registry();
+    assert!(syscall_registry
+        .lookup_syscall(ebpf::hash_symbol_name(b"log"))
+        .is_some());
+    assert!(syscall_registry
+        .lookup_syscall(ebpf::hash_symbol_name(b"64"))
+        .is_some());
+
+    // The diagnostics report the symbol name as found in the ELF
+    let mut syscall_registry = SyscallRegistry::default();
+    test_interpreter_and_jit!(register, syscall_registry, b"log" => syscalls::bpf_syscall_string);
+    test_interpreter_and_jit!(register, syscall_registry, b"log_64" => syscalls::bpf_syscall_u64);
+    assert!(matches!(
+        Executable::<TestContextObject>::from_elf(&elf, config, syscall_registry),
+        Err(EbpfError::SyscallNotRegistered(symbol)) if symbol == "log_64"
+    ));
+}
+
+#[test]
+fn test_syscall_set_region_readonly() {
+    test_interpreter_and_jit_asm!(
+        "
+        mov64 r6, r1
+        stb [r6], 1
+        syscall bpf_set_region_readonly
+        ldxb r0, [r6]
+        stb [r6], 2
+        exit",
+        [0],
+        (
+            b"bpf_set_region_readonly" => syscalls::bpf_set_region_readonly,
+        ),
+        TestContextObject::new(5),
+        {
+            |_vm, res: ProgramResult<TestUserError>| {
+                matches!(res.unwrap_err(),
+                    EbpfError::AccessViolation(pc, AccessType::Store, vm_addr, 1, "input", _)
+                    if pc == 33 && vm_addr == ebpf::MM_INPUT_START
+                )
+            }
+        },
     );
 }
 
@@ -3666,7 +5931,7 @@ fn test_syscall_static() {
             b"log" => syscalls::bpf_syscall_string,
         ),
         TestContextObject::new(5),
-        { |_vm, res: ProgramResult| { res.unwrap() == 0 } },
+        { |_vm, res: ProgramResult<TestUserError>| { res.unwrap() == 0 } },
     );
 }
 
@@ -3683,7 +5948,7 @@ fn test_syscall_unknown_static() {
             b"log" => syscalls::bpf_syscall_string,
         ),
         TestContextObject::new(1),
-        { |_vm, res: ProgramResult| { matches!(res.unwrap_err(), EbpfError::UnsupportedInstruction(29)) } },
+        { |_vm, res: ProgramResult<TestUserError>| { matches!(res.unwrap_err(), EbpfError::UnsupportedInstruction(29)) } },
     );
 }
 
@@ -3697,7 +5962,7 @@ fn test_reloc_64_64() {
         [],
         (),
         TestContextObject::new(2),
-        { |_vm, res: ProgramResult| { res.unwrap() == ebpf::MM_PROGRAM_START + 0xe8 } },
+        { |_vm, res: ProgramResult<TestUserError>| res.unwrap() == ebpf::MM_PROGRAM_START + 0xe8 },
     );
 }
 
@@ -3710,7 +5975,7 @@ fn test_reloc_64_64_high_vaddr() {
         [],
         (),
         TestContextObject::new(2),
-        { |_vm, res: ProgramResult| { res.unwrap() == ebpf::MM_PROGRAM_START } },
+        { |_vm, res: ProgramResult<TestUserError>| { res.unwrap() == ebpf::MM_PROGRAM_START } },
     );
 }
 
@@ -3725,7 +5990,7 @@ fn test_reloc_64_relative() {
         [],
         (),
         TestContextObject::new(2),
-        { |_vm, res: ProgramResult| { res.unwrap() == ebpf::MM_PROGRAM_START + 0x100 } },
+        { |_vm, res: ProgramResult<TestUserError>| res.unwrap() == ebpf::MM_PROGRAM_START + 0x100 },
     );
 }
 
@@ -3740,7 +6005,7 @@ fn test_reloc_64_relative_high_vaddr() {
         [],
         (),
         TestContextObject::new(2),
-        { |_vm, res: ProgramResult| { res.unwrap() == ebpf::MM_PROGRAM_START + 0x18 } },
+        { |_vm, res: ProgramResult<TestUserError>| res.unwrap() == ebpf::MM_PROGRAM_START + 0x18 },
     );
 }
 
@@ -3758,7 +6023,7 @@ fn test_reloc_64_relative_data() {
         [],
         (),
         TestContextObject::new(3),
-        { |_vm, res: ProgramResult| { res.unwrap() == ebpf::MM_PROGRAM_START + 0x108 } },
+        { |_vm, res: ProgramResult<TestUserError>| res.unwrap() == ebpf::MM_PROGRAM_START + 0x108 },
     );
 }
 
@@ -3776,66 +6041,245 @@ fn test_reloc_64_relative_data_high_vaddr() {
         [],
         (),
         TestContextObject::new(3),
-        { |_vm, res: ProgramResult| { res.unwrap() == ebpf::MM_PROGRAM_START + 0x20 } },
+        { |_vm, res: ProgramResult<TestUserError>| res.unwrap() == ebpf::MM_PROGRAM_START + 0x20 },
     );
 }
 
 #[test]
-fn test_reloc_64_relative_data_pre_sbfv2() {
-    // Before https://github.com/solana-labs/llvm-project/pull/35, we used to
-    // generate invalid R_BPF_64_RELATIVE relocations in sections other than
-    // .text.
-    //
-    // This test checks that the old behaviour is maintained for backwards
-    // compatibility when dealing with non-sbfv2 files. See also Elf::relocate().
-    //
-    // The program returns the address of the first .rodata byte.
-    // [ 1] .text             PROGBITS        00000000000000e8 0000e8 000020 00  AX  0   0  8
-    // [ 2] .rodata           PROGBITS        0000000000000108 000108 000019 01 AMS  0   0  1
-    //
-    // 00000000000001f8 <FILE>:
-    // 63:       00 00 00 00 08 01 00 00
+fn test_custom_region_vaddr_bases() {
+    let config = Config {
+        aligned_memory_mapping: false,
+        rodata_vaddr_base: 0x500000000,
+        heap_vaddr_base: 0x600000000,
+        ..Config::default()
+    };
+
+    // Relocated addresses follow the rodata base, in the text and in the data sections
     test_interpreter_and_jit_elf!(
-        "tests/elfs/reloc_64_relative_data_pre_sbfv2.so",
+        "tests/elfs/reloc_64_relative_data.so",
+        config,
         [],
         (),
         TestContextObject::new(3),
-        { |_vm, res: ProgramResult| { res.unwrap() == ebpf::MM_PROGRAM_START + 0x108 } },
+        { |_vm, res: ProgramResult<TestUserError>| res.unwrap() == 0x500000000 + 0x108 },
     );
-}
-
-// Programs
-
-#[test]
-fn test_mul_loop() {
-    test_interpreter_and_jit_asm!(
-        "
-        mov r0, 0x7
-        add r1, 0xa
-        lsh r1, 0x20
-        rsh r1, 0x20
-        jeq r1, 0x0, +4
-        mov r0, 0x7
-        mul r0, 0x7
-        add r1, -1
-        jne r1, 0x0, -3
-        exit",
+    test_interpreter_and_jit_elf!(
+        "tests/elfs/reloc_64_relative_data_high_vaddr.so",
+        config,
         [],
         (),
-        TestContextObject::new(37),
-        { |_vm, res: ProgramResult| { res.unwrap() == 0x75db9c97 } },
+        TestContextObject::new(3),
+        { |_vm, res: ProgramResult<TestUserError>| res.unwrap() == 0x500000000 + 0x20 },
+    );
+    test_interpreter_and_jit_elf!(
+        "tests/elfs/rodata.so",
+        config,
+        [],
+        (),
+        TestContextObject::new(3),
+        { |_vm, res: ProgramResult<TestUserError>| { res.unwrap() == 42 } },
     );
-}
 
-#[test]
-fn test_prime() {
-    test_interpreter_and_jit_asm!(
+    // The heap is mapped at its base
+    let executable = assemble::<TestContextObject>(
         "
-        mov r1, 67
-        mov r0, 0x1
-        mov r2, 0x2
-        jgt r1, 0x2, +4
-        ja +10
+        lddw r1, 0x600000000
+        stw [r1+4], 0x2A
+        ldxw r0, [r1+4]
+        exit",
+        config,
+        SyscallRegistry::default(),
+    )
+    .unwrap();
+    let verified_executable =
+        VerifiedExecutable::<RequisiteVerifier, _>::from_executable(executable).unwrap();
+    let mut heap = [0u8; 8];
+    let mut context_object = TestContextObject::new(4);
+    let mut vm = EbpfVm::new(
+        &verified_executable,
+        &mut context_object,
+        &mut heap,
+        Vec::new(),
+    )
+    .unwrap();
+    assert_eq!(vm.execute_program(true).1.unwrap(), 42);
+    assert_eq!(heap[4], 42);
+
+    // A rodata base which overlaps the heap is rejected
+    let config = Config {
+        aligned_memory_mapping: false,
+        rodata_vaddr_base: ebpf::MM_HEAP_START,
+        ..Config::default()
+    };
+    let mut file = File::open("tests/elfs/rodata.so").unwrap();
+    let mut elf = Vec::new();
+    file.read_to_end(&mut elf).unwrap();
+    let executable =
+        Executable::<TestContextObject>::from_elf(&elf, config, SyscallRegistry::default())
+            .unwrap();
+    let verified_executable =
+        VerifiedExecutable::<RequisiteVerifier, _>::from_executable(executable).unwrap();
+    let mut heap = [0u8; 0x1000];
+    let mut context_object = TestContextObject::new(3);
+    assert!(matches!(
+        EbpfVm::new(
+            &verified_executable,
+            &mut context_object,
+            &mut heap,
+            Vec::new(),
+        ),
+        Err(EbpfError::InvalidMemoryRegion(_))
+    ));
+}
+
+#[test]
+fn test_reloc_64_relative_data_sealed() {
+    // .data.rel.ro is relocated while loading and read-only afterwards
+    // [ 4] .data.rel.ro      PROGBITS        00000000000001f8 0001f8 000008 00  WA  0   0  8
+    let mut file = File::open("tests/elfs/reloc_64_relative_data.so").unwrap();
+    let mut elf = Vec::new();
+    file.read_to_end(&mut elf).unwrap();
+    let executable = Executable::<TestContextObject>::from_elf(
+        &elf,
+        Config::default(),
+        SyscallRegistry::default(),
+    )
+    .unwrap();
+    let ro_region = executable.get_ro_region();
+    assert!(!ro_region.is_writable);
+    let host_addr = ro_region
+        .vm_to_host::<TestUserError>(ebpf::MM_PROGRAM_START + 0x1f8, 8)
+        .unwrap();
+    assert_eq!(
+        unsafe { std::ptr::read_unaligned(host_addr as *const u64) },
+        ebpf::MM_PROGRAM_START + 0x108
+    );
+
+    // Replace "ldxdw r0, [r1]" by "stxdw [r1], r0", which writes to FILE in .data.rel.ro
+    elf[0xf8] = ebpf::ST_DW_REG;
+    elf[0xf9] = 0x01;
+    #[allow(unused_mut)]
+    let mut executable = Executable::<TestContextObject>::from_elf(
+        &elf,
+        Config::default(),
+        SyscallRegistry::default(),
+    )
+    .unwrap();
+    test_interpreter_and_jit!(executable, [], TestContextObject::new(2), {
+        |_vm, res: ProgramResult<TestUserError>| {
+            matches!(res.unwrap_err(),
+                EbpfError::AccessViolation(pc, access_type, vm_addr, len, _name, _)
+                if access_type == AccessType::Store && pc == 31 && vm_addr == ebpf::MM_PROGRAM_START + 0x1f8 && len == 8
+            )
+        }
+    });
+}
+
+#[test]
+fn test_reloc_64_relative_data_pre_sbfv2() {
+    // Before https://github.com/solana-labs/llvm-project/pull/35, we used to
+    // generate invalid R_BPF_64_RELATIVE relocations in sections other than
+    // .text.
+    //
+    // This test checks that the old behaviour is maintained for backwards
+    // compatibility when dealing with non-sbfv2 files. See also Elf::relocate().
+    //
+    // The program returns the address of the first .rodata byte.
+    // [ 1] .text             PROGBITS        00000000000000e8 0000e8 000020 00  AX  0   0  8
+    // [ 2] .rodata           PROGBITS        0000000000000108 000108 000019 01 AMS  0   0  1
+    //
+    // 00000000000001f8 <FILE>:
+    // 63:       00 00 00 00 08 01 00 00
+    test_interpreter_and_jit_elf!(
+        "tests/elfs/reloc_64_relative_data_pre_sbfv2.so",
+        [],
+        (),
+        TestContextObject::new(3),
+        { |_vm, res: ProgramResult<TestUserError>| res.unwrap() == ebpf::MM_PROGRAM_START + 0x108 },
+    );
+}
+
+#[test]
+fn test_reloc_64_relative_data_dereference() {
+    // The relocated pointer returned by the program must resolve to the
+    // __FILE__ string in .rodata, not just have the expected value
+    let expected = b"reloc_64_relative_data.c\0";
+    for path in [
+        "tests/elfs/reloc_64_relative_data.so",
+        "tests/elfs/reloc_64_relative_data_high_vaddr.so",
+        "tests/elfs/reloc_64_relative_data_pre_sbfv2.so",
+    ] {
+        let mut file = File::open(path).unwrap();
+        let mut elf = Vec::new();
+        file.read_to_end(&mut elf).unwrap();
+        let executable = Executable::<TestContextObject>::from_elf(
+            &elf,
+            Config::default(),
+            SyscallRegistry::default(),
+        )
+        .unwrap();
+        #[allow(unused_mut)]
+        let mut verified_executable =
+            VerifiedExecutable::<RequisiteVerifier, _>::from_executable(executable).unwrap();
+        #[cfg(all(not(windows), target_arch = "x86_64"))]
+        verified_executable.jit_compile().unwrap();
+        let ro_region = verified_executable.get_executable().get_ro_region();
+        for interpreted in [
+            true,
+            #[cfg(all(not(windows), target_arch = "x86_64"))]
+            false,
+        ] {
+            let mut context_object = TestContextObject::new(3);
+            let mut vm = EbpfVm::new(
+                &verified_executable,
+                &mut context_object,
+                &mut [],
+                Vec::new(),
+            )
+            .unwrap();
+            let vm_addr = vm.execute_program(interpreted).1.unwrap();
+            let host_addr = ro_region
+                .vm_to_host::<TestUserError>(vm_addr, expected.len() as u64)
+                .unwrap();
+            let bytes =
+                unsafe { std::slice::from_raw_parts(host_addr as *const u8, expected.len()) };
+            assert_eq!(bytes, expected, "{}", path);
+        }
+    }
+}
+
+// Programs
+
+#[test]
+fn test_mul_loop() {
+    test_interpreter_and_jit_asm!(
+        "
+        mov r0, 0x7
+        add r1, 0xa
+        lsh r1, 0x20
+        rsh r1, 0x20
+        jeq r1, 0x0, +4
+        mov r0, 0x7
+        mul r0, 0x7
+        add r1, -1
+        jne r1, 0x0, -3
+        exit",
+        [],
+        (),
+        TestContextObject::new(37),
+        { |_vm, res: ProgramResult<TestUserError>| { res.unwrap() == 0x75db9c97 } },
+    );
+}
+
+#[test]
+fn test_prime() {
+    test_interpreter_and_jit_asm!(
+        "
+        mov r1, 67
+        mov r0, 0x1
+        mov r2, 0x2
+        jgt r1, 0x2, +4
+        ja +10
         add r2, 0x1
         mov r0, 0x1
         jge r2, r1, +7
@@ -3850,7 +6294,7 @@ fn test_prime() {
         [],
         (),
         TestContextObject::new(655),
-        { |_vm, res: ProgramResult| { res.unwrap() == 0x1 } },
+        { |_vm, res: ProgramResult<TestUserError>| { res.unwrap() == 0x1 } },
     );
 }
 
@@ -3886,7 +6330,7 @@ fn test_subnet() {
         ],
         (),
         TestContextObject::new(11),
-        { |_vm, res: ProgramResult| { res.unwrap() == 0x1 } },
+        { |_vm, res: ProgramResult<TestUserError>| { res.unwrap() == 0x1 } },
     );
 }
 
@@ -3911,7 +6355,7 @@ fn test_tcp_port80_match() {
         ],
         (),
         TestContextObject::new(17),
-        { |_vm, res: ProgramResult| { res.unwrap() == 0x1 } },
+        { |_vm, res: ProgramResult<TestUserError>| { res.unwrap() == 0x1 } },
     );
 }
 
@@ -3936,7 +6380,7 @@ fn test_tcp_port80_nomatch() {
         ],
         (),
         TestContextObject::new(18),
-        { |_vm, res: ProgramResult| { res.unwrap() == 0x0 } },
+        { |_vm, res: ProgramResult<TestUserError>| { res.unwrap() == 0x0 } },
     );
 }
 
@@ -3961,7 +6405,7 @@ fn test_tcp_port80_nomatch_ethertype() {
         ],
         (),
         TestContextObject::new(7),
-        { |_vm, res: ProgramResult| { res.unwrap() == 0x0 } },
+        { |_vm, res: ProgramResult<TestUserError>| { res.unwrap() == 0x0 } },
     );
 }
 
@@ -3986,7 +6430,7 @@ fn test_tcp_port80_nomatch_proto() {
         ],
         (),
         TestContextObject::new(9),
-        { |_vm, res: ProgramResult| { res.unwrap() == 0x0 } },
+        { |_vm, res: ProgramResult<TestUserError>| { res.unwrap() == 0x0 } },
     );
 }
 
@@ -3997,7 +6441,7 @@ fn test_tcp_sack_match() {
         TCP_SACK_MATCH,
         (),
         TestContextObject::new(79),
-        { |_vm, res: ProgramResult| res.unwrap() == 0x1 },
+        { |_vm, res: ProgramResult<TestUserError>| res.unwrap() == 0x1 },
     );
 }
 
@@ -4008,7 +6452,7 @@ fn test_tcp_sack_nomatch() {
         TCP_SACK_NOMATCH,
         (),
         TestContextObject::new(55),
-        { |_vm, res: ProgramResult| res.unwrap() == 0x0 },
+        { |_vm, res: ProgramResult<TestUserError>| res.unwrap() == 0x0 },
     );
 }
 
@@ -4132,3 +6576,1305 @@ fn test_total_chaos() {
         execute_generated_program(&program);
     }
 }
+
+#[test]
+fn test_interpreter_step() {
+    let executable = assemble::<TestContextObject>(
+        "
+        mov64 r0, 1
+        call function_foo
+        add64 r0, 1
+        exit
+        function_foo:
+        mov64 r0, 2
+        exit",
+        Config::default(),
+        SyscallRegistry::default(),
+    )
+    .unwrap();
+    let verified_executable =
+        VerifiedExecutable::<RequisiteVerifier, _>::from_executable(executable).unwrap();
+    let mut context_object = TestContextObject::new(6);
+    let mut vm = EbpfVm::new(
+        &verified_executable,
+        &mut context_object,
+        &mut [],
+        Vec::new(),
+    )
+    .unwrap();
+    let mut interpreter = Interpreter::new(&mut vm).unwrap();
+    let root_frame_ptr = interpreter.get_registers()[ebpf::FRAME_PTR_REG];
+    let expected_states = [
+        (InterpreterState::Continued, 1, 0),
+        (InterpreterState::Called, 4, 1),
+        (InterpreterState::Continued, 5, 1),
+        (InterpreterState::Returned, 2, 0),
+        (InterpreterState::Continued, 3, 0),
+        (InterpreterState::Exited(3), 4, 0),
+    ];
+    for (expected_state, expected_pc, expected_frame_index) in expected_states {
+        assert_eq!(interpreter.step().unwrap(), expected_state);
+        assert_eq!(interpreter.get_pc(), expected_pc);
+        assert_eq!(
+            interpreter.get_call_frames().get_frame_index(),
+            expected_frame_index
+        );
+    }
+    assert_eq!(
+        interpreter.get_registers()[ebpf::FRAME_PTR_REG],
+        root_frame_ptr
+    );
+}
+
+#[test]
+fn test_interpreter_coverage() {
+    let executable = assemble::<TestContextObject>(
+        "
+        mov64 r0, 1
+        jeq r0, 1, +2
+        mov64 r0, 2
+        exit
+        mov64 r0, 3
+        exit",
+        Config::default(),
+        SyscallRegistry::default(),
+    )
+    .unwrap();
+    let verified_executable =
+        VerifiedExecutable::<RequisiteVerifier, _>::from_executable(executable).unwrap();
+    let mut context_object = TestContextObject::new(4);
+    let mut vm = EbpfVm::new(
+        &verified_executable,
+        &mut context_object,
+        &mut [],
+        Vec::new(),
+    )
+    .unwrap();
+    let mut interpreter = Interpreter::new(&mut vm).unwrap();
+    assert!(interpreter.coverage().is_empty());
+    interpreter.enable_coverage();
+    assert_eq!(interpreter.coverage(), &[0]);
+    assert_eq!(interpreter.run().unwrap(), 3);
+    // Only the taken branch is covered
+    assert_eq!(interpreter.coverage(), &[0b110011]);
+    interpreter.reset_coverage();
+    assert_eq!(interpreter.coverage(), &[0]);
+}
+
+#[test]
+#[cfg(feature = "instruction-class-counters")]
+fn test_interpreter_class_counts() {
+    use solana_rbpf::interpreter::ClassCounts;
+
+    let executable = assemble::<TestContextObject>(
+        "
+        mov64 r6, 0
+        stxb [r10-1], r6
+        ldxb r0, [r10-1]
+        add64 r6, 1
+        jlt r6, 3, -4
+        call function_foo
+        exit
+        function_foo:
+        ja +0
+        exit",
+        Config::default(),
+        SyscallRegistry::default(),
+    )
+    .unwrap();
+    let verified_executable =
+        VerifiedExecutable::<RequisiteVerifier, _>::from_executable(executable).unwrap();
+    let mut context_object = TestContextObject::new(17);
+    let mut vm = EbpfVm::new(
+        &verified_executable,
+        &mut context_object,
+        &mut [],
+        Vec::new(),
+    )
+    .unwrap();
+    let mut interpreter = Interpreter::new(&mut vm).unwrap();
+    assert_eq!(interpreter.class_counts(), &ClassCounts::default());
+    assert_eq!(interpreter.run().unwrap(), 2);
+    assert_eq!(
+        interpreter.class_counts(),
+        &ClassCounts {
+            alu: 4,
+            memory: 6,
+            jump: 4,
+            call: 3,
+        }
+    );
+    interpreter.reset_class_counts();
+    assert_eq!(interpreter.class_counts(), &ClassCounts::default());
+}
+
+#[test]
+fn test_multiple_input_regions() {
+    let executable = assemble::<TestContextObject>(
+        "
+        ldxb r0, [r1]
+        lddw r2, 0x500000000
+        ldxb r3, [r2+1]
+        add64 r0, r3
+        stxb [r2], r0
+        exit",
+        Config::default(),
+        SyscallRegistry::default(),
+    )
+    .unwrap();
+    #[allow(unused_mut)]
+    let mut verified_executable =
+        VerifiedExecutable::<RequisiteVerifier, _>::from_executable(executable).unwrap();
+    #[cfg(all(not(windows), target_arch = "x86_64"))]
+    verified_executable.jit_compile().unwrap();
+    for interpreted in [
+        true,
+        #[cfg(all(not(windows), target_arch = "x86_64"))]
+        false,
+    ] {
+        let mut header = [0x11];
+        let mut body = [0x00, 0x22];
+        let mut context_object = TestContextObject::new(6);
+        let mut vm = EbpfVm::new_with_inputs(
+            &verified_executable,
+            &mut context_object,
+            &mut [],
+            vec![(&mut header, false), (&mut body, true)],
+        )
+        .unwrap();
+        assert_eq!(vm.execute_program(interpreted).1.unwrap(), 0x33);
+        assert_eq!(body, [0x33, 0x22]);
+
+        // A read-only buffer can not be written
+        let mut context_object = TestContextObject::new(6);
+        let mut vm = EbpfVm::new_with_inputs(
+            &verified_executable,
+            &mut context_object,
+            &mut [],
+            vec![(&mut header, true), (&mut body, false)],
+        )
+        .unwrap();
+        assert!(matches!(
+            vm.execute_program(interpreted).1.unwrap_err(),
+            EbpfError::AccessViolation(_, AccessType::Store, 0x500000000, 1, _, _)
+        ));
+    }
+}
+
+#[test]
+fn test_vm_pool_recycled_matches_fresh() {
+    // Leaves its result on the stack and the heap, where the next run would pick it up
+    let executable = assemble::<TestContextObject>(
+        "
+        ldxdw r0, [r10-8]
+        lddw r2, 0x300000000
+        ldxdw r3, [r2]
+        add64 r0, r3
+        ldxdw r3, [r1]
+        add64 r0, r3
+        stxdw [r10-8], r0
+        stxdw [r2], r0
+        exit",
+        Config::default(),
+        SyscallRegistry::default(),
+    )
+    .unwrap();
+    #[allow(unused_mut)]
+    let mut verified_executable =
+        VerifiedExecutable::<RequisiteVerifier, _>::from_executable(executable).unwrap();
+    #[cfg(all(not(windows), target_arch = "x86_64"))]
+    verified_executable.jit_compile().unwrap();
+    for interpreted in [
+        true,
+        #[cfg(all(not(windows), target_arch = "x86_64"))]
+        false,
+    ] {
+        let mut pool = VmPool::new(&verified_executable, 0x1000, 1);
+        for input in [3u64, 5, 7] {
+            let mem = input.to_le_bytes();
+            let mut heap = vec![0u8; 0x1000];
+            let mut context_object = TestContextObject::new(9);
+            let mut vm = EbpfVm::new(
+                &verified_executable,
+                &mut context_object,
+                &mut heap,
+                vec![MemoryRegion::new_readonly(&mem, ebpf::MM_INPUT_START)],
+            )
+            .unwrap();
+            let (fresh_instruction_count, fresh_result) = vm.execute_program(interpreted);
+
+            let mut context_object = TestContextObject::new(9);
+            let mut vm = pool
+                .get(
+                    &mut context_object,
+                    vec![MemoryRegion::new_readonly(&mem, ebpf::MM_INPUT_START)],
+                )
+                .unwrap();
+            let (pooled_instruction_count, pooled_result) = vm.execute_program(interpreted);
+            pool.recycle(vm);
+
+            assert_eq!(fresh_result.unwrap(), input);
+            assert_eq!(pooled_result.unwrap(), input);
+            assert_eq!(pooled_instruction_count, fresh_instruction_count);
+        }
+        assert_eq!(pool.idle_count(), 1);
+    }
+}
+
+#[test]
+fn test_vm_pool_changes_additional_regions() {
+    let executable = assemble::<TestContextObject>(
+        "
+        ldxdw r0, [r1]
+        exit",
+        Config::default(),
+        SyscallRegistry::default(),
+    )
+    .unwrap();
+    let verified_executable =
+        VerifiedExecutable::<RequisiteVerifier, _>::from_executable(executable).unwrap();
+    let mut pool = VmPool::new(&verified_executable, 0x1000, 1);
+    // Same layout as the last run, then a longer input and finally no input at all
+    for (mut input, expected_result) in [
+        (Some(vec![3u8; 8]), Ok(0x0303030303030303)),
+        (Some(vec![5u8; 8]), Ok(0x0505050505050505)),
+        (Some(vec![7u8; 16]), Ok(0x0707070707070707)),
+        (None, Err(())),
+    ] {
+        let additional_regions = input
+            .as_mut()
+            .map(|input| vec![MemoryRegion::new_writable(input, ebpf::MM_INPUT_START)])
+            .unwrap_or_default();
+        let mut context_object = TestContextObject::new(2);
+        let mut vm = pool.get(&mut context_object, additional_regions).unwrap();
+        let (_instruction_count, result) = vm.execute_program(true);
+        pool.recycle(vm);
+        assert_eq!(Result::from(result).map_err(|_| ()), expected_result);
+    }
+    assert_eq!(pool.idle_count(), 1);
+}
+
+#[test]
+fn test_vm_pool_resets_allocator() {
+    let mut syscall_registry = SyscallRegistry::default();
+    syscall_registry
+        .register_syscall_by_name(b"bpf_alloc_free", syscalls::bpf_alloc_free)
+        .unwrap();
+    let executable = assemble::<TestContextObject>(
+        "
+        mov64 r1, 16
+        mov64 r2, 0
+        syscall bpf_alloc_free
+        exit",
+        Config::default(),
+        syscall_registry,
+    )
+    .unwrap();
+    let verified_executable =
+        VerifiedExecutable::<RequisiteVerifier, _>::from_executable(executable).unwrap();
+    let mut pool = VmPool::new(&verified_executable, 0x1000, 1);
+    // The limit only admits one allocation per run
+    let mut context_object = TestContextObject::new(4);
+    context_object.allocator = BpfAllocator::new_with_limit(ebpf::MM_HEAP_START, 0x1000, 16);
+    for _ in 0..2 {
+        context_object.remaining = 4;
+        let mut vm = pool.get(&mut context_object, Vec::new()).unwrap();
+        let (_instruction_count, result) = vm.execute_program(true);
+        pool.recycle(vm);
+        assert_eq!(result.unwrap(), ebpf::MM_HEAP_START);
+    }
+}
+
+#[test]
+fn test_interpreter_stack_high_water_marks() {
+    // Recurses three times, so function_foo is active four times at its deepest
+    for (dynamic_stack_frames, source, expected_stack_bytes) in [
+        (
+            false,
+            "
+            mov64 r1, 3
+            call function_foo
+            exit
+            function_foo:
+            jeq r1, 0, +2
+            sub64 r1, 1
+            call function_foo
+            exit",
+            5 * 4096,
+        ),
+        (
+            true,
+            "
+            mov64 r1, 3
+            call function_foo
+            exit
+            function_foo:
+            sub r11, 64
+            jeq r1, 0, +2
+            sub64 r1, 1
+            call function_foo
+            add r11, 64
+            exit",
+            4 * 64,
+        ),
+    ] {
+        let config = Config {
+            dynamic_stack_frames,
+            enable_stack_usage_tracking: true,
+            ..Config::default()
+        };
+        let executable =
+            assemble::<TestContextObject>(source, config, SyscallRegistry::default()).unwrap();
+        let verified_executable =
+            VerifiedExecutable::<RequisiteVerifier, _>::from_executable(executable).unwrap();
+        let mut context_object = TestContextObject::new(25);
+        let mut vm = EbpfVm::new(
+            &verified_executable,
+            &mut context_object,
+            &mut [],
+            Vec::new(),
+        )
+        .unwrap();
+        let mut interpreter = Interpreter::new(&mut vm).unwrap();
+        assert_eq!(interpreter.max_stack_depth(), 1);
+        interpreter.run().unwrap();
+        assert_eq!(interpreter.max_stack_depth(), 5);
+        assert_eq!(interpreter.max_stack_bytes(), expected_stack_bytes);
+        interpreter.reset_stack_high_water_marks();
+        assert_eq!(interpreter.max_stack_depth(), 1);
+        assert_eq!(
+            interpreter.max_stack_bytes(),
+            if dynamic_stack_frames { 0 } else { 4096 }
+        );
+    }
+
+    // The stack usage is not tracked by default
+    let executable = assemble::<TestContextObject>(
+        "
+        mov64 r1, 3
+        call function_foo
+        exit
+        function_foo:
+        jeq r1, 0, +2
+        sub64 r1, 1
+        call function_foo
+        exit",
+        Config::default(),
+        SyscallRegistry::default(),
+    )
+    .unwrap();
+    let verified_executable =
+        VerifiedExecutable::<RequisiteVerifier, _>::from_executable(executable).unwrap();
+    let mut context_object = TestContextObject::new(25);
+    let mut vm = EbpfVm::new(
+        &verified_executable,
+        &mut context_object,
+        &mut [],
+        Vec::new(),
+    )
+    .unwrap();
+    let mut interpreter = Interpreter::new(&mut vm).unwrap();
+    interpreter.run().unwrap();
+    assert_eq!(interpreter.max_stack_depth(), 5);
+    assert_eq!(interpreter.max_stack_bytes(), 0);
+}
+
+#[test]
+fn test_interpreter_snapshot_restore() {
+    let executable = assemble::<TestContextObject>(
+        "
+        ldxb r2, [r1]
+        add64 r2, 1
+        stxb [r1], r2
+        stxdw [r10-8], r2
+        call function_foo
+        ldxdw r0, [r10-8]
+        add64 r0, r2
+        exit
+        function_foo:
+        ldxdw r3, [r10-8]
+        add64 r3, 16
+        stxdw [r10-8], r3
+        exit",
+        Config::default(),
+        SyscallRegistry::default(),
+    )
+    .unwrap();
+    let verified_executable =
+        VerifiedExecutable::<RequisiteVerifier, _>::from_executable(executable).unwrap();
+    let mut mem = [0u8; 1];
+    let mem_region = MemoryRegion::new_writable(&mut mem, ebpf::MM_INPUT_START);
+    let mut context_object = TestContextObject::new(100);
+    let mut vm = EbpfVm::new(
+        &verified_executable,
+        &mut context_object,
+        &mut [],
+        vec![mem_region],
+    )
+    .unwrap();
+    let mut interpreter = Interpreter::new(&mut vm).unwrap();
+    let initial = interpreter.snapshot();
+    for _ in 0..5 {
+        interpreter.step().unwrap();
+    }
+    assert_eq!(interpreter.get_call_frames().get_frame_index(), 1);
+    let in_call = interpreter.snapshot();
+    assert_eq!(interpreter.run().unwrap(), 18);
+
+    interpreter.restore(&in_call).unwrap();
+    assert_eq!(interpreter.get_pc(), 8);
+    assert_eq!(interpreter.get_call_frames().get_frame_index(), 1);
+    assert_eq!(interpreter.run().unwrap(), 18);
+
+    interpreter.restore(&initial).unwrap();
+    assert_eq!(interpreter.get_pc(), 0);
+    assert_eq!(interpreter.get_call_frames().get_frame_index(), 0);
+    // The input byte was incremented by the previous runs and must be rewound as well
+    interpreter.step().unwrap();
+    assert_eq!(interpreter.get_registers()[2], 0);
+    assert_eq!(interpreter.run().unwrap(), 18);
+
+    // A snapshot can not be restored into a VM with a different memory layout
+    let mut other_mem = [0u8; 1];
+    let mem_region = MemoryRegion::new_writable(&mut other_mem, ebpf::MM_INPUT_START);
+    let mut context_object = TestContextObject::new(100);
+    let mut other_vm = EbpfVm::new(
+        &verified_executable,
+        &mut context_object,
+        &mut [],
+        vec![mem_region],
+    )
+    .unwrap();
+    let mut other_interpreter = Interpreter::new(&mut other_vm).unwrap();
+    assert!(matches!(
+        other_interpreter.restore(&initial),
+        Err(EbpfError::SnapshotMismatch(ebpf::MM_INPUT_START))
+    ));
+    assert_eq!(other_interpreter.get_pc(), 0);
+}
+
+#[test]
+fn test_interpreter_yield_and_resume() {
+    use std::sync::atomic::{AtomicBool, Ordering};
+
+    let executable = assemble::<TestContextObject>(
+        "
+        mov r0, 0
+        mov r1, 10
+        stxdw [r10-8], r1
+        call function_sum
+        exit
+        function_sum:
+        ldxdw r2, [r10-8]
+        add r0, r2
+        sub r2, 1
+        stxdw [r10-8], r2
+        jne r2, 0, -5
+        exit",
+        Config::default(),
+        SyscallRegistry::default(),
+    )
+    .unwrap();
+    let verified_executable =
+        VerifiedExecutable::<RequisiteVerifier, _>::from_executable(executable).unwrap();
+    let mut context_object = TestContextObject::new(100);
+    let mut vm = EbpfVm::new(
+        &verified_executable,
+        &mut context_object,
+        &mut [],
+        Vec::new(),
+    )
+    .unwrap();
+    let should_yield = AtomicBool::new(false);
+    let mut interpreter = Interpreter::new(&mut vm).unwrap();
+    interpreter.set_yield_signal(&should_yield, 8);
+    should_yield.store(true, Ordering::Relaxed);
+    let mut state = match interpreter.run() {
+        Err(EbpfError::Yielded(state)) => state,
+        result => panic!("{:?}", result),
+    };
+    assert_eq!(state.pc, 0);
+    assert!(!should_yield.load(Ordering::Relaxed));
+    // Every 8th instruction: 4 in the entrypoint and 4 of the 5 instruction loop body, then 8 more
+    for expected_pc in [9, 7, 5] {
+        should_yield.store(true, Ordering::Relaxed);
+        state = match interpreter.resume(&state) {
+            Err(EbpfError::Yielded(state)) => state,
+            result => panic!("{:?}", result),
+        };
+        assert_eq!(state.pc, expected_pc);
+    }
+    assert_eq!(interpreter.get_call_frames().get_frame_index(), 1);
+
+    // A new interpreter for the same VM continues where the run yielded
+    let mut interpreter = Interpreter::new(&mut vm).unwrap();
+    assert_eq!(interpreter.resume(&state).unwrap(), 55);
+}
+
+#[cfg(feature = "instruction-trace")]
+#[test]
+fn test_interpreter_trace_collector() {
+    use solana_rbpf::interpreter::{diff_traces, TraceCollector};
+
+    let executable = assemble::<TestContextObject>(
+        "
+        ldxb r0, [r1]
+        jeq r0, 0, +1
+        mov64 r0, 7
+        add64 r0, 1
+        exit",
+        Config::default(),
+        SyscallRegistry::default(),
+    )
+    .unwrap();
+    let verified_executable =
+        VerifiedExecutable::<RequisiteVerifier, _>::from_executable(executable).unwrap();
+    let collect_trace = |mut mem: [u8; 1]| {
+        let mem_region = MemoryRegion::new_writable(&mut mem, ebpf::MM_INPUT_START);
+        let mut context_object = TestContextObject::new(5);
+        let mut vm = EbpfVm::new(
+            &verified_executable,
+            &mut context_object,
+            &mut [],
+            vec![mem_region],
+        )
+        .unwrap();
+        let mut trace_collector = TraceCollector::with_capacity(5);
+        let mut interpreter = Interpreter::with_trace(&mut vm, &mut trace_collector).unwrap();
+        let result = interpreter.run().unwrap();
+        assert_eq!(trace_collector.get_entries().last().unwrap().reg[0], result);
+        trace_collector.into_entries()
+    };
+    let taken = collect_trace([0]);
+    let not_taken = collect_trace([1]);
+    assert_eq!(
+        taken.iter().map(|entry| entry.pc).collect::<Vec<_>>(),
+        vec![0, 1, 3, 4]
+    );
+    assert_eq!(
+        not_taken.iter().map(|entry| entry.pc).collect::<Vec<_>>(),
+        vec![0, 1, 2, 3, 4]
+    );
+    assert_eq!(taken[3].reg[0], 1);
+    assert_eq!(not_taken[4].reg[0], 8);
+    assert!(diff_traces(&taken, &taken).is_none());
+    let divergence = diff_traces(&taken, &not_taken).unwrap();
+    assert_eq!(divergence.index, 0);
+    assert_eq!(divergence.left.unwrap().reg[0], 0);
+    assert_eq!(divergence.right.unwrap().reg[0], 1);
+    let divergence = diff_traces(&not_taken[..2], &not_taken).unwrap();
+    assert_eq!(divergence.index, 2);
+    assert_eq!(divergence.left, None);
+    assert_eq!(divergence.right, Some(not_taken[2]));
+}
+
+#[test]
+fn test_async_syscall() {
+    use solana_rbpf::async_interpreter::{
+        AsyncInterpreter, AsyncSyscallFuture, AsyncSyscallRegistry,
+    };
+    use std::{
+        future::Future,
+        pin::Pin,
+        sync::Arc,
+        task::{Context, Poll, Wake, Waker},
+    };
+
+    struct NoopWaker;
+    impl Wake for NoopWaker {
+        fn wake(self: Arc<Self>) {}
+    }
+
+    fn block_on<F: Future>(future: F) -> F::Output {
+        let waker = Waker::from(Arc::new(NoopWaker));
+        let mut context = Context::from_waker(&waker);
+        let mut future = Box::pin(future);
+        loop {
+            if let Poll::Ready(output) = future.as_mut().poll(&mut context) {
+                return output;
+            }
+        }
+    }
+
+    /// Returns `Poll::Pending` once before completing
+    struct YieldOnce(bool);
+    impl Future for YieldOnce {
+        type Output = ();
+        fn poll(mut self: Pin<&mut Self>, context: &mut Context) -> Poll<()> {
+            if self.0 {
+                Poll::Ready(())
+            } else {
+                self.0 = true;
+                context.waker().wake_by_ref();
+                Poll::Pending
+            }
+        }
+    }
+
+    fn async_double<'a>(
+        context_object: &'a mut TestContextObject,
+        arg1: u64,
+        _arg2: u64,
+        _arg3: u64,
+        _arg4: u64,
+        _arg5: u64,
+        _memory_mapping: &'a mut MemoryMapping,
+    ) -> AsyncSyscallFuture<'a, TestUserError> {
+        Box::pin(async move {
+            YieldOnce(false).await;
+            context_object.consume(10);
+            Ok(arg1 * 2)
+        })
+    }
+
+    fn async_fail<'a>(
+        _context_object: &'a mut TestContextObject,
+        _arg1: u64,
+        _arg2: u64,
+        _arg3: u64,
+        _arg4: u64,
+        _arg5: u64,
+        _memory_mapping: &'a mut MemoryMapping,
+    ) -> AsyncSyscallFuture<'a, TestUserError> {
+        Box::pin(async move {
+            YieldOnce(false).await;
+            Err(EbpfError::SyscallNotRegistered("async_fail".to_string()))
+        })
+    }
+
+    let mut async_syscall_registry = AsyncSyscallRegistry::default();
+    async_syscall_registry
+        .register_syscall_by_name(b"async_double", async_double)
+        .unwrap();
+    async_syscall_registry
+        .register_syscall_by_name(b"async_fail", async_fail)
+        .unwrap();
+    for (source, remaining, expected_result, expected_instruction_count) in [
+        (
+            "
+            mov64 r6, 7
+            mov64 r1, 5
+            syscall async_double
+            add64 r0, r6
+            exit",
+            15,
+            ProgramResult::<TestUserError>::Ok(17),
+            15,
+        ),
+        (
+            "
+            mov64 r1, 5
+            syscall async_double
+            exit",
+            12,
+            ProgramResult::Err(EbpfError::ExceededMaxInstructions(31, 12)),
+            12,
+        ),
+        (
+            "
+            syscall async_fail
+            exit",
+            5,
+            ProgramResult::Err(EbpfError::SyscallNotRegistered("async_fail".to_string())),
+            1,
+        ),
+    ] {
+        let executable =
+            assemble::<TestContextObject>(source, Config::default(), SyscallRegistry::default())
+                .unwrap();
+        let verified_executable =
+            VerifiedExecutable::<RequisiteVerifier, _>::from_executable(executable).unwrap();
+        let mut context_object = TestContextObject::new(remaining);
+        let mut vm = EbpfVm::new(
+            &verified_executable,
+            &mut context_object,
+            &mut [],
+            Vec::new(),
+        )
+        .unwrap();
+        let mut interpreter = AsyncInterpreter::new(&mut vm, &async_syscall_registry).unwrap();
+        let (instruction_count, result) = block_on(interpreter.execute_program());
+        assert_eq!(format!("{:?}", result), format!("{:?}", expected_result));
+        assert_eq!(instruction_count, expected_instruction_count);
+    }
+}
+
+#[test]
+#[cfg(all(not(windows), target_arch = "x86_64"))]
+fn test_load_compiled_program() {
+    let source = "
+        mov64 r1, 0x1
+        mov64 r2, 0x2
+        mov64 r3, 0x3
+        mov64 r4, 0x4
+        mov64 r5, 0x5
+        syscall bpf_gather_bytes
+        mov64 r6, r0
+        mov64 r8, 0x1
+        lsh64 r8, 0x20
+        or64 r8, 0x78
+        callx r8
+        add64 r6, r0
+        call function_bar
+        add64 r0, r6
+        exit
+        function_foo:
+        mov64 r0, 0x10
+        exit
+        function_bar:
+        mov64 r0, 0x20
+        exit";
+    let create_executable = |source: &str, config: Config| {
+        let mut syscall_registry = SyscallRegistry::default();
+        syscall_registry
+            .register_syscall_by_name(b"bpf_gather_bytes", syscalls::bpf_gather_bytes)
+            .unwrap();
+        let executable = assemble::<TestContextObject>(source, config, syscall_registry).unwrap();
+        VerifiedExecutable::<RequisiteVerifier, _>::from_executable(executable).unwrap()
+    };
+    let mut compiled = create_executable(source, Config::default());
+    compiled.jit_compile().unwrap();
+    let bytes = compiled
+        .get_executable()
+        .get_compiled_program()
+        .unwrap()
+        .serialize();
+    drop(compiled);
+
+    // Safety: the bytes were produced by serialize() just now and are not modified
+    let mut loaded = create_executable(source, Config::default());
+    unsafe { loaded.load_compiled_program(&bytes) }.unwrap();
+    assert_eq!(
+        loaded
+            .get_executable()
+            .get_compiled_program()
+            .unwrap()
+            .serialize(),
+        bytes
+    );
+    let mut context_object = TestContextObject::new(19);
+    let mut vm = EbpfVm::new(&loaded, &mut context_object, &mut [], Vec::new()).unwrap();
+    let (instruction_count, result) = vm.execute_program(false);
+    assert_eq!(result.unwrap(), 0x0102030435);
+    assert_eq!(instruction_count, 19);
+
+    let mut truncated = create_executable(source, Config::default());
+    assert!(matches!(
+        unsafe { truncated.load_compiled_program(&bytes[..bytes.len() - 1]) },
+        Err(EbpfError::JitCacheMismatch)
+    ));
+    // Flip one bit of the machine code, which starts after the header of 104 bytes
+    let mut corrupted_bytes = bytes.clone();
+    corrupted_bytes[104] ^= 1;
+    let mut corrupted = create_executable(source, Config::default());
+    assert!(matches!(
+        unsafe { corrupted.load_compiled_program(&corrupted_bytes) },
+        Err(EbpfError::JitCacheMismatch)
+    ));
+    let mut other_program = create_executable(
+        &source.replace("mov64 r0, 0x20", "mov64 r0, 0x21"),
+        Config::default(),
+    );
+    assert!(matches!(
+        unsafe { other_program.load_compiled_program(&bytes) },
+        Err(EbpfError::JitCacheMismatch)
+    ));
+    let mut other_config = create_executable(
+        source,
+        Config {
+            enable_instruction_meter: false,
+            ..Config::default()
+        },
+    );
+    assert!(matches!(
+        unsafe { other_config.load_compiled_program(&bytes) },
+        Err(EbpfError::JitCacheMismatch)
+    ));
+}
+
+#[cfg(all(not(windows), target_arch = "x86_64"))]
+#[test]
+fn test_load_and_jit() {
+    let mut file = File::open("tests/elfs/relative_call.so").unwrap();
+    let mut elf = Vec::new();
+    file.read_to_end(&mut elf).unwrap();
+    let mut syscall_registry = SyscallRegistry::default();
+    test_interpreter_and_jit!(register, syscall_registry, b"log" => syscalls::bpf_syscall_string);
+    let (verified_executable, timings) =
+        VerifiedExecutable::<RequisiteVerifier, TestContextObject>::load_and_jit(
+            &elf,
+            Config::default(),
+            syscall_registry,
+        )
+        .unwrap();
+    assert!(verified_executable
+        .get_executable()
+        .get_compiled_program()
+        .is_some());
+    assert!(timings.jit_compilation > Duration::ZERO);
+    let mut context_object = TestContextObject::new(14);
+    let mut vm = EbpfVm::new(
+        &verified_executable,
+        &mut context_object,
+        &mut [],
+        vec![MemoryRegion::new_writable(&mut [1], ebpf::MM_INPUT_START)],
+    )
+    .unwrap();
+    assert_eq!(vm.execute_program(false).1.unwrap(), 2);
+
+    let config = Config {
+        reject_broken_elfs: true,
+        ..Config::default()
+    };
+    assert!(matches!(
+        VerifiedExecutable::<RequisiteVerifier, TestContextObject>::load_and_jit(
+            &elf,
+            config,
+            SyscallRegistry::default(),
+        ),
+        Err(EbpfError::SyscallNotRegistered(name)) if name == "log"
+    ));
+}
+
+#[test]
+fn test_syscall_observer() {
+    #[derive(Default)]
+    struct SyscallLog(Vec<(bool, u32)>);
+    impl SyscallObserver<TestUserError> for SyscallLog {
+        fn on_syscall_enter(&mut self, number: u32) {
+            self.0.push((true, number));
+        }
+        fn on_syscall_exit(&mut self, number: u32) {
+            self.0.push((false, number));
+        }
+    }
+
+    let mut syscall_registry = SyscallRegistry::default();
+    syscall_registry
+        .register_syscall_by_name(b"bpf_mem_frob", syscalls::bpf_mem_frob)
+        .unwrap();
+    syscall_registry
+        .register_syscall_by_name(b"bpf_gather_bytes", syscalls::bpf_gather_bytes)
+        .unwrap();
+    let executable = assemble::<TestContextObject>(
+        "
+        mov r1, r10
+        sub r1, 0x4
+        mov r2, 0x4
+        syscall bpf_mem_frob
+        syscall bpf_gather_bytes
+        exit",
+        Config::default(),
+        syscall_registry,
+    )
+    .unwrap();
+    #[allow(unused_mut)]
+    let mut verified_executable =
+        VerifiedExecutable::<RequisiteVerifier, _>::from_executable(executable).unwrap();
+    #[cfg(all(not(windows), target_arch = "x86_64"))]
+    verified_executable.jit_compile().unwrap();
+    let mem_frob = ebpf::hash_symbol_name(b"bpf_mem_frob");
+    let gather_bytes = ebpf::hash_symbol_name(b"bpf_gather_bytes");
+    let expected_log = vec![
+        (true, mem_frob),
+        (false, mem_frob),
+        (true, gather_bytes),
+        (false, gather_bytes),
+    ];
+    for interpreted in [
+        true,
+        #[cfg(all(not(windows), target_arch = "x86_64"))]
+        false,
+    ] {
+        let mut context_object = TestContextObject::new(6);
+        let mut syscall_log = SyscallLog::default();
+        let mut vm = EbpfVm::new(
+            &verified_executable,
+            &mut context_object,
+            &mut [],
+            Vec::new(),
+        )
+        .unwrap();
+        vm.set_syscall_observer(&mut syscall_log);
+        let (instruction_count, result) = vm.execute_program(interpreted);
+        assert!(result.is_ok());
+        assert_eq!(instruction_count, 6);
+        drop(vm);
+        assert_eq!(syscall_log.0, expected_log);
+    }
+}
+
+#[test]
+fn test_syscall_record_and_replay() {
+    static COUNTER: std::sync::atomic::AtomicU64 = std::sync::atomic::AtomicU64::new(0);
+    #[allow(clippy::too_many_arguments)]
+    fn nondeterministic(
+        _context_object: &mut TestContextObject,
+        _arg1: u64,
+        _arg2: u64,
+        _arg3: u64,
+        _arg4: u64,
+        _arg5: u64,
+        _memory_mapping: &mut MemoryMapping,
+        result: &mut ProgramResult<TestUserError>,
+    ) {
+        *result = ProgramResult::Ok(
+            COUNTER.fetch_add(1, std::sync::atomic::Ordering::Relaxed) * 0x100 + 0x10,
+        );
+    }
+
+    let mut syscall_registry = SyscallRegistry::default();
+    syscall_registry
+        .register_syscall_by_name(b"nondeterministic", nondeterministic)
+        .unwrap();
+    let executable = assemble::<TestContextObject>(
+        "
+        syscall nondeterministic
+        mov r6, r0
+        syscall nondeterministic
+        lsh r0, 32
+        add r0, r6
+        exit",
+        Config::default(),
+        syscall_registry,
+    )
+    .unwrap();
+    #[allow(unused_mut)]
+    let mut verified_executable =
+        VerifiedExecutable::<RequisiteVerifier, _>::from_executable(executable).unwrap();
+    #[cfg(all(not(windows), target_arch = "x86_64"))]
+    verified_executable.jit_compile().unwrap();
+    let number = ebpf::hash_symbol_name(b"nondeterministic");
+
+    let mut context_object = TestContextObject::new(6);
+    let mut recorder = SyscallRecorder::default();
+    let mut vm = EbpfVm::new(
+        &verified_executable,
+        &mut context_object,
+        &mut [],
+        Vec::new(),
+    )
+    .unwrap();
+    vm.set_syscall_observer(&mut recorder);
+    let recorded_result = vm.execute_program(true).1.unwrap();
+    drop(vm);
+    assert_eq!(recorder.log.len(), 2);
+    assert!(recorder.log.iter().all(|(recorded, _)| *recorded == number));
+    assert_eq!(recorded_result, recorder.log[1].1 << 32 | recorder.log[0].1);
+
+    for interpreted in [
+        true,
+        #[cfg(all(not(windows), target_arch = "x86_64"))]
+        false,
+    ] {
+        // Replays the same values although the syscall function would return different ones
+        let mut context_object = TestContextObject::new(6);
+        let mut replayer = SyscallReplayer::new(recorder.log.clone());
+        let mut vm = EbpfVm::new(
+            &verified_executable,
+            &mut context_object,
+            &mut [],
+            Vec::new(),
+        )
+        .unwrap();
+        vm.set_syscall_observer(&mut replayer);
+        let (instruction_count, result) = vm.execute_program(interpreted);
+        assert_eq!(result.unwrap(), recorded_result);
+        assert_eq!(instruction_count, 6);
+        drop(vm);
+        assert!(replayer.finish::<TestUserError>().is_ok());
+
+        // The program makes more syscalls than recorded
+        let mut context_object = TestContextObject::new(6);
+        let mut replayer = SyscallReplayer::new(recorder.log[0..1].to_vec());
+        let mut vm = EbpfVm::new(
+            &verified_executable,
+            &mut context_object,
+            &mut [],
+            Vec::new(),
+        )
+        .unwrap();
+        vm.set_syscall_observer(&mut replayer);
+        assert!(matches!(
+            vm.execute_program(interpreted).1,
+            ProgramResult::Err(EbpfError::SyscallReplayMismatch(1, None, Some(executed))) if executed == number
+        ));
+
+        // The recorded syscalls differ from the ones the program makes
+        let mut context_object = TestContextObject::new(6);
+        let mut replayer = SyscallReplayer::new(vec![(number, 1), (number + 1, 2)]);
+        let mut vm = EbpfVm::new(
+            &verified_executable,
+            &mut context_object,
+            &mut [],
+            Vec::new(),
+        )
+        .unwrap();
+        vm.set_syscall_observer(&mut replayer);
+        assert!(matches!(
+            vm.execute_program(interpreted).1,
+            ProgramResult::Err(EbpfError::SyscallReplayMismatch(1, Some(recorded), Some(executed))) if recorded == number + 1 && executed == number
+        ));
+
+        // The program makes fewer syscalls than recorded
+        let mut context_object = TestContextObject::new(6);
+        let mut replayer = SyscallReplayer::new(vec![(number, 1), (number, 2), (number, 3)]);
+        let mut vm = EbpfVm::new(
+            &verified_executable,
+            &mut context_object,
+            &mut [],
+            Vec::new(),
+        )
+        .unwrap();
+        vm.set_syscall_observer(&mut replayer);
+        assert_eq!(vm.execute_program(interpreted).1.unwrap(), 2 << 32 | 1);
+        drop(vm);
+        assert!(matches!(
+            replayer.finish::<TestUserError>(),
+            Err(EbpfError::SyscallReplayMismatch(2, Some(recorded), None)) if recorded == number
+        ));
+    }
+}
+
+#[test]
+fn test_call_observer() {
+    #[derive(Debug, PartialEq)]
+    enum Event {
+        Enter(usize),
+        Return,
+    }
+    #[derive(Default)]
+    struct CallLog(Vec<Event>);
+    impl CallObserver for CallLog {
+        fn on_call_enter(&mut self, target_pc: usize) {
+            self.0.push(Event::Enter(target_pc));
+        }
+        fn on_call_return(&mut self) {
+            self.0.push(Event::Return);
+        }
+    }
+
+    let executable = assemble::<TestContextObject>(
+        "
+        call function_foo
+        exit
+        function_foo:
+        call function_bar
+        mov64 r8, 0x1
+        lsh64 r8, 0x20
+        or64 r8, 0x40
+        callx r8
+        exit
+        function_bar:
+        mov64 r0, 0x1
+        exit",
+        Config::default(),
+        SyscallRegistry::default(),
+    )
+    .unwrap();
+    #[allow(unused_mut)]
+    let mut verified_executable =
+        VerifiedExecutable::<RequisiteVerifier, _>::from_executable(executable).unwrap();
+    #[cfg(all(not(windows), target_arch = "x86_64"))]
+    verified_executable.jit_compile().unwrap();
+    let expected_log = vec![
+        Event::Enter(2),
+        Event::Enter(8),
+        Event::Return,
+        Event::Enter(8),
+        Event::Return,
+        Event::Return,
+    ];
+    for interpreted in [
+        true,
+        #[cfg(all(not(windows), target_arch = "x86_64"))]
+        false,
+    ] {
+        let mut context_object = TestContextObject::new(12);
+        let mut call_log = CallLog::default();
+        let mut vm = EbpfVm::new(
+            &verified_executable,
+            &mut context_object,
+            &mut [],
+            Vec::new(),
+        )
+        .unwrap();
+        vm.set_call_observer(&mut call_log);
+        let (instruction_count, result) = vm.execute_program(interpreted);
+        assert_eq!(result.unwrap(), 1);
+        assert_eq!(instruction_count, 12);
+        drop(vm);
+        assert_eq!(call_log.0, expected_log);
+    }
+
+    // A callx to a target which is not a function fails before the call is entered
+    let executable = assemble::<TestContextObject>(
+        "
+        mov64 r8, 0x1
+        lsh64 r8, 0x20
+        or64 r8, 0x20
+        callx r8
+        exit
+        mov64 r0, 0x2A
+        exit",
+        Config::default(),
+        SyscallRegistry::default(),
+    )
+    .unwrap();
+    #[allow(unused_mut)]
+    let mut verified_executable =
+        VerifiedExecutable::<RequisiteVerifier, _>::from_executable(executable).unwrap();
+    #[cfg(all(not(windows), target_arch = "x86_64"))]
+    verified_executable.jit_compile().unwrap();
+    for interpreted in [
+        true,
+        #[cfg(all(not(windows), target_arch = "x86_64"))]
+        false,
+    ] {
+        let mut context_object = TestContextObject::new(5);
+        let mut call_log = CallLog::default();
+        let mut vm = EbpfVm::new(
+            &verified_executable,
+            &mut context_object,
+            &mut [],
+            Vec::new(),
+        )
+        .unwrap();
+        vm.set_call_observer(&mut call_log);
+        let (instruction_count, result) = vm.execute_program(interpreted);
+        assert!(matches!(
+            result.unwrap_err(),
+            EbpfError::UnsupportedInstruction(pc) if pc == 33
+        ));
+        assert_eq!(instruction_count, 5);
+        drop(vm);
+        assert!(call_log.0.is_empty());
+    }
+}
+
+#[test]
+fn test_opcode_extension() {
+    // popcnt dst, src is not part of the instruction set
+    const POPCNT: u8 = ebpf::BPF_ALU64 | ebpf::BPF_X | ebpf::BPF_END;
+    let prog = &[
+        0xb7, 0x01, 0x00, 0x00, 0xf0, 0xf0, 0x0f, 0x00, // mov64 r1, 0xff0f0
+        POPCNT, 0x10, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, // popcnt r0, r1
+        0x95, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, // exit
+    ];
+    let create_executable = |config: Config| {
+        let executable = Executable::<TestContextObject>::from_text_bytes(
+            prog,
+            config,
+            SyscallRegistry::default(),
+            FunctionRegistry::default(),
+        )
+        .unwrap();
+        VerifiedExecutable::<RequisiteVerifier, TestContextObject>::from_executable(executable)
+    };
+    let mut config = Config {
+        allow_opcode_extensions: true,
+        ..Config::default()
+    };
+    assert!(matches!(
+        create_executable(config),
+        Err(EbpfError::VerifierError(VerifierError::UnknownOpCode(
+            POPCNT, 30
+        )))
+    ));
+    config.valid_opcode_extensions.insert(POPCNT);
+    let verified_executable = create_executable(config).unwrap();
+
+    let mut context_object = TestContextObject::new(3);
+    let mut vm = EbpfVm::new(
+        &verified_executable,
+        &mut context_object,
+        &mut [],
+        Vec::new(),
+    )
+    .unwrap();
+    let (_instruction_count, result) = vm.execute_program(true);
+    assert!(matches!(
+        result,
+        ProgramResult::Err(EbpfError::UnsupportedInstruction(30))
+    ));
+
+    let mut popcnt =
+        |insn: &ebpf::Insn, reg: &mut [u64; 11], _memory_mapping: &mut MemoryMapping| {
+            reg[insn.dst as usize] = reg[insn.src as usize].count_ones() as u64;
+            Ok(())
+        };
+    let mut context_object = TestContextObject::new(3);
+    let mut vm = EbpfVm::new(
+        &verified_executable,
+        &mut context_object,
+        &mut [],
+        Vec::new(),
+    )
+    .unwrap();
+    vm.register_opcode_extension(POPCNT, &mut popcnt);
+    let (instruction_count, result) = vm.execute_program(true);
+    assert_eq!(result.unwrap(), 12);
+    assert_eq!(instruction_count, 3);
+}
+
+#[test]
+#[cfg(feature = "memory-observer")]
+fn test_memory_observer() {
+    // Records every access and vetoes stores of eight bytes
+    #[derive(Default)]
+    struct AccessLog(Vec<(AccessType, u64, u64)>);
+    impl solana_rbpf::memory_region::MemoryObserver for AccessLog {
+        fn on_access(&mut self, access_type: AccessType, vm_addr: u64, len: u64) -> bool {
+            self.0.push((access_type, vm_addr, len));
+            access_type != AccessType::Store || len != 8
+        }
+    }
+
+    let executable = assemble::<TestContextObject>(
+        "
+        stw [r10-0x4], 0x11
+        ldxw r0, [r10-0x4]
+        stdw [r10-0x10], 0x22
+        exit",
+        Config::default(),
+        SyscallRegistry::default(),
+    )
+    .unwrap();
+    #[allow(unused_mut)]
+    let mut verified_executable =
+        VerifiedExecutable::<RequisiteVerifier, _>::from_executable(executable).unwrap();
+    #[cfg(all(not(windows), target_arch = "x86_64"))]
+    verified_executable.jit_compile().unwrap();
+    for interpreted in [
+        true,
+        #[cfg(all(not(windows), target_arch = "x86_64"))]
+        false,
+    ] {
+        let mut context_object = TestContextObject::new(3);
+        let mut access_log = AccessLog::default();
+        let mut vm = EbpfVm::new(
+            &verified_executable,
+            &mut context_object,
+            &mut [],
+            Vec::new(),
+        )
+        .unwrap();
+        vm.set_memory_observer(&mut access_log);
+        let (_instruction_count, result) = vm.execute_program(interpreted);
+        drop(vm);
+        let frame_ptr = access_log.0[0].1 + 0x4;
+        assert_eq!(
+            access_log.0,
+            vec![
+                (AccessType::Store, frame_ptr - 0x4, 4),
+                (AccessType::Load, frame_ptr - 0x4, 4),
+                (AccessType::Store, frame_ptr - 0x10, 8),
+            ]
+        );
+        assert!(matches!(
+            result,
+            ProgramResult::Err(EbpfError::AccessViolation(31, AccessType::Store, vm_addr, 8, "stack", None))
+                if vm_addr == frame_ptr - 0x10
+        ));
+    }
+}
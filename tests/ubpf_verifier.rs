@@ -26,9 +26,12 @@ use solana_rbpf::{
     assembler::assemble,
     ebpf,
     elf::Executable,
-    verifier::{RequisiteVerifier, Verifier, VerifierError},
+    error::EbpfError,
+    syscalls,
+    verifier::{self, RequisiteVerifier, Verifier, VerifierError},
     vm::{
-        Config, EbpfVm, FunctionRegistry, SyscallRegistry, TestContextObject, VerifiedExecutable,
+        Config, EbpfVm, FunctionRegistry, OpcodeSet, SyscallRegistry, TestContextObject,
+        VerifiedExecutable,
     },
 };
 use test_utils::TautologyVerifier;
@@ -147,6 +150,27 @@ fn test_verifier_err_incomplete_lddw() {
             .unwrap();
 }
 
+#[test]
+fn test_verifier_err_invalid_wide_immediate() {
+    // Corrupt the registers and the offset of the second slot one at a time
+    for (index, value) in [(9, 0x01), (9, 0x10), (10, 0x01), (11, 0x80)] {
+        let mut prog = [
+            0x18, 0x00, 0x00, 0x00, 0x88, 0x77, 0x66, 0x55, //
+            0x00, 0x00, 0x00, 0x00, 0x44, 0x33, 0x22, 0x11, //
+            0x95, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, //
+        ];
+        assert_eq!(
+            RequisiteVerifier::verify(&prog, &Config::default(), &FunctionRegistry::default()),
+            Ok(())
+        );
+        prog[index] = value;
+        assert_eq!(
+            RequisiteVerifier::verify(&prog, &Config::default(), &FunctionRegistry::default()),
+            Err(VerifierError::InvalidWideImmediate(29))
+        );
+    }
+}
+
 #[test]
 fn test_verifier_err_invalid_reg_dst() {
     // r11 is disabled when dynamic_stack_frames=false, and only sub and add are
@@ -239,7 +263,39 @@ fn test_verifier_err_jmp_lddw() {
 }
 
 #[test]
-#[should_panic(expected = "JumpOutOfCode(3, 29)")]
+fn test_verifier_jmp_lddw_slots() {
+    for (assembly, expected) in [
+        ("ja +0", Ok(())),
+        ("ja +1", Err("JumpToMiddleOfLDDW(2, 29)")),
+        ("jeq r0, 0, +1", Err("JumpToMiddleOfLDDW(2, 29)")),
+        ("call 0", Ok(())),
+        ("call 1", Err("JumpToMiddleOfLDDW(2, 29)")),
+    ] {
+        let executable = assemble::<TestContextObject>(
+            &format!(
+                "
+                {}
+                lddw r0, 0x1122334455667788
+                exit",
+                assembly
+            ),
+            Config::default(),
+            SyscallRegistry::default(),
+        )
+        .unwrap();
+        let result =
+            VerifiedExecutable::<RequisiteVerifier, TestContextObject>::from_executable(executable)
+                .map(|_| ())
+                .map_err(|err| format!("{:?}", err));
+        assert_eq!(
+            result,
+            expected.map_err(|err| format!("VerifierError({})", err))
+        );
+    }
+}
+
+#[test]
+#[should_panic(expected = "JumpOutOfBounds(29, 3)")]
 fn test_verifier_err_jmp_out() {
     let executable = assemble::<TestContextObject>(
         "
@@ -255,7 +311,7 @@ fn test_verifier_err_jmp_out() {
 }
 
 #[test]
-#[should_panic(expected = "JumpOutOfCode(18446744073709551615, 29)")]
+#[should_panic(expected = "JumpOutOfBounds(29, -1)")]
 fn test_verifier_err_jmp_out_start() {
     let executable = assemble::<TestContextObject>(
         "
@@ -393,3 +449,838 @@ fn test_sdiv_disabled() {
         }
     }
 }
+
+#[test]
+fn test_verifier_stack_depth() {
+    let assembly = "
+        call function_foo
+        exit
+        function_foo:
+        call function_bar
+        exit
+        function_bar:
+        exit";
+    for (dynamic_stack_frames, max_call_depth, expected) in [
+        (false, 3, Ok(())),
+        (false, 2, Err("StackDepthExceeded(12288, 29)")),
+        (true, 2, Ok(())),
+    ] {
+        let executable = assemble::<TestContextObject>(
+            assembly,
+            Config {
+                dynamic_stack_frames,
+                max_call_depth,
+                verify_stack_depth: true,
+                ..Config::default()
+            },
+            SyscallRegistry::default(),
+        )
+        .unwrap();
+        let result =
+            VerifiedExecutable::<RequisiteVerifier, TestContextObject>::from_executable(executable)
+                .map(|_| ())
+                .map_err(|err| format!("{:?}", err));
+        assert_eq!(
+            result,
+            expected.map_err(|err| format!("VerifierError({})", err))
+        );
+    }
+}
+
+#[test]
+fn test_verifier_dynamic_stack_depth() {
+    let config = Config {
+        max_call_depth: 2,
+        stack_frame_size: 64,
+        verify_stack_depth: true,
+        ..Config::default()
+    };
+    for (frame_size, expected) in [(64, Ok(())), (72, Err("StackDepthExceeded(136, 30)"))] {
+        let executable = assemble::<TestContextObject>(
+            &format!(
+                "
+                sub r11, 64
+                call function_foo
+                add r11, 64
+                exit
+                function_foo:
+                sub r11, {}
+                add r11, {}
+                exit",
+                frame_size, frame_size
+            ),
+            config,
+            SyscallRegistry::default(),
+        )
+        .unwrap();
+        let result =
+            VerifiedExecutable::<RequisiteVerifier, TestContextObject>::from_executable(executable)
+                .map(|_| ())
+                .map_err(|err| format!("{:?}", err));
+        assert_eq!(
+            result,
+            expected.map_err(|err| format!("VerifierError({})", err))
+        );
+    }
+}
+
+#[test]
+fn test_verifier_unbounded_stack_depth() {
+    for (assembly, insn_ptr) in [
+        (
+            "
+            call function_foo
+            exit
+            function_foo:
+            call function_foo
+            exit",
+            31,
+        ),
+        (
+            "
+            mov64 r1, 0
+            callx r1
+            exit",
+            30,
+        ),
+    ] {
+        for reject_unbounded_stack_depth in [false, true] {
+            let executable = assemble::<TestContextObject>(
+                assembly,
+                Config {
+                    verify_stack_depth: true,
+                    reject_unbounded_stack_depth,
+                    ..Config::default()
+                },
+                SyscallRegistry::default(),
+            )
+            .unwrap();
+            let result =
+                VerifiedExecutable::<RequisiteVerifier, TestContextObject>::from_executable(
+                    executable,
+                )
+                .map(|_| ())
+                .map_err(|err| format!("{:?}", err));
+            if reject_unbounded_stack_depth {
+                assert_eq!(
+                    result.unwrap_err(),
+                    format!("VerifierError(UnboundedStackDepth({}))", insn_ptr)
+                );
+            } else {
+                assert!(result.is_ok());
+            }
+        }
+    }
+}
+
+#[test]
+fn test_verifier_infinite_loop() {
+    for (assembly, expected) in [
+        (
+            "
+            mov64 r0, 0
+            ja -1
+            exit",
+            Err("InfiniteLoop(29)"),
+        ),
+        (
+            "
+            mov64 r0, 0
+            jeq r0, 0, +2
+            add64 r0, 1
+            ja -3
+            exit",
+            Ok(()),
+        ),
+        (
+            "
+            call function_foo
+            exit
+            function_foo:
+            lddw r0, 0x1
+            jne r0, 0, -3
+            ja -4",
+            Err("InfiniteLoop(31)"),
+        ),
+        (
+            "
+            mov64 r0, 0
+            add64 r0, 1",
+            Ok(()),
+        ),
+    ] {
+        for reject_infinite_loops in [false, true] {
+            let executable = assemble::<TestContextObject>(
+                assembly,
+                Config {
+                    reject_infinite_loops,
+                    ..Config::default()
+                },
+                SyscallRegistry::default(),
+            )
+            .unwrap();
+            let result =
+                VerifiedExecutable::<RequisiteVerifier, TestContextObject>::from_executable(
+                    executable,
+                )
+                .map(|_| ())
+                .map_err(|err| format!("{:?}", err));
+            if reject_infinite_loops {
+                assert_eq!(
+                    result,
+                    expected.map_err(|err| format!("VerifierError({})", err))
+                );
+            } else {
+                assert!(result.is_ok());
+            }
+        }
+    }
+}
+
+#[test]
+fn test_verifier_register_not_initialized() {
+    for (assembly, expected) in [
+        // r0 is only initialized on one of the two branches
+        (
+            "
+            jeq r1, 0, +1
+            mov r0, 1
+            exit",
+            Err("RegisterNotInitialized(31, 0)"),
+        ),
+        (
+            "
+            jeq r1, 0, +2
+            mov r0, 1
+            ja +1
+            mov r0, 2
+            exit",
+            Ok(()),
+        ),
+        (
+            "
+            mov r0, r2
+            exit",
+            Err("RegisterNotInitialized(29, 2)"),
+        ),
+        (
+            "
+            lddw r0, 0x1
+            add64 r0, r1
+            stxdw [r10-8], r0
+            exit",
+            Ok(()),
+        ),
+        // Calls clobber r1 to r5 but preserve r6 to r9
+        (
+            "
+            mov r2, 1
+            call function_foo
+            mov r0, r2
+            exit
+            function_foo:
+            mov r0, r5
+            exit",
+            Err("RegisterNotInitialized(31, 2)"),
+        ),
+        (
+            "
+            mov r6, 1
+            call function_foo
+            add64 r0, r6
+            exit
+            function_foo:
+            mov r0, r5
+            exit",
+            Ok(()),
+        ),
+        (
+            "
+            call function_foo
+            exit
+            function_foo:
+            mov r0, r6
+            exit",
+            Err("RegisterNotInitialized(31, 6)"),
+        ),
+    ] {
+        for reject_uninitialized_registers in [false, true] {
+            let executable = assemble::<TestContextObject>(
+                assembly,
+                Config {
+                    reject_uninitialized_registers,
+                    ..Config::default()
+                },
+                SyscallRegistry::default(),
+            )
+            .unwrap();
+            let result =
+                VerifiedExecutable::<RequisiteVerifier, TestContextObject>::from_executable(
+                    executable,
+                )
+                .map(|_| ())
+                .map_err(|err| format!("{:?}", err));
+            if reject_uninitialized_registers {
+                assert_eq!(
+                    result,
+                    expected.map_err(|err| format!("VerifierError({})", err))
+                );
+            } else {
+                assert!(result.is_ok());
+            }
+        }
+    }
+}
+
+#[test]
+fn test_verifier_falls_off_end() {
+    for (assembly, expected) in [
+        ("exit", Ok(())),
+        (
+            "
+            mov64 r0, 0
+            ja -2",
+            Ok(()),
+        ),
+        (
+            "
+            mov64 r0, 0
+            add64 r0, 1",
+            Err(VerifierError::FallsOffEnd(30)),
+        ),
+        (
+            "
+            mov64 r0, 0
+            jeq r0, 0, -2",
+            Err(VerifierError::FallsOffEnd(30)),
+        ),
+        (
+            "
+            call function_foo
+            exit
+            function_foo:
+            call function_foo",
+            Err(VerifierError::FallsOffEnd(31)),
+        ),
+        (
+            "
+            exit
+            lddw r0, 0x1",
+            Err(VerifierError::LDDWCannotBeLast),
+        ),
+    ] {
+        for reject_falls_off_end in [false, true] {
+            let executable = assemble::<TestContextObject>(
+                assembly,
+                Config {
+                    reject_falls_off_end,
+                    ..Config::default()
+                },
+                SyscallRegistry::default(),
+            )
+            .unwrap();
+            let result =
+                VerifiedExecutable::<RequisiteVerifier, TestContextObject>::from_executable(
+                    executable,
+                )
+                .map(|_| ());
+            match &expected {
+                Err(VerifierError::FallsOffEnd(_) | VerifierError::LDDWCannotBeLast)
+                    if !reject_falls_off_end =>
+                {
+                    assert!(result.is_ok())
+                }
+                Ok(()) => assert!(result.is_ok()),
+                Err(expected) => assert!(
+                    matches!(result, Err(EbpfError::VerifierError(ref err)) if err == expected),
+                    "{:?}",
+                    result
+                ),
+            }
+        }
+    }
+}
+
+#[test]
+fn test_verified_executable_from_text_bytes() {
+    let prog = &[
+        0xb7, 0x00, 0x00, 0x00, 0xee, 0x0b, 0x00, 0x00, // mov r0, 0xbee
+        0x95, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, // exit
+    ];
+    let verified_executable =
+        VerifiedExecutable::<RequisiteVerifier, TestContextObject>::from_text_bytes(
+            prog,
+            Config::default(),
+            SyscallRegistry::default(),
+        )
+        .unwrap();
+    let mut context_object = TestContextObject::new(2);
+    let mut vm = EbpfVm::new(
+        &verified_executable,
+        &mut context_object,
+        &mut [],
+        Vec::new(),
+    )
+    .unwrap();
+    let (_instruction_count, result) = vm.execute_program(true);
+    assert_eq!(result.unwrap(), 0xbee);
+
+    let prog = &[
+        0xdc, 0x01, 0x00, 0x00, 0x03, 0x00, 0x00, 0x00, //
+        0x95, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, //
+    ];
+    let result = VerifiedExecutable::<RequisiteVerifier, TestContextObject>::from_text_bytes(
+        prog,
+        Config::default(),
+        SyscallRegistry::default(),
+    );
+    assert!(matches!(
+        result,
+        Err(EbpfError::VerifierError(
+            VerifierError::UnsupportedLEBEArgument(29)
+        ))
+    ));
+}
+
+#[test]
+fn test_atomics_disabled() {
+    for enable_atomics in [true, false] {
+        let executable = assemble::<TestContextObject>(
+            "
+            stxfetchadddw [r10-8], r1
+            exit",
+            Config {
+                enable_atomics,
+                ..Config::default()
+            },
+            SyscallRegistry::default(),
+        )
+        .unwrap();
+        let result =
+            VerifiedExecutable::<RequisiteVerifier, TestContextObject>::from_executable(executable);
+        if enable_atomics {
+            assert!(result.is_ok());
+        } else {
+            assert!(matches!(
+                result,
+                Err(EbpfError::VerifierError(VerifierError::UnknownOpCode(
+                    ebpf::ST_DW_ATOMIC,
+                    29
+                )))
+            ));
+        }
+    }
+}
+
+#[test]
+fn test_opcode_not_allowed() {
+    let mut allowed_opcodes = OpcodeSet::all();
+    allowed_opcodes.remove_class(ebpf::BPF_ST);
+    allowed_opcodes.remove_class(ebpf::BPF_STX);
+    let config = Config {
+        allowed_opcodes,
+        ..Config::default()
+    };
+    let executable = assemble::<TestContextObject>(
+        "
+        ldxdw r0, [r1]
+        exit",
+        config,
+        SyscallRegistry::default(),
+    )
+    .unwrap();
+    assert!(
+        VerifiedExecutable::<RequisiteVerifier, TestContextObject>::from_executable(executable)
+            .is_ok()
+    );
+    for restrict in [false, true] {
+        let executable = assemble::<TestContextObject>(
+            "
+            mov r0, 0
+            stxdw [r10-8], r0
+            exit",
+            if restrict { config } else { Config::default() },
+            SyscallRegistry::default(),
+        )
+        .unwrap();
+        let result =
+            VerifiedExecutable::<RequisiteVerifier, TestContextObject>::from_executable(executable);
+        if restrict {
+            assert!(matches!(
+                result,
+                Err(EbpfError::VerifierError(VerifierError::OpcodeNotAllowed(
+                    30,
+                    ebpf::ST_DW_REG
+                )))
+            ));
+        } else {
+            assert!(result.is_ok());
+        }
+    }
+}
+
+#[test]
+fn test_verifier_err_atomic_operation() {
+    let prog = &[
+        0xdb, 0x21, 0x00, 0x00, 0x10, 0x00, 0x00, 0x00, //
+        0x95, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, //
+    ];
+    let result = VerifiedExecutable::<RequisiteVerifier, TestContextObject>::from_text_bytes(
+        prog,
+        Config {
+            enable_atomics: true,
+            ..Config::default()
+        },
+        SyscallRegistry::default(),
+    );
+    assert!(matches!(
+        result,
+        Err(EbpfError::VerifierError(
+            VerifierError::UnsupportedAtomicOperation(0x10, 29)
+        ))
+    ));
+}
+
+#[test]
+fn test_verifier_err_atomic_fetch_into_r10() {
+    for (source, result) in [
+        ("stxxaddw [r1], r10", Ok(())),
+        ("stxcmpxchgdw [r1], r10", Ok(())),
+        (
+            "stxfetchaddw [r1], r10",
+            Err(VerifierError::CannotWriteR10(29)),
+        ),
+        (
+            "stxxchgdw [r1], r10",
+            Err(VerifierError::CannotWriteR10(29)),
+        ),
+    ] {
+        let executable = assemble::<TestContextObject>(
+            &format!("\n{}\nexit", source),
+            Config {
+                enable_atomics: true,
+                ..Config::default()
+            },
+            SyscallRegistry::default(),
+        )
+        .unwrap();
+        let verified_executable =
+            VerifiedExecutable::<RequisiteVerifier, TestContextObject>::from_executable(executable);
+        match result {
+            Ok(()) => assert!(verified_executable.is_ok()),
+            Err(expected) => assert!(matches!(
+                verified_executable,
+                Err(EbpfError::VerifierError(error)) if error == expected
+            )),
+        }
+    }
+}
+
+#[test]
+fn test_uhmul_disabled() {
+    for enable_uhmul in [true, false] {
+        let executable = assemble::<TestContextObject>(
+            "
+            uhmul64 r1, r2
+            exit",
+            Config {
+                enable_uhmul,
+                ..Config::default()
+            },
+            SyscallRegistry::default(),
+        )
+        .unwrap();
+        let result =
+            VerifiedExecutable::<RequisiteVerifier, TestContextObject>::from_executable(executable);
+        if enable_uhmul {
+            assert!(result.is_ok());
+        } else {
+            assert!(matches!(
+                result,
+                Err(EbpfError::VerifierError(VerifierError::UnknownOpCode(
+                    ebpf::UHMUL64_REG,
+                    29
+                )))
+            ));
+        }
+    }
+}
+
+#[test]
+fn test_jmp32_disabled() {
+    for enable_jmp32 in [true, false] {
+        let executable = assemble::<TestContextObject>(
+            "
+            jeq32 r1, r2, +0
+            exit",
+            Config {
+                enable_jmp32,
+                ..Config::default()
+            },
+            SyscallRegistry::default(),
+        )
+        .unwrap();
+        let result =
+            VerifiedExecutable::<RequisiteVerifier, TestContextObject>::from_executable(executable);
+        if enable_jmp32 {
+            assert!(result.is_ok());
+        } else {
+            assert!(matches!(
+                result,
+                Err(EbpfError::VerifierError(VerifierError::UnknownOpCode(
+                    ebpf::JEQ32_REG,
+                    29
+                )))
+            ));
+        }
+    }
+}
+
+#[test]
+fn test_verifier_err_uhmul_register_pair() {
+    // The high half of the product would be written to r10 or r11
+    for source in ["uhmul64 r9, r1", "uhmul64 r10, 2"] {
+        let executable = assemble::<TestContextObject>(
+            &format!("\n{}\nexit", source),
+            Config {
+                enable_uhmul: true,
+                ..Config::default()
+            },
+            SyscallRegistry::default(),
+        )
+        .unwrap();
+        let result =
+            VerifiedExecutable::<RequisiteVerifier, TestContextObject>::from_executable(executable);
+        assert!(matches!(
+            result,
+            Err(EbpfError::VerifierError(
+                VerifierError::InvalidDestinationRegister(29)
+            ))
+        ));
+    }
+}
+
+#[test]
+fn test_verify_raw_bytecode() {
+    let call = |src: u8, imm: i64| {
+        ebpf::Insn {
+            opc: ebpf::CALL_IMM,
+            src,
+            imm,
+            ..ebpf::Insn::default()
+        }
+        .to_vec()
+    };
+    let exit = [0x95, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00];
+    let config = Config::default();
+    let mut syscall_registry = SyscallRegistry::<TestContextObject>::default();
+    syscall_registry
+        .register_syscall_by_name(b"bpf_syscall_string", syscalls::bpf_syscall_string)
+        .unwrap();
+    let hash = ebpf::hash_symbol_name(b"bpf_syscall_string");
+
+    // Relative call to the second function and a registered syscall
+    let prog = [
+        call(1, 1),
+        exit.to_vec(),
+        call(0, hash as i64),
+        exit.to_vec(),
+    ]
+    .concat();
+    assert_eq!(verifier::verify(&prog, &config, &syscall_registry), Ok(()));
+
+    // Relative call past the end of the program
+    let prog = [call(1, 10), exit.to_vec()].concat();
+    assert_eq!(
+        verifier::verify(&prog, &config, &syscall_registry),
+        Err(VerifierError::JumpOutOfBounds(29, 11))
+    );
+
+    // Syscall which is not registered
+    let prog = [call(0, 0x1234), exit.to_vec()].concat();
+    assert_eq!(
+        verifier::verify(&prog, &config, &syscall_registry),
+        Err(VerifierError::UnknownSyscall(0x1234, 29))
+    );
+
+    // Relative call into the middle of a lddw
+    let lddw = [
+        0x18, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, //
+        0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, //
+    ];
+    let prog = [call(1, 2), exit.to_vec(), lddw.to_vec(), exit.to_vec()].concat();
+    assert_eq!(
+        verifier::verify(&prog, &config, &syscall_registry),
+        Err(VerifierError::JumpToMiddleOfLDDW(3, 29))
+    );
+    let prog = [call(1, 0), lddw.to_vec(), exit.to_vec()].concat();
+    assert_eq!(verifier::verify(&prog, &config, &syscall_registry), Ok(()));
+
+    // The checks of the RequisiteVerifier apply
+    let prog = [&[0x06, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00][..], &exit].concat();
+    assert_eq!(
+        verifier::verify(&prog, &config, &syscall_registry),
+        Err(VerifierError::UnknownOpCode(0x06, 29))
+    );
+    assert_eq!(
+        verifier::verify(&exit[..4], &config, &syscall_registry),
+        Err(VerifierError::ProgramLengthNotMultiple)
+    );
+}
+
+#[test]
+fn test_verify_collect() {
+    let config = Config {
+        reject_falls_off_end: true,
+        ..Config::default()
+    };
+    let syscall_registry = SyscallRegistry::<TestContextObject>::default();
+
+    // Three independent errors are all reported, in program order
+    let prog = [
+        0x06, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, // unknown opcode
+        0x37, 0x01, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, // div64 r1, 0
+        0x05, 0x00, 0x64, 0x00, 0x00, 0x00, 0x00, 0x00, // ja +100
+        0x95, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, // exit
+    ];
+    assert_eq!(
+        verifier::verify_collect(&prog, &config, &syscall_registry),
+        vec![
+            VerifierError::UnknownOpCode(0x06, 29),
+            VerifierError::DivisionByZero(30),
+            VerifierError::JumpOutOfBounds(31, 103),
+        ]
+    );
+    assert_eq!(
+        verifier::verify(&prog, &config, &syscall_registry),
+        Err(VerifierError::UnknownOpCode(0x06, 29))
+    );
+
+    // Errors of the call resolution and of the program as a whole are collected as well
+    let prog = [
+        0x85, 0x10, 0x00, 0x00, 0x0a, 0x00, 0x00, 0x00, // call +10
+        0x85, 0x00, 0x00, 0x00, 0x34, 0x12, 0x00, 0x00, // call 0x1234
+        0xb7, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, // mov r0, 0
+    ];
+    assert_eq!(
+        verifier::verify_collect(&prog, &config, &syscall_registry),
+        vec![
+            VerifierError::JumpOutOfBounds(29, 11),
+            VerifierError::UnknownSyscall(0x1234, 30),
+            VerifierError::FallsOffEnd(31),
+        ]
+    );
+
+    // A valid program has no errors and a program of invalid length only that one
+    let exit = [0x95, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00];
+    assert!(verifier::verify_collect(&exit, &config, &syscall_registry).is_empty());
+    assert_eq!(
+        verifier::verify_collect(&exit[..4], &config, &syscall_registry),
+        vec![VerifierError::ProgramLengthNotMultiple]
+    );
+}
+
+#[test]
+fn test_reverify_range() {
+    let insn = |opc: u8, off: i16, imm: i64| {
+        ebpf::Insn {
+            opc,
+            off,
+            imm,
+            ..ebpf::Insn::default()
+        }
+        .to_vec()
+    };
+    let mov = insn(ebpf::MOV64_IMM, 0, 1);
+    let exit = insn(ebpf::EXIT, 0, 0);
+    let lddw = [insn(ebpf::LD_DW_IMM, 0, 0), insn(0, 0, 0)].concat();
+    let config = Config {
+        reject_falls_off_end: true,
+        ..Config::default()
+    };
+    let function_registry = FunctionRegistry::default();
+    let reverify = |prog: &[u8], changed_range: std::ops::Range<usize>| {
+        verifier::reverify_range(prog, changed_range, &config, &function_registry)
+    };
+    let patch = |prog: &[u8], insn_ptr: usize, insn: &[u8]| {
+        let mut prog = prog.to_vec();
+        let offset = insn_ptr * ebpf::INSN_SIZE;
+        prog[offset..offset + insn.len()].copy_from_slice(insn);
+        prog
+    };
+
+    // ja +1 skips the first mov
+    let prog = [
+        insn(ebpf::JA, 1, 0),
+        mov.clone(),
+        mov.clone(),
+        mov.clone(),
+        exit.clone(),
+    ]
+    .concat();
+    assert_eq!(
+        RequisiteVerifier::verify(&prog, &config, &function_registry),
+        Ok(())
+    );
+    assert_eq!(
+        reverify(&patch(&prog, 2, &insn(ebpf::ADD64_IMM, 0, 2)), 2..3),
+        Ok(())
+    );
+
+    // A patched illegal opcode is still caught
+    assert_eq!(
+        reverify(&patch(&prog, 2, &insn(0x06, 0, 0)), 2..3),
+        Err(VerifierError::UnknownOpCode(0x06, 31))
+    );
+
+    // Replacing the final exit lets execution continue behind the program
+    assert_eq!(
+        reverify(&patch(&prog, 4, &mov), 4..5),
+        Err(VerifierError::FallsOffEnd(33))
+    );
+
+    // A lddw which would swallow the following instruction
+    assert_eq!(
+        reverify(&patch(&prog, 3, &insn(ebpf::LD_DW_IMM, 0, 0)), 3..4),
+        Err(VerifierError::IncompleteLDDW(32))
+    );
+
+    // The jump from outside of the range now lands in the middle of a lddw
+    assert_eq!(
+        reverify(&patch(&prog, 1, &lddw), 1..3),
+        Err(VerifierError::JumpToMiddleOfLDDW(2, 29))
+    );
+    assert_eq!(reverify(&patch(&prog, 2, &lddw), 2..4), Ok(()));
+
+    let prog = [mov.clone(), lddw.clone(), exit.clone()].concat();
+    assert_eq!(
+        RequisiteVerifier::verify(&prog, &config, &function_registry),
+        Ok(())
+    );
+
+    // Patching the second slot of a lddw checks the entire lddw
+    assert_eq!(reverify(&patch(&prog, 2, &insn(0, 0, 7)), 2..3), Ok(()));
+    assert_eq!(
+        reverify(&patch(&prog, 2, &mov), 2..3),
+        Err(VerifierError::IncompleteLDDW(30))
+    );
+
+    // Replacing the first slot of a lddw leaves the second one behind
+    assert_eq!(
+        reverify(&patch(&prog, 1, &mov), 1..2),
+        Err(VerifierError::UnknownOpCode(0, 31))
+    );
+
+    // Checks of the whole program fall back to full verification
+    let prog = [mov.clone(), exit.clone()].concat();
+    let looping = patch(&prog, 1, &insn(ebpf::JA, -1, 0));
+    assert_eq!(reverify(&looping, 1..2), Ok(()));
+    let config = Config {
+        reject_infinite_loops: true,
+        ..Config::default()
+    };
+    assert_eq!(
+        verifier::reverify_range(&looping, 1..2, &config, &function_registry),
+        Err(VerifierError::InfiniteLoop(29))
+    );
+}